@@ -9,26 +9,52 @@ use cmake::Config;
 
 use std::env;
 use std::path::PathBuf;
+use std::process::Command;
 
 fn main() {
-    let dst = Config::new("fizzy").define("FIZZY_TESTING", "OFF").build();
+    // The mock backend implements the C API in Rust, nothing is built nor linked.
+    if env::var_os("CARGO_FEATURE_MOCK").is_some() {
+        return;
+    }
+
+    println!("cargo:rerun-if-env-changed=FIZZY_SYSTEM_LIB");
+    let system_lib = env::var_os("CARGO_FEATURE_SYSTEM_LIB").is_some()
+        || env::var_os("FIZZY_SYSTEM_LIB").is_some();
+    let link_static = link_cpp_stdlib_statically();
+
+    let header = if system_lib {
+        link_system_lib()
+    } else {
+        let minimal = env::var_os("CARGO_FEATURE_MINIMAL").is_some();
+        let dst = Config::new("fizzy")
+            .define("FIZZY_TESTING", "OFF")
+            .define("FIZZY_MINIMAL", if minimal { "ON" } else { "OFF" })
+            .static_crt(crt_static())
+            .build();
 
-    println!("cargo:rustc-link-lib=static=fizzy");
-    println!("cargo:rustc-link-search=native={}/lib", dst.display());
+        println!("cargo:rustc-link-lib=static=fizzy");
+        println!("cargo:rustc-link-search=native={}/lib", dst.display());
+        PathBuf::from("fizzy/include/fizzy/fizzy.h")
+    };
 
     // We need to link against C++ std lib
-    if let Some(cpp_stdlib) = get_cpp_stdlib() {
+    if let Some(cpp_stdlib) = get_cpp_stdlib(link_static) {
+        if link_static {
+            add_cpp_stdlib_search_path();
+        }
         println!("cargo:rustc-link-lib={}", cpp_stdlib);
     }
 
     let bindings = bindgen::Builder::default()
-        .header("fizzy/include/fizzy/fizzy.h")
+        .header(header.to_string_lossy())
         // See https://github.com/rust-lang-nursery/rust-bindgen/issues/947
         .trust_clang_mangling(false)
         .generate_comments(true)
         // https://github.com/rust-lang-nursery/rust-bindgen/issues/947#issuecomment-327100002
         .layout_tests(false)
         .whitelist_function("fizzy_.*")
+        .whitelist_var("Fizzy.*")
+        .prepend_enum_name(false)
         // TODO: consider removing this
         .size_t_is_usize(true)
         .generate()
@@ -40,14 +66,90 @@ fn main() {
         .expect("Could not write bindings");
 }
 
+/// Links the fizzy library installed in the system, found with pkg-config, instead of building
+/// the bundled sources. Returns the path of its header.
+fn link_system_lib() -> PathBuf {
+    println!("cargo:rerun-if-env-changed=PKG_CONFIG_PATH");
+    let version = env::var("CARGO_PKG_VERSION").unwrap();
+    let pkg_config = |args: &[&str]| {
+        let output = Command::new(env::var("PKG_CONFIG").unwrap_or_else(|_| "pkg-config".into()))
+            .args(args)
+            .arg("fizzy")
+            .output()
+            .expect("Unable to run pkg-config");
+        if !output.status.success() {
+            panic!(
+                "Unable to find fizzy {} with pkg-config: {}",
+                version,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        String::from_utf8(output.stdout).expect("Invalid pkg-config output")
+    };
+
+    // The bindings must match the library exactly.
+    pkg_config(&["--exact-version", &version]);
+
+    for flag in pkg_config(&["--libs"]).split_whitespace() {
+        if let Some(dir) = flag.strip_prefix("-L") {
+            println!("cargo:rustc-link-search=native={}", dir);
+        } else if let Some(lib) = flag.strip_prefix("-l") {
+            println!("cargo:rustc-link-lib={}", lib);
+        }
+    }
+
+    pkg_config(&["--cflags-only-I"])
+        .split_whitespace()
+        .filter_map(|flag| flag.strip_prefix("-I"))
+        .map(|dir| PathBuf::from(dir).join("fizzy/fizzy.h"))
+        .find(|header| header.exists())
+        .expect("Unable to find fizzy/fizzy.h in the include directories of fizzy")
+}
+
+/// Whether the C runtime is linked statically, e.g. on musl targets by default or with
+/// `-C target-feature=+crt-static`.
+fn crt_static() -> bool {
+    env::var("CARGO_CFG_TARGET_FEATURE")
+        .map(|features| features.split(',').any(|feature| feature == "crt-static"))
+        .unwrap_or(false)
+}
+
+/// Whether the C++ runtime is linked statically, with the `static` feature or together with
+/// the static C runtime.
+fn link_cpp_stdlib_statically() -> bool {
+    env::var_os("CARGO_FEATURE_STATIC").is_some() || crt_static()
+}
+
+/// Adds the directory of the static C++ runtime of the C++ compiler to the library search path,
+/// as it is often not in the search path of the linker.
+fn add_cpp_stdlib_search_path() {
+    println!("cargo:rerun-if-env-changed=CXX");
+    let compiler = env::var("CXX").unwrap_or_else(|_| "c++".to_string());
+    if let Ok(output) = Command::new(compiler)
+        .arg("-print-file-name=libstdc++.a")
+        .output()
+    {
+        let path = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+        // The compiler prints only the file name if the library is not found.
+        if let Some(dir) = path.parent().filter(|_| path.is_absolute()) {
+            println!("cargo:rustc-link-search=native={}", dir.display());
+        }
+    }
+}
+
 // See https://github.com/alexcrichton/gcc-rs/blob/88ac58e25/src/lib.rs#L1197
-fn get_cpp_stdlib() -> Option<String> {
+fn get_cpp_stdlib(link_static: bool) -> Option<String> {
     env::var("TARGET").ok().and_then(|target| {
         if target.contains("msvc") {
+            // The C++ runtime is a part of the C runtime, linked statically with crt-static.
             None
-        } else if target.contains("darwin") || target.contains("freebsd") {
+        } else if target.contains("darwin") {
+            // The static libc++ is not available on Apple platforms.
             Some("c++".to_string())
-        } else if target.contains("musl") {
+        } else if target.contains("freebsd") {
+            Some(if link_static { "static=c++" } else { "c++" }.to_string())
+        } else if target.contains("musl") || link_static {
+            // Also MinGW, which links libgcc statically already.
             Some("static=stdc++".to_string())
         } else {
             Some("stdc++".to_string())
@@ -3,8 +3,17 @@
 // SPDX-License-Identifier: Apache-2.0
 
 extern crate fizzy;
+extern crate fizzy_macros;
+
+/* wat2wasm
+  (func (export "answer") (result i32) (i32.const 42))
+*/
+const ANSWER_WASM: &[u8] = fizzy_macros::include_wasm!("answer.wasm");
 
 fn main() {
-    assert_eq!(fizzy::validate(&[]), false);
+    assert!(!fizzy::validate(b""));
+    let mut instance = fizzy::parse(ANSWER_WASM).unwrap().instantiate().unwrap();
+    let result = instance.execute("answer", &[]).unwrap();
+    assert_eq!(result.value(), Some(fizzy::Value::I32(42)));
     println!("Fizzy works!");
 }
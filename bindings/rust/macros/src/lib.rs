@@ -0,0 +1,55 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Macros embedding WebAssembly modules into the binary, validated by Fizzy at compile time.
+
+extern crate proc_macro;
+
+use proc_macro::{TokenStream, TokenTree};
+use std::path::PathBuf;
+
+/// Embeds a wasm file into the binary as a `&'static [u8; N]`, like `include_bytes!`, after
+/// validating the module with Fizzy.
+///
+/// The path is relative to the directory of the manifest of the crate using the macro.
+/// An invalid module, or a file which cannot be read, fails the compilation.
+///
+/// ```ignore
+/// let module = fizzy::parse(fizzy_macros::include_wasm!("wasm/token.wasm")).unwrap();
+/// ```
+#[proc_macro]
+pub fn include_wasm(input: TokenStream) -> TokenStream {
+    match include_wasm_path(input) {
+        Ok(path) => format!("include_bytes!({:?})", path)
+            .parse()
+            .expect("include_bytes invocation"),
+        Err(message) => format!("compile_error!({:?})", message)
+            .parse()
+            .expect("compile_error invocation"),
+    }
+}
+
+/// Returns the absolute path of the validated wasm file, or the message of the compile error.
+fn include_wasm_path(input: TokenStream) -> Result<String, String> {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    let literal = match tokens.as_slice() {
+        [TokenTree::Literal(literal)] => literal.to_string(),
+        _ => return Err("include_wasm! takes a single string literal".to_string()),
+    };
+    let relative = literal
+        .strip_prefix('"')
+        .and_then(|literal| literal.strip_suffix('"'))
+        .filter(|path| !path.contains('\\'))
+        .ok_or_else(|| "include_wasm! takes a string literal without escapes".to_string())?;
+
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+        .map_err(|_| "include_wasm! requires CARGO_MANIFEST_DIR to be set".to_string())?;
+    let path = PathBuf::from(manifest_dir).join(relative);
+    let input = std::fs::read(&path)
+        .map_err(|error| format!("cannot read {}: {}", path.display(), error))?;
+    fizzy::parse(&input).map_err(|error| format!("invalid wasm module {}: {}", relative, error))?;
+    path.to_str()
+        .map(str::to_string)
+        .ok_or_else(|| format!("path {} is not valid UTF-8", path.display()))
+}
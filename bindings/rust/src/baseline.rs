@@ -0,0 +1,995 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Experimental baseline compiler tier, compiling the hot functions to native code with
+//! cranelift (the `baseline` feature).
+//!
+//! The modules parsed by [`parse_with_baseline_tier()`](crate::parse_with_baseline_tier), or by
+//! an [`Engine`](crate::engine::Engine) configured with
+//! [`Config::baseline_tier()`](crate::engine::Config::baseline_tier), keep their function bodies.
+//! Their instances count the executions of every function, and compile a function once it has
+//! been executed the configured number of times by the interpreter. The following executions
+//! run the native code, through the same [`Instance`](crate::Instance) API.
+//!
+//! Only the functions computing on `i32` and `i64` values are compiled: the functions accessing
+//! the memory, the globals or the table, calling other functions or using float values are
+//! always interpreted. As the compiled functions have no side effects, a trapping compiled
+//! function is executed again by the interpreter, which reports the trap with its stack trace.
+//! The executions with an instruction hook (e.g. debugging, coverage and profiling) or
+//! a progress callback are interpreted too, as are the nested executions by host functions.
+
+use crate::binary::{sections, usize_from, Malformed, Reader};
+use crate::{sys, Module, ValueType};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, InstBuilder, JumpTableData, MemFlags, Type, Value,
+};
+use cranelift_codegen::{settings, Context};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module as _};
+use std::sync::Arc;
+
+/// The maximum number of locals of a compiled function, including the parameters.
+const MAX_LOCAL_COUNT: u64 = 50_000;
+
+/// The code of the functions defined in a module, kept for their compilation.
+pub(crate) struct ModuleCode {
+    hot_threshold: u32,
+    import_function_count: u32,
+    /// The defined functions, `None` if the signature cannot be compiled.
+    functions: Vec<Option<FunctionCode>>,
+}
+
+/// The signature and the body of a function.
+struct FunctionCode {
+    params: Vec<Type>,
+    result: Option<Type>,
+    /// The local declarations and the instructions.
+    body: Vec<u8>,
+}
+
+/// Returns the cranelift type of the wasm value type, for the integer types only.
+fn integer_type(value_type: ValueType) -> Option<Type> {
+    match value_type {
+        ValueType::I32 => Some(types::I32),
+        ValueType::I64 => Some(types::I64),
+        ValueType::F32 | ValueType::F64 => None,
+    }
+}
+
+impl ModuleCode {
+    /// Collect the function bodies of the module parsed from the binary.
+    ///
+    /// The module is not compiled if its code section cannot be found, all functions are
+    /// interpreted then.
+    pub(crate) fn new(module: &Module, binary: &[u8], hot_threshold: u32) -> Self {
+        let import_function_count = module.import_function_count();
+        let bodies = sections(binary)
+            .ok()
+            .and_then(|sections| {
+                sections
+                    .into_iter()
+                    .find(|&(id, _)| id == 10)
+                    .and_then(|(_, payload)| code_bodies(payload).ok())
+            })
+            .unwrap_or_default();
+        let functions = (import_function_count..module.function_count())
+            .zip(bodies)
+            .map(|(func_idx, body)| {
+                let func_type = module.function_type(func_idx)?;
+                Some(FunctionCode {
+                    params: func_type
+                        .inputs
+                        .into_iter()
+                        .map(integer_type)
+                        .collect::<Option<_>>()?,
+                    result: match func_type.output {
+                        Some(output) => Some(integer_type(output)?),
+                        None => None,
+                    },
+                    body: body.to_vec(),
+                })
+            })
+            .collect();
+        ModuleCode {
+            hot_threshold,
+            import_function_count,
+            functions,
+        }
+    }
+}
+
+/// Split the payload of the code section into the function bodies.
+fn code_bodies(payload: &[u8]) -> Result<Vec<&[u8]>, Malformed> {
+    let mut reader = Reader::new(payload);
+    (0..reader.uleb()?)
+        .map(|_| {
+            let size = usize_from(reader.uleb()?)?;
+            reader.bytes(size)
+        })
+        .collect()
+}
+
+/// The compiled function, taking the pointers to the arguments and to the result, and returning
+/// non-zero if it has trapped.
+type NativeFunction = unsafe extern "C" fn(*const sys::FizzyValue, *mut sys::FizzyValue) -> u32;
+
+/// The compilation state of a function.
+#[derive(Clone, Copy)]
+enum State {
+    /// Executed by the interpreter, the given number of times.
+    Interpreted(u32),
+    Compiled(NativeFunction),
+    /// Not supported by the compiler, always interpreted.
+    Unsupported,
+}
+
+/// The baseline compiler tier of an instance, with the compiled code of its hot functions.
+pub(crate) struct Tier {
+    code: Arc<ModuleCode>,
+    states: Vec<State>,
+    /// The owner of the compiled code, created by the first compilation.
+    jit: Option<JITModule>,
+}
+
+impl Tier {
+    pub(crate) fn new(code: Arc<ModuleCode>) -> Self {
+        Tier {
+            states: vec![State::Interpreted(0); code.functions.len()],
+            code,
+            jit: None,
+        }
+    }
+
+    /// Create the tier of a copy of the instance, compiling the functions again.
+    pub(crate) fn fresh(&self) -> Self {
+        Tier::new(self.code.clone())
+    }
+
+    /// Returns whether the function has been compiled.
+    pub(crate) fn is_compiled(&self, func_idx: u32) -> bool {
+        matches!(self.state(func_idx), Some(State::Compiled(_)))
+    }
+
+    fn state(&self, func_idx: u32) -> Option<State> {
+        let index = func_idx.checked_sub(self.code.import_function_count)?;
+        self.states.get(index as usize).copied()
+    }
+
+    /// Execute the function with the compiled code, compiling it first if it has become hot.
+    ///
+    /// Returns `None` if the function is to be executed by the interpreter, which is also the
+    /// case if the compiled code has trapped.
+    pub(crate) fn execute(
+        &mut self,
+        func_idx: u32,
+        args: &[sys::FizzyValue],
+    ) -> Option<sys::FizzyExecutionResult> {
+        let index = func_idx.checked_sub(self.code.import_function_count)? as usize;
+        let native = match *self.states.get(index)? {
+            State::Compiled(native) => native,
+            State::Unsupported => return None,
+            State::Interpreted(count) if count < self.code.hot_threshold => {
+                self.states[index] = State::Interpreted(count + 1);
+                return None;
+            }
+            State::Interpreted(_) => match self.compile(func_idx, index) {
+                Some(native) => {
+                    self.states[index] = State::Compiled(native);
+                    native
+                }
+                None => {
+                    self.states[index] = State::Unsupported;
+                    return None;
+                }
+            },
+        };
+        let mut value = sys::FizzyValue { i64: 0 };
+        // The arguments have been checked against the function type.
+        let trapped = unsafe { native(args.as_ptr(), &mut value) } != 0;
+        if trapped {
+            return None;
+        }
+        Some(sys::FizzyExecutionResult {
+            trapped: false,
+            has_value: self.code.functions[index]
+                .as_ref()
+                .is_some_and(|function| function.result.is_some()),
+            value,
+        })
+    }
+
+    fn compile(&mut self, func_idx: u32, index: usize) -> Option<NativeFunction> {
+        let function = self.code.functions[index].as_ref()?;
+        if self.jit.is_none() {
+            let isa = cranelift_native::builder()
+                .ok()?
+                .finish(settings::Flags::new(settings::builder()))
+                .ok()?;
+            self.jit = Some(JITModule::new(JITBuilder::with_isa(
+                isa,
+                default_libcall_names(),
+            )));
+        }
+        let jit = self.jit.as_mut()?;
+        let pointer_type = jit.target_config().pointer_type();
+        let mut context = jit.make_context();
+        context.func.signature.params = vec![AbiParam::new(pointer_type); 2];
+        context.func.signature.returns = vec![AbiParam::new(types::I32)];
+        let mut builder_context = FunctionBuilderContext::new();
+        translate(
+            FunctionBuilder::new(&mut context.func, &mut builder_context),
+            function,
+        )
+        .ok()?;
+        define(jit, &format!("f{}", func_idx), &mut context)
+    }
+}
+
+impl Drop for Tier {
+    fn drop(&mut self) {
+        if let Some(jit) = self.jit.take() {
+            // The compiled code is not referenced after the tier is dropped.
+            unsafe { jit.free_memory() };
+        }
+    }
+}
+
+/// Compile the translated function, returning its native code.
+fn define(jit: &mut JITModule, name: &str, context: &mut Context) -> Option<NativeFunction> {
+    let id = jit
+        .declare_function(name, Linkage::Local, &context.func.signature)
+        .ok()?;
+    let defined = jit.define_function(id, context);
+    jit.clear_context(context);
+    defined.ok()?;
+    jit.finalize_definitions().ok()?;
+    let code = jit.get_finalized_function(id);
+    Some(unsafe { std::mem::transmute::<*const u8, NativeFunction>(code) })
+}
+
+/// The function uses an instruction or a type which is not compiled.
+struct Unsupported;
+
+impl From<Malformed> for Unsupported {
+    fn from(_: Malformed) -> Self {
+        Unsupported
+    }
+}
+
+/// A block, loop or if, or the function body, in the translation of the control flow.
+struct Frame {
+    /// The block following the `end`, with the result as its parameter.
+    next: Block,
+    /// The loop header, the target of the branches to a loop.
+    header: Option<Block>,
+    /// The block of the else branch of an if, used by the `end` of an if without else.
+    else_block: Option<Block>,
+    result: Option<Type>,
+    /// The height of the operand stack at the start.
+    height: usize,
+}
+
+impl Frame {
+    /// Returns the target block of the branches to the frame, and whether it takes the result.
+    fn branch_target(&self) -> (Block, bool) {
+        match self.header {
+            Some(header) => (header, false),
+            None => (self.next, self.result.is_some()),
+        }
+    }
+}
+
+/// Translate the function body to the cranelift function of the native function signature.
+fn translate(mut builder: FunctionBuilder, function: &FunctionCode) -> Result<(), Unsupported> {
+    let mut reader = Reader::new(&function.body);
+
+    let entry = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    let (args, result_ptr) = {
+        let params = builder.block_params(entry);
+        (params[0], params[1])
+    };
+
+    let mut locals = Vec::new();
+    for (i, &ty) in function.params.iter().enumerate() {
+        let arg = builder
+            .ins()
+            .load(types::I64, MemFlags::trusted(), args, (i * 8) as i32);
+        let arg = if ty == types::I32 {
+            builder.ins().ireduce(types::I32, arg)
+        } else {
+            arg
+        };
+        locals.push(declare_local(&mut builder, locals.len(), ty, arg));
+    }
+    let mut local_count = function.params.len() as u64;
+    for _ in 0..reader.uleb()? {
+        let count = reader.uleb()?;
+        let ty = value_type(reader.u8()?)?;
+        local_count += count;
+        if local_count > MAX_LOCAL_COUNT {
+            return Err(Unsupported);
+        }
+        for _ in 0..count {
+            let zero = builder.ins().iconst(ty, 0);
+            locals.push(declare_local(&mut builder, locals.len(), ty, zero));
+        }
+    }
+
+    let exit = builder.create_block();
+    if let Some(result) = function.result {
+        builder.append_block_param(exit, result);
+    }
+    let mut translator = Translator {
+        builder,
+        locals,
+        stack: Vec::new(),
+        frames: vec![Frame {
+            next: exit,
+            header: None,
+            else_block: None,
+            result: function.result,
+            height: 0,
+        }],
+        trap: None,
+        unreachable_depth: None,
+    };
+    while !translator.frames.is_empty() {
+        translator.instruction(&mut reader)?;
+    }
+
+    let trap = translator.trap;
+    let mut builder = translator.builder;
+    builder.switch_to_block(exit);
+    if function.result.is_some() {
+        let result = builder.block_params(exit)[0];
+        let result = if builder.func.dfg.value_type(result) == types::I32 {
+            builder.ins().uextend(types::I64, result)
+        } else {
+            result
+        };
+        builder
+            .ins()
+            .store(MemFlags::trusted(), result, result_ptr, 0);
+    }
+    let ok = builder.ins().iconst(types::I32, 0);
+    builder.ins().return_(&[ok]);
+    if let Some(trap) = trap {
+        builder.switch_to_block(trap);
+        let trapped = builder.ins().iconst(types::I32, 1);
+        builder.ins().return_(&[trapped]);
+    }
+    builder.seal_all_blocks();
+    builder.finalize();
+    Ok(())
+}
+
+fn declare_local(builder: &mut FunctionBuilder, index: usize, ty: Type, value: Value) -> Variable {
+    let variable = Variable::from_u32(index as u32);
+    builder.declare_var(variable, ty);
+    builder.def_var(variable, value);
+    variable
+}
+
+fn value_type(byte: u8) -> Result<Type, Unsupported> {
+    match byte {
+        0x7f => Ok(types::I32),
+        0x7e => Ok(types::I64),
+        _ => Err(Unsupported),
+    }
+}
+
+fn block_type(byte: u8) -> Result<Option<Type>, Unsupported> {
+    match byte {
+        0x40 => Ok(None),
+        _ => value_type(byte).map(Some),
+    }
+}
+
+/// Returns the condition code of the integer comparison, relative to `eq` of its type.
+fn condition(offset: u8) -> IntCC {
+    [
+        IntCC::Equal,
+        IntCC::NotEqual,
+        IntCC::SignedLessThan,
+        IntCC::UnsignedLessThan,
+        IntCC::SignedGreaterThan,
+        IntCC::UnsignedGreaterThan,
+        IntCC::SignedLessThanOrEqual,
+        IntCC::UnsignedLessThanOrEqual,
+        IntCC::SignedGreaterThanOrEqual,
+        IntCC::UnsignedGreaterThanOrEqual,
+    ][offset as usize]
+}
+
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    locals: Vec<Variable>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    /// The block returning the trap status, created by the first trapping instruction and
+    /// filled at the end.
+    trap: Option<Block>,
+    /// The depth of the blocks nested in the unreachable code, `None` if the code is reachable.
+    unreachable_depth: Option<u32>,
+}
+
+impl Translator<'_> {
+    fn pop(&mut self) -> Result<Value, Unsupported> {
+        self.stack.pop().ok_or(Unsupported)
+    }
+
+    fn peek(&self) -> Result<Value, Unsupported> {
+        self.stack.last().copied().ok_or(Unsupported)
+    }
+
+    fn push_compare(&mut self, cc: IntCC, a: Value, b: Value) {
+        let flag = self.builder.ins().icmp(cc, a, b);
+        let flag = self.builder.ins().uextend(types::I32, flag);
+        self.stack.push(flag);
+    }
+
+    /// Create the constant of the integer type, from the value truncated to the type.
+    fn iconst(&mut self, ty: Type, value: i64) -> Value {
+        let value = if ty == types::I32 {
+            value as u32 as i64
+        } else {
+            value
+        };
+        self.builder.ins().iconst(ty, value)
+    }
+
+    fn trap_block(&mut self) -> Block {
+        match self.trap {
+            Some(trap) => trap,
+            None => {
+                let trap = self.builder.create_block();
+                self.trap = Some(trap);
+                trap
+            }
+        }
+    }
+
+    /// Branch to the trap block if the condition is non-zero.
+    fn trap_if(&mut self, condition: Value) {
+        let trap = self.trap_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, trap, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    /// Returns the target block of the branch to the frame of the label, with its arguments.
+    fn branch(&self, depth: u32) -> Result<(Block, Vec<Value>), Unsupported> {
+        let frame = &self.frames[self.frames.len() - 1 - depth as usize];
+        let (target, takes_result) = frame.branch_target();
+        let args = if takes_result {
+            vec![self.peek()?]
+        } else {
+            Vec::new()
+        };
+        Ok((target, args))
+    }
+
+    fn push_frame(
+        &mut self,
+        next: Block,
+        header: Option<Block>,
+        else_block: Option<Block>,
+        result: Option<Type>,
+    ) {
+        if let Some(result) = result {
+            self.builder.append_block_param(next, result);
+        }
+        self.frames.push(Frame {
+            next,
+            header,
+            else_block,
+            result,
+            height: self.stack.len(),
+        });
+    }
+
+    /// Jump from the end of the reachable code of the frame to the block following it.
+    fn jump_to_end(&mut self) -> Result<(), Unsupported> {
+        let frame = self.frames.last().expect("frame");
+        let args = if frame.result.is_some() {
+            vec![self.peek()?]
+        } else {
+            Vec::new()
+        };
+        let next = frame.next;
+        self.builder.ins().jump(next, &args);
+        Ok(())
+    }
+
+    fn instruction(&mut self, reader: &mut Reader) -> Result<(), Unsupported> {
+        let opcode = reader.u8()?;
+        if let Some(depth) = self.unreachable_depth {
+            // Only the structure of the unreachable code is followed.
+            return match opcode {
+                0x02..=0x04 => {
+                    block_type(reader.u8()?)?;
+                    self.unreachable_depth = Some(depth + 1);
+                    Ok(())
+                }
+                0x05 | 0x0b if depth == 0 => self.control(opcode, reader),
+                0x0b => {
+                    self.unreachable_depth = Some(depth - 1);
+                    Ok(())
+                }
+                _ => skip_immediates(opcode, reader),
+            };
+        }
+        match opcode {
+            0x00..=0x0f => return self.control(opcode, reader),
+            // drop
+            0x1a => {
+                self.pop()?;
+            }
+            // select
+            0x1b => {
+                let condition = self.pop()?;
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let value = self.builder.ins().select(condition, a, b);
+                self.stack.push(value);
+            }
+            // local.get, local.set, local.tee
+            0x20..=0x22 => {
+                let index = reader.uleb()?;
+                let variable = *self.locals.get(index as usize).ok_or(Unsupported)?;
+                match opcode {
+                    0x20 => {
+                        let value = self.builder.use_var(variable);
+                        self.stack.push(value);
+                    }
+                    0x21 => {
+                        let value = self.pop()?;
+                        self.builder.def_var(variable, value);
+                    }
+                    _ => {
+                        let value = self.peek()?;
+                        self.builder.def_var(variable, value);
+                    }
+                }
+            }
+            // i32.const
+            0x41 => {
+                let value = reader.sleb()?;
+                let value = self.iconst(types::I32, value);
+                self.stack.push(value);
+            }
+            // i64.const
+            0x42 => {
+                let value = reader.sleb()?;
+                let value = self.iconst(types::I64, value);
+                self.stack.push(value);
+            }
+            // i32.eqz, i64.eqz
+            0x45 | 0x50 => {
+                let a = self.pop()?;
+                let ty = self.builder.func.dfg.value_type(a);
+                let zero = self.iconst(ty, 0);
+                self.push_compare(IntCC::Equal, a, zero);
+            }
+            // i32 and i64 comparisons
+            0x46..=0x4f | 0x51..=0x5a => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let offset = if opcode < 0x50 {
+                    opcode - 0x46
+                } else {
+                    opcode - 0x51
+                };
+                self.push_compare(condition(offset), a, b);
+            }
+            // clz, ctz, popcnt
+            0x67..=0x69 | 0x79..=0x7b => {
+                let a = self.pop()?;
+                let value = match opcode {
+                    0x67 | 0x79 => self.builder.ins().clz(a),
+                    0x68 | 0x7a => self.builder.ins().ctz(a),
+                    _ => self.builder.ins().popcnt(a),
+                };
+                self.stack.push(value);
+            }
+            // i32 and i64 binary arithmetic
+            0x6a..=0x78 | 0x7c..=0x8a => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let operation = if opcode < 0x79 {
+                    opcode - 0x6a
+                } else {
+                    opcode - 0x7c
+                };
+                let value = self.binary(operation, a, b);
+                self.stack.push(value);
+            }
+            // i32.wrap_i64
+            0xa7 => {
+                let a = self.pop()?;
+                let value = self.builder.ins().ireduce(types::I32, a);
+                self.stack.push(value);
+            }
+            // i64.extend_i32_s
+            0xac => {
+                let a = self.pop()?;
+                let value = self.builder.ins().sextend(types::I64, a);
+                self.stack.push(value);
+            }
+            // i64.extend_i32_u
+            0xad => {
+                let a = self.pop()?;
+                let value = self.builder.ins().uextend(types::I64, a);
+                self.stack.push(value);
+            }
+            _ => return Err(Unsupported),
+        }
+        Ok(())
+    }
+
+    /// Translate the binary arithmetic operation, relative to `add` of its type.
+    fn binary(&mut self, operation: u8, a: Value, b: Value) -> Value {
+        let ty = self.builder.func.dfg.value_type(a);
+        let bits = ty.bits() as i64;
+        match operation {
+            0 => self.builder.ins().iadd(a, b),
+            1 => self.builder.ins().isub(a, b),
+            2 => self.builder.ins().imul(a, b),
+            // div_s, div_u, rem_s, rem_u: the traps are checked explicitly.
+            3..=6 => {
+                let zero = self.iconst(ty, 0);
+                let division_by_zero = self.builder.ins().icmp(IntCC::Equal, b, zero);
+                self.trap_if(division_by_zero);
+                let minus_one = self.iconst(ty, -1);
+                match operation {
+                    3 => {
+                        let min = self.iconst(ty, i64::MIN >> (64 - bits));
+                        let a_min = self.builder.ins().icmp(IntCC::Equal, a, min);
+                        let b_minus_one = self.builder.ins().icmp(IntCC::Equal, b, minus_one);
+                        let overflow = self.builder.ins().band(a_min, b_minus_one);
+                        self.trap_if(overflow);
+                        self.builder.ins().sdiv(a, b)
+                    }
+                    4 => self.builder.ins().udiv(a, b),
+                    5 => {
+                        // The remainder of the division by -1 is 0, also for the minimum value.
+                        let one = self.iconst(ty, 1);
+                        let b_minus_one = self.builder.ins().icmp(IntCC::Equal, b, minus_one);
+                        let divisor = self.builder.ins().select(b_minus_one, one, b);
+                        self.builder.ins().srem(a, divisor)
+                    }
+                    _ => self.builder.ins().urem(a, b),
+                }
+            }
+            7 => self.builder.ins().band(a, b),
+            8 => self.builder.ins().bor(a, b),
+            9 => self.builder.ins().bxor(a, b),
+            // The shift and rotation counts are taken modulo the bit width, as in wasm.
+            10 => self.builder.ins().ishl(a, b),
+            11 => self.builder.ins().sshr(a, b),
+            12 => self.builder.ins().ushr(a, b),
+            13 => self.builder.ins().rotl(a, b),
+            _ => self.builder.ins().rotr(a, b),
+        }
+    }
+
+    fn control(&mut self, opcode: u8, reader: &mut Reader) -> Result<(), Unsupported> {
+        match opcode {
+            // unreachable
+            0x00 => {
+                let trap = self.trap_block();
+                self.builder.ins().jump(trap, &[]);
+                self.unreachable_depth = Some(0);
+            }
+            // nop
+            0x01 => {}
+            // block
+            0x02 => {
+                let result = block_type(reader.u8()?)?;
+                let next = self.builder.create_block();
+                self.push_frame(next, None, None, result);
+            }
+            // loop
+            0x03 => {
+                let result = block_type(reader.u8()?)?;
+                let header = self.builder.create_block();
+                let next = self.builder.create_block();
+                self.builder.ins().jump(header, &[]);
+                self.builder.switch_to_block(header);
+                self.push_frame(next, Some(header), None, result);
+            }
+            // if
+            0x04 => {
+                let result = block_type(reader.u8()?)?;
+                let condition = self.pop()?;
+                let then_block = self.builder.create_block();
+                let else_block = self.builder.create_block();
+                let next = self.builder.create_block();
+                self.builder
+                    .ins()
+                    .brif(condition, then_block, &[], else_block, &[]);
+                self.builder.switch_to_block(then_block);
+                self.push_frame(next, None, Some(else_block), result);
+            }
+            // else
+            0x05 => {
+                if self.unreachable_depth.is_none() {
+                    self.jump_to_end()?;
+                }
+                let frame = self.frames.last_mut().expect("frame");
+                let else_block = frame.else_block.take().ok_or(Unsupported)?;
+                let height = frame.height;
+                self.stack.truncate(height);
+                self.builder.switch_to_block(else_block);
+                self.unreachable_depth = None;
+            }
+            // end
+            0x0b => {
+                if self.unreachable_depth.is_none() {
+                    self.jump_to_end()?;
+                }
+                let frame = self.frames.pop().expect("frame");
+                if let Some(else_block) = frame.else_block {
+                    // The missing else branch of an if without result.
+                    self.builder.switch_to_block(else_block);
+                    self.builder.ins().jump(frame.next, &[]);
+                }
+                self.stack.truncate(frame.height);
+                self.unreachable_depth = None;
+                if !self.frames.is_empty() {
+                    self.builder.switch_to_block(frame.next);
+                    self.stack
+                        .extend_from_slice(self.builder.block_params(frame.next));
+                }
+            }
+            // br
+            0x0c => {
+                let depth = reader.uleb()? as u32;
+                let (target, args) = self.branch(depth)?;
+                self.builder.ins().jump(target, &args);
+                self.unreachable_depth = Some(0);
+            }
+            // br_if
+            0x0d => {
+                let depth = reader.uleb()? as u32;
+                let condition = self.pop()?;
+                let (target, args) = self.branch(depth)?;
+                let next = self.builder.create_block();
+                self.builder.ins().brif(condition, target, &args, next, &[]);
+                self.builder.switch_to_block(next);
+            }
+            // br_table
+            0x0e => {
+                let mut depths = Vec::new();
+                for _ in 0..reader.uleb()? {
+                    depths.push(reader.uleb()? as u32);
+                }
+                let default_depth = reader.uleb()? as u32;
+                let index = self.pop()?;
+                // Every label gets an edge block passing the arguments of its target.
+                let edges = std::iter::once(default_depth)
+                    .chain(depths)
+                    .map(|depth| Ok((self.builder.create_block(), self.branch(depth)?)))
+                    .collect::<Result<Vec<_>, Unsupported>>()?;
+                let mut calls = edges
+                    .iter()
+                    .map(|&(edge, _)| self.builder.func.dfg.block_call(edge, &[]));
+                let default = calls.next().expect("default label");
+                let table: Vec<_> = calls.collect();
+                let jump_table = self
+                    .builder
+                    .create_jump_table(JumpTableData::new(default, &table));
+                self.builder.ins().br_table(index, jump_table);
+                for (edge, (target, args)) in edges {
+                    self.builder.switch_to_block(edge);
+                    self.builder.ins().jump(target, &args);
+                }
+                self.unreachable_depth = Some(0);
+            }
+            // return
+            0x0f => {
+                let (target, args) = self.branch(self.frames.len() as u32 - 1)?;
+                self.builder.ins().jump(target, &args);
+                self.unreachable_depth = Some(0);
+            }
+            _ => return Err(Unsupported),
+        }
+        Ok(())
+    }
+}
+
+/// Skip the immediates of an instruction of the unreachable code.
+fn skip_immediates(opcode: u8, reader: &mut Reader) -> Result<(), Unsupported> {
+    match opcode {
+        0x0c | 0x0d | 0x20..=0x22 => {
+            reader.uleb()?;
+        }
+        0x0e => {
+            for _ in 0..=reader.uleb()? {
+                reader.uleb()?;
+            }
+        }
+        0x41 | 0x42 => {
+            reader.sleb()?;
+        }
+        0x00 | 0x01 | 0x0f | 0x1a | 0x1b | 0x45..=0x5a | 0x67..=0x8a | 0xa7 | 0xac | 0xad => {}
+        _ => return Err(Unsupported),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{Config, Engine};
+    use crate::host::Imports;
+    use crate::test_utils::from_hex;
+    use crate::{parse, parse_with_baseline_tier, Instance, Value};
+
+    /* wat2wasm
+      (memory 1)
+      (func (export "fib") (param $n i32) (result i64) (local $a i64) (local $b i64)
+        (local.set $b (i64.const 1))
+        (block $done
+          (loop $next
+            (br_if $done (i32.eqz (local.get $n)))
+            (local.set $b (i64.add (local.get $a) (local.tee $a (local.get $b))))
+            (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+            (br $next)))
+        (local.get $a))
+      (func (export "div") (param i32 i32) (result i32)
+        (i32.div_s (local.get 0) (local.get 1)))
+      (func (export "rem") (param i64 i64) (result i64)
+        (i64.rem_s (local.get 0) (local.get 1)))
+      (func (export "switch") (param i32) (result i32)
+        (block $outer (result i32)
+          (i32.add
+            (block $inner (result i32)
+              (br_table $inner $outer $inner (i32.const 7) (local.get 0)))
+            (i32.const 100))))
+      (func (export "misc") (param i64 i32) (result i32)
+        (if (result i32) (i64.lt_s (local.get 0) (i64.const 0))
+          (then (i32.wrap_i64 (i64.rotl (local.get 0) (i64.extend_i32_u (local.get 1)))))
+          (else (select (i32.popcnt (local.get 1)) (i32.clz (local.get 1))
+            (i64.ge_u (local.get 0) (i64.const 1000))))))
+      (func (export "fail") (param i32) (result i32)
+        (if (local.get 0) (then unreachable))
+        (i32.const 1))
+      (func (export "load") (param i32) (result i32) (i32.load (local.get 0)))
+    */
+    const WASM: &[&str] = &[
+        "0061736d01000000011d0560017f017e60027f7f017f60027e7e017e60017f017f60027e7f017f03",
+        "08070001020304030305030100010731070366696200000364697600010372656d00020673776974",
+        "63680003046d6973630004046661696c0005046c6f616400060a8201072701027e42012102024003",
+        "402000450d012001200222017c2102200041016b21000c000b0b20010b0700200020016d0b070020",
+        "002001810b1500027f027f410720000e020001000b41e4006a0b0b1f002000420053047f20002001",
+        "ad89a705200169200167200042e8075a1b0b0b0a0020000440000b41010b070020002802000b",
+    ];
+
+    fn instantiate(hot_threshold: u32) -> Instance {
+        parse_with_baseline_tier(from_hex(WASM), hot_threshold)
+            .unwrap()
+            .instantiate()
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_interpreter() {
+        let mut compiled = instantiate(0);
+        let mut interpreted = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        let cases: &[(&str, &[Value])] = &[
+            ("fib", &[Value::I32(0)]),
+            ("fib", &[Value::I32(1)]),
+            ("fib", &[Value::I32(10)]),
+            ("fib", &[Value::I32(90)]),
+            ("div", &[Value::I32(7), Value::I32(-2)]),
+            ("div", &[Value::I32(i32::MIN), Value::I32(1)]),
+            ("div", &[Value::I32(i32::MIN), Value::I32(-1)]),
+            ("div", &[Value::I32(1), Value::I32(0)]),
+            ("rem", &[Value::I64(-7), Value::I64(3)]),
+            ("rem", &[Value::I64(i64::MIN), Value::I64(-1)]),
+            ("rem", &[Value::I64(1), Value::I64(0)]),
+            ("switch", &[Value::I32(0)]),
+            ("switch", &[Value::I32(1)]),
+            ("switch", &[Value::I32(2)]),
+            ("switch", &[Value::I32(-1)]),
+            ("misc", &[Value::I64(-3), Value::I32(33)]),
+            ("misc", &[Value::I64(i64::MIN), Value::I32(1)]),
+            ("misc", &[Value::I64(999), Value::I32(0xff)]),
+            ("misc", &[Value::I64(1000), Value::I32(0xff)]),
+            ("fail", &[Value::I32(0)]),
+            ("fail", &[Value::I32(1)]),
+        ];
+        for &(name, args) in cases {
+            let expected = interpreted.execute(name, args).unwrap();
+            let result = compiled.execute(name, args).unwrap();
+            assert_eq!(result.trapped(), expected.trapped(), "{} {:?}", name, args);
+            assert_eq!(result.value(), expected.value(), "{} {:?}", name, args);
+            assert_eq!(result.stack_trace(), expected.stack_trace());
+        }
+        for name in &["fib", "div", "rem", "switch", "misc", "fail"] {
+            let func_idx = compiled.find_exported_function_index(name).unwrap();
+            assert!(compiled.is_compiled(func_idx), "{}", name);
+        }
+        assert_eq!(
+            compiled.execute("fib", &[Value::I32(50)]).unwrap().value(),
+            Some(Value::I64(12_586_269_025))
+        );
+    }
+
+    #[test]
+    fn hot_threshold() {
+        let mut instance = instantiate(2);
+        let fib = instance.find_exported_function_index("fib").unwrap();
+        for _ in 0..2 {
+            let result = instance.execute("fib", &[Value::I32(20)]).unwrap();
+            assert_eq!(result.value(), Some(Value::I64(6765)));
+            assert!(!instance.is_compiled(fib));
+        }
+        let result = instance.execute("fib", &[Value::I32(20)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I64(6765)));
+        assert!(instance.is_compiled(fib));
+
+        let copy = instance.duplicate().unwrap();
+        assert!(!copy.is_compiled(fib));
+
+        // Without the tier, all functions are interpreted.
+        let mut instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        instance.execute("fib", &[Value::I32(20)]).unwrap();
+        assert!(!instance.is_compiled(fib));
+    }
+
+    #[test]
+    fn engine_config() {
+        let mut config = Config::new();
+        config.baseline_tier(1);
+        let engine = Engine::new(&config);
+        let module = engine.parse(from_hex(WASM)).unwrap();
+        let mut instance = engine.instantiate(module, Imports::new()).unwrap();
+        let fib = instance.find_exported_function_index("fib").unwrap();
+        for _ in 0..2 {
+            let result = instance.execute("fib", &[Value::I32(20)]).unwrap();
+            assert_eq!(result.value(), Some(Value::I64(6765)));
+        }
+        assert!(instance.is_compiled(fib));
+
+        // Without the tier, all functions are interpreted.
+        let module = Engine::default().parse(from_hex(WASM)).unwrap();
+        let mut instance = Engine::default()
+            .instantiate(module, Imports::new())
+            .unwrap();
+        instance.execute("fib", &[Value::I32(20)]).unwrap();
+        instance.execute("fib", &[Value::I32(20)]).unwrap();
+        assert!(!instance.is_compiled(fib));
+    }
+
+    #[test]
+    fn interpreted_functions() {
+        let mut instance = instantiate(0);
+        // The memory accesses are not compiled.
+        let result = instance.execute("load", &[Value::I32(0)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0)));
+        let load = instance.find_exported_function_index("load").unwrap();
+        assert!(!instance.is_compiled(load));
+
+        // The hooks observe the interpreted instructions.
+        let fib = instance.find_exported_function_index("fib").unwrap();
+        let result = instance
+            .execute_with_coverage("fib", &[Value::I32(3)])
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I64(2)));
+        assert!(!instance.is_compiled(fib));
+        assert!(instance.coverage().instruction_offsets(fib).count() > 0);
+
+        instance.on_progress(1, |_| true);
+        instance.execute("fib", &[Value::I32(3)]).unwrap();
+        assert!(!instance.is_compiled(fib));
+        instance.remove_progress_callback();
+        instance.execute("fib", &[Value::I32(3)]).unwrap();
+        assert!(instance.is_compiled(fib));
+    }
+}
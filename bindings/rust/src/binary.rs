@@ -0,0 +1,253 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decoding and encoding of the wasm binary format, for the tools working on raw binaries.
+
+use crate::Error;
+
+/// The magic number and version of wasm binaries.
+pub(crate) const HEADER: &[u8] = b"\0asm\x01\0\0\0";
+
+/// Split the wasm binary into sections, returning their ids and payloads.
+pub(crate) fn sections(wasm: &[u8]) -> Result<Vec<(u8, &[u8])>, Error> {
+    let mut reader = Reader::new(wasm);
+    let header = reader.bytes(8).map_err(|_| Error::ParsingFailed)?;
+    if header != HEADER {
+        return Err(Error::ParsingFailed);
+    }
+    let mut sections = Vec::new();
+    while !reader.is_empty() {
+        let section = (|| {
+            let id = reader.u8()?;
+            let size = usize_from(reader.uleb()?)?;
+            Ok((id, reader.bytes(size)?))
+        })()
+        .map_err(|_: Malformed| Error::ParsingFailed)?;
+        sections.push(section);
+    }
+    Ok(sections)
+}
+
+pub(crate) fn count_imported_functions(payload: &[u8]) -> Result<u32, Error> {
+    let mut reader = Reader::new(payload);
+    (|| {
+        let mut count = 0;
+        for _ in 0..reader.uleb()? {
+            reader.name()?;
+            reader.name()?;
+            match reader.u8()? {
+                // Function: type index.
+                0 => {
+                    reader.uleb()?;
+                    count += 1;
+                }
+                // Table: element type and limits.
+                1 => {
+                    reader.u8()?;
+                    reader.limits()?;
+                }
+                // Memory: limits.
+                2 => reader.limits()?,
+                // Global: value type and mutability.
+                3 => {
+                    reader.bytes(2)?;
+                }
+                _ => return Err(Malformed),
+            }
+        }
+        Ok(count)
+    })()
+    .map_err(|_| Error::ParsingFailed)
+}
+
+pub(crate) struct Malformed;
+
+pub(crate) fn usize_from(value: u64) -> Result<usize, Malformed> {
+    if value > usize::MAX as u64 {
+        Err(Malformed)
+    } else {
+        Ok(value as usize)
+    }
+}
+
+/// A little-endian reader of wasm and DWARF encodings.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Reader { data, position: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.position..];
+        self.position = self.data.len();
+        rest
+    }
+
+    pub(crate) fn bytes(&mut self, size: usize) -> Result<&'a [u8], Malformed> {
+        if size > self.data.len() - self.position {
+            return Err(Malformed);
+        }
+        let bytes = &self.data[self.position..self.position + size];
+        self.position += size;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, Malformed> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, Malformed> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Malformed> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, Malformed> {
+        Ok(self.u32()? as u64 | (self.u32()? as u64) << 32)
+    }
+
+    /// Read a 4- or 8-byte section offset.
+    pub(crate) fn offset(&mut self, offset_size: usize) -> Result<u64, Malformed> {
+        if offset_size == 8 {
+            self.u64()
+        } else {
+            Ok(self.u32()? as u64)
+        }
+    }
+
+    pub(crate) fn uleb(&mut self) -> Result<u64, Malformed> {
+        let mut result = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                result |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+    }
+
+    pub(crate) fn sleb(&mut self) -> Result<i64, Malformed> {
+        let mut result = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                result |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= -1 << shift;
+                }
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Read a null-terminated string.
+    pub(crate) fn cstr(&mut self) -> Result<&'a str, Malformed> {
+        let rest = &self.data[self.position..];
+        let length = rest.iter().position(|&b| b == 0).ok_or(Malformed)?;
+        self.position += length + 1;
+        std::str::from_utf8(&rest[..length]).map_err(|_| Malformed)
+    }
+
+    /// Read a length-prefixed wasm name.
+    pub(crate) fn name(&mut self) -> Result<&'a str, Malformed> {
+        let length = usize_from(self.uleb()?)?;
+        std::str::from_utf8(self.bytes(length)?).map_err(|_| Malformed)
+    }
+
+    pub(crate) fn limits(&mut self) -> Result<(), Malformed> {
+        let has_max = self.u8()? != 0;
+        self.uleb()?;
+        if has_max {
+            self.uleb()?;
+        }
+        Ok(())
+    }
+}
+
+/// Append the unsigned LEB128 encoding of the value.
+pub(crate) fn write_uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Append the signed LEB128 encoding of the value.
+pub(crate) fn write_sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Append the section of the given id and payload.
+pub(crate) fn write_section(out: &mut Vec<u8>, id: u8, payload: &[u8]) {
+    out.push(id);
+    write_uleb(out, payload.len() as u64);
+    out.extend_from_slice(payload);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leb128() {
+        for &value in &[0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u32::MAX as u64, u64::MAX] {
+            let mut out = Vec::new();
+            write_uleb(&mut out, value);
+            let mut reader = Reader::new(&out);
+            assert_eq!(reader.uleb().ok(), Some(value));
+            assert!(reader.is_empty());
+        }
+        for &value in &[0, 1, -1, 0x3f, 0x40, -0x40, -0x41, i64::MIN, i64::MAX] {
+            let mut out = Vec::new();
+            write_sleb(&mut out, value);
+            let mut reader = Reader::new(&out);
+            assert_eq!(reader.sleb().ok(), Some(value));
+            assert!(reader.is_empty());
+        }
+
+        let mut out = Vec::new();
+        write_uleb(&mut out, 624485);
+        assert_eq!(out, [0xe5, 0x8e, 0x26]);
+        let mut out = Vec::new();
+        write_sleb(&mut out, -123456);
+        assert_eq!(out, [0xc0, 0xbb, 0x78]);
+    }
+}
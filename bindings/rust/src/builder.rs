@@ -0,0 +1,186 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Instantiation of a module configured step by step.
+//!
+//! The [`InstantiateBuilder`] collects the imports and the [`InstantiateOptions`] of
+//! an instantiation, e.g.
+//! `module.instantiate_builder().import_fn("env", "log", log).memory_limit(64).build()`.
+
+use crate::host::{HostFunction, Imports, SharedInstance};
+use crate::memory::{self, Memory};
+use crate::{Error, Instance, InstantiateOptions, MemoryBacking, Module};
+use std::sync::Arc;
+
+/// A builder of an instance of a module, created with [`Module::instantiate_builder()`].
+pub struct InstantiateBuilder {
+    module: Module,
+    imports: Imports,
+    options: InstantiateOptions,
+}
+
+impl Module {
+    /// Start building an instance of the module, without any imports and with
+    /// the default options.
+    pub fn instantiate_builder(self) -> InstantiateBuilder {
+        InstantiateBuilder {
+            module: self,
+            imports: Imports::new(),
+            options: InstantiateOptions::default(),
+        }
+    }
+}
+
+impl InstantiateBuilder {
+    /// Import the host function under the `module` and `name`, see [`Imports::define()`].
+    pub fn import_fn(mut self, module: &str, name: &str, func: HostFunction) -> Self {
+        self.imports.define(module, name, func);
+        self
+    }
+
+    /// Import the memory under the `module` and `name`, see [`Imports::define_memory()`].
+    pub fn import_memory(mut self, module: &str, name: &str, memory: &Memory) -> Self {
+        self.imports.define_memory(module, name, memory);
+        self
+    }
+
+    /// Import the exports of the instance under the `module`, see [`Imports::define_instance()`].
+    pub fn import_instance(mut self, module: &str, instance: &SharedInstance) -> Self {
+        self.imports.define_instance(module, instance);
+        self
+    }
+
+    /// Replace all the imports defined so far with the `imports`.
+    pub fn imports(mut self, imports: Imports) -> Self {
+        self.imports = imports;
+        self
+    }
+
+    /// Set the hard limit of the memory size in pages.
+    pub fn memory_limit(mut self, pages: u32) -> Self {
+        self.options.memory_pages_limit = Some(pages);
+        self
+    }
+
+    /// Set the maximum depth of nested calls.
+    pub fn stack_depth(mut self, depth: u32) -> Self {
+        self.options.max_call_depth = Some(depth);
+        self
+    }
+
+    /// Set the kind of allocation backing the memory defined by the module.
+    pub fn memory_backing(mut self, backing: MemoryBacking) -> Self {
+        self.options.memory_backing = backing;
+        self
+    }
+
+    /// Set the allocator of the memory defined by the module.
+    pub fn memory_allocator(mut self, allocator: Arc<dyn memory::Allocator>) -> Self {
+        self.options.memory_allocator = Some(allocator);
+        self
+    }
+
+    /// Do not run the start function by the instantiation, see [`Instance::run_start()`].
+    pub fn defer_start(mut self) -> Self {
+        self.options.defer_start = true;
+        self
+    }
+
+    /// Replace all the options set so far with the `options`.
+    pub fn options(mut self, options: InstantiateOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Create the instance.
+    pub fn build(self) -> Result<Instance, Error> {
+        self.module
+            .instantiate_with_options(self.imports, &self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::host::HostFunction;
+    use crate::linker::FunctionType;
+    use crate::test_utils::from_hex;
+    use crate::{parse, Error, Value, ValueType};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn build() {
+        /* wat2wasm
+          (func $log (import "env" "log") (param i32))
+          (memory 1)
+          (func (export "grow") (param i32) (result i32)
+            (call $log (local.get 0))
+            (memory.grow (local.get 0))
+          )
+        */
+        let input = from_hex(&[
+            "0061736d01000000010a0260017f0060017f017f020b0103656e76036c6f67000003020101050301",
+            "00010708010467726f7700010a0c010a0020001000200040000b",
+        ]);
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let log = {
+            let logged = logged.clone();
+            HostFunction::new(
+                FunctionType {
+                    inputs: vec![ValueType::I32],
+                    output: None,
+                },
+                move |args| {
+                    logged.lock().unwrap().push(args[0]);
+                    Ok(None)
+                },
+            )
+        };
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_builder()
+            .import_fn("env", "log", log)
+            .memory_limit(2)
+            .stack_depth(512)
+            .build()
+            .unwrap();
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(-1)));
+        assert_eq!(*logged.lock().unwrap(), [Value::I32(1), Value::I32(1)]);
+
+        let result = parse(&input).unwrap().instantiate_builder().build();
+        assert_eq!(
+            result.err(),
+            Some(Error::UnknownImport {
+                module: "env".to_string(),
+                name: "log".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn stack_depth() {
+        /* wat2wasm
+          (func (export "f") (param i32) (result i32)
+            (if (result i32) (local.get 0)
+              (then (call 0 (i32.sub (local.get 0) (i32.const 1))))
+              (else (i32.const 0))))
+        */
+        let input = from_hex(&[
+            "0061736d0100000001060160017f017f03020100070501016600000a130111002000047f200041016b",
+            "10000541000b0b",
+        ]);
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_builder()
+            .stack_depth(10)
+            .build()
+            .unwrap();
+        let result = instance.execute("f", &[Value::I32(10)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0)));
+        let result = instance.execute("f", &[Value::I32(11)]).unwrap();
+        assert!(result.trapped());
+    }
+}
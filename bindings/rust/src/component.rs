@@ -0,0 +1,658 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Experimental support for component-model value types.
+//!
+//! This is a layer over a core [`Instance`](crate::Instance) which lifts and lowers rich values
+//! (strings, lists and records) according to the
+//! [canonical ABI](https://github.com/WebAssembly/component-model/blob/main/design/mvp/CanonicalABI.md).
+//! Only a subset of the canonical ABI is supported: the value types listed in [`Type`],
+//! UTF-8 string encoding, and memory allocation via an exported `cabi_realloc` function.
+
+use crate::{Error, Instance};
+use std::convert::TryFrom;
+
+/// The maximum number of core parameters passed directly. Larger parameter lists are passed in memory.
+const MAX_FLAT_PARAMS: usize = 16;
+/// The maximum number of core results returned directly. Larger results are returned in memory.
+const MAX_FLAT_RESULTS: usize = 1;
+
+/// A component-model value type.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Bool,
+    S32,
+    U32,
+    S64,
+    U64,
+    F32,
+    F64,
+    String,
+    List(Box<Type>),
+    Record(Vec<(String, Type)>),
+}
+
+/// A component-model value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    S32(i32),
+    U32(u32),
+    S64(i64),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    String(String),
+    List(Vec<Value>),
+    Record(Vec<(String, Value)>),
+}
+
+/// The type of a component-level function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FuncType {
+    pub params: Vec<Type>,
+    pub result: Option<Type>,
+}
+
+fn align_to(offset: u32, alignment: u32) -> u32 {
+    (offset + alignment - 1) & !(alignment - 1)
+}
+
+/// Returns the address `ptr + offset`, failing if it overflows the 32-bit address space.
+fn add_offset(ptr: u32, offset: u32) -> Result<u32, Error> {
+    ptr.checked_add(offset)
+        .ok_or(Error::InvalidMemoryOffsetOrSize)
+}
+
+/// Returns the size in bytes of `len` elements of `item_size` bytes, failing if it overflows
+/// the 32-bit address space.
+fn sequence_size(len: u32, item_size: u32) -> Result<u32, Error> {
+    len.checked_mul(item_size)
+        .ok_or(Error::InvalidMemoryOffsetOrSize)
+}
+
+impl Type {
+    fn alignment(&self) -> u32 {
+        match self {
+            Type::Bool => 1,
+            Type::S32 | Type::U32 | Type::F32 => 4,
+            Type::S64 | Type::U64 | Type::F64 => 8,
+            Type::String | Type::List(_) => 4,
+            Type::Record(fields) => fields
+                .iter()
+                .map(|(_, ty)| ty.alignment())
+                .max()
+                .unwrap_or(1),
+        }
+    }
+
+    fn size(&self) -> u32 {
+        match self {
+            Type::Bool => 1,
+            Type::S32 | Type::U32 | Type::F32 => 4,
+            Type::S64 | Type::U64 | Type::F64 => 8,
+            Type::String | Type::List(_) => 8,
+            Type::Record(fields) => {
+                let size = fields.iter().fold(0, |offset, (_, ty)| {
+                    align_to(offset, ty.alignment()) + ty.size()
+                });
+                align_to(size, self.alignment())
+            }
+        }
+    }
+
+    /// Appends the core value types this type is represented with to `out`.
+    fn flatten(&self, out: &mut Vec<crate::sys::FizzyValueType>) {
+        use crate::sys::*;
+        match self {
+            Type::Bool | Type::S32 | Type::U32 => out.push(FizzyValueTypeI32),
+            Type::S64 | Type::U64 => out.push(FizzyValueTypeI64),
+            Type::F32 => out.push(FizzyValueTypeF32),
+            Type::F64 => out.push(FizzyValueTypeF64),
+            Type::String | Type::List(_) => {
+                out.push(FizzyValueTypeI32);
+                out.push(FizzyValueTypeI32);
+            }
+            Type::Record(fields) => fields.iter().for_each(|(_, ty)| ty.flatten(out)),
+        }
+    }
+
+    fn flat_count(&self) -> usize {
+        let mut flat = Vec::new();
+        self.flatten(&mut flat);
+        flat.len()
+    }
+}
+
+impl Value {
+    /// Returns true if the value is of the given type.
+    pub fn matches(&self, ty: &Type) -> bool {
+        match (self, ty) {
+            (Value::Bool(_), Type::Bool)
+            | (Value::S32(_), Type::S32)
+            | (Value::U32(_), Type::U32)
+            | (Value::S64(_), Type::S64)
+            | (Value::U64(_), Type::U64)
+            | (Value::F32(_), Type::F32)
+            | (Value::F64(_), Type::F64)
+            | (Value::String(_), Type::String) => true,
+            (Value::List(items), Type::List(item_type)) => {
+                items.iter().all(|item| item.matches(item_type))
+            }
+            (Value::Record(values), Type::Record(fields)) => {
+                values.len() == fields.len()
+                    && values.iter().zip(fields.iter()).all(
+                        |((name, value), (field_name, field_type))| {
+                            name == field_name && value.matches(field_type)
+                        },
+                    )
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A core instance called through the canonical ABI.
+///
+/// The instance must export its memory and a `cabi_realloc` function
+/// of type `(i32, i32, i32, i32) -> i32` whenever strings or lists are passed in.
+pub struct ComponentInstance {
+    instance: Instance,
+}
+
+impl ComponentInstance {
+    /// Wraps a core instance.
+    pub fn new(instance: Instance) -> Self {
+        ComponentInstance { instance }
+    }
+
+    /// Returns the underlying core instance.
+    pub fn into_inner(self) -> Instance {
+        self.instance
+    }
+
+    /// Calls the exported function `name` of the given component-level type.
+    ///
+    /// The arguments are lowered to core values (allocating strings and lists in the instance
+    /// memory) and the result is lifted back from the core result.
+    pub fn call(
+        &mut self,
+        name: &str,
+        func_type: &FuncType,
+        args: &[Value],
+    ) -> Result<Option<Value>, Error> {
+        if args.len() != func_type.params.len() {
+            return Err(Error::ArgumentCountMismatch);
+        }
+        if !args
+            .iter()
+            .zip(func_type.params.iter())
+            .all(|(arg, ty)| arg.matches(ty))
+        {
+            return Err(Error::ComponentTypeMismatch);
+        }
+
+        let flat_count: usize = func_type.params.iter().map(Type::flat_count).sum();
+        let mut core_args = Vec::with_capacity(flat_count);
+        if flat_count <= MAX_FLAT_PARAMS {
+            for (arg, ty) in args.iter().zip(func_type.params.iter()) {
+                self.lower_flat(arg, ty, &mut core_args)?;
+            }
+        } else {
+            // Pass all arguments in memory, laid out as a record.
+            let tuple_type = Type::Record(
+                func_type
+                    .params
+                    .iter()
+                    .map(|ty| (String::new(), ty.clone()))
+                    .collect(),
+            );
+            let tuple = Value::Record(
+                args.iter()
+                    .map(|arg| (String::new(), arg.clone()))
+                    .collect(),
+            );
+            let ptr = self.realloc(tuple_type.alignment(), tuple_type.size())?;
+            self.store(&tuple, &tuple_type, ptr)?;
+            core_args.push(crate::Value::I32(ptr as i32));
+        }
+
+        let result = self.instance.execute(name, &core_args)?.into_result()?;
+
+        match &func_type.result {
+            None => Ok(None),
+            Some(ty) if ty.flat_count() <= MAX_FLAT_RESULTS => {
+                let mut values = result.into_iter();
+                self.lift_flat(ty, &mut values).map(Some)
+            }
+            Some(ty) => match result {
+                Some(crate::Value::I32(ptr)) => self.load(ty, ptr as u32).map(Some),
+                _ => Err(Error::ComponentTypeMismatch),
+            },
+        }
+    }
+
+    /// Allocates `size` bytes in the instance memory using the exported `cabi_realloc`.
+    fn realloc(&mut self, alignment: u32, size: u32) -> Result<u32, Error> {
+        let args = [
+            crate::Value::I32(0),
+            crate::Value::I32(0),
+            crate::Value::I32(alignment as i32),
+            crate::Value::I32(size as i32),
+        ];
+        match self
+            .instance
+            .execute("cabi_realloc", &args)?
+            .into_result()?
+        {
+            Some(crate::Value::I32(ptr)) => Ok(ptr as u32),
+            _ => Err(Error::ComponentTypeMismatch),
+        }
+    }
+
+    /// Copies a string or list of the given element layout into a new allocation.
+    /// Returns the pointer and the number of elements.
+    fn lower_sequence(&mut self, value: &Value, ty: &Type) -> Result<(u32, u32), Error> {
+        match (value, ty) {
+            (Value::String(s), Type::String) => {
+                let len = u32::try_from(s.len()).map_err(|_| Error::InvalidMemoryOffsetOrSize)?;
+                let ptr = self.realloc(1, len)?;
+                self.instance.memory_set(ptr, s.as_bytes())?;
+                Ok((ptr, len))
+            }
+            (Value::List(items), Type::List(item_type)) => {
+                let len =
+                    u32::try_from(items.len()).map_err(|_| Error::InvalidMemoryOffsetOrSize)?;
+                let item_size = item_type.size();
+                let ptr = self.realloc(item_type.alignment(), sequence_size(len, item_size)?)?;
+                for (i, item) in (0..len).zip(items.iter()) {
+                    self.store(item, item_type, add_offset(ptr, i * item_size)?)?;
+                }
+                Ok((ptr, len))
+            }
+            _ => Err(Error::ComponentTypeMismatch),
+        }
+    }
+
+    fn lower_flat(
+        &mut self,
+        value: &Value,
+        ty: &Type,
+        out: &mut Vec<crate::Value>,
+    ) -> Result<(), Error> {
+        match (ty, value) {
+            (Type::Bool, Value::Bool(v)) => out.push(crate::Value::I32(*v as i32)),
+            (Type::S32, Value::S32(v)) => out.push(crate::Value::I32(*v)),
+            (Type::U32, Value::U32(v)) => out.push(crate::Value::I32(*v as i32)),
+            (Type::S64, Value::S64(v)) => out.push(crate::Value::I64(*v)),
+            (Type::U64, Value::U64(v)) => out.push(crate::Value::I64(*v as i64)),
+            (Type::F32, Value::F32(v)) => out.push(crate::Value::F32(*v)),
+            (Type::F64, Value::F64(v)) => out.push(crate::Value::F64(*v)),
+            (Type::String, _) | (Type::List(_), _) => {
+                let (ptr, len) = self.lower_sequence(value, ty)?;
+                out.push(crate::Value::I32(ptr as i32));
+                out.push(crate::Value::I32(len as i32));
+            }
+            (Type::Record(fields), Value::Record(values)) if values.len() == fields.len() => {
+                for ((_, value), (_, field_type)) in values.iter().zip(fields.iter()) {
+                    self.lower_flat(value, field_type, out)?;
+                }
+            }
+            _ => return Err(Error::ComponentTypeMismatch),
+        }
+        Ok(())
+    }
+
+    fn store(&mut self, value: &Value, ty: &Type, ptr: u32) -> Result<(), Error> {
+        match (ty, value) {
+            (Type::Bool, Value::Bool(v)) => self.instance.memory_set(ptr, &[*v as u8]),
+            (Type::S32, Value::S32(v)) => self.instance.memory_set(ptr, &v.to_le_bytes()),
+            (Type::U32, Value::U32(v)) => self.instance.memory_set(ptr, &v.to_le_bytes()),
+            (Type::S64, Value::S64(v)) => self.instance.memory_set(ptr, &v.to_le_bytes()),
+            (Type::U64, Value::U64(v)) => self.instance.memory_set(ptr, &v.to_le_bytes()),
+            (Type::F32, Value::F32(v)) => self.instance.memory_set(ptr, &v.to_le_bytes()),
+            (Type::F64, Value::F64(v)) => self.instance.memory_set(ptr, &v.to_le_bytes()),
+            (Type::String, _) | (Type::List(_), _) => {
+                let (data_ptr, len) = self.lower_sequence(value, ty)?;
+                self.instance.memory_set(ptr, &data_ptr.to_le_bytes())?;
+                self.instance
+                    .memory_set(add_offset(ptr, 4)?, &len.to_le_bytes())
+            }
+            (Type::Record(fields), Value::Record(values)) if values.len() == fields.len() => {
+                let mut offset = 0;
+                for ((_, value), (_, field_type)) in values.iter().zip(fields.iter()) {
+                    offset = align_to(offset, field_type.alignment());
+                    self.store(value, field_type, add_offset(ptr, offset)?)?;
+                    offset += field_type.size();
+                }
+                Ok(())
+            }
+            _ => Err(Error::ComponentTypeMismatch),
+        }
+    }
+
+    fn load_u8(&self, ptr: u32) -> Result<u8, Error> {
+        let mut bytes = [0u8; 1];
+        self.instance.memory_get(ptr, &mut bytes)?;
+        Ok(bytes[0])
+    }
+
+    fn load_4_bytes(&self, ptr: u32) -> Result<[u8; 4], Error> {
+        let mut bytes = [0u8; 4];
+        self.instance.memory_get(ptr, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn load_8_bytes(&self, ptr: u32) -> Result<[u8; 8], Error> {
+        let mut bytes = [0u8; 8];
+        self.instance.memory_get(ptr, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Checks that `size` bytes at `ptr` are within the instance memory.
+    fn check_range(&self, ptr: u32, size: u32) -> Result<(), Error> {
+        match (ptr as usize).checked_add(size as usize) {
+            Some(end) if end <= self.instance.memory_size() => Ok(()),
+            _ => Err(Error::InvalidMemoryOffsetOrSize),
+        }
+    }
+
+    /// Reads a string or list of the given type from the pointer and number of elements.
+    ///
+    /// The pointer and the number of elements come from the instance, so the whole sequence is
+    /// checked to be within the memory before anything is allocated for it. Elements of zero size
+    /// are counted as one byte, so that a guest cannot make the host allocate more elements
+    /// than the memory has bytes.
+    fn lift_sequence(&self, ty: &Type, ptr: u32, len: u32) -> Result<Value, Error> {
+        match ty {
+            Type::String => {
+                self.check_range(ptr, len)?;
+                let mut bytes = vec![0u8; len as usize];
+                self.instance.memory_get(ptr, &mut bytes)?;
+                String::from_utf8(bytes)
+                    .map(Value::String)
+                    .map_err(|_| Error::InvalidUtf8)
+            }
+            Type::List(item_type) => {
+                let item_size = item_type.size();
+                self.check_range(ptr, sequence_size(len, item_size.max(1))?)?;
+                let items = (0..len)
+                    .map(|i| self.load(item_type, add_offset(ptr, i * item_size)?))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::List(items))
+            }
+            _ => Err(Error::ComponentTypeMismatch),
+        }
+    }
+
+    fn load(&self, ty: &Type, ptr: u32) -> Result<Value, Error> {
+        Ok(match ty {
+            Type::Bool => Value::Bool(self.load_u8(ptr)? != 0),
+            Type::S32 => Value::S32(i32::from_le_bytes(self.load_4_bytes(ptr)?)),
+            Type::U32 => Value::U32(u32::from_le_bytes(self.load_4_bytes(ptr)?)),
+            Type::S64 => Value::S64(i64::from_le_bytes(self.load_8_bytes(ptr)?)),
+            Type::U64 => Value::U64(u64::from_le_bytes(self.load_8_bytes(ptr)?)),
+            Type::F32 => Value::F32(f32::from_le_bytes(self.load_4_bytes(ptr)?)),
+            Type::F64 => Value::F64(f64::from_le_bytes(self.load_8_bytes(ptr)?)),
+            Type::String | Type::List(_) => {
+                let data_ptr = u32::from_le_bytes(self.load_4_bytes(ptr)?);
+                let len = u32::from_le_bytes(self.load_4_bytes(add_offset(ptr, 4)?)?);
+                self.lift_sequence(ty, data_ptr, len)?
+            }
+            Type::Record(fields) => {
+                let mut offset = 0;
+                let mut values = Vec::with_capacity(fields.len());
+                for (name, field_type) in fields {
+                    offset = align_to(offset, field_type.alignment());
+                    values.push((
+                        name.clone(),
+                        self.load(field_type, add_offset(ptr, offset)?)?,
+                    ));
+                    offset += field_type.size();
+                }
+                Value::Record(values)
+            }
+        })
+    }
+
+    fn lift_flat(
+        &self,
+        ty: &Type,
+        values: &mut dyn Iterator<Item = crate::Value>,
+    ) -> Result<Value, Error> {
+        use crate::Value as Core;
+        if let Type::Record(fields) = ty {
+            // A record consumes the flat values of its fields, none if it is empty.
+            let mut result = Vec::with_capacity(fields.len());
+            for (name, field_type) in fields {
+                result.push((name.clone(), self.lift_flat(field_type, values)?));
+            }
+            return Ok(Value::Record(result));
+        }
+        let mut next = || values.next().ok_or(Error::ComponentTypeMismatch);
+        Ok(match (ty, next()?) {
+            (Type::Bool, Core::I32(v)) => Value::Bool(v != 0),
+            (Type::S32, Core::I32(v)) => Value::S32(v),
+            (Type::U32, Core::I32(v)) => Value::U32(v as u32),
+            (Type::S64, Core::I64(v)) => Value::S64(v),
+            (Type::U64, Core::I64(v)) => Value::U64(v as u64),
+            (Type::F32, Core::F32(v)) => Value::F32(v),
+            (Type::F64, Core::F64(v)) => Value::F64(v),
+            (Type::String, Core::I32(ptr)) | (Type::List(_), Core::I32(ptr)) => match next()? {
+                Core::I32(len) => self.lift_sequence(ty, ptr as u32, len as u32)?,
+                _ => return Err(Error::ComponentTypeMismatch),
+            },
+            _ => return Err(Error::ComponentTypeMismatch),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+
+    /* wat2wasm
+      (memory (export "memory") 1)
+      (global $heap (mut i32) (i32.const 1024))
+      (func (export "cabi_realloc") (param i32 i32 i32 i32) (result i32)
+        (local.tee 0 (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                              (i32.sub (i32.const 0) (local.get 2))))
+        (global.set $heap (i32.add (local.get 0) (local.get 3)))
+      )
+      (func (export "len") (param i32 i32) (result i32) local.get 1)
+      (func (export "echo") (param i32 i32) (result i32)
+        (i32.store (i32.const 16) (local.get 0))
+        (i32.store (i32.const 20) (local.get 1))
+        i32.const 16
+      )
+      (func (export "point_sum") (param i32 f64) (result f64)
+        (f64.add (f64.convert_i32_s (local.get 0)) (local.get 1))
+      )
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001150360047f7f7f7f017f60027f7f017f60027f7c017c030504000101020503",
+        "0100010607017f014180080b073205066d656d6f727902000c636162695f7265616c6c6f63000003",
+        "6c656e0001046563686f000209706f696e745f73756d00030a3c0419002300200241016b6a410020",
+        "026b712200200020036a24000b040020010b1200411020003602004114200136020041100b080020",
+        "00b72001a00b",
+    ];
+
+    fn instance() -> ComponentInstance {
+        let input = from_hex(WASM);
+        ComponentInstance::new(parse(&input).unwrap().instantiate().unwrap())
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(Type::Bool.size(), 1);
+        assert_eq!(Type::String.size(), 8);
+        let record = Type::Record(vec![
+            ("a".to_string(), Type::Bool),
+            ("b".to_string(), Type::F64),
+            ("c".to_string(), Type::U32),
+        ]);
+        assert_eq!(record.alignment(), 8);
+        assert_eq!(record.size(), 24);
+        assert_eq!(record.flat_count(), 3);
+    }
+
+    #[test]
+    fn call_with_string() {
+        let mut instance = instance();
+        let func_type = FuncType {
+            params: vec![Type::String],
+            result: Some(Type::U32),
+        };
+        let result = instance
+            .call("len", &func_type, &[Value::String("hello".to_string())])
+            .unwrap();
+        assert_eq!(result, Some(Value::U32(5)));
+
+        assert_eq!(
+            instance.call("len", &func_type, &[Value::U32(1)]).err(),
+            Some(Error::ComponentTypeMismatch)
+        );
+        assert_eq!(
+            instance.call("len", &func_type, &[]).err(),
+            Some(Error::ArgumentCountMismatch)
+        );
+    }
+
+    #[test]
+    fn string_and_list_round_trip() {
+        let mut instance = instance();
+        let func_type = FuncType {
+            params: vec![Type::String],
+            result: Some(Type::String),
+        };
+        let arg = Value::String("Fizzy ✓".to_string());
+        let result = instance
+            .call("echo", &func_type, std::slice::from_ref(&arg))
+            .unwrap();
+        assert_eq!(result, Some(arg));
+
+        let list_type = Type::List(Box::new(Type::U32));
+        let func_type = FuncType {
+            params: vec![list_type.clone()],
+            result: Some(list_type),
+        };
+        let arg = Value::List(vec![Value::U32(1), Value::U32(2), Value::U32(0xffffffff)]);
+        let result = instance
+            .call("echo", &func_type, std::slice::from_ref(&arg))
+            .unwrap();
+        assert_eq!(result, Some(arg));
+    }
+
+    #[test]
+    fn call_with_record() {
+        let mut instance = instance();
+        let point = Type::Record(vec![
+            ("x".to_string(), Type::S32),
+            ("y".to_string(), Type::F64),
+        ]);
+        let func_type = FuncType {
+            params: vec![point],
+            result: Some(Type::F64),
+        };
+        let arg = Value::Record(vec![
+            ("x".to_string(), Value::S32(-2)),
+            ("y".to_string(), Value::F64(0.5)),
+        ]);
+        let result = instance.call("point_sum", &func_type, &[arg]).unwrap();
+        assert_eq!(result, Some(Value::F64(-1.5)));
+    }
+
+    #[test]
+    fn lift_out_of_bounds() {
+        let mut instance = instance();
+        // The string of 4 GiB - 1 bytes is rejected before allocating a buffer for it.
+        instance
+            .instance
+            .memory_set(16, &[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff])
+            .unwrap();
+        assert_eq!(
+            instance.load(&Type::String, 16).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert_eq!(
+            instance.lift_sequence(&Type::String, 65535, 2).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+
+        // The size of the list overflows the address space.
+        let list_type = Type::List(Box::new(Type::U64));
+        assert_eq!(
+            instance.lift_sequence(&list_type, 0, 0x2000_0000).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert_eq!(
+            instance.lift_sequence(&list_type, 8, 8192).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+
+        // Each element of zero size is counted as one byte of the memory.
+        let empty_list_type = Type::List(Box::new(Type::Record(vec![])));
+        assert_eq!(
+            instance.lift_sequence(&empty_list_type, 0, 65536),
+            Ok(Value::List(vec![Value::Record(vec![]); 65536]))
+        );
+        assert_eq!(
+            instance.lift_sequence(&empty_list_type, 0, u32::MAX).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+
+        // The addresses of the length and of the record fields overflow.
+        assert_eq!(
+            instance.load(&Type::String, u32::MAX - 3).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+        let record = Type::Record(vec![
+            ("a".to_string(), Type::Bool),
+            ("b".to_string(), Type::U32),
+        ]);
+        assert_eq!(
+            instance.load(&record, u32::MAX).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+    }
+
+    #[test]
+    fn empty_record() {
+        let instance = instance();
+        let empty = Type::Record(vec![]);
+        assert_eq!(empty.flat_count(), 0);
+        let mut values = std::iter::once(crate::Value::I32(1));
+        assert_eq!(
+            instance.lift_flat(&empty, &mut values),
+            Ok(Value::Record(vec![]))
+        );
+        assert_eq!(values.next(), Some(crate::Value::I32(1)));
+    }
+
+    #[test]
+    fn lower_type_mismatch() {
+        let mut instance = instance();
+        let mut out = Vec::new();
+        assert_eq!(
+            instance.lower_flat(&Value::U32(1), &Type::S32, &mut out),
+            Err(Error::ComponentTypeMismatch)
+        );
+        assert_eq!(
+            instance.lower_flat(&Value::Record(vec![]), &Type::String, &mut out),
+            Err(Error::ComponentTypeMismatch)
+        );
+        assert!(out.is_empty());
+        let point = Type::Record(vec![("x".to_string(), Type::S32)]);
+        assert_eq!(
+            instance.store(&Value::Record(vec![]), &point, 16),
+            Err(Error::ComponentTypeMismatch)
+        );
+        assert_eq!(
+            instance.store(&Value::F64(1.0), &Type::F32, 16),
+            Err(Error::ComponentTypeMismatch)
+        );
+    }
+}
@@ -0,0 +1,396 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Collection of the instructions executed by an instance, to measure test coverage of wasm code.
+//!
+//! Executions with [`Instance::execute_with_coverage()`] add every executed instruction to
+//! the [`Coverage`] of the instance, identified by a function index and an instruction offset
+//! as for [breakpoints](crate::debug::Breakpoints).
+//!
+//! A [`CoverageReport`] relates the coverage to the functions of the module, named from the name
+//! section and located in the source code with the [DWARF debug info](crate::dwarf). It can be
+//! written in the lcov tracefile format, e.g. for `genhtml`, or as JSON.
+
+use crate::dwarf::{DebugInfo, Location};
+use crate::record::json_string;
+use crate::{function_name, sys, Error, ExecutionResult, Instance, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+/// The instructions executed at least once, by function index.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Coverage {
+    functions: BTreeMap<u32, BTreeSet<u32>>,
+}
+
+impl Coverage {
+    /// Check if the instruction at `instruction_offset` in the function `function_index` has been
+    /// executed.
+    pub fn is_covered(&self, function_index: u32, instruction_offset: u32) -> bool {
+        self.functions
+            .get(&function_index)
+            .is_some_and(|offsets| offsets.contains(&instruction_offset))
+    }
+
+    /// The indices of the functions with any instruction executed, in ascending order.
+    pub fn functions(&self) -> impl Iterator<Item = u32> + '_ {
+        self.functions.keys().copied()
+    }
+
+    /// The offsets of the executed instructions of the function, in ascending order.
+    pub fn instruction_offsets(&self, function_index: u32) -> impl Iterator<Item = u32> + '_ {
+        self.functions
+            .get(&function_index)
+            .into_iter()
+            .flat_map(|offsets| offsets.iter().copied())
+    }
+
+    /// The number of executed instructions of all functions.
+    pub fn len(&self) -> usize {
+        self.functions.values().map(BTreeSet::len).sum()
+    }
+
+    /// Check if no instruction has been executed.
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    fn add(&mut self, function_index: u32, instruction_offset: u32) {
+        self.functions
+            .entry(function_index)
+            .or_default()
+            .insert(instruction_offset);
+    }
+}
+
+/// The coverage of a function defined in the module.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionCoverage {
+    /// The function index, including imported functions in the index space.
+    pub index: u32,
+    /// The name from the name section, or `func[<index>]` if the function has no name.
+    pub name: String,
+    /// The source location of the function, if known from the debug info.
+    pub location: Option<Location>,
+    /// The number of instructions of the function.
+    pub instruction_count: u32,
+    /// The offsets of the executed instructions, in ascending order.
+    pub covered: Vec<u32>,
+}
+
+impl FunctionCoverage {
+    /// Check if any instruction of the function has been executed.
+    pub fn is_hit(&self) -> bool {
+        !self.covered.is_empty()
+    }
+}
+
+/// The coverage of all functions defined in a module, in function index order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoverageReport {
+    functions: Vec<FunctionCoverage>,
+}
+
+impl CoverageReport {
+    /// The coverage of the functions.
+    pub fn functions(&self) -> &[FunctionCoverage] {
+        &self.functions
+    }
+
+    /// Write the report in the lcov tracefile format.
+    ///
+    /// There is a record for every source file, listing its functions with their hit counts
+    /// and a line for the first line of every function. Execution counts are not collected,
+    /// so the counts are 1 for functions executed at least once, and 0 otherwise.
+    /// The functions without a source location are listed in the record of `module_path`,
+    /// with their function index as the line number.
+    pub fn write_lcov<W: Write>(&self, mut writer: W, module_path: &str) -> std::io::Result<()> {
+        let mut files: BTreeMap<&str, Vec<(u32, &FunctionCoverage)>> = BTreeMap::new();
+        for function in &self.functions {
+            let (file, line) = match &function.location {
+                Some(location) => (location.file.as_str(), location.line),
+                None => (module_path, function.index),
+            };
+            files.entry(file).or_default().push((line, function));
+        }
+
+        for (file, functions) in files {
+            writeln!(writer, "SF:{}", file)?;
+            for (line, function) in &functions {
+                writeln!(writer, "FN:{},{}", line, function.name)?;
+            }
+            for (_, function) in &functions {
+                writeln!(
+                    writer,
+                    "FNDA:{},{}",
+                    u32::from(function.is_hit()),
+                    function.name
+                )?;
+            }
+            let hit = functions.iter().filter(|(_, f)| f.is_hit()).count();
+            writeln!(writer, "FNF:{}", functions.len())?;
+            writeln!(writer, "FNH:{}", hit)?;
+            for (line, function) in &functions {
+                writeln!(writer, "DA:{},{}", line, u32::from(function.is_hit()))?;
+            }
+            writeln!(writer, "LF:{}", functions.len())?;
+            writeln!(writer, "LH:{}", hit)?;
+            writeln!(writer, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// Write the report as a JSON object, e.g.:
+    ///
+    /// ```text
+    /// {"functions":[{"index":0,"name":"abs","file":"abs.c","line":3,"instructions":11,"covered":[0,1,2]}]}
+    /// ```
+    ///
+    /// The `file` and `line` are `null` if the location of the function is not known.
+    pub fn write_json<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let functions: Vec<String> = self
+            .functions
+            .iter()
+            .map(|function| {
+                let (file, line) = match &function.location {
+                    Some(location) => (json_string(&location.file), location.line.to_string()),
+                    None => ("null".to_string(), "null".to_string()),
+                };
+                let covered: Vec<String> = function.covered.iter().map(u32::to_string).collect();
+                format!(
+                    "{{\"index\":{},\"name\":{},\"file\":{},\"line\":{},\"instructions\":{},\"covered\":[{}]}}",
+                    function.index,
+                    json_string(&function.name),
+                    file,
+                    line,
+                    function.instruction_count,
+                    covered.join(",")
+                )
+            })
+            .collect();
+        writeln!(writer, "{{\"functions\":[{}]}}", functions.join(","))
+    }
+}
+
+impl Instance {
+    /// Execute an exported function, adding the executed instructions to the coverage of
+    /// the instance.
+    pub fn execute_with_coverage(
+        &mut self,
+        name: &str,
+        args: &[Value],
+    ) -> Result<ExecutionResult, Error> {
+        let func_idx = self
+            .find_exported_function_index(name)
+            .ok_or(Error::FunctionNotFound)?;
+
+        let mut coverage = std::mem::take(&mut self.1.coverage);
+        let mut hook = |state: &sys::FizzyExecutionState| {
+            coverage.add(state.func_idx, state.instr_offset);
+            true
+        };
+        let result = self.execute_function_with_hook(func_idx, args, &mut hook);
+        self.1.coverage = coverage;
+        result
+    }
+
+    /// The instructions executed with [`Instance::execute_with_coverage()`] so far.
+    pub fn coverage(&self) -> &Coverage {
+        &self.1.coverage
+    }
+
+    /// Forget the instructions executed so far.
+    pub fn reset_coverage(&mut self) {
+        self.1.coverage = Coverage::default();
+    }
+
+    /// Create the report of the coverage of the functions defined in the module,
+    /// locating them with the optional debug info of the module.
+    pub fn coverage_report(&self, debug_info: Option<&DebugInfo>) -> CoverageReport {
+        let module = self.module();
+        let functions = (0..unsafe { sys::fizzy_get_function_count(module) })
+            .filter_map(|index| {
+                let instruction_count = unsafe {
+                    sys::fizzy_get_function_opcodes(module, index, std::ptr::null_mut(), 0)
+                };
+                // Imported functions have no instructions.
+                if instruction_count == 0 {
+                    return None;
+                }
+                Some(FunctionCoverage {
+                    index,
+                    name: function_name(module, index)
+                        .unwrap_or_else(|| format!("func[{}]", index)),
+                    location: debug_info.and_then(|info| info.function_location(index)),
+                    instruction_count: instruction_count as u32,
+                    covered: self.1.coverage.instruction_offsets(index).collect(),
+                })
+            })
+            .collect();
+        CoverageReport { functions }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+
+    /* wat2wasm --debug-names
+      (func $abs (param i32) (result i32)
+        (if (result i32) (i32.lt_s (local.get 0) (i32.const 0))
+          (then (i32.sub (i32.const 0) (local.get 0)))
+          (else (local.get 0))
+        )
+      )
+      (func (export "abs") (param i32) (result i32) (call $abs (local.get 0)))
+      (func (export "unused"))
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001090260017f017f60000003040300000107100203616273000106756e757365",
+        "6400020a1e0312002000410048047f410020006b0520000b0b0600200010000b02000b000d046e61",
+        "6d650106010003616273",
+    ];
+
+    #[test]
+    fn coverage() {
+        let mut instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        assert!(instance.coverage().is_empty());
+
+        // Executions without coverage are not collected.
+        instance.execute("abs", &[Value::I32(-1)]).unwrap();
+        assert!(instance.coverage().is_empty());
+
+        let result = instance
+            .execute_with_coverage("abs", &[Value::I32(5)])
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(5)));
+        assert_eq!(instance.coverage().functions().collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(
+            instance
+                .coverage()
+                .instruction_offsets(0)
+                .collect::<Vec<_>>(),
+            [0, 1, 2, 3, 8, 9, 10]
+        );
+        assert!(!instance.coverage().is_covered(0, 6));
+        assert_eq!(instance.coverage().instruction_offsets(2).count(), 0);
+
+        instance
+            .execute_with_coverage("abs", &[Value::I32(-5)])
+            .unwrap();
+        assert!(instance.coverage().is_covered(0, 6));
+        assert_eq!(instance.coverage().len(), 14);
+
+        assert_eq!(
+            instance.execute_with_coverage("none", &[]).err(),
+            Some(Error::FunctionNotFound)
+        );
+        instance.reset_coverage();
+        assert!(instance.coverage().is_empty());
+    }
+
+    #[test]
+    fn report() {
+        let mut instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        instance
+            .execute_with_coverage("abs", &[Value::I32(5)])
+            .unwrap();
+
+        let report = instance.coverage_report(None);
+        assert_eq!(
+            report.functions(),
+            [
+                FunctionCoverage {
+                    index: 0,
+                    name: "abs".to_string(),
+                    location: None,
+                    instruction_count: 11,
+                    covered: vec![0, 1, 2, 3, 8, 9, 10],
+                },
+                FunctionCoverage {
+                    index: 1,
+                    name: "func[1]".to_string(),
+                    location: None,
+                    instruction_count: 3,
+                    covered: vec![0, 1, 2],
+                },
+                FunctionCoverage {
+                    index: 2,
+                    name: "func[2]".to_string(),
+                    location: None,
+                    instruction_count: 1,
+                    covered: vec![],
+                },
+            ]
+        );
+
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            concat!(
+                "{\"functions\":[",
+                "{\"index\":0,\"name\":\"abs\",\"file\":null,\"line\":null,",
+                "\"instructions\":11,\"covered\":[0,1,2,3,8,9,10]},",
+                "{\"index\":1,\"name\":\"func[1]\",\"file\":null,\"line\":null,",
+                "\"instructions\":3,\"covered\":[0,1,2]},",
+                "{\"index\":2,\"name\":\"func[2]\",\"file\":null,\"line\":null,",
+                "\"instructions\":1,\"covered\":[]}]}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn lcov() {
+        let function = |index, name: &str, line: Option<u32>, covered| FunctionCoverage {
+            index,
+            name: name.to_string(),
+            location: line.map(|line| Location {
+                file: "src/main.c".to_string(),
+                line,
+                column: 1,
+            }),
+            instruction_count: 4,
+            covered,
+        };
+        let report = CoverageReport {
+            functions: vec![
+                function(1, "main", Some(3), vec![0, 1]),
+                function(2, "helper", Some(8), vec![]),
+                function(3, "func[3]", None, vec![0]),
+            ],
+        };
+
+        let mut lcov = Vec::new();
+        report.write_lcov(&mut lcov, "module.wasm").unwrap();
+        assert_eq!(
+            String::from_utf8(lcov).unwrap(),
+            concat!(
+                "SF:module.wasm\n",
+                "FN:3,func[3]\n",
+                "FNDA:1,func[3]\n",
+                "FNF:1\n",
+                "FNH:1\n",
+                "DA:3,1\n",
+                "LF:1\n",
+                "LH:1\n",
+                "end_of_record\n",
+                "SF:src/main.c\n",
+                "FN:3,main\n",
+                "FN:8,helper\n",
+                "FNDA:1,main\n",
+                "FNDA:0,helper\n",
+                "FNF:2\n",
+                "FNH:1\n",
+                "DA:3,1\n",
+                "DA:8,0\n",
+                "LF:2\n",
+                "LH:1\n",
+                "end_of_record\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,801 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Debug execution mode.
+//!
+//! In debug mode the execution pauses before an instruction and hands control to the host,
+//! which can inspect the current function, instruction offset, operand stack and locals,
+//! and decide whether to [`step()`](DebugState::step) to the next instruction,
+//! [`continue_()`](DebugState::continue_) to the end, or [`abort()`](DebugState::abort).
+//! The execution can also run freely until one of the [`Breakpoints`] is hit,
+//! or until an instruction accesses the memory watched by one of the [`Watchpoints`].
+
+use crate::{sys, Error, ExecutionResult, Instance, UntypedValue, Value};
+use std::collections::HashMap;
+
+/// What to do after the execution has been paused.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugAction {
+    /// Execute the next instruction and pause again (stepping into calls).
+    Step,
+    /// Continue the execution without pausing.
+    Continue,
+    /// Abort the execution, which results in a trap.
+    Abort,
+}
+
+/// The state of a paused execution.
+pub struct DebugState<'a> {
+    state: &'a sys::FizzyExecutionState,
+    action: DebugAction,
+}
+
+impl<'a> DebugState<'a> {
+    /// The index of the function being executed.
+    pub fn function_index(&self) -> u32 {
+        self.state.func_idx
+    }
+
+    /// The offset of the next instruction in the function's instruction array.
+    ///
+    /// Instructions are counted, not bytes, so this is not an offset into the wasm binary.
+    pub fn instruction_offset(&self) -> u32 {
+        self.state.instr_offset
+    }
+
+    /// The opcode of the next instruction.
+    pub fn opcode(&self) -> u8 {
+        self.state.opcode
+    }
+
+    /// The call depth, 0 for the function the execution was started with.
+    pub fn depth(&self) -> i32 {
+        self.state.depth
+    }
+
+    /// The arguments and local variables of the function.
+    pub fn locals(&self) -> &'a [UntypedValue] {
+        unsafe { untyped_slice(self.state.locals, self.state.locals_size) }
+    }
+
+    /// The operand stack, the bottom item first.
+    pub fn operand_stack(&self) -> &'a [UntypedValue] {
+        unsafe { untyped_slice(self.state.stack, self.state.stack_size) }
+    }
+
+    /// Execute the next instruction and pause again. This is the default action.
+    pub fn step(&mut self) {
+        self.action = DebugAction::Step;
+    }
+
+    /// Continue the execution without pausing.
+    pub fn continue_(&mut self) {
+        self.action = DebugAction::Continue;
+    }
+
+    /// Abort the execution with a trap.
+    pub fn abort(&mut self) {
+        self.action = DebugAction::Abort;
+    }
+
+    /// The action chosen so far.
+    pub fn action(&self) -> DebugAction {
+        self.action
+    }
+
+    /// The memory access of the next instruction, if it is a load or a store.
+    pub fn memory_access(&self) -> Option<MemoryAccess> {
+        let (size, kind) = match self.state.opcode {
+            0x28 | 0x2a | 0x34 | 0x35 => (4, AccessKind::Read),
+            0x29 | 0x2b => (8, AccessKind::Read),
+            0x2c | 0x2d | 0x30 | 0x31 => (1, AccessKind::Read),
+            0x2e | 0x2f | 0x32 | 0x33 => (2, AccessKind::Read),
+            0x36 | 0x38 | 0x3e => (4, AccessKind::Write),
+            0x37 | 0x39 => (8, AccessKind::Write),
+            0x3a | 0x3c => (1, AccessKind::Write),
+            0x3b | 0x3d => (2, AccessKind::Write),
+            _ => return None,
+        };
+        // The address is below the stored value.
+        let stack = self.operand_stack();
+        let address = match kind {
+            AccessKind::Read => stack[stack.len() - 1],
+            AccessKind::Write => stack[stack.len() - 2],
+        };
+        Some(MemoryAccess {
+            memory_idx: self.state.memory_idx,
+            address: u64::from(address.as_u32()) + u64::from(self.state.memory_offset),
+            size,
+            kind,
+        })
+    }
+}
+
+/// The kind of a memory access.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessKind {
+    /// A load instruction.
+    Read,
+    /// A store instruction.
+    Write,
+}
+
+/// A memory access of a load or store instruction about to be executed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryAccess {
+    memory_idx: u32,
+    address: u64,
+    size: u32,
+    kind: AccessKind,
+}
+
+impl MemoryAccess {
+    /// The index of the accessed memory.
+    pub fn memory_index(&self) -> u32 {
+        self.memory_idx
+    }
+
+    /// The effective address of the access, which may be out of the bounds of the memory.
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// The number of bytes accessed.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Whether the access reads or writes the memory.
+    pub fn kind(&self) -> AccessKind {
+        self.kind
+    }
+}
+
+unsafe fn untyped_slice<'a>(data: *const sys::FizzyValue, size: usize) -> &'a [UntypedValue] {
+    if size == 0 {
+        &[]
+    } else {
+        // UntypedValue is a transparent wrapper of FizzyValue.
+        std::slice::from_raw_parts(data as *const UntypedValue, size)
+    }
+}
+
+type Condition<'a> = dyn FnMut(&DebugState) -> bool + 'a;
+
+/// A set of breakpoints, each identified by a function index and an instruction offset.
+///
+/// A breakpoint may have a condition, which is evaluated when the breakpoint is reached
+/// and pauses the execution only if it returns true.
+#[derive(Default)]
+pub struct Breakpoints<'a> {
+    breakpoints: HashMap<(u32, u32), Option<Box<Condition<'a>>>>,
+}
+
+impl<'a> Breakpoints<'a> {
+    /// Create an empty set of breakpoints.
+    pub fn new() -> Self {
+        Breakpoints {
+            breakpoints: HashMap::new(),
+        }
+    }
+
+    /// Set a breakpoint before the instruction at `instruction_offset` in the function `function_index`.
+    pub fn set(&mut self, function_index: u32, instruction_offset: u32) {
+        self.breakpoints
+            .insert((function_index, instruction_offset), None);
+    }
+
+    /// Set a breakpoint which pauses the execution only if `condition` returns true.
+    pub fn set_conditional<F>(&mut self, function_index: u32, instruction_offset: u32, condition: F)
+    where
+        F: FnMut(&DebugState) -> bool + 'a,
+    {
+        self.breakpoints.insert(
+            (function_index, instruction_offset),
+            Some(Box::new(condition)),
+        );
+    }
+
+    /// Remove a breakpoint. Returns false if there was no breakpoint at the given location.
+    pub fn remove(&mut self, function_index: u32, instruction_offset: u32) -> bool {
+        self.breakpoints
+            .remove(&(function_index, instruction_offset))
+            .is_some()
+    }
+
+    /// Check if there is a breakpoint at the given location.
+    pub fn contains(&self, function_index: u32, instruction_offset: u32) -> bool {
+        self.breakpoints
+            .contains_key(&(function_index, instruction_offset))
+    }
+
+    /// The number of breakpoints.
+    pub fn len(&self) -> usize {
+        self.breakpoints.len()
+    }
+
+    /// Check if there are no breakpoints.
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    fn hit(&mut self, state: &DebugState) -> bool {
+        match self
+            .breakpoints
+            .get_mut(&(state.function_index(), state.instruction_offset()))
+        {
+            None => false,
+            Some(None) => true,
+            Some(Some(condition)) => condition(state),
+        }
+    }
+}
+
+/// The accesses a watchpoint reacts to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Watch {
+    /// Loads only.
+    Read,
+    /// Stores only.
+    Write,
+    /// Both loads and stores.
+    ReadWrite,
+}
+
+impl Watch {
+    fn matches(self, kind: AccessKind) -> bool {
+        match self {
+            Watch::Read => kind == AccessKind::Read,
+            Watch::Write => kind == AccessKind::Write,
+            Watch::ReadWrite => true,
+        }
+    }
+}
+
+type WatchCondition<'a> = dyn FnMut(&MemoryAccess, &DebugState) -> bool + 'a;
+
+struct Watchpoint<'a> {
+    memory_idx: u32,
+    begin: u64,
+    end: u64,
+    watch: Watch,
+    condition: Option<Box<WatchCondition<'a>>>,
+}
+
+/// A set of watchpoints, each watching the accesses of a range of one of the instance memories.
+///
+/// An instruction accessing any byte of a watched range hits the watchpoint. A watchpoint may have
+/// a condition, which is evaluated when the watchpoint is hit and pauses the execution only if it
+/// returns true. A condition returning false serves as a callback observing the accesses.
+#[derive(Default)]
+pub struct Watchpoints<'a> {
+    watchpoints: Vec<Watchpoint<'a>>,
+}
+
+impl<'a> Watchpoints<'a> {
+    /// Create an empty set of watchpoints.
+    pub fn new() -> Self {
+        Watchpoints {
+            watchpoints: Vec::new(),
+        }
+    }
+
+    /// Watch the `size` bytes of memory starting at `offset`, replacing a watchpoint of the same
+    /// range.
+    pub fn set(&mut self, offset: u32, size: u32, watch: Watch) {
+        self.set_at(0, offset, size, watch);
+    }
+
+    /// Watch the range of the memory of the index, see [`Watchpoints::set()`].
+    pub fn set_at(&mut self, memory_idx: u32, offset: u32, size: u32, watch: Watch) {
+        self.insert(memory_idx, offset, size, watch, None);
+    }
+
+    /// Watch the range of memory, pausing the execution only if `condition` returns true.
+    pub fn set_conditional<F>(&mut self, offset: u32, size: u32, watch: Watch, condition: F)
+    where
+        F: FnMut(&MemoryAccess, &DebugState) -> bool + 'a,
+    {
+        self.set_conditional_at(0, offset, size, watch, condition);
+    }
+
+    /// Watch the range of the memory of the index, pausing the execution only if `condition`
+    /// returns true.
+    pub fn set_conditional_at<F>(
+        &mut self,
+        memory_idx: u32,
+        offset: u32,
+        size: u32,
+        watch: Watch,
+        condition: F,
+    ) where
+        F: FnMut(&MemoryAccess, &DebugState) -> bool + 'a,
+    {
+        self.insert(memory_idx, offset, size, watch, Some(Box::new(condition)));
+    }
+
+    fn insert(
+        &mut self,
+        memory_idx: u32,
+        offset: u32,
+        size: u32,
+        watch: Watch,
+        condition: Option<Box<WatchCondition<'a>>>,
+    ) {
+        self.remove_at(memory_idx, offset, size);
+        let begin = u64::from(offset);
+        self.watchpoints.push(Watchpoint {
+            memory_idx,
+            begin,
+            end: begin + u64::from(size),
+            watch,
+            condition,
+        });
+    }
+
+    /// Remove the watchpoint of the range. Returns false if the range was not watched.
+    pub fn remove(&mut self, offset: u32, size: u32) -> bool {
+        self.remove_at(0, offset, size)
+    }
+
+    /// Remove the watchpoint of the range of the memory of the index. Returns false if the range
+    /// was not watched.
+    pub fn remove_at(&mut self, memory_idx: u32, offset: u32, size: u32) -> bool {
+        let begin = u64::from(offset);
+        let end = begin + u64::from(size);
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| {
+            watchpoint.memory_idx != memory_idx
+                || watchpoint.begin != begin
+                || watchpoint.end != end
+        });
+        self.watchpoints.len() != len
+    }
+
+    /// The number of watchpoints.
+    pub fn len(&self) -> usize {
+        self.watchpoints.len()
+    }
+
+    /// Check if there are no watchpoints.
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    fn hit(&mut self, state: &DebugState) -> bool {
+        if self.watchpoints.is_empty() {
+            return false;
+        }
+        let access = match state.memory_access() {
+            Some(access) => access,
+            None => return false,
+        };
+        let access_end = access.address + u64::from(access.size);
+        // Every condition of the hit watchpoints is evaluated.
+        let mut pause = false;
+        for watchpoint in &mut self.watchpoints {
+            if access.memory_idx == watchpoint.memory_idx
+                && access.address < watchpoint.end
+                && watchpoint.begin < access_end
+                && watchpoint.watch.matches(access.kind)
+            {
+                pause |= match &mut watchpoint.condition {
+                    None => true,
+                    Some(condition) => condition(&access, state),
+                };
+            }
+        }
+        pause
+    }
+}
+
+impl Instance {
+    /// Execute an exported function in debug mode.
+    ///
+    /// The execution pauses before the first instruction and `on_pause` is called.
+    /// It keeps pausing before every following instruction as long as `on_pause` chooses to step.
+    pub fn execute_debug<F>(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        on_pause: F,
+    ) -> Result<ExecutionResult, Error>
+    where
+        F: FnMut(&mut DebugState),
+    {
+        self.execute_debug_impl(
+            name,
+            args,
+            true,
+            &mut Breakpoints::new(),
+            &mut Watchpoints::new(),
+            on_pause,
+        )
+    }
+
+    /// Execute an exported function in debug mode, pausing at the given breakpoints.
+    ///
+    /// When a breakpoint is hit `on_pause` is called. Stepping from there pauses before every
+    /// following instruction, continuing runs until the next breakpoint is hit.
+    pub fn execute_with_breakpoints<F>(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        breakpoints: &mut Breakpoints,
+        on_pause: F,
+    ) -> Result<ExecutionResult, Error>
+    where
+        F: FnMut(&mut DebugState),
+    {
+        self.execute_debug_impl(
+            name,
+            args,
+            false,
+            breakpoints,
+            &mut Watchpoints::new(),
+            on_pause,
+        )
+    }
+
+    /// Execute an exported function in debug mode, pausing before the instructions accessing
+    /// the memory of the given watchpoints.
+    ///
+    /// When a watchpoint is hit `on_pause` is called, with [`DebugState::memory_access()`]
+    /// describing the access. Stepping and continuing work as with breakpoints.
+    pub fn execute_with_watchpoints<F>(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        watchpoints: &mut Watchpoints,
+        on_pause: F,
+    ) -> Result<ExecutionResult, Error>
+    where
+        F: FnMut(&mut DebugState),
+    {
+        self.execute_debug_impl(
+            name,
+            args,
+            false,
+            &mut Breakpoints::new(),
+            watchpoints,
+            on_pause,
+        )
+    }
+
+    fn execute_debug_impl<F>(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        mut stepping: bool,
+        breakpoints: &mut Breakpoints,
+        watchpoints: &mut Watchpoints,
+        mut on_pause: F,
+    ) -> Result<ExecutionResult, Error>
+    where
+        F: FnMut(&mut DebugState),
+    {
+        let func_idx = self
+            .find_exported_function_index(name)
+            .ok_or(Error::FunctionNotFound)?;
+
+        let mut hook = |state: &sys::FizzyExecutionState| {
+            let mut debug_state = DebugState {
+                state,
+                action: DebugAction::Step,
+            };
+            if !stepping && !breakpoints.hit(&debug_state) && !watchpoints.hit(&debug_state) {
+                return true;
+            }
+            on_pause(&mut debug_state);
+            match debug_state.action {
+                DebugAction::Step => {
+                    stepping = true;
+                    true
+                }
+                DebugAction::Continue => {
+                    stepping = false;
+                    true
+                }
+                DebugAction::Abort => false,
+            }
+        };
+        self.execute_function_with_hook(func_idx, args, &mut hook)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::{from_hex, parse_multi_memory};
+
+    /* wat2wasm
+      (func $div (export "div") (param i32 i32) (result i32)
+        (i32.div_u (local.get 0) (local.get 1))
+      )
+      (func (export "main") (param i32) (result i32)
+        (call $div (local.get 0) (i32.const 2))
+      )
+    */
+    const WASM: &[&str] = &[
+        "0061736d01000000010c0260027f7f017f60017f017f0303020001070e02036469760000046d6169",
+        "6e00010a12020700200020016e0b08002000410210000b",
+    ];
+
+    fn instance() -> Instance {
+        parse(from_hex(WASM)).unwrap().instantiate().unwrap()
+    }
+
+    #[test]
+    fn single_step() {
+        let mut instance = instance();
+        let mut trace = Vec::new();
+        let result = instance
+            .execute_debug("div", &[Value::I32(42), Value::I32(2)], |state| {
+                trace.push((
+                    state.instruction_offset(),
+                    state.opcode(),
+                    state
+                        .operand_stack()
+                        .iter()
+                        .map(|v| v.as_i32())
+                        .collect::<Vec<_>>(),
+                ));
+                assert_eq!(state.function_index(), 0);
+                assert_eq!(state.depth(), 0);
+                assert_eq!(state.locals().len(), 2);
+                assert_eq!(state.locals()[0].as_i32(), 42);
+                assert_eq!(state.action(), DebugAction::Step);
+            })
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(21)));
+        assert_eq!(
+            trace,
+            vec![
+                (0, 0x20, vec![]),
+                (1, 0x20, vec![42]),
+                (2, 0x6e, vec![42, 2]),
+                (3, 0x0b, vec![21]),
+            ]
+        );
+    }
+
+    #[test]
+    fn step_into_call_and_continue() {
+        let mut instance = instance();
+        let mut pauses = Vec::new();
+        let result = instance
+            .execute_debug("main", &[Value::I32(10)], |state| {
+                pauses.push((state.function_index(), state.depth()));
+                // Continue once inside the called function.
+                if state.function_index() == 0 {
+                    state.continue_();
+                }
+            })
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(5)));
+        assert_eq!(pauses, vec![(1, 0), (1, 0), (1, 0), (0, 1)]);
+    }
+
+    #[test]
+    fn abort() {
+        let mut instance = instance();
+        let result = instance
+            .execute_debug("div", &[Value::I32(42), Value::I32(2)], |state| {
+                if state.instruction_offset() == 2 {
+                    state.abort();
+                }
+            })
+            .unwrap();
+        assert!(result.trapped());
+
+        // The hook is removed after the execution.
+        let result = instance
+            .execute("div", &[Value::I32(42), Value::I32(2)])
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(21)));
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut instance = instance();
+        let mut breakpoints = Breakpoints::new();
+        assert!(breakpoints.is_empty());
+        breakpoints.set(0, 2);
+        breakpoints.set(1, 0);
+        breakpoints.set(1, 3);
+        assert_eq!(breakpoints.len(), 3);
+        assert!(breakpoints.remove(1, 0));
+        assert!(!breakpoints.remove(1, 0));
+        assert!(!breakpoints.contains(1, 0));
+        assert!(breakpoints.contains(0, 2));
+
+        let mut pauses = Vec::new();
+        let result = instance
+            .execute_with_breakpoints("main", &[Value::I32(10)], &mut breakpoints, |state| {
+                pauses.push((
+                    state.function_index(),
+                    state.instruction_offset(),
+                    state.operand_stack().len(),
+                ));
+                state.continue_();
+            })
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(5)));
+        assert_eq!(pauses, vec![(0, 2, 2), (1, 3, 1)]);
+
+        // Stepping from a breakpoint pauses at the following instructions.
+        let mut pauses = Vec::new();
+        breakpoints.remove(1, 3);
+        let result = instance
+            .execute_with_breakpoints("main", &[Value::I32(10)], &mut breakpoints, |state| {
+                pauses.push((state.function_index(), state.instruction_offset()));
+            })
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(5)));
+        assert_eq!(pauses, vec![(0, 2), (0, 3), (1, 3)]);
+    }
+
+    #[test]
+    fn conditional_breakpoint() {
+        let mut instance = instance();
+        let mut evaluated = 0;
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.set_conditional(0, 2, |state| {
+            evaluated += 1;
+            state.locals()[1].as_i32() == 0
+        });
+
+        let mut pauses = 0;
+        let result = instance
+            .execute_with_breakpoints(
+                "div",
+                &[Value::I32(42), Value::I32(2)],
+                &mut breakpoints,
+                |_| pauses += 1,
+            )
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(21)));
+        assert_eq!(pauses, 0);
+
+        let result = instance
+            .execute_with_breakpoints(
+                "div",
+                &[Value::I32(42), Value::I32(0)],
+                &mut breakpoints,
+                |state| {
+                    pauses += 1;
+                    state.abort();
+                },
+            )
+            .unwrap();
+        assert!(result.trapped());
+        assert_eq!(pauses, 1);
+
+        drop(breakpoints);
+        assert_eq!(evaluated, 2);
+    }
+
+    /* wat2wasm
+      (memory 1)
+      (func (export "run") (param i32) (result i32)
+        (i32.store offset=16 (local.get 0) (i32.const 7))
+        (i32.load offset=16 (local.get 0))
+      )
+    */
+    const MEMORY_WASM: &[&str] = &[
+        "0061736d0100000001060160017f017f0302010005030100010707010372756e00000a10010e0020",
+        "00410736021020002802100b",
+    ];
+
+    #[test]
+    fn watchpoints() {
+        let mut instance = parse(from_hex(MEMORY_WASM)).unwrap().instantiate().unwrap();
+        let mut watchpoints = Watchpoints::new();
+        assert!(watchpoints.is_empty());
+        watchpoints.set(22, 1, Watch::Write);
+        watchpoints.set(100, 4, Watch::ReadWrite);
+        assert_eq!(watchpoints.len(), 2);
+        assert!(watchpoints.remove(100, 4));
+        assert!(!watchpoints.remove(100, 4));
+
+        let mut pauses = Vec::new();
+        let result = instance
+            .execute_with_watchpoints("run", &[Value::I32(4)], &mut watchpoints, |state| {
+                pauses.push((state.instruction_offset(), state.memory_access()));
+                state.continue_();
+            })
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(7)));
+        let store = MemoryAccess {
+            memory_idx: 0,
+            address: 20,
+            size: 4,
+            kind: AccessKind::Write,
+        };
+        assert_eq!(pauses, vec![(2, Some(store))]);
+
+        // Accesses outside of the watched range do not pause.
+        pauses.clear();
+        let result = instance
+            .execute_with_watchpoints("run", &[Value::I32(8)], &mut watchpoints, |state| {
+                pauses.push((state.instruction_offset(), state.memory_access()));
+            })
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(7)));
+        assert!(pauses.is_empty());
+    }
+
+    #[test]
+    fn watchpoint_callback() {
+        let mut instance = parse(from_hex(MEMORY_WASM)).unwrap().instantiate().unwrap();
+        let mut accesses = Vec::new();
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.set_conditional(0, 64, Watch::ReadWrite, |access, state| {
+            accesses.push((
+                state.opcode(),
+                access.address(),
+                access.size(),
+                access.kind(),
+            ));
+            false
+        });
+
+        let result = instance
+            .execute_with_watchpoints("run", &[Value::I32(4)], &mut watchpoints, |_| {
+                panic!("execution paused")
+            })
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(7)));
+        drop(watchpoints);
+        assert_eq!(
+            accesses,
+            vec![
+                (0x36, 20, 4, AccessKind::Write),
+                (0x28, 20, 4, AccessKind::Read)
+            ]
+        );
+    }
+
+    #[test]
+    fn watchpoints_multiple_memories() {
+        /* wat2wasm --enable-multi-memory
+          (memory 1)
+          (memory 1)
+          (func (export "run") (param i32)
+            (i32.store 1 (local.get 0) (i32.const 7))
+            (i32.store (local.get 0) (i32.const 8))
+          )
+        */
+        let input = from_hex(&[
+            "0061736d0100000001050160017f0003020100050502000100010707010372756e00000a13011100",
+            "2000410736420100200041083602000b",
+        ]);
+        let mut instance = parse_multi_memory(input).unwrap().instantiate().unwrap();
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.set_at(1, 4, 4, Watch::Write);
+        watchpoints.set(4, 4, Watch::Write);
+        assert!(!watchpoints.remove_at(1, 8, 4));
+
+        let mut accesses = Vec::new();
+        instance
+            .execute_with_watchpoints("run", &[Value::I32(4)], &mut watchpoints, |state| {
+                accesses.push(state.memory_access().unwrap().memory_index());
+                state.continue_();
+            })
+            .unwrap();
+        assert_eq!(accesses, [1, 0]);
+
+        // The watchpoint of the memory 1 does not watch the same range of the memory 0.
+        assert!(watchpoints.remove(4, 4));
+        accesses.clear();
+        instance
+            .execute_with_watchpoints("run", &[Value::I32(4)], &mut watchpoints, |state| {
+                accesses.push(state.memory_access().unwrap().memory_index());
+                state.continue_();
+            })
+            .unwrap();
+        assert_eq!(accesses, [1]);
+    }
+}
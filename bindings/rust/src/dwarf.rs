@@ -0,0 +1,478 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Consumption of DWARF debug info embedded in custom sections.
+//!
+//! Only the line number program (`.debug_line`) is decoded, which is enough to map code section
+//! offsets and functions to the source file and line of the original guest code.
+//! The debug info may also come from a separate "split" wasm file containing only the custom
+//! sections, as produced e.g. by `wasm-split` or `llvm-objcopy --only-keep-debug`.
+
+use crate::binary::{count_imported_functions, sections, usize_from, Malformed, Reader};
+use crate::Error;
+use std::ops::Range;
+
+/// A location in the guest source code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    /// The path of the source file.
+    pub file: String,
+    /// The line number, starting from 1.
+    pub line: u32,
+    /// The column number, starting from 1, or 0 if not known.
+    pub column: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Row {
+    address: u32,
+    file: usize,
+    line: u32,
+    column: u32,
+    end_sequence: bool,
+}
+
+/// Debug info of a module.
+#[derive(Clone, Debug, Default)]
+pub struct DebugInfo {
+    /// The number of imported functions.
+    num_imported_functions: u32,
+    /// The ranges of the function bodies, relative to the start of the code section.
+    function_ranges: Vec<Range<u32>>,
+    /// All source files referenced by the line table.
+    files: Vec<String>,
+    /// The line table, sorted by address.
+    rows: Vec<Row>,
+}
+
+impl DebugInfo {
+    /// Read the debug info embedded in a wasm binary.
+    ///
+    /// A module without debug info is not an error, but none of its locations will be known.
+    pub fn parse(wasm: &[u8]) -> Result<DebugInfo, Error> {
+        DebugInfo::parse_split(wasm, wasm)
+    }
+
+    /// Read the debug info from a separate wasm binary `debug_wasm`, which belongs to `wasm`.
+    pub fn parse_split(wasm: &[u8], debug_wasm: &[u8]) -> Result<DebugInfo, Error> {
+        let mut info = DebugInfo::default();
+
+        let mut debug_line = None;
+        let mut debug_line_str = None;
+        let mut debug_str = None;
+        for (id, payload) in sections(debug_wasm)? {
+            if id == 0 {
+                let mut reader = Reader::new(payload);
+                let name = reader.name().map_err(|_| Error::ParsingFailed)?;
+                match name {
+                    ".debug_line" => debug_line = Some(reader.rest()),
+                    ".debug_line_str" => debug_line_str = Some(reader.rest()),
+                    ".debug_str" => debug_str = Some(reader.rest()),
+                    _ => {}
+                }
+            }
+        }
+
+        for (id, payload) in sections(wasm)? {
+            match id {
+                2 => info.num_imported_functions = count_imported_functions(payload)?,
+                10 => info.function_ranges = function_ranges(payload)?,
+                _ => {}
+            }
+        }
+
+        if let Some(debug_line) = debug_line {
+            let strings = StringSections {
+                debug_line_str: debug_line_str.unwrap_or(&[]),
+                debug_str: debug_str.unwrap_or(&[]),
+            };
+            let mut reader = Reader::new(debug_line);
+            while !reader.is_empty() {
+                info.parse_line_program(&mut reader, &strings)
+                    .map_err(|_| Error::InvalidDebugInfo)?;
+            }
+            // Sequences may come in any order, but rows within a sequence are already sorted.
+            info.rows
+                .sort_by_key(|row| (row.address, !row.end_sequence));
+        }
+
+        Ok(info)
+    }
+
+    /// Check if the module has a line table.
+    pub fn has_line_info(&self) -> bool {
+        !self.rows.is_empty()
+    }
+
+    /// Find the source location of the instruction at the given offset in the code section.
+    pub fn location(&self, code_offset: u32) -> Option<Location> {
+        let index = self
+            .rows
+            .partition_point(|row| row.address <= code_offset)
+            .checked_sub(1)?;
+        let row = &self.rows[index];
+        if row.end_sequence {
+            return None;
+        }
+        self.to_location(row)
+    }
+
+    /// Find the source location of the function (including imported functions in the index space).
+    ///
+    /// This is the location of the first instruction of the function that has a line assigned.
+    pub fn function_location(&self, func_idx: u32) -> Option<Location> {
+        let code_idx = func_idx.checked_sub(self.num_imported_functions)?;
+        let range = self.function_ranges.get(code_idx as usize)?;
+        let first = self.rows.partition_point(|row| row.address < range.start);
+        self.rows[first..]
+            .iter()
+            .take_while(|row| row.address < range.end)
+            .find(|row| !row.end_sequence && row.line != 0)
+            .and_then(|row| self.to_location(row))
+    }
+
+    fn to_location(&self, row: &Row) -> Option<Location> {
+        if row.line == 0 {
+            return None;
+        }
+        Some(Location {
+            file: self.files.get(row.file)?.clone(),
+            line: row.line,
+            column: row.column,
+        })
+    }
+
+    fn parse_line_program(
+        &mut self,
+        section: &mut Reader,
+        strings: &StringSections,
+    ) -> Result<(), Malformed> {
+        let (unit_length, offset_size) = match section.u32()? {
+            0xffff_ffff => (section.u64()?, 8),
+            length => (length as u64, 4),
+        };
+        let mut unit = Reader::new(section.bytes(usize_from(unit_length)?)?);
+
+        let version = unit.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(Malformed);
+        }
+        if version >= 5 {
+            let _address_size = unit.u8()?;
+            let _segment_selector_size = unit.u8()?;
+        }
+        let header_length = unit.offset(offset_size)?;
+        let mut header = Reader::new(unit.bytes(usize_from(header_length)?)?);
+        let mut program = unit;
+
+        let minimum_instruction_length = header.u8()? as u32;
+        if version >= 4 {
+            let _maximum_operations_per_instruction = header.u8()?;
+        }
+        let _default_is_stmt = header.u8()?;
+        let line_base = header.u8()? as i8 as i64;
+        let line_range = header.u8()?;
+        let opcode_base = header.u8()?;
+        if line_range == 0 || opcode_base == 0 {
+            return Err(Malformed);
+        }
+        let standard_opcode_lengths = header.bytes(opcode_base as usize - 1)?;
+
+        // Indices of the unit's files in self.files.
+        let mut files = Vec::new();
+        if version >= 5 {
+            let directories = read_entries(&mut header, offset_size, strings)?;
+            for (path, dir_index) in read_entries(&mut header, offset_size, strings)? {
+                let dir = directories
+                    .get(dir_index as usize)
+                    .map(|(dir, _)| dir.as_str());
+                files.push(self.add_file(dir, &path));
+            }
+        } else {
+            let mut directories = Vec::new();
+            loop {
+                let dir = header.cstr()?;
+                if dir.is_empty() {
+                    break;
+                }
+                directories.push(dir);
+            }
+            // File indices start from 1, so make index 0 unknown.
+            files.push(usize::MAX);
+            loop {
+                let path = header.cstr()?;
+                if path.is_empty() {
+                    break;
+                }
+                let dir_index = header.uleb()?;
+                let _mtime = header.uleb()?;
+                let _length = header.uleb()?;
+                let dir = (dir_index as usize)
+                    .checked_sub(1)
+                    .and_then(|index| directories.get(index).copied());
+                files.push(self.add_file(dir, path));
+            }
+        }
+
+        let file_index = |file: u64| files.get(file as usize).copied().unwrap_or(usize::MAX);
+        let initial = Row {
+            address: 0,
+            file: file_index(1),
+            line: 1,
+            column: 0,
+            end_sequence: false,
+        };
+        let mut row = initial;
+        while !program.is_empty() {
+            let opcode = program.u8()?;
+            if opcode >= opcode_base {
+                let adjusted = opcode - opcode_base;
+                row.address = row
+                    .address
+                    .wrapping_add((adjusted / line_range) as u32 * minimum_instruction_length);
+                row.line = (row.line as i64 + line_base + (adjusted % line_range) as i64) as u32;
+                self.rows.push(row);
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let length = usize_from(program.uleb()?)?;
+                    let mut extended = Reader::new(program.bytes(length)?);
+                    match extended.u8()? {
+                        // DW_LNE_end_sequence
+                        1 => {
+                            row.end_sequence = true;
+                            self.rows.push(row);
+                            row = initial;
+                        }
+                        // DW_LNE_set_address
+                        2 => {
+                            row.address = match length - 1 {
+                                4 => extended.u32()?,
+                                8 => extended.u64()? as u32,
+                                _ => return Err(Malformed),
+                            }
+                        }
+                        // Other extended opcodes (like the obsolete DW_LNE_define_file) are ignored.
+                        _ => {}
+                    }
+                }
+                // DW_LNS_copy
+                1 => self.rows.push(row),
+                // DW_LNS_advance_pc
+                2 => {
+                    row.address = row
+                        .address
+                        .wrapping_add(program.uleb()? as u32 * minimum_instruction_length)
+                }
+                // DW_LNS_advance_line
+                3 => row.line = (row.line as i64 + program.sleb()?) as u32,
+                // DW_LNS_set_file
+                4 => row.file = file_index(program.uleb()?),
+                // DW_LNS_set_column
+                5 => row.column = program.uleb()? as u32,
+                // DW_LNS_const_add_pc
+                8 => {
+                    row.address = row.address.wrapping_add(
+                        ((255 - opcode_base) / line_range) as u32 * minimum_instruction_length,
+                    )
+                }
+                // DW_LNS_fixed_advance_pc
+                9 => row.address = row.address.wrapping_add(program.u16()? as u32),
+                // Opcodes not affecting the locations (DW_LNS_negate_stmt etc.) and unknown ones.
+                _ => {
+                    for _ in 0..standard_opcode_lengths[opcode as usize - 1] {
+                        program.uleb()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, dir: Option<&str>, path: &str) -> usize {
+        let path = match dir {
+            Some(dir) if !dir.is_empty() && !path.starts_with('/') => format!("{}/{}", dir, path),
+            _ => path.to_string(),
+        };
+        self.files.push(path);
+        self.files.len() - 1
+    }
+}
+
+struct StringSections<'a> {
+    debug_line_str: &'a [u8],
+    debug_str: &'a [u8],
+}
+
+/// Read the DWARF 5 directory or file name entries, returning the paths and directory indices.
+fn read_entries<'a>(
+    header: &mut Reader<'a>,
+    offset_size: usize,
+    strings: &StringSections<'a>,
+) -> Result<Vec<(String, u64)>, Malformed> {
+    const DW_LNCT_PATH: u64 = 1;
+    const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+    let format_count = header.u8()?;
+    let mut format = Vec::new();
+    for _ in 0..format_count {
+        format.push((header.uleb()?, header.uleb()?));
+    }
+
+    let count = header.uleb()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let mut path = String::new();
+        let mut dir_index = 0;
+        for &(content_type, form) in &format {
+            let value = read_form(header, form, offset_size, strings)?;
+            match (content_type, value) {
+                (DW_LNCT_PATH, FormValue::String(s)) => path = s.to_string(),
+                (DW_LNCT_DIRECTORY_INDEX, FormValue::Unsigned(index)) => dir_index = index,
+                _ => {}
+            }
+        }
+        entries.push((path, dir_index));
+    }
+    Ok(entries)
+}
+
+enum FormValue<'a> {
+    String(&'a str),
+    Unsigned(u64),
+    Other,
+}
+
+fn read_form<'a>(
+    reader: &mut Reader<'a>,
+    form: u64,
+    offset_size: usize,
+    strings: &StringSections<'a>,
+) -> Result<FormValue<'a>, Malformed> {
+    let string_at = |section: &'a [u8], offset: u64| {
+        let mut reader = Reader::new(section);
+        reader.bytes(usize_from(offset)?)?;
+        reader.cstr()
+    };
+    Ok(match form {
+        // DW_FORM_string
+        0x08 => FormValue::String(reader.cstr()?),
+        // DW_FORM_strp
+        0x0e => FormValue::String(string_at(strings.debug_str, reader.offset(offset_size)?)?),
+        // DW_FORM_line_strp
+        0x1f => FormValue::String(string_at(
+            strings.debug_line_str,
+            reader.offset(offset_size)?,
+        )?),
+        // DW_FORM_udata
+        0x0f => FormValue::Unsigned(reader.uleb()?),
+        // DW_FORM_data1, DW_FORM_data2, DW_FORM_data4, DW_FORM_data8
+        0x0b => FormValue::Unsigned(reader.u8()? as u64),
+        0x05 => FormValue::Unsigned(reader.u16()? as u64),
+        0x06 => FormValue::Unsigned(reader.u32()? as u64),
+        0x07 => FormValue::Unsigned(reader.u64()?),
+        // DW_FORM_data16 (MD5 checksums)
+        0x1e => {
+            reader.bytes(16)?;
+            FormValue::Other
+        }
+        // DW_FORM_block
+        0x09 => {
+            let length = usize_from(reader.uleb()?)?;
+            reader.bytes(length)?;
+            FormValue::Other
+        }
+        _ => return Err(Malformed),
+    })
+}
+
+fn function_ranges(payload: &[u8]) -> Result<Vec<Range<u32>>, Error> {
+    let mut reader = Reader::new(payload);
+    (|| {
+        let mut ranges = Vec::new();
+        for _ in 0..reader.uleb()? {
+            let size = usize_from(reader.uleb()?)?;
+            let start = reader.position() as u32;
+            reader.bytes(size)?;
+            ranges.push(start..reader.position() as u32);
+        }
+        Ok(ranges)
+    })()
+    .map_err(|_: Malformed| Error::ParsingFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::from_hex;
+
+    /* wat2wasm
+      (func (import "env" "f"))
+      (func nop nop nop nop)
+      (func nop nop)
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001040160000002090103656e760166000003030200000a0d020600010101010b",
+        "040001010b",
+    ];
+
+    /* The .debug_line section (DWARF 4) with the line table:
+      address  file        line  column
+      3        src/main.c  3     5
+      6        src/main.c  4     5
+      10       src/main.c  8     3
+      13       end_sequence
+    */
+    const DEBUG_SECTIONS: &[&str] = &[
+        "00560b2e64656275675f6c696e6546000000040022000000010101fb0e0d00010101010000000100",
+        "000173726300006d61696e2e6300010000000005020300000005050302013d0005020a0000000503",
+        "0304010203000101",
+    ];
+
+    #[test]
+    fn locations() {
+        let wasm = from_hex(&[WASM, DEBUG_SECTIONS].concat());
+        let info = DebugInfo::parse(&wasm).unwrap();
+        assert!(info.has_line_info());
+
+        let location = |line, column| {
+            Some(Location {
+                file: "src/main.c".to_string(),
+                line,
+                column,
+            })
+        };
+        assert_eq!(info.function_location(0), None);
+        assert_eq!(info.function_location(1), location(3, 5));
+        assert_eq!(info.function_location(2), location(8, 3));
+        assert_eq!(info.function_location(3), None);
+
+        assert_eq!(info.location(2), None);
+        assert_eq!(info.location(3), location(3, 5));
+        assert_eq!(info.location(5), location(3, 5));
+        assert_eq!(info.location(6), location(4, 5));
+        assert_eq!(info.location(10), location(8, 3));
+        assert_eq!(info.location(12), location(8, 3));
+        assert_eq!(info.location(13), None);
+    }
+
+    #[test]
+    fn split_debug_info() {
+        let wasm = from_hex(WASM);
+        assert!(!DebugInfo::parse(&wasm).unwrap().has_line_info());
+
+        let debug_wasm = from_hex(&[&["0061736d01000000"], DEBUG_SECTIONS].concat());
+        let info = DebugInfo::parse_split(&wasm, &debug_wasm).unwrap();
+        assert!(info.has_line_info());
+        assert_eq!(info.function_location(2).unwrap().line, 8);
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(DebugInfo::parse(&[0x00]).err(), Some(Error::ParsingFailed));
+        let wasm = from_hex(&["0061736d01000000", "000d0b2e64656275675f6c696e6500"]);
+        assert_eq!(DebugInfo::parse(&wasm).err(), Some(Error::InvalidDebugInfo));
+    }
+}
@@ -0,0 +1,430 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Configuration shared by the parsing and instantiation of many modules.
+//!
+//! An [`Engine`] is created from a [`Config`] once, and the modules are then parsed and
+//! instantiated with [`Engine::parse()`] and [`Engine::instantiate()`], instead of passing
+//! the limits and options to every call.
+//!
+//! Restricted environments can deny opcodes, e.g. `memory.grow` or all float instructions,
+//! the modules using them are rejected by the parsing.
+//!
+//! With the `baseline` feature, the hot functions of the instances can be compiled to native
+//! code, see [`Config::baseline_tier()`].
+
+use crate::builder::InstantiateBuilder;
+use crate::gas::CostTable;
+use crate::host::Imports;
+use crate::metrics::InstructionClass;
+use crate::transform::inject_gas_metering;
+use crate::{
+    parse_with_features, Error, Features, Instance, InstantiateOptions, MemoryBacking, Module,
+    ParserLimits,
+};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// The configuration of an [`Engine`].
+#[derive(Clone, Default)]
+pub struct Config {
+    parser_limits: ParserLimits,
+    features: Features,
+    instantiate_options: InstantiateOptions,
+    gas_metering: Option<CostTable>,
+    denied_opcodes: BTreeSet<u8>,
+    #[cfg(feature = "baseline")]
+    baseline_threshold: Option<u32>,
+}
+
+/// Returns whether the instruction of the opcode operates on or produces float values.
+fn is_float_opcode(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        // Loads, stores and constants.
+        0x2a | 0x2b | 0x38 | 0x39 | 0x43 | 0x44
+        // Comparisons and arithmetic.
+        | 0x5b..=0x66 | 0x8b..=0xa6
+        // Conversions, except i32.wrap_i64 and i64.extend_i32_s/u.
+        | 0xa8..=0xab | 0xae..=0xbf
+    )
+}
+
+impl Config {
+    /// Create the default configuration.
+    pub fn new() -> Self {
+        Config::default()
+    }
+
+    /// Set the limits of the resources used by the parser.
+    pub fn parser_limits(&mut self, limits: ParserLimits) -> &mut Self {
+        self.parser_limits = limits;
+        self
+    }
+
+    /// Accept the modules using the multi-memory proposal. Disabled by default.
+    pub fn multi_memory(&mut self, enable: bool) -> &mut Self {
+        self.features.multi_memory = enable;
+        self
+    }
+
+    /// Set the kind of allocation backing the memories defined by the modules.
+    pub fn memory_backing(&mut self, backing: MemoryBacking) -> &mut Self {
+        self.instantiate_options.memory_backing = backing;
+        self
+    }
+
+    /// Set the hard limit of the memory size in pages.
+    pub fn memory_pages_limit(&mut self, pages: u32) -> &mut Self {
+        self.instantiate_options.memory_pages_limit = Some(pages);
+        self
+    }
+
+    /// Set the maximum depth of nested calls.
+    pub fn max_call_depth(&mut self, depth: u32) -> &mut Self {
+        self.instantiate_options.max_call_depth = Some(depth);
+        self
+    }
+
+    /// Set whether the stack traces of the traps are captured, true by default.
+    ///
+    /// Disabling them makes the traps cheaper, e.g. for the workloads trapping often.
+    pub fn stack_trace(&mut self, enabled: bool) -> &mut Self {
+        self.instantiate_options.disable_stack_trace = !enabled;
+        self
+    }
+
+    /// Instrument the parsed modules with calls to the imported `env.use_gas(i64)` function,
+    /// see [`inject_gas_metering()`].
+    pub fn gas_metering(&mut self, costs: CostTable) -> &mut Self {
+        self.gas_metering = Some(costs);
+        self
+    }
+
+    /// Reject the modules using the opcode.
+    pub fn deny_opcode(&mut self, opcode: u8) -> &mut Self {
+        self.denied_opcodes.insert(opcode);
+        self
+    }
+
+    /// Reject the modules using any instruction of the class, except `end` which terminates
+    /// every function body.
+    pub fn deny_class(&mut self, class: InstructionClass) -> &mut Self {
+        const END: u8 = 0x0b;
+        self.denied_opcodes.extend(
+            (0..=u8::MAX)
+                .filter(|&opcode| opcode != END && InstructionClass::of(opcode) == Some(class)),
+        );
+        self
+    }
+
+    /// Reject the modules using any instruction operating on or producing float values,
+    /// including the float loads, stores, constants and conversions.
+    pub fn deny_float(&mut self) -> &mut Self {
+        self.denied_opcodes
+            .extend((0..=u8::MAX).filter(|&opcode| is_float_opcode(opcode)));
+        self
+    }
+
+    /// Compile the functions of the instances to native code once they have been executed
+    /// `hot_threshold` times, with the experimental baseline compiler tier, as for the modules
+    /// parsed with [`parse_with_baseline_tier()`](crate::parse_with_baseline_tier). By default
+    /// all functions are interpreted.
+    #[cfg(feature = "baseline")]
+    pub fn baseline_tier(&mut self, hot_threshold: u32) -> &mut Self {
+        self.baseline_threshold = Some(hot_threshold);
+        self
+    }
+
+    /// Returns whether the opcode is denied.
+    pub fn is_denied(&self, opcode: u8) -> bool {
+        self.denied_opcodes.contains(&opcode)
+    }
+
+    /// The options used by the instantiation of modules.
+    pub fn instantiate_options(&self) -> &InstantiateOptions {
+        &self.instantiate_options
+    }
+}
+
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("Config");
+        debug
+            .field("parser_limits", &self.parser_limits)
+            .field("features", &self.features)
+            .field("instantiate_options", &self.instantiate_options)
+            .field("gas_metering", &self.gas_metering.is_some())
+            .field("denied_opcodes", &self.denied_opcodes);
+        #[cfg(feature = "baseline")]
+        debug.field("baseline_threshold", &self.baseline_threshold);
+        debug.finish()
+    }
+}
+
+/// The parser and instantiator of modules with a fixed [`Config`].
+///
+/// Cloning an engine is cheap, the clones share the configuration.
+#[derive(Clone, Debug, Default)]
+pub struct Engine {
+    config: Arc<Config>,
+}
+
+impl Engine {
+    /// Create an engine with the configuration.
+    pub fn new(config: &Config) -> Self {
+        Engine {
+            config: Arc::new(config.clone()),
+        }
+    }
+
+    /// The configuration of the engine.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Parse and validate the input, instrumented with gas metering if configured.
+    ///
+    /// Returns [`Error::DeniedOpcode`] for the first denied opcode used by the functions of
+    /// the module, in the order of their definition.
+    pub fn parse<T: AsRef<[u8]>>(&self, input: T) -> Result<Module, Error> {
+        let module = self.parse_binary(input.as_ref())?;
+        self.check_opcodes(&module)?;
+        match &self.config.gas_metering {
+            // The instrumentation is not subject to the denied opcodes.
+            Some(costs) => {
+                let instrumented = inject_gas_metering(input.as_ref(), costs)?;
+                let module = self.parse_binary(&instrumented)?;
+                Ok(self.keep_baseline_code(module, &instrumented))
+            }
+            None => Ok(self.keep_baseline_code(module, input.as_ref())),
+        }
+    }
+
+    /// Parse the binary with the configured limits and proposals.
+    fn parse_binary(&self, binary: &[u8]) -> Result<Module, Error> {
+        parse_with_features(binary, &self.config.parser_limits, &self.config.features)
+    }
+
+    /// Keep the function bodies of the module parsed from the binary for the baseline compiler
+    /// tier, if enabled.
+    #[cfg(feature = "baseline")]
+    fn keep_baseline_code(&self, module: Module, binary: &[u8]) -> Module {
+        if let Some(hot_threshold) = self.config.baseline_threshold {
+            let code = crate::baseline::ModuleCode::new(&module, binary, hot_threshold);
+            module.2.set(Some(Arc::new(code)));
+        }
+        module
+    }
+
+    #[cfg(not(feature = "baseline"))]
+    fn keep_baseline_code(&self, module: Module, _binary: &[u8]) -> Module {
+        module
+    }
+
+    fn check_opcodes(&self, module: &Module) -> Result<(), Error> {
+        if self.config.denied_opcodes.is_empty() {
+            return Ok(());
+        }
+        for func_idx in module.import_function_count()..module.function_count() {
+            let opcodes = module.function_opcodes(func_idx);
+            if let Some(&opcode) = opcodes.iter().find(|&&op| self.config.is_denied(op)) {
+                return Err(Error::DeniedOpcode { func_idx, opcode });
+            }
+        }
+        Ok(())
+    }
+
+    /// Create an instance of the module with the imports.
+    pub fn instantiate(&self, module: Module, imports: Imports) -> Result<Instance, Error> {
+        module.instantiate_with_options(imports, &self.config.instantiate_options)
+    }
+
+    /// Start building an instance of the module, with the options of the engine.
+    pub fn instantiate_builder(&self, module: Module) -> InstantiateBuilder {
+        module
+            .instantiate_builder()
+            .options(self.config.instantiate_options.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::HostFunction;
+    use crate::linker::FunctionType;
+    use crate::test_utils::from_hex;
+    use crate::{Value, ValueType};
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    /* wat2wasm
+      (memory 1)
+      (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001060160017f017f0302010005030100010708010467726f7700000a08010600",
+        "200040000b",
+    ];
+
+    #[test]
+    fn limits() {
+        let mut config = Config::new();
+        config.memory_pages_limit(2).parser_limits(ParserLimits {
+            max_function_count: 1,
+            ..Default::default()
+        });
+        let engine = Engine::new(&config);
+        assert_eq!(
+            engine.config().instantiate_options().memory_pages_limit,
+            Some(2)
+        );
+
+        let module = engine.parse(from_hex(WASM)).unwrap();
+        let mut instance = engine.instantiate(module, Imports::new()).unwrap();
+        let result = instance.execute("grow", &[Value::I32(2)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(-1)));
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+
+        let module = engine.parse(from_hex(WASM)).unwrap();
+        let mut instance = engine.instantiate_builder(module).build().unwrap();
+        let result = instance.execute("grow", &[Value::I32(2)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(-1)));
+
+        /* wat2wasm
+          (func) (func)
+        */
+        let two_functions = from_hex(&["0061736d0100000001040160000003030200000a070202000b02000b"]);
+        assert!(engine.parse(&two_functions).is_err());
+        assert!(Engine::default().parse(&two_functions).is_ok());
+    }
+
+    #[test]
+    fn multi_memory() {
+        /* wat2wasm --enable-multi-memory
+          (memory 1)
+          (memory 2)
+        */
+        let wasm = from_hex(&["0061736d0100000005050200010002"]);
+        assert!(Engine::default().parse(&wasm).is_err());
+
+        let engine = Engine::new(Config::new().multi_memory(true));
+        let instance = engine
+            .instantiate(engine.parse(&wasm).unwrap(), Imports::new())
+            .unwrap();
+        assert_eq!(instance.memory_size_at(1), 2 * 65536);
+    }
+
+    #[test]
+    fn stack_trace() {
+        /* wat2wasm
+          (func (export "fail") unreachable)
+        */
+        let input =
+            from_hex(&["0061736d0100000001040160000003020100070801046661696c00000a05010300000b"]);
+
+        let engine = Engine::default();
+        let mut instance = engine
+            .instantiate(engine.parse(&input).unwrap(), Imports::new())
+            .unwrap();
+        let result = instance.execute("fail", &[]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(result.stack_trace().len(), 1);
+
+        let mut config = Config::new();
+        config.stack_trace(false);
+        let engine = Engine::new(&config);
+        assert!(engine.config().instantiate_options().disable_stack_trace);
+        let mut instance = engine
+            .instantiate(engine.parse(&input).unwrap(), Imports::new())
+            .unwrap();
+        let result = instance.execute("fail", &[]).unwrap();
+        assert!(result.trapped());
+        assert!(result.stack_trace().is_empty());
+    }
+
+    #[test]
+    fn gas_metering() {
+        let mut config = Config::new();
+        config.gas_metering(CostTable::uniform(1));
+        let engine = Engine::new(&config);
+        let module = engine.parse(from_hex(WASM)).unwrap();
+
+        let gas = Arc::new(AtomicI64::new(0));
+        let use_gas = {
+            let gas = gas.clone();
+            HostFunction::new(
+                FunctionType {
+                    inputs: vec![ValueType::I64],
+                    output: None,
+                },
+                move |args| {
+                    gas.fetch_add(args[0].as_i64().unwrap(), Ordering::Relaxed);
+                    Ok(None)
+                },
+            )
+        };
+        let mut imports = Imports::new();
+        imports.define("env", "use_gas", use_gas);
+        let mut instance = engine.instantiate(module, imports).unwrap();
+        let result = instance.execute("grow", &[Value::I32(0)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        assert!(gas.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn denied_opcodes() {
+        let mut config = Config::new();
+        config.deny_opcode(0x40);
+        let engine = Engine::new(&config);
+        assert_eq!(
+            engine.parse(from_hex(WASM)).err(),
+            Some(Error::DeniedOpcode {
+                func_idx: 0,
+                opcode: 0x40
+            })
+        );
+        assert_eq!(
+            Error::DeniedOpcode {
+                func_idx: 0,
+                opcode: 0x40
+            }
+            .to_string(),
+            "opcode 0x40 denied in function 0"
+        );
+
+        // The calls injected by the gas metering are not denied.
+        let mut config = Config::new();
+        config
+            .gas_metering(CostTable::uniform(1))
+            .deny_class(InstructionClass::Control);
+        assert!(config.is_denied(0x10));
+        assert!(!config.is_denied(0x0b));
+        assert!(Engine::new(&config).parse(from_hex(WASM)).is_ok());
+
+        /* wat2wasm
+          (func (import "env" "f") (param i32) (result i32))
+          (func (param i32) (result i32) (local.get 0))
+          (func (param f32) (result f32) (f32.add (local.get 0) (local.get 0)))
+        */
+        let input = from_hex(&[
+            "0061736d01000000010b0260017f017f60017d017d02090103656e760166000003030200010a0e02",
+            "040020000b070020002000920b",
+        ]);
+        let mut config = Config::new();
+        config.deny_float();
+        assert!(config.is_denied(0x92));
+        assert!(config.is_denied(0xbf));
+        assert!(!config.is_denied(0x6a));
+        assert!(!config.is_denied(0xac));
+        assert_eq!(
+            Engine::new(&config).parse(&input).err(),
+            Some(Error::DeniedOpcode {
+                func_idx: 2,
+                opcode: 0x92
+            })
+        );
+        assert!(Engine::default().parse(&input).is_ok());
+    }
+}
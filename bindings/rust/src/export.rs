@@ -0,0 +1,223 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Access to the exported memories and globals of an instance by their export names.
+//!
+//! Unlike [`Instance::memory_get()`] and the other accessors of "the memory" of an instance,
+//! [`Instance::export_memory()`] finds the memory the module intends to share, which is not
+//! necessarily the memory 0 in modules with multiple memories. The exported tables are found with
+//! [`Instance::export_table()`].
+
+use crate::linker::{limits_from_sys, GlobalType, Limits};
+use crate::{sys, Error, Instance, Value, ValueType};
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+
+/// An exported memory of an instance, borrowed from it.
+///
+/// The writes through the exported memory are not recorded.
+pub struct ExportedMemory<'a> {
+    memory: NonNull<sys::FizzyMemory>,
+    limits: Limits,
+    _instance: PhantomData<&'a mut Instance>,
+}
+
+impl ExportedMemory<'_> {
+    /// Returns the limits of the memory.
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Returns the size of the memory in bytes.
+    pub fn size(&self) -> usize {
+        unsafe { sys::fizzy_get_memory_size(self.memory.as_ptr()) }
+    }
+
+    /// Returns the memory range `[offset, offset + size)` if it is within the memory.
+    fn checked_range(&self, offset: u32, size: usize) -> Result<usize, Error> {
+        let offset = offset as usize;
+        match offset.checked_add(size) {
+            Some(end) if end <= self.size() => Ok(offset),
+            _ => Err(Error::InvalidMemoryOffsetOrSize),
+        }
+    }
+
+    /// Copy memory starting at `offset` into `dst`.
+    pub fn get(&self, offset: u32, dst: &mut [u8]) -> Result<(), Error> {
+        let offset = self.checked_range(offset, dst.len())?;
+        if !dst.is_empty() {
+            unsafe {
+                let data = sys::fizzy_get_memory_data(self.memory.as_ptr());
+                std::ptr::copy_nonoverlapping(data.add(offset), dst.as_mut_ptr(), dst.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy `src` into memory starting at `offset`.
+    pub fn set(&mut self, offset: u32, src: &[u8]) -> Result<(), Error> {
+        let offset = self.checked_range(offset, src.len())?;
+        if !src.is_empty() {
+            unsafe {
+                let data = sys::fizzy_get_memory_data(self.memory.as_ptr());
+                std::ptr::copy_nonoverlapping(src.as_ptr(), data.add(offset), src.len());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An exported global of an instance, borrowed from it.
+pub struct ExportedGlobal<'a> {
+    value: NonNull<sys::FizzyValue>,
+    ty: GlobalType,
+    _instance: PhantomData<&'a mut Instance>,
+}
+
+impl ExportedGlobal<'_> {
+    /// Returns the type of the global.
+    pub fn ty(&self) -> GlobalType {
+        self.ty
+    }
+
+    /// Returns the value of the global.
+    pub fn get(&self) -> Value {
+        Value::from_sys(unsafe { *self.value.as_ptr() }, self.ty.value_type.to_sys())
+    }
+
+    /// Set the value of the global.
+    ///
+    /// Returns [`Error::ImmutableGlobal`] if the global is not mutable and
+    /// [`Error::GlobalTypeMismatch`] if the value is not of the type of the global.
+    pub fn set(&mut self, value: Value) -> Result<(), Error> {
+        if !self.ty.mutable {
+            return Err(Error::ImmutableGlobal);
+        }
+        if value.value_type() != self.ty.value_type {
+            return Err(Error::GlobalTypeMismatch);
+        }
+        unsafe { *self.value.as_ptr() = value.into() };
+        Ok(())
+    }
+}
+
+impl Instance {
+    /// Returns the memory exported under the `name`, or `None` if there is no such memory export.
+    pub fn export_memory(&mut self, name: &str) -> Option<ExportedMemory<'_>> {
+        let c_name = CString::new(name).ok()?;
+        let mut memory = sys::FizzyExternalMemory {
+            memory: std::ptr::null_mut(),
+            limits: sys::FizzyLimits {
+                min: 0,
+                max: 0,
+                has_max: false,
+            },
+        };
+        if !unsafe {
+            sys::fizzy_find_exported_memory(self.0.as_ptr(), c_name.as_ptr(), &mut memory)
+        } {
+            return None;
+        }
+        Some(ExportedMemory {
+            memory: NonNull::new(memory.memory)?,
+            limits: limits_from_sys(&memory.limits),
+            _instance: PhantomData,
+        })
+    }
+
+    /// Returns the global exported under the `name`, or `None` if there is no such global export.
+    pub fn export_global(&mut self, name: &str) -> Option<ExportedGlobal<'_>> {
+        let c_name = CString::new(name).ok()?;
+        let mut global = sys::FizzyExternalGlobal {
+            value: std::ptr::null_mut(),
+            type_: sys::FizzyGlobalType {
+                value_type: sys::FizzyValueTypeVoid,
+                is_mutable: false,
+            },
+        };
+        if !unsafe {
+            sys::fizzy_find_exported_global(self.0.as_ptr(), c_name.as_ptr(), &mut global)
+        } {
+            return None;
+        }
+        Some(ExportedGlobal {
+            value: NonNull::new(global.value)?,
+            ty: GlobalType {
+                value_type: ValueType::from_sys(global.type_.value_type),
+                mutable: global.type_.is_mutable,
+            },
+            _instance: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+
+    #[test]
+    fn exports() {
+        /* wat2wasm
+          (memory (export "mem") 1 2)
+          (table (export "tbl") 2 funcref)
+          (global (export "g") (mut i32) (i32.const 7))
+          (global (export "c") f64 (f64.const 1.5))
+          (func (export "read") (result i32) (i32.add (global.get 0) (i32.load8_u (i32.const 3))))
+        */
+        let input = from_hex(&[
+            "0061736d010000000105016000017f030201000404017000020504010101020612027f0141070b7c",
+            "0044000000000000f83f0b071c05036d656d02000374626c01000167030001630301047265616400",
+            "000a0c010a00230041032d00006a0b",
+        ]);
+        let mut instance = parse(input).unwrap().instantiate().unwrap();
+
+        let mut memory = instance.export_memory("mem").unwrap();
+        assert_eq!(
+            memory.limits(),
+            Limits {
+                min: 1,
+                max: Some(2)
+            }
+        );
+        assert_eq!(memory.size(), 65536);
+        memory.set(3, &[5]).unwrap();
+        let mut dst = [0u8; 2];
+        memory.get(2, &mut dst).unwrap();
+        assert_eq!(dst, [0, 5]);
+        assert_eq!(
+            memory.get(65535, &mut dst).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+
+        let mut global = instance.export_global("g").unwrap();
+        assert_eq!(
+            global.ty(),
+            GlobalType {
+                value_type: ValueType::I32,
+                mutable: true
+            }
+        );
+        assert_eq!(global.get(), Value::I32(7));
+        global.set(Value::I32(10)).unwrap();
+        assert_eq!(global.set(Value::I64(1)), Err(Error::GlobalTypeMismatch));
+        let result = instance.execute("read", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(15)));
+
+        let mut constant = instance.export_global("c").unwrap();
+        assert_eq!(constant.get(), Value::F64(1.5));
+        assert_eq!(constant.set(Value::F64(2.0)), Err(Error::ImmutableGlobal));
+
+        assert_eq!(instance.export_table("tbl").unwrap().size(), 2);
+
+        // The names are looked up only among the exports of the kind.
+        assert!(instance.export_memory("tbl").is_none());
+        assert!(instance.export_table("mem").is_none());
+        assert!(instance.export_global("read").is_none());
+        assert!(instance.export_global("missing").is_none());
+        assert!(instance.export_memory("m\0em").is_none());
+    }
+}
@@ -0,0 +1,399 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Static analysis of the execution cost of functions.
+//!
+//! A function is split into basic blocks: straight-line sequences of instructions which end with
+//! a control instruction. Unless execution traps, every instruction of a block is executed once
+//! the first one is, so metering can charge the cost of a whole block at its start instead of
+//! per instruction.
+//!
+//! The cost of instructions is given by a [`CostTable`], by default every instruction costs
+//! 1 unit of gas. The same tables are used for metered execution with
+//! [`Instance::execute_metered()`].
+
+use crate::metrics::InstructionClass;
+use crate::{sys, Error, ExecutionResult, Instance, Module, Value};
+
+/// The gas cost of every instruction, by opcode.
+#[derive(Clone)]
+pub struct CostTable {
+    costs: [u64; 256],
+}
+
+impl CostTable {
+    /// Create the table where every instruction has the same cost.
+    pub fn uniform(cost: u64) -> Self {
+        CostTable { costs: [cost; 256] }
+    }
+
+    /// Create the table where the cost of an instruction depends on its class.
+    ///
+    /// Opcodes which are not valid WebAssembly 1.0 opcodes cost 0.
+    pub fn from_classes<F>(class_cost: F) -> Self
+    where
+        F: Fn(InstructionClass) -> u64,
+    {
+        let mut table = CostTable::uniform(0);
+        for opcode in 0..=u8::MAX {
+            if let Some(class) = InstructionClass::of(opcode) {
+                table.costs[opcode as usize] = class_cost(class);
+            }
+        }
+        table
+    }
+
+    /// Set the cost of the instruction of the opcode.
+    pub fn set(&mut self, opcode: u8, cost: u64) -> &mut Self {
+        self.costs[opcode as usize] = cost;
+        self
+    }
+
+    /// Returns the cost of the instruction of the opcode.
+    pub fn cost(&self, opcode: u8) -> u64 {
+        self.costs[opcode as usize]
+    }
+}
+
+impl Default for CostTable {
+    fn default() -> Self {
+        CostTable::uniform(1)
+    }
+}
+
+impl std::fmt::Debug for CostTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(
+                self.costs
+                    .iter()
+                    .enumerate()
+                    .filter(|(opcode, _)| InstructionClass::of(*opcode as u8).is_some()),
+            )
+            .finish()
+    }
+}
+
+/// A basic block of a function.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BasicBlock {
+    /// The offset of the first instruction of the block in the function's instruction array,
+    /// as reported by [`crate::debug::DebugState::instruction_offset()`].
+    pub start: usize,
+    /// The number of instructions in the block.
+    pub len: usize,
+    /// The cost of executing all instructions of the block.
+    pub cost: u64,
+}
+
+/// An upper bound of the cost of a function execution.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GasBound {
+    Bounded(u64),
+    /// The function contains loops or calls, so its cost cannot be bounded statically.
+    Unbounded,
+}
+
+/// The result of the gas analysis of a function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GasAnalysis {
+    /// The basic blocks of the function, in the order of instructions.
+    pub basic_blocks: Vec<BasicBlock>,
+    /// The maximum cost of a basic block, i.e. the maximum cost of straight-line execution
+    /// between two control instructions.
+    pub max_block_cost: u64,
+    /// The upper bound of the cost of a single execution of the function,
+    /// not including the cost of the called functions.
+    pub total: GasBound,
+}
+
+const UNREACHABLE: u8 = 0x00;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_IF: u8 = 0x0d;
+const BR_TABLE: u8 = 0x0e;
+const RETURN: u8 = 0x0f;
+const CALL: u8 = 0x10;
+const CALL_INDIRECT: u8 = 0x11;
+
+/// Returns true if the instruction ends a basic block: it is either a branch
+/// or the next instruction can be a branch target.
+fn ends_basic_block(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        UNREACHABLE | BLOCK | LOOP | IF | ELSE | END | BR | BR_IF | BR_TABLE | RETURN
+    )
+}
+
+pub(crate) fn analyze(opcodes: &[u8], costs: &CostTable) -> GasAnalysis {
+    let mut basic_blocks = Vec::new();
+    let mut start = 0;
+    let mut cost = 0;
+    for (offset, &opcode) in opcodes.iter().enumerate() {
+        cost += costs.cost(opcode);
+        if ends_basic_block(opcode) || offset == opcodes.len() - 1 {
+            basic_blocks.push(BasicBlock {
+                start,
+                len: offset + 1 - start,
+                cost,
+            });
+            start = offset + 1;
+            cost = 0;
+        }
+    }
+
+    let has_loops_or_calls = opcodes
+        .iter()
+        .any(|&opcode| matches!(opcode, LOOP | CALL | CALL_INDIRECT));
+    let total = if has_loops_or_calls {
+        GasBound::Unbounded
+    } else {
+        GasBound::Bounded(basic_blocks.iter().map(|block| block.cost).sum())
+    };
+
+    GasAnalysis {
+        max_block_cost: basic_blocks
+            .iter()
+            .map(|block| block.cost)
+            .max()
+            .unwrap_or(0),
+        basic_blocks,
+        total,
+    }
+}
+
+impl Module {
+    /// Analyze the cost of a function with the default cost table.
+    ///
+    /// Returns `None` if the index is not valid or is the index of an imported function.
+    pub fn analyze_gas(&self, func_idx: u32) -> Option<GasAnalysis> {
+        self.analyze_gas_with_costs(func_idx, &CostTable::default())
+    }
+
+    /// Analyze the cost of a function with the given cost table.
+    ///
+    /// Returns `None` if the index is not valid or is the index of an imported function.
+    pub fn analyze_gas_with_costs(&self, func_idx: u32, costs: &CostTable) -> Option<GasAnalysis> {
+        let opcodes = self.function_opcodes(func_idx);
+        if opcodes.is_empty() {
+            return None;
+        }
+        Some(analyze(&opcodes, costs))
+    }
+}
+
+/// The result of a metered execution.
+#[derive(Debug)]
+pub struct MeteredExecution {
+    /// The execution result. Running out of fuel ends execution with a trap.
+    pub result: ExecutionResult,
+    /// The fuel consumed by the executed instructions.
+    pub fuel_consumed: u64,
+    /// Whether execution was aborted because the cost of the next instruction exceeded
+    /// the remaining fuel.
+    pub out_of_fuel: bool,
+}
+
+impl Instance {
+    /// Execute an exported function with the given amount of fuel.
+    ///
+    /// The cost of every instruction is charged from the fuel before the instruction is executed.
+    pub fn execute_metered(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        fuel: u64,
+        costs: &CostTable,
+    ) -> Result<MeteredExecution, Error> {
+        let func_idx = self
+            .find_exported_function_index(name)
+            .ok_or(Error::FunctionNotFound)?;
+
+        let mut remaining = fuel;
+        let mut out_of_fuel = false;
+        let mut hook = |state: &sys::FizzyExecutionState| {
+            let cost = costs.cost(state.opcode);
+            if cost > remaining {
+                out_of_fuel = true;
+                return false;
+            }
+            remaining -= cost;
+            true
+        };
+        let result = self.execute_function_with_hook(func_idx, args, &mut hook)?;
+        #[cfg(feature = "metrics")]
+        ::metrics::counter!("fizzy_fuel_consumed_total").increment(fuel - remaining);
+        Ok(MeteredExecution {
+            result,
+            fuel_consumed: fuel - remaining,
+            out_of_fuel,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::from_hex;
+    use crate::{parse, TrapOrigin};
+
+    /* wat2wasm
+      (func (param i32) (result i32)
+        (if (result i32) (local.get 0)
+          (then (i32.const 1))
+          (else (i32.add (i32.const 2) (i32.const 3)))
+        )
+      )
+      (func (param i32)
+        (loop (br_if 0 (local.get 0)))
+      )
+      (func (param i32)
+        (drop (call 0 (local.get 0)))
+      )
+    */
+    const WASM: &[&str] = &[
+        "0061736d01000000010a0260017f017f60017f000304030001010a23030f002000047f4101054102",
+        "41036a0b0b0900034020000d000b0b0700200010001a0b",
+    ];
+
+    fn block(start: usize, len: usize) -> BasicBlock {
+        BasicBlock {
+            start,
+            len,
+            cost: len as u64,
+        }
+    }
+
+    #[test]
+    fn analyze_gas() {
+        let module = parse(from_hex(WASM)).unwrap();
+
+        assert_eq!(
+            module.analyze_gas(0),
+            Some(GasAnalysis {
+                basic_blocks: vec![block(0, 2), block(2, 2), block(4, 4), block(8, 1)],
+                max_block_cost: 4,
+                total: GasBound::Bounded(9),
+            })
+        );
+
+        assert_eq!(
+            module.analyze_gas(1),
+            Some(GasAnalysis {
+                basic_blocks: vec![block(0, 1), block(1, 2), block(3, 1), block(4, 1)],
+                max_block_cost: 2,
+                total: GasBound::Unbounded,
+            })
+        );
+
+        assert_eq!(
+            module.analyze_gas(2),
+            Some(GasAnalysis {
+                basic_blocks: vec![block(0, 4)],
+                max_block_cost: 4,
+                total: GasBound::Unbounded,
+            })
+        );
+
+        assert_eq!(module.analyze_gas(3), None);
+
+        let mut costs = CostTable::default();
+        costs.set(0x6a, 10);
+        let analysis = module.analyze_gas_with_costs(0, &costs).unwrap();
+        assert_eq!(analysis.basic_blocks[2].cost, 13);
+        assert_eq!(analysis.max_block_cost, 13);
+        assert_eq!(analysis.total, GasBound::Bounded(18));
+    }
+
+    #[test]
+    fn cost_table() {
+        let costs = CostTable::default();
+        assert_eq!(costs.cost(0x00), 1);
+        assert_eq!(costs.cost(0xff), 1);
+
+        let mut costs = CostTable::from_classes(|class| match class {
+            InstructionClass::Memory => 100,
+            InstructionClass::Control => 0,
+            _ => 1,
+        });
+        assert_eq!(costs.cost(0x28), 100);
+        assert_eq!(costs.cost(0x0c), 0);
+        assert_eq!(costs.cost(0x6a), 1);
+        assert_eq!(costs.cost(0xff), 0);
+        costs.set(0x40, 1000).set(0x6a, 2);
+        assert_eq!(costs.cost(0x40), 1000);
+        assert_eq!(costs.cost(0x6a), 2);
+    }
+
+    #[test]
+    fn execute_metered() {
+        /* wat2wasm
+          (func (export "add") (param i32) (result i32)
+            (i32.add (local.get 0) (i32.const 1))
+          )
+          (func (export "spin") (param i32)
+            (loop (br_if 0 (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+          )
+        */
+        let mut instance = parse(from_hex(&[
+            "0061736d01000000010a0260017f017f60017f000303020001070e02036164640000047370696e00",
+            "010a18020700200041016a0b0e000340200041016b22000d000b0b",
+        ]))
+        .unwrap()
+        .instantiate()
+        .unwrap();
+
+        let metered = instance
+            .execute_metered("add", &[Value::I32(1)], 100, &CostTable::default())
+            .unwrap();
+        assert_eq!(metered.result.value(), Some(Value::I32(2)));
+        assert_eq!(metered.fuel_consumed, 4);
+        assert!(!metered.out_of_fuel);
+
+        let costs = CostTable::from_classes(|class| match class {
+            InstructionClass::Numeric => 10,
+            _ => 1,
+        });
+        let metered = instance
+            .execute_metered("add", &[Value::I32(1)], 22, &costs)
+            .unwrap();
+        assert_eq!(metered.result.value(), Some(Value::I32(2)));
+        assert_eq!(metered.fuel_consumed, 22);
+        assert!(!metered.out_of_fuel);
+
+        let metered = instance
+            .execute_metered("add", &[Value::I32(1)], 21, &costs)
+            .unwrap();
+        assert!(metered.result.trapped());
+        assert_eq!(metered.result.trap_origin(), Some(TrapOrigin::Aborted));
+        assert_eq!(metered.fuel_consumed, 21);
+        assert!(metered.out_of_fuel);
+
+        let metered = instance
+            .execute_metered("spin", &[Value::I32(1000)], 1000, &CostTable::default())
+            .unwrap();
+        assert!(metered.result.trapped());
+        assert_eq!(metered.fuel_consumed, 1000);
+        assert!(metered.out_of_fuel);
+
+        let metered = instance
+            .execute_metered("spin", &[Value::I32(2)], 1000, &CostTable::default())
+            .unwrap();
+        assert!(!metered.result.trapped());
+        // The branch to the loop executes the loop instruction again.
+        assert_eq!(metered.fuel_consumed, 2 * (1 + 5) + 2);
+        assert!(!metered.out_of_fuel);
+
+        assert_eq!(
+            instance
+                .execute_metered("foo", &[], 1, &CostTable::default())
+                .err(),
+            Some(Error::FunctionNotFound)
+        );
+    }
+}
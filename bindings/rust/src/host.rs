@@ -0,0 +1,1575 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host functions which can be imported by modules.
+//!
+//! [`Imports`] collects [`HostFunction`]s under `(module, name)` pairs.
+//! [`Module::instantiate_with_imports()`] resolves the function imports of a module against them.
+//!
+//! Host functions mutating their captured state are created with [`HostFunction::new_mut()`].
+//!
+//! Host functions calling back into the instance executing them receive a [`Caller`],
+//! see [`HostFunction::new_with_caller()`].
+//!
+//! Asynchronous host functions return futures, see [`HostFunction::new_async()`].
+//!
+//! The number of calls the guest may make to a host function during a single execution can be
+//! limited with [`HostFunction::with_call_limit()`]. A call exceeding the limit traps.
+//!
+//! The exports of another instance (functions, table, memory and globals) can be imported too,
+//! see [`Imports::define_instance()`], as well as memories owned by the host,
+//! see [`Imports::define_memory()`].
+//!
+//! All calls of host functions can be wrapped by middleware, see [`Imports::intercept()`].
+
+use crate::coverage::Coverage;
+use crate::linker::{
+    function_type_from_sys, limits_from_sys, ExternType, FunctionType, GlobalType, TableType,
+};
+use crate::memory::{self, Memory};
+use crate::record::{CallOutcome, Recording};
+use crate::resumable::{await_host_future, HostFuture};
+use crate::table::FuncRef;
+use crate::timing::Timings;
+use crate::{
+    sys, Error, ExecutionResult, HostCall, Instance, InstantiateOptions, Module, Trap, TrapOrigin,
+    Value, ValueType,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::future::Future;
+use std::ptr::NonNull;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// The result of a host function: the optional result value, or the reason of a trap.
+pub(crate) type HostResult = Result<Option<Value>, String>;
+
+/// The signature of host functions: the arguments are checked against the function type.
+/// Returning an error traps the execution with the error message as the reason.
+type HostFn = dyn Fn(&[Value]) -> HostResult + Send;
+
+/// The signature of host functions mutating their captured state.
+type HostFnMut = dyn FnMut(&[Value]) -> HostResult + Send;
+
+/// The signature of host functions calling back into the instance executing them.
+type HostFnWithCaller = dyn Fn(&mut Caller<'_>, &[Value]) -> HostResult + Send;
+
+/// The signature of asynchronous host functions.
+type AsyncHostFn = dyn Fn(Vec<Value>) -> HostFuture + Send;
+
+/// The signature of interceptors of host function calls.
+type Interceptor = dyn Fn(&HostCall, &[Value], Next<'_>) -> HostResult + Send + Sync;
+
+/// The rest of the chain of interceptors of a host function call, ending with the function.
+pub struct Next<'a> {
+    call: &'a HostCall,
+    interceptors: &'a [Box<Interceptor>],
+    func: &'a mut dyn FnMut(&[Value]) -> HostResult,
+}
+
+impl Next<'_> {
+    /// Call the next interceptor, or the host function, with the arguments.
+    ///
+    /// The arguments are checked against the function type before calling the host function,
+    /// the call traps on mismatch.
+    pub fn call(self, args: &[Value]) -> Result<Option<Value>, String> {
+        match self.interceptors.split_first() {
+            Some((interceptor, interceptors)) => interceptor(
+                self.call,
+                args,
+                Next {
+                    call: self.call,
+                    interceptors,
+                    func: self.func,
+                },
+            ),
+            None => (self.func)(args),
+        }
+    }
+}
+
+impl std::fmt::Debug for Next<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Next")
+            .field("call", &self.call)
+            .field("interceptors", &self.interceptors.len())
+            .finish()
+    }
+}
+
+enum HostFnKind {
+    Sync(Box<HostFn>),
+    /// Borrowed mutably for the duration of a call, so a reentrant call cannot alias it.
+    SyncMut(RefCell<Box<HostFnMut>>),
+    Async(Box<AsyncHostFn>),
+    WithCaller(Box<HostFnWithCaller>),
+    /// A function exported by a linked instance.
+    Export(SharedInstance, u32),
+}
+
+/// An instance whose exports can be imported by other instances.
+pub type SharedInstance = Arc<Mutex<Instance>>;
+
+pub(crate) fn lock(instance: &SharedInstance) -> MutexGuard<'_, Instance> {
+    // The panics of host functions are caught, so a poisoned instance is still consistent.
+    instance
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A function implemented by the host.
+///
+/// Host functions must be `Send`, so instances importing them can be moved between threads.
+pub struct HostFunction {
+    ty: FunctionType,
+    func: HostFnKind,
+    call_limit: Option<u32>,
+    max_depth: Option<u32>,
+}
+
+impl HostFunction {
+    /// Create a host function of the given type.
+    pub fn new<F>(ty: FunctionType, func: F) -> Self
+    where
+        F: Fn(&[Value]) -> Result<Option<Value>, String> + Send + 'static,
+    {
+        HostFunction {
+            ty,
+            func: HostFnKind::Sync(Box::new(func)),
+            call_limit: None,
+            max_depth: None,
+        }
+    }
+
+    /// Create a host function of the given type, which can mutate its captured state.
+    ///
+    /// The function is not reentrant: a call made while another call of the same function is in
+    /// progress, e.g. through the exports of a linked instance, traps.
+    pub fn new_mut<F>(ty: FunctionType, func: F) -> Self
+    where
+        F: FnMut(&[Value]) -> Result<Option<Value>, String> + Send + 'static,
+    {
+        HostFunction {
+            ty,
+            func: HostFnKind::SyncMut(RefCell::new(Box::new(func))),
+            call_limit: None,
+            max_depth: None,
+        }
+    }
+
+    /// Create a host function of the given type, which can call back into the instance
+    /// executing it through the [`Caller`].
+    pub fn new_with_caller<F>(ty: FunctionType, func: F) -> Self
+    where
+        F: Fn(&mut Caller<'_>, &[Value]) -> Result<Option<Value>, String> + Send + 'static,
+    {
+        HostFunction {
+            ty,
+            func: HostFnKind::WithCaller(Box::new(func)),
+            call_limit: None,
+            max_depth: None,
+        }
+    }
+
+    /// Create an asynchronous host function of the given type, returning a future of the result.
+    ///
+    /// The execution calling the function waits until the future is ready: an execution started
+    /// with [`Instance::execute_async()`] is suspended, other executions block.
+    pub fn new_async<F, Fut>(ty: FunctionType, func: F) -> Self
+    where
+        F: Fn(Vec<Value>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Option<Value>, String>> + Send + 'static,
+    {
+        HostFunction {
+            ty,
+            func: HostFnKind::Async(Box::new(move |args| Box::pin(func(args)))),
+            call_limit: None,
+            max_depth: None,
+        }
+    }
+
+    /// Limit the number of calls to the function during a single execution.
+    pub fn with_call_limit(mut self, max_calls: u32) -> Self {
+        self.call_limit = Some(max_calls);
+        self
+    }
+
+    /// Limit the call depth of the functions called back through the [`Caller`].
+    ///
+    /// The limit cannot be raised above the call depth limit of the instance executing
+    /// the function, [`InstantiateOptions::max_call_depth`](crate::InstantiateOptions).
+    pub fn with_max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// The type of the function.
+    pub fn ty(&self) -> &FunctionType {
+        &self.ty
+    }
+}
+
+impl std::fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostFunction")
+            .field("ty", &self.ty)
+            .field("call_limit", &self.call_limit)
+            .field("max_depth", &self.max_depth)
+            .finish()
+    }
+}
+
+/// The set of host functions, memories and linked instances available to satisfy module imports.
+#[derive(Default)]
+pub struct Imports {
+    functions: HashMap<(String, String), HostFunction>,
+    memories: HashMap<(String, String), Memory>,
+    instances: HashMap<String, SharedInstance>,
+    /// The exports of instances defined under other names, with the names of the exports.
+    exports: HashMap<(String, String), (SharedInstance, String)>,
+    interceptors: Vec<Box<Interceptor>>,
+}
+
+impl Imports {
+    /// Create an empty set of imports.
+    pub fn new() -> Self {
+        Imports {
+            functions: HashMap::new(),
+            memories: HashMap::new(),
+            instances: HashMap::new(),
+            exports: HashMap::new(),
+            interceptors: Vec::new(),
+        }
+    }
+
+    /// Define a host function under the `module` and `name`, replacing any previous one.
+    pub fn define(&mut self, module: &str, name: &str, func: HostFunction) -> &mut Self {
+        self.functions
+            .insert((module.to_string(), name.to_string()), func);
+        self
+    }
+
+    /// Define a memory under the `module` and `name`, replacing any previous one.
+    ///
+    /// The same memory can be imported by multiple instances.
+    pub fn define_memory(&mut self, module: &str, name: &str, memory: &Memory) -> &mut Self {
+        self.memories
+            .insert((module.to_string(), name.to_string()), memory.clone());
+        self
+    }
+
+    /// Define all exports of the `instance` under the `module`, replacing any previous instance.
+    ///
+    /// Host functions and memories defined under the same `module` and name take precedence.
+    /// The importing instance shares the memory, table and globals of the linked instance and
+    /// keeps it alive. Calls to its functions lock it, so it must not be locked by the caller
+    /// of an execution of the importing instance.
+    pub fn define_instance(&mut self, module: &str, instance: &SharedInstance) -> &mut Self {
+        self.instances.insert(module.to_string(), instance.clone());
+        self
+    }
+
+    /// Wrap the calls of all the host functions with the `interceptor`, e.g. for logging or
+    /// validating the arguments.
+    ///
+    /// The interceptor is given the called import, the arguments and the rest of the chain,
+    /// which it may call with the same or other arguments, or not at all. The interceptors are
+    /// called in the order they are added. The result is checked against the function type
+    /// after all the interceptors. The functions exported by linked instances are not
+    /// intercepted.
+    pub fn intercept<F>(&mut self, interceptor: F) -> &mut Self
+    where
+        F: Fn(&HostCall, &[Value], Next<'_>) -> Result<Option<Value>, String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
+    /// Define the export `export_name` of the `instance` under the `module` and `name`,
+    /// replacing any previous one. It takes precedence over the instance defined under
+    /// the `module`.
+    #[cfg_attr(not(feature = "wasm-c-api"), allow(dead_code))]
+    pub(crate) fn define_export(
+        &mut self,
+        module: &str,
+        name: &str,
+        instance: &SharedInstance,
+        export_name: &str,
+    ) -> &mut Self {
+        self.exports.insert(
+            (module.to_string(), name.to_string()),
+            (instance.clone(), export_name.to_string()),
+        );
+        self
+    }
+
+    /// Define the `instance` under the `module`, unless an instance is already defined there.
+    pub(crate) fn define_fallback_instance(&mut self, module: &str, instance: &SharedInstance) {
+        self.instances
+            .entry(module.to_string())
+            .or_insert_with(|| instance.clone());
+    }
+}
+
+impl std::fmt::Debug for Imports {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Imports")
+            .field("functions", &self.functions)
+            .field("memories", &self.memories)
+            .field("instances", &self.instances.keys().collect::<Vec<_>>())
+            .field("exports", &self.exports.keys().collect::<Vec<_>>())
+            .field("interceptors", &self.interceptors.len())
+            .finish()
+    }
+}
+
+/// The items imported by an instance, kept alive as long as the instance.
+#[derive(Default)]
+pub(crate) struct InstanceImports {
+    functions: Vec<ImportedFunction>,
+    /// The host functions referenced by the elements of the table, boxed to be the contexts of
+    /// their trampolines, which must not move when the vector grows.
+    #[allow(clippy::vec_box)]
+    table_functions: Vec<Box<ImportedFunction>>,
+    interceptors: Arc<[Box<Interceptor>]>,
+    memories: Vec<Memory>,
+    instances: Vec<SharedInstance>,
+    /// The allocator of the memory of the instance, boxed to be the context of its trampolines.
+    pub(crate) memory_allocator: Option<Box<Arc<dyn memory::Allocator>>>,
+    /// The recording of the interactions with the host, shared with the imported functions.
+    pub(crate) recording: Rc<RefCell<Option<Recording>>>,
+    /// The instructions executed with coverage collection.
+    pub(crate) coverage: Coverage,
+    /// The time spent in the phases of the lifetime of the instance.
+    pub(crate) timings: Timings,
+    /// Whether the executions are timed.
+    pub(crate) execution_timing: bool,
+    /// The callback of the memory growth, boxed to be the context of its trampoline.
+    pub(crate) memory_grow_callback: Option<Box<crate::MemoryGrowCallback>>,
+    /// The callback of the progress of the executions, boxed to be the context of its trampoline.
+    pub(crate) progress_callback: Option<Box<crate::ProgressCallback>>,
+    /// The limiter of the growth of the resources, boxed to be the context of its trampoline.
+    pub(crate) resource_limiter: Option<Box<Box<dyn crate::limiter::ResourceLimiter>>>,
+    /// The identity of the instance, shared with the references to its functions.
+    pub(crate) identity: Arc<()>,
+    /// The snapshot of the memory, the baseline of the dirty flags of the pages.
+    pub(crate) memory_snapshot: Option<crate::snapshot::MemorySnapshot>,
+    /// The baseline compiler tier, executing the compiled hot functions.
+    #[cfg(feature = "baseline")]
+    pub(crate) baseline: Option<crate::baseline::Tier>,
+}
+
+/// A host function bound to an instance, the context of its trampoline.
+pub(crate) struct ImportedFunction {
+    module: String,
+    name: String,
+    func: HostFunction,
+    call_count: Cell<u32>,
+    /// The trap raised by the last call, if any.
+    trap: RefCell<Option<HostTrap>>,
+    /// The trap of the last nested execution of the current call, the cause of its trap.
+    nested_trap: RefCell<Option<Arc<Trap>>>,
+    /// All functions imported by the instance, for the traps of calls back into it.
+    siblings: Cell<*const [ImportedFunction]>,
+    recording: Rc<RefCell<Option<Recording>>>,
+    interceptors: Arc<[Box<Interceptor>]>,
+}
+
+/// The trap raised by a call of an imported function.
+pub(crate) struct HostTrap {
+    pub(crate) reason: Option<String>,
+    pub(crate) call: HostCall,
+    pub(crate) cause: Option<Arc<Trap>>,
+    /// The size of the stack trace of the instance when the function has trapped.
+    /// The frames recorded before belong to the nested executions.
+    pub(crate) trace_size: usize,
+}
+
+/// Execute a function of the instance called by an imported function at the call depth,
+/// returning the trap with the trap of the imported functions of the instance as the cause.
+fn execute_nested(
+    instance: NonNull<sys::FizzyInstance>,
+    functions: &[ImportedFunction],
+    func_idx: u32,
+    args: &[sys::FizzyValue],
+    depth: i32,
+) -> Result<sys::FizzyExecutionResult, Trap> {
+    // The stack trace is cleared only by the outermost execution.
+    let trace_size =
+        unsafe { sys::fizzy_get_trap_stack_trace(instance.as_ptr(), std::ptr::null_mut(), 0) };
+    let result = unsafe { sys::fizzy_execute(instance.as_ptr(), func_idx, args.as_ptr(), depth) };
+    if !result.trapped {
+        return Ok(result);
+    }
+    Err(match functions.iter().find_map(|func| func.trap.take()) {
+        Some(host_trap) => Trap {
+            origin: TrapOrigin::HostFunction,
+            stack_trace: crate::trap_stack_trace(instance.as_ptr(), host_trap.trace_size),
+            reason: host_trap.reason,
+            host_call: Some(host_trap.call),
+            cause: host_trap.cause,
+        },
+        None => Trap {
+            origin: TrapOrigin::Wasm,
+            stack_trace: crate::trap_stack_trace(instance.as_ptr(), trace_size),
+            reason: None,
+            host_call: None,
+            cause: None,
+        },
+    })
+}
+
+/// The context of a call of a host function created with [`HostFunction::new_with_caller()`].
+///
+/// Allows the host function to call back into the instance executing it. The host functions
+/// called back have the same call limits, which are renewed only by the outermost execution.
+pub struct Caller<'a> {
+    instance: NonNull<sys::FizzyInstance>,
+    function: &'a ImportedFunction,
+    functions: &'a [ImportedFunction],
+    depth: i32,
+    max_depth: u32,
+}
+
+impl Caller<'_> {
+    /// The call depth of the host function, 1 if it is called by the outermost executed function.
+    pub fn depth(&self) -> u32 {
+        self.depth as u32
+    }
+
+    /// Returns the size of the instance memory in bytes, or 0 if the instance has no memory.
+    pub fn memory_size(&self) -> usize {
+        unsafe { sys::fizzy_get_instance_memory_size(self.instance.as_ptr()) }
+    }
+
+    /// Copy the memory of the instance starting at `offset` into `dst`.
+    pub fn memory_get(&self, offset: u32, dst: &mut [u8]) -> Result<(), Error> {
+        self.memory_get_at(0, offset, dst)
+    }
+
+    /// Copy the memory of the index of the instance starting at `offset` into `dst`.
+    pub fn memory_get_at(&self, memory_idx: u32, offset: u32, dst: &mut [u8]) -> Result<(), Error> {
+        let instance = self.instance.as_ptr();
+        let (data, start) = crate::checked_memory_range(instance, memory_idx, offset, dst.len())?;
+        if !dst.is_empty() {
+            unsafe { std::ptr::copy_nonoverlapping(data.add(start), dst.as_mut_ptr(), dst.len()) };
+        }
+        Ok(())
+    }
+
+    /// Copy `src` into the memory of the instance starting at `offset`.
+    pub fn memory_set(&mut self, offset: u32, src: &[u8]) -> Result<(), Error> {
+        self.memory_set_at(0, offset, src)
+    }
+
+    /// Copy `src` into the memory of the index of the instance starting at `offset`.
+    pub fn memory_set_at(&mut self, memory_idx: u32, offset: u32, src: &[u8]) -> Result<(), Error> {
+        let instance = self.instance.as_ptr();
+        let (data, start) = crate::checked_memory_range(instance, memory_idx, offset, src.len())?;
+        if let Some(recording) = self.function.recording.borrow_mut().as_mut() {
+            recording.memory_write(memory_idx, offset, src);
+        }
+        if !src.is_empty() {
+            unsafe { std::ptr::copy_nonoverlapping(src.as_ptr(), data.add(start), src.len()) };
+        }
+        Ok(())
+    }
+
+    /// Execute an exported function of the instance by name.
+    ///
+    /// The arguments are checked against the function type and an error is returned on mismatch.
+    /// [`Error::CallDepthExceeded`] is returned if the call would exceed the depth limit set with
+    /// [`HostFunction::with_max_depth()`].
+    ///
+    /// If the host function traps after the execution has trapped, the trap of the execution
+    /// becomes the cause of its trap.
+    pub fn execute(&mut self, name: &str, args: &[Value]) -> Result<ExecutionResult, Error> {
+        let depth = self.depth + 1;
+        if depth as u32 > self.max_depth {
+            return Err(Error::CallDepthExceeded);
+        }
+        let module = unsafe { sys::fizzy_get_instance_module(self.instance.as_ptr()) };
+        let func_idx =
+            crate::find_exported_function(module, name).ok_or(Error::FunctionNotFound)?;
+        let func_type = crate::check_arguments(module, func_idx, args)?;
+
+        let args: Vec<sys::FizzyValue> = args.iter().map(|&arg| arg.into()).collect();
+        match execute_nested(self.instance, self.functions, func_idx, &args, depth) {
+            Ok(result) => {
+                self.function.nested_trap.replace(None);
+                Ok(ExecutionResult {
+                    trapped: false,
+                    value: if result.has_value {
+                        Some(Value::from_sys(result.value, func_type.output))
+                    } else {
+                        None
+                    },
+                    stack_trace: Vec::new(),
+                    trap_reason: None,
+                    host_call: None,
+                    trap_cause: None,
+                    trap_origin: None,
+                })
+            }
+            Err(trap) => {
+                self.function
+                    .nested_trap
+                    .replace(Some(Arc::new(trap.clone())));
+                Ok(ExecutionResult {
+                    trapped: true,
+                    value: None,
+                    stack_trace: trap.stack_trace,
+                    trap_reason: trap.reason,
+                    host_call: trap.host_call,
+                    trap_cause: trap.cause,
+                    trap_origin: Some(trap.origin),
+                })
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Caller<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Caller")
+            .field("depth", &self.depth)
+            .field("max_depth", &self.max_depth)
+            .finish()
+    }
+}
+
+impl ImportedFunction {
+    fn new(module: String, name: String, func: HostFunction) -> Self {
+        ImportedFunction {
+            module,
+            name,
+            func,
+            call_count: Cell::new(0),
+            trap: RefCell::new(None),
+            nested_trap: RefCell::new(None),
+            siblings: Cell::new(&[]),
+            recording: Rc::default(),
+            interceptors: Arc::new([]),
+        }
+    }
+
+    /// Call the function. A trap without a reason is the trap of a linked instance's function.
+    fn call(
+        &self,
+        instance: NonNull<sys::FizzyInstance>,
+        args: &[sys::FizzyValue],
+        depth: i32,
+    ) -> Result<Option<Value>, Option<String>> {
+        if let HostFnKind::Export(linked, func_idx) = &self.func.func {
+            let linked = lock(linked);
+            return match execute_nested(linked.0, &linked.1.functions, *func_idx, args, depth) {
+                Ok(result) => Ok(self
+                    .func
+                    .ty
+                    .output
+                    .map(|output| Value::from_sys(result.value, output.to_sys()))),
+                Err(trap) => {
+                    let reason = trap.reason.clone();
+                    self.nested_trap.replace(Some(Arc::new(trap)));
+                    Err(reason)
+                }
+            };
+        }
+        self.call_host(instance, args, depth).map_err(Some)
+    }
+
+    /// Call the host function with the arguments of the function type.
+    fn dispatch(
+        &self,
+        instance: NonNull<sys::FizzyInstance>,
+        args: &[Value],
+        depth: i32,
+    ) -> HostResult {
+        let call_count = self.call_count.get() + 1;
+        self.call_count.set(call_count);
+        if let Some(call_limit) = self.func.call_limit {
+            if call_count > call_limit {
+                return Err(format!(
+                    "call limit of {}::{} exceeded ({} calls)",
+                    self.module, self.name, call_limit
+                ));
+            }
+        }
+
+        match &self.func.func {
+            HostFnKind::Sync(func) => func(args),
+            HostFnKind::SyncMut(func) => match func.try_borrow_mut() {
+                Ok(mut func) => func(args),
+                Err(_) => Err(format!("{}::{} called reentrantly", self.module, self.name)),
+            },
+            HostFnKind::Async(func) => await_host_future(func(args.to_vec())),
+            HostFnKind::WithCaller(func) => {
+                let call_stack_limit =
+                    unsafe { sys::fizzy_get_instance_call_stack_limit(instance.as_ptr()) };
+                let mut caller = Caller {
+                    instance,
+                    // The functions are owned by the instance, which is executing.
+                    function: self,
+                    functions: unsafe { &*self.siblings.get() },
+                    depth,
+                    max_depth: self.func.max_depth.map_or(call_stack_limit, |max_depth| {
+                        max_depth.min(call_stack_limit)
+                    }),
+                };
+                func(&mut caller, args)
+            }
+            HostFnKind::Export(..) => unreachable!("exported functions are called directly"),
+        }
+    }
+
+    fn call_host(
+        &self,
+        instance: NonNull<sys::FizzyInstance>,
+        args: &[sys::FizzyValue],
+        depth: i32,
+    ) -> Result<Option<Value>, String> {
+        let args: Vec<Value> = self
+            .func
+            .ty
+            .inputs
+            .iter()
+            .zip(args.iter())
+            .map(|(&input, &arg)| Value::from_sys(arg, input.to_sys()))
+            .collect();
+        let result = if self.interceptors.is_empty() {
+            self.dispatch(instance, &args, depth)?
+        } else {
+            let call = HostCall {
+                module: self.module.clone(),
+                name: self.name.clone(),
+                depth: depth as u32,
+            };
+            let mut func = |args: &[Value]| {
+                let types = args.iter().map(|arg| arg.value_type());
+                if !types.eq(self.func.ty.inputs.iter().copied()) {
+                    return Err(format!(
+                        "arguments of {}::{} do not match the function type",
+                        self.module, self.name
+                    ));
+                }
+                self.dispatch(instance, args, depth)
+            };
+            Next {
+                call: &call,
+                interceptors: &self.interceptors,
+                func: &mut func,
+            }
+            .call(&args)?
+        };
+        if result.map(|value| value.value_type()) != self.func.ty.output {
+            return Err(format!(
+                "result of {}::{} does not match the function type",
+                self.module, self.name
+            ));
+        }
+        Ok(result)
+    }
+}
+
+unsafe extern "C" fn host_function_trampoline(
+    context: *mut std::ffi::c_void,
+    instance: *mut sys::FizzyInstance,
+    args: *const sys::FizzyValue,
+    args_size: usize,
+    depth: i32,
+) -> sys::FizzyExecutionResult {
+    let func = &*(context as *const ImportedFunction);
+    let instance = NonNull::new_unchecked(instance);
+    let args = if args_size == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(args, args_size)
+    };
+    let recorded_call = func.recording.borrow_mut().as_mut().map(|recording| {
+        let args = func
+            .func
+            .ty
+            .inputs
+            .iter()
+            .zip(args.iter())
+            .map(|(&input, &arg)| Value::from_sys(arg, input.to_sys()))
+            .collect();
+        recording.begin_call(&func.module, &func.name, args)
+    });
+    // Unwinding across the C++ interpreter is not allowed: a panicking function traps.
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        func.call(instance, args, depth)
+    }))
+    .unwrap_or_else(|_| Err(Some(format!("{}::{} panicked", func.module, func.name))));
+    if let Some(index) = recorded_call {
+        if let Some(recording) = func.recording.borrow_mut().as_mut() {
+            let outcome = match &result {
+                Ok(value) => CallOutcome::Returned(*value),
+                Err(reason) => CallOutcome::Trapped(reason.clone()),
+            };
+            recording.end_call(index, outcome);
+        }
+    }
+    match result {
+        Ok(value) => {
+            func.nested_trap.replace(None);
+            sys::FizzyExecutionResult {
+                trapped: false,
+                has_value: value.is_some(),
+                value: value.map_or(sys::FizzyValue { i64: 0 }, |value| value.into()),
+            }
+        }
+        Err(reason) => {
+            *func.trap.borrow_mut() = Some(HostTrap {
+                reason,
+                call: HostCall {
+                    module: func.module.clone(),
+                    name: func.name.clone(),
+                    depth: depth as u32,
+                },
+                cause: func.nested_trap.take(),
+                trace_size: sys::fizzy_get_trap_stack_trace(
+                    instance.as_ptr(),
+                    std::ptr::null_mut(),
+                    0,
+                ),
+            });
+            sys::FizzyExecutionResult {
+                trapped: true,
+                has_value: false,
+                value: sys::FizzyValue { i64: 0 },
+            }
+        }
+    }
+}
+
+/// An item exported by an instance.
+enum Export {
+    Function(u32),
+    Table(sys::FizzyExternalTable),
+    Memory(sys::FizzyExternalMemory),
+    Global(sys::FizzyExternalGlobal),
+}
+
+impl Instance {
+    /// Find an export of the kind of the `required` type, returning it together with its type.
+    fn find_export(&self, name: &str, required: &ExternType) -> Option<(Export, ExternType)> {
+        let instance = self.0.as_ptr();
+        let c_name = CString::new(name).ok()?;
+        let limits = sys::FizzyLimits {
+            min: 0,
+            max: 0,
+            has_max: false,
+        };
+        match required {
+            ExternType::Func(_) => {
+                let func_idx = self.find_exported_function_index(name)?;
+                let func_type = unsafe { sys::fizzy_get_function_type(self.module(), func_idx) };
+                let ty = ExternType::Func(function_type_from_sys(&func_type));
+                Some((Export::Function(func_idx), ty))
+            }
+            ExternType::Table(_) => {
+                let mut table = sys::FizzyExternalTable {
+                    table: std::ptr::null_mut(),
+                    limits,
+                };
+                if !unsafe { sys::fizzy_find_exported_table(instance, c_name.as_ptr(), &mut table) }
+                {
+                    return None;
+                }
+                let ty = ExternType::Table(TableType {
+                    limits: limits_from_sys(&table.limits),
+                });
+                Some((Export::Table(table), ty))
+            }
+            ExternType::Memory(_) => {
+                let mut memory = sys::FizzyExternalMemory {
+                    memory: std::ptr::null_mut(),
+                    limits,
+                };
+                if !unsafe {
+                    sys::fizzy_find_exported_memory(instance, c_name.as_ptr(), &mut memory)
+                } {
+                    return None;
+                }
+                let ty = ExternType::Memory(limits_from_sys(&memory.limits));
+                Some((Export::Memory(memory), ty))
+            }
+            ExternType::Global(_) => {
+                let mut global = sys::FizzyExternalGlobal {
+                    value: std::ptr::null_mut(),
+                    type_: sys::FizzyGlobalType {
+                        value_type: sys::FizzyValueTypeVoid,
+                        is_mutable: false,
+                    },
+                };
+                if !unsafe {
+                    sys::fizzy_find_exported_global(instance, c_name.as_ptr(), &mut global)
+                } {
+                    return None;
+                }
+                let ty = ExternType::Global(GlobalType {
+                    value_type: ValueType::from_sys(global.type_.value_type),
+                    mutable: global.type_.is_mutable,
+                });
+                Some((Export::Global(global), ty))
+            }
+        }
+    }
+}
+
+impl Module {
+    /// Create an instance of a module, resolving its imports with the host functions and
+    /// the exports of the linked instances.
+    pub fn instantiate_with_imports(self, imports: Imports) -> Result<Instance, Error> {
+        self.instantiate_with_options(imports, &InstantiateOptions::default())
+    }
+
+    /// Create an instance of a module like [`Module::instantiate_with_imports()`],
+    /// configured with the options.
+    pub fn instantiate_with_options(
+        self,
+        mut imports: Imports,
+        options: &InstantiateOptions,
+    ) -> Result<Instance, Error> {
+        let mut instance_imports = InstanceImports::default();
+        let mut table = None;
+        let mut memories = Vec::new();
+        let mut globals = Vec::new();
+        for import in self.imports() {
+            let key = (import.module, import.name);
+            let expected = &import.ty;
+            let incompatible =
+                |key: (String, String), provided: ExternType| Error::IncompatibleImportType {
+                    module: key.0,
+                    name: key.1,
+                    expected: expected.clone(),
+                    provided,
+                };
+            if let Some(func) = imports.functions.remove(&key) {
+                match &import.ty {
+                    ExternType::Func(ty) if func.ty == *ty => instance_imports
+                        .functions
+                        .push(ImportedFunction::new(key.0, key.1, func)),
+                    _ => return Err(incompatible(key, ExternType::Func(func.ty))),
+                }
+                continue;
+            }
+            if let Some(imported_memory) = imports.memories.remove(&key) {
+                let ty = ExternType::Memory(imported_memory.limits());
+                if !ty.matches(&import.ty) {
+                    return Err(incompatible(key, ty));
+                }
+                memories.push(imported_memory.to_external());
+                instance_imports.memories.push(imported_memory);
+                continue;
+            }
+
+            let (shared, export_name) = match imports.exports.get(&key) {
+                Some((shared, export_name)) => (shared, export_name.as_str()),
+                None => match imports.instances.get(&key.0) {
+                    Some(shared) => (shared, key.1.as_str()),
+                    None => {
+                        return Err(Error::UnknownImport {
+                            module: key.0,
+                            name: key.1,
+                        })
+                    }
+                },
+            };
+            let (export, ty) = match lock(shared).find_export(export_name, &import.ty) {
+                Some(found) => found,
+                None => {
+                    return Err(Error::UnknownImport {
+                        module: key.0,
+                        name: key.1,
+                    })
+                }
+            };
+            if !ty.matches(&import.ty) {
+                return Err(incompatible(key, ty));
+            }
+            match (export, ty) {
+                (Export::Function(func_idx), ExternType::Func(ty)) => {
+                    let func = HostFunction {
+                        ty,
+                        func: HostFnKind::Export(shared.clone(), func_idx),
+                        call_limit: None,
+                        max_depth: None,
+                    };
+                    instance_imports
+                        .functions
+                        .push(ImportedFunction::new(key.0, key.1, func));
+                }
+                (Export::Table(external_table), _) => table = Some(external_table),
+                (Export::Memory(external_memory), _) => memories.push(external_memory),
+                (Export::Global(external_global), _) => globals.push(external_global),
+                _ => unreachable!("export kind matches its type"),
+            }
+            if !instance_imports
+                .instances
+                .iter()
+                .any(|instance| Arc::ptr_eq(instance, shared))
+            {
+                instance_imports.instances.push(shared.clone());
+            }
+        }
+
+        let interceptors: Arc<[Box<Interceptor>]> =
+            std::mem::take(&mut imports.interceptors).into();
+        instance_imports.interceptors = interceptors.clone();
+        let siblings: *const [ImportedFunction] = instance_imports.functions.as_slice();
+        for func in &mut instance_imports.functions {
+            func.siblings.set(siblings);
+            func.recording = instance_imports.recording.clone();
+            func.interceptors = interceptors.clone();
+        }
+        let sys_functions: Vec<sys::FizzyExternalFunction> = instance_imports
+            .functions
+            .iter()
+            .map(|func| sys::FizzyExternalFunction {
+                function: Some(host_function_trampoline),
+                context: func as *const ImportedFunction as *mut std::ffi::c_void,
+            })
+            .collect();
+        instance_imports.memory_allocator = options.memory_allocator.clone().map(Box::new);
+        let sys_allocator = instance_imports
+            .memory_allocator
+            .as_deref()
+            .map(memory::allocator_to_sys);
+        let sys_options = sys::FizzyInstantiateOptions {
+            memory_backing: options.memory_backing.to_sys(),
+            memory_allocator: sys_allocator
+                .as_ref()
+                .map_or(std::ptr::null(), |allocator| allocator),
+            defer_start: options.defer_start,
+            memory_pages_limit: options.memory_pages_limit.unwrap_or(0),
+            call_stack_limit: options.max_call_depth.unwrap_or(0),
+            disable_trap_stack_trace: options.disable_stack_trace,
+        };
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("instantiate").entered();
+        let mut error = crate::sys_error();
+        let started = std::time::Instant::now();
+        let ptr = unsafe {
+            sys::fizzy_instantiate_with_options(
+                self.0.as_ptr(),
+                sys_functions.as_ptr(),
+                sys_functions.len(),
+                table.as_ref().map_or(std::ptr::null(), |table| table),
+                memories.as_ptr(),
+                memories.len(),
+                globals.as_ptr(),
+                globals.len(),
+                &sys_options,
+                &mut error,
+            )
+        };
+        instance_imports.timings.instantiate = started.elapsed();
+        instance_imports.timings.parse = self.1;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            ok = !ptr.is_null(),
+            duration = ?instance_imports.timings.instantiate,
+            "instantiated"
+        );
+        #[cfg(feature = "baseline")]
+        {
+            instance_imports.baseline = self.2.take().map(crate::baseline::Tier::new);
+        }
+        // Forget Module (and avoid calling drop) because it has been consumed by instantiate (even if it failed).
+        std::mem::forget(self);
+        // The contexts of the trampolines stay valid, as the vector is never modified.
+        NonNull::new(ptr)
+            .map(|ptr| Instance(ptr, instance_imports))
+            .ok_or_else(|| Error::from_sys(&error, Error::InstantiationFailed))
+    }
+}
+
+impl Instance {
+    /// Reset the call counts of the host functions before a new execution.
+    pub(crate) fn reset_host_calls(&self) {
+        for func in self.host_functions() {
+            func.call_count.set(0);
+            func.trap.replace(None);
+            func.nested_trap.replace(None);
+        }
+        if let Some(progress) = &self.1.progress_callback {
+            progress.aborted.set(false);
+        }
+    }
+
+    /// Returns the trap raised by a host function during the last execution.
+    pub(crate) fn take_host_trap(&self) -> Option<HostTrap> {
+        self.host_functions().find_map(|func| func.trap.take())
+    }
+
+    /// Returns the imported host functions and those referenced by the table.
+    fn host_functions(&self) -> impl Iterator<Item = &ImportedFunction> {
+        let table_functions = self.1.table_functions.iter().map(|func| &**func);
+        self.1.functions.iter().chain(table_functions)
+    }
+
+    /// Create a reference to the host function, which can be placed into the table of
+    /// the instance, e.g. to be called by `call_indirect`.
+    ///
+    /// The function is bound to the instance like the imported ones, under the `module` and
+    /// `name` reported by its traps, and is kept alive as long as the instance. It is wrapped by
+    /// the interceptors of the imports of the instance.
+    pub fn host_func_ref(
+        &mut self,
+        module: &str,
+        name: &str,
+        func: HostFunction,
+    ) -> Result<FuncRef, Error> {
+        let mut func = Box::new(ImportedFunction::new(
+            module.to_string(),
+            name.to_string(),
+            func,
+        ));
+        func.siblings.set(self.1.functions.as_slice());
+        func.recording = self.1.recording.clone();
+        func.interceptors = self.1.interceptors.clone();
+        let inputs: Vec<sys::FizzyValueType> = func
+            .func
+            .ty
+            .inputs
+            .iter()
+            .map(|input| input.to_sys())
+            .collect();
+        let func_type = sys::FizzyFunctionType {
+            output: func
+                .func
+                .ty
+                .output
+                .map_or(sys::FizzyValueTypeVoid, ValueType::to_sys),
+            inputs: inputs.as_ptr(),
+            inputs_size: inputs.len(),
+        };
+        let ptr = unsafe {
+            sys::fizzy_create_funcref(
+                func_type,
+                Some(host_function_trampoline),
+                &*func as *const ImportedFunction as *mut std::ffi::c_void,
+            )
+        };
+        let ptr = NonNull::new(ptr).ok_or(Error::OutOfMemory)?;
+        self.1.table_functions.push(func);
+        Ok(FuncRef {
+            ptr,
+            instance: self.1.identity.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::from_hex;
+    use crate::{parse, Frame};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /* wat2wasm
+      (func $read (import "env" "read") (param i32) (result i32))
+      (func (export "sum") (param i32) (result i32)
+        (i32.add (call $read (local.get 0)) (call $read (i32.const 1)))
+      )
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001060160017f017f020c0103656e760472656164000003020100070701037375",
+        "6d00010a0d010b0020001000410110006a0b",
+    ];
+
+    fn read_type() -> FunctionType {
+        FunctionType {
+            inputs: vec![ValueType::I32],
+            output: Some(ValueType::I32),
+        }
+    }
+
+    fn instantiate(func: HostFunction) -> Instance {
+        let mut imports = Imports::new();
+        imports.define("env", "read", func);
+        parse(from_hex(WASM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    #[test]
+    fn call_host_function() {
+        let mut instance = instantiate(HostFunction::new(read_type(), |args| {
+            Ok(Some(Value::I32(args[0].as_i32().unwrap() * 10)))
+        }));
+        let result = instance.execute("sum", &[Value::I32(2)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(30)));
+    }
+
+    #[test]
+    fn call_mut_host_function() {
+        let mut total = 0;
+        let mut instance = instantiate(HostFunction::new_mut(read_type(), move |args| {
+            total += args[0].as_i32().unwrap();
+            Ok(Some(Value::I32(total)))
+        }));
+        // The state is kept between executions: 2 + (2 + 1).
+        let result = instance.execute("sum", &[Value::I32(2)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(5)));
+        // 6 + (6 + 1).
+        let result = instance.execute("sum", &[Value::I32(3)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(13)));
+    }
+
+    /* wat2wasm
+      (func $callback (import "env" "callback") (param i32) (result i32))
+      (func (export "fact") (param i32) (result i32)
+        (if (result i32) (i32.eqz (local.get 0))
+          (then (i32.const 1))
+          (else (i32.mul (local.get 0) (call $callback (i32.sub (local.get 0) (i32.const 1)))))
+        )
+      )
+    */
+    const CALLBACK_WASM: &[&str] = &[
+        "0061736d0100000001060160017f017f02100103656e760863616c6c6261636b0000030201000708",
+        "01046661637400010a17011500200045047f4101052000200041016b10006c0b0b",
+    ];
+
+    fn instantiate_callback(func: HostFunction) -> Instance {
+        let mut imports = Imports::new();
+        imports.define("env", "callback", func);
+        parse(from_hex(CALLBACK_WASM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    fn call_fact(caller: &mut Caller<'_>, args: &[Value]) -> HostResult {
+        let result = caller.execute("fact", args).map_err(|e| e.to_string())?;
+        match result.into_result() {
+            Ok(value) => Ok(value),
+            Err(trap) => Err(trap.reason().unwrap_or("trapped").to_string()),
+        }
+    }
+
+    #[test]
+    fn call_back_into_instance() {
+        let depths = Arc::new(Mutex::new(Vec::new()));
+        let recorded = depths.clone();
+        let mut instance = instantiate_callback(HostFunction::new_with_caller(
+            read_type(),
+            move |caller, args| {
+                recorded.lock().unwrap().push(caller.depth());
+                call_fact(caller, args)
+            },
+        ));
+        let result = instance.execute("fact", &[Value::I32(5)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(120)));
+        assert_eq!(*depths.lock().unwrap(), vec![1, 3, 5, 7, 9]);
+
+        let mut caller_errors =
+            instantiate_callback(HostFunction::new_with_caller(read_type(), |caller, _| {
+                assert_eq!(
+                    caller.execute("missing", &[]).err(),
+                    Some(Error::FunctionNotFound)
+                );
+                assert_eq!(
+                    caller.execute("fact", &[]).err(),
+                    Some(Error::ArgumentCountMismatch)
+                );
+                Ok(Some(Value::I32(1)))
+            }));
+        let result = caller_errors.execute("fact", &[Value::I32(3)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(3)));
+    }
+
+    #[test]
+    fn call_back_depth_limit() {
+        let mut instance = instantiate_callback(
+            HostFunction::new_with_caller(read_type(), call_fact).with_max_depth(6),
+        );
+        // The calls back are at the depths 2, 4 and 6.
+        let result = instance.execute("fact", &[Value::I32(3)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(6)));
+
+        let trap = instance
+            .execute("fact", &[Value::I32(4)])
+            .unwrap()
+            .into_result()
+            .unwrap_err();
+        assert_eq!(trap.reason(), Some("call depth limit exceeded"));
+
+        // Without the explicit limit, the call stack limit of the interpreter applies,
+        // trapping the innermost call of the imported function. The native stack must fit
+        // the nested executions.
+        let deep = std::thread::Builder::new()
+            .stack_size(256 << 20)
+            .spawn(|| {
+                let mut instance =
+                    instantiate_callback(HostFunction::new_with_caller(read_type(), call_fact));
+                let result = instance.execute("fact", &[Value::I32(5000)]).unwrap();
+                result
+                    .into_result()
+                    .unwrap_err()
+                    .reason()
+                    .map(str::to_string)
+            })
+            .unwrap();
+        assert_eq!(deep.join().unwrap().as_deref(), Some("trapped"));
+
+        // The explicit limit is reduced to the call depth limit of the instance.
+        let mut imports = Imports::new();
+        imports.define(
+            "env",
+            "callback",
+            HostFunction::new_with_caller(read_type(), call_fact).with_max_depth(100),
+        );
+        let options = InstantiateOptions {
+            max_call_depth: Some(7),
+            ..Default::default()
+        };
+        let mut instance = parse(from_hex(CALLBACK_WASM))
+            .unwrap()
+            .instantiate_with_options(imports, &options)
+            .unwrap();
+        let result = instance.execute("fact", &[Value::I32(3)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(6)));
+        let trap = instance
+            .execute("fact", &[Value::I32(4)])
+            .unwrap()
+            .into_result()
+            .unwrap_err();
+        assert_eq!(trap.reason(), Some("call depth limit exceeded"));
+    }
+
+    #[test]
+    fn call_back_trap_chain() {
+        let mut instance = instantiate_callback(HostFunction::new_with_caller(
+            read_type(),
+            |caller, args| {
+                if args[0] == Value::I32(0) {
+                    return Err("bottom".to_string());
+                }
+                let result = caller.execute("fact", args).unwrap();
+                match result.into_result() {
+                    Ok(value) => Ok(value),
+                    Err(_) => Err(format!("layer {}", caller.depth())),
+                }
+            },
+        ));
+        let trap = instance
+            .execute("fact", &[Value::I32(2)])
+            .unwrap()
+            .into_result()
+            .unwrap_err();
+        assert_eq!(trap.reason(), Some("layer 1"));
+        let call = trap.host_call().unwrap();
+        assert_eq!(
+            (call.module(), call.name(), call.depth()),
+            ("env", "callback", 1)
+        );
+        let functions: Vec<u32> = trap
+            .stack_trace()
+            .iter()
+            .map(Frame::function_index)
+            .collect();
+        assert_eq!(functions, [0, 1]);
+
+        let cause = trap.cause().unwrap();
+        assert_eq!(cause.reason(), Some("bottom"));
+        assert_eq!(cause.host_call().unwrap().depth(), 3);
+        assert_eq!(cause.stack_trace().len(), 2);
+        assert!(cause.cause().is_none());
+
+        let source = std::error::Error::source(&trap).unwrap();
+        assert_eq!(
+            source.to_string(),
+            "execution trapped in function #0: bottom"
+        );
+        assert!(source.source().is_none());
+    }
+
+    #[test]
+    fn unresolved_imports() {
+        assert_eq!(
+            parse(from_hex(WASM))
+                .unwrap()
+                .instantiate_with_imports(Imports::new())
+                .err(),
+            Some(Error::UnknownImport {
+                module: "env".to_string(),
+                name: "read".to_string()
+            })
+        );
+
+        let mut imports = Imports::new();
+        imports.define(
+            "env",
+            "read",
+            HostFunction::new(
+                FunctionType {
+                    inputs: vec![ValueType::I32],
+                    output: None,
+                },
+                |_| Ok(None),
+            ),
+        );
+        assert_eq!(
+            parse(from_hex(WASM))
+                .unwrap()
+                .instantiate_with_imports(imports)
+                .err(),
+            Some(Error::IncompatibleImportType {
+                module: "env".to_string(),
+                name: "read".to_string(),
+                expected: ExternType::Func(read_type()),
+                provided: ExternType::Func(FunctionType {
+                    inputs: vec![ValueType::I32],
+                    output: None,
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn call_limit() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        let mut instance = instantiate(
+            HostFunction::new(read_type(), move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(Some(Value::I32(1)))
+            })
+            .with_call_limit(2),
+        );
+
+        // The budget is renewed for every execution.
+        for _ in 0..2 {
+            let result = instance.execute("sum", &[Value::I32(0)]).unwrap();
+            assert_eq!(result.value(), Some(Value::I32(2)));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        let mut instance = instantiate(
+            HostFunction::new(read_type(), |_| Ok(Some(Value::I32(1)))).with_call_limit(1),
+        );
+        let trap = instance
+            .execute("sum", &[Value::I32(0)])
+            .unwrap()
+            .into_result()
+            .unwrap_err();
+        assert_eq!(
+            trap.reason(),
+            Some("call limit of env::read exceeded (1 calls)")
+        );
+        assert_eq!(
+            trap.to_string(),
+            "execution trapped in function #0: call limit of env::read exceeded (1 calls)"
+        );
+    }
+
+    #[test]
+    fn host_trap() {
+        let mut instance = instantiate(HostFunction::new(read_type(), |args| {
+            match args[0].as_i32().unwrap() {
+                0 => Err("invalid key".to_string()),
+                1 => Ok(Some(Value::I64(1))),
+                _ => panic!("unexpected key"),
+            }
+        }));
+        for (arg, reason) in &[
+            (0, "invalid key"),
+            (1, "result of env::read does not match the function type"),
+            (2, "env::read panicked"),
+        ] {
+            let result = instance.execute("sum", &[Value::I32(*arg)]).unwrap();
+            assert!(result.trapped());
+            assert_eq!(result.trap_origin(), Some(TrapOrigin::HostFunction));
+            assert_eq!(result.trap_reason(), Some(*reason));
+        }
+    }
+
+    #[test]
+    fn intercept() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let logger = log.clone();
+        let mut imports = Imports::new();
+        imports
+            .define(
+                "env",
+                "read",
+                HostFunction::new(read_type(), |args| {
+                    Ok(Some(Value::I32(args[0].as_i32().unwrap() * 10)))
+                }),
+            )
+            .intercept(move |call, args, next| {
+                let result = next.call(args);
+                logger.lock().unwrap().push(format!(
+                    "{}::{}@{}{:?} -> {:?}",
+                    call.module(),
+                    call.name(),
+                    call.depth(),
+                    args,
+                    result
+                ));
+                result
+            })
+            .intercept(|_, args, next| match args[0].as_i32().unwrap() {
+                // Validate and rewrite the arguments.
+                key if key < 0 => Err("negative key".to_string()),
+                9 => next.call(&[Value::I64(9)]),
+                key => next.call(&[Value::I32(key + 1)]),
+            });
+        let mut instance = parse(from_hex(WASM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+
+        let result = instance.execute("sum", &[Value::I32(2)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(50)));
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "env::read@1[i32: 2] -> Ok(Some(i32: 30))",
+                "env::read@1[i32: 1] -> Ok(Some(i32: 20))"
+            ]
+        );
+
+        let result = instance.execute("sum", &[Value::I32(-1)]).unwrap();
+        assert_eq!(result.trap_reason(), Some("negative key"));
+        let result = instance.execute("sum", &[Value::I32(9)]).unwrap();
+        assert_eq!(
+            result.trap_reason(),
+            Some("arguments of env::read do not match the function type")
+        );
+    }
+
+    /* wat2wasm
+      (memory (export "memory") 1)
+      (global $counter (export "counter") (mut i32) (i32.const 0))
+      (func (export "store") (param i32 i32)
+        (i32.store (local.get 0) (local.get 1))
+        (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+      )
+      (func (export "trap") unreachable)
+    */
+    const LIBC_WASM: &[&str] = &[
+        "0061736d0100000001090260027f7f00600000030302000105030100010606017f0141000b072304",
+        "066d656d6f7279020007636f756e74657203000573746f72650000047472617000010a1602100020",
+        "002001360200230041016a24000b0300000b",
+    ];
+
+    /* wat2wasm
+      (func $store (import "libc" "store") (param i32 i32))
+      (func $trap (import "libc" "trap"))
+      (memory (import "libc" "memory") 1)
+      (global $counter (import "libc" "counter") (mut i32))
+      (func (export "run") (result i32)
+        (call $store (i32.const 8) (i32.const 42))
+        (i32.add (i32.load (i32.const 8)) (global.get $counter))
+      )
+      (func (export "fail") (call $trap))
+    */
+    const APP_WASM: &[&str] = &[
+        "0061736d01000000010d0360027f7f006000017f600000023904046c6962630573746f7265000004",
+        "6c69626304747261700002046c696263066d656d6f7279020001046c69626307636f756e74657203",
+        "7f010303020102070e020372756e0002046661696c00030a170210004108412a1000410828020023",
+        "006a0b040010010b",
+    ];
+
+    #[test]
+    fn link_instance() {
+        let libc = parse(from_hex(LIBC_WASM)).unwrap().instantiate().unwrap();
+        let libc = Arc::new(Mutex::new(libc));
+
+        let mut imports = Imports::new();
+        imports.define_instance("libc", &libc);
+        let mut app = parse(from_hex(APP_WASM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+
+        let result = app.execute("run", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(43)));
+
+        // The memory and the global are shared with the linked instance.
+        let mut value = [0u8; 4];
+        lock(&libc).memory_get(8, &mut value).unwrap();
+        assert_eq!(value, [42, 0, 0, 0]);
+        lock(&libc)
+            .execute("store", &[Value::I32(8), Value::I32(7)])
+            .unwrap();
+        let result = app.execute("run", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(45)));
+
+        let result = app.execute("fail", &[]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(result.trap_reason(), None);
+
+        // The linked instance is kept alive by the importing instance.
+        drop(libc);
+        let result = app.execute("run", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(46)));
+    }
+
+    #[test]
+    fn link_instance_unresolved() {
+        let libc = parse(from_hex(LIBC_WASM)).unwrap().instantiate().unwrap();
+        let libc = Arc::new(Mutex::new(libc));
+
+        let mut imports = Imports::new();
+        imports.define_instance("env", &libc);
+        assert_eq!(
+            parse(from_hex(APP_WASM))
+                .unwrap()
+                .instantiate_with_imports(imports)
+                .err(),
+            Some(Error::UnknownImport {
+                module: "libc".to_string(),
+                name: "store".to_string()
+            })
+        );
+
+        /* wat2wasm
+          (global (import "libc" "counter") i32)
+        */
+        let wasm = from_hex(&["0061736d01000000021101046c69626307636f756e746572037f00"]);
+        let mut imports = Imports::new();
+        imports.define_instance("libc", &libc);
+        assert_eq!(
+            parse(wasm).unwrap().instantiate_with_imports(imports).err(),
+            Some(Error::IncompatibleImportType {
+                module: "libc".to_string(),
+                name: "counter".to_string(),
+                expected: ExternType::Global(GlobalType {
+                    value_type: ValueType::I32,
+                    mutable: false,
+                }),
+                provided: ExternType::Global(GlobalType {
+                    value_type: ValueType::I32,
+                    mutable: true,
+                }),
+            })
+        );
+
+        // The exports are found by kind.
+        /* wat2wasm
+          (memory (import "libc" "counter") 1)
+        */
+        let wasm = from_hex(&["0061736d01000000021101046c69626307636f756e746572020001"]);
+        let mut imports = Imports::new();
+        imports.define_instance("libc", &libc);
+        assert_eq!(
+            parse(wasm).unwrap().instantiate_with_imports(imports).err(),
+            Some(Error::UnknownImport {
+                module: "libc".to_string(),
+                name: "counter".to_string()
+            })
+        );
+    }
+}
@@ -4,18 +4,2316 @@
 
 //! This is a Rust interface to [Fizzy](https://github.com/wasmx/fizzy), a WebAssembly virtual machine.
 //!
-//! Currently it is only possible to validate inputs, but soon execution will be supported too.
+//! Modules can be validated, parsed, instantiated and their exported functions executed.
 
+#[cfg(feature = "baseline")]
+mod baseline;
+mod binary;
+pub mod builder;
+pub mod component;
+pub mod coverage;
+pub mod debug;
+pub mod dwarf;
+pub mod engine;
+pub mod export;
+pub mod gas;
+pub mod host;
+pub mod limiter;
+pub mod linker;
+pub mod lint;
+pub mod memory;
+pub mod metrics;
+pub mod profile;
+pub mod record;
+pub mod registry;
+pub mod resumable;
+pub mod scheduler;
+pub mod segments;
+pub mod selfcheck;
+pub mod snapshot;
+pub mod spawn;
+#[cfg(feature = "mock")]
+#[path = "mock.rs"]
 mod sys;
+#[cfg(not(feature = "mock"))]
+mod sys;
+pub mod table;
+pub mod timing;
+pub mod transform;
+pub mod typed;
+pub mod wasi;
+#[cfg(feature = "wasm-c-api")]
+pub mod wasm_c_api;
+#[cfg(feature = "wasmtime-compat")]
+pub mod wasmtime;
+
+use std::cell::Cell;
+use std::ffi::CString;
+use std::io::{IoSlice, IoSliceMut};
+use std::ops::Range;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The error type of all fallible operations of this crate.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The input is not a valid WebAssembly 1.0 module.
+    ParsingFailed,
+    /// The module binary cannot be decoded, with the reason.
+    MalformedModule(String),
+    /// The module is decoded, but does not pass the validation, with the reason.
+    InvalidModule(String),
+    /// The module could not be instantiated, with the reason.
+    InstantiationFailed(String),
+    /// There is no exported function of the given name.
+    FunctionNotFound,
+    /// The number of arguments does not match the function type.
+    ArgumentCountMismatch,
+    /// The type of an argument does not match the function type.
+    ArgumentTypeMismatch,
+    /// The memory access is outside of the bounds of the instance memory.
+    InvalidMemoryOffsetOrSize,
+    /// The execution of a function resulted in a trap, with the trap.
+    Trapped(Trap),
+    /// A component-level value does not match its declared component type.
+    ComponentTypeMismatch,
+    /// A string lifted from the instance memory is not valid UTF-8.
+    InvalidUtf8,
+    /// The DWARF debug info is malformed or of unsupported version.
+    InvalidDebugInfo,
+    /// The result type does not match the function type.
+    ResultTypeMismatch,
+    /// No item is available for the import.
+    UnknownImport { module: String, name: String },
+    /// The item available for the import is of incompatible type, with the type expected by
+    /// the import and the type of the provided item.
+    IncompatibleImportType {
+        module: String,
+        name: String,
+        expected: linker::ExternType,
+        provided: linker::ExternType,
+    },
+    /// The memory limits are invalid.
+    InvalidMemoryLimits,
+    /// A memory allocation failed.
+    OutOfMemory,
+    /// A call back into the instance would exceed the call depth limit.
+    CallDepthExceeded,
+    /// The executions of a self-checked call have diverged.
+    Diverged(selfcheck::Divergence),
+    /// The table element index is outside of the bounds of the table.
+    TableIndexOutOfBounds,
+    /// The function reference was obtained from another instance.
+    ForeignFuncRef,
+    /// The instantiation failed, because the start function has trapped.
+    StartFunctionTrapped,
+    /// A function of the module uses an opcode denied by the engine configuration.
+    DeniedOpcode { func_idx: u32, opcode: u8 },
+    /// The global is not mutable.
+    ImmutableGlobal,
+    /// The value is not of the type of the global.
+    GlobalTypeMismatch,
+    /// The instance cannot be duplicated with [`Instance::duplicate()`], with the reason.
+    NotDuplicable(NotDuplicable),
+}
+
+/// The reason why an instance cannot be duplicated, see [`Instance::duplicate()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotDuplicable {
+    /// The module has imports, which are bound to the instance.
+    Imports,
+    /// The memory is allocated with the custom allocator of the instance.
+    MemoryAllocator,
+}
+
+impl std::fmt::Display for NotDuplicable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotDuplicable::Imports => f.write_str("instance has imports"),
+            NotDuplicable::MemoryAllocator => f.write_str("instance has custom memory allocator"),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ParsingFailed => f.write_str("module parsing or validation failed"),
+            Error::MalformedModule(reason) => write!(f, "malformed module: {}", reason),
+            Error::InvalidModule(reason) => write!(f, "invalid module: {}", reason),
+            Error::InstantiationFailed(reason) => {
+                write!(f, "module instantiation failed: {}", reason)
+            }
+            Error::FunctionNotFound => f.write_str("exported function not found"),
+            Error::ArgumentCountMismatch => {
+                f.write_str("argument count does not match the function type")
+            }
+            Error::ArgumentTypeMismatch => {
+                f.write_str("argument type does not match the function type")
+            }
+            Error::InvalidMemoryOffsetOrSize => f.write_str("memory access out of bounds"),
+            Error::Trapped(trap) => trap.fmt(f),
+            Error::ComponentTypeMismatch => f.write_str("value does not match the component type"),
+            Error::InvalidUtf8 => f.write_str("string is not valid UTF-8"),
+            Error::InvalidDebugInfo => f.write_str("invalid DWARF debug info"),
+            Error::ResultTypeMismatch => {
+                f.write_str("result type does not match the function type")
+            }
+            Error::UnknownImport { module, name } => {
+                write!(f, "unknown import {}::{}", module, name)
+            }
+            Error::IncompatibleImportType {
+                module,
+                name,
+                expected,
+                provided,
+            } => write!(
+                f,
+                "incompatible type of import {}::{}: expected {}, provided {}",
+                module, name, expected, provided
+            ),
+            Error::InvalidMemoryLimits => f.write_str("invalid memory limits"),
+            Error::OutOfMemory => f.write_str("out of memory"),
+            Error::CallDepthExceeded => f.write_str("call depth limit exceeded"),
+            Error::Diverged(divergence) => write!(f, "executions diverged: {}", divergence),
+            Error::TableIndexOutOfBounds => f.write_str("table index out of bounds"),
+            Error::ForeignFuncRef => f.write_str("function reference of another instance"),
+            Error::StartFunctionTrapped => f.write_str("start function trapped"),
+            Error::DeniedOpcode { func_idx, opcode } => {
+                write!(f, "opcode {:#04x} denied in function {}", opcode, func_idx)
+            }
+            Error::ImmutableGlobal => f.write_str("global is immutable"),
+            Error::GlobalTypeMismatch => f.write_str("value does not match the global type"),
+            Error::NotDuplicable(reason) => {
+                write!(f, "instance cannot be duplicated: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error {
+    /// Returns the error reported by the C API, `otherwise` with the message if it has
+    /// no dedicated variant.
+    pub(crate) fn from_sys(error: &sys::FizzyError, otherwise: fn(String) -> Error) -> Error {
+        let message = || {
+            unsafe { std::ffi::CStr::from_ptr(error.message.as_ptr()) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        match error.code {
+            sys::FizzyErrorMalformedModule => Error::MalformedModule(message()),
+            sys::FizzyErrorInvalidModule => Error::InvalidModule(message()),
+            sys::FizzyErrorMemoryAllocationFailed => Error::OutOfMemory,
+            sys::FizzyErrorStartFunctionTrapped => Error::StartFunctionTrapped,
+            _ => otherwise(message()),
+        }
+    }
+}
+
+/// Returns the output of the errors of the C API.
+pub(crate) fn sys_error() -> sys::FizzyError {
+    sys::FizzyError {
+        code: sys::FizzySuccess,
+        message: [0; 256],
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Trapped(trap) => trap.source(),
+            _ => None,
+        }
+    }
+}
+
+/// Allows `?` on the errors of this crate in host functions, which fail with a message.
+impl From<Error> for String {
+    fn from(error: Error) -> Self {
+        error.to_string()
+    }
+}
+
+/// Returns the version of the underlying Fizzy library, e.g. "0.6.0".
+///
+/// This may differ from the version of this crate when linking the library installed
+/// in the system.
+pub fn version() -> &'static str {
+    unsafe { std::ffi::CStr::from_ptr(sys::fizzy_get_version()) }
+        .to_str()
+        .expect("version is valid UTF-8")
+}
+
+/// The optional features supported by this build, see [`capabilities()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The WASI host functions of the [`wasi`] module.
+    pub wasi: bool,
+    /// The execution metered with fuel, see the [`gas`] module.
+    pub metering: bool,
+    /// The [`MemoryBacking::Guarded`] memory backing, without falling back to
+    /// [`MemoryBacking::Mapped`].
+    pub guarded_memory: bool,
+    /// The CPU time limit, see [`Instance::execute_with_cpu_time_limit()`].
+    pub cpu_time_limit: bool,
+    /// The `wasm_c_api` module (the `wasm-c-api` feature).
+    pub wasm_c_api: bool,
+    /// The `wasmtime` module (the `wasmtime-compat` feature).
+    pub wasmtime_compat: bool,
+    /// The baseline compiler tier, see [`engine::Config`] (the `baseline` feature).
+    pub baseline_tier: bool,
+    /// The names of the supported WebAssembly proposals on top of WebAssembly 1.0, each enabled
+    /// by its [`Features`] flag.
+    pub proposals: &'static [&'static str],
+}
+
+/// Returns the optional features supported by this build of the crate and the underlying
+/// Fizzy library.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        wasi: true,
+        metering: true,
+        guarded_memory: unsafe { sys::fizzy_has_guarded_memory() },
+        cpu_time_limit: cfg!(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "ios"
+        )),
+        wasm_c_api: cfg!(feature = "wasm-c-api"),
+        wasmtime_compat: cfg!(feature = "wasmtime-compat"),
+        baseline_tier: cfg!(feature = "baseline"),
+        proposals: &["multi-memory"],
+    }
+}
+
+/// Parse and validate the input according to WebAssembly 1.0 rules. Returns true if the supplied input is valid.
+pub fn validate<T: AsRef<[u8]>>(input: T) -> bool {
+    unsafe { sys::fizzy_validate(input.as_ref().as_ptr(), input.as_ref().len()) }
+}
+
+/// A parsed and validated WebAssembly 1.0 module, with the time of its parsing.
+pub struct Module(NonNull<sys::FizzyModule>, Duration, BaselineCode);
+
+/// The function bodies of a module parsed for the baseline compiler tier, taken by
+/// its instantiation.
+#[cfg(feature = "baseline")]
+type BaselineCode = Cell<Option<Arc<baseline::ModuleCode>>>;
+#[cfg(not(feature = "baseline"))]
+#[derive(Default)]
+struct BaselineCode {}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        unsafe { sys::fizzy_free_module(self.0.as_ptr()) }
+    }
+}
+
+/// Parse and validate the input according to WebAssembly 1.0 rules.
+pub fn parse<T: AsRef<[u8]>>(input: T) -> Result<Module, Error> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("parse", size = input.as_ref().len()).entered();
+    let mut error = sys_error();
+    let started = Instant::now();
+    let ptr = unsafe {
+        sys::fizzy_parse_with_limits(
+            input.as_ref().as_ptr(),
+            input.as_ref().len(),
+            std::ptr::null(),
+            &mut error,
+        )
+    };
+    let parse_time = started.elapsed();
+    #[cfg(feature = "tracing")]
+    tracing::debug!(ok = !ptr.is_null(), duration = ?parse_time, "parsed");
+    NonNull::new(ptr as *mut sys::FizzyModule)
+        .map(|ptr| Module(ptr, parse_time, BaselineCode::default()))
+        .ok_or_else(|| Error::from_sys(&error, Error::MalformedModule))
+}
+
+/// Limits of the resources used by the parser, for parsing untrusted input.
+///
+/// The default value imposes no limits beyond the ones of the binary format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParserLimits {
+    /// The maximum number of functions, including imported ones.
+    pub max_function_count: u32,
+    /// The maximum size of a function body in bytes, including the local declarations.
+    pub max_function_body_size: u32,
+    /// The maximum number of locals of a function, excluding the function parameters.
+    pub max_local_count: u32,
+    /// The maximum depth of nested blocks, loops and ifs in a function body.
+    pub max_nesting_depth: u32,
+    /// The maximum total size in bytes of the parsed module representation (approximate).
+    pub max_allocation_size: u64,
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        ParserLimits {
+            max_function_count: u32::MAX,
+            max_function_body_size: u32::MAX,
+            max_local_count: u32::MAX,
+            max_nesting_depth: u32::MAX,
+            max_allocation_size: u64::MAX,
+        }
+    }
+}
+
+/// Parse and validate the input according to WebAssembly 1.0 rules,
+/// failing if any of the parser resource limits is exceeded.
+pub fn parse_with_limits<T: AsRef<[u8]>>(input: T, limits: &ParserLimits) -> Result<Module, Error> {
+    parse_with_features(input, limits, &Features::default())
+}
+
+/// The WebAssembly proposals accepted by the parser on top of WebAssembly 1.0.
+///
+/// All proposals are disabled by default, the modules using them are malformed or invalid.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Features {
+    /// Multiple imported and defined memories, and the memory index immediates of the memory
+    /// instructions (the `multi-memory` proposal).
+    pub multi_memory: bool,
+}
+
+/// Parse and validate the input according to WebAssembly 1.0 rules extended with the enabled
+/// proposals, failing if any of the parser resource limits is exceeded.
+pub fn parse_with_features<T: AsRef<[u8]>>(
+    input: T,
+    limits: &ParserLimits,
+    features: &Features,
+) -> Result<Module, Error> {
+    let limits = sys::FizzyParserLimits {
+        max_function_count: limits.max_function_count,
+        max_function_body_size: limits.max_function_body_size,
+        max_local_count: limits.max_local_count,
+        max_nesting_depth: limits.max_nesting_depth,
+        max_allocation_size: limits.max_allocation_size,
+    };
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("parse", size = input.as_ref().len()).entered();
+    let mut error = sys_error();
+    let started = Instant::now();
+    let features = sys::FizzyFeatures {
+        multi_memory: features.multi_memory,
+    };
+    let ptr = unsafe {
+        sys::fizzy_parse_with_features(
+            input.as_ref().as_ptr(),
+            input.as_ref().len(),
+            &limits,
+            &features,
+            &mut error,
+        )
+    };
+    let parse_time = started.elapsed();
+    #[cfg(feature = "tracing")]
+    tracing::debug!(ok = !ptr.is_null(), duration = ?parse_time, "parsed");
+    NonNull::new(ptr as *mut sys::FizzyModule)
+        .map(|ptr| Module(ptr, parse_time, BaselineCode::default()))
+        .ok_or_else(|| Error::from_sys(&error, Error::MalformedModule))
+}
+
+/// Parse and validate the input according to WebAssembly 1.0 rules, keeping the function bodies
+/// for the experimental baseline compiler tier.
+///
+/// The instances of the module compile a function to native code once it has been executed
+/// `hot_threshold` times. Only the functions computing on `i32` and `i64` values, without
+/// accessing the memory, the globals or the table and without calls, are compiled. The other
+/// functions, and the executions with a hook or a progress callback, are interpreted.
+/// [`Instance::is_compiled()`] tells whether a function has been compiled.
+#[cfg(feature = "baseline")]
+pub fn parse_with_baseline_tier<T: AsRef<[u8]>>(
+    input: T,
+    hot_threshold: u32,
+) -> Result<Module, Error> {
+    let module = parse(input.as_ref())?;
+    let code = baseline::ModuleCode::new(&module, input.as_ref(), hot_threshold);
+    module.2.set(Some(Arc::new(code)));
+    Ok(module)
+}
+
+/// The kind of allocation backing the linear memory of an instance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryBacking {
+    /// The memory is allocated on the heap and reallocated when it grows.
+    #[default]
+    Heap,
+    /// The address space for the maximum size of the memory is reserved up front and pages are
+    /// committed when it grows, so the memory is never moved.
+    /// Falls back to [`MemoryBacking::Heap`] on platforms without `mmap` or `VirtualAlloc`.
+    Mapped,
+    /// Mapped memory with the reservation covering any address accessed by an instruction,
+    /// so that the accesses are not bounds-checked. Instead, the faults of the out-of-bounds
+    /// accesses are converted into traps by a `SIGSEGV`/`SIGBUS` handler, which forwards other
+    /// faults to the previously installed handler.
+    /// Falls back to [`MemoryBacking::Mapped`] on platforms other than 64-bit Unix, and with
+    /// the `minimal` feature.
+    Guarded,
+}
+
+impl MemoryBacking {
+    pub(crate) fn to_sys(self) -> sys::FizzyMemoryBacking {
+        match self {
+            MemoryBacking::Heap => sys::FizzyMemoryBackingHeap,
+            MemoryBacking::Mapped => sys::FizzyMemoryBackingMapped,
+            MemoryBacking::Guarded => sys::FizzyMemoryBackingGuarded,
+        }
+    }
+}
+
+/// Options of the instantiation of a module.
+#[derive(Clone, Default)]
+pub struct InstantiateOptions {
+    /// The kind of allocation backing the memory defined by the module.
+    pub memory_backing: MemoryBacking,
+    /// The allocator of the memory defined by the module, used for [`MemoryBacking::Heap`] only.
+    /// By default the memory is allocated with the C runtime allocator.
+    pub memory_allocator: Option<Arc<dyn memory::Allocator>>,
+    /// Whether the start function of the module is not run by the instantiation, but later by
+    /// [`Instance::run_start()`], e.g. after the host has initialized the memory or globals.
+    pub defer_start: bool,
+    /// The hard limit of the memory size in pages, 4096 pages (256MB) by default.
+    /// The instantiation fails if the memory limits of the module exceed it.
+    pub memory_pages_limit: Option<u32>,
+    /// The maximum depth of nested calls, 2048 by default. Greater values are reduced to
+    /// the default.
+    pub max_call_depth: Option<u32>,
+    /// Whether the stack traces of the traps are not captured, which makes the traps cheaper.
+    /// [`ExecutionResult::stack_trace()`] and [`Trap::stack_trace()`] are empty then.
+    pub disable_stack_trace: bool,
+}
+
+impl std::fmt::Debug for InstantiateOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstantiateOptions")
+            .field("memory_backing", &self.memory_backing)
+            .field("memory_allocator", &self.memory_allocator.is_some())
+            .field("defer_start", &self.defer_start)
+            .field("memory_pages_limit", &self.memory_pages_limit)
+            .field("max_call_depth", &self.max_call_depth)
+            .field("disable_stack_trace", &self.disable_stack_trace)
+            .finish()
+    }
+}
+
+impl Module {
+    /// Create an instance of a module.
+    ///
+    /// Modules with imports must be instantiated with [`Module::instantiate_with_imports()`].
+    pub fn instantiate(self) -> Result<Instance, Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("instantiate").entered();
+        let mut error = sys_error();
+        let started = Instant::now();
+        let ptr = unsafe {
+            sys::fizzy_instantiate_with_options(
+                self.0.as_ptr(),
+                std::ptr::null(),
+                0,
+                std::ptr::null(),
+                std::ptr::null(),
+                0,
+                std::ptr::null(),
+                0,
+                std::ptr::null(),
+                &mut error,
+            )
+        };
+        let mut instance_imports = host::InstanceImports::default();
+        instance_imports.timings.instantiate = started.elapsed();
+        instance_imports.timings.parse = self.1;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            ok = !ptr.is_null(),
+            duration = ?instance_imports.timings.instantiate,
+            "instantiated"
+        );
+        #[cfg(feature = "baseline")]
+        {
+            instance_imports.baseline = self.2.take().map(baseline::Tier::new);
+        }
+        // Forget Module (and avoid calling drop) because it has been consumed by instantiate (even if it failed).
+        std::mem::forget(self);
+        NonNull::new(ptr)
+            .map(|ptr| Instance(ptr, instance_imports))
+            .ok_or_else(|| Error::from_sys(&error, Error::InstantiationFailed))
+    }
+
+    /// Create a copy of the module, e.g. to instantiate it more than once.
+    pub fn duplicate(&self) -> Result<Module, Error> {
+        let ptr = unsafe { sys::fizzy_clone_module(self.0.as_ptr()) };
+        #[cfg(feature = "baseline")]
+        let code = {
+            let code = self.2.take();
+            self.2.set(code.clone());
+            Cell::new(code)
+        };
+        #[cfg(not(feature = "baseline"))]
+        let code = BaselineCode::default();
+        NonNull::new(ptr as *mut sys::FizzyModule)
+            .map(|ptr| Module(ptr, self.1, code))
+            .ok_or(Error::OutOfMemory)
+    }
+
+    /// Returns the globals defined in the module, in the order of their definition.
+    ///
+    /// Imported globals are not included, the global index of the first returned global
+    /// is the number of imported globals.
+    pub fn globals(&self) -> Vec<Global> {
+        let module = self.0.as_ptr();
+        let count = unsafe { sys::fizzy_get_global_count(module) };
+        (0..count)
+            .map(|idx| {
+                let global = unsafe { sys::fizzy_get_global(module, idx) };
+                Global {
+                    ty: linker::GlobalType {
+                        value_type: ValueType::from_sys(global.type_.value_type),
+                        mutable: global.type_.is_mutable,
+                    },
+                    initializer: ConstantExpression::from_sys(
+                        &global.initializer,
+                        global.type_.value_type,
+                    ),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The type of a WebAssembly value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValueType {
+    pub(crate) fn to_sys(self) -> sys::FizzyValueType {
+        match self {
+            ValueType::I32 => sys::FizzyValueTypeI32,
+            ValueType::I64 => sys::FizzyValueTypeI64,
+            ValueType::F32 => sys::FizzyValueTypeF32,
+            ValueType::F64 => sys::FizzyValueTypeF64,
+        }
+    }
+
+    pub(crate) fn from_sys(value_type: sys::FizzyValueType) -> Self {
+        match value_type {
+            sys::FizzyValueTypeI32 => ValueType::I32,
+            sys::FizzyValueTypeI64 => ValueType::I64,
+            sys::FizzyValueTypeF32 => ValueType::F32,
+            sys::FizzyValueTypeF64 => ValueType::F64,
+            _ => panic!("invalid value type"),
+        }
+    }
+}
+
+impl std::fmt::Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ValueType::I32 => "i32",
+            ValueType::I64 => "i64",
+            ValueType::F32 => "f32",
+            ValueType::F64 => "f64",
+        })
+    }
+}
+
+/// A WebAssembly value of i32, i64, f32 or f64 type.
+///
+/// Formatting shows the type next to the value, e.g. `i32: 42` or `f64: 1.5`.
+/// The hex formats (`{:x}`, `{:#X}`, ...) show the bit pattern of the value, e.g. `f32: 0x3fc00000`.
+///
+/// Values are equal if they are of the same type and have the same bit pattern. Unlike for
+/// the floating-point numbers, NaNs of the same payload are equal and `0.0` is not equal to
+/// `-0.0`, so that values are `Eq` and `Hash`, and can be keys of maps.
+#[derive(Clone, Copy)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Value {
+    /// Returns the type of the value.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::I32(_) => ValueType::I32,
+            Value::I64(_) => ValueType::I64,
+            Value::F32(_) => ValueType::F32,
+            Value::F64(_) => ValueType::F64,
+        }
+    }
+
+    /// Converts the untyped value coming from the C API to a value of the given type.
+    ///
+    /// All values are read from the bit pattern in the i64 member, as the float members of the
+    /// union alias the i32 and f32 values only on little-endian hosts.
+    pub(crate) fn from_sys(value: sys::FizzyValue, value_type: sys::FizzyValueType) -> Self {
+        let bits = unsafe { value.i64 };
+        match value_type {
+            sys::FizzyValueTypeI32 => Value::I32(bits as u32 as i32),
+            sys::FizzyValueTypeI64 => Value::I64(bits as i64),
+            sys::FizzyValueTypeF32 => Value::F32(f32::from_bits(bits as u32)),
+            sys::FizzyValueTypeF64 => Value::F64(f64::from_bits(bits)),
+            _ => panic!("invalid value type"),
+        }
+    }
+
+    /// Returns the value if it is of i32 type.
+    pub fn as_i32(&self) -> Option<i32> {
+        match *self {
+            Value::I32(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is of i64 type.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::I64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is of f32 type.
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            Value::F32(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is of f64 type.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::F64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Creates a value of f32 type from its bit pattern, preserving NaN payloads.
+    pub fn f32_from_bits(bits: u32) -> Self {
+        Value::F32(f32::from_bits(bits))
+    }
+
+    /// Creates a value of f64 type from its bit pattern, preserving NaN payloads.
+    pub fn f64_from_bits(bits: u64) -> Self {
+        Value::F64(f64::from_bits(bits))
+    }
+
+    /// Returns the bit pattern of the value if it is of f32 type.
+    pub fn f32_to_bits(&self) -> Option<u32> {
+        self.as_f32().map(f32::to_bits)
+    }
+
+    /// Returns the bit pattern of the value if it is of f64 type.
+    pub fn f64_to_bits(&self) -> Option<u64> {
+        self.as_f64().map(f64::to_bits)
+    }
+
+    /// Returns the name of the value type as used in the WebAssembly text format.
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::I32(_) => "i32",
+            Value::I64(_) => "i64",
+            Value::F32(_) => "f32",
+            Value::F64(_) => "f64",
+        }
+    }
+
+    /// Returns the bit pattern of the value, zero-extended to 64 bits.
+    pub fn to_bits(&self) -> u64 {
+        match *self {
+            Value::I32(v) => v as u32 as u64,
+            Value::I64(v) => v as u64,
+            Value::F32(v) => v.to_bits() as u64,
+            Value::F64(v) => v.to_bits(),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.value_type() == other.value_type() && self.to_bits() == other.to_bits()
+    }
+}
+
+impl Eq for Value {}
+
+impl std::hash::Hash for Value {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.value_type().hash(state);
+        self.to_bits().hash(state);
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Value::I32(v) => write!(f, "i32: {}", v),
+            Value::I64(v) => write!(f, "i64: {}", v),
+            Value::F32(v) => write!(f, "f32: {}", v),
+            Value::F64(v) => write!(f, "f64: {}", v),
+        }
+    }
+}
+
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Unlike Display, floats always have the fractional part and NaNs show their payload.
+        match *self {
+            Value::I32(v) => write!(f, "i32: {}", v),
+            Value::I64(v) => write!(f, "i64: {}", v),
+            Value::F32(v) if v.is_nan() => write!(f, "f32: NaN ({:#010x})", v.to_bits()),
+            Value::F64(v) if v.is_nan() => write!(f, "f64: NaN ({:#018x})", v.to_bits()),
+            Value::F32(v) => write!(f, "f32: {:?}", v),
+            Value::F64(v) => write!(f, "f64: {:?}", v),
+        }
+    }
+}
+
+impl std::fmt::LowerHex for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: ", self.type_name())?;
+        std::fmt::LowerHex::fmt(&self.to_bits(), f)
+    }
+}
+
+impl std::fmt::UpperHex for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: ", self.type_name())?;
+        std::fmt::UpperHex::fmt(&self.to_bits(), f)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(v: i32) -> Self {
+        Value::I32(v)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(v: u32) -> Self {
+        Value::I32(v as i32)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::I32(v as i32)
+    }
+}
+
+impl From<i8> for Value {
+    fn from(v: i8) -> Self {
+        Value::I32(v as i32)
+    }
+}
+
+impl From<u8> for Value {
+    fn from(v: u8) -> Self {
+        Value::I32(v as i32)
+    }
+}
+
+impl From<i16> for Value {
+    fn from(v: i16) -> Self {
+        Value::I32(v as i32)
+    }
+}
+
+impl From<u16> for Value {
+    fn from(v: u16) -> Self {
+        Value::I32(v as i32)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::I64(v)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(v: u64) -> Self {
+        Value::I64(v as i64)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(v: f32) -> Self {
+        Value::F32(v)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::F64(v)
+    }
+}
+
+impl From<&Value> for Value {
+    fn from(v: &Value) -> Self {
+        *v
+    }
+}
+
+/// Builds an array of [`Value`]s from arguments converted with `Value::from`.
+///
+/// The type of each argument selects the variant, e.g. `args![1i32, 2.5f64, 7u64]` is
+/// `[Value::I32(1), Value::F64(2.5), Value::I64(7)]`. Use suffixed literals to avoid relying on
+/// the default `i32` and `f64` literal types.
+#[macro_export]
+macro_rules! args {
+    () => {
+        [$crate::Value::I32(0); 0]
+    };
+    ($($arg:expr),+ $(,)?) => {
+        [$($crate::Value::from($arg)),+]
+    };
+}
+
+impl From<Value> for sys::FizzyValue {
+    fn from(v: Value) -> Self {
+        // i32 and f32 values are stored zero-extended in the i64 member.
+        sys::FizzyValue { i64: v.to_bits() }
+    }
+}
+
+/// A WebAssembly value of unknown type, as found on the operand stack and in locals.
+///
+/// The accessors reinterpret the value as the given type.
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct UntypedValue(sys::FizzyValue);
+
+impl UntypedValue {
+    /// Returns the value as i32, from the lower 32 bits.
+    pub fn as_i32(&self) -> i32 {
+        unsafe { self.0.i64 as u32 as i32 }
+    }
+
+    /// Returns the value as u32, from the lower 32 bits.
+    pub fn as_u32(&self) -> u32 {
+        unsafe { self.0.i64 as u32 }
+    }
+
+    /// Returns the value as i64.
+    pub fn as_i64(&self) -> i64 {
+        unsafe { self.0.i64 as i64 }
+    }
+
+    /// Returns the value as u64.
+    pub fn as_u64(&self) -> u64 {
+        unsafe { self.0.i64 }
+    }
+
+    /// Returns the value as f32, from the bit pattern of the lower 32 bits.
+    pub fn as_f32(&self) -> f32 {
+        f32::from_bits(self.as_u32())
+    }
+
+    /// Returns the value as f64, from the bit pattern of all 64 bits.
+    pub fn as_f64(&self) -> f64 {
+        f64::from_bits(self.as_u64())
+    }
+}
+
+impl std::fmt::Debug for UntypedValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "UntypedValue({:#018x})", self.as_u64())
+    }
+}
+
+/// A wasm function active at the moment of a trap.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    function_index: u32,
+    function_name: Option<String>,
+}
+
+impl Frame {
+    /// The index of the function, including imported functions in the index space.
+    pub fn function_index(&self) -> u32 {
+        self.function_index
+    }
+
+    /// The name of the function from the name section, if available.
+    pub fn function_name(&self) -> Option<&str> {
+        self.function_name.as_deref()
+    }
+}
+
+/// A call of an imported function, e.g. one which has trapped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostCall {
+    module: String,
+    name: String,
+    depth: u32,
+}
+
+impl HostCall {
+    /// The module name of the import.
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+
+    /// The name of the import.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The call depth of the imported function, 1 if called by the outermost executed function.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+}
+
+/// The origin of a trap, telling the traps of the wasm code from the traps raised by the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrapOrigin {
+    /// Raised by the wasm code, e.g. by `unreachable`, an out-of-bounds memory access or
+    /// the exhaustion of the call stack.
+    Wasm,
+    /// Raised by an imported host function returning an error, or panicking.
+    HostFunction,
+    /// The execution has been aborted by the embedder, e.g. by running out of fuel in
+    /// [`Instance::execute_metered()`].
+    Aborted,
+}
+
+/// A trap which has aborted an execution.
+///
+/// A trap raised by an imported function keeps the trap of the nested execution which caused it,
+/// e.g. of a call back into the instance or of a function of a linked instance. The chain of
+/// causes is available with [`Trap::cause()`] and [`std::error::Error::source()`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trap {
+    origin: TrapOrigin,
+    stack_trace: Vec<Frame>,
+    reason: Option<String>,
+    host_call: Option<HostCall>,
+    cause: Option<Arc<Trap>>,
+}
+
+impl Trap {
+    /// Whether the trap has been raised by the wasm code or by the host.
+    pub fn origin(&self) -> TrapOrigin {
+        self.origin
+    }
+
+    /// The functions active at the moment of the trap, the innermost first.
+    pub fn stack_trace(&self) -> &[Frame] {
+        &self.stack_trace
+    }
+
+    /// The reason of the trap if it has been raised by a host function.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    /// The call of the imported function which has raised the trap, if any.
+    pub fn host_call(&self) -> Option<&HostCall> {
+        self.host_call.as_ref()
+    }
+
+    /// The trap of the nested execution which has caused the trap, if any.
+    pub fn cause(&self) -> Option<&Trap> {
+        self.cause.as_deref()
+    }
+}
+
+impl std::fmt::Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("execution trapped")?;
+        match self.stack_trace.first() {
+            Some(Frame {
+                function_name: Some(name),
+                ..
+            }) => write!(f, " in function {}", name),
+            Some(frame) => write!(f, " in function #{}", frame.function_index),
+            None => Ok(()),
+        }?;
+        match &self.reason {
+            Some(reason) => write!(f, ": {}", reason),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for Trap {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.cause
+            .as_deref()
+            .map(|cause| cause as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl From<Trap> for Error {
+    fn from(trap: Trap) -> Self {
+        Error::Trapped(trap)
+    }
+}
+
+/// The result of an execution.
+#[derive(Debug)]
+pub struct ExecutionResult {
+    trapped: bool,
+    value: Option<Value>,
+    stack_trace: Vec<Frame>,
+    trap_reason: Option<String>,
+    host_call: Option<HostCall>,
+    trap_cause: Option<Arc<Trap>>,
+    trap_origin: Option<TrapOrigin>,
+}
+
+impl ExecutionResult {
+    /// True if execution has resulted in a trap.
+    pub fn trapped(&self) -> bool {
+        self.trapped
+    }
+
+    /// The optional return value. Only a single return value is allowed in WebAssembly 1.0.
+    pub fn value(&self) -> Option<Value> {
+        self.value
+    }
+
+    /// The functions active at the moment of the trap, the innermost first.
+    /// Empty if execution has not trapped.
+    pub fn stack_trace(&self) -> &[Frame] {
+        &self.stack_trace
+    }
+
+    /// The reason of the trap if it has been raised by a host function.
+    pub fn trap_reason(&self) -> Option<&str> {
+        self.trap_reason.as_deref()
+    }
+
+    /// Whether the trap has been raised by the wasm code or by the host.
+    /// None if execution has not trapped.
+    pub fn trap_origin(&self) -> Option<TrapOrigin> {
+        self.trap_origin
+    }
+
+    /// Converts to the optional return value, or the trap if execution has trapped.
+    pub fn into_result(self) -> Result<Option<Value>, Trap> {
+        self.into()
+    }
+}
+
+impl From<ExecutionResult> for Result<Option<Value>, Trap> {
+    fn from(result: ExecutionResult) -> Self {
+        if result.trapped {
+            Err(Trap {
+                origin: result.trap_origin.unwrap_or(TrapOrigin::Wasm),
+                stack_trace: result.stack_trace,
+                reason: result.trap_reason,
+                host_call: result.host_call,
+                cause: result.trap_cause,
+            })
+        } else {
+            Ok(result.value)
+        }
+    }
+}
+
+/// A constant expression, used e.g. as an offset of a segment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConstantExpression {
+    /// A constant value.
+    Constant(Value),
+    /// The value of the global of the given index.
+    GlobalGet(u32),
+}
+
+impl ConstantExpression {
+    /// Converts the expression coming from the C API, given the type of its value.
+    fn from_sys(expr: &sys::FizzyConstantExpression, value_type: sys::FizzyValueType) -> Self {
+        match expr.kind {
+            sys::FizzyConstantExpressionKindConstant => ConstantExpression::Constant(
+                Value::from_sys(unsafe { expr.value.constant }, value_type),
+            ),
+            sys::FizzyConstantExpressionKindGlobalGet => {
+                ConstantExpression::GlobalGet(unsafe { expr.value.global_index })
+            }
+            _ => panic!("invalid constant expression kind"),
+        }
+    }
+}
+
+/// A global defined in a module.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Global {
+    pub ty: linker::GlobalType,
+    /// The expression computing the initial value, of the value type of the global.
+    pub initializer: ConstantExpression,
+}
+
+/// Returns the input types of a function type as a slice.
+///
+/// The slice is valid as long as the module the function type was taken from.
+unsafe fn function_type_inputs<'a>(
+    func_type: &sys::FizzyFunctionType,
+) -> &'a [sys::FizzyValueType] {
+    if func_type.inputs_size == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(func_type.inputs, func_type.inputs_size)
+    }
+}
+
+/// Returns the stack trace of the last execution of the instance which has trapped,
+/// skipping the first `skip` recorded frames.
+fn trap_stack_trace(instance: *mut sys::FizzyInstance, skip: usize) -> Vec<Frame> {
+    let size = unsafe { sys::fizzy_get_trap_stack_trace(instance, std::ptr::null_mut(), 0) };
+    let mut func_indices = vec![0u32; size];
+    unsafe { sys::fizzy_get_trap_stack_trace(instance, func_indices.as_mut_ptr(), size) };
+    let module = unsafe { sys::fizzy_get_instance_module(instance) };
+    func_indices
+        .into_iter()
+        .skip(skip)
+        .map(|func_idx| Frame {
+            function_index: func_idx,
+            function_name: function_name(module, func_idx),
+        })
+        .collect()
+}
+
+/// Returns the name of the function from the name section of the module, if available.
+fn function_name(module: *const sys::FizzyModule, func_idx: u32) -> Option<String> {
+    let name = unsafe { sys::fizzy_get_function_name(module, func_idx) };
+    if name.is_null() {
+        None
+    } else {
+        let name = unsafe { std::ffi::CStr::from_ptr(name) };
+        Some(name.to_string_lossy().into_owned())
+    }
+}
+
+/// Returns the data and the size of the memory of the index of the instance, or a null pointer
+/// and 0 if the instance has no such memory.
+fn instance_memory(instance: *mut sys::FizzyInstance, memory_idx: u32) -> (*mut u8, usize) {
+    let mut memory = sys::FizzyExternalMemory {
+        memory: std::ptr::null_mut(),
+        limits: sys::FizzyLimits {
+            min: 0,
+            max: 0,
+            has_max: false,
+        },
+    };
+    unsafe {
+        if !sys::fizzy_get_instance_memory(instance, memory_idx, &mut memory)
+            || memory.memory.is_null()
+        {
+            return (std::ptr::null_mut(), 0);
+        }
+        (
+            sys::fizzy_get_memory_data(memory.memory),
+            sys::fizzy_get_memory_size(memory.memory),
+        )
+    }
+}
+
+/// Returns the data of the memory of the index of the instance and the start of the memory range
+/// `[offset, offset + size)`, if the range is within the memory.
+fn checked_memory_range(
+    instance: *mut sys::FizzyInstance,
+    memory_idx: u32,
+    offset: u32,
+    size: usize,
+) -> Result<(*mut u8, usize), Error> {
+    let (data, memory_size) = instance_memory(instance, memory_idx);
+    let offset = offset as usize;
+    match offset.checked_add(size) {
+        Some(end) if end <= memory_size => Ok((data, offset)),
+        _ => Err(Error::InvalidMemoryOffsetOrSize),
+    }
+}
+
+/// Find index of exported function of the module by name.
+fn find_exported_function(module: *const sys::FizzyModule, name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut func_idx: u32 = 0;
+    let found = unsafe { sys::fizzy_find_exported_function(module, name.as_ptr(), &mut func_idx) };
+    if found {
+        Some(func_idx)
+    } else {
+        None
+    }
+}
+
+/// Check the arguments against the type of the function of the module,
+/// returning the function type.
+fn check_arguments(
+    module: *const sys::FizzyModule,
+    func_idx: u32,
+    args: &[Value],
+) -> Result<sys::FizzyFunctionType, Error> {
+    let func_type = unsafe { sys::fizzy_get_function_type(module, func_idx) };
+    let inputs = unsafe { function_type_inputs(&func_type) };
+    if inputs.len() != args.len() {
+        return Err(Error::ArgumentCountMismatch);
+    }
+    if inputs
+        .iter()
+        .zip(args.iter())
+        .any(|(&input, arg)| ValueType::from_sys(input) != arg.value_type())
+    {
+        return Err(Error::ArgumentTypeMismatch);
+    }
+    Ok(func_type)
+}
+
+/// The hook called before each executed instruction. Returning false aborts execution with a trap.
+type InstructionHook<'a> = dyn FnMut(&sys::FizzyExecutionState) -> bool + 'a;
+
+unsafe extern "C" fn instruction_hook_trampoline(
+    context: *mut std::ffi::c_void,
+    _instance: *mut sys::FizzyInstance,
+    state: *const sys::FizzyExecutionState,
+) -> bool {
+    let hook = &mut *(context as *mut &mut InstructionHook);
+    // Unwinding across the C++ interpreter is not allowed: a panicking hook aborts the execution.
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| hook(&*state))).unwrap_or(false)
+}
+
+/// The callback of the progress of the executions, with the number of executed instructions.
+pub(crate) struct ProgressCallback {
+    callback: Box<dyn FnMut(u64) -> bool + Send>,
+    interval: u64,
+    executed: u64,
+    /// Whether the callback has aborted the last execution.
+    aborted: Cell<bool>,
+}
+
+unsafe extern "C" fn progress_trampoline(
+    context: *mut std::ffi::c_void,
+    _instance: *mut sys::FizzyInstance,
+) -> bool {
+    let progress = &mut *(context as *mut ProgressCallback);
+    progress.executed += progress.interval;
+    let executed = progress.executed;
+    let callback = &mut progress.callback;
+    // Unwinding across the C++ interpreter is not allowed: a panicking callback aborts the execution.
+    let proceed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| callback(executed)))
+        .unwrap_or(false);
+    progress.aborted.set(!proceed);
+    proceed
+}
+
+/// The callback notified of the memory growth with the old and new sizes in pages.
+pub(crate) type MemoryGrowCallback = Box<dyn FnMut(u32, u32, u32) + Send>;
+
+unsafe extern "C" fn memory_grow_trampoline(
+    context: *mut std::ffi::c_void,
+    _instance: *mut sys::FizzyInstance,
+    memory_idx: u32,
+    old_pages: u32,
+    new_pages: u32,
+) {
+    let callback = &mut *(context as *mut MemoryGrowCallback);
+    // Unwinding across the C++ interpreter is not allowed, and the memory has grown already.
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        callback(memory_idx, old_pages, new_pages)
+    }));
+}
+
+/// An instance of a module, together with the host functions it imports.
+pub struct Instance(NonNull<sys::FizzyInstance>, host::InstanceImports);
+
+// The instance is not shared, and the host functions it imports are `Send`.
+unsafe impl Send for Instance {}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        unsafe { sys::fizzy_free_instance(self.0.as_ptr()) }
+    }
+}
+
+impl Instance {
+    /// Get a read-only pointer to the module.
+    fn module(&self) -> *const sys::FizzyModule {
+        unsafe { sys::fizzy_get_instance_module(self.0.as_ptr()) }
+    }
+
+    /// Returns the name, kind and index of every export of the module.
+    #[cfg_attr(
+        not(any(feature = "wasm-c-api", feature = "wasmtime-compat")),
+        allow(dead_code)
+    )]
+    fn exports(&self) -> Vec<(String, sys::FizzyExternalKind, u32)> {
+        let module = self.module();
+        let count = unsafe { sys::fizzy_get_export_count(module) };
+        (0..count)
+            .map(|export_idx| {
+                let export = unsafe { sys::fizzy_get_export_description(module, export_idx) };
+                let name = unsafe { std::ffi::CStr::from_ptr(export.name) };
+                (
+                    name.to_string_lossy().into_owned(),
+                    export.kind,
+                    export.index,
+                )
+            })
+            .collect()
+    }
+
+    /// Find index of exported function by name.
+    pub fn find_exported_function_index(&self, name: &str) -> Option<u32> {
+        find_exported_function(self.module(), name)
+    }
+
+    /// Check the arguments against the type of the function, returning the function type.
+    fn check_arguments(
+        &self,
+        func_idx: u32,
+        args: &[Value],
+    ) -> Result<sys::FizzyFunctionType, Error> {
+        check_arguments(self.module(), func_idx, args)
+    }
+
+    /// Execute a function by index, checking the arguments against the function type.
+    fn execute_function(
+        &mut self,
+        func_idx: u32,
+        args: &[Value],
+    ) -> Result<ExecutionResult, Error> {
+        let func_type = self.check_arguments(func_idx, args)?;
+
+        let args: Vec<sys::FizzyValue> = args.iter().map(|&arg| arg.into()).collect();
+        self.reset_host_calls();
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("execute", func_idx).entered();
+        #[cfg(any(feature = "tracing", feature = "metrics"))]
+        let instrumented = Instant::now();
+        let started = self.1.execution_timing.then(Instant::now);
+        let result = match self.execute_compiled(func_idx, &args) {
+            Some(result) => result,
+            None => unsafe { sys::fizzy_execute(self.0.as_ptr(), func_idx, args.as_ptr(), 0) },
+        };
+        if let Some(started) = started {
+            self.1.timings.execute += started.elapsed();
+            self.1.timings.executions += 1;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(trapped = result.trapped, duration = ?instrumented.elapsed(), "executed");
+        #[cfg(feature = "metrics")]
+        {
+            ::metrics::counter!("fizzy_executions_total").increment(1);
+            if result.trapped {
+                ::metrics::counter!("fizzy_traps_total").increment(1);
+            }
+            ::metrics::histogram!("fizzy_execution_duration_seconds")
+                .record(instrumented.elapsed());
+        }
+        Ok(self.execution_result(result, func_type.output))
+    }
+
+    /// Converts the result of an execution, with the trap raised by a host function if any.
+    fn execution_result(
+        &self,
+        result: sys::FizzyExecutionResult,
+        output: sys::FizzyValueType,
+    ) -> ExecutionResult {
+        let mut execution_result = ExecutionResult {
+            trapped: result.trapped,
+            value: if !result.trapped && result.has_value {
+                Some(Value::from_sys(result.value, output))
+            } else {
+                None
+            },
+            stack_trace: Vec::new(),
+            trap_reason: None,
+            host_call: None,
+            trap_cause: None,
+            trap_origin: None,
+        };
+        if result.trapped {
+            match self.take_host_trap() {
+                Some(host_trap) => {
+                    execution_result.trap_origin = Some(TrapOrigin::HostFunction);
+                    execution_result.stack_trace =
+                        trap_stack_trace(self.0.as_ptr(), host_trap.trace_size);
+                    execution_result.trap_reason = host_trap.reason;
+                    execution_result.host_call = Some(host_trap.call);
+                    execution_result.trap_cause = host_trap.cause;
+                }
+                None => {
+                    let aborted = self
+                        .1
+                        .progress_callback
+                        .as_ref()
+                        .is_some_and(|progress| progress.aborted.replace(false));
+                    execution_result.trap_origin = Some(if aborted {
+                        TrapOrigin::Aborted
+                    } else {
+                        TrapOrigin::Wasm
+                    });
+                    execution_result.stack_trace = self.trap_stack_trace();
+                }
+            }
+        }
+        execution_result
+    }
+
+    /// Run the start function deferred by [`InstantiateOptions::defer_start`], returning the trap
+    /// if it has trapped. Does nothing if there is no start function pending, e.g. it has
+    /// already been run.
+    pub fn run_start(&mut self) -> Result<(), Trap> {
+        self.reset_host_calls();
+        let result = unsafe { sys::fizzy_run_start(self.0.as_ptr()) };
+        self.execution_result(result, sys::FizzyValueTypeVoid)
+            .into_result()
+            .map(|_| ())
+    }
+
+    /// Execute the function compiled by the baseline compiler tier, if it is hot.
+    /// Returns `None` if the function is to be interpreted.
+    #[cfg(feature = "baseline")]
+    fn execute_compiled(
+        &mut self,
+        func_idx: u32,
+        args: &[sys::FizzyValue],
+    ) -> Option<sys::FizzyExecutionResult> {
+        // The progress callback counts the interpreted instructions.
+        if self.1.progress_callback.is_some() {
+            return None;
+        }
+        self.1.baseline.as_mut()?.execute(func_idx, args)
+    }
+
+    #[cfg(not(feature = "baseline"))]
+    fn execute_compiled(
+        &mut self,
+        _func_idx: u32,
+        _args: &[sys::FizzyValue],
+    ) -> Option<sys::FizzyExecutionResult> {
+        None
+    }
+
+    /// Returns whether the function has been compiled to native code by the baseline compiler
+    /// tier, see [`parse_with_baseline_tier()`] and
+    /// [`Config::baseline_tier()`](engine::Config::baseline_tier).
+    #[cfg(feature = "baseline")]
+    pub fn is_compiled(&self, func_idx: u32) -> bool {
+        self.1
+            .baseline
+            .as_ref()
+            .is_some_and(|tier| tier.is_compiled(func_idx))
+    }
+
+    /// Returns the stack trace of the last execution which has trapped.
+    fn trap_stack_trace(&self) -> Vec<Frame> {
+        trap_stack_trace(self.0.as_ptr(), 0)
+    }
+
+    /// Execute a function by index with the hook called before each instruction.
+    ///
+    /// A trap of the wasm code following the abort by the hook is reported as
+    /// [`TrapOrigin::Aborted`].
+    fn execute_function_with_hook(
+        &mut self,
+        func_idx: u32,
+        args: &[Value],
+        hook: &mut InstructionHook,
+    ) -> Result<ExecutionResult, Error> {
+        // Only the last call decides, the hook may have aborted a nested execution
+        // whose trap has been handled by a host function.
+        let mut aborted = false;
+        let mut hook = |state: &sys::FizzyExecutionState| {
+            // A panicking hook aborts the execution too.
+            aborted = true;
+            aborted = !hook(state);
+            !aborted
+        };
+        let mut hook: &mut InstructionHook = &mut hook;
+        unsafe {
+            sys::fizzy_set_instruction_hook(
+                self.0.as_ptr(),
+                Some(instruction_hook_trampoline),
+                &mut hook as *mut &mut InstructionHook as *mut std::ffi::c_void,
+            )
+        };
+        // The hook observes the interpreted instructions, the compiled code is not executed.
+        #[cfg(feature = "baseline")]
+        let baseline = self.1.baseline.take();
+        let mut result = self.execute_function(func_idx, args);
+        #[cfg(feature = "baseline")]
+        {
+            self.1.baseline = baseline;
+        }
+        unsafe { sys::fizzy_set_instruction_hook(self.0.as_ptr(), None, std::ptr::null_mut()) };
+        if let Ok(result) = &mut result {
+            if aborted && result.trap_origin == Some(TrapOrigin::Wasm) {
+                result.trap_origin = Some(TrapOrigin::Aborted);
+            }
+        }
+        result
+    }
+
+    /// Execute an exported function by name.
+    ///
+    /// The arguments are checked against the function type and an error is returned on mismatch.
+    pub fn execute(&mut self, name: &str, args: &[Value]) -> Result<ExecutionResult, Error> {
+        let func_idx = self
+            .find_exported_function_index(name)
+            .ok_or(Error::FunctionNotFound)?;
+        self.execute_function(func_idx, args)
+    }
+
+    /// Execute an exported function by name with the arguments of any convertible type.
+    ///
+    /// Accepts e.g. iterators of values or vectors of numbers, unlike [`Instance::execute`]
+    /// requiring a slice of values. The arguments are checked as in [`Instance::execute`].
+    pub fn call_export<I>(&mut self, name: &str, args: I) -> Result<ExecutionResult, Error>
+    where
+        I: IntoIterator,
+        I::Item: Into<Value>,
+    {
+        let args: Vec<Value> = args.into_iter().map(Into::into).collect();
+        self.execute(name, &args)
+    }
+
+    /// Returns the size of the instance memory in bytes, or 0 if the instance has no memory.
+    pub fn memory_size(&self) -> usize {
+        unsafe { sys::fizzy_get_instance_memory_size(self.0.as_ptr()) }
+    }
+
+    /// Returns the number of memories of the instance, either imported or defined. It is more
+    /// than 1 only for the modules using the multi-memory proposal.
+    pub fn memory_count(&self) -> u32 {
+        unsafe { sys::fizzy_get_memory_count(self.module()) }
+    }
+
+    /// Returns the size of the memory of the index in bytes, or 0 if there is no such memory.
+    /// The imported memories come first.
+    pub fn memory_size_at(&self, memory_idx: u32) -> usize {
+        instance_memory(self.0.as_ptr(), memory_idx).1
+    }
+
+    /// Call the `callback` whenever a memory grows by `memory.grow` executed by the instance,
+    /// with the index of the memory and its old and new sizes in pages. Replaces any previous
+    /// callback.
+    ///
+    /// The callback is called after the growth, so it can observe the new memory. It is not
+    /// called if the growth fails or the size does not change. A panic of the callback is
+    /// ignored.
+    pub fn on_memory_grow<F>(&mut self, callback: F)
+    where
+        F: FnMut(u32, u32, u32) + Send + 'static,
+    {
+        let mut callback: Box<MemoryGrowCallback> = Box::new(Box::new(callback));
+        unsafe {
+            sys::fizzy_set_memory_grow_hook(
+                self.0.as_ptr(),
+                Some(memory_grow_trampoline),
+                &mut *callback as *mut MemoryGrowCallback as *mut std::ffi::c_void,
+            )
+        };
+        self.1.memory_grow_callback = Some(callback);
+    }
+
+    /// Call the `callback` after every `interval` instructions executed by the instance, with
+    /// the number of instructions executed since the callback was set. Returning false aborts
+    /// the execution with a trap of [`TrapOrigin::Aborted`]. Replaces any previous callback.
+    ///
+    /// This is a much cheaper way to observe the progress of long executions, or to interrupt
+    /// them, than a hook called before every instruction. The instructions are counted across
+    /// the executions, including the nested executions of host functions. A panic of
+    /// the callback aborts the execution.
+    ///
+    /// Panics if the `interval` is 0.
+    pub fn on_progress<F>(&mut self, interval: u64, callback: F)
+    where
+        F: FnMut(u64) -> bool + Send + 'static,
+    {
+        assert!(interval > 0, "progress interval must be positive");
+        let mut progress = Box::new(ProgressCallback {
+            callback: Box::new(callback),
+            interval,
+            executed: 0,
+            aborted: Cell::new(false),
+        });
+        unsafe {
+            sys::fizzy_set_periodic_hook(
+                self.0.as_ptr(),
+                interval,
+                Some(progress_trampoline),
+                &mut *progress as *mut ProgressCallback as *mut std::ffi::c_void,
+            )
+        };
+        self.1.progress_callback = Some(progress);
+    }
+
+    /// Remove the callback set with [`Instance::on_progress()`].
+    pub fn remove_progress_callback(&mut self) {
+        unsafe { sys::fizzy_set_periodic_hook(self.0.as_ptr(), 0, None, std::ptr::null_mut()) };
+        self.1.progress_callback = None;
+    }
+
+    /// Remove the callback set with [`Instance::on_memory_grow()`].
+    pub fn remove_memory_grow_callback(&mut self) {
+        unsafe { sys::fizzy_set_memory_grow_hook(self.0.as_ptr(), None, std::ptr::null_mut()) };
+        self.1.memory_grow_callback = None;
+    }
+
+    /// Copy memory starting at `offset` into `dst`.
+    pub fn memory_get(&self, offset: u32, dst: &mut [u8]) -> Result<(), Error> {
+        self.memory_get_at(0, offset, dst)
+    }
+
+    /// Copy the memory of the index starting at `offset` into `dst`.
+    pub fn memory_get_at(&self, memory_idx: u32, offset: u32, dst: &mut [u8]) -> Result<(), Error> {
+        let (data, start) = checked_memory_range(self.0.as_ptr(), memory_idx, offset, dst.len())?;
+        if !dst.is_empty() {
+            unsafe { std::ptr::copy_nonoverlapping(data.add(start), dst.as_mut_ptr(), dst.len()) };
+        }
+        Ok(())
+    }
+
+    /// Copy `src` into memory starting at `offset`.
+    pub fn memory_set(&mut self, offset: u32, src: &[u8]) -> Result<(), Error> {
+        self.memory_set_at(0, offset, src)
+    }
+
+    /// Copy `src` into the memory of the index starting at `offset`.
+    pub fn memory_set_at(&mut self, memory_idx: u32, offset: u32, src: &[u8]) -> Result<(), Error> {
+        let (data, start) = checked_memory_range(self.0.as_ptr(), memory_idx, offset, src.len())?;
+        if let Some(recording) = self.1.recording.borrow_mut().as_mut() {
+            recording.memory_write(memory_idx, offset, src);
+        }
+        if !src.is_empty() {
+            unsafe { std::ptr::copy_nonoverlapping(src.as_ptr(), data.add(start), src.len()) };
+        }
+        Ok(())
+    }
+
+    /// Returns the memory data and the total size of the buffers, if the memory range of this
+    /// size starting at `offset` is within the memory of the index.
+    fn checked_vectored_range<I>(
+        &self,
+        memory_idx: u32,
+        offset: u32,
+        mut sizes: I,
+    ) -> Result<(*mut u8, usize), Error>
+    where
+        I: Iterator<Item = usize>,
+    {
+        let size = sizes
+            .try_fold(0usize, usize::checked_add)
+            .ok_or(Error::InvalidMemoryOffsetOrSize)?;
+        let (data, _) = checked_memory_range(self.0.as_ptr(), memory_idx, offset, size)?;
+        Ok((data, size))
+    }
+
+    /// Copy memory starting at `offset` into the buffers, filling them one after another.
+    ///
+    /// Returns the total number of bytes copied. Nothing is copied if the buffers together
+    /// do not fit in the memory.
+    pub fn memory_get_vectored(
+        &self,
+        offset: u32,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Result<usize, Error> {
+        self.memory_get_vectored_at(0, offset, bufs)
+    }
+
+    /// Copy the memory of the index starting at `offset` into the buffers, filling them one after
+    /// another, see [`Instance::memory_get_vectored()`].
+    pub fn memory_get_vectored_at(
+        &self,
+        memory_idx: u32,
+        offset: u32,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Result<usize, Error> {
+        let (data, size) =
+            self.checked_vectored_range(memory_idx, offset, bufs.iter().map(|buf| buf.len()))?;
+        let mut start = offset as usize;
+        for buf in bufs.iter_mut().filter(|buf| !buf.is_empty()) {
+            unsafe { std::ptr::copy_nonoverlapping(data.add(start), buf.as_mut_ptr(), buf.len()) };
+            start += buf.len();
+        }
+        Ok(size)
+    }
+
+    /// Copy the buffers one after another into memory starting at `offset`.
+    ///
+    /// Returns the total number of bytes copied. Nothing is copied if the buffers together
+    /// do not fit in the memory. While recording, every buffer is recorded as a separate write.
+    pub fn memory_set_vectored(
+        &mut self,
+        offset: u32,
+        bufs: &[IoSlice<'_>],
+    ) -> Result<usize, Error> {
+        self.memory_set_vectored_at(0, offset, bufs)
+    }
+
+    /// Copy the buffers one after another into the memory of the index starting at `offset`,
+    /// see [`Instance::memory_set_vectored()`].
+    pub fn memory_set_vectored_at(
+        &mut self,
+        memory_idx: u32,
+        offset: u32,
+        bufs: &[IoSlice<'_>],
+    ) -> Result<usize, Error> {
+        let (data, size) =
+            self.checked_vectored_range(memory_idx, offset, bufs.iter().map(|buf| buf.len()))?;
+        let mut start = offset as usize;
+        for buf in bufs.iter().filter(|buf| !buf.is_empty()) {
+            if let Some(recording) = self.1.recording.borrow_mut().as_mut() {
+                // The start of a non-empty buffer is within the memory of at most 4 GiB.
+                recording.memory_write(memory_idx, start as u32, buf);
+            }
+            unsafe { std::ptr::copy_nonoverlapping(buf.as_ptr(), data.add(start), buf.len()) };
+            start += buf.len();
+        }
+        Ok(size)
+    }
+
+    /// Make the memory range `[offset, offset + size)` read-only for the guest:
+    /// the store instructions writing any byte of it trap. The host can still write it.
+    ///
+    /// The range applies to all instances sharing the memory.
+    pub fn set_memory_read_only(&mut self, offset: u32, size: u32) -> Result<(), Error> {
+        self.set_memory_read_only_at(0, offset, size)
+    }
+
+    /// Make the range `[offset, offset + size)` of the memory of the index read-only for
+    /// the guest, see [`Instance::set_memory_read_only()`].
+    pub fn set_memory_read_only_at(
+        &mut self,
+        memory_idx: u32,
+        offset: u32,
+        size: u32,
+    ) -> Result<(), Error> {
+        let added = unsafe {
+            sys::fizzy_add_instance_memory_read_only_range(
+                self.0.as_ptr(),
+                memory_idx,
+                offset,
+                size,
+            )
+        };
+        if added {
+            Ok(())
+        } else {
+            Err(Error::InvalidMemoryOffsetOrSize)
+        }
+    }
+
+    /// Make all the memory writable for the guest again.
+    pub fn clear_memory_read_only(&mut self) {
+        self.clear_memory_read_only_at(0)
+    }
+
+    /// Make all the memory of the index writable for the guest again.
+    pub fn clear_memory_read_only_at(&mut self, memory_idx: u32) {
+        unsafe { sys::fizzy_clear_instance_memory_read_only_ranges(self.0.as_ptr(), memory_idx) }
+    }
+
+    /// Compute the SHA-256 hash of the state of the instance: its memory, globals and table.
+    ///
+    /// The hash is deterministic across platforms, so the end states of executions can be compared
+    /// without serializing them. The table elements contribute only their function types.
+    pub fn state_hash(&self) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+        unsafe { sys::fizzy_get_instance_state_hash(self.0.as_ptr(), hash.as_mut_ptr()) }
+        hash
+    }
+
+    /// Create an independent copy of the instance, with the memory, the globals and the table
+    /// copied, sharing the module. E.g. an instance warmed up by its initialization can serve as
+    /// the template of the instances executing the requests.
+    ///
+    /// The copy starts without the callbacks, the limiter and the recording of the instance,
+    /// the snapshot of the memory is copied. The baseline compiler tier of the copy compiles
+    /// its hot functions again. The instances of modules with imports and
+    /// the instances with a custom memory allocator cannot be duplicated, because the host items
+    /// are bound to the instance, [`Error::NotDuplicable`] is returned for them.
+    pub fn duplicate(&self) -> Result<Instance, Error> {
+        if unsafe { sys::fizzy_get_import_count(self.module()) } != 0 {
+            return Err(Error::NotDuplicable(NotDuplicable::Imports));
+        }
+        if self.1.memory_allocator.is_some() {
+            return Err(Error::NotDuplicable(NotDuplicable::MemoryAllocator));
+        }
+        let mut error = sys_error();
+        let ptr = unsafe { sys::fizzy_duplicate_instance(self.0.as_ptr(), &mut error) };
+        let mut instance_imports = host::InstanceImports::default();
+        instance_imports.execution_timing = self.1.execution_timing;
+        instance_imports.memory_snapshot = self.1.memory_snapshot.clone();
+        #[cfg(feature = "baseline")]
+        {
+            instance_imports.baseline = self.1.baseline.as_ref().map(baseline::Tier::fresh);
+        }
+        NonNull::new(ptr)
+            .map(|ptr| Instance(ptr, instance_imports))
+            .ok_or_else(|| Error::from_sys(&error, Error::InstantiationFailed))
+    }
+}
+
+/// Copy the `src_range` of the memory of the `src` instance into the memory of the `dst` instance
+/// starting at `dst_offset`, without an intermediate buffer.
+///
+/// Nothing is copied if either range is not within the memory. The instances may share
+/// an imported memory, then the ranges may overlap. While recording, the copy is recorded as
+/// a write to the memory of `dst`.
+pub fn copy_memory(
+    src: &Instance,
+    src_range: Range<u32>,
+    dst: &mut Instance,
+    dst_offset: u32,
+) -> Result<(), Error> {
+    copy_memory_at(src, 0, src_range, dst, 0, dst_offset)
+}
+
+/// Copy the `src_range` of the memory of the index `src_memory_idx` of the `src` instance into
+/// the memory of the index `dst_memory_idx` of the `dst` instance starting at `dst_offset`,
+/// see [`copy_memory()`].
+pub fn copy_memory_at(
+    src: &Instance,
+    src_memory_idx: u32,
+    src_range: Range<u32>,
+    dst: &mut Instance,
+    dst_memory_idx: u32,
+    dst_offset: u32,
+) -> Result<(), Error> {
+    let size = src_range
+        .end
+        .checked_sub(src_range.start)
+        .ok_or(Error::InvalidMemoryOffsetOrSize)? as usize;
+    let (src_data, src_start) =
+        checked_memory_range(src.0.as_ptr(), src_memory_idx, src_range.start, size)?;
+    let (dst_data, dst_start) =
+        checked_memory_range(dst.0.as_ptr(), dst_memory_idx, dst_offset, size)?;
+    if size == 0 {
+        return Ok(());
+    }
+    unsafe {
+        std::ptr::copy(src_data.add(src_start), dst_data.add(dst_start), size);
+        if let Some(recording) = dst.1.recording.borrow_mut().as_mut() {
+            let copied = std::slice::from_raw_parts(dst_data.add(dst_start), size);
+            recording.memory_write(dst_memory_idx, dst_offset, copied);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    /// Decodes a hex string split into multiple parts (as used for the wat2wasm outputs in tests).
+    pub fn from_hex(parts: &[&str]) -> Vec<u8> {
+        let hex: String = parts.concat();
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Parses the input with the multi-memory proposal enabled.
+    pub fn parse_multi_memory<T: AsRef<[u8]>>(input: T) -> Result<crate::Module, crate::Error> {
+        let features = crate::Features { multi_memory: true };
+        crate::parse_with_features(input, &Default::default(), &features)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_utils::{from_hex, parse_multi_memory};
+    use super::*;
+
+    #[test]
+    fn version_and_capabilities() {
+        assert_eq!(version(), env!("CARGO_PKG_VERSION"));
+
+        let capabilities = capabilities();
+        assert!(capabilities.wasi);
+        assert!(capabilities.metering);
+        assert_eq!(
+            capabilities.guarded_memory,
+            cfg!(all(
+                unix,
+                target_pointer_width = "64",
+                not(feature = "minimal"),
+                not(feature = "mock")
+            ))
+        );
+        assert_eq!(capabilities.wasm_c_api, cfg!(feature = "wasm-c-api"));
+        assert_eq!(capabilities.proposals, ["multi-memory"]);
+    }
+
+    #[test]
+    fn error_display() {
+        assert_eq!(
+            Error::FunctionNotFound.to_string(),
+            "exported function not found"
+        );
+        assert_eq!(Error::OutOfMemory.to_string(), "out of memory");
+        assert_eq!(
+            Error::InstantiationFailed("unresolved import".to_string()).to_string(),
+            "module instantiation failed: unresolved import"
+        );
+        assert_eq!(
+            Error::MalformedModule("invalid section id".to_string()).to_string(),
+            "malformed module: invalid section id"
+        );
+        let error: Box<dyn std::error::Error> = Box::new(Error::Trapped(Trap {
+            origin: TrapOrigin::Wasm,
+            stack_trace: Vec::new(),
+            reason: None,
+            host_call: None,
+            cause: None,
+        }));
+        assert_eq!(error.to_string(), "execution trapped");
+        assert_eq!(
+            Error::UnknownImport {
+                module: "env".to_string(),
+                name: "foo".to_string()
+            }
+            .to_string(),
+            "unknown import env::foo"
+        );
+    }
+
+    #[test]
+    fn parse_with_limits() {
+        /* wat2wasm
+          (func (local i32 i32) block nop end)
+          (func)
+        */
+        let wasm =
+            from_hex(&["0061736d0100000001040160000003030200000a0d020801027f0240010b0b02000b"]);
+        assert!(super::parse_with_limits(&wasm, &ParserLimits::default()).is_ok());
+
+        let limits = ParserLimits {
+            max_function_count: 2,
+            max_function_body_size: 8,
+            max_local_count: 2,
+            max_nesting_depth: 1,
+            max_allocation_size: 1024 * 1024,
+        };
+        assert!(super::parse_with_limits(&wasm, &limits).is_ok());
+        for exceeded in &[
+            ParserLimits {
+                max_function_count: 1,
+                ..limits
+            },
+            ParserLimits {
+                max_function_body_size: 7,
+                ..limits
+            },
+            ParserLimits {
+                max_local_count: 1,
+                ..limits
+            },
+            ParserLimits {
+                max_nesting_depth: 0,
+                ..limits
+            },
+            ParserLimits {
+                max_allocation_size: 16,
+                ..limits
+            },
+        ] {
+            assert!(matches!(
+                super::parse_with_limits(&wasm, exceeded).err(),
+                Some(Error::MalformedModule(reason)) if reason.ends_with("limit exceeded")
+            ));
+        }
+    }
+
+    #[test]
+    fn parse_with_features() {
+        /* wat2wasm --enable-multi-memory
+          (memory 1)
+          (memory 2)
+        */
+        let wasm = from_hex(&["0061736d0100000005050200010002"]);
+        assert_eq!(
+            parse(&wasm).err(),
+            Some(Error::InvalidModule(
+                "too many memory sections (at most one is allowed)".to_string()
+            ))
+        );
+        assert!(
+            super::parse_with_features(&wasm, &ParserLimits::default(), &Features::default())
+                .is_err()
+        );
+
+        let features = Features { multi_memory: true };
+        let module =
+            super::parse_with_features(&wasm, &ParserLimits::default(), &features).unwrap();
+        assert_eq!(module.memory_count(), 2);
+        assert!(capabilities().proposals.contains(&"multi-memory"));
+    }
+
+    #[test]
+    fn globals() {
+        /* wat2wasm
+          (global (import "env" "g") i32)
+          (global i64 (i64.const -1))
+          (global (export "f") (mut f32) (f32.const 1.5))
+          (global i32 (global.get 0))
+        */
+        let module = parse(from_hex(&[
+            "0061736d01000000020a0103656e760167037f000613037e00427f0b7d01430000c03f0b7f002300",
+            "0b07050101660302",
+        ]))
+        .unwrap();
+        let globals = module.globals();
+        assert_eq!(globals.len(), 3);
+        assert_eq!(
+            globals[0],
+            Global {
+                ty: linker::GlobalType {
+                    value_type: ValueType::I64,
+                    mutable: false
+                },
+                initializer: ConstantExpression::Constant(Value::I64(-1)),
+            }
+        );
+        assert_eq!(
+            globals[1],
+            Global {
+                ty: linker::GlobalType {
+                    value_type: ValueType::F32,
+                    mutable: true
+                },
+                initializer: ConstantExpression::Constant(Value::F32(1.5)),
+            }
+        );
+        assert_eq!(
+            globals[2],
+            Global {
+                ty: linker::GlobalType {
+                    value_type: ValueType::I32,
+                    mutable: false
+                },
+                initializer: ConstantExpression::GlobalGet(0),
+            }
+        );
+
+        /* wat2wasm
+          (func)
+        */
+        let module = parse(from_hex(&[
+            "0061736d01000000010401600000030201000a040102000b",
+        ]))
+        .unwrap();
+        assert!(module.globals().is_empty());
+    }
+
+    #[test]
+    fn value_format() {
+        assert_eq!(Value::I32(42).to_string(), "i32: 42");
+        assert_eq!(Value::I64(-1).to_string(), "i64: -1");
+        assert_eq!(Value::F32(1.5).to_string(), "f32: 1.5");
+        assert_eq!(Value::F64(1.5).to_string(), "f64: 1.5");
+
+        assert_eq!(format!("{:?}", Value::I32(-42)), "i32: -42");
+        assert_eq!(format!("{:?}", Value::F64(1.0)), "f64: 1.0");
+        assert_eq!(
+            format!("{:?}", Value::F32(f32::NAN)),
+            "f32: NaN (0x7fc00000)"
+        );
+        assert_eq!(
+            format!("{:?}", Value::F64(-f64::NAN)),
+            "f64: NaN (0xfff8000000000000)"
+        );
+        assert_eq!(format!("{:?}", [Value::I32(1)]), "[i32: 1]");
+
+        assert_eq!(format!("{:x}", Value::I32(-1)), "i32: ffffffff");
+        assert_eq!(format!("{:#x}", Value::I64(42)), "i64: 0x2a");
+        assert_eq!(format!("{:#X}", Value::F32(1.5)), "f32: 0x3FC00000");
+        assert_eq!(
+            format!("{:#018x}", Value::F64(1.0)),
+            "f64: 0x3ff0000000000000"
+        );
+        assert_eq!(Value::F64(-0.0).to_bits(), 0x8000000000000000);
+    }
+
+    #[test]
+    fn args_macro() {
+        let empty: [Value; 0] = args![];
+        assert!(empty.is_empty());
+        assert_eq!(
+            args![1i32, 2.5f64, 7u64],
+            [Value::I32(1), Value::F64(2.5), Value::I64(7)]
+        );
+        assert_eq!(
+            args![u32::MAX, -1i64, 0.5f32,],
+            [Value::I32(-1), Value::I64(-1), Value::F32(0.5)]
+        );
+        let x = 3u8;
+        assert_eq!(args![i32::from(x) * 2], [Value::I32(6)]);
+        assert_eq!(
+            args![true, false, -1i8, 0xffu8, -1i16, 0xffffu16],
+            [
+                Value::I32(1),
+                Value::I32(0),
+                Value::I32(-1),
+                Value::I32(0xff),
+                Value::I32(-1),
+                Value::I32(0xffff)
+            ]
+        );
+    }
 
-/// Parse and validate the input according to WebAssembly 1.0 rules. Returns true if the supplied input is valid.
-pub fn validate<T: AsRef<[u8]>>(input: T) -> bool {
-    unsafe { sys::fizzy_validate(input.as_ref().as_ptr(), input.as_ref().len()) }
-}
+    #[test]
+    fn value_type() {
+        assert_eq!(Value::I32(0).value_type(), ValueType::I32);
+        assert_eq!(Value::I64(0).value_type(), ValueType::I64);
+        assert_eq!(Value::F32(0.0).value_type(), ValueType::F32);
+        assert_eq!(Value::F64(0.0).value_type(), ValueType::F64);
+        assert_eq!(ValueType::I64.to_string(), "i64");
+        assert_eq!(ValueType::F32.to_string(), "f32");
+        for &ty in &[
+            ValueType::I32,
+            ValueType::I64,
+            ValueType::F32,
+            ValueType::F64,
+        ] {
+            assert_eq!(ValueType::from_sys(ty.to_sys()), ty);
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn value_bits() {
+        let nan = Value::f32_from_bits(0x7fa00001);
+        assert_eq!(nan.f32_to_bits(), Some(0x7fa00001));
+        assert_eq!(nan.f64_to_bits(), None);
+        assert_eq!(nan.to_bits(), 0x7fa00001);
+        assert_eq!(Value::f32_from_bits(0x3fc00000), Value::F32(1.5));
+
+        let nan = Value::f64_from_bits(0xfff4000000000001);
+        assert_eq!(nan.f64_to_bits(), Some(0xfff4000000000001));
+        assert_eq!(nan.f32_to_bits(), None);
+        assert_eq!(
+            Value::f64_from_bits(0x8000000000000000).as_f64(),
+            Some(-0.0)
+        );
+        assert_eq!(Value::I32(1).f32_to_bits(), None);
+
+        /* wat2wasm
+          (func (export "f32.id") (param f32) (result f32) (local.get 0))
+          (func (export "f64.neg") (param f64) (result f64) (f64.neg (local.get 0)))
+        */
+        let input = from_hex(&[
+            "0061736d01000000010b0260017d017d60017c017c0303020001071402066633322e696400000766",
+            "36342e6e656700010a0c02040020000b050020009a0b",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        let result = instance
+            .execute("f32.id", &[Value::f32_from_bits(0x7fa00001)])
+            .unwrap();
+        assert_eq!(result.value().unwrap().f32_to_bits(), Some(0x7fa00001));
+        let result = instance
+            .execute("f64.neg", &[Value::f64_from_bits(0x7ff4000000000001)])
+            .unwrap();
+        assert_eq!(
+            result.value().unwrap().f64_to_bits(),
+            Some(0xfff4000000000001)
+        );
+    }
+
+    #[test]
+    fn value_equality() {
+        use std::collections::HashSet;
+
+        assert_eq!(Value::I32(1), Value::I32(1));
+        assert_ne!(Value::I32(1), Value::I64(1));
+        assert_ne!(Value::I32(0), Value::F32(0.0));
+        assert_eq!(Value::F64(1.5), Value::F64(1.5));
+        assert_ne!(Value::F64(0.0), Value::F64(-0.0));
+        assert_eq!(Value::F32(f32::NAN), Value::F32(f32::NAN));
+        assert_ne!(
+            Value::f32_from_bits(0x7fc00000),
+            Value::f32_from_bits(0x7fc00001)
+        );
+        assert_ne!(Value::F32(f32::NAN), Value::F64(f64::NAN));
+
+        let values: HashSet<Value> = [
+            Value::I32(1),
+            Value::I64(1),
+            Value::F32(1.0),
+            Value::F32(1.0),
+            Value::f64_from_bits(0x7ff8000000000001),
+            Value::f64_from_bits(0x7ff8000000000001),
+        ]
+        .iter()
+        .copied()
+        .collect();
+        assert_eq!(values.len(), 4);
+        assert!(values.contains(&Value::f64_from_bits(0x7ff8000000000001)));
+        assert!(!values.contains(&Value::F64(1.0)));
+    }
+
+    #[test]
+    fn value_marshalling() {
+        // The expected bit patterns are independent of the host endianness.
+        let bits = |v: Value| unsafe { sys::FizzyValue::from(v).i64 };
+        assert_eq!(bits(Value::I32(-2)), 0x0000_0000_ffff_fffe);
+        assert_eq!(bits(Value::I64(-2)), 0xffff_ffff_ffff_fffe);
+        assert_eq!(bits(Value::F32(1.5)), 0x0000_0000_3fc0_0000);
+        assert_eq!(bits(Value::F64(-1.5)), 0xbff8_0000_0000_0000);
+
+        // The upper bits of i32 and f32 values are ignored.
+        let value = sys::FizzyValue {
+            i64: 0xffff_ffff_3fc0_0000,
+        };
+        assert_eq!(
+            Value::from_sys(value, sys::FizzyValueTypeI32),
+            Value::I32(0x3fc0_0000)
+        );
+        assert_eq!(
+            Value::from_sys(value, sys::FizzyValueTypeF32),
+            Value::F32(1.5)
+        );
+        assert_eq!(UntypedValue(value).as_f32(), 1.5);
+        assert_eq!(UntypedValue(value).as_i64(), -0xc040_0000);
+
+        let value = sys::FizzyValue {
+            i64: 0xbff8_0000_0000_0000,
+        };
+        assert_eq!(
+            Value::from_sys(value, sys::FizzyValueTypeF64),
+            Value::F64(-1.5)
+        );
+        assert_eq!(UntypedValue(value).as_f64(), -1.5);
+    }
+
+    #[test]
+    fn execute_floating_point() {
+        /* wat2wasm
+          (func (export "f32.neg") (param f32) (result f32) (f32.neg (local.get 0)))
+          (func (export "f32.const") (result f32) (f32.const 1.5))
+          (func (export "f64.neg") (param f64) (result f64) (f64.neg (local.get 0)))
+          (memory (export "mem") 1)
+          (data (i32.const 0) "\00\00\c0\3f")
+          (func (export "f32.load") (result f32) (f32.load (i32.const 0)))
+        */
+        let input = from_hex(&[
+            "0061736d01000000010f0360017d017d6000017d60017c017c030504000102010503010001073205",
+            "076633322e6e65670000096633322e636f6e73740001076636342e6e65670002036d656d02000866",
+            "33322e6c6f616400030a1d04050020008c0b0700430000c03f0b050020009a0b070041002a02000b",
+            "0b0a010041000b040000c03f",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+
+        let result = instance.execute("f32.neg", &[Value::F32(1.5)]).unwrap();
+        assert_eq!(result.value(), Some(Value::F32(-1.5)));
+        let result = instance.execute("f32.const", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::F32(1.5)));
+        let result = instance.execute("f64.neg", &[Value::F64(1.5)]).unwrap();
+        assert_eq!(result.value(), Some(Value::F64(-1.5)));
+        let result = instance.execute("f32.load", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::F32(1.5)));
+    }
 
     #[test]
     fn validate_wasm() {
@@ -34,4 +2332,1086 @@ mod tests {
             false
         );
     }
+
+    #[test]
+    fn parse_wasm() {
+        assert!(parse([0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]).is_ok());
+        assert_eq!(
+            parse([0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x01]).err(),
+            Some(Error::MalformedModule(
+                "invalid wasm module prefix".to_string()
+            ))
+        );
+        // The function bodies are not validated by the mock.
+        /* wat2wasm --no-check
+          (func (result i32))
+        */
+        #[cfg(not(feature = "mock"))]
+        assert_eq!(
+            parse(from_hex(&[
+                "0061736d010000000105016000017f030201000a040102000b"
+            ]))
+            .err(),
+            Some(Error::InvalidModule("stack underflow".to_string()))
+        );
+    }
+
+    #[test]
+    fn instantiate_wasm() {
+        let module = parse([0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]).unwrap();
+        assert!(module.instantiate().is_ok());
+
+        /* wat2wasm
+          (func (import "mod1" "foo1") (result i32))
+        */
+        let input = from_hex(&["0061736d010000000105016000017f020d01046d6f643104666f6f310000"]);
+        let module = parse(&input).unwrap();
+        assert_eq!(
+            module.instantiate().err(),
+            Some(Error::InstantiationFailed(
+                "module requires 1 imported functions, 0 provided".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn module_duplicate() {
+        /* wat2wasm
+          (func (export "foo") (result i32) i32.const 42)
+        */
+        let input =
+            from_hex(&["0061736d010000000105016000017f0302010007070103666f6f00000a06010400412a0b"]);
+        let module = parse(&input).unwrap();
+        let copy = module.duplicate().unwrap();
+        let mut instance = module.instantiate().unwrap();
+        assert_eq!(
+            instance.execute("foo", &[]).unwrap().value(),
+            Some(Value::I32(42))
+        );
+        let mut instance = copy.instantiate().unwrap();
+        assert_eq!(
+            instance.execute("foo", &[]).unwrap().value(),
+            Some(Value::I32(42))
+        );
+    }
+
+    #[test]
+    fn execute_wasm() {
+        /* wat2wasm
+          (func (export "foo") (result i32) i32.const 42)
+          (func (export "div") (param i32 i32) (result i32)
+            (i32.div_u (local.get 0) (local.get 1))
+          )
+          (func (export "fail") unreachable)
+          (func (export "f64") (param f64) (result f64) local.get 0)
+        */
+        let input = from_hex(&[
+            "0061736d010000000113046000017f60027f7f017f60000060017c017c03050400010203071a0403",
+            "666f6f0000036469760001046661696c00020366363400030a17040400412a0b0700200020016e0b",
+            "0300000b040020000b",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+
+        let result = instance.execute("foo", &[]).unwrap();
+        assert!(!result.trapped());
+        assert_eq!(result.value(), Some(Value::I32(42)));
+
+        let result = instance
+            .execute("div", &[Value::I32(-42), Value::I32(2)])
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(2147483627)));
+
+        let result = instance.execute("f64", &[Value::F64(1.5)]).unwrap();
+        assert_eq!(result.value(), Some(Value::F64(1.5)));
+
+        let result = instance.execute("fail", &[]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(result.value(), None);
+
+        assert_eq!(
+            instance.execute("bar", &[]).err(),
+            Some(Error::FunctionNotFound)
+        );
+        assert_eq!(
+            instance.execute("div", &[Value::I32(1)]).err(),
+            Some(Error::ArgumentCountMismatch)
+        );
+        assert_eq!(
+            instance
+                .execute("div", &[Value::I32(1), Value::I64(1)])
+                .err(),
+            Some(Error::ArgumentTypeMismatch)
+        );
+    }
+
+    #[test]
+    fn execute_with_args_macro() {
+        /* wat2wasm
+          (func (export "div") (param i32 i32) (result i32)
+            (i32.div_u (local.get 0) (local.get 1))
+          )
+          (func (export "f64") (param f64) (result f64) local.get 0)
+        */
+        let input = from_hex(&[
+            "0061736d01000000010c0260027f7f017f60017c017c0303020001070d0203646976000003663634",
+            "00010a0e020700200020016e0b040020000b",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+
+        let result = instance.execute("div", &args![-42i32, 2i32]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(2147483627)));
+        let result = instance.execute("div", &args![u32::MAX, 2u32]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(i32::MAX)));
+        let result = instance.execute("f64", &args![1.5f64]).unwrap();
+        assert_eq!(result.value(), Some(Value::F64(1.5)));
+        assert_eq!(
+            instance.execute("div", &args![1i32, 1i64]).err(),
+            Some(Error::ArgumentTypeMismatch)
+        );
+    }
+
+    #[test]
+    fn call_export() {
+        /* wat2wasm
+          (func (export "foo") (result i32) i32.const 42)
+          (func (export "div") (param i32 i32) (result i32)
+            (i32.div_u (local.get 0) (local.get 1))
+          )
+          (func (export "fail") unreachable)
+          (func (export "f64") (param f64) (result f64) local.get 0)
+        */
+        let input = from_hex(&[
+            "0061736d010000000113046000017f60027f7f017f60000060017c017c03050400010203071a0403",
+            "666f6f0000036469760001046661696c00020366363400030a17040400412a0b0700200020016e0b",
+            "0300000b040020000b",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+
+        let result = instance.call_export("foo", Vec::<Value>::new()).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(42)));
+
+        let result = instance.call_export("div", vec![42u32, 2]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(21)));
+
+        let args = vec![Value::I32(9), Value::I32(3)];
+        let result = instance.call_export("div", &args).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(3)));
+
+        let result = instance
+            .call_export("div", (1..=2).map(|x: i32| x * 10))
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0)));
+
+        let result = instance.call_export("f64", Some(1.5f64)).unwrap();
+        assert_eq!(result.value(), Some(Value::F64(1.5)));
+
+        assert_eq!(
+            instance.call_export("div", vec![1i64, 1]).err(),
+            Some(Error::ArgumentTypeMismatch)
+        );
+    }
+
+    #[test]
+    fn trap_stack_trace() {
+        /* wat2wasm --debug-names
+          (func $inner unreachable)
+          (func $outer (export "outer") call $inner)
+          (func (export "fail") unreachable)
+          (func (export "ok"))
+        */
+        let input = from_hex(&[
+            "0061736d0100000001040160000003050400000000071503056f757465720001046661696c000202",
+            "6f6b00030a11040300000b040010000b0300000b02000b0016046e616d65010f020005696e6e6572",
+            "01056f75746572",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+
+        let result = instance.execute("outer", &[]).unwrap();
+        assert!(result.trapped());
+        let trace: Vec<_> = result
+            .stack_trace()
+            .iter()
+            .map(|frame| (frame.function_index(), frame.function_name()))
+            .collect();
+        assert_eq!(trace, [(0, Some("inner")), (1, Some("outer"))]);
+
+        let result = instance.execute("fail", &[]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(result.stack_trace().len(), 1);
+        assert_eq!(result.stack_trace()[0].function_index(), 2);
+        assert_eq!(result.stack_trace()[0].function_name(), None);
+
+        let result = instance.execute("ok", &[]).unwrap();
+        assert!(!result.trapped());
+        assert!(result.stack_trace().is_empty());
+    }
+
+    #[test]
+    fn execution_result_into_result() {
+        /* wat2wasm --debug-names
+          (func $inner unreachable)
+          (func $outer (export "outer") call $inner)
+          (func (export "fail") unreachable)
+          (func (export "ok"))
+        */
+        let input = from_hex(&[
+            "0061736d0100000001040160000003050400000000071503056f757465720001046661696c000202",
+            "6f6b00030a11040300000b040010000b0300000b02000b0016046e616d65010f020005696e6e6572",
+            "01056f75746572",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+
+        let result = instance.execute("ok", &[]).unwrap();
+        assert_eq!(
+            format!("{:?}", result),
+            "ExecutionResult { trapped: false, value: None, stack_trace: [], trap_reason: None, \
+             host_call: None, trap_cause: None, trap_origin: None }"
+        );
+        assert_eq!(result.into_result(), Ok(None));
+
+        let trap = instance
+            .execute("outer", &[])
+            .unwrap()
+            .into_result()
+            .unwrap_err();
+        assert_eq!(trap.stack_trace().len(), 2);
+        assert_eq!(trap.origin(), TrapOrigin::Wasm);
+        assert_eq!(trap.to_string(), "execution trapped in function inner");
+
+        let result: Result<Option<Value>, Trap> = instance.execute("fail", &[]).unwrap().into();
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "execution trapped in function #2"
+        );
+
+        fn call(instance: &mut Instance, name: &str) -> Result<Option<Value>, Error> {
+            Ok(instance.execute(name, &[])?.into_result()?)
+        }
+        assert_eq!(call(&mut instance, "ok"), Ok(None));
+        let error = call(&mut instance, "fail").unwrap_err();
+        assert_eq!(error.to_string(), "execution trapped in function #2");
+        match error {
+            Error::Trapped(trap) => assert_eq!(trap.stack_trace().len(), 1),
+            _ => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn memory_access() {
+        /* wat2wasm
+          (memory 1)
+          (data (i32.const 1) "\11\22")
+          (func (result i32)
+            i32.const 0
+            i32.load
+          )
+          (export "foo" (func 0))
+        */
+        let input = from_hex(&[
+            "0061736d010000000105016000017f03020100050301000107070103666f6f00000a090107004100",
+            "2802000b0b08010041010b021122",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        assert_eq!(instance.memory_size(), 65536);
+
+        let mut dst = [0u8; 3];
+        instance.memory_get(0, &mut dst).unwrap();
+        assert_eq!(dst, [0x00, 0x11, 0x22]);
+
+        instance.memory_set(0, &[0xaa, 0xbb]).unwrap();
+        let result = instance.execute("foo", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0x22bbaa)));
+
+        assert_eq!(
+            instance.memory_get(65535, &mut dst).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert_eq!(
+            instance.memory_set(u32::MAX, &[0]).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert!(instance.memory_set(65536, &[]).is_ok());
+    }
+
+    #[test]
+    fn multiple_memories() {
+        /* wat2wasm --enable-multi-memory
+          (memory 1)
+          (memory 1 2)
+          (data (memory 1) (i32.const 1) "\11\22")
+          (func (export "load") (param i32) (result i32) (i32.load8_u 1 (local.get 0)))
+          (func (export "grow") (param i32) (result i32) (memory.grow 1 (local.get 0)))
+          (export "mem1" (memory 1))
+        */
+        let module = parse_multi_memory(from_hex(&[
+            "0061736d0100000001060160017f017f03030200000506020001010102071603046c6f6164000004",
+            "67726f770001046d656d3102010a1102080020002d4001000b0600200040010b0b0901020141010b",
+            "021122",
+        ]))
+        .unwrap();
+        assert_eq!(module.memory_count(), 2);
+        assert_eq!(
+            module.memory_type(1),
+            Some(linker::Limits {
+                min: 1,
+                max: Some(2)
+            })
+        );
+        assert_eq!(module.memory_type(2), None);
+
+        let mut instance = module.instantiate().unwrap();
+        assert_eq!(instance.memory_count(), 2);
+        assert_eq!(instance.memory_size_at(1), 65536);
+        assert_eq!(instance.memory_size_at(2), 0);
+        let mut dst = [0u8; 3];
+        instance.memory_get_at(1, 0, &mut dst).unwrap();
+        assert_eq!(dst, [0x00, 0x11, 0x22]);
+        instance.memory_get(0, &mut dst).unwrap();
+        assert_eq!(dst, [0; 3]);
+
+        instance.memory_set_at(1, 0, &[0x33]).unwrap();
+        let result = instance.execute("load", &[Value::I32(0)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0x33)));
+        assert_eq!(
+            instance.memory_get_at(2, 0, &mut dst).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        assert_eq!(instance.memory_size_at(1), 2 * 65536);
+        assert_eq!(instance.memory_size(), 65536);
+        let copy = instance.duplicate().unwrap();
+        copy.memory_get_at(1, 0, &mut dst).unwrap();
+        assert_eq!(dst, [0x33, 0x11, 0x22]);
+
+        /* wat2wasm --enable-multi-memory
+          (memory (import "env" "a") 1)
+          (memory (import "env" "b") 1)
+          (func (export "load") (param i32) (result i32) (i32.load8_u 1 (local.get 0)))
+        */
+        let a = memory::Memory::new(linker::Limits { min: 1, max: None }).unwrap();
+        let b = memory::Memory::new(linker::Limits { min: 1, max: None }).unwrap();
+        b.set(0, &[0x44]).unwrap();
+        let mut imports = host::Imports::new();
+        imports.define_memory("env", "a", &a);
+        imports.define_memory("env", "b", &b);
+        let mut instance = parse_multi_memory(from_hex(&[
+            "0061736d0100000001060160017f017f02130203656e76016102000103656e760162020001030201",
+            "00070801046c6f616400000a0a01080020002d4001000b",
+        ]))
+        .unwrap()
+        .instantiate_with_imports(imports)
+        .unwrap();
+        let result = instance.execute("load", &[Value::I32(0)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0x44)));
+    }
+
+    #[test]
+    fn multiple_memories_host_access() {
+        /* wat2wasm --enable-multi-memory
+          (memory 1)
+          (memory 1 3)
+          (func (export "store") (param i32 i32) (i32.store8 1 (local.get 0) (local.get 1)))
+          (func (export "grow") (param i32) (result i32) (memory.grow 1 (local.get 0)))
+        */
+        let mut instance = parse_multi_memory(from_hex(&[
+            "0061736d01000000010b0260027f7f0060017f017f03030200010506020001010103071002057374",
+            "6f726500000467726f7700010a13020a00200020013a4001000b0600200040010b",
+        ]))
+        .unwrap()
+        .instantiate()
+        .unwrap();
+
+        // The growth of the memory 1 is reported with its index and limited.
+        let growths = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = growths.clone();
+        instance.on_memory_grow(move |memory_idx, old_pages, new_pages| {
+            log.lock().unwrap().push((memory_idx, old_pages, new_pages))
+        });
+        instance.set_resource_limiter(limiter::PagesBudget::new(1));
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(-1)));
+        assert_eq!(*growths.lock().unwrap(), [(1, 1, 2)]);
+
+        instance.set_memory_read_only_at(1, 0, 4).unwrap();
+        assert_eq!(
+            instance.set_memory_read_only_at(2, 0, 4).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+        let store = [Value::I32(2), Value::I32(5)];
+        assert!(instance.execute("store", &store).unwrap().trapped());
+        instance.clear_memory_read_only();
+        assert!(instance.execute("store", &store).unwrap().trapped());
+        instance.clear_memory_read_only_at(1);
+        assert!(!instance.execute("store", &store).unwrap().trapped());
+
+        instance.start_recording();
+        let bufs = [IoSlice::new(&[1, 2]), IoSlice::new(&[3])];
+        assert_eq!(instance.memory_set_vectored_at(1, 8, &bufs), Ok(3));
+        let (mut first, mut second) = ([0u8; 1], [0u8; 4]);
+        let mut bufs = [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)];
+        assert_eq!(instance.memory_get_vectored_at(1, 1, &mut bufs), Ok(5));
+        assert_eq!((first, second), ([0], [5, 0, 0, 0]));
+        let mut dst = [0u8; 3];
+        instance.memory_get(8, &mut dst).unwrap();
+        assert_eq!(dst, [0; 3]);
+        let recording = instance.stop_recording().unwrap();
+        assert_eq!(
+            recording.events(),
+            [
+                record::Event::MemoryWrite {
+                    memory_idx: 1,
+                    offset: 8,
+                    data: vec![1, 2]
+                },
+                record::Event::MemoryWrite {
+                    memory_idx: 1,
+                    offset: 10,
+                    data: vec![3]
+                },
+            ]
+        );
+        let mut json = Vec::new();
+        recording.write_json_lines(&mut json).unwrap();
+        assert!(String::from_utf8(json)
+            .unwrap()
+            .starts_with("{\"event\":\"memory_write\",\"memory\":1,\"offset\":8,"));
+
+        let mut copy = instance.duplicate().unwrap();
+        copy.memory_set_at(1, 8, &[0; 3]).unwrap();
+        copy_memory_at(&instance, 1, 8..11, &mut copy, 0, 4).unwrap();
+        copy.memory_get(4, &mut dst).unwrap();
+        assert_eq!(dst, [1, 2, 3]);
+        copy_memory_at(&instance, 1, 8..11, &mut copy, 1, 16).unwrap();
+        copy.memory_get_at(1, 8, &mut dst).unwrap();
+        assert_eq!(dst, [0; 3]);
+        copy.memory_get_at(1, 16, &mut dst).unwrap();
+        assert_eq!(dst, [1, 2, 3]);
+        assert_eq!(
+            copy_memory_at(&instance, 1, 8..11, &mut copy, 2, 0).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+    }
+
+    #[test]
+    fn copy_memory() {
+        /* wat2wasm
+          (memory 1)
+          (data (i32.const 1) "\11\22")
+          (func (result i32)
+            i32.const 0
+            i32.load
+          )
+          (export "foo" (func 0))
+        */
+        let input = from_hex(&[
+            "0061736d010000000105016000017f03020100050301000107070103666f6f00000a090107004100",
+            "2802000b0b08010041010b021122",
+        ]);
+        let src = parse(&input).unwrap().instantiate().unwrap();
+        let mut dst = parse(&input).unwrap().instantiate().unwrap();
+        dst.memory_set(0, &[0xff; 4]).unwrap();
+
+        super::copy_memory(&src, 1..3, &mut dst, 65534).unwrap();
+        super::copy_memory(&src, 0..2, &mut dst, 2).unwrap();
+        let mut data = [0u8; 4];
+        dst.memory_get(0, &mut data).unwrap();
+        assert_eq!(data, [0xff, 0xff, 0x00, 0x11]);
+        dst.memory_get(65532, &mut data).unwrap();
+        assert_eq!(data, [0x00, 0x00, 0x11, 0x22]);
+
+        assert_eq!(
+            super::copy_memory(&src, 1..3, &mut dst, 65535),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert_eq!(
+            super::copy_memory(&src, 65535..65537, &mut dst, 0),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 3..1;
+        assert_eq!(
+            super::copy_memory(&src, reversed, &mut dst, 0),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert_eq!(
+            super::copy_memory(&src, 65536..65536, &mut dst, 65536),
+            Ok(())
+        );
+        dst.memory_get(0, &mut data).unwrap();
+        assert_eq!(data, [0xff, 0xff, 0x00, 0x11]);
+    }
+
+    #[test]
+    fn duplicate() {
+        /* wat2wasm
+          (table 1 anyfunc)
+          (memory 1)
+          (global $g (mut i32) (i32.const 0))
+          (func $inc (export "inc") (result i32)
+            (global.set $g (i32.add (global.get $g) (i32.const 1)))
+            (i32.store (i32.const 0) (global.get $g))
+            (global.get $g))
+          (func (export "call") (result i32) (call_indirect (result i32) (i32.const 0)))
+          (elem (i32.const 0) $inc)
+        */
+        let input = from_hex(&[
+            "0061736d010000000105016000017f030302000004040170000105030100010606017f0141000b07",
+            "0e0203696e6300000463616c6c00010907010041000b01000a1c021200230041016a240041002300",
+            "36020023000b070041001100000b",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        assert_eq!(
+            instance.execute("inc", &[]).unwrap().value(),
+            Some(Value::I32(1))
+        );
+        instance.snapshot_memory();
+
+        let mut copy = instance.duplicate().unwrap();
+        assert_eq!(copy.state_hash(), instance.state_hash());
+        assert_eq!(copy.memory_snapshot(), instance.memory_snapshot());
+        assert_eq!(
+            instance.execute("inc", &[]).unwrap().value(),
+            Some(Value::I32(2))
+        );
+        assert_eq!(
+            instance.execute("inc", &[]).unwrap().value(),
+            Some(Value::I32(3))
+        );
+        assert_eq!(
+            copy.execute("call", &[]).unwrap().value(),
+            Some(Value::I32(2))
+        );
+        let mut data = [0u8; 1];
+        copy.memory_get(0, &mut data).unwrap();
+        assert_eq!(data, [2]);
+        instance.memory_get(0, &mut data).unwrap();
+        assert_eq!(data, [3]);
+
+        // The table of the copy refers to the functions of the copy.
+        drop(instance);
+        assert_eq!(
+            copy.execute("call", &[]).unwrap().value(),
+            Some(Value::I32(3))
+        );
+        assert_eq!(copy.memory_changes().unwrap().pages(), [0]);
+
+        /* wat2wasm
+          (memory (import "env" "mem") 1)
+        */
+        let memory = memory::Memory::new(linker::Limits { min: 1, max: None }).unwrap();
+        let mut imports = host::Imports::new();
+        imports.define_memory("env", "mem", &memory);
+        let instance = parse(from_hex(&["0061736d01000000020c0103656e76036d656d020001"]))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+        assert_eq!(
+            instance.duplicate().err(),
+            Some(Error::NotDuplicable(NotDuplicable::Imports))
+        );
+
+        let options = InstantiateOptions {
+            memory_allocator: Some(Arc::new(memory::Arena::new(memory::PAGE_SIZE).unwrap())),
+            ..Default::default()
+        };
+        let instance = parse(&input)
+            .unwrap()
+            .instantiate_with_options(host::Imports::new(), &options)
+            .unwrap();
+        let error = instance.duplicate().err().unwrap();
+        assert_eq!(error, Error::NotDuplicable(NotDuplicable::MemoryAllocator));
+        assert_eq!(
+            error.to_string(),
+            "instance cannot be duplicated: instance has custom memory allocator"
+        );
+    }
+
+    #[test]
+    fn memory_grow_callback() {
+        /* wat2wasm
+          (memory 1 3)
+          (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+        */
+        let input = from_hex(&[
+            "0061736d0100000001060160017f017f030201000504010101030708010467726f7700000a080106",
+            "00200040000b",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+
+        let growths = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = growths.clone();
+        instance.on_memory_grow(move |memory_idx, old_pages, new_pages| {
+            assert_eq!(memory_idx, 0);
+            log.lock().unwrap().push((old_pages, new_pages))
+        });
+        for &(delta, result) in &[(1, 1), (0, 2), (2, -1), (1, 2)] {
+            let value = instance
+                .execute("grow", &[Value::I32(delta)])
+                .unwrap()
+                .value();
+            assert_eq!(value, Some(Value::I32(result)));
+        }
+        assert_eq!(*growths.lock().unwrap(), [(1, 2), (2, 3)]);
+
+        // A panic of the callback does not abort the execution.
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        instance.on_memory_grow(|_, _, _| panic!("growth"));
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        assert_eq!(instance.memory_size(), 2 * 65536);
+
+        instance
+            .on_memory_grow(move |_, _, new_pages| growths.lock().unwrap().push((0, new_pages)));
+        instance.remove_memory_grow_callback();
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(2)));
+    }
+
+    #[test]
+    fn progress_callback() {
+        /* wat2wasm
+          (func (export "loop") (param i32)
+            (loop (br_if 0 (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+          )
+        */
+        let input = from_hex(&[
+            "0061736d0100000001050160017f0003020100070801046c6f6f7000000a10010e00034020004101",
+            "6b22000d000b0b",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+
+        let progress = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = progress.clone();
+        instance.on_progress(100, move |executed| {
+            log.lock().unwrap().push(executed);
+            executed < 10000
+        });
+        // Each iteration executes 6 instructions, including the `loop` the branch jumps to.
+        let result = instance.execute("loop", &[Value::I32(100)]).unwrap();
+        assert!(!result.trapped());
+        assert_eq!(*progress.lock().unwrap(), [100, 200, 300, 400, 500, 600]);
+
+        let result = instance.execute("loop", &[Value::I32(1000000)]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(result.trap_origin(), Some(TrapOrigin::Aborted));
+        assert_eq!(progress.lock().unwrap().last(), Some(&10000));
+
+        instance.remove_progress_callback();
+        let result = instance.execute("loop", &[Value::I32(1000000)]).unwrap();
+        assert!(!result.trapped());
+        assert_eq!(progress.lock().unwrap().len(), 100);
+    }
+
+    #[test]
+    fn memory_access_vectored() {
+        /* wat2wasm
+          (memory 1)
+          (data (i32.const 1) "\11\22")
+          (func (result i32)
+            i32.const 0
+            i32.load
+          )
+          (export "foo" (func 0))
+        */
+        let input = from_hex(&[
+            "0061736d010000000105016000017f03020100050301000107070103666f6f00000a090107004100",
+            "2802000b0b08010041010b021122",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+
+        let (mut a, mut b) = ([0u8; 1], [0u8; 2]);
+        let size = instance
+            .memory_get_vectored(
+                0,
+                &mut [
+                    IoSliceMut::new(&mut a),
+                    IoSliceMut::new(&mut []),
+                    IoSliceMut::new(&mut b),
+                ],
+            )
+            .unwrap();
+        assert_eq!(size, 3);
+        assert_eq!((a, b), ([0x00], [0x11, 0x22]));
+
+        let size = instance
+            .memory_set_vectored(0, &[IoSlice::new(&[0xaa]), IoSlice::new(&[0xbb, 0xcc])])
+            .unwrap();
+        assert_eq!(size, 3);
+        let result = instance.execute("foo", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0xccbbaa)));
+
+        // Nothing is copied if the buffers do not fit.
+        assert_eq!(
+            instance
+                .memory_set_vectored(65534, &[IoSlice::new(&[1, 2]), IoSlice::new(&[3])])
+                .err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+        let mut dst = [0xffu8; 2];
+        instance.memory_get(65534, &mut dst).unwrap();
+        assert_eq!(dst, [0, 0]);
+        assert_eq!(
+            instance
+                .memory_get_vectored(65535, &mut [IoSliceMut::new(&mut dst)])
+                .err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert_eq!(
+            instance.memory_set_vectored(65536, &[IoSlice::new(&[])]),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn memory_backing_mapped() {
+        /* wat2wasm
+          (memory 1 2)
+          (data (i32.const 1) "\11\22")
+          (func (param i32) (result i32)
+            local.get 0
+            memory.grow
+          )
+          (export "grow" (func 0))
+        */
+        let input = from_hex(&[
+            "0061736d0100000001060160017f017f030201000504010101020708010467726f7700000a080106",
+            "00200040000b0b08010041010b021122",
+        ]);
+        let options = InstantiateOptions {
+            memory_backing: MemoryBacking::Mapped,
+            ..Default::default()
+        };
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_options(host::Imports::new(), &options)
+            .unwrap();
+        assert_eq!(instance.memory_size(), 65536);
+
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(-1)));
+        assert_eq!(instance.memory_size(), 2 * 65536);
+
+        let mut dst = [0u8; 3];
+        instance.memory_get(0, &mut dst).unwrap();
+        assert_eq!(dst, [0x00, 0x11, 0x22]);
+        instance.memory_get(65536, &mut dst).unwrap();
+        assert_eq!(dst, [0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn defer_start() {
+        /* wat2wasm
+          (global $g (mut i32) (i32.const 0))
+          (func $start (global.set $g (i32.add (global.get $g) (i32.const 1))))
+          (func (export "get") (result i32) (global.get $g))
+          (start $start)
+        */
+        let input = from_hex(&[
+            "0061736d010000000108026000006000017f03030200010606017f0141000b070701036765740001",
+            "0801000a10020900230041016a24000b040023000b",
+        ]);
+        let options = InstantiateOptions {
+            defer_start: true,
+            ..Default::default()
+        };
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_options(host::Imports::new(), &options)
+            .unwrap();
+        let get = |instance: &mut Instance| instance.execute("get", &[]).unwrap().value();
+        assert_eq!(get(&mut instance), Some(Value::I32(0)));
+        assert!(instance.run_start().is_ok());
+        assert_eq!(get(&mut instance), Some(Value::I32(1)));
+        // The start function is run only once.
+        assert!(instance.run_start().is_ok());
+        assert_eq!(get(&mut instance), Some(Value::I32(1)));
+
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        assert_eq!(get(&mut instance), Some(Value::I32(1)));
+        assert!(instance.run_start().is_ok());
+        assert_eq!(get(&mut instance), Some(Value::I32(1)));
+    }
+
+    #[test]
+    fn start_trapped() {
+        /* wat2wasm
+          (func $start unreachable)
+          (start $start)
+        */
+        let input = from_hex(&["0061736d01000000010401600000030201000801000a05010300000b"]);
+        assert_eq!(
+            parse(&input).unwrap().instantiate().err(),
+            Some(Error::StartFunctionTrapped)
+        );
+
+        let options = InstantiateOptions {
+            defer_start: true,
+            ..Default::default()
+        };
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_options(host::Imports::new(), &options)
+            .unwrap();
+        assert!(instance.run_start().is_err());
+    }
+
+    #[test]
+    fn memory_backing_guarded() {
+        /* wat2wasm
+          (memory 1 2)
+          (func (export "load") (param i32) (result i32)
+            local.get 0
+            i32.load
+          )
+          (func (export "grow") (param i32) (result i32)
+            local.get 0
+            memory.grow
+          )
+        */
+        let input = from_hex(&[
+            "0061736d0100000001060160017f017f0303020000050401010102070f02046c6f61640000046772",
+            "6f7700010a1002070020002802000b0600200040000b",
+        ]);
+        let options = InstantiateOptions {
+            memory_backing: MemoryBacking::Guarded,
+            ..Default::default()
+        };
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_options(host::Imports::new(), &options)
+            .unwrap();
+        instance
+            .memory_set(65532, &[0x11, 0x22, 0x33, 0x44])
+            .unwrap();
+
+        let load = |instance: &mut Instance, address: i32| {
+            instance
+                .execute("load", &[Value::I32(address)])
+                .unwrap()
+                .value()
+        };
+        assert_eq!(load(&mut instance, 65532), Some(Value::I32(0x44332211)));
+        assert_eq!(load(&mut instance, 65533), None);
+        assert_eq!(load(&mut instance, -1), None);
+
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        assert_eq!(load(&mut instance, 65533), Some(Value::I32(0x443322)));
+        assert_eq!(load(&mut instance, 2 * 65536 - 3), None);
+    }
+
+    #[test]
+    fn memory_read_only() {
+        /* wat2wasm
+          (memory 1)
+          (func (export "store") (param i32 i32)
+            local.get 0
+            local.get 1
+            i32.store16 offset=2
+          )
+          (func (export "load") (param i32) (result i32)
+            local.get 0
+            i32.load
+          )
+        */
+        let input = from_hex(&[
+            "0061736d01000000010b0260027f7f0060017f017f030302000105030100010710020573746f7265",
+            "0000046c6f616400010a13020900200020013b01020b070020002802000b",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        assert_eq!(
+            instance.set_memory_read_only(65535, 2).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+        instance.set_memory_read_only(8, 4).unwrap();
+
+        let result = instance.execute("store", &args![6i32, 0x1122i32]).unwrap();
+        assert!(result.trapped());
+        let result = instance.execute("store", &args![4i32, 0x1122i32]).unwrap();
+        assert!(!result.trapped());
+        instance.memory_set(8, &[0x33]).unwrap();
+        let result = instance.execute("load", &args![8i32]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0x33)));
+
+        instance.clear_memory_read_only();
+        let result = instance.execute("store", &args![6i32, 0x1122i32]).unwrap();
+        assert!(!result.trapped());
+        let result = instance.execute("load", &args![8i32]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0x1122)));
+    }
+
+    #[test]
+    fn state_hash() {
+        /* wat2wasm
+          (memory 1)
+          (func (export "store") (param i32 i32)
+            local.get 0
+            local.get 1
+            i32.store16 offset=2
+          )
+          (func (export "load") (param i32) (result i32)
+            local.get 0
+            i32.load
+          )
+        */
+        let input = from_hex(&[
+            "0061736d01000000010b0260027f7f0060017f017f030302000105030100010710020573746f7265",
+            "0000046c6f616400010a13020900200020013b01020b070020002802000b",
+        ]);
+        let mut instance1 = parse(&input).unwrap().instantiate().unwrap();
+        let mut instance2 = parse(&input).unwrap().instantiate().unwrap();
+        let hash = instance1.state_hash();
+        assert_eq!(instance2.state_hash(), hash);
+
+        instance1.execute("store", &args![6i32, 0x1122i32]).unwrap();
+        assert_ne!(instance1.state_hash(), hash);
+        instance2.execute("store", &args![6i32, 0x1122i32]).unwrap();
+        assert_eq!(instance1.state_hash(), instance2.state_hash());
+
+        // Executions not changing the state keep the hash.
+        let hash = instance1.state_hash();
+        instance1.execute("load", &args![8i32]).unwrap();
+        assert_eq!(instance1.state_hash(), hash);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_spans() {
+        use std::sync::Mutex;
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        /// Records the names of the created spans and the messages of the events.
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl tracing::Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut log = self.0.lock().unwrap();
+                log.push(span.metadata().name().to_string());
+                Id::from_u64(log.len() as u64)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                struct Message<'a>(&'a mut Vec<String>);
+                impl tracing::field::Visit for Message<'_> {
+                    fn record_debug(
+                        &mut self,
+                        field: &tracing::field::Field,
+                        value: &dyn std::fmt::Debug,
+                    ) {
+                        if field.name() == "message" {
+                            self.0.push(format!("{:?}", value));
+                        }
+                    }
+                }
+                event.record(&mut Message(&mut self.0.lock().unwrap()));
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let recorder = std::sync::Arc::new(Recorder::default());
+        tracing::subscriber::with_default(recorder.clone(), || {
+            /* wat2wasm
+              (func (export "foo") (result i32) (i32.const 42))
+            */
+            let input = from_hex(&[
+                "0061736d010000000105016000017f0302010007070103666f6f00000a06010400412a0b",
+            ]);
+            let mut instance = parse(&input).unwrap().instantiate().unwrap();
+            instance.execute("foo", &[]).unwrap();
+        });
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "parse",
+                "parsed",
+                "instantiate",
+                "instantiated",
+                "execute",
+                "executed"
+            ]
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics() {
+        use ::metrics::{Counter, Gauge, Histogram, Key, KeyName, Metadata, SharedString, Unit};
+        use std::collections::HashMap;
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Mutex;
+
+        /// Records the values of the counters and the number of the histogram samples.
+        #[derive(Default)]
+        struct Recorder(Mutex<HashMap<String, Arc<AtomicU64>>>);
+
+        struct SampleCount(Arc<AtomicU64>);
+
+        impl ::metrics::HistogramFn for SampleCount {
+            fn record(&self, _: f64) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        impl Recorder {
+            fn get(&self, name: &str) -> u64 {
+                self.0.lock().unwrap()[name].load(Ordering::Relaxed)
+            }
+
+            fn register(&self, key: &Key) -> Arc<AtomicU64> {
+                let mut values = self.0.lock().unwrap();
+                values.entry(key.name().to_string()).or_default().clone()
+            }
+        }
+
+        impl ::metrics::Recorder for Recorder {
+            fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+                Counter::from_arc(self.register(key))
+            }
+            fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::noop()
+            }
+            fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+                Histogram::from_arc(Arc::new(SampleCount(self.register(key))))
+            }
+        }
+
+        /* wat2wasm
+          (func (export "foo") (result i32) (i32.const 42))
+          (func (export "fail") (unreachable))
+        */
+        let input = from_hex(&[
+            "0061736d010000000108026000017f6000000303020001070e0203666f6f0000046661696c0001",
+            "0a0a020400412a0b0300000b",
+        ]);
+        let recorder = Recorder::default();
+        ::metrics::with_local_recorder(&recorder, || {
+            let mut instance = parse(&input).unwrap().instantiate().unwrap();
+            instance.execute("foo", &[]).unwrap();
+            assert!(instance.execute("fail", &[]).unwrap().trapped());
+            let metered = instance
+                .execute_metered("foo", &[], 100, &gas::CostTable::uniform(1))
+                .unwrap();
+            assert_eq!(metered.fuel_consumed, 2);
+        });
+        assert_eq!(recorder.get("fizzy_executions_total"), 3);
+        assert_eq!(recorder.get("fizzy_traps_total"), 1);
+        assert_eq!(recorder.get("fizzy_execution_duration_seconds"), 3);
+        assert_eq!(recorder.get("fizzy_fuel_consumed_total"), 2);
+    }
 }
@@ -0,0 +1,171 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Dynamic limits of the growth of instance resources.
+//!
+//! The static limits of a memory, its maximum and the hard limit of the interpreter, are checked
+//! first. The growths within them are then approved or denied by the [`ResourceLimiter`] set
+//! with [`Instance::set_resource_limiter()`], e.g. based on a budget shared by all instances of
+//! the host, see [`PagesBudget`].
+
+use crate::{sys, Instance};
+use std::sync::{Arc, Mutex};
+
+/// A limiter approving or denying the growth of the resources of an instance.
+pub trait ResourceLimiter: Send {
+    /// Returns whether a memory can grow from `current` to `desired` pages, within
+    /// the `maximum` pages allowed by the memory limits.
+    ///
+    /// This is consulted for every memory of the instance. A denied growth makes `memory.grow`
+    /// return -1.
+    fn memory_growing(&mut self, current: u32, desired: u32, maximum: u32) -> bool;
+
+    /// Returns whether a table can grow from `current` to `desired` elements, within
+    /// the `maximum` allowed by the table limits, if any.
+    ///
+    /// WebAssembly 1.0 has no instruction growing tables, so this is consulted only for
+    /// the growth of tables by the host. All growths are allowed by default.
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> bool {
+        let _ = (current, desired, maximum);
+        true
+    }
+}
+
+/// A number of memory pages the instances sharing the budget can grow their memories by.
+///
+/// The pages are not returned to the budget when the instances are dropped.
+#[derive(Clone, Debug)]
+pub struct PagesBudget(Arc<Mutex<u64>>);
+
+impl PagesBudget {
+    /// Create a budget of the given number of pages.
+    pub fn new(pages: u64) -> Self {
+        PagesBudget(Arc::new(Mutex::new(pages)))
+    }
+
+    /// The number of pages left.
+    pub fn remaining(&self) -> u64 {
+        *self.lock()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, u64> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ResourceLimiter for PagesBudget {
+    fn memory_growing(&mut self, current: u32, desired: u32, _maximum: u32) -> bool {
+        let mut remaining = self.lock();
+        let pages = u64::from(desired - current);
+        if pages > *remaining {
+            return false;
+        }
+        *remaining -= pages;
+        true
+    }
+}
+
+unsafe extern "C" fn memory_grow_limiter_trampoline(
+    context: *mut std::ffi::c_void,
+    _instance: *mut sys::FizzyInstance,
+    _memory_idx: u32,
+    current_pages: u32,
+    desired_pages: u32,
+    max_pages: u32,
+) -> bool {
+    let limiter = &mut *(context as *mut Box<dyn ResourceLimiter>);
+    // Unwinding across the C++ interpreter is not allowed: a panicking limiter denies the growth.
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        limiter.memory_growing(current_pages, desired_pages, max_pages)
+    }))
+    .unwrap_or(false)
+}
+
+impl Instance {
+    /// Set the limiter consulted on the growth of the resources of the instance, replacing any
+    /// previous one.
+    pub fn set_resource_limiter<L: ResourceLimiter + 'static>(&mut self, limiter: L) {
+        let mut limiter: Box<Box<dyn ResourceLimiter>> = Box::new(Box::new(limiter));
+        unsafe {
+            sys::fizzy_set_memory_grow_limiter(
+                self.0.as_ptr(),
+                Some(memory_grow_limiter_trampoline),
+                &mut *limiter as *mut Box<dyn ResourceLimiter> as *mut std::ffi::c_void,
+            )
+        };
+        self.1.resource_limiter = Some(limiter);
+    }
+
+    /// Remove the limiter set with [`Instance::set_resource_limiter()`].
+    pub fn remove_resource_limiter(&mut self) {
+        unsafe { sys::fizzy_set_memory_grow_limiter(self.0.as_ptr(), None, std::ptr::null_mut()) };
+        self.1.resource_limiter = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::from_hex;
+    use crate::{parse, Value};
+
+    /* wat2wasm
+      (memory 1)
+      (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001060160017f017f0302010005030100010708010467726f7700000a08010600",
+        "200040000b",
+    ];
+
+    fn grow(instance: &mut Instance, delta: i32) -> Option<Value> {
+        instance
+            .execute("grow", &[Value::I32(delta)])
+            .unwrap()
+            .value()
+    }
+
+    struct Recorder(Arc<Mutex<Vec<(u32, u32, u32)>>>);
+
+    impl ResourceLimiter for Recorder {
+        fn memory_growing(&mut self, current: u32, desired: u32, maximum: u32) -> bool {
+            self.0.lock().unwrap().push((current, desired, maximum));
+            desired <= 4
+        }
+    }
+
+    #[test]
+    fn resource_limiter() {
+        let mut instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        instance.set_resource_limiter(Recorder(requests.clone()));
+        assert_eq!(grow(&mut instance, 4), Some(Value::I32(-1)));
+        assert_eq!(grow(&mut instance, 3), Some(Value::I32(1)));
+        assert_eq!(grow(&mut instance, 0), Some(Value::I32(4)));
+        // The hard limit of the interpreter is checked first.
+        assert_eq!(grow(&mut instance, 5000), Some(Value::I32(-1)));
+        assert_eq!(*requests.lock().unwrap(), [(1, 5, 4096), (1, 4, 4096)]);
+
+        instance.remove_resource_limiter();
+        assert_eq!(grow(&mut instance, 1), Some(Value::I32(4)));
+    }
+
+    #[test]
+    fn pages_budget() {
+        let budget = PagesBudget::new(3);
+        let mut first = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        let mut second = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        first.set_resource_limiter(budget.clone());
+        second.set_resource_limiter(budget.clone());
+
+        assert_eq!(grow(&mut first, 2), Some(Value::I32(1)));
+        assert_eq!(grow(&mut second, 2), Some(Value::I32(-1)));
+        assert_eq!(grow(&mut second, 1), Some(Value::I32(1)));
+        assert_eq!(budget.remaining(), 0);
+        assert_eq!(grow(&mut first, 1), Some(Value::I32(-1)));
+        assert_eq!(first.memory_size(), 3 * 65536);
+    }
+}
@@ -0,0 +1,753 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checking module imports against the items available for linking.
+//!
+//! A [`Linker`] collects the types of the items (functions, tables, memories and globals)
+//! available under `(module, name)` pairs. [`Linker::check()`] verifies that every import of
+//! a module can be satisfied by them, without instantiating the module. Similarly,
+//! [`Module::satisfies()`] checks the imports of a module against the exports of another.
+//!
+//! The items are described by [`ExternType`], which is also used by the imports and exports
+//! of a module, see [`Module::imports()`] and [`Module::exports()`].
+
+use crate::{function_type_inputs, sys, Error, Module, ValueType};
+use std::collections::HashMap;
+use std::ffi::CStr;
+
+/// The type of a function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionType {
+    pub inputs: Vec<ValueType>,
+    pub output: Option<ValueType>,
+}
+
+/// Formats the function type in the notation of the specification, e.g. `[i32, i64] -> [f32]`.
+impl std::fmt::Display for FunctionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |types: &mut dyn Iterator<Item = &ValueType>| {
+            types
+                .map(|ty| ty.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(
+            f,
+            "[{}] -> [{}]",
+            list(&mut self.inputs.iter()),
+            list(&mut self.output.iter())
+        )
+    }
+}
+
+/// Limits of a table or memory. Memory limits are in pages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    pub min: u32,
+    pub max: Option<u32>,
+}
+
+impl std::fmt::Display for Limits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) => write!(f, "{{min {}, max {}}}", self.min, max),
+            None => write!(f, "{{min {}}}", self.min),
+        }
+    }
+}
+
+impl Limits {
+    /// Check if these limits can be used where `required` limits are expected.
+    fn matches(&self, required: &Limits) -> bool {
+        self.min >= required.min
+            && match (self.max, required.max) {
+                (_, None) => true,
+                (Some(max), Some(required_max)) => max <= required_max,
+                (None, Some(_)) => false,
+            }
+    }
+}
+
+/// The type of a table. The element type is always `funcref` in WebAssembly 1.0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TableType {
+    pub limits: Limits,
+}
+
+/// The type of a global.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlobalType {
+    pub value_type: ValueType,
+    pub mutable: bool,
+}
+
+/// The type of an item which can be imported or exported.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExternType {
+    Func(FunctionType),
+    Table(TableType),
+    Memory(Limits),
+    Global(GlobalType),
+}
+
+impl ExternType {
+    /// Check if an item of this type can satisfy an import of the `required` type.
+    pub(crate) fn matches(&self, required: &ExternType) -> bool {
+        match (self, required) {
+            (ExternType::Func(provided), ExternType::Func(required)) => provided == required,
+            (ExternType::Table(provided), ExternType::Table(required)) => {
+                provided.limits.matches(&required.limits)
+            }
+            (ExternType::Memory(provided), ExternType::Memory(required)) => {
+                provided.matches(required)
+            }
+            (ExternType::Global(provided), ExternType::Global(required)) => provided == required,
+            _ => false,
+        }
+    }
+}
+
+/// Formats the type in the notation of the specification, e.g. `func [i32] -> []`,
+/// `memory {min 1, max 2}` or `global mut i64`.
+impl std::fmt::Display for ExternType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExternType::Func(ty) => write!(f, "func {}", ty),
+            ExternType::Table(ty) => write!(f, "table {} funcref", ty.limits),
+            ExternType::Memory(limits) => write!(f, "memory {}", limits),
+            ExternType::Global(ty) if ty.mutable => write!(f, "global mut {}", ty.value_type),
+            ExternType::Global(ty) => write!(f, "global {}", ty.value_type),
+        }
+    }
+}
+
+/// An import of a module.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    pub ty: ExternType,
+}
+
+/// An export of a module.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Export {
+    pub name: String,
+    pub ty: ExternType,
+}
+
+/// Check if the `provided` item, if any, can satisfy the import.
+fn check_import(provided: Option<&ExternType>, import: Import) -> Result<(), Error> {
+    match provided {
+        None => Err(Error::UnknownImport {
+            module: import.module,
+            name: import.name,
+        }),
+        Some(ty) if !ty.matches(&import.ty) => Err(Error::IncompatibleImportType {
+            module: import.module,
+            name: import.name,
+            expected: import.ty,
+            provided: ty.clone(),
+        }),
+        Some(_) => Ok(()),
+    }
+}
+
+pub(crate) fn limits_from_sys(limits: &sys::FizzyLimits) -> Limits {
+    Limits {
+        min: limits.min,
+        max: if limits.has_max {
+            Some(limits.max)
+        } else {
+            None
+        },
+    }
+}
+
+pub(crate) fn function_type_from_sys(func_type: &sys::FizzyFunctionType) -> FunctionType {
+    FunctionType {
+        inputs: unsafe { function_type_inputs(func_type) }
+            .iter()
+            .map(|&input| ValueType::from_sys(input))
+            .collect(),
+        output: if func_type.output == sys::FizzyValueTypeVoid {
+            None
+        } else {
+            Some(ValueType::from_sys(func_type.output))
+        },
+    }
+}
+
+impl Module {
+    /// Returns the imports of the module, in the order of their definition.
+    pub fn imports(&self) -> Vec<Import> {
+        let module = self.0.as_ptr();
+        let count = unsafe { sys::fizzy_get_import_count(module) };
+        (0..count)
+            .map(|import_idx| {
+                let import = unsafe { sys::fizzy_get_import_description(module, import_idx) };
+                let ty = unsafe {
+                    match import.kind {
+                        sys::FizzyExternalKindFunction => {
+                            ExternType::Func(function_type_from_sys(&import.desc.function_type))
+                        }
+                        sys::FizzyExternalKindTable => ExternType::Table(TableType {
+                            limits: limits_from_sys(&import.desc.table_limits),
+                        }),
+                        sys::FizzyExternalKindMemory => {
+                            ExternType::Memory(limits_from_sys(&import.desc.memory_limits))
+                        }
+                        sys::FizzyExternalKindGlobal => ExternType::Global(GlobalType {
+                            value_type: ValueType::from_sys(import.desc.global_type.value_type),
+                            mutable: import.desc.global_type.is_mutable,
+                        }),
+                        _ => panic!("invalid external kind"),
+                    }
+                };
+                let module_name = unsafe { CStr::from_ptr(import.module) };
+                let name = unsafe { CStr::from_ptr(import.name) };
+                Import {
+                    module: module_name.to_string_lossy().into_owned(),
+                    name: name.to_string_lossy().into_owned(),
+                    ty,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the type of the function, either imported or defined in the module.
+    ///
+    /// Returns `None` if the function index is out of bounds.
+    pub fn function_type(&self, func_idx: u32) -> Option<FunctionType> {
+        if func_idx >= self.function_count() {
+            return None;
+        }
+        let func_type = unsafe { sys::fizzy_get_function_type(self.0.as_ptr(), func_idx) };
+        Some(function_type_from_sys(&func_type))
+    }
+
+    /// Returns the number of imports of the given kind.
+    fn import_count(&self, kind: sys::FizzyExternalKind) -> u32 {
+        let module = self.0.as_ptr();
+        let count = unsafe { sys::fizzy_get_import_count(module) };
+        (0..count)
+            .filter(|&idx| unsafe { sys::fizzy_get_import_description(module, idx) }.kind == kind)
+            .count() as u32
+    }
+
+    /// Returns the number of functions, including imported functions, i.e. the size of
+    /// the function index space.
+    pub fn function_count(&self) -> u32 {
+        unsafe { sys::fizzy_get_function_count(self.0.as_ptr()) }
+    }
+
+    /// Returns the number of imported functions, i.e. the index of the first function defined
+    /// in the module.
+    pub fn import_function_count(&self) -> u32 {
+        self.import_count(sys::FizzyExternalKindFunction)
+    }
+
+    /// Returns the number of globals, including imported globals, i.e. the size of the global
+    /// index space.
+    pub fn global_count(&self) -> u32 {
+        self.import_global_count() + unsafe { sys::fizzy_get_global_count(self.0.as_ptr()) }
+    }
+
+    /// Returns the number of imported globals, i.e. the index of the first global defined
+    /// in the module.
+    pub fn import_global_count(&self) -> u32 {
+        self.import_count(sys::FizzyExternalKindGlobal)
+    }
+
+    /// Returns the number of tables, either imported or defined. It is at most 1 in
+    /// WebAssembly 1.0.
+    pub fn table_count(&self) -> u32 {
+        let mut limits = sys::FizzyLimits {
+            min: 0,
+            max: 0,
+            has_max: false,
+        };
+        unsafe { sys::fizzy_get_table_limits(self.0.as_ptr(), &mut limits) }.into()
+    }
+
+    /// Returns the number of imported tables.
+    pub fn import_table_count(&self) -> u32 {
+        self.import_count(sys::FizzyExternalKindTable)
+    }
+
+    /// Returns the number of memories, either imported or defined. It is at most 1 in
+    /// WebAssembly 1.0, more only in the modules using the multi-memory proposal.
+    pub fn memory_count(&self) -> u32 {
+        unsafe { sys::fizzy_get_memory_count(self.0.as_ptr()) }
+    }
+
+    /// Returns the limits of the memory of the index, the imported memories first, or `None` if
+    /// there is no such memory.
+    pub fn memory_type(&self, memory_idx: u32) -> Option<Limits> {
+        if memory_idx >= self.memory_count() {
+            return None;
+        }
+        let limits = unsafe { sys::fizzy_get_memory_type(self.0.as_ptr(), memory_idx) };
+        Some(limits_from_sys(&limits))
+    }
+
+    /// Returns the number of imported memories.
+    pub fn import_memory_count(&self) -> u32 {
+        self.import_count(sys::FizzyExternalKindMemory)
+    }
+
+    /// Returns the exports of the module, in the order of their definition.
+    pub fn exports(&self) -> Vec<Export> {
+        let module = self.0.as_ptr();
+        let limits =
+            |get: unsafe extern "C" fn(*const sys::FizzyModule, *mut sys::FizzyLimits) -> bool| {
+                let mut limits = sys::FizzyLimits {
+                    min: 0,
+                    max: 0,
+                    has_max: false,
+                };
+                let found = unsafe { get(module, &mut limits) };
+                debug_assert!(found, "exported item exists");
+                limits_from_sys(&limits)
+            };
+        let imported_globals: Vec<GlobalType> = self
+            .imports()
+            .into_iter()
+            .filter_map(|import| match import.ty {
+                ExternType::Global(ty) => Some(ty),
+                _ => None,
+            })
+            .collect();
+        let count = unsafe { sys::fizzy_get_export_count(module) };
+        (0..count)
+            .map(|export_idx| {
+                let export = unsafe { sys::fizzy_get_export_description(module, export_idx) };
+                let ty = match export.kind {
+                    sys::FizzyExternalKindFunction => {
+                        ExternType::Func(function_type_from_sys(&unsafe {
+                            sys::fizzy_get_function_type(module, export.index)
+                        }))
+                    }
+                    sys::FizzyExternalKindTable => ExternType::Table(TableType {
+                        limits: limits(sys::fizzy_get_table_limits),
+                    }),
+                    sys::FizzyExternalKindMemory => {
+                        ExternType::Memory(limits(sys::fizzy_get_memory_limits))
+                    }
+                    sys::FizzyExternalKindGlobal => {
+                        let global_idx = export.index as usize;
+                        ExternType::Global(match imported_globals.get(global_idx) {
+                            Some(&ty) => ty,
+                            None => {
+                                let idx = (global_idx - imported_globals.len()) as u32;
+                                let global = unsafe { sys::fizzy_get_global(module, idx) };
+                                GlobalType {
+                                    value_type: ValueType::from_sys(global.type_.value_type),
+                                    mutable: global.type_.is_mutable,
+                                }
+                            }
+                        })
+                    }
+                    _ => panic!("invalid external kind"),
+                };
+                let name = unsafe { CStr::from_ptr(export.name) };
+                Export {
+                    name: name.to_string_lossy().into_owned(),
+                    ty,
+                }
+            })
+            .collect()
+    }
+
+    /// Check if the exports of this module, imported under the `module` name, satisfy
+    /// the imports of the `importer` from it.
+    ///
+    /// The imports of other modules are not checked. Returns the error for the first import
+    /// which cannot be satisfied.
+    pub fn satisfies(&self, module: &str, importer: &Module) -> Result<(), Error> {
+        let exports = self.exports();
+        for import in importer.imports() {
+            if import.module != module {
+                continue;
+            }
+            let provided = exports
+                .iter()
+                .find(|export| export.name == import.name)
+                .map(|export| &export.ty);
+            check_import(provided, import)?;
+        }
+        Ok(())
+    }
+}
+
+/// The set of items available to satisfy module imports.
+#[derive(Clone, Debug, Default)]
+pub struct Linker {
+    definitions: HashMap<(String, String), ExternType>,
+}
+
+impl Linker {
+    /// Create an empty linker.
+    pub fn new() -> Self {
+        Linker {
+            definitions: HashMap::new(),
+        }
+    }
+
+    /// Define an item of the given type under the `module` and `name`, replacing any previous one.
+    pub fn define(&mut self, module: &str, name: &str, ty: ExternType) -> &mut Self {
+        self.definitions
+            .insert((module.to_string(), name.to_string()), ty);
+        self
+    }
+
+    /// Check if every import of the module is defined with a matching type.
+    ///
+    /// Returns the error for the first import which cannot be satisfied.
+    pub fn check(&self, module: &Module) -> Result<(), Error> {
+        for import in module.imports() {
+            let provided = self
+                .definitions
+                .get(&(import.module.clone(), import.name.clone()));
+            check_import(provided, import)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+
+    /* wat2wasm
+      (func (import "mod1" "foo") (param i32 i64) (result f32))
+      (table (import "mod2" "t") 1 2 anyfunc)
+      (memory (import "mod3" "mem") 1)
+      (global (import "mod4" "g") (mut i64))
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001070160027f7e017d022e04046d6f643103666f6f0000046d6f643201740170",
+        "010102046d6f6433036d656d020001046d6f64340167037e01",
+    ];
+
+    fn foo_type() -> ExternType {
+        ExternType::Func(FunctionType {
+            inputs: vec![ValueType::I32, ValueType::I64],
+            output: Some(ValueType::F32),
+        })
+    }
+
+    fn linker() -> Linker {
+        let mut linker = Linker::new();
+        linker
+            .define("mod1", "foo", foo_type())
+            .define(
+                "mod2",
+                "t",
+                ExternType::Table(TableType {
+                    limits: Limits {
+                        min: 1,
+                        max: Some(2),
+                    },
+                }),
+            )
+            .define(
+                "mod3",
+                "mem",
+                ExternType::Memory(Limits { min: 2, max: None }),
+            )
+            .define(
+                "mod4",
+                "g",
+                ExternType::Global(GlobalType {
+                    value_type: ValueType::I64,
+                    mutable: true,
+                }),
+            );
+        linker
+    }
+
+    #[test]
+    fn imports() {
+        let module = parse(from_hex(WASM)).unwrap();
+        let imports = module.imports();
+        assert_eq!(imports.len(), 4);
+        assert_eq!(imports[0].module, "mod1");
+        assert_eq!(imports[0].name, "foo");
+        assert_eq!(imports[0].ty, foo_type());
+        assert_eq!(
+            imports[1].ty,
+            ExternType::Table(TableType {
+                limits: Limits {
+                    min: 1,
+                    max: Some(2)
+                }
+            })
+        );
+        assert_eq!(
+            imports[2].ty,
+            ExternType::Memory(Limits { min: 1, max: None })
+        );
+        assert_eq!(
+            imports[3].ty,
+            ExternType::Global(GlobalType {
+                value_type: ValueType::I64,
+                mutable: true
+            })
+        );
+    }
+
+    #[test]
+    fn exports() {
+        /* wat2wasm
+          (global (import "mod" "g") f32)
+          (func (export "f") (param i32) (result i64) (i64.const 0))
+          (table (export "t") 1 2 anyfunc)
+          (memory (export "m") 1)
+          (export "g1" (global 0))
+          (global (export "g2") (mut i32) (i32.const 0))
+        */
+        let module = parse(from_hex(&[
+            "0061736d0100000001060160017f017e020a01036d6f640167037d00030201000405017001010205",
+            "030100010606017f0141000b0717050166000001740100016d0200026731030002673203010a0601",
+            "040042000b",
+        ]))
+        .unwrap();
+        let export = |name: &str, ty| Export {
+            name: name.to_string(),
+            ty,
+        };
+        assert_eq!(
+            module.exports(),
+            [
+                export(
+                    "f",
+                    ExternType::Func(FunctionType {
+                        inputs: vec![ValueType::I32],
+                        output: Some(ValueType::I64),
+                    })
+                ),
+                export(
+                    "t",
+                    ExternType::Table(TableType {
+                        limits: Limits {
+                            min: 1,
+                            max: Some(2)
+                        }
+                    })
+                ),
+                export("m", ExternType::Memory(Limits { min: 1, max: None })),
+                export(
+                    "g1",
+                    ExternType::Global(GlobalType {
+                        value_type: ValueType::F32,
+                        mutable: false
+                    })
+                ),
+                export(
+                    "g2",
+                    ExternType::Global(GlobalType {
+                        value_type: ValueType::I32,
+                        mutable: true
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn function_type() {
+        /* wat2wasm
+          (func (import "mod1" "foo") (param i32 i64) (result f32))
+          (func (param f64))
+        */
+        let module = parse(from_hex(&[
+            "0061736d01000000010b0260027f7e017d60017c00020c01046d6f643103666f6f0000030201010a",
+            "040102000b",
+        ]))
+        .unwrap();
+        assert_eq!(
+            module.function_type(0),
+            Some(FunctionType {
+                inputs: vec![ValueType::I32, ValueType::I64],
+                output: Some(ValueType::F32)
+            })
+        );
+        assert_eq!(
+            module.function_type(1),
+            Some(FunctionType {
+                inputs: vec![ValueType::F64],
+                output: None
+            })
+        );
+        assert_eq!(module.function_type(2), None);
+    }
+
+    #[test]
+    fn counts() {
+        let module = parse(from_hex(WASM)).unwrap();
+        assert_eq!(module.function_count(), 1);
+        assert_eq!(module.import_function_count(), 1);
+        assert_eq!(module.global_count(), 1);
+        assert_eq!(module.import_global_count(), 1);
+        assert_eq!(module.table_count(), 1);
+        assert_eq!(module.import_table_count(), 1);
+        assert_eq!(module.memory_count(), 1);
+        assert_eq!(module.import_memory_count(), 1);
+
+        /* wat2wasm
+          (global (import "mod" "g") f32)
+          (func (export "f") (param i32) (result i64) (i64.const 0))
+          (table (export "t") 1 2 anyfunc)
+          (memory (export "m") 1)
+          (export "g1" (global 0))
+          (global (export "g2") (mut i32) (i32.const 0))
+        */
+        let module = parse(from_hex(&[
+            "0061736d0100000001060160017f017e020a01036d6f640167037d00030201000405017001010205",
+            "030100010606017f0141000b0717050166000001740100016d0200026731030002673203010a0601",
+            "040042000b",
+        ]))
+        .unwrap();
+        assert_eq!(module.function_count(), 1);
+        assert_eq!(module.import_function_count(), 0);
+        assert_eq!(module.global_count(), 2);
+        assert_eq!(module.import_global_count(), 1);
+        assert_eq!(module.table_count(), 1);
+        assert_eq!(module.import_table_count(), 0);
+        assert_eq!(module.memory_count(), 1);
+        assert_eq!(module.import_memory_count(), 0);
+
+        let module = parse(from_hex(&["0061736d01000000"])).unwrap();
+        assert_eq!(module.function_count(), 0);
+        assert_eq!(module.global_count(), 0);
+        assert_eq!(module.table_count(), 0);
+        assert_eq!(module.memory_count(), 0);
+    }
+
+    #[test]
+    fn satisfies() {
+        let importer = parse(from_hex(WASM)).unwrap();
+        /* wat2wasm
+          (func (export "foo") (param i32 i64) (result f32) (f32.const 0))
+          (table (export "t") 1 2 anyfunc)
+          (memory (export "mem") 2)
+          (global (export "g") (mut i64) (i64.const 0))
+        */
+        let exporter = parse(from_hex(&[
+            "0061736d0100000001070160027f7e017d030201000405017001010205030100020606017e014200",
+            "0b07150403666f6f000001740100036d656d0200016703000a0901070043000000000b",
+        ]))
+        .unwrap();
+        for module in &["mod1", "mod2", "mod3", "mod4", "other"] {
+            assert_eq!(exporter.satisfies(module, &importer), Ok(()));
+        }
+
+        /* wat2wasm
+          (func (export "foo") (param i32) (result f32) (f32.const 0))
+        */
+        let exporter = parse(from_hex(&[
+            "0061736d0100000001060160017f017d0302010007070103666f6f00000a0901070043000000000b",
+        ]))
+        .unwrap();
+        assert_eq!(
+            exporter.satisfies("mod1", &importer),
+            Err(Error::IncompatibleImportType {
+                module: "mod1".to_string(),
+                name: "foo".to_string(),
+                expected: foo_type(),
+                provided: ExternType::Func(FunctionType {
+                    inputs: vec![ValueType::I32],
+                    output: Some(ValueType::F32),
+                }),
+            })
+        );
+        assert_eq!(
+            exporter.satisfies("mod1", &importer).unwrap_err().to_string(),
+            "incompatible type of import mod1::foo: expected func [i32, i64] -> [f32], provided func [i32] -> [f32]"
+        );
+        assert_eq!(
+            exporter.satisfies("mod2", &importer),
+            Err(Error::UnknownImport {
+                module: "mod2".to_string(),
+                name: "t".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn check() {
+        let module = parse(from_hex(WASM)).unwrap();
+        assert_eq!(linker().check(&module), Ok(()));
+
+        assert_eq!(
+            Linker::new().check(&module),
+            Err(Error::UnknownImport {
+                module: "mod1".to_string(),
+                name: "foo".to_string()
+            })
+        );
+
+        let mut linker = self::linker();
+        linker.define(
+            "mod1",
+            "foo",
+            ExternType::Func(FunctionType {
+                inputs: vec![ValueType::I32],
+                output: Some(ValueType::F32),
+            }),
+        );
+        assert_eq!(
+            linker.check(&module),
+            Err(Error::IncompatibleImportType {
+                module: "mod1".to_string(),
+                name: "foo".to_string(),
+                expected: foo_type(),
+                provided: ExternType::Func(FunctionType {
+                    inputs: vec![ValueType::I32],
+                    output: Some(ValueType::F32),
+                }),
+            })
+        );
+
+        // Unbounded table cannot satisfy the table import with maximum.
+        let mut linker = self::linker();
+        linker.define(
+            "mod2",
+            "t",
+            ExternType::Table(TableType {
+                limits: Limits { min: 1, max: None },
+            }),
+        );
+        assert!(linker.check(&module).is_err());
+
+        // Memory of a smaller minimum size.
+        let mut linker = self::linker();
+        linker.define(
+            "mod3",
+            "mem",
+            ExternType::Memory(Limits { min: 0, max: None }),
+        );
+        assert!(linker.check(&module).is_err());
+
+        // Immutable global.
+        let mut linker = self::linker();
+        linker.define(
+            "mod4",
+            "g",
+            ExternType::Global(GlobalType {
+                value_type: ValueType::I64,
+                mutable: false,
+            }),
+        );
+        assert!(linker.check(&module).is_err());
+
+        // Item of another kind.
+        let mut linker = self::linker();
+        linker.define("mod4", "g", foo_type());
+        assert!(linker.check(&module).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Linting of modules, e.g. to give authors of modules actionable feedback at upload time.
+
+use crate::linker::ExternType;
+use crate::{find_exported_function, sys, wasi, Module};
+
+/// A potential problem found in a module, which does not make the module invalid.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LintWarning {
+    /// The initial size of the memory, either defined or imported, exceeds the configured number
+    /// of pages.
+    HugeInitialMemory { pages: u32 },
+    /// A mutable global is exported, allowing the host to modify the state of the instance.
+    ExportsMutableGlobal { name: String },
+    /// An item is imported from a module name which is not one of the known namespaces.
+    ImportsUnknownNamespace { module: String, name: String },
+    /// Functions defined in the module which cannot be called: neither exported, nor the start
+    /// function, nor in an element segment, nor called directly from any of them.
+    UnreachableFunctions { func_indices: Vec<u32> },
+}
+
+impl std::fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LintWarning::HugeInitialMemory { pages } => {
+                write!(f, "huge initial memory of {} pages", pages)
+            }
+            LintWarning::ExportsMutableGlobal { name } => {
+                write!(f, "exports mutable global \"{}\"", name)
+            }
+            LintWarning::ImportsUnknownNamespace { module, name } => {
+                write!(
+                    f,
+                    "imports \"{}\" from unknown namespace \"{}\"",
+                    name, module
+                )
+            }
+            LintWarning::UnreachableFunctions { func_indices } => {
+                write!(f, "contains {} unreachable functions", func_indices.len())
+            }
+        }
+    }
+}
+
+/// The configuration of the checks done by [`Module::lint_with_options`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct LintOptions {
+    /// The maximum initial size of the memory in pages not reported as huge.
+    pub max_initial_memory_pages: u32,
+    /// The module names of the imports provided by the host.
+    pub known_namespaces: Vec<String>,
+}
+
+/// The default options allow 64 MiB of initial memory, and imports from `env` and WASI.
+impl Default for LintOptions {
+    fn default() -> Self {
+        LintOptions {
+            max_initial_memory_pages: 1024,
+            known_namespaces: vec!["env".to_string(), wasi::MODULE.to_string()],
+        }
+    }
+}
+
+impl Module {
+    /// Returns the index of the start function, if the module has one.
+    pub fn start_function(&self) -> Option<u32> {
+        let mut func_idx = 0;
+        if unsafe { sys::fizzy_get_start_function(self.0.as_ptr(), &mut func_idx) } {
+            Some(func_idx)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the indices of the functions called directly by a function, in the order of
+    /// the call instructions.
+    ///
+    /// Returns an empty vector if the index is not valid or is the index of an imported function.
+    pub(crate) fn function_callees(&self, func_idx: u32) -> Vec<u32> {
+        let module = self.0.as_ptr();
+        let size =
+            unsafe { sys::fizzy_get_function_callees(module, func_idx, std::ptr::null_mut(), 0) };
+        let mut callees = vec![0; size];
+        unsafe { sys::fizzy_get_function_callees(module, func_idx, callees.as_mut_ptr(), size) };
+        callees
+    }
+
+    /// Returns the indices of the functions defined in the module which cannot be reached
+    /// from the exports, the start function and the element segments.
+    fn unreachable_functions(&self) -> Vec<u32> {
+        let mut reachable = vec![false; self.function_count() as usize];
+        let mut pending: Vec<u32> = self
+            .exports()
+            .into_iter()
+            .filter(|export| matches!(export.ty, ExternType::Func(_)))
+            .filter_map(|export| find_exported_function(self.0.as_ptr(), &export.name))
+            .chain(self.start_function())
+            .chain(
+                self.element_segments()
+                    .into_iter()
+                    .flat_map(|segment| segment.func_indices),
+            )
+            .collect();
+        while let Some(func_idx) = pending.pop() {
+            if !std::mem::replace(&mut reachable[func_idx as usize], true) {
+                pending.extend(self.function_callees(func_idx));
+            }
+        }
+        (self.import_function_count()..self.function_count())
+            .filter(|&func_idx| !reachable[func_idx as usize])
+            .collect()
+    }
+
+    /// Checks the module for potential problems with the default options.
+    pub fn lint(&self) -> Vec<LintWarning> {
+        self.lint_with_options(&LintOptions::default())
+    }
+
+    /// Checks the module for potential problems, returning the warnings in the order of
+    /// the memory, the imports, the exports and the functions.
+    pub fn lint_with_options(&self, options: &LintOptions) -> Vec<LintWarning> {
+        let mut warnings = Vec::new();
+        if let Some(limits) = self.stats().memory_limits {
+            if limits.min > options.max_initial_memory_pages {
+                warnings.push(LintWarning::HugeInitialMemory { pages: limits.min });
+            }
+        }
+        for import in self.imports() {
+            if !options.known_namespaces.contains(&import.module) {
+                warnings.push(LintWarning::ImportsUnknownNamespace {
+                    module: import.module,
+                    name: import.name,
+                });
+            }
+        }
+        for export in self.exports() {
+            if let ExternType::Global(ty) = export.ty {
+                if ty.mutable {
+                    warnings.push(LintWarning::ExportsMutableGlobal { name: export.name });
+                }
+            }
+        }
+        let func_indices = self.unreachable_functions();
+        if !func_indices.is_empty() {
+            warnings.push(LintWarning::UnreachableFunctions { func_indices });
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+
+    #[test]
+    fn lint() {
+        /* wat2wasm
+          (func $f (import "env" "f"))
+          (func $g (import "custom" "g"))
+          (table 1 anyfunc)
+          (memory 2048)
+          (global (mut i32) (i32.const 0))
+          (func $main (export "main") (call $a))
+          (func $a (call $f))
+          (func $dead (call $dead2))
+          (func $dead2)
+          (func $elem)
+          (func $start)
+          (export "g" (global 0))
+          (start $start)
+          (elem (i32.const 0) $elem)
+        */
+        let module = parse(from_hex(&[
+            "0061736d0100000001040160000002140203656e760166000006637573746f6d016700000307060000",
+            "000000000404017000010504010080100606017f0141000b070c02046d61696e0002016703000801",
+            "070907010041000b01060a1906040010030b040010000b040010050b02000b02000b02000b",
+        ]))
+        .unwrap();
+        assert_eq!(module.start_function(), Some(7));
+        assert_eq!(module.function_callees(2), [3]);
+        assert!(module.function_callees(0).is_empty());
+
+        let warnings = module.lint();
+        assert_eq!(
+            warnings,
+            [
+                LintWarning::HugeInitialMemory { pages: 2048 },
+                LintWarning::ImportsUnknownNamespace {
+                    module: "custom".to_string(),
+                    name: "g".to_string()
+                },
+                LintWarning::ExportsMutableGlobal {
+                    name: "g".to_string()
+                },
+                LintWarning::UnreachableFunctions {
+                    func_indices: vec![4, 5]
+                },
+            ]
+        );
+        assert_eq!(
+            warnings[1].to_string(),
+            "imports \"g\" from unknown namespace \"custom\""
+        );
+
+        let options = LintOptions {
+            max_initial_memory_pages: 2048,
+            known_namespaces: vec!["env".to_string(), "custom".to_string()],
+        };
+        assert_eq!(module.lint_with_options(&options).len(), 2);
+
+        /* wat2wasm
+          (module)
+        */
+        let module = parse(from_hex(&["0061736d01000000"])).unwrap();
+        assert_eq!(module.start_function(), None);
+        assert!(module.lint().is_empty());
+    }
+}
@@ -0,0 +1,708 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Linear memories owned by the host.
+//!
+//! A [`Memory`] can be imported by multiple instances with [`Imports::define_memory()`],
+//! which then exchange data through it in place.
+//!
+//! The memories defined by modules can be allocated with a custom [`Allocator`], e.g. from
+//! an [`Arena`] shared by many instances, making their teardown a single deallocation.
+//! On targets without a heap for the memories, the arena can be placed in a static buffer with
+//! [`Arena::with_buffer()`]. The instantiation then fails with [`Error::OutOfMemory`] if the
+//! buffer is too small for the memory, and `memory.grow` returns -1. The module, the instance
+//! and the stacks of the interpreter are still allocated by the C++ runtime.
+//!
+//! [`Imports::define_memory()`]: crate::host::Imports::define_memory
+
+use crate::linker::Limits;
+use crate::{sys, Error};
+use std::alloc::Layout;
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+
+/// The size of a memory page in bytes.
+pub const PAGE_SIZE: usize = 65536;
+
+/// The maximum number of memory pages.
+const MAX_PAGES: u32 = 65536;
+
+struct MemoryData(NonNull<sys::FizzyMemory>);
+
+// The memory is only accessed through the bounds-checked methods of `Memory` and by
+// the executions of the importing instances.
+unsafe impl Send for MemoryData {}
+unsafe impl Sync for MemoryData {}
+
+impl Drop for MemoryData {
+    fn drop(&mut self) {
+        unsafe { sys::fizzy_free_memory(self.0.as_ptr()) }
+    }
+}
+
+/// A linear memory owned by the host, which can be imported by multiple instances.
+///
+/// Cloning the memory creates another handle to the same memory. The importing instances keep
+/// the memory alive. Accesses through the handles are not synchronized with the executions of
+/// the importing instances.
+#[derive(Clone)]
+pub struct Memory {
+    data: Arc<MemoryData>,
+    limits: Limits,
+}
+
+impl Memory {
+    /// Create a memory of the limits, of the initial size of `limits.min` pages filled with zeroes.
+    pub fn new(limits: Limits) -> Result<Self, Error> {
+        if limits.min > MAX_PAGES
+            || limits
+                .max
+                .is_some_and(|max| max < limits.min || max > MAX_PAGES)
+        {
+            return Err(Error::InvalidMemoryLimits);
+        }
+        let ptr = unsafe { sys::fizzy_create_memory(limits.min) };
+        let data = NonNull::new(ptr).ok_or(Error::OutOfMemory)?;
+        Ok(Memory {
+            data: Arc::new(MemoryData(data)),
+            limits,
+        })
+    }
+
+    /// Returns the limits of the memory.
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Returns the size of the memory in bytes.
+    pub fn size(&self) -> usize {
+        unsafe { sys::fizzy_get_memory_size(self.data.0.as_ptr()) }
+    }
+
+    /// Returns the memory range `[offset, offset + size)` if it is within the memory.
+    fn checked_range(&self, offset: u32, size: usize) -> Result<usize, Error> {
+        let offset = offset as usize;
+        match offset.checked_add(size) {
+            Some(end) if end <= self.size() => Ok(offset),
+            _ => Err(Error::InvalidMemoryOffsetOrSize),
+        }
+    }
+
+    /// Copy memory starting at `offset` into `dst`.
+    pub fn get(&self, offset: u32, dst: &mut [u8]) -> Result<(), Error> {
+        let offset = self.checked_range(offset, dst.len())?;
+        if !dst.is_empty() {
+            unsafe {
+                let data = sys::fizzy_get_memory_data(self.data.0.as_ptr());
+                std::ptr::copy_nonoverlapping(data.add(offset), dst.as_mut_ptr(), dst.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy `src` into memory starting at `offset`.
+    pub fn set(&self, offset: u32, src: &[u8]) -> Result<(), Error> {
+        let offset = self.checked_range(offset, src.len())?;
+        if !src.is_empty() {
+            unsafe {
+                let data = sys::fizzy_get_memory_data(self.data.0.as_ptr());
+                std::ptr::copy_nonoverlapping(src.as_ptr(), data.add(offset), src.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the memory in the form imported by instances.
+    pub(crate) fn to_external(&self) -> sys::FizzyExternalMemory {
+        sys::FizzyExternalMemory {
+            memory: self.data.0.as_ptr(),
+            limits: sys::FizzyLimits {
+                min: self.limits.min,
+                max: self.limits.max.unwrap_or(0),
+                has_max: self.limits.max.is_some(),
+            },
+        }
+    }
+}
+
+/// A custom allocator of the memory of an instance, e.g. for accounting or for selecting
+/// a specific allocator. See [`InstantiateOptions::memory_allocator`].
+///
+/// # Safety
+///
+/// The allocator must behave like `malloc`, `realloc` and `free`: a returned pointer must be null
+/// in case of failure, or valid for reads and writes of the requested size until it is
+/// deallocated. A reallocation must preserve the contents, or leave the allocation intact
+/// in case of failure.
+///
+/// [`InstantiateOptions::memory_allocator`]: crate::InstantiateOptions::memory_allocator
+pub unsafe trait Allocator: Send + Sync {
+    /// Allocate `size` bytes, not necessarily zero-initialized.
+    fn allocate(&self, size: usize) -> *mut u8;
+
+    /// Resize the allocation of `old_size` bytes to `new_size` bytes.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be an allocation of `old_size` bytes returned by this allocator.
+    unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize, new_size: usize) -> *mut u8;
+
+    /// Free the allocation of `size` bytes.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be an allocation of `size` bytes returned by this allocator.
+    unsafe fn deallocate(&self, ptr: *mut u8, size: usize);
+}
+
+// Unwinding across the C++ interpreter is not allowed: a panicking allocator fails the allocation.
+unsafe extern "C" fn allocate_trampoline(
+    context: *mut std::ffi::c_void,
+    size: usize,
+) -> *mut std::ffi::c_void {
+    let allocator = &*(context as *const Arc<dyn Allocator>);
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| allocator.allocate(size)))
+        .unwrap_or(std::ptr::null_mut()) as *mut std::ffi::c_void
+}
+
+unsafe extern "C" fn reallocate_trampoline(
+    context: *mut std::ffi::c_void,
+    ptr: *mut std::ffi::c_void,
+    old_size: usize,
+    new_size: usize,
+) -> *mut std::ffi::c_void {
+    let allocator = &*(context as *const Arc<dyn Allocator>);
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        allocator.reallocate(ptr as *mut u8, old_size, new_size)
+    }))
+    .unwrap_or(std::ptr::null_mut()) as *mut std::ffi::c_void
+}
+
+unsafe extern "C" fn deallocate_trampoline(
+    context: *mut std::ffi::c_void,
+    ptr: *mut std::ffi::c_void,
+    size: usize,
+) {
+    let allocator = &*(context as *const Arc<dyn Allocator>);
+    // A panicking deallocation leaks the allocation.
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        allocator.deallocate(ptr as *mut u8, size)
+    }));
+}
+
+/// Returns the allocator in the form used by instantiation, valid as long as `allocator`.
+pub(crate) fn allocator_to_sys(allocator: &Arc<dyn Allocator>) -> sys::FizzyAllocator {
+    sys::FizzyAllocator {
+        allocate: Some(allocate_trampoline),
+        reallocate: Some(reallocate_trampoline),
+        deallocate: Some(deallocate_trampoline),
+        context: allocator as *const Arc<dyn Allocator> as *mut std::ffi::c_void,
+    }
+}
+
+/// The alignment of the allocations from an arena.
+const ARENA_ALIGNMENT: usize = 16;
+
+struct ArenaState {
+    /// The size of the used part of the arena.
+    used: usize,
+    /// The offset of the last allocation, which can be resized in place.
+    last: Option<usize>,
+}
+
+/// An allocator handing out the parts of a single contiguous block of a fixed capacity.
+///
+/// The memories of the instances sharing the arena, created with it as
+/// [`InstantiateOptions::memory_allocator`], are placed next to each other. The space is only
+/// reclaimed by deallocating the most recent allocation. All of it is freed at once when the arena
+/// is dropped, after all the instances using it. The last allocation is resized in place, so
+/// a growing memory is moved at most once when it is not the most recent.
+///
+/// [`InstantiateOptions::memory_allocator`]: crate::InstantiateOptions::memory_allocator
+pub struct Arena {
+    data: NonNull<u8>,
+    capacity: usize,
+    /// Whether the block has been allocated by the arena, or given with the buffer.
+    owned: bool,
+    state: Mutex<ArenaState>,
+}
+
+// The arena hands out disjoint parts of its block, synchronized by the mutex.
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Arena {
+    /// Allocate the block of the arena of `capacity` bytes.
+    pub fn new(capacity: usize) -> Result<Self, Error> {
+        let layout = Self::layout(capacity)?;
+        let data = NonNull::new(unsafe { std::alloc::alloc(layout) }).ok_or(Error::OutOfMemory)?;
+        Ok(Arena {
+            data,
+            capacity,
+            owned: true,
+            state: Mutex::new(ArenaState {
+                used: 0,
+                last: None,
+            }),
+        })
+    }
+
+    /// Create the arena in the `buffer`, without allocating its block, e.g. in a static buffer
+    /// on a target without a heap. The capacity is the size of the buffer after aligning its
+    /// start.
+    pub fn with_buffer(buffer: &'static mut [u8]) -> Self {
+        let padding = buffer
+            .as_ptr()
+            .align_offset(ARENA_ALIGNMENT)
+            .min(buffer.len());
+        let block = &mut buffer[padding..];
+        Arena {
+            capacity: block.len(),
+            data: NonNull::from(block).cast(),
+            owned: false,
+            state: Mutex::new(ArenaState {
+                used: 0,
+                last: None,
+            }),
+        }
+    }
+
+    /// The capacity of the arena in bytes.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of bytes allocated so far, including the alignment padding and the space of
+    /// the allocations which have been resized or deallocated, but not reclaimed.
+    pub fn used(&self) -> usize {
+        self.state().used
+    }
+
+    fn layout(capacity: usize) -> Result<Layout, Error> {
+        // A zero-sized allocation is not allowed, but the pointer must be valid for any arena.
+        Layout::from_size_align(capacity.max(1), ARENA_ALIGNMENT).map_err(|_| Error::OutOfMemory)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, ArenaState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn offset_of(&self, ptr: *mut u8) -> usize {
+        ptr as usize - self.data.as_ptr() as usize
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+        let layout = Self::layout(self.capacity).expect("valid arena layout");
+        unsafe { std::alloc::dealloc(self.data.as_ptr(), layout) }
+    }
+}
+
+unsafe impl Allocator for Arena {
+    fn allocate(&self, size: usize) -> *mut u8 {
+        let mut state = self.state();
+        let offset = match state.used.checked_add(ARENA_ALIGNMENT - 1) {
+            Some(end) => end / ARENA_ALIGNMENT * ARENA_ALIGNMENT,
+            None => return std::ptr::null_mut(),
+        };
+        match offset.checked_add(size) {
+            Some(end) if end <= self.capacity => {
+                state.used = end;
+                state.last = Some(offset);
+                unsafe { self.data.as_ptr().add(offset) }
+            }
+            _ => std::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize, new_size: usize) -> *mut u8 {
+        let offset = self.offset_of(ptr);
+        {
+            let mut state = self.state();
+            if state.last == Some(offset) {
+                return match offset.checked_add(new_size) {
+                    Some(end) if end <= self.capacity => {
+                        state.used = end;
+                        ptr
+                    }
+                    _ => std::ptr::null_mut(),
+                };
+            }
+        }
+        let new_ptr = self.allocate(new_size);
+        if !new_ptr.is_null() {
+            std::ptr::copy_nonoverlapping(ptr, new_ptr, old_size.min(new_size));
+        }
+        new_ptr
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, _size: usize) {
+        let offset = self.offset_of(ptr);
+        let mut state = self.state();
+        if state.last == Some(offset) {
+            state.used = offset;
+            state.last = None;
+        }
+    }
+}
+
+impl std::fmt::Debug for Arena {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Arena")
+            .field("capacity", &self.capacity)
+            .field("owned", &self.owned)
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for Memory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Memory")
+            .field("limits", &self.limits)
+            .field("size", &self.size())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::Imports;
+    use crate::linker::ExternType;
+    use crate::test_utils::from_hex;
+    use crate::{parse, Instance, Value};
+
+    /* wat2wasm
+      (memory (import "env" "memory") 1 2)
+      (func (export "store") (param i32 i32) (i32.store (local.get 0) (local.get 1)))
+      (func (export "load") (param i32) (result i32) (i32.load (local.get 0)))
+      (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+    */
+    const WASM: &[&str] = &[
+        "0061736d01000000010f0360027f7f0060017f017f6000017f02100103656e76066d656d6f727902",
+        "0101020304030001020717030573746f72650000046c6f616400010467726f7700020a1a03090020",
+        "0020013602000b070020002802000b0600410140000b",
+    ];
+
+    fn instantiate(memory: &Memory) -> Result<Instance, Error> {
+        let mut imports = Imports::new();
+        imports.define_memory("env", "memory", memory);
+        parse(from_hex(WASM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+    }
+
+    #[test]
+    fn new() {
+        for limits in &[
+            Limits {
+                min: 2,
+                max: Some(1),
+            },
+            Limits {
+                min: 65537,
+                max: None,
+            },
+            Limits {
+                min: 1,
+                max: Some(65537),
+            },
+        ] {
+            assert_eq!(Memory::new(*limits).err(), Some(Error::InvalidMemoryLimits));
+        }
+
+        let memory = Memory::new(Limits { min: 1, max: None }).unwrap();
+        assert_eq!(memory.size(), PAGE_SIZE);
+        assert_eq!(memory.limits(), Limits { min: 1, max: None });
+
+        let mut dst = [0xffu8; 2];
+        memory.set(0, &[1, 2]).unwrap();
+        memory.get(0, &mut dst).unwrap();
+        assert_eq!(dst, [1, 2]);
+        assert_eq!(
+            memory.get(PAGE_SIZE as u32 - 1, &mut dst),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert_eq!(
+            memory.set(u32::MAX, &[1]),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+    }
+
+    #[test]
+    fn shared_between_instances() {
+        let memory = Memory::new(Limits {
+            min: 1,
+            max: Some(2),
+        })
+        .unwrap();
+        let mut writer = instantiate(&memory).unwrap();
+        let mut reader = instantiate(&memory).unwrap();
+
+        writer
+            .execute("store", &[Value::I32(8), Value::I32(42)])
+            .unwrap();
+        let result = reader.execute("load", &[Value::I32(8)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(42)));
+
+        memory.set(8, &[7, 0, 0, 0]).unwrap();
+        let result = reader.execute("load", &[Value::I32(8)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(7)));
+
+        // The growth by one instance is visible to the others.
+        let result = writer.execute("grow", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        assert_eq!(memory.size(), 2 * PAGE_SIZE);
+        let result = reader
+            .execute("load", &[Value::I32(PAGE_SIZE as i32)])
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0)));
+        let result = reader.execute("grow", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(-1)));
+
+        // The instances keep the memory alive.
+        drop(memory);
+        let result = reader.execute("load", &[Value::I32(8)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(7)));
+    }
+
+    #[test]
+    fn incompatible_limits() {
+        let memory = Memory::new(Limits { min: 1, max: None }).unwrap();
+        assert_eq!(
+            instantiate(&memory).err(),
+            Some(Error::IncompatibleImportType {
+                module: "env".to_string(),
+                name: "memory".to_string(),
+                expected: ExternType::Memory(Limits {
+                    min: 1,
+                    max: Some(2)
+                }),
+                provided: ExternType::Memory(Limits { min: 1, max: None }),
+            })
+        );
+    }
+
+    /// The allocator counting the allocated bytes, failing the allocations above the limit.
+    #[cfg_attr(feature = "mock", allow(dead_code))]
+    struct CountingAllocator(std::sync::atomic::AtomicUsize, usize);
+
+    impl Default for CountingAllocator {
+        fn default() -> Self {
+            CountingAllocator(Default::default(), usize::MAX)
+        }
+    }
+
+    #[cfg_attr(feature = "mock", allow(dead_code))]
+    impl CountingAllocator {
+        fn allocated(&self) -> usize {
+            self.0.load(std::sync::atomic::Ordering::SeqCst)
+        }
+
+        fn layout(size: usize) -> std::alloc::Layout {
+            std::alloc::Layout::from_size_align(size, 16).unwrap()
+        }
+    }
+
+    unsafe impl Allocator for CountingAllocator {
+        fn allocate(&self, size: usize) -> *mut u8 {
+            if size > self.1 {
+                return std::ptr::null_mut();
+            }
+            self.0.fetch_add(size, std::sync::atomic::Ordering::SeqCst);
+            unsafe { std::alloc::alloc(Self::layout(size)) }
+        }
+
+        unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize, new_size: usize) -> *mut u8 {
+            if new_size > self.1 {
+                return std::ptr::null_mut();
+            }
+            self.0
+                .fetch_add(new_size, std::sync::atomic::Ordering::SeqCst);
+            self.0
+                .fetch_sub(old_size, std::sync::atomic::Ordering::SeqCst);
+            std::alloc::realloc(ptr, Self::layout(old_size), new_size)
+        }
+
+        unsafe fn deallocate(&self, ptr: *mut u8, size: usize) {
+            self.0.fetch_sub(size, std::sync::atomic::Ordering::SeqCst);
+            std::alloc::dealloc(ptr, Self::layout(size))
+        }
+    }
+
+    #[test]
+    // The mock ignores the allocator and keeps the memory on the heap.
+    #[cfg(not(feature = "mock"))]
+    fn allocator() {
+        /* wat2wasm
+          (memory 1 2)
+          (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+        */
+        let wasm = from_hex(&[
+            "0061736d010000000105016000017f030201000504010101020708010467726f7700000a08010600",
+            "410140000b",
+        ]);
+        let allocator = Arc::new(CountingAllocator::default());
+        let options = crate::InstantiateOptions {
+            memory_allocator: Some(allocator.clone()),
+            ..Default::default()
+        };
+        let mut instance = parse(&wasm)
+            .unwrap()
+            .instantiate_with_options(Imports::new(), &options)
+            .unwrap();
+        assert_eq!(allocator.allocated(), PAGE_SIZE);
+
+        let result = instance.execute("grow", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        assert_eq!(allocator.allocated(), 2 * PAGE_SIZE);
+        let mut dst = [0xffu8; 4];
+        instance
+            .memory_get(2 * PAGE_SIZE as u32 - 4, &mut dst)
+            .unwrap();
+        assert_eq!(dst, [0, 0, 0, 0]);
+
+        drop(instance);
+        assert_eq!(allocator.allocated(), 0);
+    }
+
+    #[test]
+    // The mock ignores the allocator and keeps the memory on the heap.
+    #[cfg(not(feature = "mock"))]
+    fn allocation_failure() {
+        /* wat2wasm
+          (memory 1 2)
+          (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+        */
+        let wasm = from_hex(&[
+            "0061736d010000000105016000017f030201000504010101020708010467726f7700000a08010600",
+            "410140000b",
+        ]);
+        let instantiate = |limit: usize| {
+            let options = crate::InstantiateOptions {
+                memory_allocator: Some(Arc::new(CountingAllocator(Default::default(), limit))),
+                ..Default::default()
+            };
+            parse(&wasm)
+                .unwrap()
+                .instantiate_with_options(Imports::new(), &options)
+        };
+        assert_eq!(instantiate(PAGE_SIZE - 1).err(), Some(Error::OutOfMemory));
+
+        // The failed growth is reported to the instance.
+        let mut instance = instantiate(PAGE_SIZE).unwrap();
+        let result = instance.execute("grow", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(-1)));
+        assert_eq!(instance.memory_size(), PAGE_SIZE);
+    }
+
+    #[test]
+    // The mock ignores the allocator and keeps the memory on the heap.
+    #[cfg(not(feature = "mock"))]
+    fn arena() {
+        /* wat2wasm
+          (memory 1 2)
+          (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+        */
+        let wasm = from_hex(&[
+            "0061736d010000000105016000017f030201000504010101020708010467726f7700000a08010600",
+            "410140000b",
+        ]);
+        let arena = Arc::new(Arena::new(4 * PAGE_SIZE).unwrap());
+        let instantiate = || {
+            let options = crate::InstantiateOptions {
+                memory_allocator: Some(arena.clone()),
+                ..Default::default()
+            };
+            parse(&wasm)
+                .unwrap()
+                .instantiate_with_options(Imports::new(), &options)
+        };
+        let mut first = instantiate().unwrap();
+        let mut second = instantiate().unwrap();
+        assert_eq!(arena.used(), 2 * PAGE_SIZE);
+
+        // The last allocation grows in place.
+        let result = second.execute("grow", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        assert_eq!(arena.used(), 3 * PAGE_SIZE);
+
+        // Other allocations are moved, if there is enough space.
+        first.memory_set(8, &[42]).unwrap();
+        let result = first.execute("grow", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(-1)));
+        assert_eq!(arena.used(), 3 * PAGE_SIZE);
+
+        // The space of the last allocation is reclaimed.
+        drop(second);
+        assert_eq!(arena.used(), PAGE_SIZE);
+        let result = first.execute("grow", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        assert_eq!(arena.used(), 3 * PAGE_SIZE);
+        let mut dst = [0u8; 1];
+        first.memory_get(8, &mut dst).unwrap();
+        assert_eq!(dst, [42]);
+
+        let third = instantiate().unwrap();
+        assert_eq!(arena.used(), 4 * PAGE_SIZE);
+        assert_eq!(instantiate().err(), Some(Error::OutOfMemory));
+
+        // The instances keep the arena alive.
+        drop(first);
+        drop(third);
+        assert_eq!(Arc::strong_count(&arena), 1);
+    }
+
+    #[test]
+    // The mock ignores the allocator and keeps the memory on the heap.
+    #[cfg(not(feature = "mock"))]
+    fn arena_with_buffer() {
+        /* wat2wasm
+          (memory 1 2)
+          (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+        */
+        let wasm = from_hex(&[
+            "0061736d010000000105016000017f030201000504010101020708010467726f7700000a08010600",
+            "410140000b",
+        ]);
+        // The buffer may be unaligned, at most the alignment padding is lost.
+        let buffer = Box::leak(vec![0u8; PAGE_SIZE + ARENA_ALIGNMENT].into_boxed_slice());
+        let arena = Arc::new(Arena::with_buffer(&mut buffer[1..]));
+        assert!(arena.capacity() >= PAGE_SIZE);
+        assert!(arena.capacity() < PAGE_SIZE + ARENA_ALIGNMENT);
+        let options = crate::InstantiateOptions {
+            memory_allocator: Some(arena.clone()),
+            ..Default::default()
+        };
+
+        let mut instance = parse(&wasm)
+            .unwrap()
+            .instantiate_with_options(Imports::new(), &options)
+            .unwrap();
+        assert_eq!(arena.used(), PAGE_SIZE);
+        instance.memory_set(8, &[42]).unwrap();
+
+        // The buffer is too small for the growth and for another memory.
+        let result = instance.execute("grow", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(-1)));
+        assert_eq!(
+            parse(&wasm)
+                .unwrap()
+                .instantiate_with_options(Imports::new(), &options)
+                .err(),
+            Some(Error::OutOfMemory)
+        );
+
+        let mut dst = [0u8; 1];
+        instance.memory_get(8, &mut dst).unwrap();
+        assert_eq!(dst, [42]);
+        drop(instance);
+        assert_eq!(arena.used(), 0);
+    }
+}
@@ -0,0 +1,469 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Static metrics of module code, e.g. for pricing execution or rejecting oversized modules.
+
+use crate::binary::{sections, Reader};
+use crate::linker::{function_type_from_sys, limits_from_sys, Limits};
+use crate::record::json_string;
+use crate::{parse, sys, Error, Module};
+
+/// Code metrics of a function defined in a module.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FunctionMetrics {
+    /// The size of the function body in the wasm binary in bytes, including local declarations.
+    pub body_size: u32,
+    /// The number of local variables, not including the arguments.
+    pub local_count: u32,
+    /// The maximum height of the operand stack during execution of the function.
+    pub max_stack_height: u32,
+}
+
+/// The class of an instruction, following the grouping of instructions in the specification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InstructionClass {
+    /// Control instructions, including branches and calls.
+    Control,
+    /// `drop` and `select`.
+    Parametric,
+    /// Accessing locals and globals.
+    Variable,
+    /// Loads, stores, `memory.size` and `memory.grow`.
+    Memory,
+    /// Constants and all arithmetic, comparison and conversion instructions.
+    Numeric,
+}
+
+impl InstructionClass {
+    /// Returns the class of the instruction of the given opcode, or `None` if the opcode is not
+    /// a valid WebAssembly 1.0 opcode.
+    pub fn of(opcode: u8) -> Option<Self> {
+        match opcode {
+            0x00..=0x11 => Some(InstructionClass::Control),
+            0x1a..=0x1b => Some(InstructionClass::Parametric),
+            0x20..=0x24 => Some(InstructionClass::Variable),
+            0x28..=0x40 => Some(InstructionClass::Memory),
+            0x41..=0xbf => Some(InstructionClass::Numeric),
+            _ => None,
+        }
+    }
+}
+
+/// The number of instructions of each class.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InstructionHistogram {
+    pub control: u64,
+    pub parametric: u64,
+    pub variable: u64,
+    pub memory: u64,
+    pub numeric: u64,
+}
+
+impl InstructionHistogram {
+    /// Returns the number of instructions of the class.
+    pub fn get(&self, class: InstructionClass) -> u64 {
+        match class {
+            InstructionClass::Control => self.control,
+            InstructionClass::Parametric => self.parametric,
+            InstructionClass::Variable => self.variable,
+            InstructionClass::Memory => self.memory,
+            InstructionClass::Numeric => self.numeric,
+        }
+    }
+
+    /// Returns the total number of instructions.
+    pub fn total(&self) -> u64 {
+        self.control + self.parametric + self.variable + self.memory + self.numeric
+    }
+
+    fn add(&mut self, class: InstructionClass) {
+        let count = match class {
+            InstructionClass::Control => &mut self.control,
+            InstructionClass::Parametric => &mut self.parametric,
+            InstructionClass::Variable => &mut self.variable,
+            InstructionClass::Memory => &mut self.memory,
+            InstructionClass::Numeric => &mut self.numeric,
+        };
+        *count += 1;
+    }
+}
+
+/// Aggregate statistics of a module, e.g. for pricing deployment or enforcing size policies
+/// before instantiation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleStats {
+    /// The total size of the bodies of the functions defined in the module in bytes.
+    pub code_size: u64,
+    /// The number of functions defined in the module.
+    pub function_count: u32,
+    /// The number of imports of any kind.
+    pub import_count: u32,
+    /// The number of exports of any kind.
+    pub export_count: u32,
+    /// The limits of the memory, either defined or imported.
+    pub memory_limits: Option<Limits>,
+    /// The limits of the table, either defined or imported.
+    pub table_limits: Option<Limits>,
+    /// The instructions of all functions defined in the module, by class.
+    pub instructions: InstructionHistogram,
+}
+
+fn limits_from_out_param(f: impl FnOnce(*mut sys::FizzyLimits) -> bool) -> Option<Limits> {
+    let mut limits = sys::FizzyLimits {
+        min: 0,
+        max: 0,
+        has_max: false,
+    };
+    if f(&mut limits) {
+        Some(limits_from_sys(&limits))
+    } else {
+        None
+    }
+}
+
+/// Returns the name of a section of the given id, the name in the payload for custom sections.
+fn section_name(id: u8, payload: &[u8]) -> Result<String, Error> {
+    let name = match id {
+        0 => {
+            return Reader::new(payload)
+                .name()
+                .map(str::to_string)
+                .map_err(|_| Error::ParsingFailed)
+        }
+        1 => "type",
+        2 => "import",
+        3 => "function",
+        4 => "table",
+        5 => "memory",
+        6 => "global",
+        7 => "export",
+        8 => "start",
+        9 => "element",
+        10 => "code",
+        11 => "data",
+        12 => "datacount",
+        _ => return Err(Error::ParsingFailed),
+    };
+    Ok(name.to_string())
+}
+
+fn json_limits(limits: Option<Limits>) -> String {
+    match limits {
+        Some(Limits {
+            min,
+            max: Some(max),
+        }) => format!("{{\"min\":{},\"max\":{}}}", min, max),
+        Some(Limits { min, max: None }) => format!("{{\"min\":{},\"max\":null}}", min),
+        None => "null".to_string(),
+    }
+}
+
+/// Describe the structure of a module as a JSON object, e.g. for indexing modules in registries.
+///
+/// The object contains the size and the sections of the binary, in the order of their
+/// appearance, the types of the type section, the imports and exports, with their types in
+/// the notation of the specification, and the [`ModuleStats`] of the module. For example:
+///
+/// ```text
+/// {"size":83,"sections":[{"id":1,"name":"type","size":6},...,{"id":10,"name":"code","size":19}],
+/// "types":["[i32] -> [i32]"],"imports":[{"module":"env","name":"f","type":"func [i32] -> [i32]"}],
+/// "exports":[{"name":"f","type":"func [i32] -> [i32]"}],"code_size":17,"function_count":1,
+/// "memory":{"min":1,"max":3},"table":null,
+/// "instructions":{"control":4,"parametric":1,"variable":1,"memory":1,"numeric":3}}
+/// ```
+///
+/// Returns the parsing error if the binary is not a valid module.
+pub fn module_json(wasm: &[u8]) -> Result<String, Error> {
+    let module = parse(wasm)?;
+    let sections = sections(wasm)?
+        .into_iter()
+        .map(|(id, payload)| {
+            Ok(format!(
+                "{{\"id\":{},\"name\":{},\"size\":{}}}",
+                id,
+                json_string(&section_name(id, payload)?),
+                payload.len()
+            ))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let type_count = unsafe { sys::fizzy_get_type_count(module.0.as_ptr()) };
+    let types: Vec<String> = (0..type_count)
+        .map(|type_idx| {
+            let func_type = unsafe { sys::fizzy_get_type(module.0.as_ptr(), type_idx) };
+            json_string(&function_type_from_sys(&func_type).to_string())
+        })
+        .collect();
+    let imports: Vec<String> = module
+        .imports()
+        .iter()
+        .map(|import| {
+            format!(
+                "{{\"module\":{},\"name\":{},\"type\":{}}}",
+                json_string(&import.module),
+                json_string(&import.name),
+                json_string(&import.ty.to_string())
+            )
+        })
+        .collect();
+    let exports: Vec<String> = module
+        .exports()
+        .iter()
+        .map(|export| {
+            format!(
+                "{{\"name\":{},\"type\":{}}}",
+                json_string(&export.name),
+                json_string(&export.ty.to_string())
+            )
+        })
+        .collect();
+    let stats = module.stats();
+    let instructions = &stats.instructions;
+    Ok(format!(
+        concat!(
+            "{{\"size\":{},\"sections\":[{}],\"types\":[{}],\"imports\":[{}],\"exports\":[{}],",
+            "\"code_size\":{},\"function_count\":{},\"memory\":{},\"table\":{},",
+            "\"instructions\":{{\"control\":{},\"parametric\":{},\"variable\":{},",
+            "\"memory\":{},\"numeric\":{}}}}}"
+        ),
+        wasm.len(),
+        sections.join(","),
+        types.join(","),
+        imports.join(","),
+        exports.join(","),
+        stats.code_size,
+        stats.function_count,
+        json_limits(stats.memory_limits),
+        json_limits(stats.table_limits),
+        instructions.control,
+        instructions.parametric,
+        instructions.variable,
+        instructions.memory,
+        instructions.numeric
+    ))
+}
+
+impl Module {
+    /// Returns the opcodes of the instructions of a function, including the final `end`.
+    ///
+    /// Returns an empty vector if the index is not valid or is the index of an imported function.
+    pub(crate) fn function_opcodes(&self, func_idx: u32) -> Vec<u8> {
+        let module = self.0.as_ptr();
+        let size =
+            unsafe { sys::fizzy_get_function_opcodes(module, func_idx, std::ptr::null_mut(), 0) };
+        let mut opcodes = vec![0; size];
+        unsafe { sys::fizzy_get_function_opcodes(module, func_idx, opcodes.as_mut_ptr(), size) };
+        opcodes
+    }
+
+    /// Returns aggregate statistics of the module.
+    pub fn stats(&self) -> ModuleStats {
+        let module = self.0.as_ptr();
+        let mut stats = ModuleStats {
+            code_size: 0,
+            function_count: 0,
+            import_count: unsafe { sys::fizzy_get_import_count(module) },
+            export_count: unsafe { sys::fizzy_get_export_count(module) },
+            memory_limits: limits_from_out_param(|limits| unsafe {
+                sys::fizzy_get_memory_limits(module, limits)
+            }),
+            table_limits: limits_from_out_param(|limits| unsafe {
+                sys::fizzy_get_table_limits(module, limits)
+            }),
+            instructions: InstructionHistogram::default(),
+        };
+        let function_count = unsafe { sys::fizzy_get_function_count(module) };
+        for func_idx in 0..function_count {
+            if let Some(metrics) = self.function_metrics(func_idx) {
+                stats.function_count += 1;
+                stats.code_size += u64::from(metrics.body_size);
+                for opcode in self.function_opcodes(func_idx) {
+                    if let Some(class) = InstructionClass::of(opcode) {
+                        stats.instructions.add(class);
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    /// Returns the code metrics of a function.
+    ///
+    /// Returns `None` if the index is not valid or is the index of an imported function.
+    pub fn function_metrics(&self, func_idx: u32) -> Option<FunctionMetrics> {
+        let mut metrics = sys::FizzyFunctionMetrics {
+            body_size: 0,
+            local_count: 0,
+            max_stack_height: 0,
+        };
+        if !unsafe { sys::fizzy_get_function_metrics(self.0.as_ptr(), func_idx, &mut metrics) } {
+            return None;
+        }
+        Some(FunctionMetrics {
+            body_size: metrics.body_size,
+            local_count: metrics.local_count,
+            max_stack_height: metrics.max_stack_height,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+
+    #[test]
+    fn function_metrics() {
+        /* wat2wasm
+          (func (import "env" "f") (param i32))
+          (func (param i32) (local i64 i64)
+            (drop (i32.add (i32.const 1) (i32.const 2)))
+          )
+        */
+        let module = parse(from_hex(&[
+            "0061736d0100000001050160017f0002090103656e7601660000030201000a0c010a01027e410141",
+            "026a1a0b",
+        ]))
+        .unwrap();
+        assert_eq!(module.function_metrics(0), None);
+        assert_eq!(module.function_metrics(2), None);
+        assert_eq!(
+            module.function_metrics(1),
+            Some(FunctionMetrics {
+                body_size: 10,
+                local_count: 2,
+                // The mock does not compute the stack height.
+                max_stack_height: if cfg!(feature = "mock") { 0 } else { 2 },
+            })
+        );
+    }
+
+    #[test]
+    fn instruction_class() {
+        assert_eq!(InstructionClass::of(0x0b), Some(InstructionClass::Control));
+        assert_eq!(InstructionClass::of(0x10), Some(InstructionClass::Control));
+        assert_eq!(
+            InstructionClass::of(0x1b),
+            Some(InstructionClass::Parametric)
+        );
+        assert_eq!(InstructionClass::of(0x23), Some(InstructionClass::Variable));
+        assert_eq!(InstructionClass::of(0x40), Some(InstructionClass::Memory));
+        assert_eq!(InstructionClass::of(0x41), Some(InstructionClass::Numeric));
+        assert_eq!(InstructionClass::of(0xbf), Some(InstructionClass::Numeric));
+        assert_eq!(InstructionClass::of(0x12), None);
+        assert_eq!(InstructionClass::of(0xc0), None);
+    }
+
+    #[test]
+    fn stats() {
+        /* wat2wasm
+          (func (import "env" "f") (param i32) (result i32))
+          (memory (import "env" "mem") 1 3)
+          (table 2 anyfunc)
+          (func (export "f") (param i32) (result i32)
+            (i32.add (i32.load (local.get 0)) (i32.const 1))
+            (drop (i32.const 0))
+            (block (nop))
+          )
+          (export "mem" (memory 0))
+        */
+        let module = parse(from_hex(&[
+            "0061736d0100000001060160017f017f02150203656e760166000003656e76036d656d0201010303",
+            "020100040401700002070b0201660001036d656d02000a13011100200028020041016a41001a0240",
+            "010b0b",
+        ]))
+        .unwrap();
+        assert_eq!(
+            module.function_opcodes(1),
+            [0x20, 0x28, 0x41, 0x6a, 0x41, 0x1a, 0x02, 0x01, 0x0b, 0x0b]
+        );
+        assert!(module.function_opcodes(0).is_empty());
+
+        let stats = module.stats();
+        assert_eq!(
+            stats,
+            ModuleStats {
+                code_size: 17,
+                function_count: 1,
+                import_count: 2,
+                export_count: 2,
+                memory_limits: Some(Limits {
+                    min: 1,
+                    max: Some(3)
+                }),
+                table_limits: Some(Limits { min: 2, max: None }),
+                instructions: InstructionHistogram {
+                    control: 4,
+                    parametric: 1,
+                    variable: 1,
+                    memory: 1,
+                    numeric: 3,
+                },
+            }
+        );
+        assert_eq!(stats.instructions.get(InstructionClass::Numeric), 3);
+        assert_eq!(stats.instructions.total(), 10);
+
+        /* wat2wasm
+          (module)
+        */
+        let stats = parse(from_hex(&["0061736d01000000"])).unwrap().stats();
+        assert_eq!(stats.code_size, 0);
+        assert_eq!(stats.function_count, 0);
+        assert_eq!(stats.memory_limits, None);
+        assert_eq!(stats.table_limits, None);
+        assert_eq!(stats.instructions.total(), 0);
+    }
+
+    #[test]
+    fn module_json() {
+        /* wat2wasm
+          (func (import "env" "f") (param i32) (result i32))
+          (memory (import "env" "mem") 1 3)
+          (table 2 anyfunc)
+          (func (export "f") (param i32) (result i32)
+            (i32.add (i32.load (local.get 0)) (i32.const 1))
+            (drop (i32.const 0))
+            (block (nop))
+          )
+          (export "mem" (memory 0))
+        */
+        let wasm = from_hex(&[
+            "0061736d0100000001060160017f017f02150203656e760166000003656e76036d656d0201010303",
+            "020100040401700002070b0201660001036d656d02000a13011100200028020041016a41001a0240",
+            "010b0b",
+        ]);
+        assert_eq!(
+            super::module_json(&wasm).unwrap(),
+            concat!(
+                "{\"size\":83,\"sections\":[{\"id\":1,\"name\":\"type\",\"size\":6},",
+                "{\"id\":2,\"name\":\"import\",\"size\":21},{\"id\":3,\"name\":\"function\",\"size\":2},",
+                "{\"id\":4,\"name\":\"table\",\"size\":4},{\"id\":7,\"name\":\"export\",\"size\":11},",
+                "{\"id\":10,\"name\":\"code\",\"size\":19}],\"types\":[\"[i32] -> [i32]\"],",
+                "\"imports\":[{\"module\":\"env\",\"name\":\"f\",\"type\":\"func [i32] -> [i32]\"},",
+                "{\"module\":\"env\",\"name\":\"mem\",\"type\":\"memory {min 1, max 3}\"}],",
+                "\"exports\":[{\"name\":\"f\",\"type\":\"func [i32] -> [i32]\"},",
+                "{\"name\":\"mem\",\"type\":\"memory {min 1, max 3}\"}],",
+                "\"code_size\":17,\"function_count\":1,\"memory\":{\"min\":1,\"max\":3},",
+                "\"table\":{\"min\":2,\"max\":null},\"instructions\":{\"control\":4,",
+                "\"parametric\":1,\"variable\":1,\"memory\":1,\"numeric\":3}}"
+            )
+        );
+
+        /* wat2wasm
+          (module (@custom "test" ""))
+        */
+        assert_eq!(
+            super::module_json(&from_hex(&["0061736d0100000000050474657374"])).unwrap(),
+            concat!(
+                "{\"size\":15,\"sections\":[{\"id\":0,\"name\":\"test\",\"size\":5}],",
+                "\"types\":[],\"imports\":[],\"exports\":[],\"code_size\":0,\"function_count\":0,",
+                "\"memory\":null,\"table\":null,\"instructions\":{\"control\":0,\"parametric\":0,",
+                "\"variable\":0,\"memory\":0,\"numeric\":0}}"
+            )
+        );
+
+        assert!(super::module_json(&from_hex(&["0061736d01000000000102"])).is_err());
+    }
+}
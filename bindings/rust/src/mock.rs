@@ -0,0 +1,3035 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The C API of Fizzy implemented in Rust, replacing the bindings of the C++ library with
+//! the `mock` feature.
+//!
+//! Nothing is built from the C++ sources nor linked, so the crate and the crates depending on it
+//! can be tested without a C++ toolchain, and under Miri. The modules are decoded and executed by
+//! a simple interpreter of WebAssembly 1.0, which differs from the C++ library in:
+//! - the function bodies are not validated, only decoded,
+//! - the opcodes returned by `fizzy_get_function_opcodes`, and their indices reported to
+//!   the instruction hook, are the ones of the binary, not of the internal instructions,
+//! - the maximum stack height of the function metrics is 0,
+//! - the state hash is not SHA-256, it only tells the different states apart,
+//! - the memory backing and allocator options are ignored, the memory is always on the heap.
+//!
+//! The type definitions mirror the ones generated by bindgen from `fizzy.h`.
+
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(dead_code)]
+
+use crate::binary::{usize_from, Malformed, Reader, HEADER};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::Arc;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyModule {
+    _unused: [u8; 0],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyInstance {
+    _unused: [u8; 0],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyTable {
+    _unused: [u8; 0],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyFuncRef {
+    _unused: [u8; 0],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyMemory {
+    _unused: [u8; 0],
+}
+pub type FizzyValueType = u8;
+pub const FizzyValueTypeI32: FizzyValueType = 127;
+pub const FizzyValueTypeI64: FizzyValueType = 126;
+pub const FizzyValueTypeF32: FizzyValueType = 125;
+pub const FizzyValueTypeF64: FizzyValueType = 124;
+pub const FizzyValueTypeVoid: FizzyValueType = 0;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyFunctionType {
+    pub output: FizzyValueType,
+    pub inputs: *const FizzyValueType,
+    pub inputs_size: usize,
+}
+pub const FizzyExternalKindFunction: FizzyExternalKind = 0;
+pub const FizzyExternalKindTable: FizzyExternalKind = 1;
+pub const FizzyExternalKindMemory: FizzyExternalKind = 2;
+pub const FizzyExternalKindGlobal: FizzyExternalKind = 3;
+pub type FizzyExternalKind = u32;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyExportDescription {
+    pub name: *const c_char,
+    pub kind: FizzyExternalKind,
+    pub index: u32,
+}
+pub const FizzySuccess: FizzyErrorCode = 0;
+pub const FizzyErrorMalformedModule: FizzyErrorCode = 1;
+pub const FizzyErrorInvalidModule: FizzyErrorCode = 2;
+pub const FizzyErrorMemoryAllocationFailed: FizzyErrorCode = 3;
+pub const FizzyErrorOther: FizzyErrorCode = 4;
+pub const FizzyErrorStartFunctionTrapped: FizzyErrorCode = 5;
+pub type FizzyErrorCode = u32;
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FizzyError {
+    pub code: FizzyErrorCode,
+    pub message: [c_char; 256usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyParserLimits {
+    pub max_function_count: u32,
+    pub max_function_body_size: u32,
+    pub max_local_count: u32,
+    pub max_nesting_depth: u32,
+    pub max_allocation_size: u64,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyFeatures {
+    pub multi_memory: bool,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyLimits {
+    pub min: u32,
+    pub max: u32,
+    pub has_max: bool,
+}
+pub const FizzyMemoryBackingHeap: FizzyMemoryBacking = 0;
+pub const FizzyMemoryBackingMapped: FizzyMemoryBacking = 1;
+pub const FizzyMemoryBackingGuarded: FizzyMemoryBacking = 2;
+pub type FizzyMemoryBacking = u32;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyAllocator {
+    pub allocate: Option<unsafe extern "C" fn(context: *mut c_void, size: usize) -> *mut c_void>,
+    pub reallocate: Option<
+        unsafe extern "C" fn(
+            context: *mut c_void,
+            ptr: *mut c_void,
+            old_size: usize,
+            new_size: usize,
+        ) -> *mut c_void,
+    >,
+    pub deallocate:
+        Option<unsafe extern "C" fn(context: *mut c_void, ptr: *mut c_void, size: usize)>,
+    pub context: *mut c_void,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyInstantiateOptions {
+    pub memory_backing: FizzyMemoryBacking,
+    pub memory_allocator: *const FizzyAllocator,
+    pub defer_start: bool,
+    pub memory_pages_limit: u32,
+    pub call_stack_limit: u32,
+    pub disable_trap_stack_trace: bool,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyGlobalType {
+    pub value_type: FizzyValueType,
+    pub is_mutable: bool,
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FizzyImportDescription {
+    pub module: *const c_char,
+    pub name: *const c_char,
+    pub kind: FizzyExternalKind,
+    pub desc: FizzyImportDescription__bindgen_ty_1,
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union FizzyImportDescription__bindgen_ty_1 {
+    pub function_type: FizzyFunctionType,
+    pub table_limits: FizzyLimits,
+    pub memory_limits: FizzyLimits,
+    pub global_type: FizzyGlobalType,
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union FizzyValue {
+    pub i64: u64,
+    pub f32: f32,
+    pub f64: f64,
+    _bindgen_union_align: u64,
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FizzyExecutionResult {
+    pub trapped: bool,
+    pub has_value: bool,
+    pub value: FizzyValue,
+}
+pub const FizzyConstantExpressionKindConstant: FizzyConstantExpressionKind = 0;
+pub const FizzyConstantExpressionKindGlobalGet: FizzyConstantExpressionKind = 1;
+pub type FizzyConstantExpressionKind = u32;
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FizzyConstantExpression {
+    pub kind: FizzyConstantExpressionKind,
+    pub value: FizzyConstantExpression__bindgen_ty_1,
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union FizzyConstantExpression__bindgen_ty_1 {
+    pub constant: FizzyValue,
+    pub global_index: u32,
+    _bindgen_union_align: u64,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyFunctionMetrics {
+    pub body_size: u32,
+    pub local_count: u32,
+    pub max_stack_height: u32,
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FizzyGlobal {
+    pub type_: FizzyGlobalType,
+    pub initializer: FizzyConstantExpression,
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FizzyDataSegment {
+    pub memory_index: u32,
+    pub offset: FizzyConstantExpression,
+    pub data: *const u8,
+    pub size: usize,
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FizzyElementSegment {
+    pub table_index: u32,
+    pub offset: FizzyConstantExpression,
+    pub func_indices: *const u32,
+    pub func_indices_size: usize,
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FizzyExecutionState {
+    pub func_idx: u32,
+    pub instr_offset: u32,
+    pub opcode: u8,
+    pub locals: *const FizzyValue,
+    pub locals_size: usize,
+    pub stack: *const FizzyValue,
+    pub stack_size: usize,
+    pub depth: c_int,
+    pub memory_offset: u32,
+    pub memory_idx: u32,
+}
+pub type FizzyInstructionHook = Option<
+    unsafe extern "C" fn(
+        context: *mut c_void,
+        instance: *mut FizzyInstance,
+        state: *const FizzyExecutionState,
+    ) -> bool,
+>;
+pub type FizzyPeriodicHook =
+    Option<unsafe extern "C" fn(context: *mut c_void, instance: *mut FizzyInstance) -> bool>;
+pub type FizzyMemoryGrowHook = Option<
+    unsafe extern "C" fn(
+        context: *mut c_void,
+        instance: *mut FizzyInstance,
+        memory_idx: u32,
+        old_pages: u32,
+        new_pages: u32,
+    ),
+>;
+pub type FizzyMemoryGrowLimiter = Option<
+    unsafe extern "C" fn(
+        context: *mut c_void,
+        instance: *mut FizzyInstance,
+        memory_idx: u32,
+        current_pages: u32,
+        desired_pages: u32,
+        max_pages: u32,
+    ) -> bool,
+>;
+pub type FizzyExternalFn = Option<
+    unsafe extern "C" fn(
+        context: *mut c_void,
+        instance: *mut FizzyInstance,
+        args: *const FizzyValue,
+        args_size: usize,
+        depth: c_int,
+    ) -> FizzyExecutionResult,
+>;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyExternalFunction {
+    pub function: FizzyExternalFn,
+    pub context: *mut c_void,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyExternalTable {
+    pub table: *mut FizzyTable,
+    pub limits: FizzyLimits,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyExternalMemory {
+    pub memory: *mut FizzyMemory,
+    pub limits: FizzyLimits,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FizzyExternalGlobal {
+    pub value: *mut FizzyValue,
+    pub type_: FizzyGlobalType,
+}
+
+const PAGE_SIZE: usize = 65536;
+/// The maximum memory size in pages allowed by the validation.
+const MEMORY_PAGES_VALIDATION_LIMIT: u32 = 65536;
+/// The default hard limit of the memory size in pages.
+const DEFAULT_MEMORY_PAGES_LIMIT: u32 = 4096;
+/// The default and maximum limit of the call depth.
+const DEFAULT_CALL_STACK_LIMIT: u32 = 2048;
+
+/// The parser limits of the parsing functions without limits.
+const NO_PARSER_LIMITS: FizzyParserLimits = FizzyParserLimits {
+    max_function_count: u32::MAX,
+    max_function_body_size: u32::MAX,
+    max_local_count: u32::MAX,
+    max_nesting_depth: u32::MAX,
+    max_allocation_size: u64::MAX,
+};
+
+/// The proposals accepted by the parsing functions without features, i.e. only WebAssembly 1.0.
+const NO_FEATURES: FizzyFeatures = FizzyFeatures {
+    multi_memory: false,
+};
+
+/// The error of a failed operation, with the code and the message reported by the C API.
+struct Failure(FizzyErrorCode, String);
+
+fn malformed(message: &str) -> Failure {
+    Failure(FizzyErrorMalformedModule, message.to_string())
+}
+
+fn invalid(message: &str) -> Failure {
+    Failure(FizzyErrorInvalidModule, message.to_string())
+}
+
+fn instantiate_error(message: String) -> Failure {
+    Failure(FizzyErrorOther, message)
+}
+
+impl From<Malformed> for Failure {
+    fn from(_: Malformed) -> Self {
+        malformed("unexpected EOF")
+    }
+}
+
+/// The execution of a function trapped.
+struct Trap;
+
+#[derive(Clone, PartialEq)]
+struct FuncType {
+    inputs: Vec<FizzyValueType>,
+    output: FizzyValueType,
+}
+
+impl FuncType {
+    /// Returns the type of the C API, valid as long as this type.
+    fn to_sys(&self) -> FizzyFunctionType {
+        FizzyFunctionType {
+            output: self.output,
+            inputs: if self.inputs.is_empty() {
+                std::ptr::null()
+            } else {
+                self.inputs.as_ptr()
+            },
+            inputs_size: self.inputs.len(),
+        }
+    }
+
+    unsafe fn from_sys(func_type: &FizzyFunctionType) -> Self {
+        FuncType {
+            inputs: slice(func_type.inputs, func_type.inputs_size).to_vec(),
+            output: func_type.output,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Import {
+    module: CString,
+    name: CString,
+    desc: ImportDesc,
+}
+
+#[derive(Clone)]
+enum ImportDesc {
+    Function(FuncType),
+    Table(FizzyLimits),
+    Memory(FizzyLimits),
+    Global(FizzyGlobalType),
+}
+
+#[derive(Clone)]
+struct Code {
+    /// The size of the body, including the local declarations.
+    body_size: u32,
+    locals: Vec<FizzyValueType>,
+    /// The instructions, ending with the `end` of the function.
+    instructions: Vec<u8>,
+    /// The offsets of the `else` and `end` of the blocks, by the offset of their first instruction.
+    blocks: HashMap<usize, (Option<usize>, usize)>,
+    opcodes: Vec<u8>,
+    /// The offsets of the instructions, in the order of the opcodes. The hooks get the index of
+    /// the instruction, as the library has an array of instructions without the immediates.
+    instruction_offsets: Vec<usize>,
+    /// The indices of the functions called directly.
+    callees: Vec<u32>,
+}
+
+#[derive(Clone, Default)]
+struct Module {
+    types: Vec<FuncType>,
+    imports: Vec<Import>,
+    /// The type indices of all functions, the imported ones first.
+    functions: Vec<u32>,
+    imported_function_count: usize,
+    /// The limits of the defined or imported table.
+    table: Option<FizzyLimits>,
+    has_imported_table: bool,
+    /// The limits of all memories, the imported ones first.
+    memories: Vec<FizzyLimits>,
+    imported_memory_count: usize,
+    /// The types of all globals, the imported ones first.
+    global_types: Vec<FizzyGlobalType>,
+    imported_global_count: usize,
+    globals: Vec<FizzyGlobal>,
+    exports: Vec<(CString, FizzyExternalKind, u32)>,
+    start: Option<u32>,
+    elements: Vec<(FizzyConstantExpression, Vec<u32>)>,
+    /// The memory index, the offset and the contents of the data segments.
+    data: Vec<(u32, FizzyConstantExpression, Vec<u8>)>,
+    codes: Vec<Code>,
+    function_names: HashMap<u32, CString>,
+}
+
+impl Module {
+    fn function_type(&self, func_idx: u32) -> Option<&FuncType> {
+        let type_idx = *self.functions.get(func_idx as usize)?;
+        self.types.get(type_idx as usize)
+    }
+
+    fn find_export(&self, name: &CStr, kind: FizzyExternalKind) -> Option<u32> {
+        self.exports
+            .iter()
+            .find(|(export_name, export_kind, _)| {
+                *export_kind == kind && export_name.as_c_str() == name
+            })
+            .map(|&(_, _, index)| index)
+    }
+}
+
+fn read_u32(reader: &mut Reader) -> Result<u32, Failure> {
+    u32::try_from(reader.uleb()?).map_err(|_| malformed("integer representation too long"))
+}
+
+fn read_count(reader: &mut Reader) -> Result<usize, Failure> {
+    Ok(usize_from(reader.uleb()?)?)
+}
+
+fn read_name(reader: &mut Reader) -> Result<CString, Failure> {
+    let name = reader
+        .name()
+        .map_err(|_| malformed("invalid UTF-8 encoded string"))?;
+    CString::new(name).map_err(|_| malformed("name with a null character"))
+}
+
+fn read_value_type(reader: &mut Reader) -> Result<FizzyValueType, Failure> {
+    match reader.u8()? {
+        value_type @ FizzyValueTypeF64..=FizzyValueTypeI32 => Ok(value_type),
+        _ => Err(malformed("invalid valtype")),
+    }
+}
+
+fn read_limits(reader: &mut Reader) -> Result<FizzyLimits, Failure> {
+    let has_max = match reader.u8()? {
+        0 => false,
+        1 => true,
+        _ => return Err(malformed("invalid limits")),
+    };
+    let min = read_u32(reader)?;
+    let max = if has_max { read_u32(reader)? } else { 0 };
+    if has_max && max < min {
+        return Err(invalid("malformed limits (minimum is larger than maximum)"));
+    }
+    Ok(FizzyLimits { min, max, has_max })
+}
+
+fn read_memory_limits(reader: &mut Reader) -> Result<FizzyLimits, Failure> {
+    let limits = read_limits(reader)?;
+    if limits.min > MEMORY_PAGES_VALIDATION_LIMIT
+        || (limits.has_max && limits.max > MEMORY_PAGES_VALIDATION_LIMIT)
+    {
+        return Err(invalid("maximum memory page limit exceeded"));
+    }
+    Ok(limits)
+}
+
+fn read_table_limits(reader: &mut Reader) -> Result<FizzyLimits, Failure> {
+    if reader.u8()? != 0x70 {
+        return Err(malformed("unexpected table elemtype"));
+    }
+    read_limits(reader)
+}
+
+fn read_global_type(reader: &mut Reader) -> Result<FizzyGlobalType, Failure> {
+    let value_type = read_value_type(reader)?;
+    let is_mutable = match reader.u8()? {
+        0 => false,
+        1 => true,
+        _ => {
+            return Err(malformed(
+                "unexpected byte value, expected 0x00 or 0x01 for global mutability",
+            ))
+        }
+    };
+    Ok(FizzyGlobalType {
+        value_type,
+        is_mutable,
+    })
+}
+
+fn constant(bits: u64) -> FizzyConstantExpression {
+    FizzyConstantExpression {
+        kind: FizzyConstantExpressionKindConstant,
+        value: FizzyConstantExpression__bindgen_ty_1 {
+            constant: FizzyValue { i64: bits },
+        },
+    }
+}
+
+fn read_constant_expression(reader: &mut Reader) -> Result<FizzyConstantExpression, Failure> {
+    let expression = match reader.u8()? {
+        0x41 => constant(u64::from(reader.sleb()? as u32)),
+        0x42 => constant(reader.sleb()? as u64),
+        0x43 => constant(u64::from(reader.u32()?)),
+        0x44 => constant(reader.u64()?),
+        0x23 => FizzyConstantExpression {
+            kind: FizzyConstantExpressionKindGlobalGet,
+            value: FizzyConstantExpression__bindgen_ty_1 {
+                global_index: read_u32(reader)?,
+            },
+        },
+        _ => return Err(invalid("constant expression required")),
+    };
+    if reader.u8()? != 0x0b {
+        return Err(invalid("constant expression has multiple instructions"));
+    }
+    Ok(expression)
+}
+
+/// Skip the immediate values of the instruction, other than a block instruction.
+fn skip_immediates(
+    opcode: u8,
+    reader: &mut Reader,
+    features: &FizzyFeatures,
+) -> Result<(), Failure> {
+    match opcode {
+        0x00 | 0x01 | 0x0f | 0x1a | 0x1b | 0x45..=0xbf => {}
+        0x0c | 0x0d | 0x10 | 0x20..=0x24 => {
+            reader.uleb()?;
+        }
+        0x0e => {
+            for _ in 0..=reader.uleb()? {
+                reader.uleb()?;
+            }
+        }
+        0x11 => {
+            reader.uleb()?;
+            reader.u8()?;
+        }
+        0x28..=0x3e => {
+            // The bit 6 of the alignment flags the memory index of the multi-memory proposal.
+            if reader.uleb()? & 0x40 != 0 {
+                if !features.multi_memory {
+                    return Err(invalid("alignment cannot exceed operand size"));
+                }
+                reader.uleb()?;
+            }
+            reader.uleb()?;
+        }
+        0x3f | 0x40 => {
+            if features.multi_memory {
+                reader.uleb()?;
+            } else if reader.u8()? != 0 {
+                return Err(malformed("invalid memory index encountered"));
+            }
+        }
+        0x41 | 0x42 => {
+            reader.sleb()?;
+        }
+        0x43 => {
+            reader.u32()?;
+        }
+        0x44 => {
+            reader.u64()?;
+        }
+        _ => return Err(malformed("invalid instruction")),
+    }
+    Ok(())
+}
+
+fn read_code(
+    body: &[u8],
+    limits: &FizzyParserLimits,
+    features: &FizzyFeatures,
+) -> Result<Code, Failure> {
+    if body.len() > limits.max_function_body_size as usize {
+        return Err(malformed("function body size limit exceeded"));
+    }
+    let mut reader = Reader::new(body);
+    let mut local_declarations = Vec::new();
+    let mut local_count = 0u64;
+    for _ in 0..reader.uleb()? {
+        let count = reader.uleb()?;
+        local_declarations.push((count, read_value_type(&mut reader)?));
+        local_count += count;
+        if local_count > u64::from(u32::MAX) {
+            return Err(malformed("too many local variables"));
+        }
+    }
+    if local_count > u64::from(limits.max_local_count) {
+        return Err(malformed("local count limit exceeded"));
+    }
+    let mut locals = Vec::new();
+    for (count, value_type) in local_declarations {
+        locals.resize(locals.len() + count as usize, value_type);
+    }
+
+    let instructions = reader.rest().to_vec();
+    let mut reader = Reader::new(&instructions);
+    let mut blocks = HashMap::new();
+    let mut opcodes = Vec::new();
+    let mut instruction_offsets = Vec::new();
+    let mut callees = Vec::new();
+    // The offsets of the open blocks and of their else.
+    let mut open_blocks: Vec<(usize, Option<usize>)> = Vec::new();
+    loop {
+        let offset = reader.position();
+        let opcode = reader.u8()?;
+        opcodes.push(opcode);
+        instruction_offsets.push(offset);
+        match opcode {
+            0x02..=0x04 => {
+                reader.u8()?;
+                open_blocks.push((offset, None));
+                if open_blocks.len() > limits.max_nesting_depth as usize {
+                    return Err(malformed("nesting depth limit exceeded"));
+                }
+            }
+            0x05 => match open_blocks.last_mut() {
+                Some((_, else_offset @ None)) => *else_offset = Some(offset),
+                _ => return Err(malformed("unexpected else instruction")),
+            },
+            0x0b => match open_blocks.pop() {
+                Some((start, else_offset)) => {
+                    blocks.insert(start, (else_offset, offset));
+                }
+                None => break,
+            },
+            0x10 => callees.push(read_u32(&mut reader)?),
+            _ => skip_immediates(opcode, &mut reader, features)?,
+        }
+    }
+    if !reader.is_empty() {
+        return Err(malformed("malformed size field for function"));
+    }
+    Ok(Code {
+        body_size: body.len() as u32,
+        locals,
+        instructions,
+        blocks,
+        opcodes,
+        instruction_offsets,
+        callees,
+    })
+}
+
+/// Read the function names of the name section, ignoring a malformed section.
+fn read_function_names(reader: &mut Reader) -> HashMap<u32, CString> {
+    let mut names = HashMap::new();
+    let _ = (|| -> Result<(), Failure> {
+        while !reader.is_empty() {
+            let id = reader.u8()?;
+            let size = read_count(reader)?;
+            let mut subsection = Reader::new(reader.bytes(size)?);
+            if id == 1 {
+                for _ in 0..subsection.uleb()? {
+                    let func_idx = read_u32(&mut subsection)?;
+                    names.insert(func_idx, read_name(&mut subsection)?);
+                }
+            }
+        }
+        Ok(())
+    })();
+    names
+}
+
+fn parse(
+    wasm: &[u8],
+    limits: &FizzyParserLimits,
+    features: &FizzyFeatures,
+) -> Result<Module, Failure> {
+    let mut reader = Reader::new(wasm);
+    if reader.bytes(HEADER.len()).ok() != Some(HEADER) {
+        return Err(malformed("invalid wasm module prefix"));
+    }
+
+    let mut module = Module::default();
+    let mut defined_functions = Vec::new();
+    while !reader.is_empty() {
+        let id = reader.u8()?;
+        let size = read_count(&mut reader)?;
+        let mut section = Reader::new(reader.bytes(size)?);
+        match id {
+            0 => {
+                if section.name()? == "name" {
+                    module.function_names = read_function_names(&mut section);
+                }
+                section.rest();
+            }
+            1 => {
+                for _ in 0..section.uleb()? {
+                    if section.u8()? != 0x60 {
+                        return Err(malformed(
+                            "unexpected byte value, expected 0x60 for functype",
+                        ));
+                    }
+                    let mut inputs = Vec::new();
+                    for _ in 0..section.uleb()? {
+                        inputs.push(read_value_type(&mut section)?);
+                    }
+                    let output = match section.uleb()? {
+                        0 => FizzyValueTypeVoid,
+                        1 => read_value_type(&mut section)?,
+                        _ => return Err(invalid("function has more than one result")),
+                    };
+                    module.types.push(FuncType { inputs, output });
+                }
+            }
+            2 => {
+                for _ in 0..section.uleb()? {
+                    let import_module = read_name(&mut section)?;
+                    let name = read_name(&mut section)?;
+                    let desc = match section.u8()? {
+                        0 => {
+                            let type_idx = read_u32(&mut section)?;
+                            let func_type =
+                                module.types.get(type_idx as usize).ok_or_else(|| {
+                                    invalid("invalid type index of an imported function")
+                                })?;
+                            module.functions.push(type_idx);
+                            module.imported_function_count += 1;
+                            ImportDesc::Function(func_type.clone())
+                        }
+                        1 => {
+                            let limits = read_table_limits(&mut section)?;
+                            if module.table.replace(limits).is_some() {
+                                return Err(invalid("too many imported tables"));
+                            }
+                            module.has_imported_table = true;
+                            ImportDesc::Table(limits)
+                        }
+                        2 => {
+                            let limits = read_memory_limits(&mut section)?;
+                            module.memories.push(limits);
+                            module.imported_memory_count += 1;
+                            ImportDesc::Memory(limits)
+                        }
+                        3 => {
+                            let global_type = read_global_type(&mut section)?;
+                            module.global_types.push(global_type);
+                            module.imported_global_count += 1;
+                            ImportDesc::Global(global_type)
+                        }
+                        _ => return Err(malformed("unexpected import kind value")),
+                    };
+                    module.imports.push(Import {
+                        module: import_module,
+                        name,
+                        desc,
+                    });
+                }
+            }
+            3 => {
+                for _ in 0..section.uleb()? {
+                    let type_idx = read_u32(&mut section)?;
+                    if type_idx as usize >= module.types.len() {
+                        return Err(invalid("invalid function type index"));
+                    }
+                    defined_functions.push(type_idx);
+                }
+            }
+            4 => {
+                for _ in 0..section.uleb()? {
+                    if module
+                        .table
+                        .replace(read_table_limits(&mut section)?)
+                        .is_some()
+                    {
+                        return Err(invalid("too many table sections (at most one is allowed)"));
+                    }
+                }
+            }
+            5 => {
+                for _ in 0..section.uleb()? {
+                    module.memories.push(read_memory_limits(&mut section)?);
+                }
+            }
+            6 => {
+                for _ in 0..section.uleb()? {
+                    let type_ = read_global_type(&mut section)?;
+                    let initializer = read_constant_expression(&mut section)?;
+                    module.global_types.push(type_);
+                    module.globals.push(FizzyGlobal { type_, initializer });
+                }
+            }
+            7 => {
+                for _ in 0..section.uleb()? {
+                    let name = read_name(&mut section)?;
+                    let kind = match section.u8()? {
+                        kind @ 0..=3 => FizzyExternalKind::from(kind),
+                        _ => return Err(malformed("unexpected export kind value")),
+                    };
+                    let index = read_u32(&mut section)?;
+                    module.exports.push((name, kind, index));
+                }
+            }
+            8 => module.start = Some(read_u32(&mut section)?),
+            9 => {
+                for _ in 0..section.uleb()? {
+                    if section.uleb()? != 0 {
+                        return Err(invalid("invalid table index in element segment"));
+                    }
+                    let offset = read_constant_expression(&mut section)?;
+                    let mut func_indices = Vec::new();
+                    for _ in 0..section.uleb()? {
+                        func_indices.push(read_u32(&mut section)?);
+                    }
+                    module.elements.push((offset, func_indices));
+                }
+            }
+            10 => {
+                for _ in 0..section.uleb()? {
+                    let size = read_count(&mut section)?;
+                    let code = read_code(section.bytes(size)?, limits, features)?;
+                    module.codes.push(code);
+                }
+            }
+            11 => {
+                for _ in 0..section.uleb()? {
+                    // With multi-memory the flags 2 are followed by the index of the memory.
+                    let memory_idx = match section.uleb()? {
+                        0 => 0,
+                        2 if features.multi_memory => read_u32(&mut section)?,
+                        flags => {
+                            return Err(malformed(&format!("unexpected memidx value {}", flags)))
+                        }
+                    };
+                    if memory_idx as usize >= module.memories.len() {
+                        return Err(invalid("invalid memory index in data segment"));
+                    }
+                    let offset = read_constant_expression(&mut section)?;
+                    let size = read_count(&mut section)?;
+                    module
+                        .data
+                        .push((memory_idx, offset, section.bytes(size)?.to_vec()));
+                }
+            }
+            _ => return Err(malformed("unknown section encountered")),
+        }
+        if !section.is_empty() {
+            return Err(malformed("incorrect section size"));
+        }
+        if module.imported_function_count + defined_functions.len()
+            > limits.max_function_count as usize
+        {
+            return Err(malformed("function count limit exceeded"));
+        }
+        if allocation_size(&module) + defined_functions.len() as u64 * 4
+            > limits.max_allocation_size
+        {
+            return Err(malformed("allocation size limit exceeded"));
+        }
+    }
+
+    if !features.multi_memory {
+        let defined_memory_count = module.memories.len() - module.imported_memory_count;
+        if defined_memory_count > 1 {
+            return Err(invalid("too many memory sections (at most one is allowed)"));
+        }
+        if module.imported_memory_count > 1 {
+            return Err(invalid(
+                "too many imported memories (at most one is allowed)",
+            ));
+        }
+        if defined_memory_count != 0 && module.imported_memory_count != 0 {
+            return Err(invalid(
+                "both module memory and imported memory are defined (at most one of them is allowed)",
+            ));
+        }
+    }
+
+    if defined_functions.len() != module.codes.len() {
+        return Err(malformed(
+            "malformed binary: number of function and code entries must match",
+        ));
+    }
+    module.functions.extend(defined_functions);
+    Ok(module)
+}
+
+/// Returns the approximate size of the memory allocated for the module.
+fn allocation_size(module: &Module) -> u64 {
+    use std::mem::size_of;
+    let types: usize = module
+        .types
+        .iter()
+        .map(|func_type| size_of::<FuncType>() + func_type.inputs.len())
+        .sum();
+    let imports: usize = module
+        .imports
+        .iter()
+        .map(|import| {
+            size_of::<Import>() + import.module.as_bytes().len() + import.name.as_bytes().len()
+        })
+        .sum();
+    let exports: usize = module
+        .exports
+        .iter()
+        .map(|(name, _, _)| size_of::<(CString, FizzyExternalKind, u32)>() + name.as_bytes().len())
+        .sum();
+    let elements: usize = module
+        .elements
+        .iter()
+        .map(|(_, func_indices)| {
+            size_of::<(FizzyConstantExpression, Vec<u32>)>() + func_indices.len() * 4
+        })
+        .sum();
+    let data: usize = module
+        .data
+        .iter()
+        .map(|(_, _, data)| size_of::<(u32, FizzyConstantExpression, Vec<u8>)>() + data.len())
+        .sum();
+    let codes: usize = module
+        .codes
+        .iter()
+        .map(|code| {
+            size_of::<Code>()
+                + code.instructions.len()
+                + code.opcodes.len()
+                + code.callees.len() * 4
+        })
+        .sum();
+    (types
+        + imports
+        + exports
+        + elements
+        + data
+        + codes
+        + module.functions.len() * 4
+        + module.globals.len() * size_of::<FizzyGlobal>()) as u64
+}
+
+struct Memory {
+    data: Vec<u8>,
+    /// The read-only ranges, as the offset and the size.
+    read_only_ranges: Vec<(u32, u32)>,
+}
+
+struct Table {
+    elements: Vec<Option<FuncRef>>,
+}
+
+#[derive(Clone)]
+struct FuncRef {
+    func_type: FuncType,
+    kind: FuncRefKind,
+}
+
+#[derive(Clone, Copy)]
+enum FuncRefKind {
+    Host(FizzyExternalFunction),
+    /// A function of an instance, by the function index.
+    Wasm(*mut Instance, u32),
+}
+
+/// A memory of an instance, either imported or owned.
+struct InstanceMemory {
+    memory: *mut Memory,
+    limits: FizzyLimits,
+    owned: bool,
+}
+
+struct Instance {
+    /// The module, shared with the duplicates of the instance.
+    module: Arc<Module>,
+    imported_functions: Vec<FizzyExternalFunction>,
+    table: *mut Table,
+    owns_table: bool,
+    /// All memories, the imported ones first.
+    memories: Vec<InstanceMemory>,
+    memory_pages_limit: u32,
+    /// The values of all globals, the ones of the defined globals are owned.
+    globals: Vec<*mut FizzyValue>,
+    call_stack_limit: u32,
+    start_pending: Cell<bool>,
+    /// The indices of the functions which have trapped, the innermost first.
+    trap_stack_trace: RefCell<Vec<u32>>,
+    trap_stack_trace_enabled: bool,
+    instruction_hook: Cell<(FizzyInstructionHook, *mut c_void)>,
+    periodic_hook: Cell<(FizzyPeriodicHook, *mut c_void, u64)>,
+    periodic_hook_countdown: Cell<u64>,
+    memory_grow_hook: Cell<(FizzyMemoryGrowHook, *mut c_void)>,
+    memory_grow_limiter: Cell<(FizzyMemoryGrowLimiter, *mut c_void)>,
+}
+
+impl Instance {
+    /// Returns the memory of the index, null if there is no such memory.
+    fn memory(&self, memory_idx: u32) -> *mut Memory {
+        self.memories
+            .get(memory_idx as usize)
+            .map_or(std::ptr::null_mut(), |memory| memory.memory)
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        unsafe {
+            for &global in self.globals.iter().skip(self.module.imported_global_count) {
+                drop(Box::from_raw(global));
+            }
+            for memory in self.memories.iter().filter(|memory| memory.owned) {
+                drop(Box::from_raw(memory.memory));
+            }
+            if self.owns_table && !self.table.is_null() {
+                drop(Box::from_raw(self.table));
+            }
+        }
+    }
+}
+
+/// Returns the bits of the value of the type, without the bits above the type width.
+fn value_bits(value_type: FizzyValueType, value: u64) -> u64 {
+    match value_type {
+        FizzyValueTypeI32 | FizzyValueTypeF32 => value & 0xffff_ffff,
+        _ => value,
+    }
+}
+
+unsafe fn slice<'a, T>(ptr: *const T, size: usize) -> &'a [T] {
+    if size == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, size)
+    }
+}
+
+unsafe fn constant_value(
+    expression: &FizzyConstantExpression,
+    globals: &[*mut FizzyValue],
+) -> Result<u64, Failure> {
+    match expression.kind {
+        FizzyConstantExpressionKindGlobalGet => {
+            let global = globals
+                .get(expression.value.global_index as usize)
+                .ok_or_else(|| invalid("invalid global index in constant expression"))?;
+            Ok((**global).i64)
+        }
+        _ => Ok(expression.value.constant.i64),
+    }
+}
+
+unsafe fn instantiate(
+    module: Box<Module>,
+    imported_functions: &[FizzyExternalFunction],
+    imported_table: Option<&FizzyExternalTable>,
+    imported_memories: &[FizzyExternalMemory],
+    imported_globals: &[FizzyExternalGlobal],
+    options: Option<&FizzyInstantiateOptions>,
+) -> Result<*mut Instance, Failure> {
+    let memory_pages_limit = options
+        .map(|options| options.memory_pages_limit)
+        .filter(|&limit| limit != 0)
+        .unwrap_or(DEFAULT_MEMORY_PAGES_LIMIT);
+    let call_stack_limit = options
+        .map(|options| options.call_stack_limit)
+        .filter(|&limit| limit != 0)
+        .map_or(DEFAULT_CALL_STACK_LIMIT, |limit| {
+            limit.min(DEFAULT_CALL_STACK_LIMIT)
+        });
+    let instance = Box::into_raw(Box::new(Instance {
+        module: Arc::from(module),
+        imported_functions: imported_functions.to_vec(),
+        table: std::ptr::null_mut(),
+        owns_table: false,
+        memories: Vec::new(),
+        memory_pages_limit,
+        globals: Vec::new(),
+        call_stack_limit,
+        start_pending: Cell::new(true),
+        trap_stack_trace: RefCell::new(Vec::new()),
+        trap_stack_trace_enabled: !matches!(options, Some(options) if options.disable_trap_stack_trace),
+        instruction_hook: Cell::new((None, std::ptr::null_mut())),
+        periodic_hook: Cell::new((None, std::ptr::null_mut(), 0)),
+        periodic_hook_countdown: Cell::new(0),
+        memory_grow_hook: Cell::new((None, std::ptr::null_mut())),
+        memory_grow_limiter: Cell::new((None, std::ptr::null_mut())),
+    }));
+    match initialize(
+        instance,
+        imported_table,
+        imported_memories,
+        imported_globals,
+        options,
+    ) {
+        Ok(()) => Ok(instance),
+        Err(failure) => {
+            drop(Box::from_raw(instance));
+            Err(failure)
+        }
+    }
+}
+
+unsafe fn initialize(
+    instance: *mut Instance,
+    imported_table: Option<&FizzyExternalTable>,
+    imported_memories: &[FizzyExternalMemory],
+    imported_globals: &[FizzyExternalGlobal],
+    options: Option<&FizzyInstantiateOptions>,
+) -> Result<(), Failure> {
+    let module = &(*instance).module;
+    let imported_function_count = (*instance).imported_functions.len();
+    if imported_function_count != module.imported_function_count {
+        return Err(instantiate_error(format!(
+            "module requires {} imported functions, {} provided",
+            module.imported_function_count, imported_function_count
+        )));
+    }
+
+    match (module.has_imported_table, imported_table) {
+        (true, Some(table)) => {
+            if table.table.is_null() {
+                return Err(instantiate_error(
+                    "provided imported table has a null pointer to data".to_string(),
+                ));
+            }
+            (*instance).table = table.table as *mut Table;
+        }
+        (true, None) => {
+            return Err(instantiate_error(
+                "module defines an imported table but none was provided".to_string(),
+            ))
+        }
+        (false, Some(_)) => {
+            return Err(instantiate_error(
+                "trying to provide imported table to a module that doesn't define one".to_string(),
+            ))
+        }
+        (false, None) => {
+            if let Some(limits) = module.table {
+                (*instance).table = Box::into_raw(Box::new(Table {
+                    elements: vec![None; limits.min as usize],
+                }));
+                (*instance).owns_table = true;
+            }
+        }
+    }
+
+    match (module.imported_memory_count, imported_memories.len()) {
+        (0, 0) => {}
+        (0, _) => {
+            return Err(instantiate_error(
+                "trying to provide imported memory to a module that doesn't define one".to_string(),
+            ))
+        }
+        (_, 0) => {
+            return Err(instantiate_error(
+                "module defines an imported memory but none was provided".to_string(),
+            ))
+        }
+        (required, provided) if required != provided => {
+            return Err(instantiate_error(format!(
+                "module requires {} imported memories, {} provided",
+                required, provided
+            )))
+        }
+        _ => {}
+    }
+    for memory in imported_memories {
+        if memory.memory.is_null() {
+            return Err(instantiate_error(
+                "provided imported memory has a null pointer to data".to_string(),
+            ));
+        }
+        (*instance).memories.push(InstanceMemory {
+            memory: memory.memory as *mut Memory,
+            limits: memory.limits,
+            owned: false,
+        });
+    }
+    let pages_limit = (*instance).memory_pages_limit;
+    for &limits in &module.memories[module.imported_memory_count..] {
+        if limits.min > pages_limit || (limits.has_max && limits.max > pages_limit) {
+            return Err(instantiate_error(format!(
+                "cannot exceed hard memory limit of {} bytes",
+                pages_limit as usize * PAGE_SIZE
+            )));
+        }
+        (*instance).memories.push(InstanceMemory {
+            memory: Box::into_raw(Box::new(Memory {
+                data: vec![0; limits.min as usize * PAGE_SIZE],
+                read_only_ranges: Vec::new(),
+            })),
+            limits,
+            owned: true,
+        });
+    }
+
+    if imported_globals.len() != module.imported_global_count {
+        return Err(instantiate_error(format!(
+            "module requires {} imported globals, {} provided",
+            module.imported_global_count,
+            imported_globals.len()
+        )));
+    }
+    for (i, (global, global_type)) in imported_globals
+        .iter()
+        .zip(&module.global_types)
+        .enumerate()
+    {
+        if global.type_.value_type != global_type.value_type {
+            return Err(instantiate_error(format!(
+                "global {} value type doesn't match module's global type",
+                i
+            )));
+        }
+        if global.type_.is_mutable != global_type.is_mutable {
+            return Err(instantiate_error(format!(
+                "global {} mutability doesn't match module's global mutability",
+                i
+            )));
+        }
+        if global.value.is_null() {
+            return Err(instantiate_error(format!(
+                "global {} has a null pointer to value",
+                i
+            )));
+        }
+        (*instance).globals.push(global.value);
+    }
+    for global in &module.globals {
+        let value = constant_value(&global.initializer, &(*instance).globals)?;
+        (*instance)
+            .globals
+            .push(Box::into_raw(Box::new(FizzyValue { i64: value })));
+    }
+
+    let mut element_offsets = Vec::new();
+    for (offset, func_indices) in &module.elements {
+        let offset = constant_value(offset, &(*instance).globals)? as u32 as usize;
+        let table_size = (*instance)
+            .table
+            .as_ref()
+            .map_or(0, |table| table.elements.len());
+        if offset + func_indices.len() > table_size {
+            return Err(instantiate_error(
+                "element segment is out of table bounds".to_string(),
+            ));
+        }
+        element_offsets.push(offset);
+    }
+    let mut data_offsets = Vec::new();
+    for (memory_idx, offset, data) in &module.data {
+        let offset = constant_value(offset, &(*instance).globals)? as u32 as usize;
+        let memory_size = (*instance)
+            .memory(*memory_idx)
+            .as_ref()
+            .map_or(0, |memory| memory.data.len());
+        if offset + data.len() > memory_size {
+            return Err(instantiate_error(
+                "data segment is out of memory bounds".to_string(),
+            ));
+        }
+        data_offsets.push(offset);
+    }
+    for ((_, func_indices), offset) in module.elements.iter().zip(element_offsets) {
+        for (i, &func_idx) in func_indices.iter().enumerate() {
+            let func_type = module
+                .function_type(func_idx)
+                .ok_or_else(|| invalid("invalid function index in element segment"))?;
+            (&mut (*(*instance).table).elements)[offset + i] = Some(FuncRef {
+                func_type: func_type.clone(),
+                kind: FuncRefKind::Wasm(instance, func_idx),
+            });
+        }
+    }
+    for ((memory_idx, _, data), offset) in module.data.iter().zip(data_offsets) {
+        let memory = &mut *(*instance).memory(*memory_idx);
+        memory.data[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    if !matches!(options, Some(options) if options.defer_start) && run_start(instance).is_err() {
+        return Err(Failure(
+            FizzyErrorStartFunctionTrapped,
+            "start function failed to execute".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+unsafe fn run_start(instance: *mut Instance) -> Result<Option<u64>, Trap> {
+    match (*instance).module.as_ref().start {
+        Some(start) if (*instance).start_pending.replace(false) => execute(instance, start, &[], 0),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the number of values returned by a block of the block type.
+fn block_arity(block_type: u8) -> usize {
+    if block_type == 0x40 {
+        0
+    } else {
+        1
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Label {
+    /// The offset of the instruction to continue at after a branch to the label.
+    target: usize,
+    /// The number of values passed by a branch to the label.
+    arity: usize,
+    /// The height of the operand stack at the entry of the block.
+    height: usize,
+}
+
+/// Branch to the label of the depth, returning the offset of the instruction to continue at.
+fn branch(labels: &mut Vec<Label>, stack: &mut Vec<u64>, depth: u64) -> Result<usize, Trap> {
+    let index = usize_from(depth)
+        .ok()
+        .and_then(|depth| labels.len().checked_sub(depth + 1))
+        .ok_or(Trap)?;
+    let label = labels[index];
+    let results = stack.len().checked_sub(label.arity).ok_or(Trap)?;
+    if results < label.height {
+        return Err(Trap);
+    }
+    stack.drain(label.height..results);
+    labels.truncate(index);
+    Ok(label.target)
+}
+
+fn pop(stack: &mut Vec<u64>) -> Result<u64, Trap> {
+    stack.pop().ok_or(Trap)
+}
+
+fn pop_args(stack: &mut Vec<u64>, count: usize) -> Result<Vec<u64>, Trap> {
+    let start = stack.len().checked_sub(count).ok_or(Trap)?;
+    Ok(stack.split_off(start))
+}
+
+fn read_uleb(instructions: &[u8], pc: &mut usize) -> Result<u64, Trap> {
+    let mut reader = Reader::new(&instructions[*pc..]);
+    let value = reader.uleb().map_err(|_| Trap)?;
+    *pc += reader.position();
+    Ok(value)
+}
+
+fn read_sleb(instructions: &[u8], pc: &mut usize) -> Result<i64, Trap> {
+    let mut reader = Reader::new(&instructions[*pc..]);
+    let value = reader.sleb().map_err(|_| Trap)?;
+    *pc += reader.position();
+    Ok(value)
+}
+
+fn read_fixed(instructions: &[u8], pc: &mut usize, size: usize) -> Result<u64, Trap> {
+    let bytes = instructions.get(*pc..*pc + size).ok_or(Trap)?;
+    let mut value = [0; 8];
+    value[..size].copy_from_slice(bytes);
+    *pc += size;
+    Ok(u64::from_le_bytes(value))
+}
+
+unsafe fn call_host(
+    function: &FizzyExternalFunction,
+    instance: *mut Instance,
+    args: &[u64],
+    depth: c_int,
+    output: FizzyValueType,
+) -> Result<Option<u64>, Trap> {
+    let callback = function.function.ok_or(Trap)?;
+    let result = callback(
+        function.context,
+        instance as *mut FizzyInstance,
+        args.as_ptr() as *const FizzyValue,
+        args.len(),
+        depth,
+    );
+    if result.trapped {
+        Err(Trap)
+    } else if output == FizzyValueTypeVoid {
+        Ok(None)
+    } else {
+        Ok(Some(value_bits(output, result.value.i64)))
+    }
+}
+
+unsafe fn call_funcref(
+    funcref: &FuncRef,
+    instance: *mut Instance,
+    args: &[u64],
+    depth: c_int,
+) -> Result<Option<u64>, Trap> {
+    match funcref.kind {
+        FuncRefKind::Host(function) => {
+            if depth > (*instance).call_stack_limit as c_int {
+                return Err(Trap);
+            }
+            call_host(&function, instance, args, depth, funcref.func_type.output)
+        }
+        FuncRefKind::Wasm(instance, func_idx) => execute(instance, func_idx, args, depth),
+    }
+}
+
+/// Call the instruction hook and the periodic hook before executing the instruction.
+unsafe fn run_hooks(
+    instance: *mut Instance,
+    state: impl FnOnce() -> FizzyExecutionState,
+) -> Result<(), Trap> {
+    if let (Some(hook), context) = (*instance).instruction_hook.get() {
+        if !hook(context, instance as *mut FizzyInstance, &state()) {
+            return Err(Trap);
+        }
+    }
+    if let (Some(hook), context, interval) = (*instance).periodic_hook.get() {
+        let countdown = (*instance).periodic_hook_countdown.get().saturating_sub(1);
+        if countdown == 0 {
+            (*instance).periodic_hook_countdown.set(interval);
+            if !hook(context, instance as *mut FizzyInstance) {
+                return Err(Trap);
+            }
+        } else {
+            (*instance).periodic_hook_countdown.set(countdown);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the immediates of a memory access, returning the memory index and the offset. The memory
+/// index follows the alignment if its bit 6 is set, as in the multi-memory proposal.
+fn read_memarg(instructions: &[u8], pc: &mut usize) -> Result<(u32, u64), Trap> {
+    let align = read_uleb(instructions, pc)?;
+    let memory_idx = if align & 0x40 != 0 {
+        read_uleb(instructions, pc)? as u32
+    } else {
+        0
+    };
+    let offset = read_uleb(instructions, pc)?;
+    Ok((memory_idx, offset))
+}
+
+unsafe fn memory_address(memory: *mut Memory, address: u64, size: usize) -> Result<usize, Trap> {
+    let memory = memory.as_ref().ok_or(Trap)?;
+    let address = usize_from(address).map_err(|_| Trap)?;
+    if address.checked_add(size).ok_or(Trap)? > memory.data.len() {
+        return Err(Trap);
+    }
+    Ok(address)
+}
+
+unsafe fn load(memory: *mut Memory, address: u64, size: usize) -> Result<u64, Trap> {
+    let address = memory_address(memory, address, size)?;
+    let mut value = [0; 8];
+    value[..size].copy_from_slice(&(&(*memory).data)[address..address + size]);
+    Ok(u64::from_le_bytes(value))
+}
+
+unsafe fn store(memory: *mut Memory, address: u64, size: usize, value: u64) -> Result<(), Trap> {
+    let address = memory_address(memory, address, size)?;
+    let memory = &mut *memory;
+    if memory.read_only_ranges.iter().any(|&(offset, range_size)| {
+        address < offset as usize + range_size as usize && (offset as usize) < address + size
+    }) {
+        return Err(Trap);
+    }
+    memory.data[address..address + size].copy_from_slice(&value.to_le_bytes()[..size]);
+    Ok(())
+}
+
+/// Grow the memory by the number of pages, returning the previous size in pages,
+/// or None if the memory cannot be grown.
+unsafe fn grow_memory(instance: *mut Instance, memory_idx: u32, delta: u32) -> Option<u32> {
+    let memories = &(*instance).memories;
+    let instance_memory = memories.get(memory_idx as usize)?;
+    let memory = instance_memory.memory;
+    let limits = instance_memory.limits;
+    let max_pages = if limits.has_max {
+        limits.max
+    } else {
+        MEMORY_PAGES_VALIDATION_LIMIT
+    }
+    .min((*instance).memory_pages_limit);
+    let current_pages = ((*memory).data.len() / PAGE_SIZE) as u32;
+    let new_pages = u64::from(current_pages) + u64::from(delta);
+    if new_pages > u64::from(max_pages) {
+        return None;
+    }
+    let new_pages = new_pages as u32;
+    if new_pages != current_pages {
+        if let (Some(limiter), context) = (*instance).memory_grow_limiter.get() {
+            let instance = instance as *mut FizzyInstance;
+            if !limiter(
+                context,
+                instance,
+                memory_idx,
+                current_pages,
+                new_pages,
+                max_pages,
+            ) {
+                return None;
+            }
+        }
+        (*memory).data.resize(new_pages as usize * PAGE_SIZE, 0);
+        if let (Some(hook), context) = (*instance).memory_grow_hook.get() {
+            hook(
+                context,
+                instance as *mut FizzyInstance,
+                memory_idx,
+                current_pages,
+                new_pages,
+            );
+        }
+    }
+    Some(current_pages)
+}
+
+/// Execute the function of the instance with the arguments, returning its result.
+unsafe fn execute(
+    instance: *mut Instance,
+    func_idx: u32,
+    args: &[u64],
+    depth: c_int,
+) -> Result<Option<u64>, Trap> {
+    if depth == 0 {
+        (*instance).trap_stack_trace.borrow_mut().clear();
+    }
+    if depth > (*instance).call_stack_limit as c_int {
+        return Err(Trap);
+    }
+    let module = &(*instance).module;
+    let func_type = module.function_type(func_idx).ok_or(Trap)?;
+    let result = if (func_idx as usize) < module.imported_function_count {
+        let function = (&(*instance).imported_functions)[func_idx as usize];
+        call_host(&function, instance, args, depth, func_type.output)
+    } else {
+        execute_code(instance, module, func_type, func_idx, args, depth)
+    };
+    if result.is_err() && (*instance).trap_stack_trace_enabled {
+        (*instance).trap_stack_trace.borrow_mut().push(func_idx);
+    }
+    result
+}
+
+/// Execute the function defined in the module of the instance.
+unsafe fn execute_code(
+    instance: *mut Instance,
+    module: &Module,
+    func_type: &FuncType,
+    func_idx: u32,
+    args: &[u64],
+    depth: c_int,
+) -> Result<Option<u64>, Trap> {
+    let code = &module.codes[func_idx as usize - module.imported_function_count];
+    let instructions = &code.instructions[..];
+    let mut locals = args.to_vec();
+    locals.resize(args.len() + code.locals.len(), 0);
+    let mut stack: Vec<u64> = Vec::new();
+    let mut labels = vec![Label {
+        target: instructions.len(),
+        arity: block_arity(if func_type.output == FizzyValueTypeVoid {
+            0x40
+        } else {
+            func_type.output
+        }),
+        height: 0,
+    }];
+    let mut pc = 0;
+    while !labels.is_empty() {
+        let offset = pc;
+        let opcode = *instructions.get(offset).ok_or(Trap)?;
+        pc += 1;
+        run_hooks(instance, || {
+            let (mut memory_idx, mut memory_offset) = (0, 0);
+            if let 0x28..=0x3e = opcode {
+                let mut immediates = pc;
+                if let Ok((idx, offset)) = read_memarg(instructions, &mut immediates) {
+                    memory_idx = idx;
+                    memory_offset = offset as u32;
+                }
+            }
+            FizzyExecutionState {
+                func_idx,
+                instr_offset: code
+                    .instruction_offsets
+                    .binary_search(&offset)
+                    .unwrap_or_default() as u32,
+                opcode,
+                locals: locals.as_ptr() as *const FizzyValue,
+                locals_size: locals.len(),
+                stack: stack.as_ptr() as *const FizzyValue,
+                stack_size: stack.len(),
+                depth,
+                memory_offset,
+                memory_idx,
+            }
+        })?;
+
+        match opcode {
+            0x00 => return Err(Trap),
+            0x01 => {}
+            0x02 | 0x03 => {
+                let arity = block_arity(instructions[pc]);
+                pc += 1;
+                let is_loop = opcode == 0x03;
+                labels.push(Label {
+                    // The branch to a loop executes the loop instruction again.
+                    target: if is_loop {
+                        offset
+                    } else {
+                        code.blocks[&offset].1 + 1
+                    },
+                    arity: if is_loop { 0 } else { arity },
+                    height: stack.len(),
+                });
+            }
+            0x04 => {
+                let arity = block_arity(instructions[pc]);
+                pc += 1;
+                let (else_offset, end_offset) = code.blocks[&offset];
+                let label = Label {
+                    target: end_offset + 1,
+                    arity,
+                    height: stack.len() - 1,
+                };
+                if pop(&mut stack)? as u32 != 0 {
+                    labels.push(label);
+                } else if let Some(else_offset) = else_offset {
+                    labels.push(label);
+                    pc = else_offset + 1;
+                } else {
+                    pc = end_offset + 1;
+                }
+            }
+            // The end of the then branch, continue after the end of the if.
+            0x05 => pc = labels.pop().ok_or(Trap)?.target,
+            0x0b => {
+                labels.pop();
+            }
+            0x0c => {
+                let depth = read_uleb(instructions, &mut pc)?;
+                pc = branch(&mut labels, &mut stack, depth)?;
+            }
+            0x0d => {
+                let depth = read_uleb(instructions, &mut pc)?;
+                if pop(&mut stack)? as u32 != 0 {
+                    pc = branch(&mut labels, &mut stack, depth)?;
+                }
+            }
+            0x0e => {
+                let count = read_uleb(instructions, &mut pc)?;
+                let mut depths = Vec::new();
+                for _ in 0..count {
+                    depths.push(read_uleb(instructions, &mut pc)?);
+                }
+                let default_depth = read_uleb(instructions, &mut pc)?;
+                let index = pop(&mut stack)? as u32 as usize;
+                let depth = depths.get(index).copied().unwrap_or(default_depth);
+                pc = branch(&mut labels, &mut stack, depth)?;
+            }
+            0x0f => {
+                let depth = labels.len() as u64 - 1;
+                pc = branch(&mut labels, &mut stack, depth)?;
+            }
+            0x10 => {
+                let callee = read_uleb(instructions, &mut pc)? as u32;
+                let callee_type = module.function_type(callee).ok_or(Trap)?;
+                let args = pop_args(&mut stack, callee_type.inputs.len())?;
+                stack.extend(execute(instance, callee, &args, depth + 1)?);
+            }
+            0x11 => {
+                let type_idx = read_uleb(instructions, &mut pc)? as usize;
+                pc += 1;
+                let element_idx = pop(&mut stack)? as u32 as usize;
+                let expected_type = module.types.get(type_idx).ok_or(Trap)?;
+                let funcref = (*instance)
+                    .table
+                    .as_ref()
+                    .and_then(|table| table.elements.get(element_idx).cloned().flatten())
+                    .ok_or(Trap)?;
+                if funcref.func_type != *expected_type {
+                    return Err(Trap);
+                }
+                let args = pop_args(&mut stack, expected_type.inputs.len())?;
+                stack.extend(call_funcref(&funcref, instance, &args, depth + 1)?);
+            }
+            0x1a => {
+                pop(&mut stack)?;
+            }
+            0x1b => {
+                let condition = pop(&mut stack)? as u32;
+                let second = pop(&mut stack)?;
+                let first = pop(&mut stack)?;
+                stack.push(if condition != 0 { first } else { second });
+            }
+            0x20..=0x22 => {
+                let local_idx = read_uleb(instructions, &mut pc)? as usize;
+                let local = locals.get_mut(local_idx).ok_or(Trap)?;
+                match opcode {
+                    0x20 => stack.push(*local),
+                    0x21 => *local = pop(&mut stack)?,
+                    _ => *local = *stack.last().ok_or(Trap)?,
+                }
+            }
+            0x23 | 0x24 => {
+                let global_idx = read_uleb(instructions, &mut pc)? as usize;
+                let globals = &(*instance).globals;
+                let global = *globals.get(global_idx).ok_or(Trap)?;
+                if opcode == 0x23 {
+                    let value_type = module.global_types[global_idx].value_type;
+                    stack.push(value_bits(value_type, (*global).i64));
+                } else {
+                    (*global).i64 = pop(&mut stack)?;
+                }
+            }
+            0x28..=0x35 => {
+                let (memory_idx, offset) = read_memarg(instructions, &mut pc)?;
+                let address = u64::from(pop(&mut stack)? as u32) + offset;
+                // The size of the access, whether it is sign-extended and if to i32.
+                let (size, signed, to_i32) = match opcode {
+                    0x28 | 0x2a => (4, false, true),
+                    0x29 | 0x2b => (8, false, false),
+                    0x2c | 0x2d => (1, opcode == 0x2c, true),
+                    0x2e | 0x2f => (2, opcode == 0x2e, true),
+                    0x30 | 0x31 => (1, opcode == 0x30, false),
+                    0x32 | 0x33 => (2, opcode == 0x32, false),
+                    _ => (4, opcode == 0x34, false),
+                };
+                let mut value = load((*instance).memory(memory_idx), address, size)?;
+                if signed {
+                    let shift = 64 - 8 * size as u32;
+                    value = ((value << shift) as i64 >> shift) as u64;
+                }
+                stack.push(if to_i32 { value & 0xffff_ffff } else { value });
+            }
+            0x36..=0x3e => {
+                let (memory_idx, offset) = read_memarg(instructions, &mut pc)?;
+                let value = pop(&mut stack)?;
+                let address = u64::from(pop(&mut stack)? as u32) + offset;
+                let size = match opcode {
+                    0x37 | 0x39 => 8,
+                    0x3a | 0x3c => 1,
+                    0x3b | 0x3d => 2,
+                    _ => 4,
+                };
+                store((*instance).memory(memory_idx), address, size, value)?;
+            }
+            0x3f => {
+                let memory_idx = read_uleb(instructions, &mut pc)? as u32;
+                let memory = (*instance).memory(memory_idx).as_ref().ok_or(Trap)?;
+                stack.push((memory.data.len() / PAGE_SIZE) as u64);
+            }
+            0x40 => {
+                let memory_idx = read_uleb(instructions, &mut pc)? as u32;
+                let delta = pop(&mut stack)? as u32;
+                let result = grow_memory(instance, memory_idx, delta);
+                stack.push(u64::from(result.unwrap_or(u32::MAX)));
+            }
+            0x41 => stack.push(u64::from(read_sleb(instructions, &mut pc)? as u32)),
+            0x42 => stack.push(read_sleb(instructions, &mut pc)? as u64),
+            0x43 => stack.push(read_fixed(instructions, &mut pc, 4)?),
+            0x44 => stack.push(read_fixed(instructions, &mut pc, 8)?),
+            0x45 | 0x50 | 0x67..=0x69 | 0x79..=0x7b | 0x8b..=0x91 | 0x99..=0x9f | 0xa7..=0xbf => {
+                let value = pop(&mut stack)?;
+                stack.push(unary(opcode, value)?);
+            }
+            0x46..=0xa6 => {
+                let second = pop(&mut stack)?;
+                let first = pop(&mut stack)?;
+                stack.push(binary(opcode, first, second)?);
+            }
+            _ => return Err(Trap),
+        }
+    }
+
+    if func_type.output == FizzyValueTypeVoid {
+        Ok(None)
+    } else {
+        pop(&mut stack).map(|value| Some(value_bits(func_type.output, value)))
+    }
+}
+
+fn bool_value(value: bool) -> u64 {
+    u64::from(value)
+}
+
+fn f32_value(value: f32) -> u64 {
+    u64::from(value.to_bits())
+}
+
+fn f64_value(value: f64) -> u64 {
+    value.to_bits()
+}
+
+/// Round to the nearest integer, the ties to even.
+fn nearest(value: f64) -> f64 {
+    if (value - value.trunc()).abs() == 0.5 {
+        2.0 * (value / 2.0).round()
+    } else {
+        value.round()
+    }
+}
+
+/// Applies the unary float operation: ceil, floor, trunc, nearest or sqrt.
+///
+/// The f32 operations are computed in f64, which gives the same results after the rounding.
+fn float_unary(op: u8, value: f64) -> f64 {
+    match op {
+        0 => value.ceil(),
+        1 => value.floor(),
+        2 => value.trunc(),
+        3 => nearest(value),
+        _ => value.sqrt(),
+    }
+}
+
+/// Applies the binary float operation: add, sub, mul, div, min, max or copysign.
+///
+/// The f32 operations are computed in f64, which gives the same results after the rounding.
+fn float_binary(op: u8, first: f64, second: f64) -> f64 {
+    match op {
+        0 => first + second,
+        1 => first - second,
+        2 => first * second,
+        3 => first / second,
+        4 | 5 if first.is_nan() || second.is_nan() => f64::NAN,
+        // Of the equal values, i.e. the zeros, min returns the negative one and max the positive.
+        4 if first == second => f64::from_bits(first.to_bits() | second.to_bits()),
+        5 if first == second => f64::from_bits(first.to_bits() & second.to_bits()),
+        4 => first.min(second),
+        5 => first.max(second),
+        _ => first.copysign(second),
+    }
+}
+
+/// Applies the float comparison: eq, ne, lt, gt, le or ge.
+fn float_compare(op: u8, first: f64, second: f64) -> bool {
+    match op {
+        0 => first == second,
+        1 => first != second,
+        2 => first < second,
+        3 => first > second,
+        4 => first <= second,
+        _ => first >= second,
+    }
+}
+
+/// Applies the integer comparison: eq, ne, lt_s, lt_u, gt_s, gt_u, le_s, le_u, ge_s or ge_u,
+/// to the sign- and zero-extended values.
+fn int_compare(op: u8, first: (i64, u64), second: (i64, u64)) -> bool {
+    match op {
+        0 => first.1 == second.1,
+        1 => first.1 != second.1,
+        2 => first.0 < second.0,
+        3 => first.1 < second.1,
+        4 => first.0 > second.0,
+        5 => first.1 > second.1,
+        6 => first.0 <= second.0,
+        7 => first.1 <= second.1,
+        8 => first.0 >= second.0,
+        _ => first.1 >= second.1,
+    }
+}
+
+/// Truncates the float to an integer of the signedness and the bit width, trapping if it is NaN
+/// or out of the integer range.
+fn trunc(value: f64, signed: bool, bits: i32) -> Result<u64, Trap> {
+    let value = value.trunc();
+    let (min, max) = if signed {
+        (-(2.0f64.powi(bits - 1)), 2.0f64.powi(bits - 1))
+    } else {
+        (0.0, 2.0f64.powi(bits))
+    };
+    if !(value >= min && value < max) {
+        return Err(Trap);
+    }
+    let result = if signed {
+        value as i64 as u64
+    } else {
+        value as u64
+    };
+    Ok(if bits == 32 {
+        result & 0xffff_ffff
+    } else {
+        result
+    })
+}
+
+fn unary(opcode: u8, value: u64) -> Result<u64, Trap> {
+    let value32 = value as u32;
+    let float32 = f64::from(f32::from_bits(value32));
+    let float64 = f64::from_bits(value);
+    Ok(match opcode {
+        0x45 => bool_value(value32 == 0),
+        0x50 => bool_value(value == 0),
+        0x67 => u64::from(value32.leading_zeros()),
+        0x68 => u64::from(value32.trailing_zeros()),
+        0x69 => u64::from(value32.count_ones()),
+        0x79 => u64::from(value.leading_zeros()),
+        0x7a => u64::from(value.trailing_zeros()),
+        0x7b => u64::from(value.count_ones()),
+        0x8b => value & 0x7fff_ffff,
+        0x8c => (value ^ 0x8000_0000) & 0xffff_ffff,
+        0x8d..=0x91 => f32_value(float_unary(opcode - 0x8d, float32) as f32),
+        0x99 => value & 0x7fff_ffff_ffff_ffff,
+        0x9a => value ^ 0x8000_0000_0000_0000,
+        0x9b..=0x9f => f64_value(float_unary(opcode - 0x9b, float64)),
+        0xa7 => u64::from(value32),
+        0xa8 => trunc(float32, true, 32)?,
+        0xa9 => trunc(float32, false, 32)?,
+        0xaa => trunc(float64, true, 32)?,
+        0xab => trunc(float64, false, 32)?,
+        0xac => value32 as i32 as i64 as u64,
+        0xad => u64::from(value32),
+        0xae => trunc(float32, true, 64)?,
+        0xaf => trunc(float32, false, 64)?,
+        0xb0 => trunc(float64, true, 64)?,
+        0xb1 => trunc(float64, false, 64)?,
+        0xb2 => f32_value(value32 as i32 as f32),
+        0xb3 => f32_value(value32 as f32),
+        0xb4 => f32_value(value as i64 as f32),
+        0xb5 => f32_value(value as f32),
+        0xb6 => f32_value(float64 as f32),
+        0xb7 => f64_value(f64::from(value32 as i32)),
+        0xb8 => f64_value(f64::from(value32)),
+        0xb9 => f64_value(value as i64 as f64),
+        0xba => f64_value(value as f64),
+        0xbb => f64_value(float32),
+        // The reinterpretations keep the bits.
+        0xbc | 0xbe => u64::from(value32),
+        0xbd | 0xbf => value,
+        _ => return Err(Trap),
+    })
+}
+
+fn binary(opcode: u8, first: u64, second: u64) -> Result<u64, Trap> {
+    let (a32, b32) = (first as u32, second as u32);
+    let (s32a, s32b) = (a32 as i32, b32 as i32);
+    let (s64a, s64b) = (first as i64, second as i64);
+    let (f32a, f32b) = (
+        f64::from(f32::from_bits(a32)),
+        f64::from(f32::from_bits(b32)),
+    );
+    let (f64a, f64b) = (f64::from_bits(first), f64::from_bits(second));
+    Ok(match opcode {
+        0x46..=0x4f => bool_value(int_compare(
+            opcode - 0x46,
+            (i64::from(s32a), u64::from(a32)),
+            (i64::from(s32b), u64::from(b32)),
+        )),
+        0x51..=0x5a => bool_value(int_compare(opcode - 0x51, (s64a, first), (s64b, second))),
+        0x5b..=0x60 => bool_value(float_compare(opcode - 0x5b, f32a, f32b)),
+        0x61..=0x66 => bool_value(float_compare(opcode - 0x61, f64a, f64b)),
+        0x6a => u64::from(a32.wrapping_add(b32)),
+        0x6b => u64::from(a32.wrapping_sub(b32)),
+        0x6c => u64::from(a32.wrapping_mul(b32)),
+        0x6d => {
+            if b32 == 0 || (s32a == i32::MIN && s32b == -1) {
+                return Err(Trap);
+            }
+            u64::from((s32a / s32b) as u32)
+        }
+        0x6e => u64::from(a32.checked_div(b32).ok_or(Trap)?),
+        0x6f => {
+            if b32 == 0 {
+                return Err(Trap);
+            }
+            u64::from(s32a.wrapping_rem(s32b) as u32)
+        }
+        0x70 => u64::from(a32.checked_rem(b32).ok_or(Trap)?),
+        0x71 => u64::from(a32 & b32),
+        0x72 => u64::from(a32 | b32),
+        0x73 => u64::from(a32 ^ b32),
+        0x74 => u64::from(a32.wrapping_shl(b32)),
+        0x75 => u64::from(s32a.wrapping_shr(b32) as u32),
+        0x76 => u64::from(a32.wrapping_shr(b32)),
+        0x77 => u64::from(a32.rotate_left(b32 % 32)),
+        0x78 => u64::from(a32.rotate_right(b32 % 32)),
+        0x7c => first.wrapping_add(second),
+        0x7d => first.wrapping_sub(second),
+        0x7e => first.wrapping_mul(second),
+        0x7f => {
+            if second == 0 || (s64a == i64::MIN && s64b == -1) {
+                return Err(Trap);
+            }
+            (s64a / s64b) as u64
+        }
+        0x80 => first.checked_div(second).ok_or(Trap)?,
+        0x81 => {
+            if second == 0 {
+                return Err(Trap);
+            }
+            s64a.wrapping_rem(s64b) as u64
+        }
+        0x82 => first.checked_rem(second).ok_or(Trap)?,
+        0x83 => first & second,
+        0x84 => first | second,
+        0x85 => first ^ second,
+        0x86 => first.wrapping_shl(b32),
+        0x87 => s64a.wrapping_shr(b32) as u64,
+        0x88 => first.wrapping_shr(b32),
+        0x89 => first.rotate_left(b32 % 64),
+        0x8a => first.rotate_right(b32 % 64),
+        0x92..=0x98 => f32_value(float_binary(opcode - 0x92, f32a, f32b) as f32),
+        0xa0..=0xa6 => f64_value(float_binary(opcode - 0xa0, f64a, f64b)),
+        _ => return Err(Trap),
+    })
+}
+
+fn execution_result(result: Result<Option<u64>, Trap>) -> FizzyExecutionResult {
+    match result {
+        Ok(value) => FizzyExecutionResult {
+            trapped: false,
+            has_value: value.is_some(),
+            value: FizzyValue {
+                i64: value.unwrap_or(0),
+            },
+        },
+        Err(Trap) => FizzyExecutionResult {
+            trapped: true,
+            has_value: false,
+            value: FizzyValue { i64: 0 },
+        },
+    }
+}
+
+unsafe fn set_error(error: *mut FizzyError, code: FizzyErrorCode, message: &str) {
+    if let Some(error) = error.as_mut() {
+        error.code = code;
+        let size = message.len().min(error.message.len() - 1);
+        for (dst, &src) in error.message.iter_mut().zip(&message.as_bytes()[..size]) {
+            *dst = src as c_char;
+        }
+        error.message[size] = 0;
+    }
+}
+
+unsafe fn module<'a>(module: *const FizzyModule) -> &'a Module {
+    &*(module as *const Module)
+}
+
+unsafe fn instance_module<'a>(instance: *const FizzyInstance) -> &'a Module {
+    &(*(instance as *const Instance)).module
+}
+
+fn export_name_kind_index(
+    module: &Module,
+    name: *const c_char,
+    kind: FizzyExternalKind,
+) -> Option<u32> {
+    module.find_export(unsafe { CStr::from_ptr(name) }, kind)
+}
+
+pub unsafe extern "C" fn fizzy_get_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+pub unsafe extern "C" fn fizzy_has_guarded_memory() -> bool {
+    false
+}
+
+pub unsafe extern "C" fn fizzy_validate(wasm_binary: *const u8, wasm_binary_size: usize) -> bool {
+    parse(
+        slice(wasm_binary, wasm_binary_size),
+        &NO_PARSER_LIMITS,
+        &NO_FEATURES,
+    )
+    .is_ok()
+}
+
+pub unsafe extern "C" fn fizzy_parse(
+    wasm_binary: *const u8,
+    wasm_binary_size: usize,
+) -> *const FizzyModule {
+    fizzy_parse_with_limits(
+        wasm_binary,
+        wasm_binary_size,
+        std::ptr::null(),
+        std::ptr::null_mut(),
+    )
+}
+
+pub unsafe extern "C" fn fizzy_parse_with_limits(
+    wasm_binary: *const u8,
+    wasm_binary_size: usize,
+    limits: *const FizzyParserLimits,
+    error: *mut FizzyError,
+) -> *const FizzyModule {
+    fizzy_parse_with_features(
+        wasm_binary,
+        wasm_binary_size,
+        limits,
+        std::ptr::null(),
+        error,
+    )
+}
+
+pub unsafe extern "C" fn fizzy_parse_with_features(
+    wasm_binary: *const u8,
+    wasm_binary_size: usize,
+    limits: *const FizzyParserLimits,
+    features: *const FizzyFeatures,
+    error: *mut FizzyError,
+) -> *const FizzyModule {
+    let limits = limits.as_ref().unwrap_or(&NO_PARSER_LIMITS);
+    let features = features.as_ref().unwrap_or(&NO_FEATURES);
+    match parse(slice(wasm_binary, wasm_binary_size), limits, features) {
+        Ok(module) => {
+            set_error(error, FizzySuccess, "");
+            Box::into_raw(Box::new(module)) as *const FizzyModule
+        }
+        Err(Failure(code, message)) => {
+            set_error(error, code, &message);
+            std::ptr::null()
+        }
+    }
+}
+
+pub unsafe extern "C" fn fizzy_free_module(module: *const FizzyModule) {
+    if !module.is_null() {
+        drop(Box::from_raw(module as *mut Module));
+    }
+}
+
+pub unsafe extern "C" fn fizzy_clone_module(module: *const FizzyModule) -> *const FizzyModule {
+    Box::into_raw(Box::new(self::module(module).clone())) as *const FizzyModule
+}
+
+pub unsafe extern "C" fn fizzy_get_function_type(
+    module: *const FizzyModule,
+    func_idx: u32,
+) -> FizzyFunctionType {
+    self::module(module)
+        .function_type(func_idx)
+        .expect("valid function index")
+        .to_sys()
+}
+
+pub unsafe extern "C" fn fizzy_get_function_name(
+    module: *const FizzyModule,
+    func_idx: u32,
+) -> *const c_char {
+    self::module(module)
+        .function_names
+        .get(&func_idx)
+        .map_or(std::ptr::null(), |name| name.as_ptr())
+}
+
+pub unsafe extern "C" fn fizzy_get_global_count(module: *const FizzyModule) -> u32 {
+    self::module(module).globals.len() as u32
+}
+
+pub unsafe extern "C" fn fizzy_get_global(module: *const FizzyModule, idx: u32) -> FizzyGlobal {
+    self::module(module).globals[idx as usize]
+}
+
+pub unsafe extern "C" fn fizzy_get_data_segment_count(module: *const FizzyModule) -> u32 {
+    self::module(module).data.len() as u32
+}
+
+pub unsafe extern "C" fn fizzy_get_data_segment(
+    module: *const FizzyModule,
+    data_idx: u32,
+) -> FizzyDataSegment {
+    let (memory_index, offset, data) = &self::module(module).data[data_idx as usize];
+    FizzyDataSegment {
+        memory_index: *memory_index,
+        offset: *offset,
+        data: data.as_ptr(),
+        size: data.len(),
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_element_segment_count(module: *const FizzyModule) -> u32 {
+    self::module(module).elements.len() as u32
+}
+
+pub unsafe extern "C" fn fizzy_get_element_segment(
+    module: *const FizzyModule,
+    elem_idx: u32,
+) -> FizzyElementSegment {
+    let (offset, func_indices) = &self::module(module).elements[elem_idx as usize];
+    FizzyElementSegment {
+        table_index: 0,
+        offset: *offset,
+        func_indices: func_indices.as_ptr(),
+        func_indices_size: func_indices.len(),
+    }
+}
+
+/// Returns the code of a function defined in the module.
+fn defined_function_code(module: &Module, func_idx: u32) -> Option<&Code> {
+    (func_idx as usize)
+        .checked_sub(module.imported_function_count)
+        .and_then(|code_idx| module.codes.get(code_idx))
+}
+
+pub unsafe extern "C" fn fizzy_get_function_metrics(
+    module: *const FizzyModule,
+    func_idx: u32,
+    out_metrics: *mut FizzyFunctionMetrics,
+) -> bool {
+    match defined_function_code(self::module(module), func_idx) {
+        Some(code) => {
+            *out_metrics = FizzyFunctionMetrics {
+                body_size: code.body_size,
+                local_count: code.locals.len() as u32,
+                max_stack_height: 0,
+            };
+            true
+        }
+        None => false,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_type_count(module: *const FizzyModule) -> u32 {
+    self::module(module).types.len() as u32
+}
+
+pub unsafe extern "C" fn fizzy_get_type(
+    module: *const FizzyModule,
+    type_idx: u32,
+) -> FizzyFunctionType {
+    self::module(module).types[type_idx as usize].to_sys()
+}
+
+pub unsafe extern "C" fn fizzy_get_function_count(module: *const FizzyModule) -> u32 {
+    self::module(module).functions.len() as u32
+}
+
+pub unsafe extern "C" fn fizzy_get_function_opcodes(
+    module: *const FizzyModule,
+    func_idx: u32,
+    opcodes: *mut u8,
+    opcodes_size: usize,
+) -> usize {
+    match defined_function_code(self::module(module), func_idx) {
+        Some(code) => {
+            let size = code.opcodes.len().min(opcodes_size);
+            if size != 0 {
+                std::ptr::copy_nonoverlapping(code.opcodes.as_ptr(), opcodes, size);
+            }
+            code.opcodes.len()
+        }
+        None => 0,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_function_callees(
+    module: *const FizzyModule,
+    func_idx: u32,
+    callees: *mut u32,
+    callees_size: usize,
+) -> usize {
+    match defined_function_code(self::module(module), func_idx) {
+        Some(code) => {
+            let size = code.callees.len().min(callees_size);
+            if size != 0 {
+                std::ptr::copy_nonoverlapping(code.callees.as_ptr(), callees, size);
+            }
+            code.callees.len()
+        }
+        None => 0,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_table_limits(
+    module: *const FizzyModule,
+    out_limits: *mut FizzyLimits,
+) -> bool {
+    match self::module(module).table {
+        Some(limits) => {
+            *out_limits = limits;
+            true
+        }
+        None => false,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_memory_limits(
+    module: *const FizzyModule,
+    out_limits: *mut FizzyLimits,
+) -> bool {
+    match self::module(module).memories.first() {
+        Some(&limits) => {
+            *out_limits = limits;
+            true
+        }
+        None => false,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_memory_count(module: *const FizzyModule) -> u32 {
+    self::module(module).memories.len() as u32
+}
+
+pub unsafe extern "C" fn fizzy_get_memory_type(
+    module: *const FizzyModule,
+    memory_idx: u32,
+) -> FizzyLimits {
+    self::module(module).memories[memory_idx as usize]
+}
+
+pub unsafe extern "C" fn fizzy_get_start_function(
+    module: *const FizzyModule,
+    out_func_idx: *mut u32,
+) -> bool {
+    match self::module(module).start {
+        Some(func_idx) => {
+            *out_func_idx = func_idx;
+            true
+        }
+        None => false,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_import_count(module: *const FizzyModule) -> u32 {
+    self::module(module).imports.len() as u32
+}
+
+pub unsafe extern "C" fn fizzy_get_import_description(
+    module: *const FizzyModule,
+    import_idx: u32,
+) -> FizzyImportDescription {
+    let import = &self::module(module).imports[import_idx as usize];
+    let (kind, desc) = match &import.desc {
+        ImportDesc::Function(func_type) => (
+            FizzyExternalKindFunction,
+            FizzyImportDescription__bindgen_ty_1 {
+                function_type: func_type.to_sys(),
+            },
+        ),
+        ImportDesc::Table(limits) => (
+            FizzyExternalKindTable,
+            FizzyImportDescription__bindgen_ty_1 {
+                table_limits: *limits,
+            },
+        ),
+        ImportDesc::Memory(limits) => (
+            FizzyExternalKindMemory,
+            FizzyImportDescription__bindgen_ty_1 {
+                memory_limits: *limits,
+            },
+        ),
+        ImportDesc::Global(global_type) => (
+            FizzyExternalKindGlobal,
+            FizzyImportDescription__bindgen_ty_1 {
+                global_type: *global_type,
+            },
+        ),
+    };
+    FizzyImportDescription {
+        module: import.module.as_ptr(),
+        name: import.name.as_ptr(),
+        kind,
+        desc,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_export_count(module: *const FizzyModule) -> u32 {
+    self::module(module).exports.len() as u32
+}
+
+pub unsafe extern "C" fn fizzy_get_export_description(
+    module: *const FizzyModule,
+    export_idx: u32,
+) -> FizzyExportDescription {
+    let (name, kind, index) = &self::module(module).exports[export_idx as usize];
+    FizzyExportDescription {
+        name: name.as_ptr(),
+        kind: *kind,
+        index: *index,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_find_exported_function(
+    module: *const FizzyModule,
+    name: *const c_char,
+    out_func_idx: *mut u32,
+) -> bool {
+    match export_name_kind_index(self::module(module), name, FizzyExternalKindFunction) {
+        Some(func_idx) => {
+            *out_func_idx = func_idx;
+            true
+        }
+        None => false,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_instantiate(
+    module: *const FizzyModule,
+    imported_functions: *const FizzyExternalFunction,
+    imported_functions_size: usize,
+) -> *mut FizzyInstance {
+    fizzy_instantiate_with_imports(
+        module,
+        imported_functions,
+        imported_functions_size,
+        std::ptr::null(),
+        std::ptr::null(),
+        std::ptr::null(),
+        0,
+    )
+}
+
+pub unsafe extern "C" fn fizzy_instantiate_with_imports(
+    module: *const FizzyModule,
+    imported_functions: *const FizzyExternalFunction,
+    imported_functions_size: usize,
+    imported_table: *const FizzyExternalTable,
+    imported_memory: *const FizzyExternalMemory,
+    imported_globals: *const FizzyExternalGlobal,
+    imported_globals_size: usize,
+) -> *mut FizzyInstance {
+    fizzy_instantiate_with_options(
+        module,
+        imported_functions,
+        imported_functions_size,
+        imported_table,
+        imported_memory,
+        if imported_memory.is_null() { 0 } else { 1 },
+        imported_globals,
+        imported_globals_size,
+        std::ptr::null(),
+        std::ptr::null_mut(),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn fizzy_instantiate_with_options(
+    module: *const FizzyModule,
+    imported_functions: *const FizzyExternalFunction,
+    imported_functions_size: usize,
+    imported_table: *const FizzyExternalTable,
+    imported_memories: *const FizzyExternalMemory,
+    imported_memories_size: usize,
+    imported_globals: *const FizzyExternalGlobal,
+    imported_globals_size: usize,
+    options: *const FizzyInstantiateOptions,
+    error: *mut FizzyError,
+) -> *mut FizzyInstance {
+    match instantiate(
+        Box::from_raw(module as *mut Module),
+        slice(imported_functions, imported_functions_size),
+        imported_table.as_ref(),
+        slice(imported_memories, imported_memories_size),
+        slice(imported_globals, imported_globals_size),
+        options.as_ref(),
+    ) {
+        Ok(instance) => {
+            set_error(error, FizzySuccess, "");
+            instance as *mut FizzyInstance
+        }
+        Err(Failure(code, message)) => {
+            set_error(error, code, &message);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+pub unsafe extern "C" fn fizzy_run_start(instance: *mut FizzyInstance) -> FizzyExecutionResult {
+    execution_result(run_start(instance as *mut Instance))
+}
+
+pub unsafe extern "C" fn fizzy_duplicate_instance(
+    instance: *const FizzyInstance,
+    error: *mut FizzyError,
+) -> *mut FizzyInstance {
+    let instance = &*(instance as *const Instance);
+    let module = &instance.module;
+    let memories = instance
+        .memories
+        .iter()
+        .map(|memory| {
+            let mut copy = InstanceMemory {
+                memory: memory.memory,
+                limits: memory.limits,
+                owned: memory.owned,
+            };
+            if memory.owned {
+                let source = &*memory.memory;
+                copy.memory = Box::into_raw(Box::new(Memory {
+                    data: source.data.clone(),
+                    read_only_ranges: source.read_only_ranges.clone(),
+                }));
+            }
+            copy
+        })
+        .collect();
+    let (table, owns_table) = match instance.table.as_ref() {
+        Some(table) if instance.owns_table => {
+            let table = Table {
+                elements: table.elements.clone(),
+            };
+            (Box::into_raw(Box::new(table)), true)
+        }
+        _ => (instance.table, false),
+    };
+    let globals = instance
+        .globals
+        .iter()
+        .enumerate()
+        .map(|(idx, &global)| {
+            if idx < module.imported_global_count {
+                global
+            } else {
+                Box::into_raw(Box::new(*global))
+            }
+        })
+        .collect();
+    let copy = Box::into_raw(Box::new(Instance {
+        module: module.clone(),
+        imported_functions: instance.imported_functions.clone(),
+        table,
+        owns_table,
+        memories,
+        memory_pages_limit: instance.memory_pages_limit,
+        globals,
+        call_stack_limit: instance.call_stack_limit,
+        start_pending: instance.start_pending.clone(),
+        trap_stack_trace: RefCell::new(Vec::new()),
+        trap_stack_trace_enabled: instance.trap_stack_trace_enabled,
+        instruction_hook: Cell::new((None, std::ptr::null_mut())),
+        periodic_hook: Cell::new((None, std::ptr::null_mut(), 0)),
+        periodic_hook_countdown: Cell::new(0),
+        memory_grow_hook: Cell::new((None, std::ptr::null_mut())),
+        memory_grow_limiter: Cell::new((None, std::ptr::null_mut())),
+    }));
+    // The functions of the instance in the table are rebound to the copy.
+    if owns_table {
+        for element in (*table).elements.iter_mut().flatten() {
+            if let FuncRefKind::Wasm(func_instance, func_idx) = element.kind {
+                if std::ptr::eq(func_instance, instance) {
+                    element.kind = FuncRefKind::Wasm(copy, func_idx);
+                }
+            }
+        }
+    }
+    set_error(error, FizzySuccess, "");
+    copy as *mut FizzyInstance
+}
+
+pub unsafe extern "C" fn fizzy_find_exported_table(
+    instance: *mut FizzyInstance,
+    name: *const c_char,
+    out_table: *mut FizzyExternalTable,
+) -> bool {
+    let module = instance_module(instance);
+    if export_name_kind_index(module, name, FizzyExternalKindTable).is_none() {
+        return false;
+    }
+    fizzy_get_instance_table(instance, out_table)
+}
+
+pub unsafe extern "C" fn fizzy_find_exported_memory(
+    instance: *mut FizzyInstance,
+    name: *const c_char,
+    out_memory: *mut FizzyExternalMemory,
+) -> bool {
+    let module = instance_module(instance);
+    match export_name_kind_index(module, name, FizzyExternalKindMemory) {
+        Some(memory_idx) => fizzy_get_instance_memory(instance, memory_idx, out_memory),
+        None => false,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_find_exported_global(
+    instance: *mut FizzyInstance,
+    name: *const c_char,
+    out_global: *mut FizzyExternalGlobal,
+) -> bool {
+    let module = instance_module(instance);
+    match export_name_kind_index(module, name, FizzyExternalKindGlobal) {
+        Some(global_idx) => {
+            *out_global = FizzyExternalGlobal {
+                value: (&(*(instance as *mut Instance)).globals)[global_idx as usize],
+                type_: module.global_types[global_idx as usize],
+            };
+            true
+        }
+        None => false,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_create_memory(pages: u32) -> *mut FizzyMemory {
+    if pages > MEMORY_PAGES_VALIDATION_LIMIT {
+        return std::ptr::null_mut();
+    }
+    Box::into_raw(Box::new(Memory {
+        data: vec![0; pages as usize * PAGE_SIZE],
+        read_only_ranges: Vec::new(),
+    })) as *mut FizzyMemory
+}
+
+pub unsafe extern "C" fn fizzy_free_memory(memory: *mut FizzyMemory) {
+    if !memory.is_null() {
+        drop(Box::from_raw(memory as *mut Memory));
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_memory_data(memory: *mut FizzyMemory) -> *mut u8 {
+    (*(memory as *mut Memory)).data.as_mut_ptr()
+}
+
+pub unsafe extern "C" fn fizzy_get_memory_size(memory: *const FizzyMemory) -> usize {
+    (*(memory as *const Memory)).data.len()
+}
+
+pub unsafe extern "C" fn fizzy_find_exported_funcref(
+    instance: *mut FizzyInstance,
+    name: *const c_char,
+) -> *mut FizzyFuncRef {
+    let module = instance_module(instance);
+    match export_name_kind_index(module, name, FizzyExternalKindFunction) {
+        Some(func_idx) => Box::into_raw(Box::new(FuncRef {
+            func_type: module
+                .function_type(func_idx)
+                .expect("valid function index")
+                .clone(),
+            kind: FuncRefKind::Wasm(instance as *mut Instance, func_idx),
+        })) as *mut FizzyFuncRef,
+        None => std::ptr::null_mut(),
+    }
+}
+
+pub unsafe extern "C" fn fizzy_create_funcref(
+    type_: FizzyFunctionType,
+    function: FizzyExternalFn,
+    context: *mut c_void,
+) -> *mut FizzyFuncRef {
+    Box::into_raw(Box::new(FuncRef {
+        func_type: FuncType::from_sys(&type_),
+        kind: FuncRefKind::Host(FizzyExternalFunction { function, context }),
+    })) as *mut FizzyFuncRef
+}
+
+pub unsafe extern "C" fn fizzy_get_funcref_type(funcref: *const FizzyFuncRef) -> FizzyFunctionType {
+    (*(funcref as *const FuncRef)).func_type.to_sys()
+}
+
+pub unsafe extern "C" fn fizzy_free_funcref(funcref: *mut FizzyFuncRef) {
+    if !funcref.is_null() {
+        drop(Box::from_raw(funcref as *mut FuncRef));
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_table_size(table: *const FizzyTable) -> u32 {
+    (*(table as *const Table)).elements.len() as u32
+}
+
+pub unsafe extern "C" fn fizzy_grow_table(
+    table: *mut FizzyTable,
+    delta: u32,
+    max_size: u32,
+) -> bool {
+    let elements = &mut (*(table as *mut Table)).elements;
+    let new_size = elements.len() as u64 + u64::from(delta);
+    if new_size > u64::from(max_size) {
+        return false;
+    }
+    elements.resize(new_size as usize, None);
+    true
+}
+
+pub unsafe extern "C" fn fizzy_get_table_element(
+    table: *const FizzyTable,
+    index: u32,
+) -> *mut FizzyFuncRef {
+    let table = &*(table as *const Table);
+    match table.elements.get(index as usize) {
+        Some(Some(funcref)) => Box::into_raw(Box::new(funcref.clone())) as *mut FizzyFuncRef,
+        _ => std::ptr::null_mut(),
+    }
+}
+
+pub unsafe extern "C" fn fizzy_set_table_element(
+    table: *mut FizzyTable,
+    index: u32,
+    funcref: *const FizzyFuncRef,
+) -> bool {
+    let table = &mut *(table as *mut Table);
+    match table.elements.get_mut(index as usize) {
+        Some(element) => {
+            *element = (funcref as *const FuncRef).as_ref().cloned();
+            true
+        }
+        None => false,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_instance_memory(
+    instance: *mut FizzyInstance,
+    memory_idx: u32,
+    out_memory: *mut FizzyExternalMemory,
+) -> bool {
+    let memories = &(*(instance as *mut Instance)).memories;
+    match memories.get(memory_idx as usize) {
+        Some(memory) => {
+            *out_memory = FizzyExternalMemory {
+                memory: memory.memory as *mut FizzyMemory,
+                limits: memory.limits,
+            };
+            true
+        }
+        None => false,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_instance_table(
+    instance: *mut FizzyInstance,
+    out_table: *mut FizzyExternalTable,
+) -> bool {
+    let instance = instance as *mut Instance;
+    if (*instance).table.is_null() {
+        return false;
+    }
+    *out_table = FizzyExternalTable {
+        table: (*instance).table as *mut FizzyTable,
+        limits: (*instance)
+            .module
+            .as_ref()
+            .table
+            .expect("module with table"),
+    };
+    true
+}
+
+pub unsafe extern "C" fn fizzy_free_instance(instance: *mut FizzyInstance) {
+    if !instance.is_null() {
+        drop(Box::from_raw(instance as *mut Instance));
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_instance_module(
+    instance: *mut FizzyInstance,
+) -> *const FizzyModule {
+    Arc::as_ptr(&(*(instance as *mut Instance)).module) as *const FizzyModule
+}
+
+pub unsafe extern "C" fn fizzy_get_instance_call_stack_limit(
+    instance: *const FizzyInstance,
+) -> u32 {
+    (*(instance as *const Instance)).call_stack_limit
+}
+
+pub unsafe extern "C" fn fizzy_get_instance_memory_data(instance: *mut FizzyInstance) -> *mut u8 {
+    match (*(instance as *mut Instance)).memory(0).as_mut() {
+        Some(memory) => memory.data.as_mut_ptr(),
+        None => std::ptr::null_mut(),
+    }
+}
+
+pub unsafe extern "C" fn fizzy_add_instance_memory_read_only_range(
+    instance: *mut FizzyInstance,
+    memory_idx: u32,
+    offset: u32,
+    size: u32,
+) -> bool {
+    match (*(instance as *mut Instance)).memory(memory_idx).as_mut() {
+        Some(memory) if u64::from(offset) + u64::from(size) <= memory.data.len() as u64 => {
+            if size != 0 {
+                memory.read_only_ranges.push((offset, size));
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+pub unsafe extern "C" fn fizzy_clear_instance_memory_read_only_ranges(
+    instance: *mut FizzyInstance,
+    memory_idx: u32,
+) {
+    if let Some(memory) = (*(instance as *mut Instance)).memory(memory_idx).as_mut() {
+        memory.read_only_ranges.clear();
+    }
+}
+
+pub unsafe extern "C" fn fizzy_get_instance_memory_size(instance: *mut FizzyInstance) -> usize {
+    (*(instance as *mut Instance))
+        .memory(0)
+        .as_ref()
+        .map_or(0, |memory| memory.data.len())
+}
+
+pub unsafe extern "C" fn fizzy_set_instruction_hook(
+    instance: *mut FizzyInstance,
+    hook: FizzyInstructionHook,
+    context: *mut c_void,
+) {
+    (*(instance as *mut Instance))
+        .instruction_hook
+        .set((hook, context));
+}
+
+pub unsafe extern "C" fn fizzy_set_periodic_hook(
+    instance: *mut FizzyInstance,
+    interval: u64,
+    hook: FizzyPeriodicHook,
+    context: *mut c_void,
+) {
+    let instance = instance as *mut Instance;
+    if interval == 0 || hook.is_none() {
+        (*instance)
+            .periodic_hook
+            .set((None, std::ptr::null_mut(), 0));
+    } else {
+        (*instance).periodic_hook.set((hook, context, interval));
+        (*instance).periodic_hook_countdown.set(interval);
+    }
+}
+
+pub unsafe extern "C" fn fizzy_set_memory_grow_hook(
+    instance: *mut FizzyInstance,
+    hook: FizzyMemoryGrowHook,
+    context: *mut c_void,
+) {
+    (*(instance as *mut Instance))
+        .memory_grow_hook
+        .set((hook, context));
+}
+
+pub unsafe extern "C" fn fizzy_set_memory_grow_limiter(
+    instance: *mut FizzyInstance,
+    limiter: FizzyMemoryGrowLimiter,
+    context: *mut c_void,
+) {
+    (*(instance as *mut Instance))
+        .memory_grow_limiter
+        .set((limiter, context));
+}
+
+pub unsafe extern "C" fn fizzy_get_trap_stack_trace(
+    instance: *const FizzyInstance,
+    func_indices: *mut u32,
+    func_indices_size: usize,
+) -> usize {
+    let trace = (*(instance as *const Instance)).trap_stack_trace.borrow();
+    let size = trace.len().min(func_indices_size);
+    if size != 0 {
+        std::ptr::copy_nonoverlapping(trace.as_ptr(), func_indices, size);
+    }
+    trace.len()
+}
+
+pub unsafe extern "C" fn fizzy_get_instance_state_hash(
+    instance: *const FizzyInstance,
+    hash: *mut u8,
+) {
+    let instance = &*(instance as *const Instance);
+    let mut state = Vec::new();
+    for memory in &instance.memories {
+        state.extend_from_slice(&(*memory.memory).data);
+    }
+    for &global in &instance.globals {
+        state.extend_from_slice(&(*global).i64.to_le_bytes());
+    }
+    // FNV-1a of the state, with 4 different offset bases.
+    for (i, chunk) in std::slice::from_raw_parts_mut(hash, 32)
+        .chunks_mut(8)
+        .enumerate()
+    {
+        let mut value = 0xcbf2_9ce4_8422_2325u64 ^ i as u64;
+        for &byte in &state {
+            value = (value ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+pub unsafe extern "C" fn fizzy_execute(
+    instance: *mut FizzyInstance,
+    func_idx: u32,
+    args: *const FizzyValue,
+    depth: c_int,
+) -> FizzyExecutionResult {
+    let instance = instance as *mut Instance;
+    let func_type = (*(*instance).module)
+        .function_type(func_idx)
+        .expect("valid function index");
+    let args: Vec<u64> = slice(args, func_type.inputs.len())
+        .iter()
+        .zip(&func_type.inputs)
+        .map(|(arg, &value_type)| value_bits(value_type, arg.i64))
+        .collect();
+    execution_result(execute(instance, func_idx, &args, depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::from_hex;
+    use crate::{parse, Value};
+
+    #[test]
+    fn numeric() {
+        /* wat2wasm
+          (func (export "div_s") (param i32 i32) (result i32) (i32.div_s (local.get 0) (local.get 1)))
+          (func (export "nearest") (param f64) (result f64) (f64.nearest (local.get 0)))
+          (func (export "min") (param f32 f32) (result f32) (f32.min (local.get 0) (local.get 1)))
+          (func (export "trunc") (param f64) (result i32) (i32.trunc_f64_s (local.get 0)))
+        */
+        let input = from_hex(&[
+            "0061736d0100000001170460027f7f017f60017c017c60027d7d017d60017c017f03050400010203",
+            "072104056469765f730000076e6561726573740001036d696e0002057472756e6300030a1d040700",
+            "200020016d0b050020009e0b070020002001960b05002000aa0b",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        let mut execute = |name: &str, args: &[Value]| {
+            let result = instance.execute(name, args).unwrap();
+            if result.trapped() {
+                None
+            } else {
+                result.value()
+            }
+        };
+
+        assert_eq!(
+            execute("div_s", &[Value::I32(-7), Value::I32(2)]),
+            Some(Value::I32(-3))
+        );
+        assert_eq!(execute("div_s", &[Value::I32(1), Value::I32(0)]), None);
+        assert_eq!(
+            execute("div_s", &[Value::I32(i32::MIN), Value::I32(-1)]),
+            None
+        );
+        assert_eq!(
+            execute("nearest", &[Value::F64(2.5)]),
+            Some(Value::F64(2.0))
+        );
+        assert_eq!(
+            execute("nearest", &[Value::F64(-3.5)]),
+            Some(Value::F64(-4.0))
+        );
+        match execute("min", &[Value::F32(0.0), Value::F32(-0.0)]) {
+            Some(Value::F32(value)) => assert_eq!(value.to_bits(), (-0.0f32).to_bits()),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(execute("trunc", &[Value::F64(-1.9)]), Some(Value::I32(-1)));
+        assert_eq!(execute("trunc", &[Value::F64(f64::NAN)]), None);
+        assert_eq!(execute("trunc", &[Value::F64(2147483648.0)]), None);
+    }
+
+    #[test]
+    fn br_table() {
+        /* wat2wasm
+          (func (export "switch") (param i32) (result i32)
+            (block (block (block (br_table 0 1 2 (local.get 0)))
+              (return (i32.const 10)))
+              (return (i32.const 20)))
+            (i32.const 30))
+        */
+        let input = from_hex(&[
+            "0061736d0100000001060160017f017f03020100070a010673776974636800000a1c011a00024002",
+            "40024020000e020001020b410a0f0b41140f0b411e0b",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        for (arg, expected) in &[(0, 10), (1, 20), (2, 30), (-1, 30)] {
+            let result = instance.execute("switch", &[Value::I32(*arg)]).unwrap();
+            assert_eq!(result.value(), Some(Value::I32(*expected)));
+        }
+    }
+
+    #[test]
+    fn call_indirect() {
+        /* wat2wasm
+          (type $i32 (func (result i32)))
+          (table 3 funcref)
+          (elem (i32.const 0) $f $g)
+          (func $f (result i32) (i32.const 42))
+          (func $g (result i64) (i64.const 42))
+          (func (export "call") (param i32) (result i32) (call_indirect (type $i32) (local.get 0)))
+        */
+        let input = from_hex(&[
+            "0061736d01000000010e036000017f6000017e60017f017f03040300010204040170000307080104",
+            "63616c6c00020908010041000b0200010a13030400412a0b0400422a0b070020001100000b",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        let result = instance.execute("call", &[Value::I32(0)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(42)));
+        // The type mismatch, the null element and the element out of the table bounds trap.
+        for element in 1..4 {
+            let result = instance.execute("call", &[Value::I32(element)]).unwrap();
+            assert!(result.trapped());
+        }
+    }
+
+    #[test]
+    fn memory() {
+        /* wat2wasm
+          (memory 1 2)
+          (data (i32.const 0) "\ff\7f")
+          (func (export "load8_s") (param i32) (result i32) (i32.load8_s (local.get 0)))
+          (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+        */
+        let input = from_hex(&[
+            "0061736d0100000001060160017f017f0303020000050401010102071202076c6f6164385f730000",
+            "0467726f7700010a1002070020002c00000b0600200040000b0b08010041000b02ff7f",
+        ]);
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        let result = instance.execute("load8_s", &[Value::I32(0)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(-1)));
+        let result = instance.execute("load8_s", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0x7f)));
+        let result = instance.execute("load8_s", &[Value::I32(65536)]).unwrap();
+        assert!(result.trapped());
+
+        let result = instance.execute("grow", &[Value::I32(2)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(-1)));
+        let result = instance.execute("grow", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        assert_eq!(instance.memory_size(), 2 * 65536);
+        let result = instance.execute("load8_s", &[Value::I32(65536)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0)));
+    }
+}
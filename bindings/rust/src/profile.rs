@@ -0,0 +1,173 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Call-tree profiling of guest code.
+//!
+//! Executions with [`Instance::execute_profiled()`] attribute every executed instruction to
+//! the stack of functions active when it is executed. The [`Profile`] is deterministic, as it
+//! counts instructions instead of sampling the time. It can be written in the folded stacks
+//! format, the input of `inferno-flamegraph` and `flamegraph.pl`.
+
+use crate::{function_name, sys, Error, ExecutionResult, Instance, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+/// The number of instructions executed by every stack of functions.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    stacks: BTreeMap<Vec<Option<u32>>, u64>,
+    names: HashMap<u32, String>,
+}
+
+impl Profile {
+    /// Create an empty profile.
+    pub fn new() -> Self {
+        Profile::default()
+    }
+
+    /// The stacks of functions, the outermost first, with the number of instructions executed
+    /// in their innermost function. `None` stands for a host function calling back into
+    /// the instance.
+    pub fn stacks(&self) -> impl Iterator<Item = (&[Option<u32>], u64)> + '_ {
+        self.stacks
+            .iter()
+            .map(|(stack, &count)| (stack.as_slice(), count))
+    }
+
+    /// The total number of executed instructions.
+    pub fn instruction_count(&self) -> u64 {
+        self.stacks.values().sum()
+    }
+
+    /// Write the profile in the folded stacks format, one stack per line, e.g.
+    ///
+    /// ```text
+    /// main;fib 1200
+    /// main;fib;fib 3400
+    /// ```
+    ///
+    /// The functions are named from the name section, or `func[<index>]` if they have no name,
+    /// with `;` replaced by `:`. Host functions are named `[host]`.
+    pub fn write_folded<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        for (stack, count) in &self.stacks {
+            let frames: Vec<String> = stack
+                .iter()
+                .map(|frame| match frame {
+                    Some(func_idx) => self.names[func_idx].replace(';', ":"),
+                    None => "[host]".to_string(),
+                })
+                .collect();
+            writeln!(writer, "{} {}", frames.join(";"), count)?;
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, stack: &[Option<u32>], count: u64) {
+        if count != 0 {
+            *self.stacks.entry(stack.to_vec()).or_insert(0) += count;
+        }
+    }
+}
+
+impl Instance {
+    /// Execute an exported function, adding the executed instructions to the profile.
+    ///
+    /// The functions are identified by their indices, so a profile should only collect
+    /// the executions of instances of the same module.
+    pub fn execute_profiled(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        profile: &mut Profile,
+    ) -> Result<ExecutionResult, Error> {
+        let func_idx = self
+            .find_exported_function_index(name)
+            .ok_or(Error::FunctionNotFound)?;
+
+        // Instructions are counted for the current stack until it changes.
+        let mut stack: Vec<Option<u32>> = Vec::new();
+        let mut count = 0;
+        let mut hook = |state: &sys::FizzyExecutionState| {
+            let depth = state.depth as usize;
+            if stack.len() != depth + 1 || stack[depth] != Some(state.func_idx) {
+                profile.add(&stack, count);
+                count = 0;
+                // The frames of host functions are not visible to the hook.
+                stack.resize(depth, None);
+                stack.push(Some(state.func_idx));
+            }
+            count += 1;
+            true
+        };
+        let result = self.execute_function_with_hook(func_idx, args, &mut hook);
+        profile.add(&stack, count);
+
+        let module = self.module();
+        for func_idx in profile.stacks.keys().flatten().flatten() {
+            profile.names.entry(*func_idx).or_insert_with(|| {
+                function_name(module, *func_idx).unwrap_or_else(|| format!("func[{}]", func_idx))
+            });
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+
+    /* wat2wasm --debug-names
+      (func $leaf (result i32) (i32.const 1))
+      (func (export "main") (result i32) (i32.add (call $leaf) (call $leaf)))
+    */
+    const WASM: &[&str] = &[
+        "0061736d010000000105016000017f0303020000070801046d61696e00010a0e02040041010b0700",
+        "100010006a0b000e046e616d6501070100046c656166",
+    ];
+
+    #[test]
+    fn profile() {
+        let mut instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        let mut profile = Profile::new();
+        let result = instance
+            .execute_profiled("main", &[], &mut profile)
+            .unwrap();
+        assert_eq!(result.value(), Some(Value::I32(2)));
+        assert_eq!(
+            profile.stacks().collect::<Vec<_>>(),
+            [(&[Some(1)][..], 4), (&[Some(1), Some(0)][..], 4)]
+        );
+        assert_eq!(profile.instruction_count(), 8);
+
+        // Executions accumulate.
+        instance
+            .execute_profiled("main", &[], &mut profile)
+            .unwrap();
+        let mut folded = Vec::new();
+        profile.write_folded(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "func[1] 8\nfunc[1];leaf 8\n"
+        );
+
+        assert_eq!(
+            instance.execute_profiled("none", &[], &mut profile).err(),
+            Some(Error::FunctionNotFound)
+        );
+    }
+
+    #[test]
+    fn folded_host_frames() {
+        let mut profile = Profile::new();
+        profile.add(&[Some(0), None, Some(1)], 3);
+        profile.add(&[Some(0)], 0);
+        profile.names.insert(0, "a;b".to_string());
+        profile.names.insert(1, "c".to_string());
+        let mut folded = Vec::new();
+        profile.write_folded(&mut folded).unwrap();
+        assert_eq!(String::from_utf8(folded).unwrap(), "a:b;[host];c 3\n");
+    }
+}
@@ -0,0 +1,340 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording of the interactions of the host with an instance.
+//!
+//! While recording is active (see [`Instance::start_recording()`]), every call of an imported
+//! function and every write of the instance memories by the host, with [`Instance::memory_set()`]
+//! or by a host function with [`Caller::memory_set()`](crate::host::Caller::memory_set), is
+//! logged as an [`Event`]. The [`Recording`] can be written in the JSON Lines format, one event per line,
+//! e.g. to attach it to a bug report and reproduce the execution of the guest later.
+
+use crate::{Instance, Value};
+use std::io::Write;
+
+/// How a call of a function has ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CallOutcome {
+    /// The function has returned the optional value.
+    Returned(Option<Value>),
+    /// The function has trapped with the optional reason.
+    Trapped(Option<String>),
+}
+
+/// An interaction of the host with the instance.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A call of an imported function by the guest.
+    ImportCall {
+        module: String,
+        name: String,
+        args: Vec<Value>,
+        outcome: CallOutcome,
+    },
+    /// A write of a memory of the instance by the host, including the host functions.
+    MemoryWrite {
+        memory_idx: u32,
+        offset: u32,
+        data: Vec<u8>,
+    },
+}
+
+/// The events recorded for an instance, in the order the calls were made.
+///
+/// A call made by a host function calling back into the instance follows the call of that
+/// host function.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recording {
+    events: Vec<Event>,
+}
+
+impl Recording {
+    /// The recorded events.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Add the call with a trapped outcome, returning its index to set the outcome when returned.
+    pub(crate) fn begin_call(&mut self, module: &str, name: &str, args: Vec<Value>) -> usize {
+        self.events.push(Event::ImportCall {
+            module: module.to_string(),
+            name: name.to_string(),
+            args,
+            outcome: CallOutcome::Trapped(None),
+        });
+        self.events.len() - 1
+    }
+
+    /// Set the outcome of the call of the index returned by [`Recording::begin_call()`].
+    pub(crate) fn end_call(&mut self, index: usize, result: CallOutcome) {
+        if let Some(Event::ImportCall { outcome, .. }) = self.events.get_mut(index) {
+            *outcome = result;
+        }
+    }
+
+    pub(crate) fn memory_write(&mut self, memory_idx: u32, offset: u32, data: &[u8]) {
+        self.events.push(Event::MemoryWrite {
+            memory_idx,
+            offset,
+            data: data.to_vec(),
+        });
+    }
+
+    /// Write the events in the JSON Lines format, one JSON object per line.
+    ///
+    /// Values are objects of a single member named after their type. The floating-point values
+    /// are written as their bit patterns, so that NaN payloads are preserved. Memory data is
+    /// written as a hex string, the index of the memory only if it is not the memory 0.
+    /// For example:
+    ///
+    /// ```text
+    /// {"event":"import_call","module":"env","name":"read","args":[{"i32":2}],"returned":{"f32":1065353216}}
+    /// {"event":"import_call","module":"env","name":"fail","args":[],"trapped":"reason"}
+    /// {"event":"memory_write","offset":16,"data":"0a0b"}
+    /// {"event":"memory_write","memory":1,"offset":0,"data":"ff"}
+    /// ```
+    pub fn write_json_lines<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        for event in &self.events {
+            let line = match event {
+                Event::ImportCall {
+                    module,
+                    name,
+                    args,
+                    outcome,
+                } => {
+                    let args: Vec<String> = args.iter().map(|&arg| json_value(arg)).collect();
+                    let outcome = match outcome {
+                        CallOutcome::Returned(value) => format!(
+                            "\"returned\":{}",
+                            value.map_or_else(|| "null".to_string(), json_value)
+                        ),
+                        CallOutcome::Trapped(reason) => format!(
+                            "\"trapped\":{}",
+                            reason
+                                .as_deref()
+                                .map_or_else(|| "null".to_string(), json_string)
+                        ),
+                    };
+                    format!(
+                        "{{\"event\":\"import_call\",\"module\":{},\"name\":{},\"args\":[{}],{}}}",
+                        json_string(module),
+                        json_string(name),
+                        args.join(","),
+                        outcome
+                    )
+                }
+                Event::MemoryWrite {
+                    memory_idx,
+                    offset,
+                    data,
+                } => {
+                    let memory = match memory_idx {
+                        0 => String::new(),
+                        _ => format!("\"memory\":{},", memory_idx),
+                    };
+                    let data: String = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+                    format!(
+                        "{{\"event\":\"memory_write\",{}\"offset\":{},\"data\":\"{}\"}}",
+                        memory, offset, data
+                    )
+                }
+            };
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+fn json_value(value: Value) -> String {
+    match value {
+        Value::I32(v) => format!("{{\"i32\":{}}}", v),
+        Value::I64(v) => format!("{{\"i64\":{}}}", v),
+        Value::F32(v) => format!("{{\"f32\":{}}}", v.to_bits()),
+        Value::F64(v) => format!("{{\"f64\":{}}}", v.to_bits()),
+    }
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Instance {
+    /// Start recording the interactions of the host with the instance,
+    /// discarding the events recorded so far.
+    pub fn start_recording(&mut self) {
+        self.1.recording.replace(Some(Recording::default()));
+    }
+
+    /// Stop recording, returning the recorded events, or `None` if recording was not active.
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.1.recording.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::{HostFunction, Imports};
+    use crate::linker::FunctionType;
+    use crate::test_utils::from_hex;
+    use crate::{parse, ValueType};
+
+    /* wat2wasm
+      (func $read (import "env" "read") (param i32) (result i32))
+      (memory 1)
+      (func (export "sum") (param i32) (result i32)
+        (i32.add (call $read (local.get 0)) (i32.load (i32.const 16)))
+      )
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001060160017f017f020c0103656e760472656164000003020100050301000107",
+        "07010373756d00010a0e010c002000100041102802006a0b",
+    ];
+
+    fn instance() -> Instance {
+        let mut imports = Imports::new();
+        imports.define(
+            "env",
+            "read",
+            HostFunction::new(
+                FunctionType {
+                    inputs: vec![ValueType::I32],
+                    output: Some(ValueType::I32),
+                },
+                |args| match args[0] {
+                    Value::I32(0) => Err("no \"zero\"".to_string()),
+                    Value::I32(v) => Ok(Some(Value::I32(v * 10))),
+                    _ => unreachable!(),
+                },
+            ),
+        );
+        parse(from_hex(WASM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    #[test]
+    fn record() {
+        let mut instance = instance();
+        assert_eq!(instance.stop_recording(), None);
+        // Not recorded.
+        instance.memory_set(16, &[1]).unwrap();
+
+        instance.start_recording();
+        instance.memory_set(16, &[2, 0]).unwrap();
+        let result = instance.execute("sum", &[Value::I32(3)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(32)));
+        assert!(instance.execute("sum", &[Value::I32(0)]).unwrap().trapped());
+
+        let recording = instance.stop_recording().unwrap();
+        assert_eq!(
+            recording.events(),
+            [
+                Event::MemoryWrite {
+                    memory_idx: 0,
+                    offset: 16,
+                    data: vec![2, 0]
+                },
+                Event::ImportCall {
+                    module: "env".to_string(),
+                    name: "read".to_string(),
+                    args: vec![Value::I32(3)],
+                    outcome: CallOutcome::Returned(Some(Value::I32(30))),
+                },
+                Event::ImportCall {
+                    module: "env".to_string(),
+                    name: "read".to_string(),
+                    args: vec![Value::I32(0)],
+                    outcome: CallOutcome::Trapped(Some("no \"zero\"".to_string())),
+                },
+            ]
+        );
+
+        let mut json = Vec::new();
+        recording.write_json_lines(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            concat!(
+                "{\"event\":\"memory_write\",\"offset\":16,\"data\":\"0200\"}\n",
+                "{\"event\":\"import_call\",\"module\":\"env\",\"name\":\"read\",",
+                "\"args\":[{\"i32\":3}],\"returned\":{\"i32\":30}}\n",
+                "{\"event\":\"import_call\",\"module\":\"env\",\"name\":\"read\",",
+                "\"args\":[{\"i32\":0}],\"trapped\":\"no \\\"zero\\\"\"}\n",
+            )
+        );
+
+        // Recording has stopped.
+        instance.execute("sum", &[Value::I32(3)]).unwrap();
+        assert_eq!(instance.stop_recording(), None);
+    }
+
+    #[test]
+    fn record_caller_memory_writes() {
+        let mut imports = Imports::new();
+        imports.define(
+            "env",
+            "read",
+            HostFunction::new_with_caller(
+                FunctionType {
+                    inputs: vec![ValueType::I32],
+                    output: Some(ValueType::I32),
+                },
+                |caller, args| {
+                    caller.memory_set(16, &[5]).map_err(|e| e.to_string())?;
+                    Ok(Some(Value::I32(args[0].as_i32().unwrap() * 10)))
+                },
+            ),
+        );
+        let mut instance = parse(from_hex(WASM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+
+        instance.start_recording();
+        let result = instance.execute("sum", &[Value::I32(3)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(35)));
+        assert_eq!(
+            instance.stop_recording().unwrap().events(),
+            [
+                Event::ImportCall {
+                    module: "env".to_string(),
+                    name: "read".to_string(),
+                    args: vec![Value::I32(3)],
+                    outcome: CallOutcome::Returned(Some(Value::I32(30))),
+                },
+                Event::MemoryWrite {
+                    memory_idx: 0,
+                    offset: 16,
+                    data: vec![5]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn json_values() {
+        assert_eq!(json_value(Value::I64(-1)), "{\"i64\":-1}");
+        assert_eq!(json_value(Value::F32(1.0)), "{\"f32\":1065353216}");
+        assert_eq!(
+            json_value(Value::f64_from_bits(0x7ff8000000000001)),
+            "{\"f64\":9221120237041090561}"
+        );
+        assert_eq!(json_string("a\u{1}\t"), "\"a\\u0001\\t\"");
+    }
+}
@@ -0,0 +1,177 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A registry of named instances, resolving the imports of subsequently instantiated modules.
+//!
+//! Instances registered in a [`Registry`] under a name (e.g. `env` or `wasi_snapshot_preview1`)
+//! provide their exports to the modules instantiated through the registry which import from
+//! the namespace of that name.
+
+use crate::host::{Imports, SharedInstance};
+use crate::{Error, Instance, Module};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A set of instances registered under names.
+#[derive(Default)]
+pub struct Registry {
+    instances: HashMap<String, SharedInstance>,
+}
+
+impl Registry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Registry {
+            instances: HashMap::new(),
+        }
+    }
+
+    /// Register the instance under the `name`, replacing any previous one.
+    ///
+    /// Instances already importing from the previous instance keep using it.
+    pub fn register(&mut self, name: &str, instance: Instance) -> SharedInstance {
+        let instance = Arc::new(Mutex::new(instance));
+        self.register_shared(name, &instance);
+        instance
+    }
+
+    /// Register the shared instance under the `name`, replacing any previous one.
+    pub fn register_shared(&mut self, name: &str, instance: &SharedInstance) {
+        self.instances.insert(name.to_string(), instance.clone());
+    }
+
+    /// Returns the instance registered under the `name`.
+    pub fn get(&self, name: &str) -> Option<&SharedInstance> {
+        self.instances.get(name)
+    }
+
+    /// Create an instance of a module, resolving its imports with the registered instances.
+    pub fn instantiate(&self, module: Module) -> Result<Instance, Error> {
+        self.instantiate_with_imports(module, Imports::new())
+    }
+
+    /// Create an instance of a module, resolving its imports with the `imports` and
+    /// the registered instances.
+    ///
+    /// The host functions and instances defined in the `imports` take precedence over
+    /// the registered instances.
+    pub fn instantiate_with_imports(
+        &self,
+        module: Module,
+        mut imports: Imports,
+    ) -> Result<Instance, Error> {
+        for (name, instance) in &self.instances {
+            imports.define_fallback_instance(name, instance);
+        }
+        module.instantiate_with_imports(imports)
+    }
+
+    /// Create an instance of a module and register it under the `name`.
+    pub fn instantiate_and_register(
+        &mut self,
+        name: &str,
+        module: Module,
+    ) -> Result<SharedInstance, Error> {
+        let instance = self.instantiate(module)?;
+        Ok(self.register(name, instance))
+    }
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Registry")
+            .field("instances", &self.instances.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::HostFunction;
+    use crate::linker::FunctionType;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+    use crate::{Value, ValueType};
+
+    /* wat2wasm
+      (func (export "one") (result i32) (i32.const 1))
+    */
+    const BASE_WASM: &[&str] =
+        &["0061736d010000000105016000017f03020100070701036f6e6500000a0601040041010b"];
+
+    /* wat2wasm
+      (func $one (import "base" "one") (result i32))
+      (func (export "two") (result i32) (i32.add (call $one) (call $one)))
+    */
+    const MID_WASM: &[&str] = &[
+        "0061736d010000000105016000017f020c010462617365036f6e650000030201000707010374776f",
+        "00010a09010700100010006a0b",
+    ];
+
+    /* wat2wasm
+      (func $two (import "mid" "two") (result i32))
+      (func $one (import "base" "one") (result i32))
+      (func (export "three") (result i32) (i32.add (call $two) (call $one)))
+    */
+    const APP_WASM: &[&str] = &[
+        "0061736d010000000105016000017f021602036d69640374776f00000462617365036f6e65000003",
+        "02010007090105746872656500020a09010700100010016a0b",
+    ];
+
+    #[test]
+    fn resolve_registered_instances() {
+        let mut registry = Registry::new();
+        assert_eq!(
+            registry
+                .instantiate(parse(from_hex(MID_WASM)).unwrap())
+                .err(),
+            Some(Error::UnknownImport {
+                module: "base".to_string(),
+                name: "one".to_string()
+            })
+        );
+
+        registry
+            .instantiate_and_register("base", parse(from_hex(BASE_WASM)).unwrap())
+            .unwrap();
+        registry
+            .instantiate_and_register("mid", parse(from_hex(MID_WASM)).unwrap())
+            .unwrap();
+        assert!(registry.get("mid").is_some());
+        assert!(registry.get("app").is_none());
+
+        let mut app = registry
+            .instantiate(parse(from_hex(APP_WASM)).unwrap())
+            .unwrap();
+        let result = app.execute("three", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(3)));
+    }
+
+    #[test]
+    fn imports_take_precedence() {
+        let mut registry = Registry::new();
+        registry
+            .instantiate_and_register("base", parse(from_hex(BASE_WASM)).unwrap())
+            .unwrap();
+
+        let mut imports = Imports::new();
+        imports.define(
+            "base",
+            "one",
+            HostFunction::new(
+                FunctionType {
+                    inputs: vec![],
+                    output: Some(ValueType::I32),
+                },
+                |_| Ok(Some(Value::I32(2))),
+            ),
+        );
+        let mut mid = registry
+            .instantiate_with_imports(parse(from_hex(MID_WASM)).unwrap(), imports)
+            .unwrap();
+        let result = mid.execute("two", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(4)));
+    }
+}
@@ -0,0 +1,703 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Executions which can be suspended and resumed later.
+//!
+//! An execution started with [`Instance::execute_resumable()`] is suspended when it runs out of
+//! fuel or when the suspension is requested with a [`SuspendHandle`], either from another thread
+//! or from a host function. The [`SuspendedExecution`] can be resumed later, so the caller can
+//! interleave many executions cooperatively.
+//!
+//! [`Instance::execute_async()`] returns a future of the execution result. The execution is
+//! suspended while a future returned by an asynchronous host function
+//! (see [`HostFunction::new_async()`](crate::host::HostFunction::new_async)) is pending.
+//!
+//! The interpreter keeps the state of an execution on the native stack, therefore a resumable
+//! execution runs on a dedicated thread, which is parked while the execution is suspended.
+//! Only one of the caller and the execution runs at a time, and host functions are called
+//! on the execution thread.
+
+use crate::gas::CostTable;
+use crate::host::HostResult;
+use crate::{sys, Error, ExecutionResult, Instance, Value};
+use std::cell::RefCell;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::JoinHandle;
+
+/// The reason of a suspension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SuspendReason {
+    /// The cost of the next instruction exceeds the remaining fuel.
+    OutOfFuel,
+    /// The suspension has been requested with a [`SuspendHandle`].
+    Requested,
+}
+
+/// A handle requesting the suspension of the executions it has been given to.
+///
+/// The handle can be cloned and shared between threads.
+#[derive(Clone, Debug, Default)]
+pub struct SuspendHandle(Arc<AtomicBool>);
+
+impl SuspendHandle {
+    /// Create a new handle.
+    pub fn new() -> Self {
+        SuspendHandle(Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Request the suspension of the execution before its next instruction.
+    ///
+    /// If no execution is running, the next one started or resumed is suspended immediately.
+    pub fn suspend(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+
+    /// Take the pending request.
+    fn take_request(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// The state of a resumable execution.
+pub enum Execution<'a> {
+    /// The execution has finished.
+    Finished(ExecutionResult),
+    /// The execution has been suspended.
+    Suspended(SuspendedExecution<'a>),
+}
+
+impl<'a> Execution<'a> {
+    /// Returns the result if the execution has finished.
+    pub fn finished(self) -> Option<ExecutionResult> {
+        match self {
+            Execution::Finished(result) => Some(result),
+            Execution::Suspended(_) => None,
+        }
+    }
+}
+
+/// The future returned by an asynchronous host function.
+pub(crate) type HostFuture = Pin<Box<dyn Future<Output = HostResult> + Send>>;
+
+/// Messages from the execution thread.
+enum Event {
+    Suspended(SuspendReason, u64),
+    /// A host future must be awaited before the execution continues.
+    Await(HostFuture),
+    Finished(ExecutionResult),
+}
+
+/// Messages to the execution thread waiting for the caller.
+enum Command {
+    /// Start or resume with the additional fuel.
+    Resume(u64),
+    /// Continue with the result of the awaited host future.
+    Complete(HostResult),
+    /// Abort the execution with a trap.
+    Abort,
+}
+
+/// The ends of the channels used by the execution thread.
+struct Channels {
+    events: Sender<Event>,
+    commands: Receiver<Command>,
+}
+
+thread_local! {
+    /// The channels of the resumable execution running on this thread.
+    static CHANNELS: RefCell<Option<Channels>> = const { RefCell::new(None) };
+}
+
+/// Send the event to the caller and wait for its command, on the execution thread.
+///
+/// Returns None if the caller has gone away.
+fn exchange(event: Event) -> Option<Command> {
+    CHANNELS.with(|channels| {
+        let channels = channels.borrow();
+        let channels = channels.as_ref().expect("resumable execution thread");
+        channels.events.send(event).ok()?;
+        channels.commands.recv().ok()
+    })
+}
+
+/// Wait for the result of a host future.
+///
+/// Within a resumable execution the future is awaited by the caller, otherwise the thread blocks
+/// until the future is ready.
+pub(crate) fn await_host_future(future: HostFuture) -> HostResult {
+    if !CHANNELS.with(|channels| channels.borrow().is_some()) {
+        return block_on(future);
+    }
+    match exchange(Event::Await(future)) {
+        Some(Command::Complete(result)) => result,
+        _ => Err("execution aborted".to_string()),
+    }
+}
+
+/// Wakes the thread blocked on a future.
+struct ThreadWaker(std::thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark()
+    }
+}
+
+/// Poll the future until it is ready, parking the thread while it is pending.
+fn block_on<T>(mut future: Pin<Box<dyn Future<Output = T> + Send>>) -> T {
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(value) => return value,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+/// The pointer to the instance borrowed by the execution thread.
+struct InstancePtr(*mut Instance);
+
+// The instance is used by one thread at a time: the caller waits while the execution runs.
+unsafe impl Send for InstancePtr {}
+
+/// The execution thread and the channels to communicate with it.
+struct Worker {
+    thread: JoinHandle<()>,
+    commands: Sender<Command>,
+    events: Receiver<Event>,
+}
+
+impl Worker {
+    /// Spawn the execution thread, which waits for the command to start running `run`.
+    fn spawn<F>(instance: &mut Instance, run: F) -> Self
+    where
+        F: FnOnce(&mut Instance) -> ExecutionResult + Send + 'static,
+    {
+        let (commands, commands_receiver) = channel();
+        let (events_sender, events) = channel();
+        let instance = InstancePtr(instance);
+        let thread = std::thread::spawn(move || {
+            if let Ok(Command::Resume(_)) = commands_receiver.recv() {
+                CHANNELS.with(|channels| {
+                    *channels.borrow_mut() = Some(Channels {
+                        events: events_sender.clone(),
+                        commands: commands_receiver,
+                    })
+                });
+                let result = run(unsafe { &mut *instance.0 });
+                let _ = events_sender.send(Event::Finished(result));
+            }
+        });
+        Worker {
+            thread,
+            commands,
+            events,
+        }
+    }
+
+    fn send(&self, command: Command) {
+        // The execution thread waits for the command, so it cannot be disconnected.
+        let _ = self.commands.send(command);
+    }
+
+    /// Wait for the next event of the execution.
+    fn next_event(&self) -> Option<Event> {
+        self.events.recv().ok()
+    }
+
+    /// Wait until the execution is suspended or finished. Host futures are awaited by blocking.
+    fn wait<'a>(self) -> Execution<'a> {
+        loop {
+            match self.next_event() {
+                Some(Event::Suspended(reason, remaining_fuel)) => {
+                    return Execution::Suspended(SuspendedExecution {
+                        reason,
+                        remaining_fuel,
+                        worker: Some(self),
+                        instance: PhantomData,
+                    })
+                }
+                Some(Event::Await(future)) => self.send(Command::Complete(block_on(future))),
+                Some(Event::Finished(result)) => {
+                    self.join();
+                    return Execution::Finished(result);
+                }
+                None => {
+                    self.join();
+                    unreachable!("execution thread has ended without a result")
+                }
+            }
+        }
+    }
+
+    /// Abort the execution and wait for the thread to end.
+    fn abort(self) -> Option<ExecutionResult> {
+        self.send(Command::Abort);
+        loop {
+            match self.next_event() {
+                Some(Event::Await(_)) | Some(Event::Suspended(..)) => self.send(Command::Abort),
+                Some(Event::Finished(result)) => {
+                    self.join();
+                    return Some(result);
+                }
+                None => {
+                    self.join();
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn join(self) {
+        if let Err(panic) = self.thread.join() {
+            std::panic::resume_unwind(panic)
+        }
+    }
+}
+
+/// An execution which has been suspended. It is aborted with a trap when dropped.
+pub struct SuspendedExecution<'a> {
+    reason: SuspendReason,
+    remaining_fuel: u64,
+    worker: Option<Worker>,
+    instance: PhantomData<&'a mut Instance>,
+}
+
+impl<'a> SuspendedExecution<'a> {
+    /// The reason of the suspension.
+    pub fn reason(&self) -> SuspendReason {
+        self.reason
+    }
+
+    /// The fuel left at the moment of the suspension.
+    pub fn remaining_fuel(&self) -> u64 {
+        self.remaining_fuel
+    }
+
+    /// Resume the execution with the additional fuel.
+    pub fn resume(mut self, fuel: u64) -> Execution<'a> {
+        let worker = self.worker.take().expect("worker of suspended execution");
+        worker.send(Command::Resume(fuel));
+        worker.wait()
+    }
+
+    /// Abort the execution with a trap, returning its result.
+    pub fn abort(mut self) -> ExecutionResult {
+        let worker = self.worker.take().expect("worker of suspended execution");
+        worker.abort().expect("result of aborted execution")
+    }
+}
+
+impl<'a> Drop for SuspendedExecution<'a> {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            worker.abort();
+        }
+    }
+}
+
+impl<'a> std::fmt::Debug for SuspendedExecution<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SuspendedExecution")
+            .field("reason", &self.reason)
+            .field("remaining_fuel", &self.remaining_fuel)
+            .finish()
+    }
+}
+
+/// The future of an asynchronous execution. The execution is aborted with a trap when dropped.
+pub struct ExecutionFuture<'a> {
+    worker: Option<Worker>,
+    started: bool,
+    /// The host future the execution is waiting for.
+    pending: Option<HostFuture>,
+    instance: PhantomData<&'a mut Instance>,
+}
+
+impl<'a> Future for ExecutionFuture<'a> {
+    type Output = ExecutionResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<ExecutionResult> {
+        let this = &mut *self;
+        let worker = this
+            .worker
+            .as_ref()
+            .expect("execution future polled after completion");
+        if !this.started {
+            this.started = true;
+            worker.send(Command::Resume(0));
+        }
+        loop {
+            if let Some(future) = &mut this.pending {
+                match future.as_mut().poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(result) => {
+                        this.pending = None;
+                        worker.send(Command::Complete(result));
+                    }
+                }
+            }
+            match worker.next_event() {
+                Some(Event::Await(future)) => this.pending = Some(future),
+                Some(Event::Finished(result)) => {
+                    this.worker.take().expect("worker").join();
+                    return Poll::Ready(result);
+                }
+                Some(Event::Suspended(..)) | None => {
+                    unreachable!("asynchronous execution has been suspended")
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Drop for ExecutionFuture<'a> {
+    fn drop(&mut self) {
+        // Drop the pending host future on this thread, before the execution ends.
+        self.pending = None;
+        if let Some(worker) = self.worker.take() {
+            worker.abort();
+        }
+    }
+}
+
+impl Instance {
+    /// Execute an exported function which can be suspended and resumed.
+    ///
+    /// The cost of every instruction is charged from the fuel before the instruction is executed,
+    /// the execution is suspended if the fuel is not sufficient. The `handle` can be used
+    /// to request a suspension.
+    ///
+    /// Futures of asynchronous host functions are awaited by blocking the calling thread.
+    pub fn execute_resumable(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        fuel: u64,
+        costs: &CostTable,
+        handle: &SuspendHandle,
+    ) -> Result<Execution<'_>, Error> {
+        let func_idx = self
+            .find_exported_function_index(name)
+            .ok_or(Error::FunctionNotFound)?;
+        self.check_arguments(func_idx, args)?;
+
+        let args = args.to_vec();
+        let costs = costs.clone();
+        let handle = handle.clone();
+        let worker = Worker::spawn(self, move |instance| {
+            let mut remaining = fuel;
+            let mut hook = |state: &sys::FizzyExecutionState| loop {
+                let cost = costs.cost(state.opcode);
+                let reason = if handle.take_request() {
+                    SuspendReason::Requested
+                } else if cost > remaining {
+                    SuspendReason::OutOfFuel
+                } else {
+                    remaining -= cost;
+                    return true;
+                };
+                match exchange(Event::Suspended(reason, remaining)) {
+                    Some(Command::Resume(fuel)) => remaining = remaining.saturating_add(fuel),
+                    _ => return false,
+                }
+            };
+            instance
+                .execute_function_with_hook(func_idx, &args, &mut hook)
+                .expect("arguments of resumable execution checked")
+        });
+        worker.send(Command::Resume(0));
+        Ok(worker.wait())
+    }
+
+    /// Execute an exported function, returning the future of the result.
+    ///
+    /// The execution starts when the future is first polled. While a future returned by
+    /// an asynchronous host function is pending, the execution is suspended and the returned
+    /// future is pending too.
+    pub fn execute_async(
+        &mut self,
+        name: &str,
+        args: &[Value],
+    ) -> Result<ExecutionFuture<'_>, Error> {
+        let func_idx = self
+            .find_exported_function_index(name)
+            .ok_or(Error::FunctionNotFound)?;
+        self.check_arguments(func_idx, args)?;
+
+        let args = args.to_vec();
+        let worker = Worker::spawn(self, move |instance| {
+            instance
+                .execute_function(func_idx, &args)
+                .expect("arguments of asynchronous execution checked")
+        });
+        Ok(ExecutionFuture {
+            worker: Some(worker),
+            started: false,
+            pending: None,
+            instance: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::{HostFunction, Imports};
+    use crate::linker::FunctionType;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+
+    /* wat2wasm
+      (func $yield (import "env" "yield"))
+      (func (export "sum") (param $n i32) (result i32) (local $acc i32)
+        (block
+          (loop
+            (br_if 1 (i32.eqz (local.get $n)))
+            (call $yield)
+            (local.set $acc (i32.add (local.get $acc) (local.get $n)))
+            (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+            (br 0)
+          )
+        )
+        (local.get $acc)
+      )
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001090260000060017f017f020d0103656e76057969656c640000030201010707",
+        "010373756d00010a25012301017f024003402000450d011000200120006a2101200041016b21000c",
+        "000b0b20010b",
+    ];
+
+    /// Instantiate the module with `yield` requesting the suspension with the handle.
+    fn instantiate(handle: &SuspendHandle) -> Instance {
+        let handle = handle.clone();
+        let mut imports = Imports::new();
+        imports.define(
+            "env",
+            "yield",
+            HostFunction::new(
+                FunctionType {
+                    inputs: vec![],
+                    output: None,
+                },
+                move |_| {
+                    handle.suspend();
+                    Ok(None)
+                },
+            ),
+        );
+        parse(from_hex(WASM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    #[test]
+    fn suspend_on_request() {
+        let handle = SuspendHandle::new();
+        let mut instance = instantiate(&handle);
+        let mut execution = instance
+            .execute_resumable(
+                "sum",
+                &[Value::I32(3)],
+                u64::MAX,
+                &CostTable::default(),
+                &handle,
+            )
+            .unwrap();
+        let mut suspensions = 0;
+        let result = loop {
+            match execution {
+                Execution::Finished(result) => break result,
+                Execution::Suspended(suspended) => {
+                    assert_eq!(suspended.reason(), SuspendReason::Requested);
+                    suspensions += 1;
+                    execution = suspended.resume(0);
+                }
+            }
+        };
+        assert_eq!(suspensions, 3);
+        assert_eq!(result.value(), Some(Value::I32(6)));
+    }
+
+    #[test]
+    fn suspend_out_of_fuel() {
+        let mut instance = instantiate(&SuspendHandle::new());
+        // The handle of the yield function is not given to the execution.
+        let handle = SuspendHandle::new();
+        let costs = CostTable::default();
+        let mut execution = instance
+            .execute_resumable("sum", &[Value::I32(100)], 10, &costs, &handle)
+            .unwrap();
+        let mut suspensions = 0;
+        let result = loop {
+            match execution {
+                Execution::Finished(result) => break result,
+                Execution::Suspended(suspended) => {
+                    assert_eq!(suspended.reason(), SuspendReason::OutOfFuel);
+                    assert!(suspended.remaining_fuel() < 10);
+                    suspensions += 1;
+                    execution = suspended.resume(10);
+                }
+            }
+        };
+        assert!(suspensions > 100);
+        assert_eq!(result.value(), Some(Value::I32(5050)));
+    }
+
+    #[test]
+    fn interleave_executions() {
+        let costs = CostTable::default();
+        let handle = SuspendHandle::new();
+        let mut instances = [instantiate(&handle), instantiate(&handle)];
+        let (first, second) = instances.split_at_mut(1);
+        let mut executions = vec![
+            first[0]
+                .execute_resumable("sum", &[Value::I32(10)], 5, &costs, &handle)
+                .unwrap(),
+            second[0]
+                .execute_resumable("sum", &[Value::I32(20)], 5, &costs, &handle)
+                .unwrap(),
+        ];
+        let mut results = Vec::new();
+        while !executions.is_empty() {
+            for execution in std::mem::take(&mut executions) {
+                match execution {
+                    Execution::Finished(result) => results.push(result.value()),
+                    Execution::Suspended(suspended) => executions.push(suspended.resume(5)),
+                }
+            }
+        }
+        assert_eq!(results, [Some(Value::I32(55)), Some(Value::I32(210))]);
+    }
+
+    #[test]
+    fn abort() {
+        let handle = SuspendHandle::new();
+        let mut instance = instantiate(&handle);
+        let costs = CostTable::default();
+        match instance
+            .execute_resumable("sum", &[Value::I32(3)], u64::MAX, &costs, &handle)
+            .unwrap()
+        {
+            Execution::Suspended(suspended) => assert!(suspended.abort().trapped()),
+            Execution::Finished(_) => panic!("execution has not been suspended"),
+        }
+
+        // Dropping the suspended execution aborts it, the instance can be used again.
+        let execution = instance
+            .execute_resumable("sum", &[Value::I32(3)], 1, &costs, &handle)
+            .unwrap();
+        assert!(execution.finished().is_none());
+        let result = instance.execute("sum", &[Value::I32(0)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(0)));
+
+        assert_eq!(
+            instance
+                .execute_resumable("sum", &[], 1, &costs, &handle)
+                .err(),
+            Some(Error::ArgumentCountMismatch)
+        );
+    }
+
+    /// A future of the host function which is ready once the gate has been opened.
+    #[derive(Clone, Default)]
+    struct Gate(Arc<Mutex<(bool, Option<Waker>)>>);
+
+    impl Gate {
+        fn open(&self) {
+            let mut state = self.0.lock().unwrap();
+            state.0 = true;
+            if let Some(waker) = state.1.take() {
+                waker.wake()
+            }
+        }
+
+        /// Wait for the gate to be opened, closing it again.
+        async fn pass(self) -> HostResult {
+            std::future::poll_fn(|cx| {
+                let mut state = self.0.lock().unwrap();
+                if std::mem::take(&mut state.0) {
+                    Poll::Ready(Ok(None))
+                } else {
+                    state.1 = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            })
+            .await
+        }
+    }
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn instantiate_async(gate: &Gate) -> Instance {
+        let gate = gate.clone();
+        let mut imports = Imports::new();
+        imports.define(
+            "env",
+            "yield",
+            HostFunction::new_async(
+                FunctionType {
+                    inputs: vec![],
+                    output: None,
+                },
+                move |_| gate.clone().pass(),
+            ),
+        );
+        parse(from_hex(WASM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    #[test]
+    fn execute_async() {
+        let gate = Gate::default();
+        let mut instance = instantiate_async(&gate);
+        let mut future = instance.execute_async("sum", &[Value::I32(2)]).unwrap();
+
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(wakes.clone());
+        let mut context = Context::from_waker(&waker);
+
+        // The execution waits at every call of the yield function.
+        for i in 0..2 {
+            assert!(Pin::new(&mut future).poll(&mut context).is_pending());
+            assert_eq!(wakes.0.load(Ordering::SeqCst), i);
+            gate.open();
+        }
+        match Pin::new(&mut future).poll(&mut context) {
+            Poll::Ready(result) => assert_eq!(result.value(), Some(Value::I32(3))),
+            Poll::Pending => panic!("execution has not finished"),
+        }
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 2);
+        drop(future);
+
+        // Dropping the pending future aborts the execution.
+        let mut future = instance.execute_async("sum", &[Value::I32(2)]).unwrap();
+        assert!(Pin::new(&mut future).poll(&mut context).is_pending());
+        drop(future);
+
+        // Blocking executions wait for the host future to be ready.
+        gate.open();
+        let result = instance.execute("sum", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+    }
+}
@@ -0,0 +1,197 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Cooperative scheduling of many executions on one thread.
+//!
+//! A [`Scheduler`] runs the executions spawned into it in the round-robin order, each for
+//! a slice of fuel. An execution running out of its slice is suspended and resumed in the next
+//! round, so the long executions do not starve the short ones.
+//!
+//! The executions are resumable executions (see [`Instance::execute_resumable()`]), driven by
+//! the thread running the scheduler. Each of them keeps its state on a parked thread of its own,
+//! but only one of them runs at a time.
+
+use crate::gas::CostTable;
+use crate::resumable::{Execution, SuspendHandle, SuspendedExecution};
+use crate::{Error, ExecutionResult, Instance, Value};
+use std::collections::VecDeque;
+
+/// The identifier of an execution spawned into a [`Scheduler`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+/// The executor running many executions of instances in turns.
+pub struct Scheduler<'a> {
+    slice_fuel: u64,
+    costs: CostTable,
+    next_id: u64,
+    /// The suspended executions, in the order they are resumed.
+    queue: VecDeque<(TaskId, SuspendedExecution<'a>)>,
+    /// The executions finished before their first turn.
+    finished: Vec<(TaskId, ExecutionResult)>,
+}
+
+impl<'a> Scheduler<'a> {
+    /// Create a scheduler resuming every execution with the `slice_fuel` in each round,
+    /// charging the cost of every instruction from the `costs`.
+    ///
+    /// Panics if the `slice_fuel` is 0.
+    pub fn new(slice_fuel: u64, costs: CostTable) -> Self {
+        assert!(slice_fuel > 0, "slice fuel must be positive");
+        Scheduler {
+            slice_fuel,
+            costs,
+            next_id: 0,
+            queue: VecDeque::new(),
+            finished: Vec::new(),
+        }
+    }
+
+    /// Spawn the execution of an exported function of the instance, to be started in the next
+    /// round.
+    ///
+    /// The arguments are checked against the function type and an error is returned on mismatch.
+    pub fn spawn(
+        &mut self,
+        instance: &'a mut Instance,
+        name: &str,
+        args: &[Value],
+    ) -> Result<TaskId, Error> {
+        let id = TaskId(self.next_id);
+        // Without fuel the execution is suspended before its first instruction.
+        match instance.execute_resumable(name, args, 0, &self.costs, &SuspendHandle::new())? {
+            Execution::Suspended(suspended) => self.queue.push_back((id, suspended)),
+            Execution::Finished(result) => self.finished.push((id, result)),
+        }
+        self.next_id += 1;
+        Ok(id)
+    }
+
+    /// The number of executions which have not finished yet.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns true if all executions have finished.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Resume every suspended execution once with the slice fuel, returning the results
+    /// of the executions finished in this round, in the order of their finish.
+    pub fn run_round(&mut self) -> Vec<(TaskId, ExecutionResult)> {
+        let mut finished = std::mem::take(&mut self.finished);
+        for _ in 0..self.queue.len() {
+            let (id, suspended) = self.queue.pop_front().expect("suspended execution");
+            match suspended.resume(self.slice_fuel) {
+                Execution::Suspended(suspended) => self.queue.push_back((id, suspended)),
+                Execution::Finished(result) => finished.push((id, result)),
+            }
+        }
+        finished
+    }
+
+    /// Run the rounds until all executions have finished, returning their results in the order
+    /// of their finish.
+    pub fn run(&mut self) -> Vec<(TaskId, ExecutionResult)> {
+        let mut finished = std::mem::take(&mut self.finished);
+        while !self.queue.is_empty() {
+            finished.extend(self.run_round());
+        }
+        finished
+    }
+
+    /// Abort the execution with a trap, returning its result. Returns None if the execution
+    /// is not suspended in the scheduler, e.g. it has finished.
+    pub fn abort(&mut self, id: TaskId) -> Option<ExecutionResult> {
+        let position = self.queue.iter().position(|(task, _)| *task == id)?;
+        let (_, suspended) = self.queue.remove(position)?;
+        Some(suspended.abort())
+    }
+}
+
+impl<'a> std::fmt::Debug for Scheduler<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("slice_fuel", &self.slice_fuel)
+            .field("pending", &self.queue.len())
+            .field("finished", &self.finished.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+
+    /* wat2wasm
+      (func (export "spin") (param i32) (result i32)
+        (loop (br_if 0 (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+        (local.get 0)
+      )
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001060160017f017f03020100070801047370696e00000a120110000340200041",
+        "016b22000d000b20000b",
+    ];
+
+    fn instantiate() -> Instance {
+        parse(from_hex(WASM)).unwrap().instantiate().unwrap()
+    }
+
+    #[test]
+    fn round_robin() {
+        let mut instances = [instantiate(), instantiate(), instantiate()];
+        let mut scheduler = Scheduler::new(100, CostTable::default());
+        let mut ids = Vec::new();
+        for (instance, iterations) in instances.iter_mut().zip(&[1000, 10, 100]) {
+            ids.push(
+                scheduler
+                    .spawn(instance, "spin", &[Value::I32(*iterations)])
+                    .unwrap(),
+            );
+        }
+        assert_eq!(scheduler.len(), 3);
+
+        // The short execution finishes in the first round.
+        let finished = scheduler.run_round();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].0, ids[1]);
+        assert_eq!(finished[0].1.value(), Some(Value::I32(0)));
+        assert_eq!(scheduler.len(), 2);
+
+        let finished = scheduler.run();
+        assert!(scheduler.is_empty());
+        let order: Vec<TaskId> = finished.iter().map(|(id, _)| *id).collect();
+        assert_eq!(order, [ids[2], ids[0]]);
+        assert!(finished
+            .iter()
+            .all(|(_, result)| result.value() == Some(Value::I32(0))));
+    }
+
+    #[test]
+    fn abort() {
+        let mut instances = [instantiate(), instantiate(), instantiate()];
+        let (first, rest) = instances.split_at_mut(1);
+        let (second, third) = rest.split_at_mut(1);
+        let mut scheduler = Scheduler::new(10, CostTable::default());
+        let long = scheduler
+            .spawn(&mut first[0], "spin", &[Value::I32(1000)])
+            .unwrap();
+        let short = scheduler
+            .spawn(&mut second[0], "spin", &[Value::I32(1)])
+            .unwrap();
+        assert_eq!(
+            scheduler.spawn(&mut third[0], "spin", &[]).err(),
+            Some(Error::ArgumentCountMismatch)
+        );
+
+        scheduler.run_round();
+        assert!(scheduler.abort(long).unwrap().trapped());
+        assert!(scheduler.abort(short).is_none());
+        assert!(scheduler.is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Introspection of the segments initializing memories and tables of a module.
+
+use crate::{sys, ConstantExpression, Module};
+
+/// How a data segment is used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DataMode {
+    /// The segment is copied into the memory at instantiation.
+    Active {
+        memory_index: u32,
+        /// The offset in the memory, of i32 type.
+        offset: ConstantExpression,
+    },
+    /// The segment is only copied by `memory.init` instructions.
+    ///
+    /// Passive segments are part of the bulk memory operations proposal,
+    /// modules containing them are currently rejected by the parser.
+    Passive,
+}
+
+/// A data segment of a module.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataSegment {
+    pub mode: DataMode,
+    pub bytes: Vec<u8>,
+}
+
+/// An element segment of a module, initializing a table with function references.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ElementSegment {
+    pub table_index: u32,
+    /// The offset in the table, of i32 type.
+    pub offset: ConstantExpression,
+    pub func_indices: Vec<u32>,
+}
+
+impl Module {
+    /// Returns the data segments of the module, in the order of their definition.
+    pub fn data_segments(&self) -> Vec<DataSegment> {
+        let module = self.0.as_ptr();
+        let count = unsafe { sys::fizzy_get_data_segment_count(module) };
+        (0..count)
+            .map(|data_idx| {
+                let data = unsafe { sys::fizzy_get_data_segment(module, data_idx) };
+                let bytes = if data.size == 0 {
+                    Vec::new()
+                } else {
+                    unsafe { std::slice::from_raw_parts(data.data, data.size) }.to_vec()
+                };
+                DataSegment {
+                    mode: DataMode::Active {
+                        memory_index: data.memory_index,
+                        offset: ConstantExpression::from_sys(&data.offset, sys::FizzyValueTypeI32),
+                    },
+                    bytes,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the element segments of the module, in the order of their definition.
+    pub fn element_segments(&self) -> Vec<ElementSegment> {
+        let module = self.0.as_ptr();
+        let count = unsafe { sys::fizzy_get_element_segment_count(module) };
+        (0..count)
+            .map(|elem_idx| {
+                let element = unsafe { sys::fizzy_get_element_segment(module, elem_idx) };
+                let func_indices = if element.func_indices_size == 0 {
+                    Vec::new()
+                } else {
+                    unsafe {
+                        std::slice::from_raw_parts(element.func_indices, element.func_indices_size)
+                    }
+                    .to_vec()
+                };
+                ElementSegment {
+                    table_index: element.table_index,
+                    offset: ConstantExpression::from_sys(&element.offset, sys::FizzyValueTypeI32),
+                    func_indices,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::from_hex;
+    use crate::{parse, Value};
+
+    #[test]
+    fn data_segments() {
+        /* wat2wasm
+          (global (import "env" "g") i32)
+          (memory 1)
+          (data (i32.const 16) "abc")
+          (data (global.get 0) "")
+        */
+        let module = parse(from_hex(&[
+            "0061736d01000000020a0103656e760167037f0005030100010b0e020041100b036162630023000b",
+            "00",
+        ]))
+        .unwrap();
+        assert_eq!(
+            module.data_segments(),
+            vec![
+                DataSegment {
+                    mode: DataMode::Active {
+                        memory_index: 0,
+                        offset: ConstantExpression::Constant(Value::I32(16)),
+                    },
+                    bytes: b"abc".to_vec(),
+                },
+                DataSegment {
+                    mode: DataMode::Active {
+                        memory_index: 0,
+                        offset: ConstantExpression::GlobalGet(0),
+                    },
+                    bytes: Vec::new(),
+                },
+            ]
+        );
+
+        /* wat2wasm
+          (memory 1)
+        */
+        let module = parse(from_hex(&["0061736d010000000503010001"])).unwrap();
+        assert!(module.data_segments().is_empty());
+    }
+
+    #[test]
+    fn element_segments() {
+        /* wat2wasm
+          (global (import "env" "g") i32)
+          (table 4 anyfunc)
+          (elem (i32.const 1) $f1 $f0 $f1)
+          (elem (global.get 0))
+          (func $f0)
+          (func $f1)
+        */
+        let module = parse(from_hex(&[
+            "0061736d01000000010401600000020a0103656e760167037f000303020000040401700004090e02",
+            "0041010b030100010023000b000a070202000b02000b",
+        ]))
+        .unwrap();
+        assert_eq!(
+            module.element_segments(),
+            vec![
+                ElementSegment {
+                    table_index: 0,
+                    offset: ConstantExpression::Constant(Value::I32(1)),
+                    func_indices: vec![1, 0, 1],
+                },
+                ElementSegment {
+                    table_index: 0,
+                    offset: ConstantExpression::GlobalGet(0),
+                    func_indices: Vec::new(),
+                },
+            ]
+        );
+        assert!(module.data_segments().is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Self-checking executions, detecting nondeterminism.
+//!
+//! A [`SelfCheckedInstance`] executes every call on two independently created instances of
+//! the same module and compares the results and the [state hashes](Instance::state_hash()) of
+//! the instances afterwards. Any difference is reported as [`Error::Diverged`].
+//!
+//! The host functions are called once per instance, so the imports of the instances must not
+//! share any state. Otherwise the executions diverge because of the imports.
+
+use crate::record::CallOutcome;
+use crate::{Error, ExecutionResult, Instance, Value};
+
+/// A difference between the two executions of a self-checked call.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Divergence {
+    /// The executions have ended differently.
+    Outcome {
+        primary: CallOutcome,
+        shadow: CallOutcome,
+    },
+    /// The states of the instances differ, given by their hashes.
+    State { primary: [u8; 32], shadow: [u8; 32] },
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Divergence::Outcome { primary, shadow } => {
+                write!(f, "outcome {:?} differs from {:?}", primary, shadow)
+            }
+            Divergence::State { .. } => f.write_str("instance states differ"),
+        }
+    }
+}
+
+/// A pair of instances executing every call twice.
+pub struct SelfCheckedInstance {
+    primary: Instance,
+    shadow: Instance,
+}
+
+impl SelfCheckedInstance {
+    /// Pair the instances, which must be created independently from the same module.
+    ///
+    /// Fails with [`Error::Diverged`] if the states of the instances already differ.
+    pub fn new(primary: Instance, shadow: Instance) -> Result<Self, Error> {
+        let instance = SelfCheckedInstance { primary, shadow };
+        instance.check_state()?;
+        Ok(instance)
+    }
+
+    /// Create the pair of instances of the module without imports.
+    pub fn instantiate<T: AsRef<[u8]>>(input: T) -> Result<Self, Error> {
+        let input = input.as_ref();
+        Self::new(
+            crate::parse(input)?.instantiate()?,
+            crate::parse(input)?.instantiate()?,
+        )
+    }
+
+    /// The instance whose results are returned.
+    pub fn primary(&self) -> &Instance {
+        &self.primary
+    }
+
+    /// The instance checking the results of the primary one.
+    pub fn shadow(&self) -> &Instance {
+        &self.shadow
+    }
+
+    /// Unpair the instances, returning the primary one first.
+    pub fn into_instances(self) -> (Instance, Instance) {
+        (self.primary, self.shadow)
+    }
+
+    /// Execute an exported function by name on both instances.
+    ///
+    /// Returns the result of the primary instance, or [`Error::Diverged`] if the executions
+    /// have ended differently or have left the instances in different states. The trap reasons
+    /// are not compared, and the values are compared by their bit patterns.
+    pub fn execute(&mut self, name: &str, args: &[Value]) -> Result<ExecutionResult, Error> {
+        let primary = self.primary.execute(name, args)?;
+        let shadow = self.shadow.execute(name, args)?;
+
+        if primary.trapped() != shadow.trapped() || !same_value(primary.value(), shadow.value()) {
+            return Err(Error::Diverged(Divergence::Outcome {
+                primary: outcome(&primary),
+                shadow: outcome(&shadow),
+            }));
+        }
+
+        self.check_state()?;
+        Ok(primary)
+    }
+
+    fn check_state(&self) -> Result<(), Error> {
+        let primary = self.primary.state_hash();
+        let shadow = self.shadow.state_hash();
+        if primary != shadow {
+            return Err(Error::Diverged(Divergence::State { primary, shadow }));
+        }
+        Ok(())
+    }
+}
+
+fn same_value(primary: Option<Value>, shadow: Option<Value>) -> bool {
+    match (primary, shadow) {
+        (Some(p), Some(s)) => p.value_type() == s.value_type() && p.to_bits() == s.to_bits(),
+        (p, s) => p.is_none() && s.is_none(),
+    }
+}
+
+fn outcome(result: &ExecutionResult) -> CallOutcome {
+    if result.trapped() {
+        CallOutcome::Trapped(result.trap_reason().map(str::to_string))
+    } else {
+        CallOutcome::Returned(result.value())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::{HostFunction, Imports};
+    use crate::linker::FunctionType;
+    use crate::test_utils::from_hex;
+    use crate::{parse, ValueType};
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Arc;
+
+    /* wat2wasm
+      (func $next (import "env" "next") (result i32))
+      (memory 1)
+      (func (export "run") (i32.store (i32.const 0) (call $next)))
+      (func (export "square") (param i32) (result i32) (i32.mul (local.get 0) (local.get 0)))
+      (func (export "next") (result i32) (call $next))
+    */
+    const WASM: &[&str] = &[
+        "0061736d01000000010d036000017f60000060017f017f020c0103656e76046e6578740000030403",
+        "01020005030100010717030372756e0001067371756172650002046e65787400030a180309004100",
+        "10003602000b0700200020006c0b040010000b",
+    ];
+
+    /* wat2wasm
+      (memory 1)
+      (func (export "square") (param i32) (result i32) (i32.mul (local.get 0) (local.get 0)))
+    */
+    const WASM_NO_IMPORTS: &[&str] = &[
+        "0061736d0100000001060160017f017f030201000503010001070a010673717561726500000a0901",
+        "0700200020006c0b",
+    ];
+
+    fn instance(counter: Arc<AtomicI32>) -> Instance {
+        let mut imports = Imports::new();
+        imports.define(
+            "env",
+            "next",
+            HostFunction::new(
+                FunctionType {
+                    inputs: vec![],
+                    output: Some(ValueType::I32),
+                },
+                move |_| Ok(Some(Value::I32(counter.fetch_add(1, Ordering::SeqCst)))),
+            ),
+        );
+        parse(from_hex(WASM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    #[test]
+    fn deterministic() {
+        let mut instance = SelfCheckedInstance::new(
+            instance(Arc::new(AtomicI32::new(1))),
+            instance(Arc::new(AtomicI32::new(1))),
+        )
+        .unwrap();
+        let result = instance.execute("square", &[Value::I32(7)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(49)));
+        let result = instance.execute("next", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(1)));
+        assert!(instance.execute("run", &[]).unwrap().value().is_none());
+        assert_eq!(
+            instance.execute("square", &[]).err(),
+            Some(Error::ArgumentCountMismatch)
+        );
+
+        let (primary, shadow) = instance.into_instances();
+        let mut data = [0u8; 4];
+        primary.memory_get(0, &mut data).unwrap();
+        assert_eq!(data, [2, 0, 0, 0]);
+        assert_eq!(primary.state_hash(), shadow.state_hash());
+    }
+
+    #[test]
+    fn diverged_outcome() {
+        let counter = Arc::new(AtomicI32::new(1));
+        let mut instance =
+            SelfCheckedInstance::new(instance(counter.clone()), instance(counter)).unwrap();
+        let error = instance.execute("next", &[]).err().unwrap();
+        assert_eq!(
+            error,
+            Error::Diverged(Divergence::Outcome {
+                primary: CallOutcome::Returned(Some(Value::I32(1))),
+                shadow: CallOutcome::Returned(Some(Value::I32(2))),
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "executions diverged: outcome Returned(Some(i32: 1)) differs from \
+             Returned(Some(i32: 2))"
+        );
+    }
+
+    #[test]
+    fn diverged_state() {
+        let counter = Arc::new(AtomicI32::new(1));
+        let mut instance =
+            SelfCheckedInstance::new(instance(counter.clone()), instance(counter)).unwrap();
+        match instance.execute("run", &[]) {
+            Err(Error::Diverged(Divergence::State { primary, shadow })) => {
+                assert_eq!(primary, instance.primary().state_hash());
+                assert_eq!(shadow, instance.shadow().state_hash());
+            }
+            _ => panic!("state must diverge"),
+        }
+
+        let mut primary = parse(from_hex(WASM_NO_IMPORTS))
+            .unwrap()
+            .instantiate()
+            .unwrap();
+        primary.memory_set(0, &[1]).unwrap();
+        let shadow = parse(from_hex(WASM_NO_IMPORTS))
+            .unwrap()
+            .instantiate()
+            .unwrap();
+        assert!(matches!(
+            SelfCheckedInstance::new(primary, shadow),
+            Err(Error::Diverged(Divergence::State { .. }))
+        ));
+    }
+
+    #[test]
+    fn instantiate() {
+        let mut instance = SelfCheckedInstance::instantiate(from_hex(WASM_NO_IMPORTS)).unwrap();
+        let result = instance.execute("square", &[Value::I32(-3)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(9)));
+    }
+}
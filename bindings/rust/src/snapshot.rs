@@ -0,0 +1,405 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Page-level access to the memory of instances and snapshots of it, e.g. for incremental
+//! persistence of the state of instances.
+//!
+//! After [`Instance::snapshot_memory()`], the pages yielded by [`Instance::memory_pages_iter()`]
+//! are flagged dirty if they differ from the snapshot. The changed bytes are found with
+//! [`Instance::memory_changes()`] or by diffing snapshots with [`MemorySnapshot::diff()`].
+
+use crate::memory::PAGE_SIZE;
+use crate::Instance;
+use std::ops::Range;
+
+/// A copy of a memory of an instance.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MemorySnapshot {
+    memory_idx: u32,
+    data: Vec<u8>,
+}
+
+impl MemorySnapshot {
+    /// Returns the index of the memory of the snapshot.
+    pub fn memory_index(&self) -> u32 {
+        self.memory_idx
+    }
+
+    /// Returns the contents of the memory at the time of the snapshot.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the number of pages of the memory at the time of the snapshot.
+    pub fn page_count(&self) -> u32 {
+        (self.data.len() / PAGE_SIZE) as u32
+    }
+
+    /// Returns the changes from this snapshot to a `newer` one.
+    pub fn diff(&self, newer: &MemorySnapshot) -> MemoryDiff {
+        diff(&self.data, &newer.data)
+    }
+}
+
+impl std::fmt::Debug for MemorySnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemorySnapshot")
+            .field("memory_idx", &self.memory_idx)
+            .field("pages", &self.page_count())
+            .finish()
+    }
+}
+
+/// The changes between two versions of a memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryDiff {
+    /// The maximal ranges of changed bytes, in increasing order. The bytes beyond the end of one
+    /// of the versions, e.g. added by the growth of the memory, are changed.
+    pub ranges: Vec<Range<usize>>,
+    /// The size of the older version in bytes.
+    pub old_size: usize,
+    /// The size of the newer version in bytes.
+    pub new_size: usize,
+}
+
+impl MemoryDiff {
+    /// Returns true if the versions are the same.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Returns the indices of the pages containing changed bytes, in increasing order.
+    pub fn pages(&self) -> Vec<u32> {
+        let mut pages: Vec<u32> = Vec::new();
+        for range in &self.ranges {
+            let first = (range.start / PAGE_SIZE) as u32;
+            let last = ((range.end - 1) / PAGE_SIZE) as u32;
+            let first = match pages.last() {
+                Some(&page) if page >= first => page + 1,
+                _ => first,
+            };
+            pages.extend(first..=last);
+        }
+        pages
+    }
+}
+
+/// Returns the changes from the `old` to the `new` contents of a memory.
+///
+/// The pages are compared first, so that the unchanged pages are not scanned byte by byte.
+fn diff(old: &[u8], new: &[u8]) -> MemoryDiff {
+    let common = old.len().min(new.len());
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut add = |range: Range<usize>| match ranges.last_mut() {
+        Some(last) if last.end == range.start => last.end = range.end,
+        _ => ranges.push(range),
+    };
+    for start in (0..common).step_by(PAGE_SIZE) {
+        let end = (start + PAGE_SIZE).min(common);
+        if old[start..end] == new[start..end] {
+            continue;
+        }
+        let mut offset = start;
+        while offset < end {
+            if old[offset] == new[offset] {
+                offset += 1;
+                continue;
+            }
+            let changed = offset;
+            while offset < end && old[offset] != new[offset] {
+                offset += 1;
+            }
+            add(changed..offset);
+        }
+    }
+    let size = old.len().max(new.len());
+    if common < size {
+        add(common..size);
+    }
+    MemoryDiff {
+        ranges,
+        old_size: old.len(),
+        new_size: new.len(),
+    }
+}
+
+/// A page of the memory of an instance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryPage<'a> {
+    pub index: u32,
+    /// The contents of the page, of [`PAGE_SIZE`] bytes.
+    pub data: &'a [u8],
+    /// Whether the page differs from the snapshot of the memory, or `None` if no snapshot has
+    /// been taken. The pages added by the growth of the memory after the snapshot are dirty.
+    pub dirty: Option<bool>,
+}
+
+/// An iterator over the pages of the memory of an instance, see
+/// [`Instance::memory_pages_iter()`].
+#[derive(Clone, Debug)]
+pub struct MemoryPages<'a> {
+    data: &'a [u8],
+    snapshot: Option<&'a [u8]>,
+    index: usize,
+}
+
+impl<'a> Iterator for MemoryPages<'a> {
+    type Item = MemoryPage<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.index * PAGE_SIZE;
+        let data = self.data.get(start..start + PAGE_SIZE)?;
+        let dirty = self
+            .snapshot
+            .map(|snapshot| snapshot.get(start..start + PAGE_SIZE) != Some(data));
+        let page = MemoryPage {
+            index: self.index as u32,
+            data,
+            dirty,
+        };
+        self.index += 1;
+        Some(page)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.data.len() / PAGE_SIZE - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for MemoryPages<'_> {}
+
+impl Instance {
+    /// Returns the memory of the instance, empty if the instance has no memory.
+    ///
+    /// Writes to the memory through the handles of an imported [`crate::memory::Memory`] are not
+    /// synchronized with the borrows of the slice.
+    pub(crate) fn memory_data(&self) -> &[u8] {
+        self.memory_data_at(0)
+    }
+
+    /// Returns the memory of the index, empty if there is no such memory.
+    pub(crate) fn memory_data_at(&self, memory_idx: u32) -> &[u8] {
+        let (data, size) = crate::instance_memory(self.0.as_ptr(), memory_idx);
+        if size == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(data, size) }
+    }
+
+    /// Returns the number of pages of the memory of the instance.
+    pub fn memory_page_count(&self) -> u32 {
+        (self.memory_size() / PAGE_SIZE) as u32
+    }
+
+    /// Returns the contents of the memory page of the given index, or `None` if the index is
+    /// not within the memory.
+    pub fn memory_page(&self, index: u32) -> Option<&[u8]> {
+        let start = (index as usize).checked_mul(PAGE_SIZE)?;
+        self.memory_data().get(start..start + PAGE_SIZE)
+    }
+
+    /// Returns an iterator over the pages of the memory of the instance, flagged dirty if they
+    /// differ from the snapshot taken with [`Instance::snapshot_memory()`].
+    pub fn memory_pages_iter(&self) -> MemoryPages<'_> {
+        self.memory_pages_iter_at(0)
+    }
+
+    /// Returns an iterator over the pages of the memory of the index, flagged dirty if they differ
+    /// from the snapshot taken with [`Instance::snapshot_memory_at()`] of the same memory.
+    pub fn memory_pages_iter_at(&self, memory_idx: u32) -> MemoryPages<'_> {
+        MemoryPages {
+            data: self.memory_data_at(memory_idx),
+            snapshot: self
+                .1
+                .memory_snapshot
+                .as_ref()
+                .filter(|snapshot| snapshot.memory_idx == memory_idx)
+                .map(MemorySnapshot::data),
+            index: 0,
+        }
+    }
+
+    /// Take a snapshot of the memory of the instance, replacing the previous one. The pages are
+    /// compared against it by [`Instance::memory_pages_iter()`].
+    pub fn snapshot_memory(&mut self) {
+        self.snapshot_memory_at(0)
+    }
+
+    /// Take a snapshot of the memory of the index, replacing the previous snapshot of any memory.
+    /// An instance without the memory of the index gets an empty snapshot.
+    pub fn snapshot_memory_at(&mut self, memory_idx: u32) {
+        self.1.memory_snapshot = Some(MemorySnapshot {
+            memory_idx,
+            data: self.memory_data_at(memory_idx).to_vec(),
+        });
+    }
+
+    /// Returns the last snapshot taken with [`Instance::snapshot_memory()`].
+    pub fn memory_snapshot(&self) -> Option<&MemorySnapshot> {
+        self.1.memory_snapshot.as_ref()
+    }
+
+    /// Drop the snapshot of the memory, the pages are not flagged dirty anymore.
+    pub fn clear_memory_snapshot(&mut self) {
+        self.1.memory_snapshot = None;
+    }
+
+    /// Returns the changes of the memory of the instance since the `snapshot` of it, which can be
+    /// taken from another instance of the same module.
+    pub fn memory_diff(&self, snapshot: &MemorySnapshot) -> MemoryDiff {
+        diff(snapshot.data(), self.memory_data_at(snapshot.memory_idx))
+    }
+
+    /// Returns the changes of the memory since the snapshot taken with
+    /// [`Instance::snapshot_memory()`] or [`Instance::snapshot_memory_at()`], or `None` if no
+    /// snapshot has been taken.
+    pub fn memory_changes(&self) -> Option<MemoryDiff> {
+        self.memory_snapshot()
+            .map(|snapshot| self.memory_diff(snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::{from_hex, parse_multi_memory};
+
+    #[test]
+    fn diff_ranges() {
+        let old = vec![0u8; 2 * PAGE_SIZE];
+        let same = diff(&old, &old);
+        assert!(same.is_empty());
+        assert!(same.pages().is_empty());
+
+        let mut new = old.clone();
+        new[3] = 1;
+        new[4] = 1;
+        new[6] = 1;
+        new[PAGE_SIZE - 1] = 1;
+        new[PAGE_SIZE] = 1;
+        let changes = diff(&old, &new);
+        assert_eq!(changes.ranges, [3..5, 6..7, PAGE_SIZE - 1..PAGE_SIZE + 1]);
+        assert_eq!(changes.pages(), [0, 1]);
+
+        // The bytes beyond the end of the shorter version are changed.
+        new.resize(4 * PAGE_SIZE, 0);
+        let changes = diff(&old, &new);
+        assert_eq!(
+            changes.ranges,
+            [
+                3..5,
+                6..7,
+                PAGE_SIZE - 1..PAGE_SIZE + 1,
+                2 * PAGE_SIZE..4 * PAGE_SIZE
+            ]
+        );
+        assert_eq!(changes.pages(), [0, 1, 2, 3]);
+        assert_eq!((changes.old_size, changes.new_size), (old.len(), new.len()));
+        let changes = diff(&new, &old);
+        assert_eq!(changes.ranges[3], 2 * PAGE_SIZE..4 * PAGE_SIZE);
+        assert_eq!((changes.old_size, changes.new_size), (new.len(), old.len()));
+    }
+
+    #[test]
+    fn memory_pages() {
+        /* wat2wasm
+          (memory 2 3)
+          (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+        */
+        let input = from_hex(&[
+            "0061736d010000000105016000017f030201000504010102030708010467726f7700000a08010600",
+            "410140000b",
+        ]);
+        let mut instance = parse(input).unwrap().instantiate().unwrap();
+        assert_eq!(instance.memory_page_count(), 2);
+        assert_eq!(instance.memory_page(1).unwrap().len(), 65536);
+        assert_eq!(instance.memory_page(2), None);
+        assert_eq!(instance.memory_page(u32::MAX), None);
+
+        let pages: Vec<_> = instance.memory_pages_iter().collect();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[1].index, 1);
+        assert_eq!(pages[1].dirty, None);
+        assert_eq!(instance.memory_snapshot(), None);
+
+        instance.snapshot_memory();
+        assert_eq!(instance.memory_snapshot().unwrap().page_count(), 2);
+        instance.memory_set(65536 + 7, &[1]).unwrap();
+        instance.execute("grow", &[]).unwrap();
+        let iter = instance.memory_pages_iter();
+        assert_eq!(iter.len(), 3);
+        let dirty: Vec<_> = iter.map(|page| page.dirty).collect();
+        assert_eq!(dirty, [Some(false), Some(true), Some(true)]);
+        assert_eq!(instance.memory_page(1).unwrap()[7], 1);
+        assert_eq!(instance.memory_snapshot().unwrap().data()[65536 + 7], 0);
+        let changes = instance.memory_changes().unwrap();
+        assert_eq!(changes.ranges, [65536 + 7..65536 + 8, 2 * 65536..3 * 65536]);
+        assert_eq!(changes.pages(), [1, 2]);
+
+        let snapshot = instance.memory_snapshot().unwrap().clone();
+        instance.snapshot_memory();
+        assert_eq!(snapshot.diff(instance.memory_snapshot().unwrap()), changes);
+        assert_eq!(
+            instance.memory_changes().map(|diff| diff.is_empty()),
+            Some(true)
+        );
+        assert_eq!(instance.memory_diff(&snapshot), changes);
+
+        instance.snapshot_memory();
+        assert!(instance
+            .memory_pages_iter()
+            .all(|page| page.dirty == Some(false)));
+        instance.clear_memory_snapshot();
+        assert!(instance
+            .memory_pages_iter()
+            .all(|page| page.dirty.is_none()));
+        assert_eq!(instance.memory_changes(), None);
+
+        /* wat2wasm
+          (module)
+        */
+        let instance = parse(from_hex(&["0061736d01000000"]))
+            .unwrap()
+            .instantiate()
+            .unwrap();
+        assert_eq!(instance.memory_page_count(), 0);
+        assert_eq!(instance.memory_pages_iter().next(), None);
+    }
+
+    #[test]
+    fn multiple_memories() {
+        /* wat2wasm --enable-multi-memory
+          (memory (export "mem0") 1)
+          (memory (export "mem1") 2)
+        */
+        let mut instance = parse_multi_memory(from_hex(&[
+            "0061736d0100000005050200010002070f02046d656d300200046d656d310201",
+        ]))
+        .unwrap()
+        .instantiate()
+        .unwrap();
+
+        instance.snapshot_memory_at(1);
+        assert_eq!(instance.memory_snapshot().unwrap().memory_index(), 1);
+        assert_eq!(instance.memory_snapshot().unwrap().page_count(), 2);
+        instance.memory_set_at(1, 65536 + 3, &[1]).unwrap();
+        instance.memory_set(3, &[1]).unwrap();
+        let changes = instance.memory_changes().unwrap();
+        assert_eq!(changes.pages(), [1]);
+        assert_eq!(changes.ranges.len(), 1);
+        assert_eq!(changes.ranges[0], 65536 + 3..65536 + 4);
+        let dirty: Vec<_> = instance
+            .memory_pages_iter_at(1)
+            .map(|page| page.dirty)
+            .collect();
+        assert_eq!(dirty, [Some(false), Some(true)]);
+        // The memory 0 is not compared against the snapshot of the memory 1.
+        assert!(instance
+            .memory_pages_iter()
+            .all(|page| page.dirty.is_none()));
+        assert_eq!(instance.memory_pages_iter_at(2).next(), None);
+    }
+}
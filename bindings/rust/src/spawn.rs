@@ -0,0 +1,188 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Executions running on worker threads.
+//!
+//! [`Instance::spawn_execute()`] moves the instance to a new thread executing the function.
+//! The returned [`ExecutionHandle`] can cancel the execution, which then traps before its next
+//! instruction, and gives the instance back when joined.
+
+use crate::{sys, Error, ExecutionResult, Instance, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// The handle of an execution running on a worker thread.
+///
+/// Dropping the handle cancels the execution and detaches the thread.
+pub struct ExecutionHandle {
+    thread: Option<JoinHandle<(Instance, ExecutionResult)>>,
+    cancelled: Arc<AtomicBool>,
+    finished: Receiver<()>,
+}
+
+impl ExecutionHandle {
+    /// Request the cancellation of the execution. It traps before the next instruction.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst)
+    }
+
+    /// Check if the execution has finished.
+    pub fn is_finished(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+    }
+
+    /// Wait for the execution to finish, returning the instance and the result.
+    pub fn join(mut self) -> (Instance, ExecutionResult) {
+        let thread = self.thread.take().expect("thread of execution handle");
+        match thread.join() {
+            Ok(finished) => finished,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+
+    /// Wait for the execution to finish at most for the `timeout`, cancelling it afterwards.
+    ///
+    /// Returns the instance, the result, and whether the execution has been cancelled.
+    pub fn join_timeout(self, timeout: Duration) -> (Instance, ExecutionResult, bool) {
+        let timed_out = match self.finished.recv_timeout(timeout) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => false,
+            Err(RecvTimeoutError::Timeout) => {
+                self.cancel();
+                true
+            }
+        };
+        let (instance, result) = self.join();
+        // The execution may have finished right before the cancellation.
+        let cancelled = timed_out && result.trapped();
+        (instance, result, cancelled)
+    }
+}
+
+impl Drop for ExecutionHandle {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.cancel();
+        }
+    }
+}
+
+impl std::fmt::Debug for ExecutionHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExecutionHandle")
+            .field("cancelled", &self.cancelled.load(Ordering::SeqCst))
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+impl Instance {
+    /// Execute an exported function on a new thread.
+    ///
+    /// The instance is moved to the thread and returned by [`ExecutionHandle::join()`].
+    /// If the function is not found or the arguments do not match, the instance is dropped.
+    pub fn spawn_execute(self, name: &str, args: &[Value]) -> Result<ExecutionHandle, Error> {
+        let func_idx = self
+            .find_exported_function_index(name)
+            .ok_or(Error::FunctionNotFound)?;
+        self.check_arguments(func_idx, args)?;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (finished_sender, finished) = channel();
+        let args = args.to_vec();
+        let mut instance = self;
+        let thread_cancelled = cancelled.clone();
+        let thread = std::thread::spawn(move || {
+            let mut hook = |_: &sys::FizzyExecutionState| !thread_cancelled.load(Ordering::Relaxed);
+            let mut result = instance
+                .execute_function_with_hook(func_idx, &args, &mut hook)
+                .expect("arguments of spawned execution checked");
+            if result.trapped
+                && result.trap_reason.is_none()
+                && thread_cancelled.load(Ordering::Relaxed)
+            {
+                result.trap_reason = Some("execution cancelled".to_string());
+            }
+            let _ = finished_sender.send(());
+            (instance, result)
+        });
+
+        Ok(ExecutionHandle {
+            thread: Some(thread),
+            cancelled,
+            finished,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+    use std::time::Instant;
+
+    /* wat2wasm
+      (func (export "spin") (param i32) (result i32)
+        (loop (br_if 0 (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+        (i32.const 42)
+      )
+      (func (export "forever") (loop (br 0)))
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001090260017f017f6000000303020001071202047370696e000007666f726576",
+        "657200010a1a0210000340200041016b22000d000b412a0b070003400c000b0b",
+    ];
+
+    #[test]
+    fn join() {
+        let instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        let handle = instance.spawn_execute("spin", &[Value::I32(1000)]).unwrap();
+        let (mut instance, result) = handle.join();
+        assert_eq!(result.value(), Some(Value::I32(42)));
+
+        // The instance can be used after the execution.
+        let result = instance.execute("spin", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(42)));
+
+        assert_eq!(
+            instance.spawn_execute("spin", &[]).err(),
+            Some(Error::ArgumentCountMismatch)
+        );
+    }
+
+    #[test]
+    fn cancel() {
+        let instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        let handle = instance.spawn_execute("forever", &[]).unwrap();
+        assert!(!handle.is_finished());
+        handle.cancel();
+        let (mut instance, result) = handle.join();
+        assert!(result.trapped());
+        assert_eq!(result.trap_reason(), Some("execution cancelled"));
+
+        let result = instance.execute("spin", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(42)));
+    }
+
+    #[test]
+    fn join_timeout() {
+        let instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        let handle = instance.spawn_execute("forever", &[]).unwrap();
+        let start = Instant::now();
+        let (instance, result, cancelled) = handle.join_timeout(Duration::from_millis(50));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(cancelled);
+        assert!(result.trapped());
+
+        let handle = instance.spawn_execute("spin", &[Value::I32(1)]).unwrap();
+        let (_, result, cancelled) = handle.join_timeout(Duration::from_secs(60));
+        assert!(!cancelled);
+        assert_eq!(result.value(), Some(Value::I32(42)));
+    }
+}
@@ -0,0 +1,458 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tables of function references.
+//!
+//! The [`Table`] of an instance, either defined or imported, is accessed with
+//! [`Instance::table()`]. Its elements can be read and replaced and the table grown by the host,
+//! e.g. to install the functions called by `call_indirect` at runtime.
+//!
+//! The elements are [`FuncRef`]s, obtained from the table itself, from the exported functions
+//! with [`Instance::func_ref()`] or from host functions with [`Instance::host_func_ref()`].
+//! A function reference can only be placed into the table of the instance it was obtained from.
+//!
+//! The types of the elements can be inspected, e.g. with [`Table::check_indirect_call()`]
+//! predicting whether a `call_indirect` would trap before calling the function.
+
+use crate::linker::{function_type_from_sys, limits_from_sys, FunctionType, Limits};
+use crate::{sys, Error, Instance};
+use std::ffi::CString;
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+/// A reference to a function, which can be an element of a table.
+pub struct FuncRef {
+    pub(crate) ptr: NonNull<sys::FizzyFuncRef>,
+    /// The identity of the instance the reference was obtained from.
+    pub(crate) instance: Arc<()>,
+}
+
+impl FuncRef {
+    /// Returns the type of the function referenced.
+    pub fn ty(&self) -> FunctionType {
+        function_type_from_sys(&unsafe { sys::fizzy_get_funcref_type(self.ptr.as_ptr()) })
+    }
+}
+
+impl Drop for FuncRef {
+    fn drop(&mut self) {
+        unsafe { sys::fizzy_free_funcref(self.ptr.as_ptr()) }
+    }
+}
+
+impl std::fmt::Debug for FuncRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("FuncRef").field(&self.ptr).finish()
+    }
+}
+
+/// The reason why a `call_indirect` traps before calling the function.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IndirectCallError {
+    /// The type index is not defined by the module.
+    UnknownType,
+    /// The element index is outside of the bounds of the table.
+    UndefinedElement,
+    /// The element is null.
+    UninitializedElement,
+    /// The type of the function does not match the type expected by the instruction.
+    TypeMismatch {
+        expected: FunctionType,
+        actual: FunctionType,
+    },
+}
+
+impl std::fmt::Display for IndirectCallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndirectCallError::UnknownType => f.write_str("unknown type"),
+            IndirectCallError::UndefinedElement => f.write_str("undefined element"),
+            IndirectCallError::UninitializedElement => f.write_str("uninitialized element"),
+            IndirectCallError::TypeMismatch { expected, actual } => write!(
+                f,
+                "indirect call type mismatch: expected {}, got {}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+/// The table of an instance, borrowed from it.
+pub struct Table<'a> {
+    instance: &'a mut Instance,
+    table: NonNull<sys::FizzyTable>,
+    limits: Limits,
+}
+
+impl Table<'_> {
+    /// Returns the limits of the table.
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Returns the size of the table in elements.
+    pub fn size(&self) -> u32 {
+        unsafe { sys::fizzy_get_table_size(self.table.as_ptr()) }
+    }
+
+    /// Grow the table by `delta` null elements, returning the previous size.
+    ///
+    /// Returns `None` if the new size would exceed the maximum of the table limits, the growth is
+    /// denied by the [`ResourceLimiter`] of the instance or the allocation failed.
+    ///
+    /// [`ResourceLimiter`]: crate::limiter::ResourceLimiter
+    pub fn grow(&mut self, delta: u32) -> Option<u32> {
+        let current = self.size();
+        let max = self.limits.max.unwrap_or(u32::MAX);
+        let desired = current
+            .checked_add(delta)
+            .filter(|&desired| desired <= max)?;
+        if desired != current {
+            if let Some(limiter) = self.instance.1.resource_limiter.as_mut() {
+                if !limiter.table_growing(current, desired, self.limits.max) {
+                    return None;
+                }
+            }
+        }
+        if !unsafe { sys::fizzy_grow_table(self.table.as_ptr(), delta, max) } {
+            return None;
+        }
+        Some(current)
+    }
+
+    /// Returns the element at `index`, `None` if it is null.
+    pub fn get(&self, index: u32) -> Result<Option<FuncRef>, Error> {
+        self.check_index(index)?;
+        let ptr = unsafe { sys::fizzy_get_table_element(self.table.as_ptr(), index) };
+        // The element is null or the allocation of the reference failed.
+        Ok(NonNull::new(ptr).map(|ptr| FuncRef {
+            ptr,
+            instance: self.instance.1.identity.clone(),
+        }))
+    }
+
+    /// Set the element at `index` to the function reference, or to null if `None`.
+    pub fn set(&mut self, index: u32, funcref: Option<&FuncRef>) -> Result<(), Error> {
+        self.check_index(index)?;
+        let ptr = match funcref {
+            Some(funcref) => {
+                if !Arc::ptr_eq(&funcref.instance, &self.instance.1.identity) {
+                    return Err(Error::ForeignFuncRef);
+                }
+                funcref.ptr.as_ptr() as *const sys::FizzyFuncRef
+            }
+            None => std::ptr::null(),
+        };
+        if !unsafe { sys::fizzy_set_table_element(self.table.as_ptr(), index, ptr) } {
+            return Err(Error::OutOfMemory);
+        }
+        Ok(())
+    }
+
+    /// Returns the type of the function of the element at `index`, `None` if it is null.
+    pub fn element_type(&self, index: u32) -> Result<Option<FunctionType>, Error> {
+        Ok(self.get(index)?.map(|funcref| funcref.ty()))
+    }
+
+    /// Returns the index of the first type of the module of the instance equal to the type of
+    /// the element at `index`, `None` if the element is null or the module has no such type.
+    ///
+    /// A `call_indirect` of any type index with the same type calls the function.
+    pub fn element_type_index(&self, index: u32) -> Result<Option<u32>, Error> {
+        Ok(self.element_type(index)?.and_then(|ty| {
+            let module = self.instance.module();
+            let count = unsafe { sys::fizzy_get_type_count(module) };
+            (0..count).find(|&type_idx| self.module_type(type_idx).as_ref() == Some(&ty))
+        }))
+    }
+
+    /// Check whether a `call_indirect` of the type `type_idx` of the module of the instance
+    /// would call the function of the element at `index`, returning the reason of the trap
+    /// otherwise.
+    pub fn check_indirect_call(&self, index: u32, type_idx: u32) -> Result<(), IndirectCallError> {
+        let expected = self
+            .module_type(type_idx)
+            .ok_or(IndirectCallError::UnknownType)?;
+        let actual = self
+            .element_type(index)
+            .map_err(|_| IndirectCallError::UndefinedElement)?
+            .ok_or(IndirectCallError::UninitializedElement)?;
+        if actual != expected {
+            return Err(IndirectCallError::TypeMismatch { expected, actual });
+        }
+        Ok(())
+    }
+
+    /// Returns the type of the type section of the module of the instance.
+    fn module_type(&self, type_idx: u32) -> Option<FunctionType> {
+        let module = self.instance.module();
+        if type_idx >= unsafe { sys::fizzy_get_type_count(module) } {
+            return None;
+        }
+        Some(function_type_from_sys(&unsafe {
+            sys::fizzy_get_type(module, type_idx)
+        }))
+    }
+
+    fn check_index(&self, index: u32) -> Result<(), Error> {
+        if index >= self.size() {
+            return Err(Error::TableIndexOutOfBounds);
+        }
+        Ok(())
+    }
+}
+
+impl Instance {
+    /// Returns the table of the instance, either defined or imported, or `None` if it has no table.
+    pub fn table(&mut self) -> Option<Table<'_>> {
+        let mut table = sys::FizzyExternalTable {
+            table: std::ptr::null_mut(),
+            limits: sys::FizzyLimits {
+                min: 0,
+                max: 0,
+                has_max: false,
+            },
+        };
+        if !unsafe { sys::fizzy_get_instance_table(self.0.as_ptr(), &mut table) } {
+            return None;
+        }
+        Some(Table {
+            table: NonNull::new(table.table)?,
+            limits: limits_from_sys(&table.limits),
+            instance: self,
+        })
+    }
+
+    /// Returns the table exported under the `name`, or `None` if there is no such table export.
+    pub fn export_table(&mut self, name: &str) -> Option<Table<'_>> {
+        let c_name = CString::new(name).ok()?;
+        let mut table = sys::FizzyExternalTable {
+            table: std::ptr::null_mut(),
+            limits: sys::FizzyLimits {
+                min: 0,
+                max: 0,
+                has_max: false,
+            },
+        };
+        if !unsafe { sys::fizzy_find_exported_table(self.0.as_ptr(), c_name.as_ptr(), &mut table) }
+        {
+            return None;
+        }
+        Some(Table {
+            table: NonNull::new(table.table)?,
+            limits: limits_from_sys(&table.limits),
+            instance: self,
+        })
+    }
+
+    /// Returns a reference to the exported function of the given name, which can be placed into
+    /// the table of the instance.
+    pub fn func_ref(&self, name: &str) -> Option<FuncRef> {
+        let c_name = CString::new(name).ok()?;
+        let ptr = unsafe { sys::fizzy_find_exported_funcref(self.0.as_ptr(), c_name.as_ptr()) };
+        Some(FuncRef {
+            ptr: NonNull::new(ptr)?,
+            instance: self.1.identity.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::HostFunction;
+    use crate::limiter::ResourceLimiter;
+    use crate::linker::FunctionType;
+    use crate::test_utils::from_hex;
+    use crate::{parse, Value, ValueType};
+
+    /* wat2wasm
+      (type $t (func (result i32)))
+      (table 1 3 funcref)
+      (elem (i32.const 0) $one)
+      (func $one (result i32) (i32.const 1))
+      (func $two (export "two") (result i32) (i32.const 2))
+      (func (export "call") (param i32) (result i32) (call_indirect (type $t) (local.get 0)))
+    */
+    const WASM: &[&str] = &[
+        "0061736d01000000010a026000017f60017f017f03040300000104050170010103070e020374776f00010463",
+        "616c6c00020907010041000b01000a1303040041010b040041020b070020001100000b",
+    ];
+
+    fn call(instance: &mut Instance, index: i32) -> Result<Option<Value>, Error> {
+        Ok(instance
+            .execute("call", &[Value::I32(index)])?
+            .into_result()?)
+    }
+
+    #[test]
+    fn table() {
+        let mut instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        let two = instance.func_ref("two").unwrap();
+        assert!(instance.func_ref("one").is_none());
+
+        let mut table = instance.table().unwrap();
+        assert_eq!(
+            table.limits(),
+            Limits {
+                min: 1,
+                max: Some(3)
+            }
+        );
+        assert_eq!(table.size(), 1);
+        assert_eq!(table.grow(3), None);
+        assert_eq!(table.grow(2), Some(1));
+        assert_eq!(table.size(), 3);
+        assert!(table.get(2).unwrap().is_none());
+        assert_eq!(table.get(3).unwrap_err(), Error::TableIndexOutOfBounds);
+        assert_eq!(
+            table.set(3, Some(&two)).unwrap_err(),
+            Error::TableIndexOutOfBounds
+        );
+
+        table.set(2, Some(&two)).unwrap();
+        let one = table.get(0).unwrap().unwrap();
+        table.set(1, Some(&one)).unwrap();
+        table.set(0, None).unwrap();
+        assert!(matches!(call(&mut instance, 0), Err(Error::Trapped(_))));
+        assert_eq!(call(&mut instance, 1), Ok(Some(Value::I32(1))));
+        assert_eq!(call(&mut instance, 2), Ok(Some(Value::I32(2))));
+
+        let mut other = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        assert_eq!(
+            other.table().unwrap().set(0, Some(&two)).unwrap_err(),
+            Error::ForeignFuncRef
+        );
+    }
+
+    #[test]
+    fn host_func_ref() {
+        /* wat2wasm
+          (type $t (func (param i32) (result i32)))
+          (table 1 funcref)
+          (func (export "call") (param i32) (result i32)
+            (call_indirect (type $t) (local.get 0) (i32.const 0)))
+        */
+        const WASM: &[&str] = &[
+            "0061736d0100000001060160017f017f030201000404017000010708010463616c6c00000a0b0109",
+            "00200041001100000b",
+        ];
+        let mut instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        let ty = FunctionType {
+            inputs: vec![ValueType::I32],
+            output: Some(ValueType::I32),
+        };
+        let double = HostFunction::new(ty.clone(), |args: &[Value]| match args {
+            [Value::I32(x)] => Ok(Some(Value::I32(x * 2))),
+            _ => unreachable!(),
+        });
+        let fail = HostFunction::new(ty, |_: &[Value]| Err("failed".to_string()));
+        let double = instance.host_func_ref("env", "double", double).unwrap();
+        let fail = instance.host_func_ref("env", "fail", fail).unwrap();
+
+        instance.table().unwrap().set(0, Some(&double)).unwrap();
+        assert_eq!(
+            instance.execute("call", &[Value::I32(21)]).unwrap().value(),
+            Some(Value::I32(42))
+        );
+
+        instance.table().unwrap().set(0, Some(&fail)).unwrap();
+        let result = instance.execute("call", &[Value::I32(21)]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(result.trap_reason(), Some("failed"));
+        let trap = result.into_result().unwrap_err();
+        let call = trap.host_call().unwrap();
+        assert_eq!((call.module(), call.name()), ("env", "fail"));
+
+        let mut other = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        assert_eq!(
+            other.table().unwrap().set(0, Some(&double)).unwrap_err(),
+            Error::ForeignFuncRef
+        );
+    }
+
+    #[test]
+    fn indirect_call_types() {
+        let mut instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        let mut table = instance.table().unwrap();
+        table.grow(1).unwrap();
+        let result_i32 = FunctionType {
+            inputs: vec![],
+            output: Some(ValueType::I32),
+        };
+        let param_i32 = FunctionType {
+            inputs: vec![ValueType::I32],
+            output: Some(ValueType::I32),
+        };
+        assert_eq!(table.element_type(0), Ok(Some(result_i32.clone())));
+        assert_eq!(table.element_type_index(0), Ok(Some(0)));
+        assert_eq!(table.element_type(1), Ok(None));
+        assert_eq!(table.element_type_index(1), Ok(None));
+        assert_eq!(table.element_type(2), Err(Error::TableIndexOutOfBounds));
+
+        assert_eq!(table.check_indirect_call(0, 0), Ok(()));
+        let mismatch = table.check_indirect_call(0, 1).unwrap_err();
+        assert_eq!(
+            mismatch,
+            IndirectCallError::TypeMismatch {
+                expected: param_i32,
+                actual: result_i32
+            }
+        );
+        assert_eq!(
+            mismatch.to_string(),
+            "indirect call type mismatch: expected [i32] -> [i32], got [] -> [i32]"
+        );
+        assert_eq!(
+            table.check_indirect_call(1, 0),
+            Err(IndirectCallError::UninitializedElement)
+        );
+        assert_eq!(
+            table.check_indirect_call(2, 0),
+            Err(IndirectCallError::UndefinedElement)
+        );
+        assert_eq!(
+            table.check_indirect_call(0, 2),
+            Err(IndirectCallError::UnknownType)
+        );
+
+        let call = instance.func_ref("call").unwrap();
+        assert_eq!(call.ty().inputs, [ValueType::I32]);
+    }
+
+    struct DenyTables;
+
+    impl ResourceLimiter for DenyTables {
+        fn memory_growing(&mut self, _current: u32, _desired: u32, _maximum: u32) -> bool {
+            true
+        }
+
+        fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> bool {
+            assert_eq!((current, desired, maximum), (1, 2, Some(3)));
+            false
+        }
+    }
+
+    #[test]
+    fn table_growth_limited() {
+        let mut instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        instance.set_resource_limiter(DenyTables);
+        let mut table = instance.table().unwrap();
+        assert_eq!(table.grow(1), None);
+        assert_eq!(table.grow(0), Some(1));
+        assert_eq!(table.size(), 1);
+    }
+
+    #[test]
+    fn no_table() {
+        /* wat2wasm
+          (memory 1)
+        */
+        let mut instance = parse(from_hex(&["0061736d010000000503010001"]))
+            .unwrap()
+            .instantiate()
+            .unwrap();
+        assert!(instance.table().is_none());
+    }
+}
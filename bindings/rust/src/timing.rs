@@ -0,0 +1,211 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Timing of the phases of the lifetime of modules and instances.
+//!
+//! Parsing a module and instantiating it are always timed, as they happen once. The executions
+//! are timed only after [`Instance::set_execution_timing()`] enables it, which adds two clock
+//! reads to every execution. The timings of an instance include the parsing of its module.
+//!
+//! [`Instance::execute_with_cpu_time_limit()`] limits the CPU time consumed by an execution,
+//! measured with the CPU-time clock of the executing thread. Unlike wall-clock timeouts, the
+//! limit is not consumed while the thread is descheduled, e.g. on a heavily loaded host.
+
+use crate::{sys, Error, ExecutionResult, Instance, Module, Value};
+use std::time::Duration;
+
+/// The number of instructions executed between the reads of the CPU-time clock.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+))]
+const CPU_TIME_CHECK_INTERVAL: u32 = 1024;
+
+/// Returns the CPU time consumed by the current thread.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+))]
+fn thread_cpu_time() -> Duration {
+    #[repr(C)]
+    struct Timespec {
+        tv_sec: std::os::raw::c_long,
+        tv_nsec: std::os::raw::c_long,
+    }
+    extern "C" {
+        fn clock_gettime(clock_id: std::os::raw::c_int, tp: *mut Timespec) -> std::os::raw::c_int;
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const CLOCK_THREAD_CPUTIME_ID: std::os::raw::c_int = 3;
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const CLOCK_THREAD_CPUTIME_ID: std::os::raw::c_int = 16;
+
+    let mut time = Timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    let result = unsafe { clock_gettime(CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    assert_eq!(result, 0, "thread CPU-time clock unavailable");
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// The time spent in the phases of the lifetime of an instance.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Timings {
+    /// The time of parsing the module. The module is validated in the same pass,
+    /// so this includes the validation.
+    pub parse: Duration,
+    /// The time of instantiating the module, including running its start function.
+    pub instantiate: Duration,
+    /// The total time of the timed executions, including the host functions they call.
+    pub execute: Duration,
+    /// The number of timed executions.
+    pub executions: u64,
+}
+
+impl Module {
+    /// The time of parsing and validating the module.
+    pub fn parse_time(&self) -> Duration {
+        self.1
+    }
+}
+
+impl Instance {
+    /// The time spent in the phases of the lifetime of the instance so far.
+    pub fn timings(&self) -> Timings {
+        self.1.timings
+    }
+
+    /// Enable or disable the timing of the executions of exported functions.
+    pub fn set_execution_timing(&mut self, enabled: bool) {
+        self.1.execution_timing = enabled;
+    }
+
+    /// Forget the timed executions, keeping the parse and instantiate times.
+    pub fn reset_execution_timing(&mut self) {
+        self.1.timings.execute = Duration::default();
+        self.1.timings.executions = 0;
+    }
+
+    /// Execute an exported function, trapping once it has consumed more than `limit` of
+    /// the CPU time of the current thread.
+    ///
+    /// The clock is read every 1024 instructions, so the execution can exceed the limit
+    /// slightly. The CPU time of the host functions it calls counts towards the limit.
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios"
+    ))]
+    pub fn execute_with_cpu_time_limit(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        limit: Duration,
+    ) -> Result<ExecutionResult, Error> {
+        let func_idx = self
+            .find_exported_function_index(name)
+            .ok_or(Error::FunctionNotFound)?;
+
+        let started = thread_cpu_time();
+        let mut countdown = CPU_TIME_CHECK_INTERVAL;
+        let mut exceeded = false;
+        let mut hook = |_: &sys::FizzyExecutionState| {
+            countdown -= 1;
+            if countdown == 0 {
+                countdown = CPU_TIME_CHECK_INTERVAL;
+                exceeded = thread_cpu_time() - started > limit;
+            }
+            !exceeded
+        };
+        let mut result = self.execute_function_with_hook(func_idx, args, &mut hook)?;
+        if result.trapped && result.trap_reason.is_none() && exceeded {
+            result.trap_reason = Some("CPU time limit exceeded".to_string());
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::from_hex;
+    use crate::{parse, TrapOrigin};
+
+    /* wat2wasm
+      (func (export "loop") (param i32)
+        (loop (br_if 0 (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+      )
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001050160017f0003020100070801046c6f6f7000000a10010e00034020004101",
+        "6b22000d000b0b",
+    ];
+
+    #[test]
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios"
+    ))]
+    fn cpu_time_limit() {
+        let mut instance = parse(from_hex(WASM)).unwrap().instantiate().unwrap();
+        let limit = Duration::from_millis(20);
+        let result = instance
+            .execute_with_cpu_time_limit("loop", &[Value::I32(1000)], limit)
+            .unwrap();
+        assert!(!result.trapped());
+
+        // The loop runs for 2^32 iterations.
+        let started = thread_cpu_time();
+        let result = instance
+            .execute_with_cpu_time_limit("loop", &[Value::I32(0)], limit)
+            .unwrap();
+        assert!(thread_cpu_time() - started >= limit);
+        assert!(result.trapped());
+        assert_eq!(result.trap_origin(), Some(TrapOrigin::Aborted));
+        assert_eq!(result.trap_reason(), Some("CPU time limit exceeded"));
+
+        assert_eq!(
+            instance
+                .execute_with_cpu_time_limit("missing", &[], limit)
+                .err(),
+            Some(Error::FunctionNotFound)
+        );
+    }
+
+    #[test]
+    fn timings() {
+        let module = parse(from_hex(WASM)).unwrap();
+        let parse_time = module.parse_time();
+        let mut instance = module.instantiate().unwrap();
+        let timings = instance.timings();
+        assert_eq!(timings.parse, parse_time);
+        assert_eq!(timings.execute, Duration::default());
+
+        // Not timed by default.
+        instance.execute("loop", &[Value::I32(1000)]).unwrap();
+        assert_eq!(instance.timings(), timings);
+
+        instance.set_execution_timing(true);
+        instance.execute("loop", &[Value::I32(100000)]).unwrap();
+        instance.execute("loop", &[Value::I32(100000)]).unwrap();
+        assert_eq!(instance.timings().executions, 2);
+        assert!(instance.timings().execute > Duration::default());
+        assert_eq!(instance.timings().instantiate, timings.instantiate);
+
+        instance.set_execution_timing(false);
+        instance.execute("loop", &[Value::I32(1)]).unwrap();
+        assert_eq!(instance.timings().executions, 2);
+
+        instance.reset_execution_timing();
+        assert_eq!(instance.timings(), timings);
+    }
+}
@@ -0,0 +1,587 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transformations of wasm binaries.
+
+use crate::binary::{
+    count_imported_functions, sections, usize_from, write_section, write_sleb, write_uleb,
+    Malformed, Reader, HEADER,
+};
+use crate::gas::{analyze, CostTable};
+use crate::{parse, Error};
+
+/// The module name of the gas charging function imported by [`inject_gas_metering()`].
+pub const GAS_MODULE: &str = "env";
+/// The name of the gas charging function imported by [`inject_gas_metering()`].
+pub const GAS_FUNCTION: &str = "use_gas";
+
+const CUSTOM_SECTION: u8 = 0;
+const TYPE_SECTION: u8 = 1;
+const IMPORT_SECTION: u8 = 2;
+const EXPORT_SECTION: u8 = 7;
+const START_SECTION: u8 = 8;
+const ELEMENT_SECTION: u8 = 9;
+const CODE_SECTION: u8 = 10;
+
+/// Instrument a module with calls to the imported `env.use_gas(i64)` function.
+///
+/// The cost of every basic block (see [`crate::gas`]) is charged at the start of the block,
+/// by calling the function with the cost as the argument. The function is expected to trap
+/// when the gas is exhausted. The indices of the functions defined in the module are shifted by
+/// one, as the function is imported after all other imported functions.
+///
+/// Returns the instrumented binary, or the parsing error if the input is not valid.
+pub fn inject_gas_metering(wasm: &[u8], costs: &CostTable) -> Result<Vec<u8>, Error> {
+    parse(wasm)?;
+    let sections = sections(wasm)?;
+    inject(&sections, costs).map_err(|_| Error::ParsingFailed)
+}
+
+/// Remove all custom sections, e.g. names, producers and debug info, from a binary.
+///
+/// Returns [`Error::ParsingFailed`] if the binary cannot be split into sections.
+pub fn strip_custom_sections(wasm: &[u8]) -> Result<Vec<u8>, Error> {
+    retain_custom_sections(wasm, |_| false)
+}
+
+/// Remove the custom sections for which `keep` returns false from a binary.
+///
+/// `keep` is called with the name of every custom section. The other sections are not modified.
+/// Returns [`Error::ParsingFailed`] if the binary cannot be split into sections.
+pub fn retain_custom_sections<F>(wasm: &[u8], mut keep: F) -> Result<Vec<u8>, Error>
+where
+    F: FnMut(&str) -> bool,
+{
+    let mut out = HEADER.to_vec();
+    for (id, payload) in sections(wasm)? {
+        if id == CUSTOM_SECTION {
+            let name = Reader::new(payload)
+                .name()
+                .map_err(|_| Error::ParsingFailed)?;
+            if !keep(name) {
+                continue;
+            }
+        }
+        write_section(&mut out, id, payload);
+    }
+    Ok(out)
+}
+
+fn inject(sections: &[(u8, &[u8])], costs: &CostTable) -> Result<Vec<u8>, Malformed> {
+    let type_section = sections.iter().find(|(id, _)| *id == TYPE_SECTION);
+    let import_section = sections.iter().find(|(id, _)| *id == IMPORT_SECTION);
+
+    let (type_count, gas_type_idx) = match type_section {
+        Some((_, payload)) => find_gas_type(payload)?,
+        None => (0, None),
+    };
+    let num_imported_functions = match import_section {
+        Some((_, payload)) => count_imported_functions(payload).map_err(|_| Malformed)?,
+        None => 0,
+    };
+    let gas_func_idx = num_imported_functions;
+    let shift = |func_idx: u32| {
+        if func_idx >= gas_func_idx {
+            func_idx + 1
+        } else {
+            func_idx
+        }
+    };
+
+    // Append the (i64) -> () type if not present.
+    let mut new_type_section = None;
+    if gas_type_idx.is_none() {
+        let mut payload = Vec::new();
+        write_uleb(&mut payload, u64::from(type_count) + 1);
+        if let Some((_, types)) = type_section {
+            let mut reader = Reader::new(types);
+            reader.uleb()?;
+            payload.extend_from_slice(reader.rest());
+        }
+        payload.extend_from_slice(&[0x60, 1, 0x7e, 0]);
+        new_type_section = Some(payload);
+    }
+    let gas_type_idx = gas_type_idx.unwrap_or(type_count);
+
+    // Append the import of the gas function.
+    let (import_count, imports) = match import_section {
+        Some((_, imports)) => {
+            let mut reader = Reader::new(imports);
+            (reader.uleb()?, reader.rest())
+        }
+        None => (0, &[][..]),
+    };
+    let mut payload = Vec::new();
+    write_uleb(&mut payload, import_count + 1);
+    payload.extend_from_slice(imports);
+    write_name(&mut payload, GAS_MODULE);
+    write_name(&mut payload, GAS_FUNCTION);
+    payload.push(0);
+    write_uleb(&mut payload, u64::from(gas_type_idx));
+    let mut new_import_section = Some(payload);
+
+    let mut out = HEADER.to_vec();
+    for &(id, payload) in sections {
+        if id != CUSTOM_SECTION {
+            if id > TYPE_SECTION {
+                if let Some(types) = new_type_section.take() {
+                    write_section(&mut out, TYPE_SECTION, &types);
+                }
+            }
+            if id > IMPORT_SECTION {
+                if let Some(imports) = new_import_section.take() {
+                    write_section(&mut out, IMPORT_SECTION, &imports);
+                }
+            }
+        }
+        match id {
+            TYPE_SECTION => match new_type_section.take() {
+                Some(types) => write_section(&mut out, id, &types),
+                None => write_section(&mut out, id, payload),
+            },
+            IMPORT_SECTION => {
+                if let Some(imports) = new_import_section.take() {
+                    write_section(&mut out, id, &imports);
+                }
+            }
+            EXPORT_SECTION => write_section(&mut out, id, &shift_exports(payload, shift)?),
+            START_SECTION => {
+                let func_idx = Reader::new(payload).uleb()? as u32;
+                let mut new_payload = Vec::new();
+                write_uleb(&mut new_payload, u64::from(shift(func_idx)));
+                write_section(&mut out, id, &new_payload);
+            }
+            ELEMENT_SECTION => write_section(&mut out, id, &shift_elements(payload, shift)?),
+            CODE_SECTION => {
+                let code = instrument_code(payload, costs, gas_func_idx, shift)?;
+                write_section(&mut out, id, &code);
+            }
+            CUSTOM_SECTION => {
+                let mut reader = Reader::new(payload);
+                if reader.name()? == "name" {
+                    // The name section is optional, drop it if it cannot be updated.
+                    if let Ok(names) = shift_names(payload, shift) {
+                        write_section(&mut out, id, &names);
+                    }
+                } else {
+                    write_section(&mut out, id, payload);
+                }
+            }
+            _ => write_section(&mut out, id, payload),
+        }
+    }
+    if let Some(types) = new_type_section {
+        write_section(&mut out, TYPE_SECTION, &types);
+    }
+    if let Some(imports) = new_import_section {
+        write_section(&mut out, IMPORT_SECTION, &imports);
+    }
+    Ok(out)
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_uleb(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+/// Returns the number of types and the index of the `(i64) -> ()` type, if present.
+fn find_gas_type(payload: &[u8]) -> Result<(u32, Option<u32>), Malformed> {
+    let mut reader = Reader::new(payload);
+    let count = reader.uleb()? as u32;
+    let mut gas_type_idx = None;
+    for type_idx in 0..count {
+        if reader.u8()? != 0x60 {
+            return Err(Malformed);
+        }
+        let inputs_size = usize_from(reader.uleb()?)?;
+        let inputs = reader.bytes(inputs_size)?;
+        let outputs_size = usize_from(reader.uleb()?)?;
+        let outputs = reader.bytes(outputs_size)?;
+        if gas_type_idx.is_none() && inputs == [0x7e] && outputs.is_empty() {
+            gas_type_idx = Some(type_idx);
+        }
+    }
+    Ok((count, gas_type_idx))
+}
+
+fn shift_exports(payload: &[u8], shift: impl Fn(u32) -> u32) -> Result<Vec<u8>, Malformed> {
+    let mut reader = Reader::new(payload);
+    let mut out = Vec::new();
+    let count = reader.uleb()?;
+    write_uleb(&mut out, count);
+    for _ in 0..count {
+        write_name(&mut out, reader.name()?);
+        let kind = reader.u8()?;
+        out.push(kind);
+        let idx = reader.uleb()? as u32;
+        write_uleb(
+            &mut out,
+            u64::from(if kind == 0 { shift(idx) } else { idx }),
+        );
+    }
+    Ok(out)
+}
+
+/// Skip a constant expression, including the final end instruction.
+fn skip_constant_expression(reader: &mut Reader) -> Result<(), Malformed> {
+    loop {
+        let opcode = reader.u8()?;
+        if opcode == 0x0b {
+            return Ok(());
+        }
+        skip_immediates(reader, opcode)?;
+    }
+}
+
+fn shift_elements(payload: &[u8], shift: impl Fn(u32) -> u32) -> Result<Vec<u8>, Malformed> {
+    let mut reader = Reader::new(payload);
+    let mut out = Vec::new();
+    let count = reader.uleb()?;
+    write_uleb(&mut out, count);
+    for _ in 0..count {
+        write_uleb(&mut out, reader.uleb()?);
+        let offset_start = reader.position();
+        skip_constant_expression(&mut reader)?;
+        out.extend_from_slice(&payload[offset_start..reader.position()]);
+        let size = reader.uleb()?;
+        write_uleb(&mut out, size);
+        for _ in 0..size {
+            let func_idx = reader.uleb()? as u32;
+            write_uleb(&mut out, u64::from(shift(func_idx)));
+        }
+    }
+    Ok(out)
+}
+
+fn shift_names(payload: &[u8], shift: impl Fn(u32) -> u32) -> Result<Vec<u8>, Malformed> {
+    let mut reader = Reader::new(payload);
+    let mut out = Vec::new();
+    write_name(&mut out, reader.name()?);
+    while !reader.is_empty() {
+        let id = reader.u8()?;
+        let size = usize_from(reader.uleb()?)?;
+        let content = reader.bytes(size)?;
+        let new_content = match id {
+            // Function names and local names, both start with a function index.
+            1 | 2 => {
+                let mut content_reader = Reader::new(content);
+                let mut new_content = Vec::new();
+                let count = content_reader.uleb()?;
+                write_uleb(&mut new_content, count);
+                for _ in 0..count {
+                    let func_idx = content_reader.uleb()? as u32;
+                    write_uleb(&mut new_content, u64::from(shift(func_idx)));
+                    if id == 1 {
+                        write_name(&mut new_content, content_reader.name()?);
+                    } else {
+                        let num_locals = content_reader.uleb()?;
+                        write_uleb(&mut new_content, num_locals);
+                        for _ in 0..num_locals {
+                            write_uleb(&mut new_content, content_reader.uleb()?);
+                            write_name(&mut new_content, content_reader.name()?);
+                        }
+                    }
+                }
+                if !content_reader.is_empty() {
+                    return Err(Malformed);
+                }
+                new_content
+            }
+            _ => content.to_vec(),
+        };
+        out.push(id);
+        write_uleb(&mut out, new_content.len() as u64);
+        out.extend_from_slice(&new_content);
+    }
+    Ok(out)
+}
+
+/// Skip the immediate values of the instruction, returning the function index if it is a call.
+fn skip_immediates(reader: &mut Reader, opcode: u8) -> Result<Option<u32>, Malformed> {
+    match opcode {
+        // block, loop, if: block type
+        0x02..=0x04 => {
+            reader.u8()?;
+        }
+        // br, br_if, local.get, local.set, local.tee, global.get, global.set
+        0x0c | 0x0d | 0x20..=0x24 => {
+            reader.uleb()?;
+        }
+        // br_table
+        0x0e => {
+            for _ in 0..reader.uleb()? {
+                reader.uleb()?;
+            }
+            reader.uleb()?;
+        }
+        // call
+        0x10 => return Ok(Some(reader.uleb()? as u32)),
+        // call_indirect: type index and table index
+        0x11 => {
+            reader.uleb()?;
+            reader.u8()?;
+        }
+        // loads and stores: alignment and offset
+        0x28..=0x3e => {
+            reader.uleb()?;
+            reader.uleb()?;
+        }
+        // memory.size, memory.grow: memory index
+        0x3f | 0x40 => {
+            reader.u8()?;
+        }
+        // i32.const, i64.const
+        0x41 | 0x42 => {
+            reader.sleb()?;
+        }
+        // f32.const
+        0x43 => {
+            reader.bytes(4)?;
+        }
+        // f64.const
+        0x44 => {
+            reader.bytes(8)?;
+        }
+        _ => {}
+    }
+    Ok(None)
+}
+
+fn instrument_code(
+    payload: &[u8],
+    costs: &CostTable,
+    gas_func_idx: u32,
+    shift: impl Fn(u32) -> u32,
+) -> Result<Vec<u8>, Malformed> {
+    let mut reader = Reader::new(payload);
+    let mut out = Vec::new();
+    let count = reader.uleb()?;
+    write_uleb(&mut out, count);
+    for _ in 0..count {
+        let size = usize_from(reader.uleb()?)?;
+        let body = instrument_body(reader.bytes(size)?, costs, gas_func_idx, &shift)?;
+        write_uleb(&mut out, body.len() as u64);
+        out.extend_from_slice(&body);
+    }
+    Ok(out)
+}
+
+/// An instruction of a function body: its opcode, encoding and the index of the called function.
+struct Instruction<'a> {
+    opcode: u8,
+    bytes: &'a [u8],
+    call_target: Option<u32>,
+}
+
+fn instrument_body(
+    body: &[u8],
+    costs: &CostTable,
+    gas_func_idx: u32,
+    shift: impl Fn(u32) -> u32,
+) -> Result<Vec<u8>, Malformed> {
+    let mut reader = Reader::new(body);
+    for _ in 0..reader.uleb()? {
+        reader.uleb()?;
+        reader.u8()?;
+    }
+    let locals_end = reader.position();
+
+    let mut instructions = Vec::new();
+    while !reader.is_empty() {
+        let start = reader.position();
+        let opcode = reader.u8()?;
+        let call_target = skip_immediates(&mut reader, opcode)?;
+        instructions.push(Instruction {
+            opcode,
+            bytes: &body[start..reader.position()],
+            call_target,
+        });
+    }
+
+    let opcodes: Vec<u8> = instructions.iter().map(|instr| instr.opcode).collect();
+    let analysis = analyze(&opcodes, costs);
+
+    let mut out = body[..locals_end].to_vec();
+    for block in analysis.basic_blocks {
+        if block.cost != 0 {
+            // i64.const cost, call use_gas
+            out.push(0x42);
+            write_sleb(&mut out, block.cost as i64);
+            out.push(0x10);
+            write_uleb(&mut out, u64::from(gas_func_idx));
+        }
+        for instr in &instructions[block.start..block.start + block.len] {
+            match instr.call_target {
+                Some(func_idx) => {
+                    out.push(instr.opcode);
+                    write_uleb(&mut out, u64::from(shift(func_idx)));
+                }
+                None => out.extend_from_slice(instr.bytes),
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::from_hex;
+    use crate::{parse, sys, Instance, Value};
+    use std::ptr::NonNull;
+
+    /* wat2wasm
+      (func $add (export "add") (param i32) (result i32)
+        (i32.add (local.get 0) (i32.const 1))
+      )
+      (func $spin (export "spin") (param i32)
+        (loop (br_if 0 (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+      )
+      (func $call (export "call") (result i32)
+        (call $add (i32.const 41))
+      )
+      (func $nop)
+      (func $fail (export "fail") unreachable)
+      (table 1 anyfunc)
+      (elem (i32.const 0) $spin)
+      (start $nop)
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001110460017f017f60017f006000017f60000003060500010203030404017000",
+        "01071c04036164640000047370696e00010463616c6c0002046661696c0004080103090701004100",
+        "0b01010a26050700200041016a0b0e000340200041016b22000d000b0b0600412910000b02000b03",
+        "00000b0024046e616d65011d05000361646401047370696e020463616c6c03036e6f700404666169",
+        "6c",
+    ];
+
+    unsafe extern "C" fn use_gas(
+        context: *mut std::ffi::c_void,
+        _instance: *mut sys::FizzyInstance,
+        args: *const sys::FizzyValue,
+        _args_size: usize,
+        _depth: i32,
+    ) -> sys::FizzyExecutionResult {
+        *(context as *mut u64) += (*args).i64;
+        sys::FizzyExecutionResult {
+            trapped: false,
+            has_value: false,
+            value: sys::FizzyValue { i64: 0 },
+        }
+    }
+
+    #[test]
+    fn inject_gas_metering() {
+        let wasm = super::inject_gas_metering(&from_hex(WASM), &CostTable::default()).unwrap();
+
+        let module = parse(&wasm).unwrap();
+        let imports = module.imports();
+        assert_eq!(imports.len(), 1);
+        assert_eq!(imports[0].module, "env");
+        assert_eq!(imports[0].name, "use_gas");
+        assert_eq!(module.element_segments()[0].func_indices, [2]);
+
+        let mut gas: u64 = 0;
+        let import = sys::FizzyExternalFunction {
+            function: Some(use_gas),
+            context: &mut gas as *mut u64 as *mut std::ffi::c_void,
+        };
+        let module = std::mem::ManuallyDrop::new(module);
+        let mut instance = Instance(
+            NonNull::new(unsafe { sys::fizzy_instantiate(module.0.as_ptr(), &import, 1) }).unwrap(),
+            Default::default(),
+        );
+        // The start function.
+        assert_eq!(gas, 1);
+
+        gas = 0;
+        let result = instance.execute("add", &[Value::I32(1)]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(2)));
+        assert_eq!(gas, 4);
+
+        gas = 0;
+        let result = instance.execute("call", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(42)));
+        assert_eq!(gas, 3 + 4);
+
+        gas = 0;
+        assert!(!instance
+            .execute("spin", &[Value::I32(2)])
+            .unwrap()
+            .trapped());
+        assert_eq!(gas, 1 + 2 * 5 + 2);
+
+        let result = instance.execute("fail", &[]).unwrap();
+        assert!(result.trapped());
+        let trace = result.stack_trace();
+        assert_eq!(trace[0].function_index(), 5);
+        assert_eq!(trace[0].function_name(), Some("fail"));
+    }
+
+    #[test]
+    fn inject_gas_metering_reuses_type() {
+        /* wat2wasm
+          (func (import "env" "f") (param i64))
+          (func (param i64) (call 0 (local.get 0)))
+        */
+        let wasm = from_hex(&[
+            "0061736d0100000001050160017e0002090103656e7601660000030201000a08010600200010000b",
+        ]);
+        let instrumented = super::inject_gas_metering(&wasm, &CostTable::uniform(2)).unwrap();
+
+        /* wat2wasm
+          (func (import "env" "f") (param i64))
+          (func (import "env" "use_gas") (param i64))
+          (func (param i64) (call 1 (i64.const 6)) (call 0 (local.get 0)))
+        */
+        let expected = from_hex(&[
+            "0061736d0100000001050160017e0002170203656e760166000003656e76077573655f6761730000",
+            "030201000a0c010a0042061001200010000b",
+        ]);
+        assert_eq!(instrumented, expected);
+
+        assert_eq!(
+            super::inject_gas_metering(&[0, 1, 2], &CostTable::default()),
+            Err(Error::MalformedModule(
+                "invalid wasm module prefix".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn strip_custom_sections() {
+        let wasm = from_hex(WASM);
+        let stripped = super::strip_custom_sections(&wasm).unwrap();
+        assert!(crate::validate(&stripped));
+        assert_eq!(stripped, &wasm[..wasm.len() - 38]);
+
+        let mut instance = parse(&stripped).unwrap().instantiate().unwrap();
+        let result = instance.execute("fail", &[]).unwrap();
+        assert_eq!(result.stack_trace()[0].function_name(), None);
+
+        assert_eq!(
+            super::retain_custom_sections(&wasm, |name| name == "name"),
+            Ok(wasm.clone())
+        );
+        assert_eq!(
+            super::retain_custom_sections(&wasm, |name| name == ".debug_info"),
+            Ok(stripped)
+        );
+
+        /* custom sections "a" and "b" around the type section */
+        let wasm = from_hex(&["0061736d01000000000201610104016000000003016202"]);
+        assert_eq!(
+            super::retain_custom_sections(&wasm, |name| name == "b"),
+            Ok(from_hex(&["0061736d010000000104016000000003016202"]))
+        );
+        assert_eq!(
+            super::strip_custom_sections(&wasm),
+            Ok(from_hex(&["0061736d01000000010401600000"]))
+        );
+
+        assert_eq!(
+            super::strip_custom_sections(b"\0asm\x01\0\0\0\x01\x05"),
+            Err(Error::ParsingFailed)
+        );
+    }
+}
@@ -0,0 +1,388 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Statically typed function calls.
+//!
+//! The [`WasmParams`] and [`WasmResults`] traits map native Rust types and tuples to lists of
+//! WebAssembly values. The function type is checked once when a [`TypedFunction`] is created,
+//! afterwards the calls cannot fail because of argument or result type mismatch.
+//!
+//! [`ExtractValues`] converts lists of values back to tuples, e.g. the arguments of host functions.
+
+use crate::linker::function_type_from_sys;
+use crate::{sys, Error, Instance, Trap, Value, ValueType};
+use std::marker::PhantomData;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A native type corresponding to a WebAssembly value type.
+///
+/// The unsigned integer types are mapped to the signed WebAssembly types of the same width.
+pub trait WasmTy: sealed::Sealed + Copy {
+    #[doc(hidden)]
+    fn value_type() -> ValueType;
+    #[doc(hidden)]
+    fn into_value(self) -> Value;
+    #[doc(hidden)]
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+macro_rules! impl_wasm_ty {
+    ($ty:ty, $variant:ident, $wasm_ty:ty) => {
+        impl sealed::Sealed for $ty {}
+
+        impl WasmTy for $ty {
+            fn value_type() -> ValueType {
+                ValueType::$variant
+            }
+
+            fn into_value(self) -> Value {
+                Value::$variant(self as $wasm_ty)
+            }
+
+            fn from_value(value: Value) -> Option<Self> {
+                match value {
+                    Value::$variant(v) => Some(v as $ty),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_wasm_ty!(i32, I32, i32);
+impl_wasm_ty!(u32, I32, i32);
+impl_wasm_ty!(i64, I64, i64);
+impl_wasm_ty!(u64, I64, i64);
+impl_wasm_ty!(f32, F32, f32);
+impl_wasm_ty!(f64, F64, f64);
+
+/// A list of function parameters: `()`, a single [`WasmTy`] or a tuple of them.
+pub trait WasmParams: sealed::Sealed {
+    #[doc(hidden)]
+    fn value_types() -> Vec<ValueType>;
+    #[doc(hidden)]
+    fn into_values(self) -> Vec<Value>;
+    #[doc(hidden)]
+    fn from_values(values: &[Value]) -> Option<Self>
+    where
+        Self: Sized;
+}
+
+/// A list of function results: `()` or a single [`WasmTy`],
+/// as WebAssembly 1.0 functions have at most one result.
+pub trait WasmResults: sealed::Sealed + Sized {
+    #[doc(hidden)]
+    fn value_types() -> Vec<ValueType>;
+    #[doc(hidden)]
+    fn from_value(value: Option<Value>) -> Option<Self>;
+}
+
+impl<T: WasmTy> WasmParams for T {
+    fn value_types() -> Vec<ValueType> {
+        vec![T::value_type()]
+    }
+
+    fn into_values(self) -> Vec<Value> {
+        vec![self.into_value()]
+    }
+
+    fn from_values(values: &[Value]) -> Option<Self> {
+        match *values {
+            [value] => T::from_value(value),
+            _ => None,
+        }
+    }
+}
+
+impl<T: WasmTy> WasmResults for T {
+    fn value_types() -> Vec<ValueType> {
+        vec![T::value_type()]
+    }
+
+    fn from_value(value: Option<Value>) -> Option<Self> {
+        T::from_value(value?)
+    }
+}
+
+impl sealed::Sealed for () {}
+
+impl WasmParams for () {
+    fn value_types() -> Vec<ValueType> {
+        Vec::new()
+    }
+
+    fn into_values(self) -> Vec<Value> {
+        Vec::new()
+    }
+
+    fn from_values(values: &[Value]) -> Option<Self> {
+        if values.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+}
+
+impl WasmResults for () {
+    fn value_types() -> Vec<ValueType> {
+        Vec::new()
+    }
+
+    fn from_value(value: Option<Value>) -> Option<Self> {
+        match value {
+            None => Some(()),
+            Some(_) => None,
+        }
+    }
+}
+
+macro_rules! impl_wasm_params {
+    ($($t:ident)+) => {
+        impl<$($t: WasmTy),+> sealed::Sealed for ($($t,)+) {}
+
+        impl<$($t: WasmTy),+> WasmParams for ($($t,)+) {
+            fn value_types() -> Vec<ValueType> {
+                vec![$($t::value_type()),+]
+            }
+
+            #[allow(non_snake_case)]
+            fn into_values(self) -> Vec<Value> {
+                let ($($t,)+) = self;
+                vec![$($t.into_value()),+]
+            }
+
+            #[allow(non_snake_case)]
+            fn from_values(values: &[Value]) -> Option<Self> {
+                match *values {
+                    [$($t),+] => Some(($($t::from_value($t)?,)+)),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_wasm_params!(A);
+impl_wasm_params!(A B);
+impl_wasm_params!(A B C);
+impl_wasm_params!(A B C D);
+impl_wasm_params!(A B C D E);
+impl_wasm_params!(A B C D E F);
+impl_wasm_params!(A B C D E F G);
+impl_wasm_params!(A B C D E F G H);
+
+impl<A: WasmTy> WasmResults for (A,) {
+    fn value_types() -> Vec<ValueType> {
+        vec![A::value_type()]
+    }
+
+    fn from_value(value: Option<Value>) -> Option<Self> {
+        Some((A::from_value(value?)?,))
+    }
+}
+
+/// Conversion of a list of values to native Rust types.
+pub trait ExtractValues {
+    /// Converts the values to `T`, a single [`WasmTy`] or a tuple of them, e.g.
+    /// `args.extract::<(i32, f64)>()?` in a host function.
+    ///
+    /// Returns [`Error::ArgumentCountMismatch`] or [`Error::ArgumentTypeMismatch`] if the values
+    /// do not match the types of `T`.
+    fn extract<T: WasmParams>(&self) -> Result<T, Error>;
+}
+
+impl ExtractValues for [Value] {
+    fn extract<T: WasmParams>(&self) -> Result<T, Error> {
+        if self.len() != T::value_types().len() {
+            return Err(Error::ArgumentCountMismatch);
+        }
+        T::from_values(self).ok_or(Error::ArgumentTypeMismatch)
+    }
+}
+
+/// An exported function of an instance with its type checked against `Params` and `Results`.
+pub struct TypedFunction<Params, Results> {
+    module: *const sys::FizzyModule,
+    func_idx: u32,
+    _marker: PhantomData<fn(Params) -> Results>,
+}
+
+impl<Params: WasmParams, Results: WasmResults> TypedFunction<Params, Results> {
+    /// Call the function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `instance` is not the instance the function was taken from.
+    pub fn call(&self, instance: &mut Instance, params: Params) -> Result<Results, Trap> {
+        assert!(
+            instance.module() == self.module,
+            "typed function called with a different instance"
+        );
+        let result = instance
+            .execute_function(self.func_idx, &params.into_values())
+            .expect("function type must have been checked");
+        let value = result.into_result()?;
+        Ok(Results::from_value(value).expect("function type must have been checked"))
+    }
+}
+
+impl Instance {
+    /// Find an exported function by name and check its type against `Params` and `Results`.
+    pub fn typed_function<Params: WasmParams, Results: WasmResults>(
+        &self,
+        name: &str,
+    ) -> Result<TypedFunction<Params, Results>, Error> {
+        let func_idx = self
+            .find_exported_function_index(name)
+            .ok_or(Error::FunctionNotFound)?;
+        let func_type = unsafe { sys::fizzy_get_function_type(self.module(), func_idx) };
+        let func_type = function_type_from_sys(&func_type);
+        let params = Params::value_types();
+        if func_type.inputs.len() != params.len() {
+            return Err(Error::ArgumentCountMismatch);
+        }
+        if func_type.inputs != params {
+            return Err(Error::ArgumentTypeMismatch);
+        }
+        let outputs: Vec<ValueType> = func_type.output.into_iter().collect();
+        if outputs != Results::value_types() {
+            return Err(Error::ResultTypeMismatch);
+        }
+        Ok(TypedFunction {
+            module: self.module(),
+            func_idx,
+            _marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+
+    /* wat2wasm
+      (func (export "div") (param i32 i32) (result i32)
+        (i32.div_u (local.get 0) (local.get 1))
+      )
+      (func (export "add64") (param i64 i64) (result i64)
+        (i64.add (local.get 0) (local.get 1))
+      )
+      (func (export "nop"))
+      (func (export "fail") unreachable)
+    */
+    const WASM: &[&str] = &[
+        "0061736d0100000001100360027f7f017f60027e7e017e60000003050400010202071c0403646976",
+        "00000561646436340001036e6f700002046661696c00030a18040700200020016e0b070020002001",
+        "7c0b02000b0300000b",
+    ];
+
+    fn instance() -> Instance {
+        parse(from_hex(WASM)).unwrap().instantiate().unwrap()
+    }
+
+    #[test]
+    fn call() {
+        let mut instance = instance();
+
+        let div = instance.typed_function::<(u32, u32), u32>("div").unwrap();
+        assert_eq!(div.call(&mut instance, (42, 2)), Ok(21));
+        assert_eq!(div.call(&mut instance, (0xffff_fffe, 2)), Ok(0x7fff_ffff));
+        assert!(div.call(&mut instance, (1, 0)).is_err());
+
+        let add64 = instance
+            .typed_function::<(i64, i64), (i64,)>("add64")
+            .unwrap();
+        assert_eq!(add64.call(&mut instance, (-1, 2)), Ok((1,)));
+
+        let nop = instance.typed_function::<(), ()>("nop").unwrap();
+        assert_eq!(nop.call(&mut instance, ()), Ok(()));
+
+        let fail = instance.typed_function::<(), ()>("fail").unwrap();
+        let trap = fail.call(&mut instance, ()).unwrap_err();
+        assert_eq!(trap.stack_trace()[0].function_index(), 3);
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let instance = instance();
+        assert_eq!(
+            instance.typed_function::<(), ()>("foo").err().unwrap(),
+            Error::FunctionNotFound
+        );
+        assert_eq!(
+            instance.typed_function::<i32, i32>("div").err().unwrap(),
+            Error::ArgumentCountMismatch
+        );
+        assert_eq!(
+            instance
+                .typed_function::<(i32, i64), i32>("div")
+                .err()
+                .unwrap(),
+            Error::ArgumentTypeMismatch
+        );
+        assert_eq!(
+            instance
+                .typed_function::<(i32, i32), f32>("div")
+                .err()
+                .unwrap(),
+            Error::ResultTypeMismatch
+        );
+        assert_eq!(
+            instance
+                .typed_function::<(i32, i32), ()>("div")
+                .err()
+                .unwrap(),
+            Error::ResultTypeMismatch
+        );
+        assert_eq!(
+            instance.typed_function::<(), i32>("nop").err().unwrap(),
+            Error::ResultTypeMismatch
+        );
+    }
+
+    #[test]
+    fn extract() {
+        let values = [Value::I32(-1), Value::F64(1.5), Value::I64(7)];
+        assert_eq!(values.extract::<(i32, f64, i64)>(), Ok((-1, 1.5, 7)));
+        assert_eq!(
+            values.extract::<(u32, f64, u64)>(),
+            Ok((0xffff_ffff, 1.5, 7))
+        );
+        assert_eq!(values[..1].extract::<i32>(), Ok(-1));
+        assert_eq!(values[..1].extract::<(i32,)>(), Ok((-1,)));
+        assert_eq!(values[..0].extract::<()>(), Ok(()));
+        assert_eq!(
+            values.extract::<(i32, f64)>(),
+            Err(Error::ArgumentCountMismatch)
+        );
+        assert_eq!(values.extract::<()>(), Err(Error::ArgumentCountMismatch));
+        assert_eq!(
+            values.extract::<(i32, f32, i64)>(),
+            Err(Error::ArgumentTypeMismatch)
+        );
+        assert_eq!(
+            [Value::F32(2.0)].extract::<i32>(),
+            Err(Error::ArgumentTypeMismatch)
+        );
+
+        // The errors convert to the messages of host function failures.
+        let extract = |args: &[Value]| -> Result<i64, String> { Ok(args.extract::<i64>()?) };
+        assert_eq!(extract(&[Value::I64(1)]), Ok(1));
+        assert_eq!(extract(&[]), Err(Error::ArgumentCountMismatch.to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "typed function called with a different instance")]
+    fn different_instance() {
+        let instance = instance();
+        let nop = instance.typed_function::<(), ()>("nop").unwrap();
+        let _ = nop.call(&mut self::instance(), ());
+    }
+}
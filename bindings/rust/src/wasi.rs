@@ -0,0 +1,1503 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! WASI implemented in Rust.
+//!
+//! The functions of `wasi_snapshot_preview1` are provided as host functions by [`Wasi`].
+//! The guest sees only the arguments, environment variables, standard streams and preopened
+//! directories given to it. Paths are resolved inside the preopened directories: absolute
+//! paths, and `..` components and symbolic links escaping them are rejected with
+//! [`Errno::Notcapable`]. The resolved paths are then checked by the [`SandboxPolicy`].
+//!
+//! The supported functions are listed in [`FUNCTIONS`].
+
+use crate::host::{Caller, HostFunction, Imports};
+use crate::linker::FunctionType;
+use crate::{Error, Instance, Value, ValueType};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The name of the imported module.
+pub const MODULE: &str = "wasi_snapshot_preview1";
+
+/// All the supported functions.
+pub const FUNCTIONS: &[&str] = &[
+    "args_get",
+    "args_sizes_get",
+    "environ_get",
+    "environ_sizes_get",
+    "clock_time_get",
+    "random_get",
+    "fd_close",
+    "fd_read",
+    "fd_write",
+    "fd_seek",
+    "fd_prestat_get",
+    "fd_prestat_dir_name",
+    "path_open",
+    "path_create_directory",
+    "path_remove_directory",
+    "path_unlink_file",
+    "proc_exit",
+];
+
+/// The functions operating on the filesystem, to be excluded from the [`FUNCTIONS`] allowed by
+/// [`Wasi::allow_only()`] for guests without filesystem access.
+pub const FILESYSTEM_FUNCTIONS: &[&str] = &[
+    "fd_prestat_get",
+    "fd_prestat_dir_name",
+    "fd_seek",
+    "path_open",
+    "path_create_directory",
+    "path_remove_directory",
+    "path_unlink_file",
+];
+
+/// The handling of the calls to the functions not allowed by [`Wasi::allow_only()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisallowedCall {
+    /// The function returns [`Errno::Notcapable`].
+    Notcapable,
+    /// The function traps.
+    Trap,
+}
+
+/// The error codes returned to the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u16)]
+pub enum Errno {
+    Success = 0,
+    Acces = 2,
+    Again = 6,
+    Badf = 8,
+    Dquot = 19,
+    Exist = 20,
+    Fault = 21,
+    Intr = 27,
+    Inval = 28,
+    Io = 29,
+    Isdir = 31,
+    Mfile = 33,
+    Noent = 44,
+    Nosys = 52,
+    Notdir = 54,
+    Spipe = 70,
+    Notcapable = 76,
+}
+
+impl Errno {
+    /// The default mapping of host I/O errors, by their kinds.
+    pub fn from_io_error(error: &std::io::Error) -> Errno {
+        use std::io::ErrorKind;
+        match error.kind() {
+            ErrorKind::NotFound => Errno::Noent,
+            ErrorKind::PermissionDenied => Errno::Acces,
+            ErrorKind::AlreadyExists => Errno::Exist,
+            ErrorKind::WouldBlock => Errno::Again,
+            ErrorKind::InvalidInput => Errno::Inval,
+            ErrorKind::Interrupted => Errno::Intr,
+            _ => Errno::Io,
+        }
+    }
+}
+
+// The flags of path_open.
+const OFLAGS_CREAT: u32 = 1;
+const OFLAGS_DIRECTORY: u32 = 2;
+const OFLAGS_EXCL: u32 = 4;
+const OFLAGS_TRUNC: u32 = 8;
+const FDFLAGS_APPEND: u32 = 1;
+const RIGHTS_FD_READ: u64 = 1 << 1;
+const RIGHTS_FD_WRITE: u64 = 1 << 6;
+
+/// The exit code passed by the guest to `proc_exit`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExitCode(pub u32);
+
+impl ExitCode {
+    /// The exit code of a successful run, also the one of a `_start` function returning.
+    pub const SUCCESS: ExitCode = ExitCode(0);
+
+    /// Returns whether the exit code is 0.
+    pub fn is_success(self) -> bool {
+        self == Self::SUCCESS
+    }
+}
+
+impl std::fmt::Display for ExitCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "exit code {}", self.0)
+    }
+}
+
+impl From<ExitCode> for std::process::ExitCode {
+    /// The exit code of the host process, truncated to 8 bits as by POSIX `exit()`.
+    fn from(code: ExitCode) -> Self {
+        std::process::ExitCode::from(code.0 as u8)
+    }
+}
+
+/// The limits of the I/O of the guest, `None` for no limit.
+///
+/// The calls exceeding a limit fail: opening a descriptor with [`Errno::Mfile`], reading or
+/// writing with [`Errno::Dquot`]. A read is shortened to the bytes left to read, but a write
+/// fails if all the bytes cannot be written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoLimits {
+    /// The maximum number of open descriptors, including the standard streams and the preopened
+    /// directories.
+    pub max_descriptors: Option<u32>,
+    /// The maximum number of bytes read from all the descriptors.
+    pub max_bytes_read: Option<u64>,
+    /// The maximum number of bytes written to all the descriptors.
+    pub max_bytes_written: Option<u64>,
+}
+
+/// The statistics of the I/O of the guest.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoStats {
+    /// The number of currently open descriptors.
+    pub open_descriptors: u32,
+    /// The maximum number of descriptors open at the same time.
+    pub peak_descriptors: u32,
+    /// The number of bytes read from all the descriptors.
+    pub bytes_read: u64,
+    /// The number of bytes written to all the descriptors.
+    pub bytes_written: u64,
+    /// The number of calls failed because of the [`IoLimits`].
+    pub limited_calls: u64,
+}
+
+/// The kind of access to a path requested by the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Opening a file for reading, or opening a directory.
+    Read,
+    /// Opening a file for writing or truncating it, or removing a file or directory.
+    Write,
+    /// Creating a file or directory.
+    Create,
+}
+
+/// A policy deciding which paths inside the preopened directories the guest can access.
+///
+/// The policy is given canonical host paths, with the symbolic links resolved, except for
+/// the last component of a path not existing yet or removed by the guest. Denied accesses are
+/// reported to the guest as [`Errno::Acces`].
+///
+/// The checks are not atomic with the filesystem operations, so the host must not let the
+/// preopened directories be modified concurrently by untrusted parties.
+pub trait SandboxPolicy: Send {
+    /// Returns whether the access to the path is allowed.
+    fn allows(&self, path: &Path, access: Access) -> bool;
+}
+
+impl<F> SandboxPolicy for F
+where
+    F: Fn(&Path, Access) -> bool + Send,
+{
+    fn allows(&self, path: &Path, access: Access) -> bool {
+        self(path, access)
+    }
+}
+
+/// A [`SandboxPolicy`] allowing the accesses to the given paths and everything under them.
+#[derive(Clone, Debug, Default)]
+pub struct PathPolicy {
+    rules: Vec<(PathBuf, Access)>,
+}
+
+impl PathPolicy {
+    /// Create a policy denying all accesses.
+    pub fn new() -> Self {
+        PathPolicy::default()
+    }
+
+    /// Allow the access to the host path and everything under it.
+    ///
+    /// The path is canonicalized if it exists.
+    pub fn allow<P: Into<PathBuf>>(mut self, path: P, access: Access) -> Self {
+        let path = path.into();
+        let path = path.canonicalize().unwrap_or(path);
+        self.rules.push((path, access));
+        self
+    }
+}
+
+impl SandboxPolicy for PathPolicy {
+    fn allows(&self, path: &Path, access: Access) -> bool {
+        self.rules
+            .iter()
+            .any(|(prefix, allowed)| *allowed == access && path.starts_with(prefix))
+    }
+}
+
+/// The clocks of `clock_time_get`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockId {
+    Realtime,
+    Monotonic,
+    ProcessCputime,
+    ThreadCputime,
+}
+
+impl ClockId {
+    fn from_u32(id: u32) -> Option<ClockId> {
+        match id {
+            0 => Some(ClockId::Realtime),
+            1 => Some(ClockId::Monotonic),
+            2 => Some(ClockId::ProcessCputime),
+            3 => Some(ClockId::ThreadCputime),
+            _ => None,
+        }
+    }
+}
+
+type Clock = dyn FnMut(ClockId) -> Result<u64, Errno> + Send;
+
+type Random = dyn FnMut(&mut [u8]) -> std::io::Result<()> + Send;
+
+/// The default clock: the time since the Unix epoch and since the creation of the environment.
+fn system_clock() -> Box<Clock> {
+    let start = Instant::now();
+    Box::new(move |id| {
+        let time = match id {
+            ClockId::Realtime => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| Errno::Io)?,
+            ClockId::Monotonic => start.elapsed(),
+            _ => return Err(Errno::Inval),
+        };
+        Ok(time.as_nanos() as u64)
+    })
+}
+
+fn system_random(data: &mut [u8]) -> std::io::Result<()> {
+    File::open("/dev/urandom")?.read_exact(data)
+}
+
+enum Descriptor {
+    Reader(Box<dyn Read + Send>),
+    Writer(Box<dyn Write + Send>),
+    File(File),
+    /// A directory opened by the guest, inside the preopened directory `root`.
+    Dir {
+        host: PathBuf,
+        root: PathBuf,
+    },
+    /// A directory given to the guest, with the name it is given under.
+    Preopen {
+        host: PathBuf,
+        guest: String,
+    },
+}
+
+impl Descriptor {
+    /// The host path of the directory and of the preopened directory containing it.
+    fn dir(&self) -> Option<(&Path, &Path)> {
+        match self {
+            Descriptor::Dir { host, root } => Some((host, root)),
+            Descriptor::Preopen { host, .. } => Some((host, host)),
+            _ => None,
+        }
+    }
+}
+
+struct State {
+    args: Vec<String>,
+    env: Vec<String>,
+    fds: Vec<Option<Descriptor>>,
+    map_error: fn(&std::io::Error) -> Errno,
+    policy: Box<dyn SandboxPolicy>,
+    clock: Box<Clock>,
+    random: Box<Random>,
+    allowed: Option<HashSet<String>>,
+    disallowed_call: DisallowedCall,
+    limits: IoLimits,
+    stats: IoStats,
+    exit_code: Option<ExitCode>,
+}
+
+impl State {
+    fn descriptor(&mut self, fd: u32) -> Result<&mut Descriptor, Errno> {
+        self.fds
+            .get_mut(fd as usize)
+            .and_then(Option::as_mut)
+            .ok_or(Errno::Badf)
+    }
+
+    fn insert(&mut self, descriptor: Descriptor) -> Result<u32, Errno> {
+        let open = self.open_descriptors();
+        if self.limits.max_descriptors.is_some_and(|max| open >= max) {
+            return Err(self.limited(Errno::Mfile));
+        }
+        self.stats.peak_descriptors = self.stats.peak_descriptors.max(open + 1);
+        match self.fds.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.fds[fd] = Some(descriptor);
+                Ok(fd as u32)
+            }
+            None => {
+                self.fds.push(Some(descriptor));
+                Ok((self.fds.len() - 1) as u32)
+            }
+        }
+    }
+
+    fn open_descriptors(&self) -> u32 {
+        self.fds.iter().flatten().count() as u32
+    }
+
+    /// Counts a call failed because of the limits.
+    fn limited(&mut self, errno: Errno) -> Errno {
+        self.stats.limited_calls += 1;
+        errno
+    }
+
+    /// Resolves the guest path relative to the directory `fd` to a host path, returned with
+    /// the preopened directory containing it.
+    ///
+    /// The symbolic links are followed, except for the last component if `follow` is false.
+    /// A dangling symbolic link cannot be followed, so it is rejected.
+    fn resolve(&mut self, fd: u32, path: &[u8], follow: bool) -> Result<(PathBuf, PathBuf), Errno> {
+        let (dir, root) = self.descriptor(fd)?.dir().ok_or(Errno::Notdir)?;
+        let (dir, root) = (dir.canonicalize(), root.canonicalize());
+        let (mut resolved, root) = match (dir, root) {
+            (Ok(dir), Ok(root)) => (dir, root),
+            (Err(error), _) | (_, Err(error)) => return Err(self.io_error(error)),
+        };
+        let path = std::str::from_utf8(path).map_err(|_| Errno::Inval)?;
+        let mut components = Path::new(path).components().peekable();
+        while let Some(component) = components.next() {
+            match component {
+                Component::Normal(name) => {
+                    resolved.push(name);
+                    let last = components.peek().is_none();
+                    let is_symlink = resolved
+                        .symlink_metadata()
+                        .is_ok_and(|metadata| metadata.file_type().is_symlink());
+                    if is_symlink && (follow || !last) {
+                        resolved = resolved.canonicalize().map_err(|_| Errno::Notcapable)?;
+                    }
+                }
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                _ => return Err(Errno::Notcapable),
+            }
+            if !resolved.starts_with(&root) {
+                return Err(Errno::Notcapable);
+            }
+        }
+        Ok((resolved, root))
+    }
+
+    fn check(&self, path: &Path, access: Access) -> Result<(), Errno> {
+        if self.policy.allows(path, access) {
+            Ok(())
+        } else {
+            Err(Errno::Acces)
+        }
+    }
+
+    fn is_allowed(&self, name: &str) -> bool {
+        self.allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(name))
+    }
+
+    fn io_error(&self, error: std::io::Error) -> Errno {
+        (self.map_error)(&error)
+    }
+}
+
+/// The WASI environment of a guest.
+///
+/// By default, the guest has no arguments, no environment variables and no preopened
+/// directories, and its standard streams are those of the host process.
+pub struct Wasi {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Wasi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Wasi {
+    /// Create the default environment.
+    pub fn new() -> Self {
+        Wasi {
+            state: Arc::new(Mutex::new(State {
+                args: Vec::new(),
+                env: Vec::new(),
+                fds: vec![
+                    Some(Descriptor::Reader(Box::new(std::io::stdin()))),
+                    Some(Descriptor::Writer(Box::new(std::io::stdout()))),
+                    Some(Descriptor::Writer(Box::new(std::io::stderr()))),
+                ],
+                map_error: Errno::from_io_error,
+                policy: Box::new(|_: &Path, _| true),
+                clock: system_clock(),
+                random: Box::new(system_random),
+                allowed: None,
+                disallowed_call: DisallowedCall::Notcapable,
+                limits: IoLimits::default(),
+                stats: IoStats::default(),
+                exit_code: None,
+            })),
+        }
+    }
+
+    /// Add a command line argument. The first one is the program name by convention.
+    pub fn arg(self, arg: &str) -> Self {
+        self.lock().args.push(arg.to_string());
+        self
+    }
+
+    /// Add an environment variable.
+    pub fn env(self, key: &str, value: &str) -> Self {
+        self.lock().env.push(format!("{}={}", key, value));
+        self
+    }
+
+    /// Replace the standard input.
+    pub fn stdin(self, reader: Box<dyn Read + Send>) -> Self {
+        self.lock().fds[0] = Some(Descriptor::Reader(reader));
+        self
+    }
+
+    /// Replace the standard output.
+    pub fn stdout(self, writer: Box<dyn Write + Send>) -> Self {
+        self.lock().fds[1] = Some(Descriptor::Writer(writer));
+        self
+    }
+
+    /// Replace the standard error.
+    pub fn stderr(self, writer: Box<dyn Write + Send>) -> Self {
+        self.lock().fds[2] = Some(Descriptor::Writer(writer));
+        self
+    }
+
+    /// Give the guest access to the host directory under the name `guest`.
+    ///
+    /// The preopened directories get the descriptors following the standard streams, in the
+    /// order they are added.
+    pub fn preopen_dir<P: Into<PathBuf>>(self, host: P, guest: &str) -> Self {
+        self.lock().fds.push(Some(Descriptor::Preopen {
+            host: host.into(),
+            guest: guest.to_string(),
+        }));
+        self
+    }
+
+    /// Set the policy checking the accesses to the paths inside the preopened directories.
+    ///
+    /// By default, all accesses are allowed.
+    pub fn sandbox_policy<P: SandboxPolicy + 'static>(self, policy: P) -> Self {
+        self.lock().policy = Box::new(policy);
+        self
+    }
+
+    /// Replace the clocks of `clock_time_get`, returning the time in nanoseconds.
+    ///
+    /// A fixed or scripted clock makes the executions reproducible. By default, the realtime and
+    /// monotonic clocks are those of the host, and the CPU time clocks are not supported.
+    pub fn clock<F>(self, clock: F) -> Self
+    where
+        F: FnMut(ClockId) -> Result<u64, Errno> + Send + 'static,
+    {
+        self.lock().clock = Box::new(clock);
+        self
+    }
+
+    /// Replace the source of `random_get`, filling the buffer with random bytes.
+    ///
+    /// A seeded generator makes the executions reproducible. By default, the bytes are read from
+    /// `/dev/urandom`.
+    pub fn random<F>(self, random: F) -> Self
+    where
+        F: FnMut(&mut [u8]) -> std::io::Result<()> + Send + 'static,
+    {
+        self.lock().random = Box::new(random);
+        self
+    }
+
+    /// Allow the guest to call only the given functions, e.g. `clock_time_get` and `random_get`.
+    ///
+    /// The other functions are still defined, so the modules importing them can be instantiated,
+    /// but their calls are handled as given. `proc_exit` always traps, without an exit code.
+    pub fn allow_only(self, functions: &[&str], disallowed_call: DisallowedCall) -> Self {
+        {
+            let mut state = self.lock();
+            state.allowed = Some(functions.iter().map(|name| name.to_string()).collect());
+            state.disallowed_call = disallowed_call;
+        }
+        self
+    }
+
+    /// Set the limits of the I/O of the guest.
+    pub fn io_limits(self, limits: IoLimits) -> Self {
+        self.lock().limits = limits;
+        self
+    }
+
+    /// The statistics of the I/O of the guest, accumulated over all the executions.
+    pub fn io_stats(&self) -> IoStats {
+        let state = self.lock();
+        let open_descriptors = state.open_descriptors();
+        IoStats {
+            open_descriptors,
+            peak_descriptors: state.stats.peak_descriptors.max(open_descriptors),
+            ..state.stats
+        }
+    }
+
+    /// Replace the mapping of host I/O errors to the error codes returned to the guest.
+    pub fn map_errors(self, map_error: fn(&std::io::Error) -> Errno) -> Self {
+        self.lock().map_error = map_error;
+        self
+    }
+
+    /// Define the WASI functions in the imports.
+    ///
+    /// The functions of all the instances importing them share this environment.
+    pub fn define(&self, imports: &mut Imports) {
+        self.define_function(imports, "args_get", &[I32, I32], args_get);
+        self.define_function(imports, "args_sizes_get", &[I32, I32], args_sizes_get);
+        self.define_function(imports, "environ_get", &[I32, I32], environ_get);
+        self.define_function(imports, "environ_sizes_get", &[I32, I32], environ_sizes_get);
+        self.define_function(imports, "clock_time_get", &[I32, I64, I32], clock_time_get);
+        self.define_function(imports, "random_get", &[I32, I32], random_get);
+        self.define_function(imports, "fd_close", &[I32], fd_close);
+        self.define_function(imports, "fd_read", &[I32, I32, I32, I32], fd_read);
+        self.define_function(imports, "fd_write", &[I32, I32, I32, I32], fd_write);
+        self.define_function(imports, "fd_seek", &[I32, I64, I32, I32], fd_seek);
+        self.define_function(imports, "fd_prestat_get", &[I32, I32], fd_prestat_get);
+        self.define_function(
+            imports,
+            "fd_prestat_dir_name",
+            &[I32, I32, I32],
+            fd_prestat_dir_name,
+        );
+        self.define_function(
+            imports,
+            "path_open",
+            &[I32, I32, I32, I32, I32, I64, I64, I32, I32],
+            path_open,
+        );
+        self.define_function(
+            imports,
+            "path_create_directory",
+            &[I32, I32, I32],
+            path_create_directory,
+        );
+        self.define_function(
+            imports,
+            "path_remove_directory",
+            &[I32, I32, I32],
+            path_remove_directory,
+        );
+        self.define_function(
+            imports,
+            "path_unlink_file",
+            &[I32, I32, I32],
+            path_unlink_file,
+        );
+
+        let state = self.state.clone();
+        imports.define(
+            MODULE,
+            "proc_exit",
+            HostFunction::new(
+                FunctionType {
+                    inputs: vec![I32],
+                    output: None,
+                },
+                move |args| {
+                    let mut state = lock(&state);
+                    if !state.is_allowed("proc_exit") {
+                        return Err("proc_exit is not allowed".to_string());
+                    }
+                    let code = ExitCode(arg(args, 0));
+                    state.exit_code = Some(code);
+                    // The execution is ended by a trap, distinguished by its reason.
+                    Err(format!("proc_exit: {}", code))
+                },
+            ),
+        );
+    }
+
+    /// The exit code passed to `proc_exit`, if it has been called.
+    ///
+    /// An execution trapped by `proc_exit` has the trap reason `proc_exit: exit code <code>`.
+    pub fn exit_code(&self) -> Option<ExitCode> {
+        self.lock().exit_code
+    }
+
+    /// Take the exit code passed to `proc_exit`, so a following execution can be checked.
+    pub fn take_exit_code(&self) -> Option<ExitCode> {
+        self.lock().exit_code.take()
+    }
+
+    /// Execute the `_start` function of a WASI command, returning its exit code.
+    ///
+    /// The exit code is [`ExitCode::SUCCESS`] if `_start` returns. [`Error::Trapped`] is returned
+    /// with the trap if it traps other than by calling `proc_exit`.
+    pub fn run(&self, instance: &mut Instance) -> Result<ExitCode, Error> {
+        self.take_exit_code();
+        let result = instance.execute("_start", &[])?;
+        match self.exit_code() {
+            Some(code) => Ok(code),
+            None => result
+                .into_result()
+                .map(|_| ExitCode::SUCCESS)
+                .map_err(Error::from),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+
+    fn define_function(
+        &self,
+        imports: &mut Imports,
+        name: &'static str,
+        inputs: &[ValueType],
+        func: fn(&mut State, &mut Caller<'_>, &[Value]) -> Result<(), Errno>,
+    ) {
+        let state = self.state.clone();
+        imports.define(
+            MODULE,
+            name,
+            HostFunction::new_with_caller(
+                FunctionType {
+                    inputs: inputs.to_vec(),
+                    output: Some(I32),
+                },
+                move |caller, args| {
+                    let mut state = lock(&state);
+                    if !state.is_allowed(name) {
+                        return match state.disallowed_call {
+                            DisallowedCall::Notcapable => {
+                                Ok(Some(Value::I32(Errno::Notcapable as i32)))
+                            }
+                            DisallowedCall::Trap => Err(format!("{} is not allowed", name)),
+                        };
+                    }
+                    let errno = match func(&mut state, caller, args) {
+                        Ok(()) => Errno::Success,
+                        Err(errno) => errno,
+                    };
+                    Ok(Some(Value::I32(errno as i32)))
+                },
+            ),
+        );
+    }
+}
+
+impl std::fmt::Debug for Wasi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.lock();
+        f.debug_struct("Wasi")
+            .field("args", &state.args)
+            .field("env", &state.env)
+            .field("exit_code", &state.exit_code)
+            .finish()
+    }
+}
+
+const I32: ValueType = ValueType::I32;
+const I64: ValueType = ValueType::I64;
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    // The panics of host functions are caught, so a poisoned state is still consistent.
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The argument as u32, the arguments are checked against the function type by the caller.
+fn arg(args: &[Value], index: usize) -> u32 {
+    match args[index] {
+        Value::I32(value) => value as u32,
+        _ => unreachable!("argument {} is not i32", index),
+    }
+}
+
+fn arg64(args: &[Value], index: usize) -> u64 {
+    match args[index] {
+        Value::I64(value) => value as u64,
+        _ => unreachable!("argument {} is not i64", index),
+    }
+}
+
+/// Checks that the memory range is within the instance memory, before a buffer of its size is
+/// allocated, so that guest-controlled sizes cannot exhaust the host memory.
+fn check_range(caller: &Caller<'_>, offset: u32, size: u64) -> Result<(), Errno> {
+    match (offset as u64).checked_add(size) {
+        Some(end) if end <= caller.memory_size() as u64 => Ok(()),
+        _ => Err(Errno::Fault),
+    }
+}
+
+fn load(caller: &Caller<'_>, offset: u32, size: u32) -> Result<Vec<u8>, Errno> {
+    check_range(caller, offset, size as u64)?;
+    let mut data = vec![0; size as usize];
+    caller
+        .memory_get(offset, &mut data)
+        .map_err(|_| Errno::Fault)?;
+    Ok(data)
+}
+
+fn store(caller: &mut Caller<'_>, offset: u32, data: &[u8]) -> Result<(), Errno> {
+    caller.memory_set(offset, data).map_err(|_| Errno::Fault)
+}
+
+fn load_u32(caller: &Caller<'_>, offset: u32) -> Result<u32, Errno> {
+    let mut data = [0; 4];
+    caller
+        .memory_get(offset, &mut data)
+        .map_err(|_| Errno::Fault)?;
+    Ok(u32::from_le_bytes(data))
+}
+
+/// Loads the iovec array, as pairs of buffer offsets and sizes.
+fn load_iovecs(caller: &Caller<'_>, iovs: u32, iovs_len: u32) -> Result<Vec<(u32, u32)>, Errno> {
+    (0..iovs_len)
+        .map(|i| {
+            let iov = i
+                .checked_mul(8)
+                .and_then(|offset| iovs.checked_add(offset))
+                .ok_or(Errno::Fault)?;
+            Ok((
+                load_u32(caller, iov)?,
+                load_u32(caller, iov.checked_add(4).ok_or(Errno::Fault)?)?,
+            ))
+        })
+        .collect()
+}
+
+/// Stores the strings null-terminated into the buffer and their offsets into the pointer array.
+fn store_strings(
+    caller: &mut Caller<'_>,
+    strings: &[String],
+    ptrs: u32,
+    buf: u32,
+) -> Result<(), Errno> {
+    let mut offset = buf;
+    for (i, string) in strings.iter().enumerate() {
+        let ptr = (4 * i as u32).checked_add(ptrs).ok_or(Errno::Fault)?;
+        store(caller, ptr, &offset.to_le_bytes())?;
+        let mut data = string.as_bytes().to_vec();
+        data.push(0);
+        store(caller, offset, &data)?;
+        offset = offset.checked_add(data.len() as u32).ok_or(Errno::Fault)?;
+    }
+    Ok(())
+}
+
+fn store_sizes(
+    caller: &mut Caller<'_>,
+    strings: &[String],
+    count_ptr: u32,
+    size_ptr: u32,
+) -> Result<(), Errno> {
+    let size: usize = strings.iter().map(|string| string.len() + 1).sum();
+    store(caller, count_ptr, &(strings.len() as u32).to_le_bytes())?;
+    store(caller, size_ptr, &(size as u32).to_le_bytes())
+}
+
+fn args_get(state: &mut State, caller: &mut Caller<'_>, args: &[Value]) -> Result<(), Errno> {
+    store_strings(caller, &state.args, arg(args, 0), arg(args, 1))
+}
+
+fn args_sizes_get(state: &mut State, caller: &mut Caller<'_>, args: &[Value]) -> Result<(), Errno> {
+    store_sizes(caller, &state.args, arg(args, 0), arg(args, 1))
+}
+
+fn environ_get(state: &mut State, caller: &mut Caller<'_>, args: &[Value]) -> Result<(), Errno> {
+    store_strings(caller, &state.env, arg(args, 0), arg(args, 1))
+}
+
+fn environ_sizes_get(
+    state: &mut State,
+    caller: &mut Caller<'_>,
+    args: &[Value],
+) -> Result<(), Errno> {
+    store_sizes(caller, &state.env, arg(args, 0), arg(args, 1))
+}
+
+fn clock_time_get(state: &mut State, caller: &mut Caller<'_>, args: &[Value]) -> Result<(), Errno> {
+    let id = ClockId::from_u32(arg(args, 0)).ok_or(Errno::Inval)?;
+    let time = (state.clock)(id)?;
+    store(caller, arg(args, 2), &time.to_le_bytes())
+}
+
+fn random_get(state: &mut State, caller: &mut Caller<'_>, args: &[Value]) -> Result<(), Errno> {
+    check_range(caller, arg(args, 0), arg(args, 1) as u64)?;
+    let mut data = vec![0; arg(args, 1) as usize];
+    (state.random)(&mut data).map_err(|error| state.io_error(error))?;
+    store(caller, arg(args, 0), &data)
+}
+
+fn fd_close(state: &mut State, _: &mut Caller<'_>, args: &[Value]) -> Result<(), Errno> {
+    state.descriptor(arg(args, 0))?;
+    state.fds[arg(args, 0) as usize] = None;
+    Ok(())
+}
+
+fn fd_read(state: &mut State, caller: &mut Caller<'_>, args: &[Value]) -> Result<(), Errno> {
+    let iovecs = load_iovecs(caller, arg(args, 1), arg(args, 2))?;
+    let mut remaining = state
+        .limits
+        .max_bytes_read
+        .map_or(u64::MAX, |max| max.saturating_sub(state.stats.bytes_read));
+    if remaining == 0 && iovecs.iter().any(|&(_, len)| len != 0) {
+        return Err(state.limited(Errno::Dquot));
+    }
+    let mut total = 0u32;
+    for (buf, len) in iovecs {
+        let buf_len = (len as u64).min(remaining);
+        check_range(caller, buf, buf_len)?;
+        let mut data = vec![0; buf_len as usize];
+        let result = match state.descriptor(arg(args, 0))? {
+            Descriptor::Reader(reader) => reader.read(&mut data),
+            Descriptor::File(file) => file.read(&mut data),
+            _ => return Err(Errno::Badf),
+        };
+        let size = result.map_err(|error| state.io_error(error))?;
+        store(caller, buf, &data[..size])?;
+        state.stats.bytes_read += size as u64;
+        remaining -= size as u64;
+        total += size as u32;
+        if size < len as usize {
+            break;
+        }
+    }
+    store(caller, arg(args, 3), &total.to_le_bytes())
+}
+
+fn fd_write(state: &mut State, caller: &mut Caller<'_>, args: &[Value]) -> Result<(), Errno> {
+    let iovecs = load_iovecs(caller, arg(args, 1), arg(args, 2))?;
+    let size: u64 = iovecs.iter().map(|&(_, len)| len as u64).sum();
+    if let Some(max) = state.limits.max_bytes_written {
+        if state.stats.bytes_written + size > max {
+            return Err(state.limited(Errno::Dquot));
+        }
+    }
+    let mut total = 0u32;
+    for (buf, len) in iovecs {
+        let data = load(caller, buf, len)?;
+        let result = match state.descriptor(arg(args, 0))? {
+            Descriptor::Writer(writer) => writer.write_all(&data).and_then(|_| writer.flush()),
+            Descriptor::File(file) => file.write_all(&data),
+            _ => return Err(Errno::Badf),
+        };
+        result.map_err(|error| state.io_error(error))?;
+        state.stats.bytes_written += len as u64;
+        total += len;
+    }
+    store(caller, arg(args, 3), &total.to_le_bytes())
+}
+
+fn fd_seek(state: &mut State, caller: &mut Caller<'_>, args: &[Value]) -> Result<(), Errno> {
+    let offset = arg64(args, 1) as i64;
+    let pos = match arg(args, 2) {
+        0 if offset >= 0 => SeekFrom::Start(offset as u64),
+        1 => SeekFrom::Current(offset),
+        2 => SeekFrom::End(offset),
+        _ => return Err(Errno::Inval),
+    };
+    let result = match state.descriptor(arg(args, 0))? {
+        Descriptor::File(file) => file.seek(pos),
+        Descriptor::Reader(_) | Descriptor::Writer(_) => return Err(Errno::Spipe),
+        _ => return Err(Errno::Badf),
+    };
+    let position = result.map_err(|error| state.io_error(error))?;
+    store(caller, arg(args, 3), &position.to_le_bytes())
+}
+
+fn fd_prestat_get(state: &mut State, caller: &mut Caller<'_>, args: &[Value]) -> Result<(), Errno> {
+    match state.descriptor(arg(args, 0))? {
+        Descriptor::Preopen { guest, .. } => {
+            // The prestat of a directory: the tag 0 followed by the length of its name.
+            let mut prestat = [0; 8];
+            prestat[4..].copy_from_slice(&(guest.len() as u32).to_le_bytes());
+            store(caller, arg(args, 1), &prestat)
+        }
+        _ => Err(Errno::Badf),
+    }
+}
+
+fn fd_prestat_dir_name(
+    state: &mut State,
+    caller: &mut Caller<'_>,
+    args: &[Value],
+) -> Result<(), Errno> {
+    match state.descriptor(arg(args, 0))? {
+        Descriptor::Preopen { guest, .. } => {
+            let name = guest.as_bytes();
+            if (arg(args, 2) as usize) < name.len() {
+                return Err(Errno::Inval);
+            }
+            store(caller, arg(args, 1), name)
+        }
+        _ => Err(Errno::Badf),
+    }
+}
+
+fn path_open(state: &mut State, caller: &mut Caller<'_>, args: &[Value]) -> Result<(), Errno> {
+    let path = load(caller, arg(args, 2), arg(args, 3))?;
+    let (path, root) = state.resolve(arg(args, 0), &path, true)?;
+    let oflags = arg(args, 4);
+    let rights = arg64(args, 5);
+    let fdflags = arg(args, 7);
+
+    let descriptor = if oflags & OFLAGS_DIRECTORY != 0 || path.is_dir() {
+        if !path.is_dir() {
+            return Err(Errno::Notdir);
+        }
+        state.check(&path, Access::Read)?;
+        Descriptor::Dir { host: path, root }
+    } else {
+        let write = rights & RIGHTS_FD_WRITE != 0;
+        let read = rights & RIGHTS_FD_READ != 0 || !write;
+        if oflags & OFLAGS_CREAT != 0 && !path.exists() {
+            state.check(&path, Access::Create)?;
+        }
+        if write || oflags & OFLAGS_TRUNC != 0 {
+            state.check(&path, Access::Write)?;
+        }
+        if read {
+            state.check(&path, Access::Read)?;
+        }
+        let file = OpenOptions::new()
+            .read(read)
+            .write(write)
+            .append(fdflags & FDFLAGS_APPEND != 0)
+            .create(oflags & OFLAGS_CREAT != 0)
+            .create_new(oflags & OFLAGS_CREAT != 0 && oflags & OFLAGS_EXCL != 0)
+            .truncate(oflags & OFLAGS_TRUNC != 0)
+            .open(&path)
+            .map_err(|error| state.io_error(error))?;
+        Descriptor::File(file)
+    };
+    let fd = state.insert(descriptor)?;
+    store(caller, arg(args, 8), &fd.to_le_bytes())
+}
+
+fn path_create_directory(
+    state: &mut State,
+    caller: &mut Caller<'_>,
+    args: &[Value],
+) -> Result<(), Errno> {
+    let path = load(caller, arg(args, 1), arg(args, 2))?;
+    let (path, _) = state.resolve(arg(args, 0), &path, true)?;
+    state.check(&path, Access::Create)?;
+    std::fs::create_dir(path).map_err(|error| state.io_error(error))
+}
+
+fn path_remove_directory(
+    state: &mut State,
+    caller: &mut Caller<'_>,
+    args: &[Value],
+) -> Result<(), Errno> {
+    let path = load(caller, arg(args, 1), arg(args, 2))?;
+    let (path, _) = state.resolve(arg(args, 0), &path, false)?;
+    state.check(&path, Access::Write)?;
+    std::fs::remove_dir(path).map_err(|error| state.io_error(error))
+}
+
+fn path_unlink_file(
+    state: &mut State,
+    caller: &mut Caller<'_>,
+    args: &[Value],
+) -> Result<(), Errno> {
+    let path = load(caller, arg(args, 1), arg(args, 2))?;
+    let (path, _) = state.resolve(arg(args, 0), &path, false)?;
+    if path
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.is_dir())
+    {
+        return Err(Errno::Isdir);
+    }
+    state.check(&path, Access::Write)?;
+    std::fs::remove_file(path).map_err(|error| state.io_error(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::test_utils::from_hex;
+
+    /* wat2wasm
+      (import "wasi_snapshot_preview1" "fd_write"
+        (func $fd_write (param i32 i32 i32 i32) (result i32)))
+      (import "wasi_snapshot_preview1" "fd_read"
+        (func $fd_read (param i32 i32 i32 i32) (result i32)))
+      (import "wasi_snapshot_preview1" "args_sizes_get"
+        (func $args_sizes_get (param i32 i32) (result i32)))
+      (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+      (import "wasi_snapshot_preview1" "path_open"
+        (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+      (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+      (memory (export "memory") 1)
+      (data (i32.const 0) "\08\00\00\00\06\00\00\00hello\n")
+      (data (i32.const 32) "\40\00\00\00\10\00\00\00")
+      (data (i32.const 80) "out.txt")
+      (func (export "_start")
+        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))
+        (drop (call $args_sizes_get (i32.const 20) (i32.const 24)))
+        (call $proc_exit (i32.load (i32.const 20))))
+      (func (export "echo") (result i32)
+        ;; The number of read bytes is stored as the length of the iovec.
+        (drop (call $fd_read (i32.const 0) (i32.const 32) (i32.const 1) (i32.const 36)))
+        (call $fd_write (i32.const 1) (i32.const 32) (i32.const 1) (i32.const 40)))
+      (func (export "write_file") (result i32) (local i32)
+        (local.set 0 (call $path_open (i32.const 3) (i32.const 0) (i32.const 80) (i32.const 7)
+          (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 96)))
+        (if (local.get 0) (then (return (local.get 0))))
+        (drop (call $fd_write (i32.load (i32.const 96)) (i32.const 0) (i32.const 1)
+          (i32.const 16)))
+        (call $fd_close (i32.load (i32.const 96))))
+    */
+    const WASM: &[&str] = &[
+        "0061736d01000000012c0760047f7f7f7f017f60027f7f017f60017f0060097f7f7f7f7f7e7e7f7f",
+        "017f60017f017f6000006000017f02d4010616776173695f736e617073686f745f70726576696577",
+        "310866645f7772697465000016776173695f736e617073686f745f70726576696577310766645f72",
+        "656164000016776173695f736e617073686f745f70726576696577310e617267735f73697a65735f",
+        "676574000116776173695f736e617073686f745f70726576696577310970726f635f657869740002",
+        "16776173695f736e617073686f745f707265766965773109706174685f6f70656e00031677617369",
+        "5f736e617073686f745f70726576696577310866645f636c6f736500040304030506060503010001",
+        "072704066d656d6f72790200065f73746172740006046563686f00070a77726974655f66696c6500",
+        "080a72031b00410141004101411010001a4114411810021a411428020010030b1700410041204101",
+        "412410011a410141204101412810000b3c01017f4103410041d0004107410142c0004200410041e0",
+        "00100421002000044020000f0b41e00028020041004101411010001a41e00028020010050b0b2e03",
+        "0041000b0e080000000600000068656c6c6f0a0041200b0840000000100000000041d0000b076f75",
+        "742e747874",
+    ];
+
+    /* wat2wasm
+      (import "wasi_snapshot_preview1" "clock_time_get"
+        (func $clock_time_get (param i32 i64 i32) (result i32)))
+      (import "wasi_snapshot_preview1" "random_get"
+        (func $random_get (param i32 i32) (result i32)))
+      (memory (export "memory") 1)
+      (func (export "clock") (param i32) (result i32)
+        (call $clock_time_get (local.get 0) (i64.const 0) (i32.const 0)))
+      (func (export "random") (result i32) (call $random_get (i32.const 8) (i32.const 8)))
+    */
+    const WASM_CLOCK_RANDOM: &[&str] = &[
+        "0061736d0100000001170460037f7e7f017f60027f7f017f60017f017f6000017f024d0216776173",
+        "695f736e617073686f745f70726576696577310e636c6f636b5f74696d655f676574000016776173",
+        "695f736e617073686f745f70726576696577310a72616e646f6d5f67657400010303020203050301",
+        "0001071b03066d656d6f7279020005636c6f636b00020672616e646f6d00030a15020a0020004200",
+        "410010000b08004108410810010b",
+    ];
+
+    /* wat2wasm
+      (import "wasi_snapshot_preview1" "random_get"
+        (func $random_get (param i32 i32) (result i32)))
+      (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+      (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+      (memory (export "memory") 1)
+      (func (export "random") (param i32 i32) (result i32)
+        (call $random_get (local.get 0) (local.get 1)))
+      (func (export "read") (param i32 i32) (result i32)
+        (call $fd_read (i32.const 0) (local.get 0) (local.get 1) (i32.const 0)))
+      (func (export "write") (param i32 i32) (result i32)
+        (call $fd_write (i32.const 1) (local.get 0) (local.get 1) (i32.const 0)))
+    */
+    const WASM_BUFFERS: &[&str] = &[
+        "0061736d01000000010f0260027f7f017f60047f7f7f7f017f02680316776173695f736e61707368",
+        "6f745f70726576696577310a72616e646f6d5f676574000016776173695f736e617073686f745f70",
+        "726576696577310766645f72656164000116776173695f736e617073686f745f7072657669657731",
+        "0866645f777269746500010304030000000503010001072204066d656d6f727902000672616e646f",
+        "6d00030472656164000405777269746500050a240308002000200110000b0c004100200020014100",
+        "10010b0c00410120002001410010020b",
+    ];
+
+    /// A writer to a shared buffer.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn instantiate(wasi: &Wasi) -> Instance {
+        let mut imports = Imports::new();
+        wasi.define(&mut imports);
+        parse(from_hex(WASM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    #[test]
+    fn run() {
+        let stdout = Output::default();
+        let wasi = Wasi::new()
+            .arg("prog")
+            .arg("x")
+            .arg("y")
+            .stdout(Box::new(stdout.clone()));
+        let mut instance = instantiate(&wasi);
+        assert_eq!(wasi.exit_code(), None);
+        assert_eq!(wasi.run(&mut instance), Ok(ExitCode(3)));
+        assert_eq!(wasi.exit_code(), Some(ExitCode(3)));
+        assert_eq!(stdout.contents(), "hello\n");
+
+        let mut sizes = [0; 8];
+        instance.memory_get(20, &mut sizes).unwrap();
+        assert_eq!(sizes, [3, 0, 0, 0, 9, 0, 0, 0]);
+
+        assert_eq!(wasi.take_exit_code(), Some(ExitCode(3)));
+        assert_eq!(wasi.exit_code(), None);
+        let result = instance.execute("_start", &[]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(result.trap_reason(), Some("proc_exit: exit code 3"));
+        assert_eq!(wasi.take_exit_code(), Some(ExitCode(3)));
+    }
+
+    #[test]
+    fn allow_only() {
+        let wasi = Wasi::new()
+            .arg("prog")
+            .stdout(Box::new(Output::default()))
+            .allow_only(&["fd_write", "proc_exit"], DisallowedCall::Notcapable);
+        let mut instance = instantiate(&wasi);
+        // The sizes are not stored by args_sizes_get, so the exit code is 0.
+        assert_eq!(wasi.run(&mut instance), Ok(ExitCode(0)));
+
+        let wasi = Wasi::new()
+            .stdout(Box::new(Output::default()))
+            .allow_only(&["fd_write", "proc_exit"], DisallowedCall::Trap);
+        let mut instance = instantiate(&wasi);
+        let result = instance.execute("_start", &[]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(result.trap_reason(), Some("args_sizes_get is not allowed"));
+
+        let functions: Vec<&str> = FUNCTIONS
+            .iter()
+            .copied()
+            .filter(|name| !FILESYSTEM_FUNCTIONS.contains(name))
+            .collect();
+        let wasi = Wasi::new()
+            .stdout(Box::new(Output::default()))
+            .allow_only(&functions, DisallowedCall::Notcapable);
+        let mut instance = instantiate(&wasi);
+        assert_eq!(wasi.run(&mut instance), Ok(ExitCode(0)));
+        let result = instance.execute("write_file", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Notcapable as i32)));
+
+        let wasi = Wasi::new().allow_only(&[], DisallowedCall::Notcapable);
+        let mut instance = instantiate(&wasi);
+        assert!(matches!(wasi.run(&mut instance), Err(Error::Trapped(_))));
+        assert_eq!(wasi.exit_code(), None);
+        let result = instance.execute("write_file", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Notcapable as i32)));
+    }
+
+    #[test]
+    fn io_limits() {
+        let stdout = Output::default();
+        let wasi = Wasi::new()
+            .stdin(Box::new(&b"input"[..]))
+            .stdout(Box::new(stdout.clone()))
+            .io_limits(IoLimits {
+                max_bytes_read: Some(3),
+                max_bytes_written: Some(5),
+                ..IoLimits::default()
+            });
+        let mut instance = instantiate(&wasi);
+        let result = instance.execute("echo", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Success as i32)));
+        assert_eq!(stdout.contents(), "inp");
+
+        // Nothing is left to read, and the writes would exceed the limit.
+        let result = instance.execute("echo", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Dquot as i32)));
+        assert_eq!(wasi.run(&mut instance), Ok(ExitCode(0)));
+        assert_eq!(stdout.contents(), "inp");
+        assert_eq!(
+            wasi.io_stats(),
+            IoStats {
+                open_descriptors: 3,
+                peak_descriptors: 3,
+                bytes_read: 3,
+                bytes_written: 3,
+                limited_calls: 3,
+            }
+        );
+
+        let dir = temp_dir("limits");
+        let wasi = Wasi::new().preopen_dir(&dir, "/").io_limits(IoLimits {
+            max_descriptors: Some(4),
+            ..IoLimits::default()
+        });
+        let mut instance = instantiate(&wasi);
+        let result = instance.execute("write_file", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Mfile as i32)));
+        assert_eq!(wasi.io_stats().limited_calls, 1);
+
+        let wasi = Wasi::new()
+            .stdout(Box::new(Output::default()))
+            .preopen_dir(&dir, "/")
+            .io_limits(IoLimits {
+                max_descriptors: Some(5),
+                ..IoLimits::default()
+            });
+        let mut instance = instantiate(&wasi);
+        let result = instance.execute("write_file", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Success as i32)));
+        let stats = wasi.io_stats();
+        assert_eq!((stats.open_descriptors, stats.peak_descriptors), (4, 5));
+        assert_eq!(stats.bytes_written, 6);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exit_code() {
+        assert!(ExitCode::SUCCESS.is_success());
+        assert!(!ExitCode(1).is_success());
+        assert_eq!(ExitCode(2).to_string(), "exit code 2");
+        assert_eq!(
+            std::process::ExitCode::from(ExitCode(258)),
+            std::process::ExitCode::from(2)
+        );
+    }
+
+    #[test]
+    fn echo() {
+        let stdout = Output::default();
+        let wasi = Wasi::new()
+            .stdin(Box::new(&b"input"[..]))
+            .stdout(Box::new(stdout.clone()));
+        let mut instance = instantiate(&wasi);
+        let result = instance.execute("echo", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Success as i32)));
+        assert_eq!(stdout.contents(), "input");
+    }
+
+    #[test]
+    fn preopened_dir() {
+        let dir = temp_dir("preopen");
+
+        let wasi = Wasi::new().preopen_dir(&dir, "/sandbox");
+        let mut instance = instantiate(&wasi);
+        let result = instance.execute("write_file", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Success as i32)));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("out.txt")).unwrap(),
+            "hello\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // No directory is preopened.
+        let mut instance = instantiate(&Wasi::new());
+        let result = instance.execute("write_file", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Badf as i32)));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fizzy-wasi-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn resolve() {
+        let dir = temp_dir("resolve");
+        std::fs::create_dir(dir.join("a")).unwrap();
+        let wasi = Wasi::new().preopen_dir(&dir, "/");
+        let mut state = wasi.lock();
+        let resolve = |state: &mut State, path: &[u8]| state.resolve(3, path, true).map(|r| r.0);
+        assert_eq!(resolve(&mut state, b"a/./b/../c"), Ok(dir.join("a/c")));
+        assert_eq!(resolve(&mut state, b"x/../a"), Ok(dir.join("a")));
+        assert_eq!(resolve(&mut state, b"a/../.."), Err(Errno::Notcapable));
+        assert_eq!(resolve(&mut state, b"/etc/passwd"), Err(Errno::Notcapable));
+        assert_eq!(state.resolve(1, b"a", true), Err(Errno::Notdir));
+        assert_eq!(state.resolve(4, b"a", true), Err(Errno::Badf));
+        drop(state);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_escape() {
+        let dir = temp_dir("symlink");
+        std::fs::create_dir(dir.join("a")).unwrap();
+        std::os::unix::fs::symlink("/", dir.join("root")).unwrap();
+        std::os::unix::fs::symlink("a", dir.join("inner")).unwrap();
+        std::os::unix::fs::symlink("missing", dir.join("dangling")).unwrap();
+        let wasi = Wasi::new().preopen_dir(&dir, "/");
+        let mut state = wasi.lock();
+        assert_eq!(state.resolve(3, b"root/etc", true), Err(Errno::Notcapable));
+        assert_eq!(state.resolve(3, b"root", true), Err(Errno::Notcapable));
+        assert_eq!(state.resolve(3, b"dangling", true), Err(Errno::Notcapable));
+        assert_eq!(
+            state.resolve(3, b"inner/x", true),
+            Ok((dir.join("a/x"), dir.clone()))
+        );
+
+        // The links themselves can be removed.
+        assert_eq!(
+            state.resolve(3, b"root", false),
+            Ok((dir.join("root"), dir.clone()))
+        );
+        drop(state);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sandbox_policy() {
+        let dir = temp_dir("policy");
+        let policy = PathPolicy::new().allow(&dir, Access::Read);
+        assert!(policy.allows(&dir.join("a"), Access::Read));
+        assert!(!policy.allows(&dir.join("a"), Access::Write));
+        assert!(!policy.allows(Path::new("/"), Access::Read));
+
+        let wasi = Wasi::new()
+            .preopen_dir(&dir, "/sandbox")
+            .sandbox_policy(policy.clone());
+        let mut instance = instantiate(&wasi);
+        let result = instance.execute("write_file", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Acces as i32)));
+        assert!(!dir.join("out.txt").exists());
+
+        // Creating the file requires both creating and writing.
+        let wasi = Wasi::new()
+            .preopen_dir(&dir, "/sandbox")
+            .sandbox_policy(policy.clone().allow(&dir, Access::Create));
+        let mut instance = instantiate(&wasi);
+        let result = instance.execute("write_file", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Acces as i32)));
+
+        let wasi = Wasi::new().preopen_dir(&dir, "/sandbox").sandbox_policy(
+            policy
+                .allow(&dir, Access::Create)
+                .allow(dir.join("out.txt"), Access::Write),
+        );
+        let mut instance = instantiate(&wasi);
+        let result = instance.execute("write_file", &[]).unwrap();
+        assert_eq!(result.value(), Some(Value::I32(Errno::Success as i32)));
+        assert!(dir.join("out.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clock_and_random() {
+        let mut ticks = 100;
+        let wasi = Wasi::new()
+            .clock(move |id| match id {
+                ClockId::Realtime => Ok(1_600_000_000_000_000_000),
+                ClockId::Monotonic => {
+                    ticks += 1;
+                    Ok(ticks)
+                }
+                _ => Err(Errno::Nosys),
+            })
+            .random(|data| {
+                for (i, byte) in data.iter_mut().enumerate() {
+                    *byte = i as u8;
+                }
+                Ok(())
+            });
+        let mut imports = Imports::new();
+        wasi.define(&mut imports);
+        let mut instance = parse(from_hex(WASM_CLOCK_RANDOM))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+
+        let mut clock = |id: i32| {
+            let result = instance.execute("clock", &[Value::I32(id)]).unwrap();
+            let mut time = [0; 8];
+            instance.memory_get(0, &mut time).unwrap();
+            (result.value(), u64::from_le_bytes(time))
+        };
+        let success = Some(Value::I32(Errno::Success as i32));
+        assert_eq!(clock(0), (success, 1_600_000_000_000_000_000));
+        assert_eq!(clock(1), (success, 101));
+        assert_eq!(clock(1), (success, 102));
+        assert_eq!(clock(2).0, Some(Value::I32(Errno::Nosys as i32)));
+        assert_eq!(clock(4).0, Some(Value::I32(Errno::Inval as i32)));
+
+        let result = instance.execute("random", &[]).unwrap();
+        assert_eq!(result.value(), success);
+        let mut data = [0; 8];
+        instance.memory_get(8, &mut data).unwrap();
+        assert_eq!(data, [0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn buffers_out_of_bounds() {
+        let stdout = Output::default();
+        let wasi = Wasi::new()
+            .stdout(Box::new(stdout.clone()))
+            .random(|_| panic!("random_get with out-of-bounds buffer"));
+        let mut imports = Imports::new();
+        wasi.define(&mut imports);
+        let mut instance = parse(from_hex(WASM_BUFFERS))
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+        // The iovec at 16 points to 0xfffffff0 bytes at 100.
+        let iovec: Vec<u8> = [100u32, 0xfffffff0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        instance.memory_set(16, &iovec).unwrap();
+        let mut call = |name: &str, ptr: u32, len: u32| {
+            let args = [Value::I32(ptr as i32), Value::I32(len as i32)];
+            instance.execute(name, &args).unwrap().value()
+        };
+        let fault = Some(Value::I32(Errno::Fault as i32));
+
+        // The buffers are checked before they are allocated on the host.
+        assert_eq!(call("random", 0, u32::MAX), fault);
+        assert_eq!(call("random", 65535, 2), fault);
+        assert_eq!(call("random", u32::MAX, 1), fault);
+        assert_eq!(call("read", 16, 1), fault);
+        assert_eq!(call("write", 16, 1), fault);
+        // The iovec arrays out of bounds.
+        assert_eq!(call("read", 65535, 1), fault);
+        assert_eq!(call("write", 16, u32::MAX), fault);
+        assert_eq!(stdout.contents(), "");
+    }
+
+    #[test]
+    fn errors() {
+        let error = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert_eq!(Errno::from_io_error(&error), Errno::Noent);
+        let error = std::io::Error::from(std::io::ErrorKind::Other);
+        assert_eq!(Errno::from_io_error(&error), Errno::Io);
+
+        let wasi = Wasi::new().map_errors(|_| Errno::Acces);
+        assert_eq!(wasi.lock().io_error(error), Errno::Acces);
+    }
+}
@@ -0,0 +1,1576 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! An adapter exposing the standard [wasm-c-api](https://github.com/WebAssembly/wasm-c-api)
+//! (the `wasm_*` functions declared in `wasm.h`) over Fizzy.
+//!
+//! The functions are exported with C linkage from the static and dynamic libraries built from
+//! this crate, so projects written against `wasm.h` can switch engines.
+//!
+//! Supported are engines, stores, value and function types, modules, instances, host functions
+//! created with `wasm_func_new`, memories created with `wasm_memory_new`, and access to
+//! the exported functions, memories, globals and tables. Instances can import host functions,
+//! host memories and any exports of other instances.
+//!
+//! Not supported are references (the elements of tables cannot be accessed), the creation of
+//! globals and tables by the host, the growth of memories by the host (`wasm_memory_grow`
+//! returns false) and calls back into an instance while it is executing: `wasm_func_call` of
+//! a function of the executing instance returns a trap and `wasm_table_grow` of its tables
+//! returns false.
+
+#![allow(non_camel_case_types)]
+#![allow(clippy::missing_safety_doc)]
+
+use crate::host::{lock, HostFunction, Imports, SharedInstance};
+use crate::linker::{function_type_from_sys, FunctionType, GlobalType, Limits};
+use crate::memory::{Memory, PAGE_SIZE};
+use crate::{sys, Module, Value, ValueType};
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+
+pub type wasm_byte_t = c_char;
+
+#[repr(C)]
+pub struct wasm_byte_vec_t {
+    pub size: usize,
+    pub data: *mut wasm_byte_t,
+}
+
+pub type wasm_name_t = wasm_byte_vec_t;
+pub type wasm_message_t = wasm_name_t;
+
+pub type wasm_valkind_t = u8;
+pub const WASM_I32: wasm_valkind_t = 0;
+pub const WASM_I64: wasm_valkind_t = 1;
+pub const WASM_F32: wasm_valkind_t = 2;
+pub const WASM_F64: wasm_valkind_t = 3;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union wasm_val_union_t {
+    pub i32: i32,
+    pub i64: i64,
+    pub f32: f32,
+    pub f64: f64,
+    pub ref_: *mut c_void,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct wasm_val_t {
+    pub kind: wasm_valkind_t,
+    pub of: wasm_val_union_t,
+}
+
+#[repr(C)]
+pub struct wasm_val_vec_t {
+    pub size: usize,
+    pub data: *mut wasm_val_t,
+}
+
+pub struct wasm_valtype_t {
+    kind: wasm_valkind_t,
+}
+
+#[repr(C)]
+pub struct wasm_valtype_vec_t {
+    pub size: usize,
+    pub data: *mut *mut wasm_valtype_t,
+}
+
+pub struct wasm_functype_t {
+    params: wasm_valtype_vec_t,
+    results: wasm_valtype_vec_t,
+}
+
+impl Drop for wasm_functype_t {
+    fn drop(&mut self) {
+        unsafe {
+            wasm_valtype_vec_delete(&mut self.params);
+            wasm_valtype_vec_delete(&mut self.results);
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct wasm_limits_t {
+    pub min: u32,
+    pub max: u32,
+}
+
+/// The maximum of limits without a maximum, `wasm_limits_max_default` in `wasm.h`.
+pub const WASM_LIMITS_MAX_DEFAULT: u32 = 0xffffffff;
+
+pub struct wasm_memorytype_t {
+    limits: wasm_limits_t,
+}
+
+pub type wasm_externkind_t = u8;
+pub const WASM_EXTERN_FUNC: wasm_externkind_t = 0;
+pub const WASM_EXTERN_GLOBAL: wasm_externkind_t = 1;
+pub const WASM_EXTERN_TABLE: wasm_externkind_t = 2;
+pub const WASM_EXTERN_MEMORY: wasm_externkind_t = 3;
+
+pub type wasm_func_callback_t =
+    Option<unsafe extern "C" fn(*const wasm_val_vec_t, *mut wasm_val_vec_t) -> *mut wasm_trap_t>;
+pub type wasm_func_callback_with_env_t = Option<
+    unsafe extern "C" fn(
+        *mut c_void,
+        *const wasm_val_vec_t,
+        *mut wasm_val_vec_t,
+    ) -> *mut wasm_trap_t,
+>;
+pub type wasm_finalizer_t = Option<unsafe extern "C" fn(*mut c_void)>;
+
+pub struct wasm_engine_t {}
+
+pub struct wasm_store_t {}
+
+/// A Fizzy module is consumed by instantiation, while a wasm-c-api module can be instantiated
+/// many times, so every instantiation takes a copy of the module.
+pub struct wasm_module_t {
+    module: Module,
+}
+
+pub struct wasm_instance_t {
+    instance: SharedInstance,
+}
+
+/// A host function created with `wasm_func_new` or `wasm_func_new_with_env`.
+struct HostCallback {
+    ty: FunctionType,
+    callback: unsafe extern "C" fn(
+        *mut c_void,
+        *const wasm_val_vec_t,
+        *mut wasm_val_vec_t,
+    ) -> *mut wasm_trap_t,
+    env: *mut c_void,
+    finalizer: wasm_finalizer_t,
+}
+
+// The callback is called by the thread executing the importing instance. As in the other
+// implementations of the wasm-c-api, the embedder is responsible for the thread safety of
+// the environment.
+unsafe impl Send for HostCallback {}
+unsafe impl Sync for HostCallback {}
+
+impl Drop for HostCallback {
+    fn drop(&mut self) {
+        if let Some(finalizer) = self.finalizer {
+            unsafe { finalizer(self.env) }
+        }
+    }
+}
+
+/// The environment of the callbacks created with `wasm_func_new`, which do not take one.
+unsafe extern "C" fn call_without_env(
+    env: *mut c_void,
+    args: *const wasm_val_vec_t,
+    results: *mut wasm_val_vec_t,
+) -> *mut wasm_trap_t {
+    let callback: unsafe extern "C" fn(
+        *const wasm_val_vec_t,
+        *mut wasm_val_vec_t,
+    ) -> *mut wasm_trap_t = std::mem::transmute(env);
+    callback(args, results)
+}
+
+impl HostCallback {
+    /// Calls the callback, returning the message of its trap if it has trapped or if it has
+    /// stored a result of other type than the function type.
+    fn call(&self, args: &[Value]) -> Result<Option<Value>, String> {
+        let mut args: Vec<wasm_val_t> = args.iter().map(|&arg| from_value(arg)).collect();
+        let args = wasm_val_vec_t {
+            size: args.len(),
+            data: args.as_mut_ptr(),
+        };
+        let mut result: Vec<wasm_val_t> = self
+            .ty
+            .output
+            .iter()
+            .map(|&output| from_value(zero_value(output)))
+            .collect();
+        let mut results = wasm_val_vec_t {
+            size: result.len(),
+            data: result.as_mut_ptr(),
+        };
+        let trap = unsafe { (self.callback)(self.env, &args, &mut results) };
+        if !trap.is_null() {
+            return Err(unsafe { Box::from_raw(trap) }.message);
+        }
+        match (result.first(), self.ty.output) {
+            (None, None) => Ok(None),
+            (Some(value), Some(output)) if value.kind == valkind(output) => Ok(to_value(value)),
+            _ => Err("result type does not match the function type".to_string()),
+        }
+    }
+}
+
+/// An export of an instance, with the pointers to the memory and global taken when it is
+/// exported, so they can be accessed while the instance is executing.
+enum Export {
+    Func(u32, FunctionType),
+    Memory(NonNull<sys::FizzyMemory>),
+    Global(NonNull<sys::FizzyValue>, GlobalType),
+    Table(NonNull<sys::FizzyTable>),
+}
+
+enum Item {
+    /// An export of the instance, with its name.
+    Export(SharedInstance, String, Export),
+    /// A function defined by the host.
+    HostFunc(Arc<HostCallback>),
+    /// A memory created by the host.
+    HostMemory(Memory),
+}
+
+pub struct wasm_extern_t {
+    item: Item,
+}
+
+impl wasm_extern_t {
+    fn kind(&self) -> wasm_externkind_t {
+        match &self.item {
+            Item::Export(_, _, Export::Func(..)) | Item::HostFunc(_) => WASM_EXTERN_FUNC,
+            Item::Export(_, _, Export::Memory(_)) | Item::HostMemory(_) => WASM_EXTERN_MEMORY,
+            Item::Export(_, _, Export::Global(..)) => WASM_EXTERN_GLOBAL,
+            Item::Export(_, _, Export::Table(_)) => WASM_EXTERN_TABLE,
+        }
+    }
+
+    /// Returns the type of the function.
+    fn func_type(&self) -> FunctionType {
+        match &self.item {
+            Item::Export(_, _, Export::Func(_, ty)) => ty.clone(),
+            Item::HostFunc(callback) => callback.ty.clone(),
+            _ => unreachable!("the extern is a function"),
+        }
+    }
+
+    fn memory(&self) -> *mut sys::FizzyMemory {
+        match &self.item {
+            Item::Export(_, _, Export::Memory(memory)) => memory.as_ptr(),
+            Item::HostMemory(memory) => memory.to_external().memory,
+            _ => unreachable!("the extern is a memory"),
+        }
+    }
+}
+
+#[repr(transparent)]
+pub struct wasm_func_t {
+    ext: wasm_extern_t,
+}
+
+#[repr(transparent)]
+pub struct wasm_memory_t {
+    ext: wasm_extern_t,
+}
+
+#[repr(transparent)]
+pub struct wasm_global_t {
+    ext: wasm_extern_t,
+}
+
+#[repr(transparent)]
+pub struct wasm_table_t {
+    ext: wasm_extern_t,
+}
+
+pub struct wasm_trap_t {
+    message: String,
+}
+
+#[repr(C)]
+pub struct wasm_extern_vec_t {
+    pub size: usize,
+    pub data: *mut *mut wasm_extern_t,
+}
+
+/// Converts a vector to the (size, data) pair owned by the C side.
+fn into_raw_parts<T>(v: Vec<T>) -> (usize, *mut T) {
+    let boxed = v.into_boxed_slice();
+    let size = boxed.len();
+    (size, Box::into_raw(boxed) as *mut T)
+}
+
+/// Frees the (size, data) pair previously created with `into_raw_parts`.
+unsafe fn drop_raw_parts<T>(size: usize, data: *mut T) {
+    if !data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+            data, size,
+        )));
+    }
+}
+
+unsafe fn as_slice<'a, T>(size: usize, data: *const T) -> &'a [T] {
+    if size == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, size)
+    }
+}
+
+/// Drops the boxed object, if the pointer is not null.
+unsafe fn delete<T>(ptr: *mut T) {
+    if !ptr.is_null() {
+        drop(Box::from_raw(ptr));
+    }
+}
+
+fn new_trap(message: &str) -> *mut wasm_trap_t {
+    Box::into_raw(Box::new(wasm_trap_t {
+        message: message.to_string(),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_byte_vec_new_empty(out: *mut wasm_byte_vec_t) {
+    *out = wasm_byte_vec_t {
+        size: 0,
+        data: std::ptr::null_mut(),
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_byte_vec_new_uninitialized(out: *mut wasm_byte_vec_t, size: usize) {
+    let (size, data) = into_raw_parts(vec![0; size]);
+    *out = wasm_byte_vec_t { size, data };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_byte_vec_new(
+    out: *mut wasm_byte_vec_t,
+    size: usize,
+    data: *const wasm_byte_t,
+) {
+    let (size, data) = into_raw_parts(as_slice(size, data).to_vec());
+    *out = wasm_byte_vec_t { size, data };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_byte_vec_delete(vec: *mut wasm_byte_vec_t) {
+    if vec.is_null() {
+        return;
+    }
+    drop_raw_parts((*vec).size, (*vec).data);
+    (*vec).data = std::ptr::null_mut();
+    (*vec).size = 0;
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_engine_new() -> *mut wasm_engine_t {
+    Box::into_raw(Box::new(wasm_engine_t {}))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_engine_delete(engine: *mut wasm_engine_t) {
+    delete(engine);
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_store_new(_engine: *mut wasm_engine_t) -> *mut wasm_store_t {
+    Box::into_raw(Box::new(wasm_store_t {}))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_store_delete(store: *mut wasm_store_t) {
+    delete(store);
+}
+
+fn value_type(kind: wasm_valkind_t) -> Option<ValueType> {
+    match kind {
+        WASM_I32 => Some(ValueType::I32),
+        WASM_I64 => Some(ValueType::I64),
+        WASM_F32 => Some(ValueType::F32),
+        WASM_F64 => Some(ValueType::F64),
+        _ => None,
+    }
+}
+
+fn valkind(value_type: ValueType) -> wasm_valkind_t {
+    match value_type {
+        ValueType::I32 => WASM_I32,
+        ValueType::I64 => WASM_I64,
+        ValueType::F32 => WASM_F32,
+        ValueType::F64 => WASM_F64,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_valtype_new(kind: wasm_valkind_t) -> *mut wasm_valtype_t {
+    Box::into_raw(Box::new(wasm_valtype_t { kind }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_valtype_delete(valtype: *mut wasm_valtype_t) {
+    delete(valtype);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_valtype_kind(valtype: *const wasm_valtype_t) -> wasm_valkind_t {
+    (*valtype).kind
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_valtype_vec_new_empty(out: *mut wasm_valtype_vec_t) {
+    *out = wasm_valtype_vec_t {
+        size: 0,
+        data: std::ptr::null_mut(),
+    };
+}
+
+/// Creates a vector taking the ownership of the value types.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_valtype_vec_new(
+    out: *mut wasm_valtype_vec_t,
+    size: usize,
+    data: *const *mut wasm_valtype_t,
+) {
+    let (size, data) = into_raw_parts(as_slice(size, data).to_vec());
+    *out = wasm_valtype_vec_t { size, data };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_valtype_vec_delete(vec: *mut wasm_valtype_vec_t) {
+    if vec.is_null() {
+        return;
+    }
+    for &valtype in as_slice((*vec).size, (*vec).data) {
+        wasm_valtype_delete(valtype);
+    }
+    drop_raw_parts((*vec).size, (*vec).data);
+    (*vec).data = std::ptr::null_mut();
+    (*vec).size = 0;
+}
+
+fn new_valtype_vec(types: impl Iterator<Item = ValueType>) -> wasm_valtype_vec_t {
+    let (size, data) = into_raw_parts(types.map(|ty| wasm_valtype_new(valkind(ty))).collect());
+    wasm_valtype_vec_t { size, data }
+}
+
+/// Creates a function type taking the ownership of the parameter and result types.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_functype_new(
+    params: *mut wasm_valtype_vec_t,
+    results: *mut wasm_valtype_vec_t,
+) -> *mut wasm_functype_t {
+    let take = |vec: *mut wasm_valtype_vec_t| {
+        std::mem::replace(
+            &mut *vec,
+            wasm_valtype_vec_t {
+                size: 0,
+                data: std::ptr::null_mut(),
+            },
+        )
+    };
+    Box::into_raw(Box::new(wasm_functype_t {
+        params: take(params),
+        results: take(results),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_functype_delete(functype: *mut wasm_functype_t) {
+    delete(functype);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_functype_params(
+    functype: *const wasm_functype_t,
+) -> *const wasm_valtype_vec_t {
+    &(*functype).params
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_functype_results(
+    functype: *const wasm_functype_t,
+) -> *const wasm_valtype_vec_t {
+    &(*functype).results
+}
+
+/// Converts the function type, or returns `None` if it has more than one result.
+unsafe fn to_function_type(functype: *const wasm_functype_t) -> Option<FunctionType> {
+    let value_types = |vec: &wasm_valtype_vec_t| -> Option<Vec<ValueType>> {
+        as_slice(vec.size, vec.data)
+            .iter()
+            .map(|&valtype| value_type((*valtype).kind))
+            .collect()
+    };
+    let inputs = value_types(&(*functype).params)?;
+    let output = match value_types(&(*functype).results)?.as_slice() {
+        [] => None,
+        [output] => Some(*output),
+        _ => return None,
+    };
+    Some(FunctionType { inputs, output })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_memorytype_new(
+    limits: *const wasm_limits_t,
+) -> *mut wasm_memorytype_t {
+    Box::into_raw(Box::new(wasm_memorytype_t { limits: *limits }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_memorytype_delete(memorytype: *mut wasm_memorytype_t) {
+    delete(memorytype);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_memorytype_limits(
+    memorytype: *const wasm_memorytype_t,
+) -> *const wasm_limits_t {
+    &(*memorytype).limits
+}
+
+unsafe fn binary_bytes<'a>(binary: *const wasm_byte_vec_t) -> &'a [u8] {
+    as_slice((*binary).size, (*binary).data as *const u8)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_module_validate(
+    _store: *mut wasm_store_t,
+    binary: *const wasm_byte_vec_t,
+) -> bool {
+    crate::validate(binary_bytes(binary))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_module_new(
+    _store: *mut wasm_store_t,
+    binary: *const wasm_byte_vec_t,
+) -> *mut wasm_module_t {
+    match crate::parse(binary_bytes(binary)) {
+        Ok(module) => Box::into_raw(Box::new(wasm_module_t { module })),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_module_delete(module: *mut wasm_module_t) {
+    delete(module);
+}
+
+/// Creates an instance of the module, with the imports in the order of the imports of
+/// the module.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_instance_new(
+    _store: *mut wasm_store_t,
+    module: *const wasm_module_t,
+    imports: *const wasm_extern_vec_t,
+    trap: *mut *mut wasm_trap_t,
+) -> *mut wasm_instance_t {
+    let report = |message: &str| {
+        if !trap.is_null() {
+            *trap = new_trap(message);
+        }
+        std::ptr::null_mut()
+    };
+
+    let module = match (*module).module.duplicate() {
+        Ok(module) => module,
+        Err(error) => return report(&error.to_string()),
+    };
+    let externs = if imports.is_null() {
+        &[]
+    } else {
+        as_slice((*imports).size, (*imports).data)
+    };
+    let module_imports = module.imports();
+    if externs.len() != module_imports.len() {
+        return report("number of imports does not match the module");
+    }
+    let mut defined = Imports::new();
+    for (import, &ext) in module_imports.iter().zip(externs) {
+        if ext.is_null() {
+            return report("missing import");
+        }
+        match &(*ext).item {
+            Item::Export(instance, name, _) => {
+                defined.define_export(&import.module, &import.name, instance, name)
+            }
+            Item::HostFunc(callback) => {
+                let callback = callback.clone();
+                let func = HostFunction::new(callback.ty.clone(), move |args| callback.call(args));
+                defined.define(&import.module, &import.name, func)
+            }
+            Item::HostMemory(memory) => defined.define_memory(&import.module, &import.name, memory),
+        };
+    }
+
+    match module.instantiate_with_imports(defined) {
+        Ok(instance) => Box::into_raw(Box::new(wasm_instance_t {
+            instance: Arc::new(Mutex::new(instance)),
+        })),
+        Err(error) => report(&error.to_string()),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_instance_delete(instance: *mut wasm_instance_t) {
+    delete(instance);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_instance_exports(
+    instance: *const wasm_instance_t,
+    out: *mut wasm_extern_vec_t,
+) {
+    let shared = &(*instance).instance;
+    let instance = lock(shared);
+    let externs: Vec<*mut wasm_extern_t> = instance
+        .exports()
+        .into_iter()
+        .filter_map(|(name, kind, index)| {
+            let export = match kind {
+                sys::FizzyExternalKindFunction => {
+                    let ty = sys::fizzy_get_function_type(instance.module(), index);
+                    Export::Func(index, function_type_from_sys(&ty))
+                }
+                sys::FizzyExternalKindTable => {
+                    let c_name = CString::new(name.as_str()).ok()?;
+                    let mut table = sys::FizzyExternalTable {
+                        table: std::ptr::null_mut(),
+                        limits: sys::FizzyLimits {
+                            min: 0,
+                            max: 0,
+                            has_max: false,
+                        },
+                    };
+                    sys::fizzy_find_exported_table(
+                        instance.0.as_ptr(),
+                        c_name.as_ptr(),
+                        &mut table,
+                    );
+                    Export::Table(NonNull::new(table.table)?)
+                }
+                sys::FizzyExternalKindMemory => {
+                    let c_name = CString::new(name.as_str()).ok()?;
+                    let mut memory = sys::FizzyExternalMemory {
+                        memory: std::ptr::null_mut(),
+                        limits: sys::FizzyLimits {
+                            min: 0,
+                            max: 0,
+                            has_max: false,
+                        },
+                    };
+                    sys::fizzy_find_exported_memory(
+                        instance.0.as_ptr(),
+                        c_name.as_ptr(),
+                        &mut memory,
+                    );
+                    Export::Memory(NonNull::new(memory.memory)?)
+                }
+                _ => {
+                    let c_name = CString::new(name.as_str()).ok()?;
+                    let mut global = sys::FizzyExternalGlobal {
+                        value: std::ptr::null_mut(),
+                        type_: sys::FizzyGlobalType {
+                            value_type: sys::FizzyValueTypeVoid,
+                            is_mutable: false,
+                        },
+                    };
+                    sys::fizzy_find_exported_global(
+                        instance.0.as_ptr(),
+                        c_name.as_ptr(),
+                        &mut global,
+                    );
+                    let ty = GlobalType {
+                        value_type: ValueType::from_sys(global.type_.value_type),
+                        mutable: global.type_.is_mutable,
+                    };
+                    Export::Global(NonNull::new(global.value)?, ty)
+                }
+            };
+            Some(Box::into_raw(Box::new(wasm_extern_t {
+                item: Item::Export(shared.clone(), name, export),
+            })))
+        })
+        .collect();
+    let (size, data) = into_raw_parts(externs);
+    *out = wasm_extern_vec_t { size, data };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_extern_kind(ext: *const wasm_extern_t) -> wasm_externkind_t {
+    (*ext).kind()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_extern_as_func(ext: *mut wasm_extern_t) -> *mut wasm_func_t {
+    if (*ext).kind() == WASM_EXTERN_FUNC {
+        ext as *mut wasm_func_t
+    } else {
+        std::ptr::null_mut()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_extern_as_memory(ext: *mut wasm_extern_t) -> *mut wasm_memory_t {
+    if (*ext).kind() == WASM_EXTERN_MEMORY {
+        ext as *mut wasm_memory_t
+    } else {
+        std::ptr::null_mut()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_extern_as_table(ext: *mut wasm_extern_t) -> *mut wasm_table_t {
+    if (*ext).kind() == WASM_EXTERN_TABLE {
+        ext as *mut wasm_table_t
+    } else {
+        std::ptr::null_mut()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_extern_as_global(ext: *mut wasm_extern_t) -> *mut wasm_global_t {
+    if (*ext).kind() == WASM_EXTERN_GLOBAL {
+        ext as *mut wasm_global_t
+    } else {
+        std::ptr::null_mut()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_extern_delete(ext: *mut wasm_extern_t) {
+    delete(ext);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_extern_vec_delete(vec: *mut wasm_extern_vec_t) {
+    if vec.is_null() {
+        return;
+    }
+    for &ext in as_slice((*vec).size, (*vec).data) {
+        wasm_extern_delete(ext);
+    }
+    drop_raw_parts((*vec).size, (*vec).data);
+    (*vec).data = std::ptr::null_mut();
+    (*vec).size = 0;
+}
+
+/// Creates a host function calling the `callback`.
+///
+/// Returns NULL if the function type has more than one result.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_func_new(
+    store: *mut wasm_store_t,
+    functype: *const wasm_functype_t,
+    callback: wasm_func_callback_t,
+) -> *mut wasm_func_t {
+    match callback {
+        Some(callback) => wasm_func_new_with_env(
+            store,
+            functype,
+            Some(call_without_env),
+            callback as *mut c_void,
+            None,
+        ),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Creates a host function calling the `callback` with the `env`. The `finalizer`, if not NULL,
+/// is called with the `env` when the function and the instances importing it are deleted.
+///
+/// Returns NULL if the function type has more than one result.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_func_new_with_env(
+    _store: *mut wasm_store_t,
+    functype: *const wasm_functype_t,
+    callback: wasm_func_callback_with_env_t,
+    env: *mut c_void,
+    finalizer: wasm_finalizer_t,
+) -> *mut wasm_func_t {
+    let (ty, callback) = match (to_function_type(functype), callback) {
+        (Some(ty), Some(callback)) => (ty, callback),
+        _ => return std::ptr::null_mut(),
+    };
+    Box::into_raw(Box::new(wasm_func_t {
+        ext: wasm_extern_t {
+            item: Item::HostFunc(Arc::new(HostCallback {
+                ty,
+                callback,
+                env,
+                finalizer,
+            })),
+        },
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_func_delete(func: *mut wasm_func_t) {
+    delete(func);
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_func_as_extern(func: *mut wasm_func_t) -> *mut wasm_extern_t {
+    func as *mut wasm_extern_t
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_func_type(func: *const wasm_func_t) -> *mut wasm_functype_t {
+    let ty = (*func).ext.func_type();
+    Box::into_raw(Box::new(wasm_functype_t {
+        params: new_valtype_vec(ty.inputs.into_iter()),
+        results: new_valtype_vec(ty.output.into_iter()),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_func_param_arity(func: *const wasm_func_t) -> usize {
+    (*func).ext.func_type().inputs.len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_func_result_arity(func: *const wasm_func_t) -> usize {
+    (*func).ext.func_type().output.iter().count()
+}
+
+fn zero_value(value_type: ValueType) -> Value {
+    match value_type {
+        ValueType::I32 => Value::I32(0),
+        ValueType::I64 => Value::I64(0),
+        ValueType::F32 => Value::F32(0.0),
+        ValueType::F64 => Value::F64(0.0),
+    }
+}
+
+fn to_value(val: &wasm_val_t) -> Option<Value> {
+    unsafe {
+        match val.kind {
+            WASM_I32 => Some(Value::I32(val.of.i32)),
+            WASM_I64 => Some(Value::I64(val.of.i64)),
+            WASM_F32 => Some(Value::F32(val.of.f32)),
+            WASM_F64 => Some(Value::F64(val.of.f64)),
+            _ => None,
+        }
+    }
+}
+
+fn from_value(value: Value) -> wasm_val_t {
+    match value {
+        Value::I32(v) => wasm_val_t {
+            kind: WASM_I32,
+            of: wasm_val_union_t { i32: v },
+        },
+        Value::I64(v) => wasm_val_t {
+            kind: WASM_I64,
+            of: wasm_val_union_t { i64: v },
+        },
+        Value::F32(v) => wasm_val_t {
+            kind: WASM_F32,
+            of: wasm_val_union_t { f32: v },
+        },
+        Value::F64(v) => wasm_val_t {
+            kind: WASM_F64,
+            of: wasm_val_union_t { f64: v },
+        },
+    }
+}
+
+/// Calls the function. Returns NULL on success, or a trap otherwise.
+/// Type mismatches of arguments are reported as traps too.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_func_call(
+    func: *const wasm_func_t,
+    args: *const wasm_val_vec_t,
+    results: *mut wasm_val_vec_t,
+) -> *mut wasm_trap_t {
+    let args: Option<Vec<Value>> = if args.is_null() {
+        Some(Vec::new())
+    } else {
+        as_slice((*args).size, (*args).data)
+            .iter()
+            .map(to_value)
+            .collect()
+    };
+    let args = match args {
+        Some(args) => args,
+        None => return new_trap("unsupported argument type"),
+    };
+
+    let value = match &(*func).ext.item {
+        Item::Export(instance, _, Export::Func(func_idx, _)) => {
+            let mut instance = match instance.try_lock() {
+                Ok(instance) => instance,
+                Err(_) => return new_trap("instance is executing"),
+            };
+            let result = match instance.execute_function(*func_idx, &args) {
+                Ok(result) => result,
+                Err(_) => return new_trap("argument mismatch"),
+            };
+            if result.trapped() {
+                return new_trap("trapped");
+            }
+            result.value()
+        }
+        Item::HostFunc(callback) => {
+            let inputs: Vec<ValueType> = args.iter().map(Value::value_type).collect();
+            if inputs != callback.ty.inputs {
+                return new_trap("argument mismatch");
+            }
+            match callback.call(&args) {
+                Ok(value) => value,
+                Err(message) => return new_trap(&message),
+            }
+        }
+        _ => unreachable!("the extern is a function"),
+    };
+    if let Some(value) = value {
+        if results.is_null() || (*results).size == 0 {
+            return new_trap("missing space for result");
+        }
+        *(*results).data = from_value(value);
+    }
+    std::ptr::null_mut()
+}
+
+/// Creates a memory of the type, filled with zeroes.
+///
+/// Returns NULL if the limits are invalid or the allocation failed.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_memory_new(
+    _store: *mut wasm_store_t,
+    memorytype: *const wasm_memorytype_t,
+) -> *mut wasm_memory_t {
+    let limits = (*memorytype).limits;
+    let limits = Limits {
+        min: limits.min,
+        max: Some(limits.max).filter(|&max| max != WASM_LIMITS_MAX_DEFAULT),
+    };
+    match Memory::new(limits) {
+        Ok(memory) => Box::into_raw(Box::new(wasm_memory_t {
+            ext: wasm_extern_t {
+                item: Item::HostMemory(memory),
+            },
+        })),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_memory_delete(memory: *mut wasm_memory_t) {
+    delete(memory);
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_memory_as_extern(memory: *mut wasm_memory_t) -> *mut wasm_extern_t {
+    memory as *mut wasm_extern_t
+}
+
+/// Returns the data of the memory, invalidated when the memory grows.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_memory_data(memory: *mut wasm_memory_t) -> *mut wasm_byte_t {
+    sys::fizzy_get_memory_data((*memory).ext.memory()) as *mut wasm_byte_t
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_memory_data_size(memory: *const wasm_memory_t) -> usize {
+    sys::fizzy_get_memory_size((*memory).ext.memory())
+}
+
+/// Returns the size of the memory in pages.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_memory_size(memory: *const wasm_memory_t) -> u32 {
+    (wasm_memory_data_size(memory) / PAGE_SIZE) as u32
+}
+
+/// The memories cannot be grown by the host, always returns false.
+#[no_mangle]
+pub extern "C" fn wasm_memory_grow(_memory: *mut wasm_memory_t, _delta: u32) -> bool {
+    false
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_global_delete(global: *mut wasm_global_t) {
+    delete(global);
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_global_as_extern(global: *mut wasm_global_t) -> *mut wasm_extern_t {
+    global as *mut wasm_extern_t
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_global_get(global: *const wasm_global_t, out: *mut wasm_val_t) {
+    if let Item::Export(_, _, Export::Global(value, ty)) = &(*global).ext.item {
+        *out = from_value(Value::from_sys(*value.as_ptr(), ty.value_type.to_sys()));
+    }
+}
+
+/// Sets the value of the global. Has no effect if the global is immutable or the value is not of
+/// the type of the global.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_global_set(global: *mut wasm_global_t, val: *const wasm_val_t) {
+    if let Item::Export(_, _, Export::Global(value, ty)) = &(*global).ext.item {
+        match to_value(&*val) {
+            Some(new_value) if ty.mutable && new_value.value_type() == ty.value_type => {
+                *value.as_ptr() = new_value.into()
+            }
+            _ => {}
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_table_delete(table: *mut wasm_table_t) {
+    delete(table);
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_table_as_extern(table: *mut wasm_table_t) -> *mut wasm_extern_t {
+    table as *mut wasm_extern_t
+}
+
+/// Returns the size of the table in elements.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_table_size(table: *const wasm_table_t) -> u32 {
+    match &(*table).ext.item {
+        Item::Export(_, _, Export::Table(table)) => sys::fizzy_get_table_size(table.as_ptr()),
+        _ => unreachable!("the extern is a table"),
+    }
+}
+
+/// Grows the table by `delta` null elements. The `init` reference must be NULL.
+///
+/// Returns false if the growth failed or the instance of the table is executing.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_table_grow(
+    table: *mut wasm_table_t,
+    delta: u32,
+    init: *mut c_void,
+) -> bool {
+    if !init.is_null() {
+        return false;
+    }
+    match &(*table).ext.item {
+        Item::Export(instance, name, Export::Table(_)) => match instance.try_lock() {
+            Ok(mut instance) => instance
+                .export_table(name)
+                .and_then(|mut table| table.grow(delta))
+                .is_some(),
+            Err(_) => false,
+        },
+        _ => unreachable!("the extern is a table"),
+    }
+}
+
+/// Creates a trap with the message, which is null-terminated by convention.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_trap_new(
+    _store: *mut wasm_store_t,
+    message: *const wasm_message_t,
+) -> *mut wasm_trap_t {
+    let bytes = binary_bytes(message);
+    let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+    new_trap(&String::from_utf8_lossy(bytes))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_trap_message(trap: *const wasm_trap_t, out: *mut wasm_message_t) {
+    // The message is null-terminated and the terminating 0 is included in the size.
+    let mut message: Vec<wasm_byte_t> = (*trap).message.bytes().map(|b| b as wasm_byte_t).collect();
+    message.push(0);
+    let (size, data) = into_raw_parts(message);
+    *out = wasm_byte_vec_t { size, data };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_trap_delete(trap: *mut wasm_trap_t) {
+    delete(trap);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::from_hex;
+
+    /* wat2wasm
+      (func (export "add") (param i32 i32) (result i32)
+        (i32.add (local.get 0) (local.get 1))
+      )
+      (func (export "fail") unreachable)
+      (memory (export "mem") 1)
+    */
+    const WASM: &[&str] = &[
+        "0061736d01000000010a0260027f7f017f6000000303020001050301000107140303616464000004",
+        "6661696c0001036d656d02000a0d020700200020016a0b0300000b",
+    ];
+
+    /* wat2wasm
+      (func $add1 (import "env" "add1") (param i32) (result i32))
+      (memory (import "env" "mem") 1)
+      (global (export "g") (mut i32) (i32.const 5))
+      (table (export "tbl") 1 funcref)
+      (func (export "run") (param i32) (result i32)
+        (i32.store (i32.const 0) (call $add1 (local.get 0)))
+        (i32.load (i32.const 0))
+      )
+    */
+    const IMPORTING_WASM: &[&str] = &[
+        "0061736d0100000001060160017f017f02170203656e760461646431000003656e76036d656d0200",
+        "01030201000404017000010606017f0141050b0711030372756e0001016703000374626c01000a12",
+        "01100041002000100036020041002802000b",
+    ];
+
+    /* wat2wasm
+      (func (export "add1") (param i32) (result i32) (i32.add (local.get 0) (i32.const 1)))
+      (memory (export "mem") 1)
+    */
+    const EXPORTING_WASM: &[&str] = &[
+        "0061736d0100000001060160017f017f030201000503010001070e0204616464310000036d656d02",
+        "000a09010700200041016a0b",
+    ];
+
+    fn empty_externs() -> wasm_extern_vec_t {
+        wasm_extern_vec_t {
+            size: 0,
+            data: std::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn new_module(store: *mut wasm_store_t, hex: &[&str]) -> *mut wasm_module_t {
+        let wasm = from_hex(hex);
+        let binary = wasm_byte_vec_t {
+            size: wasm.len(),
+            data: wasm.as_ptr() as *mut wasm_byte_t,
+        };
+        wasm_module_new(store, &binary)
+    }
+
+    unsafe fn trap_message(trap: *mut wasm_trap_t) -> String {
+        let mut message = wasm_byte_vec_t {
+            size: 0,
+            data: std::ptr::null_mut(),
+        };
+        wasm_trap_message(trap, &mut message);
+        let result = std::ffi::CStr::from_ptr(message.data)
+            .to_string_lossy()
+            .into_owned();
+        wasm_byte_vec_delete(&mut message);
+        wasm_trap_delete(trap);
+        result
+    }
+
+    unsafe fn call_i32(func: *const wasm_func_t, arg: i32) -> Result<i32, String> {
+        let mut args = [from_value(Value::I32(arg))];
+        let args = wasm_val_vec_t {
+            size: 1,
+            data: args.as_mut_ptr(),
+        };
+        let mut result = [from_value(Value::I32(0))];
+        let mut results = wasm_val_vec_t {
+            size: 1,
+            data: result.as_mut_ptr(),
+        };
+        let trap = wasm_func_call(func, &args, &mut results);
+        if trap.is_null() {
+            Ok(result[0].of.i32)
+        } else {
+            Err(trap_message(trap))
+        }
+    }
+
+    unsafe extern "C" fn add1(
+        args: *const wasm_val_vec_t,
+        results: *mut wasm_val_vec_t,
+    ) -> *mut wasm_trap_t {
+        let arg = (*(*args).data).of.i32;
+        if arg < 0 {
+            let message = b"negative\0";
+            let message = wasm_byte_vec_t {
+                size: message.len(),
+                data: message.as_ptr() as *mut wasm_byte_t,
+            };
+            return wasm_trap_new(std::ptr::null_mut(), &message);
+        }
+        *(*results).data = from_value(Value::I32(arg + 1));
+        std::ptr::null_mut()
+    }
+
+    unsafe extern "C" fn add_env(
+        env: *mut c_void,
+        args: *const wasm_val_vec_t,
+        results: *mut wasm_val_vec_t,
+    ) -> *mut wasm_trap_t {
+        *(*results).data = from_value(Value::I32((*(*args).data).of.i32 + *(env as *const i32)));
+        std::ptr::null_mut()
+    }
+
+    unsafe extern "C" fn add1_i64(
+        args: *const wasm_val_vec_t,
+        results: *mut wasm_val_vec_t,
+    ) -> *mut wasm_trap_t {
+        *(*results).data = from_value(Value::I64((*(*args).data).of.i32 as i64 + 1));
+        std::ptr::null_mut()
+    }
+
+    unsafe extern "C" fn finalize(env: *mut c_void) {
+        *(env as *mut i32) = -1;
+    }
+
+    unsafe fn i32_functype() -> *mut wasm_functype_t {
+        let mut params = wasm_valtype_vec_t {
+            size: 0,
+            data: std::ptr::null_mut(),
+        };
+        wasm_valtype_vec_new(&mut params, 1, [wasm_valtype_new(WASM_I32)].as_ptr());
+        let mut results = wasm_valtype_vec_t {
+            size: 0,
+            data: std::ptr::null_mut(),
+        };
+        wasm_valtype_vec_new(&mut results, 1, [wasm_valtype_new(WASM_I32)].as_ptr());
+        wasm_functype_new(&mut params, &mut results)
+    }
+
+    #[test]
+    fn call_exported_function() {
+        unsafe {
+            let engine = wasm_engine_new();
+            let store = wasm_store_new(engine);
+
+            let wasm = from_hex(WASM);
+            let mut binary = wasm_byte_vec_t {
+                size: 0,
+                data: std::ptr::null_mut(),
+            };
+            wasm_byte_vec_new(&mut binary, wasm.len(), wasm.as_ptr() as *const wasm_byte_t);
+            assert!(wasm_module_validate(store, &binary));
+            let module = wasm_module_new(store, &binary);
+            assert!(!module.is_null());
+            wasm_byte_vec_delete(&mut binary);
+
+            let instance = wasm_instance_new(store, module, std::ptr::null(), std::ptr::null_mut());
+            assert!(!instance.is_null());
+
+            let mut exports = wasm_extern_vec_t {
+                size: 0,
+                data: std::ptr::null_mut(),
+            };
+            wasm_instance_exports(instance, &mut exports);
+            assert_eq!(exports.size, 3);
+            let externs = std::slice::from_raw_parts(exports.data, exports.size);
+            assert_eq!(wasm_extern_kind(externs[0]), WASM_EXTERN_FUNC);
+            assert_eq!(wasm_extern_kind(externs[2]), WASM_EXTERN_MEMORY);
+            assert!(wasm_extern_as_func(externs[2]).is_null());
+
+            let add = wasm_extern_as_func(externs[0]);
+            assert_eq!(wasm_func_param_arity(add), 2);
+            assert_eq!(wasm_func_result_arity(add), 1);
+
+            let mut args = [from_value(Value::I32(40)), from_value(Value::I32(2))];
+            let args = wasm_val_vec_t {
+                size: 2,
+                data: args.as_mut_ptr(),
+            };
+            let mut result = [from_value(Value::I32(0))];
+            let mut results = wasm_val_vec_t {
+                size: 1,
+                data: result.as_mut_ptr(),
+            };
+            assert!(wasm_func_call(add, &args, &mut results).is_null());
+            assert_eq!(to_value(&result[0]), Some(Value::I32(42)));
+
+            let fail = wasm_extern_as_func(externs[1]);
+            let trap = wasm_func_call(fail, std::ptr::null(), std::ptr::null_mut());
+            assert!(!trap.is_null());
+            let mut message = wasm_byte_vec_t {
+                size: 0,
+                data: std::ptr::null_mut(),
+            };
+            wasm_trap_message(trap, &mut message);
+            assert_eq!(
+                std::ffi::CStr::from_ptr(message.data).to_str(),
+                Ok("trapped")
+            );
+            wasm_byte_vec_delete(&mut message);
+            wasm_trap_delete(trap);
+
+            wasm_extern_vec_delete(&mut exports);
+            wasm_instance_delete(instance);
+            wasm_module_delete(module);
+            wasm_store_delete(store);
+            wasm_engine_delete(engine);
+        }
+    }
+
+    #[test]
+    fn instantiate_with_imports_fails() {
+        unsafe {
+            let store = wasm_store_new(std::ptr::null_mut());
+            let wasm = from_hex(WASM);
+            let binary = wasm_byte_vec_t {
+                size: wasm.len(),
+                data: wasm.as_ptr() as *mut wasm_byte_t,
+            };
+            let module = wasm_module_new(store, &binary);
+
+            let mut imports_data = [std::ptr::null_mut::<wasm_extern_t>()];
+            let imports = wasm_extern_vec_t {
+                size: 1,
+                data: imports_data.as_mut_ptr(),
+            };
+            let mut trap = std::ptr::null_mut();
+            let instance = wasm_instance_new(store, module, &imports, &mut trap);
+            assert!(instance.is_null());
+            assert!(!trap.is_null());
+            wasm_trap_delete(trap);
+
+            wasm_module_delete(module);
+            wasm_store_delete(store);
+        }
+    }
+
+    #[test]
+    fn function_type_and_memory() {
+        unsafe {
+            let store = wasm_store_new(std::ptr::null_mut());
+            let module = new_module(store, WASM);
+            assert!(!module.is_null());
+
+            // The module can be instantiated more than once.
+            for _ in 0..2 {
+                let instance =
+                    wasm_instance_new(store, module, std::ptr::null(), std::ptr::null_mut());
+                assert!(!instance.is_null());
+                let mut exports = empty_externs();
+                wasm_instance_exports(instance, &mut exports);
+                let externs = std::slice::from_raw_parts(exports.data, exports.size);
+
+                let ty = wasm_func_type(wasm_extern_as_func(externs[0]));
+                let params = &*wasm_functype_params(ty);
+                assert_eq!(params.size, 2);
+                assert_eq!(wasm_valtype_kind(*params.data), WASM_I32);
+                assert_eq!(
+                    wasm_valtype_kind(*(*wasm_functype_results(ty)).data),
+                    WASM_I32
+                );
+                wasm_functype_delete(ty);
+                let ty = wasm_func_type(wasm_extern_as_func(externs[1]));
+                assert_eq!((*wasm_functype_params(ty)).size, 0);
+                assert_eq!((*wasm_functype_results(ty)).size, 0);
+                wasm_functype_delete(ty);
+
+                let memory = wasm_extern_as_memory(externs[2]);
+                assert!(!memory.is_null());
+                assert!(wasm_extern_as_memory(externs[0]).is_null());
+                assert_eq!(wasm_memory_size(memory), 1);
+                assert_eq!(wasm_memory_data_size(memory), 65536);
+                assert_eq!(*wasm_memory_data(memory), 0);
+                *wasm_memory_data(memory) = 42;
+                assert!(!wasm_memory_grow(memory, 1));
+
+                wasm_extern_vec_delete(&mut exports);
+                wasm_instance_delete(instance);
+            }
+
+            wasm_module_delete(module);
+            wasm_store_delete(store);
+        }
+    }
+
+    #[test]
+    fn new_trap_and_delete_null() {
+        unsafe {
+            let message = b"failed\0";
+            let message = wasm_byte_vec_t {
+                size: message.len(),
+                data: message.as_ptr() as *mut wasm_byte_t,
+            };
+            let trap = wasm_trap_new(std::ptr::null_mut(), &message);
+            assert_eq!(trap_message(trap), "failed");
+
+            wasm_trap_delete(std::ptr::null_mut());
+            wasm_extern_delete(std::ptr::null_mut());
+            wasm_extern_vec_delete(std::ptr::null_mut());
+            wasm_byte_vec_delete(std::ptr::null_mut());
+            wasm_module_delete(std::ptr::null_mut());
+            wasm_instance_delete(std::ptr::null_mut());
+            wasm_table_delete(std::ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn host_imports() {
+        unsafe {
+            let store = wasm_store_new(std::ptr::null_mut());
+            let module = new_module(store, IMPORTING_WASM);
+
+            let functype = i32_functype();
+            let func = wasm_func_new(store, functype, Some(add1));
+            assert!(!func.is_null());
+            let ty = wasm_func_type(func);
+            assert_eq!((*wasm_functype_params(ty)).size, 1);
+            assert_eq!(
+                wasm_valtype_kind(*(*wasm_functype_results(ty)).data),
+                WASM_I32
+            );
+            wasm_functype_delete(ty);
+            wasm_functype_delete(functype);
+            assert_eq!(call_i32(func, 1), Ok(2));
+            assert_eq!(call_i32(func, -1), Err("negative".to_string()));
+
+            let memorytype = wasm_memorytype_new(&wasm_limits_t {
+                min: 1,
+                max: WASM_LIMITS_MAX_DEFAULT,
+            });
+            let memory = wasm_memory_new(store, memorytype);
+            assert!(!memory.is_null());
+            assert_eq!((*wasm_memorytype_limits(memorytype)).min, 1);
+            wasm_memorytype_delete(memorytype);
+
+            let mut imports_data = [wasm_func_as_extern(func), wasm_memory_as_extern(memory)];
+            let imports = wasm_extern_vec_t {
+                size: 2,
+                data: imports_data.as_mut_ptr(),
+            };
+            let instance = wasm_instance_new(store, module, &imports, std::ptr::null_mut());
+            assert!(!instance.is_null());
+            // The instance keeps the imports alive.
+            wasm_func_delete(func);
+
+            let mut exports = empty_externs();
+            wasm_instance_exports(instance, &mut exports);
+            let externs = std::slice::from_raw_parts(exports.data, exports.size);
+            let run = wasm_extern_as_func(externs[0]);
+            assert_eq!(call_i32(run, 41), Ok(42));
+            assert_eq!(wasm_memory_size(memory), 1);
+            assert_eq!(wasm_memory_data_size(memory), 65536);
+            assert_eq!(*wasm_memory_data(memory), 42);
+            assert!(!wasm_memory_grow(memory, 1));
+            // The trap of the host function traps the instance.
+            assert_eq!(call_i32(run, -1), Err("trapped".to_string()));
+
+            let global = wasm_extern_as_global(externs[1]);
+            assert!(!global.is_null());
+            let mut value = from_value(Value::I32(0));
+            wasm_global_get(global, &mut value);
+            assert_eq!(to_value(&value), Some(Value::I32(5)));
+            wasm_global_set(global, &from_value(Value::I32(6)));
+            wasm_global_set(global, &from_value(Value::I64(7)));
+            wasm_global_get(global, &mut value);
+            assert_eq!(to_value(&value), Some(Value::I32(6)));
+
+            let table = wasm_extern_as_table(externs[2]);
+            assert!(!table.is_null());
+            assert!(wasm_extern_as_memory(externs[2]).is_null());
+            assert_eq!(wasm_table_size(table), 1);
+            assert!(wasm_table_grow(table, 2, std::ptr::null_mut()));
+            assert_eq!(wasm_table_size(table), 3);
+
+            wasm_extern_vec_delete(&mut exports);
+            wasm_instance_delete(instance);
+            wasm_memory_delete(memory);
+            wasm_module_delete(module);
+            wasm_store_delete(store);
+        }
+    }
+
+    #[test]
+    fn host_function_with_env() {
+        unsafe {
+            let mut env: i32 = 10;
+            let functype = i32_functype();
+            let func = wasm_func_new_with_env(
+                std::ptr::null_mut(),
+                functype,
+                Some(add_env),
+                &mut env as *mut i32 as *mut c_void,
+                Some(finalize),
+            );
+            wasm_functype_delete(functype);
+            assert_eq!(call_i32(func, 1), Ok(11));
+            let trap = wasm_func_call(func, std::ptr::null(), std::ptr::null_mut());
+            assert_eq!(trap_message(trap), "argument mismatch");
+            wasm_func_delete(func);
+            assert_eq!(env, -1);
+        }
+    }
+
+    #[test]
+    fn host_function_result_type() {
+        unsafe {
+            let store = wasm_store_new(std::ptr::null_mut());
+            let module = new_module(store, IMPORTING_WASM);
+            let functype = i32_functype();
+            let func = wasm_func_new(store, functype, Some(add1_i64));
+            wasm_functype_delete(functype);
+            let message = "result type does not match the function type";
+            assert_eq!(call_i32(func, 1), Err(message.to_string()));
+
+            let memorytype = wasm_memorytype_new(&wasm_limits_t {
+                min: 1,
+                max: WASM_LIMITS_MAX_DEFAULT,
+            });
+            let memory = wasm_memory_new(store, memorytype);
+            wasm_memorytype_delete(memorytype);
+            let mut imports_data = [wasm_func_as_extern(func), wasm_memory_as_extern(memory)];
+            let imports = wasm_extern_vec_t {
+                size: 2,
+                data: imports_data.as_mut_ptr(),
+            };
+            // The module can be instantiated more than once.
+            for _ in 0..2 {
+                let instance = wasm_instance_new(store, module, &imports, std::ptr::null_mut());
+                assert!(!instance.is_null());
+                let mut exports = empty_externs();
+                wasm_instance_exports(instance, &mut exports);
+                let run = wasm_extern_as_func(*exports.data);
+                assert_eq!(call_i32(run, 41), Err("trapped".to_string()));
+                wasm_extern_vec_delete(&mut exports);
+                wasm_instance_delete(instance);
+            }
+
+            wasm_func_delete(func);
+            wasm_memory_delete(memory);
+            wasm_module_delete(module);
+            wasm_store_delete(store);
+        }
+    }
+
+    #[test]
+    fn import_exports_of_instance() {
+        unsafe {
+            let store = wasm_store_new(std::ptr::null_mut());
+            let exporting = new_module(store, EXPORTING_WASM);
+            let exporter =
+                wasm_instance_new(store, exporting, std::ptr::null(), std::ptr::null_mut());
+            let mut exports = empty_externs();
+            wasm_instance_exports(exporter, &mut exports);
+            assert_eq!(exports.size, 2);
+
+            let importing = new_module(store, IMPORTING_WASM);
+            let instance = wasm_instance_new(store, importing, &exports, std::ptr::null_mut());
+            assert!(!instance.is_null());
+            let mut importer_exports = empty_externs();
+            wasm_instance_exports(instance, &mut importer_exports);
+            let run = wasm_extern_as_func(*importer_exports.data);
+            assert_eq!(call_i32(run, 6), Ok(7));
+
+            // The memory of the exporting instance is shared.
+            let memory = wasm_extern_as_memory(*exports.data.add(1));
+            assert_eq!(*wasm_memory_data(memory), 7);
+
+            // The imports of the wrong kinds are rejected.
+            std::slice::from_raw_parts_mut(exports.data, exports.size).swap(0, 1);
+            let mut trap = std::ptr::null_mut();
+            assert!(wasm_instance_new(store, importing, &exports, &mut trap).is_null());
+            assert_eq!(trap_message(trap), "unknown import env::add1");
+
+            wasm_extern_vec_delete(&mut importer_exports);
+            wasm_extern_vec_delete(&mut exports);
+            wasm_instance_delete(instance);
+            wasm_instance_delete(exporter);
+            wasm_module_delete(importing);
+            wasm_module_delete(exporting);
+            wasm_store_delete(store);
+        }
+    }
+}
@@ -0,0 +1,366 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A facade mirroring the [wasmtime](https://docs.rs/wasmtime) API surface, backed by Fizzy.
+//!
+//! Applications written against `Engine`, `Store`, `Module`, `Instance`, `Func` and `Memory`
+//! can switch to Fizzy by replacing `use wasmtime::*` with `use fizzy::wasmtime::*`.
+//! Importing host functions is not supported yet.
+
+use crate::Error;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A WebAssembly value.
+pub type Val = crate::Value;
+
+/// Global configuration options used to create an [`Engine`].
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    features: crate::Features,
+}
+
+impl Config {
+    /// Returns the default configuration, accepting only WebAssembly 1.0 modules.
+    pub fn new() -> Self {
+        Config::default()
+    }
+
+    /// Configures whether the WebAssembly multi-memory proposal is enabled.
+    pub fn wasm_multi_memory(&mut self, enable: bool) -> &mut Self {
+        self.features.multi_memory = enable;
+        self
+    }
+}
+
+/// The global context for compilation, holding the enabled WebAssembly proposals.
+#[derive(Clone, Default)]
+pub struct Engine {
+    features: crate::Features,
+}
+
+impl Engine {
+    /// Returns a new engine.
+    pub fn new() -> Self {
+        Engine::default()
+    }
+
+    /// Returns a new engine with the configuration.
+    pub fn with_config(config: &Config) -> Self {
+        Engine {
+            features: config.features,
+        }
+    }
+
+    fn parse(&self, binary: &[u8]) -> Result<crate::Module, Error> {
+        crate::parse_with_features(binary, &Default::default(), &self.features)
+    }
+}
+
+/// A collection of instances.
+#[derive(Clone)]
+pub struct Store {
+    engine: Engine,
+}
+
+impl Store {
+    /// Creates a new store using the given engine.
+    pub fn new(engine: &Engine) -> Self {
+        Store {
+            engine: engine.clone(),
+        }
+    }
+
+    /// Returns the engine this store was created with.
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+}
+
+/// A validated WebAssembly module, which can be instantiated multiple times.
+#[derive(Clone)]
+pub struct Module {
+    // A Fizzy module is consumed by instantiation, so the binary is kept and parsed again.
+    binary: Rc<[u8]>,
+    engine: Engine,
+}
+
+impl Module {
+    /// Creates a new module from the WebAssembly binary.
+    pub fn new(engine: &Engine, bytes: impl AsRef<[u8]>) -> Result<Module, Error> {
+        Module::from_binary(engine, bytes.as_ref())
+    }
+
+    /// Creates a new module from the WebAssembly binary.
+    pub fn from_binary(engine: &Engine, binary: &[u8]) -> Result<Module, Error> {
+        Module::validate(engine, binary)?;
+        Ok(Module {
+            binary: binary.into(),
+            engine: engine.clone(),
+        })
+    }
+
+    /// Validates the WebAssembly binary.
+    pub fn validate(engine: &Engine, binary: &[u8]) -> Result<(), Error> {
+        engine.parse(binary).map(drop)
+    }
+}
+
+/// An external item which can be imported or exported.
+#[derive(Clone)]
+pub enum Extern {
+    Func(Func),
+    Memory(Memory),
+}
+
+impl Extern {
+    /// Returns the function, if this is a function.
+    pub fn into_func(self) -> Option<Func> {
+        match self {
+            Extern::Func(func) => Some(func),
+            _ => None,
+        }
+    }
+
+    /// Returns the memory, if this is a memory.
+    pub fn into_memory(self) -> Option<Memory> {
+        match self {
+            Extern::Memory(memory) => Some(memory),
+            _ => None,
+        }
+    }
+}
+
+/// An instantiated module.
+#[derive(Clone)]
+pub struct Instance {
+    instance: Rc<RefCell<crate::Instance>>,
+}
+
+impl Instance {
+    /// Instantiates the module. Imports are not supported, so `imports` must be empty.
+    pub fn new(_store: &Store, module: &Module, imports: &[Extern]) -> Result<Instance, Error> {
+        if !imports.is_empty() {
+            return Err(Error::InstantiationFailed(
+                "imports are not supported".to_string(),
+            ));
+        }
+        let instance = module.engine.parse(&module.binary)?.instantiate()?;
+        Ok(Instance {
+            instance: Rc::new(RefCell::new(instance)),
+        })
+    }
+
+    /// Looks up an export by name.
+    pub fn get_export(&self, name: &str) -> Option<Extern> {
+        let exports = self.instance.borrow().exports();
+        let (_, kind, index) = exports
+            .into_iter()
+            .find(|(export_name, _, _)| export_name == name)?;
+        match kind {
+            crate::sys::FizzyExternalKindFunction => Some(Extern::Func(Func {
+                instance: self.instance.clone(),
+                func_idx: index,
+            })),
+            crate::sys::FizzyExternalKindMemory => Some(Extern::Memory(Memory {
+                instance: self.instance.clone(),
+                memory_idx: index,
+            })),
+            _ => None,
+        }
+    }
+
+    /// Looks up an exported function by name.
+    pub fn get_func(&self, name: &str) -> Option<Func> {
+        self.get_export(name)?.into_func()
+    }
+
+    /// Looks up an exported memory by name.
+    pub fn get_memory(&self, name: &str) -> Option<Memory> {
+        self.get_export(name)?.into_memory()
+    }
+}
+
+/// A WebAssembly function exported from an instance.
+#[derive(Clone)]
+pub struct Func {
+    instance: Rc<RefCell<crate::Instance>>,
+    func_idx: u32,
+}
+
+impl Func {
+    /// Calls the function. A trap is reported as [`Error::Trapped`].
+    pub fn call(&self, params: &[Val]) -> Result<Box<[Val]>, Error> {
+        let result = self
+            .instance
+            .borrow_mut()
+            .execute_function(self.func_idx, params)?
+            .into_result()?;
+        Ok(result.into_iter().collect())
+    }
+
+    /// Returns the number of parameters of the function.
+    pub fn param_arity(&self) -> usize {
+        let instance = self.instance.borrow();
+        unsafe { crate::sys::fizzy_get_function_type(instance.module(), self.func_idx).inputs_size }
+    }
+
+    /// Returns the number of results of the function.
+    pub fn result_arity(&self) -> usize {
+        let instance = self.instance.borrow();
+        let output =
+            unsafe { crate::sys::fizzy_get_function_type(instance.module(), self.func_idx).output };
+        if output == crate::sys::FizzyValueTypeVoid {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// The size of a memory page in bytes.
+const PAGE_SIZE: usize = 65536;
+
+/// A linear memory exported from an instance.
+#[derive(Clone)]
+pub struct Memory {
+    instance: Rc<RefCell<crate::Instance>>,
+    memory_idx: u32,
+}
+
+impl Memory {
+    /// Returns the size of the memory in bytes.
+    pub fn data_size(&self) -> usize {
+        self.instance.borrow().memory_size_at(self.memory_idx)
+    }
+
+    /// Returns the size of the memory in pages.
+    pub fn size(&self) -> u32 {
+        (self.data_size() / PAGE_SIZE) as u32
+    }
+
+    /// Reads memory starting at `offset` into `buffer`.
+    pub fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        let offset = u32_offset(offset)?;
+        self.instance
+            .borrow()
+            .memory_get_at(self.memory_idx, offset, buffer)
+    }
+
+    /// Writes `buffer` into memory starting at `offset`.
+    pub fn write(&self, offset: usize, buffer: &[u8]) -> Result<(), Error> {
+        let offset = u32_offset(offset)?;
+        self.instance
+            .borrow_mut()
+            .memory_set_at(self.memory_idx, offset, buffer)
+    }
+}
+
+fn u32_offset(offset: usize) -> Result<u32, Error> {
+    if offset > u32::MAX as usize {
+        Err(Error::InvalidMemoryOffsetOrSize)
+    } else {
+        Ok(offset as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::from_hex;
+
+    #[test]
+    fn instantiate_and_call() {
+        /* wat2wasm
+          (func (export "add") (param i32 i32) (result i32)
+            (i32.add (local.get 0) (local.get 1))
+          )
+          (func (export "fail") unreachable)
+          (memory (export "mem") 1)
+        */
+        let wasm = from_hex(&[
+            "0061736d01000000010a0260027f7f017f6000000303020001050301000107140303616464000004",
+            "6661696c0001036d656d02000a0d020700200020016a0b0300000b",
+        ]);
+
+        let engine = Engine::default();
+        let store = Store::new(&engine);
+        let module = Module::new(store.engine(), &wasm).unwrap();
+        let instance = Instance::new(&store, &module, &[]).unwrap();
+
+        let add = instance.get_func("add").unwrap();
+        assert_eq!(add.param_arity(), 2);
+        assert_eq!(add.result_arity(), 1);
+        let results = add.call(&[Val::I32(40), Val::I32(2)]).unwrap();
+        assert_eq!(&*results, &[Val::I32(42)]);
+
+        let fail = instance.get_func("fail").unwrap();
+        assert!(matches!(fail.call(&[]), Err(Error::Trapped(_))));
+
+        assert!(instance.get_func("mem").is_none());
+        assert!(instance.get_func("foo").is_none());
+
+        let memory = instance.get_memory("mem").unwrap();
+        assert_eq!(memory.size(), 1);
+        assert_eq!(memory.data_size(), 65536);
+        memory.write(10, &[1, 2, 3]).unwrap();
+        let mut buffer = [0u8; 3];
+        memory.read(10, &mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2, 3]);
+        assert_eq!(
+            memory.read(65535, &mut buffer).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+
+        // The module can be instantiated again.
+        let instance2 = Instance::new(&store, &module, &[]).unwrap();
+        let mut buffer = [0u8; 3];
+        instance2
+            .get_memory("mem")
+            .unwrap()
+            .read(10, &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, [0, 0, 0]);
+    }
+
+    #[test]
+    fn multiple_memories() {
+        /* wat2wasm --enable-multi-memory
+          (memory (export "mem0") 1)
+          (memory (export "mem1") 2)
+        */
+        let wasm = from_hex(&["0061736d0100000005050200010002070f02046d656d300200046d656d310201"]);
+
+        assert!(Module::new(&Engine::default(), &wasm).is_err());
+
+        let engine = Engine::with_config(Config::new().wasm_multi_memory(true));
+        let store = Store::new(&engine);
+        let module = Module::new(store.engine(), &wasm).unwrap();
+        let instance = Instance::new(&store, &module, &[]).unwrap();
+
+        let mem0 = instance.get_memory("mem0").unwrap();
+        let mem1 = instance.get_memory("mem1").unwrap();
+        assert_eq!(mem0.size(), 1);
+        assert_eq!(mem1.size(), 2);
+        mem1.write(65536, &[1, 2]).unwrap();
+        let mut buffer = [0u8; 2];
+        mem1.read(65536, &mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2]);
+        assert_eq!(
+            mem0.read(65536, &mut buffer).err(),
+            Some(Error::InvalidMemoryOffsetOrSize)
+        );
+    }
+
+    #[test]
+    fn invalid_module() {
+        let engine = Engine::new();
+        assert_eq!(
+            Module::new(&engine, [0x00]).err(),
+            Some(Error::MalformedModule(
+                "invalid wasm module prefix".to_string()
+            ))
+        );
+    }
+}
@@ -17,9 +17,162 @@ typedef struct FizzyModule FizzyModule;
 /// The opaque data type representing an instance (instantiated module).
 typedef struct FizzyInstance FizzyInstance;
 
+/// The opaque data type representing a table.
+typedef struct FizzyTable FizzyTable;
+
+/// The opaque data type representing a reference to a function, an element of a table.
+typedef struct FizzyFuncRef FizzyFuncRef;
+
+/// The opaque data type representing a memory.
+typedef struct FizzyMemory FizzyMemory;
+
+/// The type of a value.
+typedef uint8_t FizzyValueType;
+
+/// Value type for i32.
+static const FizzyValueType FizzyValueTypeI32 = 0x7f;
+/// Value type for i64.
+static const FizzyValueType FizzyValueTypeI64 = 0x7e;
+/// Value type for f32.
+static const FizzyValueType FizzyValueTypeF32 = 0x7d;
+/// Value type for f64.
+static const FizzyValueType FizzyValueTypeF64 = 0x7c;
+/// Special value used as a function output type to indicate the absence of a result.
+static const FizzyValueType FizzyValueTypeVoid = 0;
+
+/// Function type.
+typedef struct FizzyFunctionType
+{
+    /// Output type, equals to FizzyValueTypeVoid, if function has no output.
+    FizzyValueType output;
+    /// Pointer to input types array.
+    const FizzyValueType* inputs;
+    /// Input types array size.
+    size_t inputs_size;
+} FizzyFunctionType;
+
+/// The kind of an external item (imported or exported).
+typedef enum FizzyExternalKind
+{
+    FizzyExternalKindFunction,
+    FizzyExternalKindTable,
+    FizzyExternalKindMemory,
+    FizzyExternalKindGlobal
+} FizzyExternalKind;
+
+/// Export description.
+///
+/// @note  Only valid as long as the module it was obtained from is alive.
+typedef struct FizzyExportDescription
+{
+    /// Export name. NULL-terminated string.
+    const char* name;
+    /// Export kind.
+    FizzyExternalKind kind;
+    /// Index of the exported item in the module's index space of the given kind.
+    uint32_t index;
+} FizzyExportDescription;
+
+/// Limits of a table or memory.
+typedef struct FizzyLimits
+{
+    /// Minimum value.
+    uint32_t min;
+    /// Maximum value. Valid only if has_max equals true.
+    uint32_t max;
+    /// Whether limits has maximum value.
+    bool has_max;
+} FizzyLimits;
+
+/// Global type.
+typedef struct FizzyGlobalType
+{
+    /// Value type of the global.
+    FizzyValueType value_type;
+    /// Whether the global is mutable.
+    bool is_mutable;
+} FizzyGlobalType;
+
+/// The kind of allocation backing the linear memory of an instance.
+typedef enum FizzyMemoryBacking
+{
+    /// Memory is allocated on the heap and reallocated on growth.
+    FizzyMemoryBackingHeap,
+    /// Address space for the maximum memory size is reserved up front and pages are committed on
+    /// growth, so the memory is never moved. Falls back to heap on unsupported platforms.
+    FizzyMemoryBackingMapped,
+    /// Mapped memory with the reservation covering any address accessed by an instruction.
+    /// The accesses are not bounds-checked, instead a SIGSEGV/SIGBUS handler converts the faults
+    /// of the out-of-bounds accesses into traps. Falls back to mapped on platforms other than
+    /// 64-bit POSIX. The handler forwards other faults to the previously installed one.
+    FizzyMemoryBackingGuarded
+} FizzyMemoryBacking;
+
+/// Custom allocator, e.g. for accounting or for selecting a specific allocator.
+///
+/// All functions must be set. They report failure by returning NULL.
+typedef struct FizzyAllocator
+{
+    /// Allocate @p size bytes, not necessarily zero-initialized.
+    void* (*allocate)(void* context, size_t size);
+    /// Resize the allocation of @p old_size bytes to @p new_size bytes, preserving its contents.
+    /// The allocation must be left intact in case of failure.
+    void* (*reallocate)(void* context, void* ptr, size_t old_size, size_t new_size);
+    /// Free the allocation of @p size bytes.
+    void (*deallocate)(void* context, void* ptr, size_t size);
+    /// Pointer to external data passed to the functions. Can be NULL.
+    void* context;
+} FizzyAllocator;
+
+/// Instantiation options.
+typedef struct FizzyInstantiateOptions
+{
+    /// The kind of allocation backing the memory defined by the module.
+    FizzyMemoryBacking memory_backing;
+    /// Pointer to the allocator of the memory defined by the module, used for
+    /// FizzyMemoryBackingHeap only. Can be NULL, in which case the C runtime allocator is used.
+    /// The allocator functions are called until the instance is freed.
+    const FizzyAllocator* memory_allocator;
+    /// Whether the start function of the module is not run by the instantiation, but later with
+    /// fizzy_run_start.
+    bool defer_start;
+    /// The hard limit of the memory size in pages, or 0 for the default limit of 4096 pages
+    /// (256MB). The instantiation fails if the memory limits of the module exceed it.
+    uint32_t memory_pages_limit;
+    /// The maximum depth of nested calls, or 0 for the default limit of 2048. Greater values are
+    /// reduced to the default limit.
+    uint32_t call_stack_limit;
+    /// Whether the stack traces of the traps are not recorded, which makes the traps cheaper.
+    /// fizzy_get_trap_stack_trace returns 0 then.
+    bool disable_trap_stack_trace;
+} FizzyInstantiateOptions;
+
+/// Import description.
+///
+/// @note  Only valid as long as the module it was obtained from is alive.
+typedef struct FizzyImportDescription
+{
+    /// Import's module name. NULL-terminated string.
+    const char* module;
+    /// Import name. NULL-terminated string.
+    const char* name;
+    /// Import kind.
+    FizzyExternalKind kind;
+    /// Type of the imported item, the member to use depends on kind.
+    union
+    {
+        FizzyFunctionType function_type;
+        FizzyLimits table_limits;
+        FizzyLimits memory_limits;
+        FizzyGlobalType global_type;
+    } desc;
+} FizzyImportDescription;
+
 /// The data type representing numeric values.
 ///
 /// i64 member is used to represent values of both i32 and i64 type.
+/// Values of f32 type are stored as bit pattern in the lower 32 bits of the i64 member.
+/// The f32 member can be used to access them only on little-endian hosts.
 union FizzyValue
 {
     uint64_t i64;
@@ -39,6 +192,140 @@ typedef struct FizzyExecutionResult
     union FizzyValue value;
 } FizzyExecutionResult;
 
+/// The kind of a constant expression.
+typedef enum FizzyConstantExpressionKind
+{
+    FizzyConstantExpressionKindConstant,
+    FizzyConstantExpressionKindGlobalGet
+} FizzyConstantExpressionKind;
+
+/// Constant expression, e.g. an offset of a segment.
+typedef struct FizzyConstantExpression
+{
+    /// Expression kind.
+    FizzyConstantExpressionKind kind;
+    /// The constant value or the index of the global which value is used,
+    /// the member to use depends on kind.
+    union
+    {
+        union FizzyValue constant;
+        uint32_t global_index;
+    } value;
+} FizzyConstantExpression;
+
+/// Data segment.
+///
+/// @note  Only valid as long as the module it was obtained from is alive.
+typedef struct FizzyDataSegment
+{
+    /// Index of the memory to initialize.
+    uint32_t memory_index;
+    /// Offset in the memory, of i32 type.
+    FizzyConstantExpression offset;
+    /// Pointer to the data bytes. Can be NULL iff size equals 0.
+    const uint8_t* data;
+    /// Number of data bytes.
+    size_t size;
+} FizzyDataSegment;
+
+/// Element segment.
+///
+/// @note  Only valid as long as the module it was obtained from is alive.
+typedef struct FizzyElementSegment
+{
+    /// Index of the table to initialize.
+    uint32_t table_index;
+    /// Offset in the table, of i32 type.
+    FizzyConstantExpression offset;
+    /// Pointer to the function indices. Can be NULL iff func_indices_size equals 0.
+    const uint32_t* func_indices;
+    /// Number of function indices.
+    size_t func_indices_size;
+} FizzyElementSegment;
+
+/// Code metrics of a function defined in the module.
+typedef struct FizzyFunctionMetrics
+{
+    /// Size of the function body in the wasm binary in bytes, including the local declarations.
+    uint32_t body_size;
+    /// Number of local variables, not including the arguments.
+    uint32_t local_count;
+    /// Maximum height of the operand stack during execution of the function.
+    uint32_t max_stack_height;
+} FizzyFunctionMetrics;
+
+/// Global defined in the module.
+typedef struct FizzyGlobal
+{
+    /// Type of the global.
+    FizzyGlobalType type;
+    /// Initializer expression, of the value type of the global.
+    FizzyConstantExpression initializer;
+} FizzyGlobal;
+
+
+/// The state of the execution at the point right before an instruction is executed.
+typedef struct FizzyExecutionState
+{
+    /// Index of the function being executed.
+    uint32_t func_idx;
+    /// Offset of the instruction in the function's internal instruction array.
+    uint32_t instr_offset;
+    /// Opcode of the instruction.
+    uint8_t opcode;
+    /// Pointer to the arguments and local variables of the function.
+    const union FizzyValue* locals;
+    /// Number of arguments and local variables.
+    size_t locals_size;
+    /// Pointer to the operand stack, bottom item first.
+    const union FizzyValue* stack;
+    /// Operand stack height.
+    size_t stack_size;
+    /// Call stack depth.
+    int depth;
+    /// Static offset of a memory load or store instruction, 0 for other instructions.
+    uint32_t memory_offset;
+    /// Memory index of a memory load or store instruction, 0 for other instructions.
+    uint32_t memory_idx;
+} FizzyExecutionState;
+
+/// Pointer to instruction hook.
+///
+/// @param context      Opaque pointer to hook context.
+/// @param instance     Pointer to module instance.
+/// @param state        Pointer to the execution state. Only valid during the call.
+/// @returns            true to continue execution, false to abort it with a trap.
+typedef bool (*FizzyInstructionHook)(
+    void* context, FizzyInstance* instance, const FizzyExecutionState* state);
+
+/// Pointer to periodic hook.
+///
+/// @param context      Opaque pointer to hook context.
+/// @param instance     Pointer to module instance.
+/// @returns            true to continue execution, false to abort it with a trap.
+typedef bool (*FizzyPeriodicHook)(void* context, FizzyInstance* instance);
+
+/// Pointer to memory growth hook.
+///
+/// @param context      Opaque pointer to hook context.
+/// @param instance     Pointer to module instance.
+/// @param memory_idx   Index of the grown memory.
+/// @param old_pages    Memory size in pages before the growth.
+/// @param new_pages    Memory size in pages after the growth.
+typedef void (*FizzyMemoryGrowHook)(void* context, FizzyInstance* instance, uint32_t memory_idx,
+    uint32_t old_pages, uint32_t new_pages);
+
+/// Pointer to memory growth limiter.
+///
+/// @param context          Opaque pointer to limiter context.
+/// @param instance         Pointer to module instance.
+/// @param memory_idx       Index of the memory to grow.
+/// @param current_pages    Memory size in pages.
+/// @param desired_pages    Memory size in pages requested by the growth.
+/// @param max_pages        Maximum memory size in pages allowed by the memory limits.
+/// @returns                true to allow the growth, false to fail it.
+typedef bool (*FizzyMemoryGrowLimiter)(void* context, FizzyInstance* instance,
+    uint32_t memory_idx, uint32_t current_pages, uint32_t desired_pages, uint32_t max_pages);
 
 /// Pointer to external function.
 ///
@@ -61,6 +348,94 @@ typedef struct FizzyExternalFunction
     void* context;
 } FizzyExternalFunction;
 
+/// External table.
+typedef struct FizzyExternalTable
+{
+    /// Pointer to table.
+    FizzyTable* table;
+    /// Table limits.
+    FizzyLimits limits;
+} FizzyExternalTable;
+
+/// External memory.
+typedef struct FizzyExternalMemory
+{
+    /// Pointer to memory.
+    FizzyMemory* memory;
+    /// Memory limits.
+    FizzyLimits limits;
+} FizzyExternalMemory;
+
+/// External global.
+typedef struct FizzyExternalGlobal
+{
+    /// Pointer to global value.
+    union FizzyValue* value;
+    /// Type of global.
+    FizzyGlobalType type;
+} FizzyExternalGlobal;
+
+/// The kind of a failure of an operation.
+typedef enum FizzyErrorCode
+{
+    /// The operation succeeded.
+    FizzySuccess = 0,
+    /// The module binary cannot be decoded.
+    FizzyErrorMalformedModule,
+    /// The module is decoded, but does not pass the validation.
+    FizzyErrorInvalidModule,
+    /// A memory allocation failed.
+    FizzyErrorMemoryAllocationFailed,
+    /// The operation failed for another reason, described by the message.
+    FizzyErrorOther,
+    /// The instantiation failed, because the start function has trapped.
+    FizzyErrorStartFunctionTrapped
+} FizzyErrorCode;
+
+/// The error of a failed operation.
+typedef struct FizzyError
+{
+    /// Error code.
+    FizzyErrorCode code;
+    /// NULL-terminated error message, possibly truncated.
+    char message[256];
+} FizzyError;
+
+/// Limits of the resources used when parsing a module.
+typedef struct FizzyParserLimits
+{
+    /// The maximum number of functions, including imported ones.
+    uint32_t max_function_count;
+    /// The maximum size of a function body in bytes, including the local declarations.
+    uint32_t max_function_body_size;
+    /// The maximum number of locals of a function, excluding the function parameters.
+    uint32_t max_local_count;
+    /// The maximum depth of nested blocks, loops and ifs in a function body.
+    uint32_t max_nesting_depth;
+    /// The maximum total size in bytes of the parsed module representation (approximate).
+    uint64_t max_allocation_size;
+} FizzyParserLimits;
+
+/// The WebAssembly proposals accepted when parsing a module, on top of WebAssembly 1.0.
+typedef struct FizzyFeatures
+{
+    /// Multiple imported and defined memories, and the memory index immediates of the memory
+    /// instructions (the multi-memory proposal).
+    bool multi_memory;
+} FizzyFeatures;
+
+/// Get the version of the library.
+///
+/// @returns Null-terminated version string, e.g. "0.6.0", valid for the lifetime of the program.
+const char* fizzy_get_version(void);
+
+/// Check if the library supports the guarded memory, i.e. the memory accesses bounds checked
+/// by the virtual memory protection.
+///
+/// The guarded memory is supported on 64-bit Unix platforms, unless the library is built with
+/// the FIZZY_MINIMAL option.
+bool fizzy_has_guarded_memory(void);
+
 /// Validate binary module.
 bool fizzy_validate(const uint8_t* wasm_binary, size_t wasm_binary_size);
 
@@ -69,12 +444,235 @@ bool fizzy_validate(const uint8_t* wasm_binary, size_t wasm_binary_size);
 /// @returns non-NULL pointer to module in case of success, NULL otherwise.
 const FizzyModule* fizzy_parse(const uint8_t* wasm_binary, size_t wasm_binary_size);
 
+/// Parse binary module, failing if any of the parser resource limits is exceeded.
+///
+/// @param wasm_binary      Pointer to module binary data.
+/// @param wasm_binary_size Size of the module binary data.
+/// @param limits           Pointer to the parser limits. Can be NULL, in which case no limits
+///                         are imposed.
+/// @param error            Pointer to the output where the error is stored. Can be NULL.
+/// @returns non-NULL pointer to module in case of success, NULL otherwise.
+const FizzyModule* fizzy_parse_with_limits(const uint8_t* wasm_binary, size_t wasm_binary_size,
+    const FizzyParserLimits* limits, FizzyError* error);
+
+/// Parse binary module, accepting the enabled WebAssembly proposals.
+///
+/// The modules using the proposals are malformed or invalid for the other parse functions.
+///
+/// @param wasm_binary      Pointer to module binary data.
+/// @param wasm_binary_size Size of the module binary data.
+/// @param limits           Pointer to the parser limits. Can be NULL, in which case no limits
+///                         are imposed.
+/// @param features         Pointer to the enabled proposals. Can be NULL, in which case only
+///                         WebAssembly 1.0 is accepted.
+/// @param error            Pointer to the output where the error is stored. Can be NULL.
+/// @returns non-NULL pointer to module in case of success, NULL otherwise.
+const FizzyModule* fizzy_parse_with_features(const uint8_t* wasm_binary, size_t wasm_binary_size,
+    const FizzyParserLimits* limits, const FizzyFeatures* features, FizzyError* error);
+
 /// Free resources associated with the module.
 ///
 /// Should be called unless @p module was passed to fizzy_instantiate.
 /// If passed pointer is NULL, has no effect.
 void fizzy_free_module(const FizzyModule* module);
 
+/// Make a copy of a module.
+///
+/// @param  module  Pointer to module. Cannot be NULL.
+/// @returns        non-NULL pointer to a newly allocated module in case of success, NULL if memory
+///                 allocation failed.
+const FizzyModule* fizzy_clone_module(const FizzyModule* module);
+
+/// Get type of the function defined in the module.
+///
+/// @param module   Pointer to module.
+/// @param func_idx Function index. Can be either index of an imported function or of a function
+///                 defined in module. Behaviour is undefined, if index is not valid according
+///                 to module definition.
+/// @returns        Type of the function corresponding to the index.
+///
+/// @note All module function indices are greater than all imported function indices.
+FizzyFunctionType fizzy_get_function_type(const FizzyModule* module, uint32_t func_idx);
+
+/// Get number of types in the type section of the module.
+///
+/// @param  module  Pointer to module. Cannot be NULL.
+/// @returns        Number of types, i.e. the size of the type index space.
+uint32_t fizzy_get_type_count(const FizzyModule* module);
+
+/// Get type from the type section of the module, e.g. the type expected by call_indirect.
+///
+/// @param module   Pointer to module. Cannot be NULL.
+/// @param type_idx Type index. Behaviour is undefined, if index is not valid according
+///                 to module definition.
+/// @returns        Type corresponding to the index.
+FizzyFunctionType fizzy_get_type(const FizzyModule* module, uint32_t type_idx);
+
+/// Get number of functions in the module, including imported functions.
+///
+/// @param  module  Pointer to module. Cannot be NULL.
+/// @returns        Number of functions, i.e. the size of the function index space.
+uint32_t fizzy_get_function_count(const FizzyModule* module);
+
+/// Get name of the function from the name section of the module.
+///
+/// @param  module      Pointer to module. Cannot be NULL.
+/// @param  func_idx    Function index.
+/// @returns            Null-terminated name of the function, or NULL if the module has no name for
+///                     the function. The string is owned by the module.
+const char* fizzy_get_function_name(const FizzyModule* module, uint32_t func_idx);
+
+/// Get code metrics of the function defined in the module.
+///
+/// @param  module      Pointer to module. Cannot be NULL.
+/// @param  func_idx    Function index.
+/// @param  out_metrics Pointer to output where the metrics will be stored. Cannot be NULL.
+/// @returns            true if the metrics were stored, false if the index is not valid or is the
+///                     index of an imported function.
+bool fizzy_get_function_metrics(
+    const FizzyModule* module, uint32_t func_idx, FizzyFunctionMetrics* out_metrics);
+
+/// Get opcodes of the instructions of the function defined in the module.
+///
+/// The instructions are in the order of the function body, including the final end instruction.
+///
+/// @param  module          Pointer to module. Cannot be NULL.
+/// @param  func_idx        Function index.
+/// @param  opcodes         Pointer to the array to write the opcodes into.
+///                         Can be NULL if opcodes_size is 0.
+/// @param  opcodes_size    Size of the opcodes array.
+/// @returns                The full number of instructions of the function, which can be greater
+///                         than opcodes_size. 0 if the index is not valid or is the index of an
+///                         imported function.
+size_t fizzy_get_function_opcodes(
+    const FizzyModule* module, uint32_t func_idx, uint8_t* opcodes, size_t opcodes_size);
+
+/// Get indices of the functions called directly by the function defined in the module.
+///
+/// The indices are in the order of the call instructions in the function body, including
+/// duplicates. The functions called indirectly through the table are not included.
+///
+/// @param  module          Pointer to module. Cannot be NULL.
+/// @param  func_idx        Function index.
+/// @param  callees         Pointer to the array to write the function indices into.
+///                         Can be NULL if callees_size is 0.
+/// @param  callees_size    Size of the callees array.
+/// @returns                The full number of call instructions of the function, which can be
+///                         greater than callees_size. 0 if the index is not valid or is the index
+///                         of an imported function.
+size_t fizzy_get_function_callees(
+    const FizzyModule* module, uint32_t func_idx, uint32_t* callees, size_t callees_size);
+
+/// Get limits of the table of the module, either defined or imported.
+///
+/// @param  module      Pointer to module. Cannot be NULL.
+/// @param  out_limits  Pointer to output where the limits will be stored. Cannot be NULL.
+/// @returns            true if the module has a table, false otherwise.
+bool fizzy_get_table_limits(const FizzyModule* module, FizzyLimits* out_limits);
+
+/// Get limits of the memory of the module, either defined or imported.
+///
+/// @param  module      Pointer to module. Cannot be NULL.
+/// @param  out_limits  Pointer to output where the limits will be stored. Cannot be NULL.
+/// @returns            true if the module has a memory, false otherwise.
+/// @note   In modules with multiple memories the limits of the memory 0 are returned.
+bool fizzy_get_memory_limits(const FizzyModule* module, FizzyLimits* out_limits);
+
+/// Get number of memories of the module, either defined or imported.
+///
+/// @param  module  Pointer to module. Cannot be NULL.
+/// @returns        Number of memories, more than 1 only in modules using the multi-memory proposal.
+uint32_t fizzy_get_memory_count(const FizzyModule* module);
+
+/// Get limits of a memory of the module.
+///
+/// @param  module      Pointer to module. Cannot be NULL.
+/// @param  memory_idx  Memory index, the imported memories come first. Behaviour is undefined if
+///                     index is not valid.
+/// @returns            Limits of the memory.
+FizzyLimits fizzy_get_memory_type(const FizzyModule* module, uint32_t memory_idx);
+
+/// Get the index of the start function of the module.
+///
+/// @param  module          Pointer to module. Cannot be NULL.
+/// @param  out_func_idx    Pointer to output where the function index will be stored.
+///                         Cannot be NULL.
+/// @returns                true if the module has a start function, false otherwise.
+bool fizzy_get_start_function(const FizzyModule* module, uint32_t* out_func_idx);
+
+/// Get number of imports defined in the module.
+///
+/// @param  module  Pointer to module. Cannot be NULL.
+/// @returns        Number of imports in the module.
+uint32_t fizzy_get_import_count(const FizzyModule* module);
+
+/// Get the import description defined in the module.
+///
+/// @param  module      Pointer to module. Cannot be NULL.
+/// @param  import_idx  Import index. Behaviour is undefined if index is not valid according
+///                     to module definition.
+/// @returns            Import description.
+FizzyImportDescription fizzy_get_import_description(
+    const FizzyModule* module, uint32_t import_idx);
+
+/// Get number of exports defined in the module.
+///
+/// @param  module  Pointer to module. Cannot be NULL.
+/// @returns        Number of exports in the module.
+uint32_t fizzy_get_export_count(const FizzyModule* module);
+
+/// Get the export description defined in the module.
+///
+/// @param  module      Pointer to module. Cannot be NULL.
+/// @param  export_idx  Export index. Behaviour is undefined if index is not valid according
+///                     to module definition.
+/// @returns            Export description.
+FizzyExportDescription fizzy_get_export_description(
+    const FizzyModule* module, uint32_t export_idx);
+
+/// Get number of globals defined in the module, not including imported globals.
+///
+/// @param  module  Pointer to module. Cannot be NULL.
+/// @returns        Number of globals defined in the module.
+uint32_t fizzy_get_global_count(const FizzyModule* module);
+
+/// Get the global defined in the module.
+///
+/// @param  module  Pointer to module. Cannot be NULL.
+/// @param  idx     Index of the global among the globals defined in the module, i.e. its global
+///                 index minus the number of imported globals. Behaviour is undefined if index
+///                 is not valid according to module definition.
+/// @returns        Global type and initializer.
+FizzyGlobal fizzy_get_global(const FizzyModule* module, uint32_t idx);
+
+/// Get number of data segments defined in the module.
+///
+/// @param  module  Pointer to module. Cannot be NULL.
+/// @returns        Number of data segments in the module.
+uint32_t fizzy_get_data_segment_count(const FizzyModule* module);
+
+/// Get the data segment defined in the module.
+///
+/// @param  module      Pointer to module. Cannot be NULL.
+/// @param  data_idx    Data segment index. Behaviour is undefined if index is not valid according
+///                     to module definition.
+/// @returns            Data segment.
+FizzyDataSegment fizzy_get_data_segment(const FizzyModule* module, uint32_t data_idx);
+
+/// Get number of element segments defined in the module.
+///
+/// @param  module  Pointer to module. Cannot be NULL.
+/// @returns        Number of element segments in the module.
+uint32_t fizzy_get_element_segment_count(const FizzyModule* module);
+
+/// Get the element segment defined in the module.
+///
+/// @param  module      Pointer to module. Cannot be NULL.
+/// @param  elem_idx    Element segment index. Behaviour is undefined if index is not valid
+///                     according to module definition.
+/// @returns            Element segment.
+FizzyElementSegment fizzy_get_element_segment(const FizzyModule* module, uint32_t elem_idx);
+
 /// Find index of exported function by name.
 ///
 /// @param  module          Pointer to module.
@@ -101,10 +699,219 @@ bool fizzy_find_exported_function(
 FizzyInstance* fizzy_instantiate(const FizzyModule* module,
     const FizzyExternalFunction* imported_functions, size_t imported_functions_size);
 
+/// Instantiate a module with imported functions, table, memory and globals.
+/// Takes ownership of module, i.e. @p module is invalidated after this call.
+///
+/// @param      module                   Pointer to module.
+/// @param      imported_functions       Pointer to the imported function array. Can be NULL iff
+///                                      imported_functions_size equals 0.
+/// @param      imported_functions_size  Size of the imported function array. Can be zero.
+/// @param      imported_table           Pointer to the imported table. Can be NULL iff module
+///                                      doesn't import a table.
+/// @param      imported_memory          Pointer to the imported memory. Can be NULL iff module
+///                                      doesn't import a memory.
+/// @param      imported_globals         Pointer to the imported globals array. Can be NULL iff
+///                                      imported_globals_size equals 0.
+/// @param      imported_globals_size    Size of the imported global array. Can be zero.
+/// @returns    non-NULL pointer to instance in case of success, NULL otherwise.
+///
+/// @note
+/// Function expects @a imported_functions and @a imported_globals to be in the order of imports
+/// defined in the module. The types of the table, memory and globals are validated against
+/// the module's imports, the types of the functions are not.
+FizzyInstance* fizzy_instantiate_with_imports(const FizzyModule* module,
+    const FizzyExternalFunction* imported_functions, size_t imported_functions_size,
+    const FizzyExternalTable* imported_table, const FizzyExternalMemory* imported_memory,
+    const FizzyExternalGlobal* imported_globals, size_t imported_globals_size);
+
+/// Instantiate a module with imports and instantiation options.
+/// Takes ownership of module, i.e. @p module is invalidated after this call.
+///
+/// @param      module                   Pointer to module.
+/// @param      imported_functions       Pointer to the imported function array. Can be NULL iff
+///                                      imported_functions_size equals 0.
+/// @param      imported_functions_size  Size of the imported function array. Can be zero.
+/// @param      imported_table           Pointer to the imported table. Can be NULL iff module
+///                                      doesn't import a table.
+/// @param      imported_memories        Pointer to the imported memory array. Can be NULL iff
+///                                      imported_memories_size equals 0.
+/// @param      imported_memories_size   Size of the imported memory array. Can be zero.
+/// @param      imported_globals         Pointer to the imported globals array. Can be NULL iff
+///                                      imported_globals_size equals 0.
+/// @param      imported_globals_size    Size of the imported global array. Can be zero.
+/// @param      options                  Pointer to the instantiation options. Can be NULL, in which
+///                                      case the defaults are used.
+/// @param      error                    Pointer to the output where the error is stored. Can be
+///                                      NULL.
+/// @returns    non-NULL pointer to instance in case of success, NULL otherwise.
+///
+/// @note
+/// Function expects @a imported_functions, @a imported_memories and @a imported_globals to be in
+/// the order of imports defined in the module. The types of the table, memories and globals are
+/// validated against the module's imports, the types of the functions are not.
+FizzyInstance* fizzy_instantiate_with_options(const FizzyModule* module,
+    const FizzyExternalFunction* imported_functions, size_t imported_functions_size,
+    const FizzyExternalTable* imported_table, const FizzyExternalMemory* imported_memories,
+    size_t imported_memories_size, const FizzyExternalGlobal* imported_globals,
+    size_t imported_globals_size, const FizzyInstantiateOptions* options, FizzyError* error);
+
+/// Run the start function of an instance instantiated with the start function deferred.
+///
+/// @param  instance    Pointer to module instance. Cannot be NULL.
+/// @returns            Result of execution of the start function, which has no result value.
+///                     The result is not trapped if there is no start function pending,
+///                     e.g. it has already been run.
+FizzyExecutionResult fizzy_run_start(FizzyInstance* instance);
+
+/// Create an independent copy of an instance, sharing its module.
+///
+/// The memory, the table and the globals defined in the module are copied, the imported ones are
+/// shared with the instance. The elements of the table referring to the functions of the instance
+/// refer to the functions of the copy instead. The hooks of the instance are not copied.
+///
+/// @param  instance    Pointer to module instance. Cannot be NULL.
+/// @param  error       Pointer to the output where the error is stored. Can be NULL.
+/// @returns            non-NULL pointer to the copy in case of success, to be freed with
+///                     fizzy_free_instance, NULL if memory allocation failed.
+///
+/// @note  The imported items must outlive the copy too.
+FizzyInstance* fizzy_duplicate_instance(const FizzyInstance* instance, FizzyError* error);
+
+/// Find exported table of an instance by name.
+///
+/// @param  instance    Pointer to instance.
+/// @param  name        The table name. NULL-terminated string. Cannot be NULL.
+/// @param  out_table   Pointer to output where the table will be stored. Cannot be NULL.
+/// @returns            true if table was found, false otherwise.
+///
+/// @note  The table is valid only as long as the instance is alive.
+bool fizzy_find_exported_table(
+    FizzyInstance* instance, const char* name, FizzyExternalTable* out_table);
+
+/// Find exported memory of an instance by name.
+///
+/// @param  instance    Pointer to instance.
+/// @param  name        The memory name. NULL-terminated string. Cannot be NULL.
+/// @param  out_memory  Pointer to output where the memory will be stored. Cannot be NULL.
+/// @returns            true if memory was found, false otherwise.
+///
+/// @note  The memory is valid only as long as the instance is alive.
+bool fizzy_find_exported_memory(
+    FizzyInstance* instance, const char* name, FizzyExternalMemory* out_memory);
+
+/// Find exported global of an instance by name.
+///
+/// @param  instance    Pointer to instance.
+/// @param  name        The global name. NULL-terminated string. Cannot be NULL.
+/// @param  out_global  Pointer to output where the global will be stored. Cannot be NULL.
+/// @returns            true if global was found, false otherwise.
+///
+/// @note  The global is valid only as long as the instance is alive.
+bool fizzy_find_exported_global(
+    FizzyInstance* instance, const char* name, FizzyExternalGlobal* out_global);
+
+/// Find exported function of an instance by name and create a reference to it.
+///
+/// @param  instance    Pointer to instance.
+/// @param  name        The function name. NULL-terminated string. Cannot be NULL.
+/// @returns            non-NULL pointer to the function reference, to be freed with
+///                     fizzy_free_funcref, if the function was found and memory allocation
+///                     succeeded, NULL otherwise.
+///
+/// @note  The function reference is valid only as long as the instance is alive.
+FizzyFuncRef* fizzy_find_exported_funcref(FizzyInstance* instance, const char* name);
+
+/// Create a reference to a host function, which can be set as an element of a table.
+///
+/// @param  type        Type of the function. The array of input types is copied.
+/// @param  function    Pointer to the function. Cannot be NULL.
+/// @param  context     Opaque pointer to execution context, that will be passed to the function.
+/// @returns            non-NULL pointer to the function reference, to be freed with
+///                     fizzy_free_funcref, in case of success, NULL if memory allocation failed.
+FizzyFuncRef* fizzy_create_funcref(
+    FizzyFunctionType type, FizzyExternalFn function, void* context);
+
+/// Get type of the function referenced.
+///
+/// @param  funcref     Pointer to the function reference. Cannot be NULL.
+/// @returns            Type of the function, valid as long as the function reference is alive.
+FizzyFunctionType fizzy_get_funcref_type(const FizzyFuncRef* funcref);
+
+/// Free resources associated with the function reference.
+/// If passed pointer is NULL, has no effect.
+void fizzy_free_funcref(FizzyFuncRef* funcref);
+
+/// Create a memory owned by the host, which can be imported by instances.
+///
+/// @param  pages   The initial size of the memory in pages (64 KiB). Must not exceed 65536.
+/// @returns        non-NULL pointer to memory filled with zeroes in case of success,
+///                 NULL otherwise.
+///
+/// @note  The memory must outlive the instances importing it.
+FizzyMemory* fizzy_create_memory(uint32_t pages);
+
+/// Free resources associated with the memory created with fizzy_create_memory.
+/// If passed pointer is NULL, has no effect.
+void fizzy_free_memory(FizzyMemory* memory);
+
+/// Get pointer to memory data.
+///
+/// @note  The pointer is invalidated when the memory is grown.
+uint8_t* fizzy_get_memory_data(FizzyMemory* memory);
+
+/// Get size of memory in bytes.
+size_t fizzy_get_memory_size(const FizzyMemory* memory);
+
+/// Get size of table in elements.
+uint32_t fizzy_get_table_size(const FizzyTable* table);
+
+/// Grow table by the number of null elements.
+///
+/// @param  table       Pointer to table. Cannot be NULL.
+/// @param  delta       The number of elements to add.
+/// @param  max_size    The maximum size of the table in elements, usually from its limits.
+/// @returns            false if the new size would exceed @p max_size or memory allocation failed,
+///                     true otherwise.
+bool fizzy_grow_table(FizzyTable* table, uint32_t delta, uint32_t max_size);
+
+/// Get element of table.
+///
+/// @param  table   Pointer to table. Cannot be NULL.
+/// @param  index   Index of the element. Behaviour is undefined if it is not less than the size
+///                 of the table.
+/// @returns        non-NULL pointer to a new reference to the function of the element, to be freed
+///                 with fizzy_free_funcref, or NULL if the element is null or memory allocation
+///                 failed.
+FizzyFuncRef* fizzy_get_table_element(const FizzyTable* table, uint32_t index);
+
+/// Set element of table.
+///
+/// @param  table       Pointer to table. Cannot be NULL.
+/// @param  index       Index of the element. Behaviour is undefined if it is not less than
+///                     the size of the table.
+/// @param  funcref     Pointer to the function reference to be copied into the element,
+///                     or NULL to make the element null.
+/// @returns            false if memory allocation failed, true otherwise.
+///
+/// @note  The function referenced must outlive the table, unless the element is set again.
+bool fizzy_set_table_element(FizzyTable* table, uint32_t index, const FizzyFuncRef* funcref);
+
 /// Free resources associated with the instance.
 /// If passed pointer is NULL, has no effect.
 void fizzy_free_instance(FizzyInstance* instance);
 
+/// Get pointer to module of an instance.
+///
+/// @note The returned pointer represents non-owning, "view"-access to the module and must not be
+///       passed to fizzy_free_module.
+const FizzyModule* fizzy_get_instance_module(FizzyInstance* instance);
+
+/// Get the maximum depth of nested calls of an instance.
+///
+/// @param  instance    Pointer to module instance. Cannot be NULL.
+/// @return             The call_stack_limit of the instantiation options, or the default limit.
+uint32_t fizzy_get_instance_call_stack_limit(const FizzyInstance* instance);
+
 /// Get pointer to memory of an instance.
 ///
 /// @returns Pointer to memory data or NULL in case instance doesn't have any memory.
@@ -117,6 +924,116 @@ uint8_t* fizzy_get_instance_memory_data(FizzyInstance* instance);
 /// @note    Function returns memory size regardless of whether memory is exported or not.
 size_t fizzy_get_instance_memory_size(FizzyInstance* instance);
 
+/// Get a memory of an instance.
+///
+/// @param  instance    Pointer to instance. Cannot be NULL.
+/// @param  memory_idx  Memory index, the imported memories come first.
+/// @param  out_memory  Pointer to output where the memory will be stored. Cannot be NULL.
+/// @returns            true if the instance has a memory of the index, either defined or imported,
+///                     false otherwise.
+/// @note    Function returns memory regardless of whether memory is exported or not.
+bool fizzy_get_instance_memory(
+    FizzyInstance* instance, uint32_t memory_idx, FizzyExternalMemory* out_memory);
+
+/// Get table of an instance.
+///
+/// @param  instance    Pointer to instance. Cannot be NULL.
+/// @param  out_table   Pointer to output where the table will be stored. Cannot be NULL.
+/// @returns            true if the instance has a table, either defined or imported,
+///                     false otherwise.
+/// @note    Function returns table regardless of whether table is exported or not.
+bool fizzy_get_instance_table(FizzyInstance* instance, FizzyExternalTable* out_table);
+
+/// Mark a range of a memory of an instance read-only for the instructions.
+///
+/// The store instructions writing any byte of the range trap, in all instances sharing the memory.
+/// The memory can still be written by the host.
+///
+/// @param  instance    Pointer to module instance. Cannot be NULL.
+/// @param  memory_idx  Memory index, the imported memories come first.
+/// @param  offset      Offset of the range in bytes.
+/// @param  size        Size of the range in bytes.
+/// @returns            false if the instance has no memory of the index, the range is out of its
+///                     bounds or memory allocation failed, true otherwise.
+bool fizzy_add_instance_memory_read_only_range(
+    FizzyInstance* instance, uint32_t memory_idx, uint32_t offset, uint32_t size);
+
+/// Make all of a memory of an instance writable by the instructions again.
+///
+/// @param  instance    Pointer to module instance. Cannot be NULL.
+/// @param  memory_idx  Memory index, the imported memories come first.
+void fizzy_clear_instance_memory_read_only_ranges(FizzyInstance* instance, uint32_t memory_idx);
+
+/// Set the hook called before each instruction executed in the instance.
+///
+/// A hook set by a host function during an execution applies only to the functions called after
+/// it is set.
+///
+/// @param instance     Pointer to module instance.
+/// @param hook         Pointer to the hook function. NULL removes the hook.
+/// @param context      Opaque pointer to hook context, that will be passed to hook.
+void fizzy_set_instruction_hook(FizzyInstance* instance, FizzyInstructionHook hook, void* context);
+
+/// Set the hook called after every `interval` instructions executed in the instance.
+///
+/// The instructions are counted across the executions, from the moment the hook is set. Calling
+/// the hook only periodically is much cheaper than an instruction hook. A hook set by a host
+/// function during an execution applies only to the functions called after it is set.
+///
+/// @param instance     Pointer to module instance. Cannot be NULL.
+/// @param interval     Number of executed instructions between the calls of the hook.
+///                     0 removes the hook.
+/// @param hook         Pointer to the hook function. NULL removes the hook.
+/// @param context      Opaque pointer to hook context, that will be passed to hook.
+void fizzy_set_periodic_hook(
+    FizzyInstance* instance, uint64_t interval, FizzyPeriodicHook hook, void* context);
+
+/// Set the hook called after any memory of the instance has been grown by `memory.grow`.
+///
+/// The hook is not called if the growth fails or if the memory size does not change.
+///
+/// @param instance     Pointer to module instance. Cannot be NULL.
+/// @param hook         Pointer to the hook function. NULL removes the hook.
+/// @param context      Opaque pointer to hook context, that will be passed to hook.
+void fizzy_set_memory_grow_hook(FizzyInstance* instance, FizzyMemoryGrowHook hook, void* context);
+
+/// Set the limiter consulted before any memory of the instance is grown by `memory.grow`.
+///
+/// The limiter is consulted only for the growths within the memory limits, which change
+/// the memory size. A denied growth makes `memory.grow` return -1.
+///
+/// @param instance     Pointer to module instance. Cannot be NULL.
+/// @param limiter      Pointer to the limiter function. NULL removes the limiter.
+/// @param context      Opaque pointer to limiter context, that will be passed to limiter.
+void fizzy_set_memory_grow_limiter(
+    FizzyInstance* instance, FizzyMemoryGrowLimiter limiter, void* context);
+
+/// Get the stack trace of the last execution, if it has trapped.
+///
+/// The stack trace misses the outermost functions if there was not enough memory to record them.
+///
+/// @param instance             Pointer to module instance. Cannot be NULL.
+/// @param func_indices         Pointer to the array to write indices of the functions active at
+///                             the moment of the trap into, the innermost function first.
+///                             Can be NULL if func_indices_size is 0.
+/// @param func_indices_size    Size of the func_indices array.
+/// @returns                    The full number of functions in the stack trace, which can be
+///                             greater than func_indices_size. 0 if the last execution has not
+///                             trapped.
+size_t fizzy_get_trap_stack_trace(
+    const FizzyInstance* instance, uint32_t* func_indices, size_t func_indices_size);
+
+/// Compute the hash of the state of an instance.
+///
+/// The SHA-256 hash covers the memory, the values of all globals (imported ones included) and
+/// the table of the instance. It is deterministic across platforms, so the states of instances
+/// can be compared without serializing them. The table elements contribute only their function
+/// types.
+///
+/// @param instance     Pointer to module instance. Cannot be NULL.
+/// @param hash         Pointer to the 32-byte array to write the hash into. Cannot be NULL.
+void fizzy_get_instance_state_hash(const FizzyInstance* instance, uint8_t* hash);
+
 /// Execute module function.
 ///
 /// @param instance     Pointer to module instance.
@@ -3,14 +3,25 @@
 // SPDX-License-Identifier: Apache-2.0
 
 #include "cxx20/bit.hpp"
+#include "exceptions.hpp"
 #include "execute.hpp"
 #include "instantiate.hpp"
+#include "limits.hpp"
 #include "parser.hpp"
+#include "trap_handler.hpp"
 #include <fizzy/fizzy.h>
+#include <algorithm>
+#include <cstring>
 #include <memory>
 
 namespace
 {
+static_assert(sizeof(FizzyValueType) == sizeof(fizzy::ValType));
+static_assert(FizzyValueTypeI32 == static_cast<FizzyValueType>(fizzy::ValType::i32));
+static_assert(FizzyValueTypeI64 == static_cast<FizzyValueType>(fizzy::ValType::i64));
+static_assert(FizzyValueTypeF32 == static_cast<FizzyValueType>(fizzy::ValType::f32));
+static_assert(FizzyValueTypeF64 == static_cast<FizzyValueType>(fizzy::ValType::f64));
+
 inline const FizzyModule* wrap(const fizzy::Module* module) noexcept
 {
     return reinterpret_cast<const FizzyModule*>(module);
@@ -21,6 +32,95 @@ inline const fizzy::Module* unwrap(const FizzyModule* module) noexcept
     return reinterpret_cast<const fizzy::Module*>(module);
 }
 
+inline FizzyValueType wrap(fizzy::ValType value_type) noexcept
+{
+    return static_cast<FizzyValueType>(value_type);
+}
+
+inline FizzyFunctionType wrap(const fizzy::FuncType& type) noexcept
+{
+    return {(type.outputs.empty() ? FizzyValueTypeVoid : wrap(type.outputs[0])),
+        (type.inputs.empty() ? nullptr : reinterpret_cast<const FizzyValueType*>(&type.inputs[0])),
+        type.inputs.size()};
+}
+
+inline fizzy::FuncType unwrap(const FizzyFunctionType& type)
+{
+    fizzy::FuncType func_type;
+    func_type.inputs.reserve(type.inputs_size);
+    for (size_t i = 0; i < type.inputs_size; ++i)
+        func_type.inputs.emplace_back(static_cast<fizzy::ValType>(type.inputs[i]));
+    if (type.output != FizzyValueTypeVoid)
+        func_type.outputs.emplace_back(static_cast<fizzy::ValType>(type.output));
+    return func_type;
+}
+
+inline FizzyExternalKind wrap(fizzy::ExternalKind kind) noexcept
+{
+    switch (kind)
+    {
+    case fizzy::ExternalKind::Function:
+        return FizzyExternalKindFunction;
+    case fizzy::ExternalKind::Table:
+        return FizzyExternalKindTable;
+    case fizzy::ExternalKind::Memory:
+        return FizzyExternalKindMemory;
+    case fizzy::ExternalKind::Global:
+        return FizzyExternalKindGlobal;
+    }
+    __builtin_unreachable();
+}
+
+inline FizzyExportDescription wrap(const fizzy::Export& exp) noexcept
+{
+    return {exp.name.c_str(), wrap(exp.kind), exp.index};
+}
+
+inline FizzyLimits wrap(const fizzy::Limits& limits) noexcept
+{
+    return {limits.min, limits.max.value_or(0), limits.max.has_value()};
+}
+
+inline FizzyGlobalType wrap(const fizzy::GlobalType& type) noexcept
+{
+    return {wrap(type.value_type), type.is_mutable};
+}
+
+inline fizzy::Limits unwrap(const FizzyLimits& limits) noexcept
+{
+    return {limits.min, limits.has_max ? std::optional<uint32_t>{limits.max} : std::nullopt};
+}
+
+inline fizzy::GlobalType unwrap(const FizzyGlobalType& type) noexcept
+{
+    return {static_cast<fizzy::ValType>(type.value_type), type.is_mutable};
+}
+
+inline FizzyImportDescription wrap(const fizzy::Import& import, const fizzy::Module& module) noexcept
+{
+    FizzyImportDescription c_import_description;
+    c_import_description.module = import.module.c_str();
+    c_import_description.name = import.name.c_str();
+    c_import_description.kind = wrap(import.kind);
+    switch (c_import_description.kind)
+    {
+    case FizzyExternalKindFunction:
+        c_import_description.desc.function_type =
+            wrap(module.typesec[import.desc.function_type_index]);
+        break;
+    case FizzyExternalKindTable:
+        c_import_description.desc.table_limits = wrap(import.desc.table.limits);
+        break;
+    case FizzyExternalKindMemory:
+        c_import_description.desc.memory_limits = wrap(import.desc.memory.limits);
+        break;
+    case FizzyExternalKindGlobal:
+        c_import_description.desc.global_type = wrap(import.desc.global);
+        break;
+    }
+    return c_import_description;
+}
+
 inline FizzyValue wrap(fizzy::Value value) noexcept
 {
     return fizzy::bit_cast<FizzyValue>(value);
@@ -41,6 +141,212 @@ inline const fizzy::Value* unwrap(const FizzyValue* values) noexcept
     return reinterpret_cast<const fizzy::Value*>(values);
 }
 
+inline FizzyConstantExpression wrap(const fizzy::ConstantExpression& expr) noexcept
+{
+    FizzyConstantExpression c_expr;
+    if (expr.kind == fizzy::ConstantExpression::Kind::Constant)
+    {
+        c_expr.kind = FizzyConstantExpressionKindConstant;
+        c_expr.value.constant = wrap(expr.value.constant);
+    }
+    else
+    {
+        c_expr.kind = FizzyConstantExpressionKindGlobalGet;
+        c_expr.value.global_index = expr.value.global_index;
+    }
+    return c_expr;
+}
+
+inline FizzyGlobal wrap(const fizzy::Global& global) noexcept
+{
+    return {wrap(global.type), wrap(global.expression)};
+}
+
+inline FizzyDataSegment wrap(const fizzy::Data& data) noexcept
+{
+    return {data.memory_index, wrap(data.offset),
+        (data.init.empty() ? nullptr : data.init.data()), data.init.size()};
+}
+
+inline FizzyElementSegment wrap(const fizzy::Element& element) noexcept
+{
+    // WebAssembly 1.0 allows only the table of index 0.
+    return {0, wrap(element.offset), (element.init.empty() ? nullptr : element.init.data()),
+        element.init.size()};
+}
+
+inline FizzyValue* wrap(fizzy::Value* value) noexcept
+{
+    return reinterpret_cast<FizzyValue*>(value);
+}
+
+inline fizzy::Value* unwrap(FizzyValue* value) noexcept
+{
+    return reinterpret_cast<fizzy::Value*>(value);
+}
+
+inline FizzyTable* wrap(fizzy::table_elements* table) noexcept
+{
+    return reinterpret_cast<FizzyTable*>(table);
+}
+
+inline fizzy::table_elements* unwrap(FizzyTable* table) noexcept
+{
+    return reinterpret_cast<fizzy::table_elements*>(table);
+}
+
+inline const fizzy::table_elements* unwrap(const FizzyTable* table) noexcept
+{
+    return reinterpret_cast<const fizzy::table_elements*>(table);
+}
+
+inline FizzyFuncRef* wrap(fizzy::ExternalFunction* funcref) noexcept
+{
+    return reinterpret_cast<FizzyFuncRef*>(funcref);
+}
+
+inline fizzy::ExternalFunction* unwrap(FizzyFuncRef* funcref) noexcept
+{
+    return reinterpret_cast<fizzy::ExternalFunction*>(funcref);
+}
+
+inline const fizzy::ExternalFunction* unwrap(const FizzyFuncRef* funcref) noexcept
+{
+    return reinterpret_cast<const fizzy::ExternalFunction*>(funcref);
+}
+
+inline FizzyMemory* wrap(fizzy::LinearMemory* memory) noexcept
+{
+    return reinterpret_cast<FizzyMemory*>(memory);
+}
+
+inline fizzy::LinearMemory* unwrap(FizzyMemory* memory) noexcept
+{
+    return reinterpret_cast<fizzy::LinearMemory*>(memory);
+}
+
+inline const fizzy::LinearMemory* unwrap(const FizzyMemory* memory) noexcept
+{
+    return reinterpret_cast<const fizzy::LinearMemory*>(memory);
+}
+
+inline FizzyExternalTable wrap(const fizzy::ExternalTable& external_table) noexcept
+{
+    return {wrap(external_table.table), wrap(external_table.limits)};
+}
+
+inline fizzy::ExternalTable unwrap(const FizzyExternalTable& external_table) noexcept
+{
+    return {unwrap(external_table.table), unwrap(external_table.limits)};
+}
+
+inline FizzyExternalMemory wrap(const fizzy::ExternalMemory& external_memory) noexcept
+{
+    return {wrap(external_memory.data), wrap(external_memory.limits)};
+}
+
+inline fizzy::ExternalMemory unwrap(const FizzyExternalMemory& external_memory) noexcept
+{
+    return {unwrap(external_memory.memory), unwrap(external_memory.limits)};
+}
+
+inline FizzyExternalGlobal wrap(const fizzy::ExternalGlobal& external_global) noexcept
+{
+    return {wrap(external_global.value), wrap(external_global.type)};
+}
+
+inline fizzy::ExternalGlobal unwrap(const FizzyExternalGlobal& external_global) noexcept
+{
+    return {unwrap(external_global.value), unwrap(external_global.type)};
+}
+
+inline fizzy::MemoryBacking unwrap(FizzyMemoryBacking memory_backing) noexcept
+{
+    switch (memory_backing)
+    {
+    case FizzyMemoryBackingMapped:
+        return fizzy::MemoryBacking::mapped;
+    case FizzyMemoryBackingGuarded:
+        return fizzy::MemoryBacking::guarded;
+    default:
+        return fizzy::MemoryBacking::heap;
+    }
+}
+
+inline fizzy::Allocator unwrap(const FizzyAllocator& allocator) noexcept
+{
+    fizzy::Allocator cpp_allocator;
+    cpp_allocator.allocate = allocator.allocate;
+    cpp_allocator.reallocate = allocator.reallocate;
+    cpp_allocator.deallocate = allocator.deallocate;
+    cpp_allocator.context = allocator.context;
+    return cpp_allocator;
+}
+
+inline void set_success(FizzyError* error) noexcept
+{
+    if (error == nullptr)
+        return;
+
+    error->code = FizzySuccess;
+    error->message[0] = '\0';
+}
+
+inline void set_error(FizzyErrorCode code, const char* message, FizzyError* error) noexcept
+{
+    if (error == nullptr)
+        return;
+
+    error->code = code;
+    const auto size = std::min(std::strlen(message), sizeof(error->message) - 1);
+    std::memcpy(error->message, message, size);
+    error->message[size] = '\0';
+}
+
+/// Stores the error of the exception being handled. Must be called from a catch block.
+inline void set_error_from_current_exception(FizzyError* error) noexcept
+{
+    try
+    {
+        throw;
+    }
+    catch (const fizzy::parser_error& e)
+    {
+        set_error(FizzyErrorMalformedModule, e.what(), error);
+    }
+    catch (const fizzy::validation_error& e)
+    {
+        set_error(FizzyErrorInvalidModule, e.what(), error);
+    }
+    catch (const fizzy::start_function_trapped& e)
+    {
+        set_error(FizzyErrorStartFunctionTrapped, e.what(), error);
+    }
+    catch (const std::bad_alloc&)
+    {
+        set_error(FizzyErrorMemoryAllocationFailed, "memory allocation failed", error);
+    }
+    catch (const std::exception& e)
+    {
+        set_error(FizzyErrorOther, e.what(), error);
+    }
+    catch (...)
+    {
+        set_error(FizzyErrorOther, "unknown error", error);
+    }
+}
+
+inline fizzy::ParserLimits unwrap(const FizzyParserLimits& limits) noexcept
+{
+    return {limits.max_function_count, limits.max_function_body_size, limits.max_local_count,
+        limits.max_nesting_depth, limits.max_allocation_size};
+}
+
+inline fizzy::Features unwrap(const FizzyFeatures& features) noexcept
+{
+    return {features.multi_memory};
+}
+
 inline FizzyInstance* wrap(fizzy::Instance* instance) noexcept
 {
     return reinterpret_cast<FizzyInstance*>(instance);
@@ -51,6 +357,11 @@ inline fizzy::Instance* unwrap(FizzyInstance* instance) noexcept
     return reinterpret_cast<fizzy::Instance*>(instance);
 }
 
+inline const fizzy::Instance* unwrap(const FizzyInstance* instance) noexcept
+{
+    return reinterpret_cast<const fizzy::Instance*>(instance);
+}
+
 inline FizzyExecutionResult wrap(const fizzy::ExecutionResult& result) noexcept
 {
     return {result.trapped, result.has_value, wrap(result.value)};
@@ -66,6 +377,13 @@ inline fizzy::ExecutionResult unwrap(const FizzyExecutionResult& result) noexcep
         return unwrap(result.value);
 }
 
+inline FizzyExecutionState wrap(const fizzy::ExecutionState& state) noexcept
+{
+    return {state.func_idx, state.instr_offset, static_cast<uint8_t>(state.opcode),
+        wrap(state.locals.data()), state.locals.size(), wrap(state.stack.data()),
+        state.stack.size(), state.depth, state.memory_offset, state.memory_idx};
+}
+
 inline auto unwrap(FizzyExternalFn func, void* context) noexcept
 {
     return [func, context](fizzy::Instance& instance, fizzy::span<const fizzy::Value> args,
@@ -77,6 +395,20 @@ inline auto unwrap(FizzyExternalFn func, void* context) noexcept
 }  // namespace
 
 extern "C" {
+const char* fizzy_get_version(void)
+{
+    return FIZZY_VERSION;
+}
+
+bool fizzy_has_guarded_memory(void)
+{
+#if defined(FIZZY_GUARDED_MEMORY)
+    return true;
+#else
+    return false;
+#endif
+}
+
 bool fizzy_validate(const uint8_t* wasm_binary, size_t wasm_binary_size)
 {
     try
@@ -91,14 +423,30 @@ bool fizzy_validate(const uint8_t* wasm_binary, size_t wasm_binary_size)
 }
 
 const FizzyModule* fizzy_parse(const uint8_t* wasm_binary, size_t wasm_binary_size)
+{
+    return fizzy_parse_with_limits(wasm_binary, wasm_binary_size, nullptr, nullptr);
+}
+
+const FizzyModule* fizzy_parse_with_limits(const uint8_t* wasm_binary, size_t wasm_binary_size,
+    const FizzyParserLimits* limits, FizzyError* error)
+{
+    return fizzy_parse_with_features(wasm_binary, wasm_binary_size, limits, nullptr, error);
+}
+
+const FizzyModule* fizzy_parse_with_features(const uint8_t* wasm_binary, size_t wasm_binary_size,
+    const FizzyParserLimits* limits, const FizzyFeatures* features, FizzyError* error)
 {
     try
     {
-        auto module = fizzy::parse({wasm_binary, wasm_binary_size});
+        auto module = fizzy::parse({wasm_binary, wasm_binary_size},
+            (limits != nullptr) ? unwrap(*limits) : fizzy::ParserLimits{},
+            (features != nullptr) ? unwrap(*features) : fizzy::Features{});
+        set_success(error);
         return wrap(module.release());
     }
     catch (...)
     {
+        set_error_from_current_exception(error);
         return nullptr;
     }
 }
@@ -108,6 +456,181 @@ void fizzy_free_module(const FizzyModule* module)
     delete unwrap(module);
 }
 
+const FizzyModule* fizzy_clone_module(const FizzyModule* module)
+{
+    try
+    {
+        auto clone = std::make_unique<fizzy::Module>(*unwrap(module));
+        return wrap(clone.release());
+    }
+    catch (...)
+    {
+        return nullptr;
+    }
+}
+
+FizzyFunctionType fizzy_get_function_type(const FizzyModule* module, uint32_t func_idx)
+{
+    return wrap(unwrap(module)->get_function_type(func_idx));
+}
+
+uint32_t fizzy_get_type_count(const FizzyModule* module)
+{
+    return static_cast<uint32_t>(unwrap(module)->typesec.size());
+}
+
+FizzyFunctionType fizzy_get_type(const FizzyModule* module, uint32_t type_idx)
+{
+    return wrap(unwrap(module)->typesec[type_idx]);
+}
+
+uint32_t fizzy_get_function_count(const FizzyModule* module)
+{
+    return static_cast<uint32_t>(unwrap(module)->get_function_count());
+}
+
+bool fizzy_get_function_metrics(
+    const FizzyModule* module, uint32_t func_idx, FizzyFunctionMetrics* out_metrics)
+{
+    const auto& m = *unwrap(module);
+    const auto num_imported_functions = m.imported_function_types.size();
+    if (func_idx < num_imported_functions || func_idx >= m.get_function_count())
+        return false;
+
+    const auto& code = m.get_code(func_idx);
+    *out_metrics = {code.body_size, code.local_count, static_cast<uint32_t>(code.max_stack_height)};
+    return true;
+}
+
+size_t fizzy_get_function_opcodes(
+    const FizzyModule* module, uint32_t func_idx, uint8_t* opcodes, size_t opcodes_size)
+{
+    const auto& m = *unwrap(module);
+    if (func_idx < m.imported_function_types.size() || func_idx >= m.get_function_count())
+        return 0;
+
+    const auto& instructions = m.get_code(func_idx).instructions;
+    std::transform(instructions.begin(),
+        instructions.begin() +
+            static_cast<ptrdiff_t>(std::min(instructions.size(), opcodes_size)),
+        opcodes, [](fizzy::Instr instr) noexcept { return static_cast<uint8_t>(instr); });
+    return instructions.size();
+}
+
+size_t fizzy_get_function_callees(
+    const FizzyModule* module, uint32_t func_idx, uint32_t* callees, size_t callees_size)
+{
+    const auto& m = *unwrap(module);
+    if (func_idx < m.imported_function_types.size() || func_idx >= m.get_function_count())
+        return 0;
+
+    const auto& code_callees = m.get_code(func_idx).callees;
+    std::copy_n(code_callees.begin(), std::min(code_callees.size(), callees_size), callees);
+    return code_callees.size();
+}
+
+bool fizzy_get_table_limits(const FizzyModule* module, FizzyLimits* out_limits)
+{
+    const auto& m = *unwrap(module);
+    if (!m.has_table())
+        return false;
+
+    const auto& table = !m.tablesec.empty() ? m.tablesec[0] : m.imported_table_types[0];
+    *out_limits = wrap(table.limits);
+    return true;
+}
+
+bool fizzy_get_memory_limits(const FizzyModule* module, FizzyLimits* out_limits)
+{
+    const auto& m = *unwrap(module);
+    if (!m.has_memory())
+        return false;
+
+    const auto& memory = !m.memorysec.empty() ? m.memorysec[0] : m.imported_memory_types[0];
+    *out_limits = wrap(memory.limits);
+    return true;
+}
+
+uint32_t fizzy_get_memory_count(const FizzyModule* module)
+{
+    return static_cast<uint32_t>(unwrap(module)->get_memory_count());
+}
+
+FizzyLimits fizzy_get_memory_type(const FizzyModule* module, uint32_t memory_idx)
+{
+    return wrap(unwrap(module)->get_memory_limits(memory_idx));
+}
+
+bool fizzy_get_start_function(const FizzyModule* module, uint32_t* out_func_idx)
+{
+    const auto& m = *unwrap(module);
+    if (!m.startfunc)
+        return false;
+
+    *out_func_idx = *m.startfunc;
+    return true;
+}
+
+uint32_t fizzy_get_import_count(const FizzyModule* module)
+{
+    return static_cast<uint32_t>(unwrap(module)->importsec.size());
+}
+
+FizzyImportDescription fizzy_get_import_description(
+    const FizzyModule* module, uint32_t import_idx)
+{
+    const auto& m = *unwrap(module);
+    return wrap(m.importsec[import_idx], m);
+}
+
+uint32_t fizzy_get_export_count(const FizzyModule* module)
+{
+    return static_cast<uint32_t>(unwrap(module)->exportsec.size());
+}
+
+FizzyExportDescription fizzy_get_export_description(
+    const FizzyModule* module, uint32_t export_idx)
+{
+    return wrap(unwrap(module)->exportsec[export_idx]);
+}
+
+uint32_t fizzy_get_global_count(const FizzyModule* module)
+{
+    return static_cast<uint32_t>(unwrap(module)->globalsec.size());
+}
+
+FizzyGlobal fizzy_get_global(const FizzyModule* module, uint32_t idx)
+{
+    return wrap(unwrap(module)->globalsec[idx]);
+}
+
+uint32_t fizzy_get_data_segment_count(const FizzyModule* module)
+{
+    return static_cast<uint32_t>(unwrap(module)->datasec.size());
+}
+
+FizzyDataSegment fizzy_get_data_segment(const FizzyModule* module, uint32_t data_idx)
+{
+    return wrap(unwrap(module)->datasec[data_idx]);
+}
+
+uint32_t fizzy_get_element_segment_count(const FizzyModule* module)
+{
+    return static_cast<uint32_t>(unwrap(module)->elementsec.size());
+}
+
+FizzyElementSegment fizzy_get_element_segment(const FizzyModule* module, uint32_t elem_idx)
+{
+    return wrap(unwrap(module)->elementsec[elem_idx]);
+}
+
+const char* fizzy_get_function_name(const FizzyModule* module, uint32_t func_idx)
+{
+    const auto& function_names = unwrap(module)->function_names;
+    const auto it = function_names.find(func_idx);
+    return it != function_names.end() ? it->second.c_str() : nullptr;
+}
+
 bool fizzy_find_exported_function(
     const FizzyModule* module, const char* name, uint32_t* out_func_idx)
 {
@@ -121,6 +644,26 @@ bool fizzy_find_exported_function(
 
 FizzyInstance* fizzy_instantiate(const FizzyModule* module,
     const FizzyExternalFunction* imported_functions, size_t imported_functions_size)
+{
+    return fizzy_instantiate_with_imports(
+        module, imported_functions, imported_functions_size, nullptr, nullptr, nullptr, 0);
+}
+
+FizzyInstance* fizzy_instantiate_with_imports(const FizzyModule* module,
+    const FizzyExternalFunction* imported_functions, size_t imported_functions_size,
+    const FizzyExternalTable* imported_table, const FizzyExternalMemory* imported_memory,
+    const FizzyExternalGlobal* imported_globals, size_t imported_globals_size)
+{
+    return fizzy_instantiate_with_options(module, imported_functions, imported_functions_size,
+        imported_table, imported_memory, imported_memory != nullptr ? 1 : 0, imported_globals,
+        imported_globals_size, nullptr, nullptr);
+}
+
+FizzyInstance* fizzy_instantiate_with_options(const FizzyModule* module,
+    const FizzyExternalFunction* imported_functions, size_t imported_functions_size,
+    const FizzyExternalTable* imported_table, const FizzyExternalMemory* imported_memories,
+    size_t imported_memories_size, const FizzyExternalGlobal* imported_globals,
+    size_t imported_globals_size, const FizzyInstantiateOptions* options, FizzyError* error)
 {
     try
     {
@@ -139,22 +682,253 @@ FizzyInstance* fizzy_instantiate(const FizzyModule* module,
                 fizzy::ExternalFunction{std::move(func), std::move(func_type)};
         }
 
-        auto instance = fizzy::instantiate(
-            std::unique_ptr<const fizzy::Module>(unwrap(module)), std::move(functions));
+        std::vector<fizzy::ExternalTable> tables;
+        if (imported_table != nullptr)
+            tables.emplace_back(unwrap(*imported_table));
+
+        std::vector<fizzy::ExternalMemory> memories(imported_memories_size);
+        std::transform(imported_memories, imported_memories + imported_memories_size,
+            memories.begin(), [](const FizzyExternalMemory& memory) { return unwrap(memory); });
 
+        std::vector<fizzy::ExternalGlobal> globals(imported_globals_size);
+        std::transform(imported_globals, imported_globals + imported_globals_size, globals.begin(),
+            [](const FizzyExternalGlobal& global) { return unwrap(global); });
+
+        auto memory_backing = fizzy::MemoryBacking::heap;
+        fizzy::Allocator memory_allocator;
+        bool defer_start = false;
+        uint32_t memory_pages_limit = fizzy::DefaultMemoryPagesLimit;
+        int call_stack_limit = fizzy::CallStackLimit;
+        bool trap_stack_trace_enabled = true;
+        if (options != nullptr)
+        {
+            memory_backing = unwrap(options->memory_backing);
+            if (options->memory_allocator != nullptr)
+                memory_allocator = unwrap(*options->memory_allocator);
+            defer_start = options->defer_start;
+            if (options->memory_pages_limit != 0)
+                memory_pages_limit = options->memory_pages_limit;
+            if (options->call_stack_limit != 0)
+                call_stack_limit = static_cast<int>(
+                    std::min(options->call_stack_limit, uint32_t{fizzy::CallStackLimit}));
+            trap_stack_trace_enabled = !options->disable_trap_stack_trace;
+        }
+
+        // The start function is deferred to run it with the call stack limit of the instance.
+        auto instance = fizzy::instantiate(std::unique_ptr<const fizzy::Module>(unwrap(module)),
+            std::move(functions), std::move(tables), std::move(memories), std::move(globals),
+            memory_pages_limit, memory_backing, memory_allocator, true);
+        instance->call_stack_limit = call_stack_limit;
+        instance->trap_stack_trace_enabled = trap_stack_trace_enabled;
+        if (!defer_start && fizzy::run_start(*instance).trapped)
+            throw fizzy::start_function_trapped{"start function failed to execute"};
+
+        set_success(error);
         return wrap(instance.release());
     }
     catch (...)
+    {
+        set_error_from_current_exception(error);
+        return nullptr;
+    }
+}
+
+FizzyExecutionResult fizzy_run_start(FizzyInstance* instance)
+{
+    return wrap(fizzy::run_start(*unwrap(instance)));
+}
+
+FizzyInstance* fizzy_duplicate_instance(const FizzyInstance* instance, FizzyError* error)
+{
+    try
+    {
+        auto copy = fizzy::duplicate(*unwrap(instance));
+        set_success(error);
+        return wrap(copy.release());
+    }
+    catch (...)
+    {
+        set_error_from_current_exception(error);
+        return nullptr;
+    }
+}
+
+bool fizzy_find_exported_table(
+    FizzyInstance* instance, const char* name, FizzyExternalTable* out_table)
+{
+    const auto optional_external_table = fizzy::find_exported_table(*unwrap(instance), name);
+    if (!optional_external_table)
+        return false;
+
+    *out_table = wrap(*optional_external_table);
+    return true;
+}
+
+bool fizzy_find_exported_memory(
+    FizzyInstance* instance, const char* name, FizzyExternalMemory* out_memory)
+{
+    const auto optional_external_memory = fizzy::find_exported_memory(*unwrap(instance), name);
+    if (!optional_external_memory)
+        return false;
+
+    *out_memory = wrap(*optional_external_memory);
+    return true;
+}
+
+bool fizzy_find_exported_global(
+    FizzyInstance* instance, const char* name, FizzyExternalGlobal* out_global)
+{
+    const auto optional_external_global = fizzy::find_exported_global(*unwrap(instance), name);
+    if (!optional_external_global)
+        return false;
+
+    *out_global = wrap(*optional_external_global);
+    return true;
+}
+
+FizzyFuncRef* fizzy_find_exported_funcref(FizzyInstance* instance, const char* name)
+{
+    try
+    {
+        auto optional_func = fizzy::find_exported_function(*unwrap(instance), name);
+        if (!optional_func)
+            return nullptr;
+
+        return wrap(new fizzy::ExternalFunction(std::move(*optional_func)));
+    }
+    catch (...)
+    {
+        return nullptr;
+    }
+}
+
+FizzyFuncRef* fizzy_create_funcref(
+    FizzyFunctionType type, FizzyExternalFn function, void* context)
+{
+    try
+    {
+        return wrap(new fizzy::ExternalFunction{unwrap(function, context), unwrap(type)});
+    }
+    catch (...)
+    {
+        return nullptr;
+    }
+}
+
+FizzyFunctionType fizzy_get_funcref_type(const FizzyFuncRef* funcref)
+{
+    return wrap(unwrap(funcref)->type);
+}
+
+void fizzy_free_funcref(FizzyFuncRef* funcref)
+{
+    delete unwrap(funcref);
+}
+
+FizzyMemory* fizzy_create_memory(uint32_t pages)
+{
+    if (pages > fizzy::MemoryPagesValidationLimit)
+        return nullptr;
+
+    try
+    {
+        return wrap(new fizzy::LinearMemory(size_t{pages} * fizzy::PageSize));
+    }
+    catch (...)
+    {
+        return nullptr;
+    }
+}
+
+void fizzy_free_memory(FizzyMemory* memory)
+{
+    delete unwrap(memory);
+}
+
+uint8_t* fizzy_get_memory_data(FizzyMemory* memory)
+{
+    return unwrap(memory)->data();
+}
+
+size_t fizzy_get_memory_size(const FizzyMemory* memory)
+{
+    return unwrap(memory)->size();
+}
+
+uint32_t fizzy_get_table_size(const FizzyTable* table)
+{
+    return static_cast<uint32_t>(unwrap(table)->size());
+}
+
+bool fizzy_grow_table(FizzyTable* table, uint32_t delta, uint32_t max_size)
+{
+    auto& elements = *unwrap(table);
+    const auto new_size = uint64_t{elements.size()} + delta;
+    if (new_size > max_size)
+        return false;
+
+    try
+    {
+        elements.resize(static_cast<size_t>(new_size));
+        return true;
+    }
+    catch (...)
+    {
+        return false;
+    }
+}
+
+FizzyFuncRef* fizzy_get_table_element(const FizzyTable* table, uint32_t index)
+{
+    const auto& element = (*unwrap(table))[index];
+    if (!element.has_value())
+        return nullptr;
+
+    try
+    {
+        return wrap(new fizzy::ExternalFunction(*element));
+    }
+    catch (...)
     {
         return nullptr;
     }
 }
 
+bool fizzy_set_table_element(FizzyTable* table, uint32_t index, const FizzyFuncRef* funcref)
+{
+    auto& element = (*unwrap(table))[index];
+    if (funcref == nullptr)
+    {
+        element.reset();
+        return true;
+    }
+
+    try
+    {
+        element = *unwrap(funcref);
+        return true;
+    }
+    catch (...)
+    {
+        return false;
+    }
+}
+
 void fizzy_free_instance(FizzyInstance* instance)
 {
     delete unwrap(instance);
 }
 
+const FizzyModule* fizzy_get_instance_module(FizzyInstance* instance)
+{
+    return wrap(unwrap(instance)->module.get());
+}
+
+uint32_t fizzy_get_instance_call_stack_limit(const FizzyInstance* instance)
+{
+    return static_cast<uint32_t>(unwrap(instance)->call_stack_limit);
+}
+
 uint8_t* fizzy_get_instance_memory_data(FizzyInstance* instance)
 {
     auto& memory = unwrap(instance)->memory;
@@ -173,6 +947,135 @@ size_t fizzy_get_instance_memory_size(FizzyInstance* instance)
     return memory->size();
 }
 
+bool fizzy_get_instance_memory(
+    FizzyInstance* instance, uint32_t memory_idx, FizzyExternalMemory* out_memory)
+{
+    const auto& instance_ref = *unwrap(instance);
+    if (memory_idx >= instance_ref.module->get_memory_count())
+        return false;
+
+    const auto& limits = memory_idx == 0 ? instance_ref.memory_limits :
+                                           instance_ref.memories[memory_idx - 1].limits;
+    *out_memory = wrap(fizzy::ExternalMemory{instance_ref.get_memory(memory_idx), limits});
+    return true;
+}
+
+bool fizzy_get_instance_table(FizzyInstance* instance, FizzyExternalTable* out_table)
+{
+    const auto& instance_ref = *unwrap(instance);
+    if (!instance_ref.table)
+        return false;
+
+    *out_table = wrap(fizzy::ExternalTable{instance_ref.table.get(), instance_ref.table_limits});
+    return true;
+}
+
+bool fizzy_add_instance_memory_read_only_range(
+    FizzyInstance* instance, uint32_t memory_idx, uint32_t offset, uint32_t size)
+{
+    auto* const memory = unwrap(instance)->get_memory(memory_idx);
+    if (memory == nullptr || uint64_t{offset} + size > memory->size())
+        return false;
+
+    try
+    {
+        memory->add_read_only_range(offset, size);
+        return true;
+    }
+    catch (...)
+    {
+        return false;
+    }
+}
+
+void fizzy_clear_instance_memory_read_only_ranges(FizzyInstance* instance, uint32_t memory_idx)
+{
+    auto* const memory = unwrap(instance)->get_memory(memory_idx);
+    if (memory != nullptr)
+        memory->clear_read_only_ranges();
+}
+
+void fizzy_set_instruction_hook(FizzyInstance* instance, FizzyInstructionHook hook, void* context)
+{
+    if (hook == nullptr)
+    {
+        unwrap(instance)->instruction_hook = nullptr;
+        return;
+    }
+
+    unwrap(instance)->instruction_hook = [hook, context](fizzy::Instance& _instance,
+                                             const fizzy::ExecutionState& state) noexcept {
+        const auto cstate = wrap(state);
+        return hook(context, wrap(&_instance), &cstate);
+    };
+}
+
+void fizzy_set_periodic_hook(
+    FizzyInstance* instance, uint64_t interval, FizzyPeriodicHook hook, void* context)
+{
+    if (hook == nullptr || interval == 0)
+    {
+        unwrap(instance)->periodic_hook = nullptr;
+        unwrap(instance)->periodic_hook_interval = 0;
+        unwrap(instance)->periodic_hook_countdown = 0;
+        return;
+    }
+
+    unwrap(instance)->periodic_hook = [hook, context](fizzy::Instance& _instance) noexcept {
+        return hook(context, wrap(&_instance));
+    };
+    unwrap(instance)->periodic_hook_interval = interval;
+    unwrap(instance)->periodic_hook_countdown = interval;
+}
+
+void fizzy_set_memory_grow_hook(FizzyInstance* instance, FizzyMemoryGrowHook hook, void* context)
+{
+    if (hook == nullptr)
+    {
+        unwrap(instance)->memory_grow_hook = nullptr;
+        return;
+    }
+
+    unwrap(instance)->memory_grow_hook = [hook, context](fizzy::Instance& _instance,
+                                             uint32_t memory_idx, uint32_t old_pages,
+                                             uint32_t new_pages) noexcept {
+        hook(context, wrap(&_instance), memory_idx, old_pages, new_pages);
+    };
+}
+
+void fizzy_set_memory_grow_limiter(
+    FizzyInstance* instance, FizzyMemoryGrowLimiter limiter, void* context)
+{
+    if (limiter == nullptr)
+    {
+        unwrap(instance)->memory_grow_limiter = nullptr;
+        return;
+    }
+
+    unwrap(instance)->memory_grow_limiter = [limiter, context](fizzy::Instance& _instance,
+                                                uint32_t memory_idx, uint32_t current_pages,
+                                                uint32_t desired_pages) noexcept {
+        const auto max_pages = memory_idx == 0 ? _instance.memory_pages_limit :
+                                                 _instance.memories[memory_idx - 1].pages_limit;
+        return limiter(context, wrap(&_instance), memory_idx, current_pages, desired_pages,
+            max_pages);
+    };
+}
+
+size_t fizzy_get_trap_stack_trace(
+    const FizzyInstance* instance, uint32_t* func_indices, size_t func_indices_size)
+{
+    const auto& trace = unwrap(instance)->trap_stack_trace;
+    std::copy_n(trace.begin(), std::min(trace.size(), func_indices_size), func_indices);
+    return trace.size();
+}
+
+void fizzy_get_instance_state_hash(const FizzyInstance* instance, uint8_t* hash)
+{
+    const auto result = fizzy::state_hash(*unwrap(instance));
+    std::copy(result.begin(), result.end(), hash);
+}
+
 FizzyExecutionResult fizzy_execute(
     FizzyInstance* instance, uint32_t func_idx, const FizzyValue* args, int depth)
 {
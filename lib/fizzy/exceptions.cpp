@@ -9,4 +9,5 @@ namespace fizzy
 parser_error::~parser_error() noexcept = default;
 validation_error::~validation_error() noexcept = default;
 instantiate_error::~instantiate_error() noexcept = default;
+start_function_trapped::~start_function_trapped() noexcept = default;
 }  // namespace fizzy
@@ -29,4 +29,11 @@ struct instantiate_error : public std::runtime_error
     ~instantiate_error() noexcept override;
 };
 
+struct start_function_trapped : public instantiate_error
+{
+    using instantiate_error::instantiate_error;
+
+    ~start_function_trapped() noexcept override;
+};
+
 }  // namespace fizzy
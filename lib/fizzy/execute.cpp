@@ -4,13 +4,16 @@
 
 #include "execute.hpp"
 #include "cxx20/bit.hpp"
+#include "le.hpp"
 #include "stack.hpp"
+#include "trap_handler.hpp"
 #include "trunc_boundaries.hpp"
 #include "types.hpp"
 #include <algorithm>
 #include <cassert>
 #include <cmath>
 #include <cstring>
+#include <optional>
 #include <stack>
 
 namespace fizzy
@@ -35,17 +38,15 @@ inline T read(const uint8_t*& input) noexcept
 }
 
 template <typename T>
-inline void store(bytes& input, size_t offset, T value) noexcept
+inline void store(LinearMemory& input, size_t offset, T value) noexcept
 {
-    __builtin_memcpy(input.data() + offset, &value, sizeof(value));
+    le::store(input.data() + offset, value);
 }
 
 template <typename T>
 inline T load(bytes_view input, size_t offset) noexcept
 {
-    T ret;
-    __builtin_memcpy(&ret, input.data() + offset, sizeof(ret));
-    return ret;
+    return le::load<T>(input.data() + offset);
 }
 
 template <typename DstT, typename SrcT>
@@ -62,18 +63,39 @@ inline constexpr DstT extend(SrcT in) noexcept
         return DstT{in};
 }
 
-template <typename DstT, typename SrcT = DstT>
-inline bool load_from_memory(
-    bytes_view memory, OperandStack& stack, const uint8_t*& immediates) noexcept
+/// Returns the memory accessed by a memory instruction. In the modules with multiple memories
+/// reads the memory index immediate, the accesses of the memories other than the memory 0
+/// are always bounds-checked.
+template <bool MultiMemory>
+inline LinearMemory& read_memory(
+    const Instance& instance, const uint8_t*& immediates, bool& bounds_check) noexcept
+{
+    if constexpr (MultiMemory)
+    {
+        const auto memory_idx = read<uint32_t>(immediates);
+        if (memory_idx != 0)
+        {
+            bounds_check = true;
+            return *instance.memories[memory_idx - 1].memory;
+        }
+    }
+    return *instance.memory;
+}
+
+template <bool MultiMemory, typename DstT, typename SrcT = DstT>
+inline bool load_from_memory(const Instance& instance, OperandStack& stack,
+    const uint8_t*& immediates, bool bounds_check) noexcept
 {
     const auto address = stack.top().as<uint32_t>();
     // NOTE: alignment is dropped by the parser
     const auto offset = read<uint32_t>(immediates);
+    const bytes_view memory = read_memory<MultiMemory>(instance, immediates, bounds_check);
     // Addressing is 32-bit, but we keep the value as 64-bit to detect overflows.
-    if ((uint64_t{address} + offset + sizeof(SrcT)) > memory.size())
+    const auto effective_address = uint64_t{address} + offset;
+    if (bounds_check && (effective_address + sizeof(SrcT)) > memory.size())
         return false;
 
-    const auto ret = load<SrcT>(memory, address + offset);
+    const auto ret = load<SrcT>(memory, static_cast<size_t>(effective_address));
     stack.top() = extend<DstT>(ret);
     return true;
 }
@@ -92,19 +114,23 @@ inline constexpr DstT shrink(Value value) noexcept
     }
 }
 
-template <typename DstT>
-inline bool store_into_memory(
-    bytes& memory, OperandStack& stack, const uint8_t*& immediates) noexcept
+template <bool MultiMemory, typename DstT>
+inline bool store_into_memory(const Instance& instance, OperandStack& stack,
+    const uint8_t*& immediates, bool bounds_check) noexcept
 {
     const auto value = shrink<DstT>(stack.pop());
     const auto address = stack.pop().as<uint32_t>();
     // NOTE: alignment is dropped by the parser
     const auto offset = read<uint32_t>(immediates);
+    auto& memory = read_memory<MultiMemory>(instance, immediates, bounds_check);
     // Addressing is 32-bit, but we keep the value as 64-bit to detect overflows.
-    if ((uint64_t{address} + offset + sizeof(DstT)) > memory.size())
+    const auto effective_address = uint64_t{address} + offset;
+    if (bounds_check && (effective_address + sizeof(DstT)) > memory.size())
+        return false;
+    if (memory.is_read_only(effective_address, sizeof(DstT)))
         return false;
 
-    store<DstT>(memory, address + offset, value);
+    store<DstT>(memory, static_cast<size_t>(effective_address), value);
     return true;
 }
 
@@ -514,21 +540,29 @@ inline bool invoke_function(const FuncType& func_type, uint32_t func_idx, Instan
     };
     return invoke_function(func_type, func, instance, stack, depth);
 }
-}  // namespace
 
-ExecutionResult execute(Instance& instance, FuncIdx func_idx, const Value* args, int depth)
+/// Records the function in the trap stack trace, if enabled. The trace is truncated when
+/// there is no memory to extend it.
+inline void record_trap_frame(Instance& instance, FuncIdx func_idx) noexcept
 {
-    assert(depth >= 0);
-    if (depth > CallStackLimit)
-        return Trap;
-
-    const auto& func_type = instance.module->get_function_type(func_idx);
-
-    assert(instance.module->imported_function_types.size() == instance.imported_functions.size());
-    if (func_idx < instance.imported_functions.size())
-        return instance.imported_functions[func_idx].function(
-            instance, {args, func_type.inputs.size()}, depth);
+    if (!instance.trap_stack_trace_enabled)
+        return;
+    try
+    {
+        instance.trap_stack_trace.push_back(func_idx);
+    }
+    catch (const std::bad_alloc&)
+    {
+    }
+}
 
+/// Executes the code of a function defined in the module.
+/// The instrumented variant invokes the instruction and periodic hooks, the multi-memory variant
+/// reads the memory index immediates of the memory instructions.
+template <bool Instrumented, bool MultiMemory>
+ExecutionResult execute_code(
+    Instance& instance, FuncIdx func_idx, const FuncType& func_type, const Value* args, int depth)
+{
     const auto& code = instance.module->get_code(func_idx);
     auto* const memory = instance.memory.get();
 
@@ -538,8 +572,50 @@ ExecutionResult execute(Instance& instance, FuncIdx func_idx, const Value* args,
     const Instr* pc = code.instructions.data();
     const uint8_t* immediates = code.immediates.data();
 
+    // The accesses of guarded memory are not bounds-checked,
+    // instead the faults of the out-of-bounds accesses jump back here and trap.
+    const bool bounds_check = memory == nullptr || memory->backing() != MemoryBacking::guarded;
+#if defined(FIZZY_GUARDED_MEMORY)
+    std::optional<TrapScope> trap_scope;
+    if (!bounds_check)
+    {
+        trap_scope.emplace(memory);
+        if (sigsetjmp(trap_scope->env, 0) != 0)
+            goto trap;
+    }
+#endif
+
     while (true)
     {
+        if constexpr (Instrumented)
+        {
+            if (instance.instruction_hook)
+            {
+                // The offset and the memory index are the immediates of the load and store
+                // instructions.
+                const auto is_memory_access =
+                    *pc >= Instr::i32_load && *pc <= Instr::i64_store32;
+                auto memarg_immediates = immediates;
+                const auto memory_offset =
+                    is_memory_access ? read<uint32_t>(memarg_immediates) : 0;
+                const auto memory_idx =
+                    MultiMemory && is_memory_access ? read<uint32_t>(memarg_immediates) : 0;
+                const ExecutionState state{func_idx,
+                    static_cast<uint32_t>(pc - code.instructions.data()), *pc,
+                    {stack.locals(), stack.num_locals()}, {stack.rbegin(), stack.size()}, depth,
+                    memory_offset, memory_idx};
+                if (!instance.instruction_hook(instance, state))
+                    goto trap;
+            }
+
+            if (instance.periodic_hook_countdown != 0 && --instance.periodic_hook_countdown == 0)
+            {
+                instance.periodic_hook_countdown = instance.periodic_hook_interval;
+                if (!instance.periodic_hook(instance))
+                    goto trap;
+            }
+        }
+
         const auto instruction = *pc++;
         switch (instruction)
         {
@@ -717,150 +793,184 @@ ExecutionResult execute(Instance& instance, FuncIdx func_idx, const Value* args,
         }
         case Instr::i32_load:
         {
-            if (!load_from_memory<uint32_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint32_t>(instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i64_load:
         {
-            if (!load_from_memory<uint64_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint64_t>(instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::f32_load:
         {
-            if (!load_from_memory<float>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, float>(instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::f64_load:
         {
-            if (!load_from_memory<double>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, double>(instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i32_load8_s:
         {
-            if (!load_from_memory<uint32_t, int8_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint32_t, int8_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i32_load8_u:
         {
-            if (!load_from_memory<uint32_t, uint8_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint32_t, uint8_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i32_load16_s:
         {
-            if (!load_from_memory<uint32_t, int16_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint32_t, int16_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i32_load16_u:
         {
-            if (!load_from_memory<uint32_t, uint16_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint32_t, uint16_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i64_load8_s:
         {
-            if (!load_from_memory<uint64_t, int8_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint64_t, int8_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i64_load8_u:
         {
-            if (!load_from_memory<uint64_t, uint8_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint64_t, uint8_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i64_load16_s:
         {
-            if (!load_from_memory<uint64_t, int16_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint64_t, int16_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i64_load16_u:
         {
-            if (!load_from_memory<uint64_t, uint16_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint64_t, uint16_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i64_load32_s:
         {
-            if (!load_from_memory<uint64_t, int32_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint64_t, int32_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i64_load32_u:
         {
-            if (!load_from_memory<uint64_t, uint32_t>(*memory, stack, immediates))
+            if (!load_from_memory<MultiMemory, uint64_t, uint32_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i32_store:
         {
-            if (!store_into_memory<uint32_t>(*memory, stack, immediates))
+            if (!store_into_memory<MultiMemory, uint32_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i64_store:
         {
-            if (!store_into_memory<uint64_t>(*memory, stack, immediates))
+            if (!store_into_memory<MultiMemory, uint64_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::f32_store:
         {
-            if (!store_into_memory<float>(*memory, stack, immediates))
+            if (!store_into_memory<MultiMemory, float>(instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::f64_store:
         {
-            if (!store_into_memory<double>(*memory, stack, immediates))
+            if (!store_into_memory<MultiMemory, double>(instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i32_store8:
         case Instr::i64_store8:
         {
-            if (!store_into_memory<uint8_t>(*memory, stack, immediates))
+            if (!store_into_memory<MultiMemory, uint8_t>(instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i32_store16:
         case Instr::i64_store16:
         {
-            if (!store_into_memory<uint16_t>(*memory, stack, immediates))
+            if (!store_into_memory<MultiMemory, uint16_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::i64_store32:
         {
-            if (!store_into_memory<uint32_t>(*memory, stack, immediates))
+            if (!store_into_memory<MultiMemory, uint32_t>(
+                    instance, stack, immediates, bounds_check))
                 goto trap;
             break;
         }
         case Instr::memory_size:
         {
-            stack.push(static_cast<uint32_t>(memory->size() / PageSize));
+            const auto memory_idx = MultiMemory ? read<uint32_t>(immediates) : 0;
+            const auto& size_memory =
+                memory_idx == 0 ? *memory : *instance.memories[memory_idx - 1].memory;
+            stack.push(static_cast<uint32_t>(size_memory.size() / PageSize));
             break;
         }
         case Instr::memory_grow:
         {
             const auto delta = stack.pop().as<uint32_t>();
-            const auto cur_pages = memory->size() / PageSize;
+            const auto memory_idx = MultiMemory ? read<uint32_t>(immediates) : 0;
+            auto& grown_memory =
+                memory_idx == 0 ? *memory : *instance.memories[memory_idx - 1].memory;
+            const auto pages_limit = memory_idx == 0 ?
+                                         instance.memory_pages_limit :
+                                         instance.memories[memory_idx - 1].pages_limit;
+            const auto cur_pages = grown_memory.size() / PageSize;
             assert(cur_pages <= size_t(std::numeric_limits<int32_t>::max()));
             const auto new_pages = cur_pages + delta;
             assert(new_pages >= cur_pages);
             uint32_t ret = static_cast<uint32_t>(cur_pages);
             try
             {
-                if (new_pages > instance.memory_pages_limit)
+                if (new_pages > pages_limit)
                     throw std::bad_alloc();
-                memory->resize(new_pages * PageSize);
+                if (instance.memory_grow_limiter && new_pages != cur_pages &&
+                    !instance.memory_grow_limiter(instance, memory_idx,
+                        static_cast<uint32_t>(cur_pages), static_cast<uint32_t>(new_pages)))
+                {
+                    throw std::bad_alloc();
+                }
+                grown_memory.resize(new_pages * PageSize);
+                if (instance.memory_grow_hook && new_pages != cur_pages)
+                {
+                    instance.memory_grow_hook(instance, memory_idx,
+                        static_cast<uint32_t>(cur_pages), static_cast<uint32_t>(new_pages));
+                }
             }
             catch (std::bad_alloc const&)
             {
@@ -1530,7 +1640,7 @@ ExecutionResult execute(Instance& instance, FuncIdx func_idx, const Value* args,
         }
         case Instr::f64_promote_f32:
         {
-            stack.top() = double{stack.top().f32};
+            stack.top() = double{stack.top().as<float>()};
             break;
         }
         case Instr::i32_reinterpret_f32:
@@ -1567,6 +1677,45 @@ end:
     return stack.size() != 0 ? ExecutionResult{stack.top()} : Void;
 
 trap:
+    record_trap_frame(instance, func_idx);
     return Trap;
 }
+}  // namespace
+
+ExecutionResult execute(Instance& instance, FuncIdx func_idx, const Value* args, int depth)
+{
+    assert(depth >= 0);
+    if (depth == 0)
+        instance.trap_stack_trace.clear();
+    if (depth > instance.call_stack_limit)
+        return Trap;
+
+    const auto& func_type = instance.module->get_function_type(func_idx);
+
+    assert(instance.module->imported_function_types.size() == instance.imported_functions.size());
+    if (func_idx < instance.imported_functions.size())
+    {
+#if defined(FIZZY_GUARDED_MEMORY)
+        // The faults of host functions are not converted into traps.
+        const TrapScope host_scope{nullptr};
+#endif
+        const auto ret = instance.imported_functions[func_idx].function(
+            instance, {args, func_type.inputs.size()}, depth);
+        if (ret.trapped)
+            record_trap_frame(instance, func_idx);
+        return ret;
+    }
+
+    const auto instrumented =
+        instance.instruction_hook != nullptr || instance.periodic_hook_countdown != 0;
+    const auto multi_memory = instance.module->get_memory_count() > 1;
+    if (instrumented)
+    {
+        return multi_memory ?
+                   execute_code<true, true>(instance, func_idx, func_type, args, depth) :
+                   execute_code<true, false>(instance, func_idx, func_type, args, depth);
+    }
+    return multi_memory ? execute_code<false, true>(instance, func_idx, func_type, args, depth) :
+                          execute_code<false, false>(instance, func_idx, func_type, args, depth);
+}
 }  // namespace fizzy
@@ -83,11 +83,6 @@ void match_imported_tables(const std::vector<Table>& module_imported_tables,
 void match_imported_memories(const std::vector<Memory>& module_imported_memories,
     const std::vector<ExternalMemory>& imported_memories)
 {
-    assert(module_imported_memories.size() <= 1);
-
-    if (imported_memories.size() > 1)
-        throw instantiate_error{"only 1 imported memory is allowed"};
-
     if (module_imported_memories.empty())
     {
         if (!imported_memories.empty())
@@ -95,20 +90,29 @@ void match_imported_memories(const std::vector<Memory>& module_imported_memories
             throw instantiate_error{
                 "trying to provide imported memory to a module that doesn't define one"};
         }
+        return;
     }
-    else
+
+    if (imported_memories.empty())
+        throw instantiate_error{"module defines an imported memory but none was provided"};
+
+    if (module_imported_memories.size() != imported_memories.size())
     {
-        if (imported_memories.empty())
-            throw instantiate_error{"module defines an imported memory but none was provided"};
+        throw instantiate_error{
+            "module requires " + std::to_string(module_imported_memories.size()) +
+            " imported memories, " + std::to_string(imported_memories.size()) + " provided"};
+    }
 
-        match_limits(imported_memories[0].limits, module_imported_memories[0].limits);
+    for (size_t i = 0; i < imported_memories.size(); ++i)
+    {
+        match_limits(imported_memories[i].limits, module_imported_memories[i].limits);
 
-        if (imported_memories[0].data == nullptr)
+        if (imported_memories[i].data == nullptr)
             throw instantiate_error{"provided imported memory has a null pointer to data"};
 
-        const auto size = imported_memories[0].data->size();
-        const auto min = imported_memories[0].limits.min;
-        const auto& max = imported_memories[0].limits.max;
+        const auto size = imported_memories[i].data->size();
+        const auto min = imported_memories[i].limits.min;
+        const auto& max = imported_memories[i].limits.max;
         if (size < min * PageSize || (max.has_value() && size > *max * PageSize))
             throw instantiate_error{"provided imported memory doesn't fit provided limits"};
     }
@@ -162,18 +166,20 @@ std::tuple<table_ptr, Limits> allocate_table(
         return {table_ptr{nullptr, null_delete}, Limits{}};
 }
 
-std::tuple<bytes_ptr, Limits> allocate_memory(const std::vector<Memory>& module_memories,
-    const std::vector<ExternalMemory>& imported_memories, uint32_t memory_pages_limit)
+std::tuple<memory_ptr, Limits> allocate_memory(const std::vector<Memory>& module_memories,
+    const std::vector<ExternalMemory>& imported_memories, MemIdx memory_idx,
+    uint32_t memory_pages_limit, MemoryBacking memory_backing, const Allocator& memory_allocator)
 {
-    static const auto bytes_delete = [](bytes* b) noexcept { delete b; };
-    static const auto null_delete = [](bytes*) noexcept {};
+    static const auto memory_delete = [](LinearMemory* m) noexcept { delete m; };
+    static const auto null_delete = [](LinearMemory*) noexcept {};
 
-    assert(module_memories.size() + imported_memories.size() <= 1);
-
-    if (module_memories.size() == 1)
+    // The imported memories come first in the index space.
+    if (memory_idx >= imported_memories.size() &&
+        memory_idx - imported_memories.size() < module_memories.size())
     {
-        const auto memory_min = module_memories[0].limits.min;
-        const auto memory_max = module_memories[0].limits.max;
+        const auto& module_memory = module_memories[memory_idx - imported_memories.size()];
+        const auto memory_min = module_memory.limits.min;
+        const auto memory_max = module_memory.limits.max;
 
         // TODO: better error handling
         if ((memory_min > memory_pages_limit) ||
@@ -184,13 +190,17 @@ std::tuple<bytes_ptr, Limits> allocate_memory(const std::vector<Memory>& module_
         }
 
         // NOTE: fill it with zeroes
-        bytes_ptr memory{new bytes(memory_min * PageSize, 0), bytes_delete};
-        return {std::move(memory), module_memories[0].limits};
+        const auto memory_max_pages = memory_max.value_or(memory_pages_limit);
+        memory_ptr memory{new LinearMemory(size_t{memory_min} * PageSize, memory_backing,
+                              size_t{memory_max_pages} * PageSize, memory_allocator),
+            memory_delete};
+        return {std::move(memory), module_memory.limits};
     }
-    else if (imported_memories.size() == 1)
+    else if (memory_idx < imported_memories.size())
     {
-        const auto memory_min = imported_memories[0].limits.min;
-        const auto memory_max = imported_memories[0].limits.max;
+        const auto& imported_memory = imported_memories[memory_idx];
+        const auto memory_min = imported_memory.limits.min;
+        const auto memory_max = imported_memory.limits.max;
 
         // TODO: better error handling
         if ((memory_min > memory_pages_limit) ||
@@ -200,12 +210,12 @@ std::tuple<bytes_ptr, Limits> allocate_memory(const std::vector<Memory>& module_
                                     std::to_string(memory_pages_limit * PageSize) + " bytes"};
         }
 
-        bytes_ptr memory{imported_memories[0].data, null_delete};
-        return {std::move(memory), imported_memories[0].limits};
+        memory_ptr memory{imported_memory.data, null_delete};
+        return {std::move(memory), imported_memory.limits};
     }
     else
     {
-        bytes_ptr memory{nullptr, null_delete};
+        memory_ptr memory{nullptr, null_delete};
         return {std::move(memory), Limits{}};
     }
 }
@@ -235,12 +245,38 @@ std::optional<uint32_t> find_export(const Module& module, ExternalKind kind, std
     return (it != module.exportsec.end() ? std::make_optional(it->index) : std::nullopt);
 }
 
+// Copy the memory owned by an instance, with the hard limit for its growth in pages.
+memory_ptr duplicate_memory(const LinearMemory& source, uint32_t pages_limit)
+{
+    static const auto memory_delete = [](LinearMemory* m) noexcept { delete m; };
+
+    memory_ptr memory{new LinearMemory(source.size(), source.backing(),
+                          size_t{pages_limit} * PageSize, source.allocator()),
+        memory_delete};
+    std::copy(source.data(), source.data() + source.size(), memory->data());
+    for (const auto& [begin, end] : source.read_only_ranges())
+        memory->add_read_only_range(begin, end - begin);
+    return memory;
+}
+
+// Create the function calling the function of the instance.
+ExternalFunction instance_function(Instance& instance, FuncIdx idx)
+{
+    auto func = [idx, &instance](fizzy::Instance&, span<const Value> args, int depth) {
+        return execute(instance, idx, args.data(), depth);
+    };
+
+    return ExternalFunction{
+        std::move(func), instance.module->get_function_type(idx), &instance, idx};
+}
 }  // namespace
 
 std::unique_ptr<Instance> instantiate(std::unique_ptr<const Module> module,
     std::vector<ExternalFunction> imported_functions, std::vector<ExternalTable> imported_tables,
     std::vector<ExternalMemory> imported_memories, std::vector<ExternalGlobal> imported_globals,
-    uint32_t memory_pages_limit /*= DefaultMemoryPagesLimit*/)
+    uint32_t memory_pages_limit /*= DefaultMemoryPagesLimit*/,
+    MemoryBacking memory_backing /*= MemoryBacking::heap*/,
+    const Allocator& memory_allocator /*= {}*/, bool defer_start /*= false*/)
 {
     assert(module->funcsec.size() == module->codesec.size());
 
@@ -264,8 +300,20 @@ std::unique_ptr<Instance> instantiate(std::unique_ptr<const Module> module,
 
     auto [table, table_limits] = allocate_table(module->tablesec, imported_tables);
 
-    auto [memory, memory_limits] =
-        allocate_memory(module->memorysec, imported_memories, memory_pages_limit);
+    // The memories other than the memory 0 of multi-memory modules. Their accesses are always
+    // bounds-checked, so they are not guarded even if backed by guarded memory.
+    std::vector<InstanceMemory> memories;
+    for (MemIdx memory_idx = 1; memory_idx < module->get_memory_count(); ++memory_idx)
+    {
+        auto [other_memory, other_memory_limits] = allocate_memory(module->memorysec,
+            imported_memories, memory_idx, memory_pages_limit, memory_backing, memory_allocator);
+        const auto pages_limit = other_memory_limits.max.value_or(memory_pages_limit);
+        memories.push_back(
+            InstanceMemory{std::move(other_memory), other_memory_limits, pages_limit});
+    }
+
+    auto [memory, memory_limits] = allocate_memory(module->memorysec, imported_memories, 0,
+        memory_pages_limit, memory_backing, memory_allocator);
     // In case upper limit for local/imported memory is defined,
     // we adjust the hard memory limit, to ensure memory.grow will fail when exceeding it.
     // Note: allocate_memory ensures memory's max limit is always below memory_pages_limit.
@@ -285,7 +333,10 @@ std::unique_ptr<Instance> instantiate(std::unique_ptr<const Module> module,
         const uint64_t offset =
             eval_constant_expression(data.offset, imported_globals, globals).i64;
 
-        if (offset + data.init.size() > memory->size())
+        const auto* data_memory = (data.memory_index == 0) ?
+                                      memory.get() :
+                                      memories[data.memory_index - 1].memory.get();
+        if (offset + data.init.size() > data_memory->size())
             throw instantiate_error{"data segment is out of memory bounds"};
 
         datasec_offsets.emplace_back(offset);
@@ -309,9 +360,12 @@ std::unique_ptr<Instance> instantiate(std::unique_ptr<const Module> module,
     // Fill out memory based on data segments
     for (size_t i = 0; i < module->datasec.size(); ++i)
     {
+        const auto memory_index = module->datasec[i].memory_index;
+        auto* data_memory =
+            (memory_index == 0) ? memory.get() : memories[memory_index - 1].memory.get();
         // NOTE: these instructions can overlap
         std::copy(module->datasec[i].init.begin(), module->datasec[i].init.end(),
-            memory->data() + datasec_offsets[i]);
+            data_memory->data() + datasec_offsets[i]);
     }
 
     // We need to create instance before filling table,
@@ -322,6 +376,7 @@ std::unique_ptr<Instance> instantiate(std::unique_ptr<const Module> module,
         // NOLINTNEXTLINE(clang-analyzer-cplusplus.NewDeleteLeaks)
         memory_pages_limit, std::move(table), table_limits, std::move(globals),
         std::move(imported_functions), std::move(imported_globals));
+    instance->memories = std::move(memories);
 
     // Fill the table based on elements segment
     for (size_t i = 0; i < instance->module->elementsec.size(); ++i)
@@ -329,17 +384,13 @@ std::unique_ptr<Instance> instantiate(std::unique_ptr<const Module> module,
         // Overwrite table[offset..] with element.init
         auto it_table = instance->table->begin() + elementsec_offsets[i];
         for (const auto idx : instance->module->elementsec[i].init)
-        {
-            auto func = [idx, &instance_ref = *instance](fizzy::Instance&, span<const Value> args,
-                            int depth) { return execute(instance_ref, idx, args.data(), depth); };
-
-            *it_table++ =
-                ExternalFunction{std::move(func), instance->module->get_function_type(idx)};
-        }
+            *it_table++ = instance_function(*instance, idx);
     }
 
-    // Run start function if present
-    if (instance->module->startfunc)
+    // Run start function if present, unless deferred
+    if (instance->module->startfunc && defer_start)
+        instance->start_pending = true;
+    else if (instance->module->startfunc)
     {
         const auto funcidx = *instance->module->startfunc;
         assert(funcidx < instance->imported_functions.size() + instance->module->funcsec.size());
@@ -369,13 +420,69 @@ std::unique_ptr<Instance> instantiate(std::unique_ptr<const Module> module,
                     }
                 }
             }
-            throw instantiate_error{"start function failed to execute"};
+            throw start_function_trapped{"start function failed to execute"};
         }
     }
 
     return instance;
 }
 
+ExecutionResult run_start(Instance& instance)
+{
+    if (!instance.start_pending)
+        return Void;
+
+    instance.start_pending = false;
+    return execute(instance, *instance.module->startfunc, {});
+}
+
+std::unique_ptr<Instance> duplicate(const Instance& instance)
+{
+    static const auto null_memory_delete = [](LinearMemory*) noexcept {};
+    static const auto table_delete = [](table_elements* t) noexcept { delete t; };
+    static const auto null_table_delete = [](table_elements*) noexcept {};
+
+    // The imported memories and table are shared, the imported memories come first.
+    const auto imported_memory_count = instance.module->imported_memory_types.size();
+    memory_ptr memory{instance.memory.get(), null_memory_delete};
+    if (instance.memory != nullptr && imported_memory_count == 0)
+        memory = duplicate_memory(*instance.memory, instance.memory_pages_limit);
+
+    std::vector<InstanceMemory> memories;
+    for (size_t i = 0; i < instance.memories.size(); ++i)
+    {
+        const auto& source = instance.memories[i];
+        memory_ptr other_memory{source.memory.get(), null_memory_delete};
+        if (i + 1 >= imported_memory_count)
+            other_memory = duplicate_memory(*source.memory, source.pages_limit);
+        memories.push_back(InstanceMemory{std::move(other_memory), source.limits, source.pages_limit});
+    }
+
+    table_ptr table{instance.table.get(), null_table_delete};
+    if (instance.table != nullptr && !instance.module->tablesec.empty())
+        table = table_ptr{new table_elements(*instance.table), table_delete};
+
+    auto copy = std::make_unique<Instance>(instance.module, std::move(memory),
+        instance.memory_limits, instance.memory_pages_limit, std::move(table),
+        instance.table_limits, instance.globals, instance.imported_functions,
+        instance.imported_globals);
+    copy->memories = std::move(memories);
+    copy->trap_stack_trace_enabled = instance.trap_stack_trace_enabled;
+    copy->start_pending = instance.start_pending;
+    copy->call_stack_limit = instance.call_stack_limit;
+
+    if (copy->table != nullptr && !copy->module->tablesec.empty())
+    {
+        for (auto& element : *copy->table)
+        {
+            if (element.has_value() && element->instance == &instance)
+                element = instance_function(*copy, element->func_idx);
+        }
+    }
+
+    return copy;
+}
+
 std::vector<ExternalFunction> resolve_imported_functions(
     const Module& module, std::vector<ImportedFunction> imported_functions)
 {
@@ -434,12 +541,7 @@ std::optional<ExternalFunction> find_exported_function(Instance& instance, std::
     if (!opt_index.has_value())
         return std::nullopt;
 
-    const auto idx = *opt_index;
-    auto func = [idx, &instance](fizzy::Instance&, span<const Value> args, int depth) {
-        return execute(instance, idx, args.data(), depth);
-    };
-
-    return ExternalFunction{std::move(func), instance.module->get_function_type(idx)};
+    return instance_function(instance, *opt_index);
 }
 
 std::optional<ExternalGlobal> find_exported_global(Instance& instance, std::string_view name)
@@ -475,11 +577,77 @@ std::optional<ExternalTable> find_exported_table(Instance& instance, std::string
 
 std::optional<ExternalMemory> find_exported_memory(Instance& instance, std::string_view name)
 {
-    // Index returned from find_export is discarded, because there's no more than 1 memory
-    if (!find_export(*instance.module, ExternalKind::Memory, name))
+    const auto opt_index = find_export(*instance.module, ExternalKind::Memory, name);
+    if (!opt_index.has_value())
         return std::nullopt;
 
-    return ExternalMemory{instance.memory.get(), instance.memory_limits};
+    const auto memory_idx = *opt_index;
+    if (memory_idx == 0)
+        return ExternalMemory{instance.memory.get(), instance.memory_limits};
+
+    const auto& memory = instance.memories[memory_idx - 1];
+    return ExternalMemory{memory.memory.get(), memory.limits};
+}
+
+hash256 state_hash(const Instance& instance)
+{
+    Sha256 h;
+    const auto update_byte = [&h](uint8_t byte) noexcept { h.update(&byte, 1); };
+    const auto update_le = [&h](uint64_t value, size_t size) noexcept {
+        for (size_t i = 0; i < size; ++i)
+        {
+            const auto byte = static_cast<uint8_t>(value >> (i * 8));
+            h.update(&byte, 1);
+        }
+    };
+
+    update_byte(instance.memory != nullptr);
+    if (instance.memory)
+    {
+        update_le(instance.memory->size(), 8);
+        h.update(instance.memory->data(), instance.memory->size());
+    }
+    // The other memories of multi-memory modules, not changing the hash of the other modules.
+    for (const auto& other : instance.memories)
+    {
+        update_le(other.memory->size(), 8);
+        h.update(other.memory->data(), other.memory->size());
+    }
+
+    const auto& module = *instance.module;
+    update_le(module.get_global_count(), 4);
+    for (GlobalIdx idx = 0; idx < module.get_global_count(); ++idx)
+    {
+        const auto type = module.get_global_type(idx).value_type;
+        const auto& value = idx < instance.imported_globals.size() ?
+                                *instance.imported_globals[idx].value :
+                                instance.globals[idx - instance.imported_globals.size()];
+        update_byte(static_cast<uint8_t>(type));
+        if (type == ValType::i32 || type == ValType::f32)
+            update_le(value.as<uint32_t>(), 4);
+        else
+            update_le(value.as<uint64_t>(), 8);
+    }
+
+    update_byte(instance.table != nullptr);
+    if (instance.table)
+    {
+        update_le(instance.table->size(), 4);
+        for (const auto& element : *instance.table)
+        {
+            update_byte(element.has_value());
+            if (!element.has_value())
+                continue;
+            update_le(element->type.inputs.size(), 4);
+            for (const auto type : element->type.inputs)
+                update_byte(static_cast<uint8_t>(type));
+            update_le(element->type.outputs.size(), 4);
+            for (const auto type : element->type.outputs)
+                update_byte(static_cast<uint8_t>(type));
+        }
+    }
+
+    return h.finalize();
 }
 
 }  // namespace fizzy
@@ -7,7 +7,9 @@
 #include "cxx20/span.hpp"
 #include "exceptions.hpp"
 #include "limits.hpp"
+#include "linear_memory.hpp"
 #include "module.hpp"
+#include "sha256.hpp"
 #include "types.hpp"
 #include "value.hpp"
 #include <cstdint>
@@ -26,6 +28,10 @@ struct ExternalFunction
 {
     std::function<ExecutionResult(Instance&, span<const Value>, int depth)> function;
     FuncType type;
+    // The instance the function is defined in, null for host functions.
+    // Used to rebind the table elements of duplicated instances.
+    const Instance* instance = nullptr;
+    FuncIdx func_idx = 0;
 };
 
 using table_elements = std::vector<std::optional<ExternalFunction>>;
@@ -39,7 +45,7 @@ struct ExternalTable
 
 struct ExternalMemory
 {
-    bytes* data = nullptr;
+    LinearMemory* data = nullptr;
     Limits limits;
 };
 
@@ -49,19 +55,66 @@ struct ExternalGlobal
     GlobalType type;
 };
 
-using bytes_ptr = std::unique_ptr<bytes, void (*)(bytes*)>;
+using memory_ptr = std::unique_ptr<LinearMemory, void (*)(LinearMemory*)>;
+
+/// The state of the execution at the point right before an instruction is executed.
+struct ExecutionState
+{
+    FuncIdx func_idx = 0;
+    /// The offset of the instruction in the function's instruction array.
+    uint32_t instr_offset = 0;
+    Instr opcode = Instr::unreachable;
+    /// Arguments and local variables of the function.
+    span<const Value> locals;
+    /// The operand stack, the bottom item first.
+    span<const Value> stack;
+    int depth = 0;
+    /// The static offset of a memory load or store instruction, 0 for other instructions.
+    uint32_t memory_offset = 0;
+    /// The index of the memory of a load or store instruction, 0 for other instructions.
+    uint32_t memory_idx = 0;
+};
+
+/// The hook called before each executed instruction. Returning false aborts execution with a trap.
+using InstructionHook = std::function<bool(Instance&, const ExecutionState&)>;
+
+/// The hook called periodically after a number of executed instructions. Returning false aborts
+/// execution with a trap.
+using PeriodicHook = std::function<bool(Instance&)>;
+
+/// The hook called after a memory has been grown by an instruction, with the index of the memory
+/// and the old and new sizes in pages.
+using MemoryGrowHook = std::function<void(Instance&, uint32_t, uint32_t, uint32_t)>;
+
+/// The limiter consulted before a memory is grown by an instruction within its pages limit,
+/// with the index of the memory and the current and desired sizes in pages. Returning false fails
+/// the growth.
+using MemoryGrowLimiter = std::function<bool(Instance&, uint32_t, uint32_t, uint32_t)>;
+
+// A memory of an instance other than the memory 0, in modules using the multi-memory proposal.
+struct InstanceMemory
+{
+    // Either owned by the instance or imported, like the memory 0.
+    memory_ptr memory = {nullptr, [](LinearMemory*) {}};
+    Limits limits;
+    // Hard limit for memory growth in pages.
+    uint32_t pages_limit = 0;
+};
 
 // The module instance.
 struct Instance
 {
-    std::unique_ptr<const Module> module;
-    // Memory is either allocated and owned by the instance or imported as already allocated bytes
+    // The module is shared with the duplicates of the instance.
+    std::shared_ptr<const Module> module;
+    // Memory is either allocated and owned by the instance or imported as already allocated memory
     // and owned externally.
     // For these cases unique_ptr would either have a normal deleter or noop deleter respectively
-    bytes_ptr memory = {nullptr, [](bytes*) {}};
+    memory_ptr memory = {nullptr, [](LinearMemory*) {}};
     Limits memory_limits;
     // Hard limit for memory growth in pages, checked when memory is defined as unbounded in module
     uint32_t memory_pages_limit = 0;
+    // The memories of the indices starting from 1, in modules using the multi-memory proposal.
+    std::vector<InstanceMemory> memories;
     // Table is either allocated and owned by the instance or imported and owned externally.
     // For these cases unique_ptr would either have a normal deleter or noop deleter respectively.
     table_ptr table = {nullptr, [](table_elements*) {}};
@@ -69,8 +122,31 @@ struct Instance
     std::vector<Value> globals;
     std::vector<ExternalFunction> imported_functions;
     std::vector<ExternalGlobal> imported_globals;
-
-    Instance(std::unique_ptr<const Module> _module, bytes_ptr _memory, Limits _memory_limits,
+    // Optional hook called before each executed instruction.
+    // The hooks are checked when a function execution starts, the functions executing without
+    // hooks don't pay for them.
+    InstructionHook instruction_hook;
+    // Optional hook called every periodic_hook_interval executed instructions.
+    PeriodicHook periodic_hook;
+    uint64_t periodic_hook_interval = 0;
+    // The number of instructions to execute before the next call of the periodic hook,
+    // 0 if there is no periodic hook.
+    uint64_t periodic_hook_countdown = 0;
+    // Optional hook called after any of the memories has grown.
+    MemoryGrowHook memory_grow_hook;
+    // Optional limiter approving the growth of any of the memories.
+    MemoryGrowLimiter memory_grow_limiter;
+    // Indices of the functions active when the last execution trapped, the innermost first.
+    // Truncated if there was not enough memory to record all of them.
+    std::vector<FuncIdx> trap_stack_trace;
+    // Whether the stack trace is recorded when an execution traps.
+    bool trap_stack_trace_enabled = true;
+    // Whether the start function was deferred at instantiation and has not been run yet.
+    bool start_pending = false;
+    // Maximum depth of nested calls, at most CallStackLimit.
+    int call_stack_limit = CallStackLimit;
+
+    Instance(std::shared_ptr<const Module> _module, memory_ptr _memory, Limits _memory_limits,
         uint32_t _memory_pages_limit, table_ptr _table, Limits _table_limits,
         std::vector<Value> _globals, std::vector<ExternalFunction> _imported_functions,
         std::vector<ExternalGlobal> _imported_globals)
@@ -84,6 +160,14 @@ struct Instance
         imported_functions(std::move(_imported_functions)),
         imported_globals(std::move(_imported_globals))
     {}
+
+    // Get the memory of the index, null if there is no such memory.
+    LinearMemory* get_memory(MemIdx idx) const noexcept
+    {
+        if (idx == 0)
+            return memory.get();
+        return idx <= memories.size() ? memories[idx - 1].memory.get() : nullptr;
+    }
 };
 
 // Instantiate a module.
@@ -92,7 +176,19 @@ std::unique_ptr<Instance> instantiate(std::unique_ptr<const Module> module,
     std::vector<ExternalTable> imported_tables = {},
     std::vector<ExternalMemory> imported_memories = {},
     std::vector<ExternalGlobal> imported_globals = {},
-    uint32_t memory_pages_limit = DefaultMemoryPagesLimit);
+    uint32_t memory_pages_limit = DefaultMemoryPagesLimit,
+    MemoryBacking memory_backing = MemoryBacking::heap, const Allocator& memory_allocator = {},
+    bool defer_start = false);
+
+// Run the start function of an instance, which was deferred at instantiation.
+// Returns Void if there is no start function pending, e.g. it has already been run.
+ExecutionResult run_start(Instance& instance);
+
+// Create an independent copy of an instance sharing its module. The memory, the table and
+// the globals defined in the module are copied, the imported ones are shared. The elements of
+// the table referring to the functions of the instance refer to the copy's functions instead.
+// The hooks are not copied.
+std::unique_ptr<Instance> duplicate(const Instance& instance);
 
 // Function that should be used by instantiate as imports, identified by module and function name.
 struct ImportedFunction
@@ -125,4 +221,9 @@ std::optional<ExternalTable> find_exported_table(Instance& instance, std::string
 // Find exported memory by name.
 std::optional<ExternalMemory> find_exported_memory(Instance& instance, std::string_view name);
 
+// Compute the SHA-256 hash of the state of an instance: its memories, globals and table.
+// The hash is deterministic across platforms, so can be used to compare end states of executions.
+// Table elements contribute only their function types, as function identity is not observable.
+hash256 state_hash(const Instance& instance);
+
 }  // namespace fizzy
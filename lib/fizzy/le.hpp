@@ -0,0 +1,73 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+#pragma once
+
+#include <cstdint>
+#include <type_traits>
+
+/// Access to values stored in the little-endian byte order, as defined by WebAssembly
+/// for the binary format and the memory. On big-endian hosts the bytes are swapped.
+namespace fizzy::le
+{
+#if defined(__BYTE_ORDER__) && __BYTE_ORDER__ == __ORDER_BIG_ENDIAN__
+constexpr bool host_is_big_endian = true;
+#else
+constexpr bool host_is_big_endian = false;
+#endif
+
+/// Reverses the byte order of an integer.
+template <typename T>
+inline T byteswap(T value) noexcept
+{
+    static_assert(std::is_integral_v<T>);
+    if constexpr (sizeof(T) == 1)
+        return value;
+    else if constexpr (sizeof(T) == 2)
+        return static_cast<T>(__builtin_bswap16(static_cast<uint16_t>(value)));
+    else if constexpr (sizeof(T) == 4)
+        return static_cast<T>(__builtin_bswap32(static_cast<uint32_t>(value)));
+    else
+        return static_cast<T>(__builtin_bswap64(static_cast<uint64_t>(value)));
+}
+
+/// Loads a value of type T from little-endian bytes.
+template <typename T>
+inline T load(const uint8_t* src) noexcept
+{
+    T value;
+    if constexpr (host_is_big_endian && std::is_floating_point_v<T>)
+    {
+        using U = std::conditional_t<sizeof(T) == 4, uint32_t, uint64_t>;
+        const auto bits = load<U>(src);
+        __builtin_memcpy(&value, &bits, sizeof(value));
+    }
+    else
+    {
+        __builtin_memcpy(&value, src, sizeof(value));
+        if constexpr (host_is_big_endian)
+            value = byteswap(value);
+    }
+    return value;
+}
+
+/// Stores a value of type T as little-endian bytes.
+template <typename T>
+inline void store(uint8_t* dst, T value) noexcept
+{
+    if constexpr (host_is_big_endian && std::is_floating_point_v<T>)
+    {
+        using U = std::conditional_t<sizeof(T) == 4, uint32_t, uint64_t>;
+        U bits;
+        __builtin_memcpy(&bits, &value, sizeof(bits));
+        store(dst, bits);
+    }
+    else
+    {
+        if constexpr (host_is_big_endian)
+            value = byteswap(value);
+        __builtin_memcpy(dst, &value, sizeof(value));
+    }
+}
+}  // namespace fizzy::le
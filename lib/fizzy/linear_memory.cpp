@@ -0,0 +1,227 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+#include "linear_memory.hpp"
+#include "trap_handler.hpp"
+#include <cassert>
+#include <algorithm>
+#include <cstdlib>
+#include <cstring>
+#include <new>
+
+#if defined(_WIN32)
+#define FIZZY_MAPPED_MEMORY 1
+#ifndef NOMINMAX
+#define NOMINMAX
+#endif
+#include <windows.h>
+#elif defined(__unix__) || defined(__APPLE__)
+#define FIZZY_MAPPED_MEMORY 1
+#include <sys/mman.h>
+#include <unistd.h>
+#endif
+
+namespace fizzy
+{
+namespace
+{
+uint8_t* allocate_zeroed(const Allocator& allocator, size_t size) noexcept
+{
+    if (allocator.allocate == nullptr)
+        return static_cast<uint8_t*>(std::calloc(size, 1));
+
+    auto* const data = static_cast<uint8_t*>(allocator.allocate(allocator.context, size));
+    if (data != nullptr)
+        std::memset(data, 0, size);
+    return data;
+}
+
+uint8_t* reallocate(
+    const Allocator& allocator, uint8_t* data, size_t old_size, size_t new_size) noexcept
+{
+    if (allocator.reallocate == nullptr)
+        return static_cast<uint8_t*>(std::realloc(data, new_size));
+    return static_cast<uint8_t*>(allocator.reallocate(allocator.context, data, old_size, new_size));
+}
+
+void deallocate(const Allocator& allocator, uint8_t* data, size_t size) noexcept
+{
+    if (allocator.deallocate == nullptr)
+        std::free(data);
+    else if (data != nullptr)
+        allocator.deallocate(allocator.context, data, size);
+}
+
+#if defined(FIZZY_MAPPED_MEMORY)
+size_t os_page_size() noexcept
+{
+#if defined(_WIN32)
+    SYSTEM_INFO info;
+    GetSystemInfo(&info);
+    return info.dwPageSize;
+#else
+    return static_cast<size_t>(sysconf(_SC_PAGESIZE));
+#endif
+}
+
+size_t round_up_to_os_page(size_t size) noexcept
+{
+    static const auto page_size = os_page_size();
+    return (size + page_size - 1) / page_size * page_size;
+}
+
+/// Reserve the address space without making it accessible. Returns nullptr on failure.
+uint8_t* reserve(size_t size) noexcept
+{
+#if defined(_WIN32)
+    return static_cast<uint8_t*>(VirtualAlloc(nullptr, size, MEM_RESERVE, PAGE_NOACCESS));
+#else
+    void* const ptr =
+        mmap(nullptr, size, PROT_NONE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, -1, 0);
+    return ptr != MAP_FAILED ? static_cast<uint8_t*>(ptr) : nullptr;
+#endif
+}
+
+/// Make the reserved pages of the range [0, size) readable and writable.
+bool commit(uint8_t* data, size_t size) noexcept
+{
+    if (size == 0)
+        return true;
+#if defined(_WIN32)
+    return VirtualAlloc(data, size, MEM_COMMIT, PAGE_READWRITE) != nullptr;
+#else
+    return mprotect(data, size, PROT_READ | PROT_WRITE) == 0;
+#endif
+}
+
+#if defined(FIZZY_GUARDED_MEMORY)
+/// Make the committed pages of the range [0, size) inaccessible again.
+void decommit(uint8_t* data, size_t size) noexcept
+{
+    if (size != 0)
+        mprotect(data, size, PROT_NONE);
+}
+#endif
+
+void release(uint8_t* data, size_t size) noexcept
+{
+#if defined(_WIN32)
+    (void)size;
+    VirtualFree(data, 0, MEM_RELEASE);
+#else
+    munmap(data, size);
+#endif
+}
+#endif
+}  // namespace
+
+LinearMemory::LinearMemory(
+    size_t size, MemoryBacking backing, size_t max_size, const Allocator& allocator)
+{
+    assert((allocator.allocate != nullptr) == (allocator.reallocate != nullptr) &&
+           (allocator.allocate != nullptr) == (allocator.deallocate != nullptr));
+
+#if defined(FIZZY_GUARDED_MEMORY)
+    if (backing == MemoryBacking::guarded)
+    {
+        assert(size == round_up_to_os_page(size));
+        if (size <= GuardedMemoryReservation && install_trap_handler())
+            max_size = GuardedMemoryReservation;
+        else
+            backing = MemoryBacking::mapped;
+    }
+#else
+    if (backing == MemoryBacking::guarded)
+        backing = MemoryBacking::mapped;
+#endif
+
+#if defined(FIZZY_MAPPED_MEMORY)
+    if (backing == MemoryBacking::mapped || backing == MemoryBacking::guarded)
+    {
+        // Reserve at least one page, so that mapped memory always has the address space.
+        const auto capacity = round_up_to_os_page(std::max({size, max_size, size_t{1}}));
+        m_data = reserve(capacity);
+        if (m_data == nullptr)
+            throw std::bad_alloc();
+        if (!commit(m_data, round_up_to_os_page(size)))
+        {
+            release(m_data, capacity);
+            throw std::bad_alloc();
+        }
+        m_size = size;
+        m_capacity = capacity;
+        m_backing = backing;
+        return;
+    }
+#else
+    (void)backing;
+    (void)max_size;
+#endif
+
+    if (size != 0)
+    {
+        m_data = allocate_zeroed(allocator, size);
+        if (m_data == nullptr)
+            throw std::bad_alloc();
+    }
+    m_size = size;
+    m_capacity = size;
+    m_allocator = allocator;
+}
+
+LinearMemory::~LinearMemory() noexcept
+{
+#if defined(FIZZY_MAPPED_MEMORY)
+    if (m_backing != MemoryBacking::heap)
+    {
+        release(m_data, m_capacity);
+        return;
+    }
+#endif
+    deallocate(m_allocator, m_data, m_capacity);
+}
+
+void LinearMemory::resize(size_t new_size)
+{
+    if (new_size <= m_size)
+    {
+        // Clear the dropped bytes, so that they are zero-initialized when the memory is regrown.
+        std::memset(m_data + new_size, 0, m_size - new_size);
+#if defined(FIZZY_GUARDED_MEMORY)
+        // The accesses of guarded memory beyond its size must fault.
+        if (m_backing == MemoryBacking::guarded)
+        {
+            assert(new_size == round_up_to_os_page(new_size));
+            decommit(m_data + new_size, m_size - new_size);
+        }
+#endif
+        m_size = new_size;
+        return;
+    }
+
+#if defined(FIZZY_MAPPED_MEMORY)
+    if (m_backing != MemoryBacking::heap)
+    {
+        // Committed pages of anonymous mappings are zero-initialized.
+        if (new_size > m_capacity || !commit(m_data, round_up_to_os_page(new_size)))
+            throw std::bad_alloc();
+        m_size = new_size;
+        return;
+    }
+#endif
+
+    if (new_size > m_capacity)
+    {
+        auto* const new_data = (m_data == nullptr) ?
+                                   allocate_zeroed(m_allocator, new_size) :
+                                   reallocate(m_allocator, m_data, m_capacity, new_size);
+        if (new_data == nullptr)
+            throw std::bad_alloc();
+        m_data = new_data;
+        m_capacity = new_size;
+    }
+    std::memset(m_data + m_size, 0, new_size - m_size);
+    m_size = new_size;
+}
+}  // namespace fizzy
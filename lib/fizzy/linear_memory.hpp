@@ -0,0 +1,151 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+#pragma once
+
+#include "bytes.hpp"
+#include <algorithm>
+#include <cstddef>
+#include <cstdint>
+#include <iterator>
+#include <utility>
+#include <vector>
+
+namespace fizzy
+{
+/// The storage backing a linear memory.
+enum class MemoryBacking : uint8_t
+{
+    /// Memory allocated on the heap, reallocated (and copied) when grown.
+    heap,
+    /// Memory reserved in the virtual address space (mmap/VirtualAlloc) for its maximum size
+    /// and grown in place by committing the reserved pages.
+    /// Falls back to heap on platforms without virtual memory reservations.
+    mapped,
+    /// Mapped memory with the reservation covering any address of a memory access instruction,
+    /// so that the accesses are not bounds-checked, but the faults of the inaccessible pages
+    /// are converted into traps by a signal handler.
+    /// Falls back to mapped on platforms other than 64-bit POSIX.
+    guarded,
+};
+
+/// The custom allocator of heap memory, e.g. for accounting or for selecting a specific allocator.
+/// Either all functions are set or none, in which case the C runtime allocator is used.
+/// The functions report failure by returning nullptr.
+struct Allocator
+{
+    /// Allocate @p size bytes, not necessarily zero-initialized.
+    void* (*allocate)(void* context, size_t size) = nullptr;
+    /// Resize the allocation of @p old_size bytes to @p new_size bytes, preserving its contents.
+    /// The allocation is left intact in case of failure.
+    void* (*reallocate)(void* context, void* ptr, size_t old_size, size_t new_size) = nullptr;
+    /// Free the allocation of @p size bytes.
+    void (*deallocate)(void* context, void* ptr, size_t size) = nullptr;
+    /// The context passed to the functions.
+    void* context = nullptr;
+};
+
+/// The zero-initialized bytes of a linear memory.
+class LinearMemory
+{
+    uint8_t* m_data = nullptr;
+    size_t m_size = 0;
+    /// The size of the allocation for heap memory, or of the reservation for mapped memory.
+    size_t m_capacity = 0;
+    MemoryBacking m_backing = MemoryBacking::heap;
+    Allocator m_allocator;
+    /// The sorted and disjoint [begin, end) ranges of the bytes which cannot be written by
+    /// the instructions.
+    std::vector<std::pair<uint64_t, uint64_t>> m_read_only_ranges;
+
+public:
+    LinearMemory() noexcept = default;
+
+    /// Allocate memory of the given size. Mapped memory reserves @p max_size bytes
+    /// (at least @p size) to be grown in place. The size of guarded memory must be a multiple of
+    /// the OS page size, which WebAssembly pages are.
+    /// Heap memory is allocated with the @p allocator, mapped memory ignores it.
+    /// Throws std::bad_alloc if the memory cannot be allocated.
+    explicit LinearMemory(size_t size, MemoryBacking backing = MemoryBacking::heap,
+        size_t max_size = 0, const Allocator& allocator = {});
+
+    LinearMemory(const LinearMemory&) = delete;
+    LinearMemory& operator=(const LinearMemory&) = delete;
+
+    ~LinearMemory() noexcept;
+
+    uint8_t* data() noexcept { return m_data; }
+    const uint8_t* data() const noexcept { return m_data; }
+
+    size_t size() const noexcept { return m_size; }
+
+    /// The size of the allocation for heap memory, or of the reservation for mapped memory.
+    size_t capacity() const noexcept { return m_capacity; }
+
+    bool empty() const noexcept { return m_size == 0; }
+
+    uint8_t* begin() noexcept { return m_data; }
+    uint8_t* end() noexcept { return m_data + m_size; }
+
+    uint8_t& operator[](size_t offset) noexcept { return m_data[offset]; }
+
+    operator bytes_view() const noexcept { return {m_data, m_size}; }
+
+    /// The storage backing the memory, differs from the requested one when unsupported.
+    MemoryBacking backing() const noexcept { return m_backing; }
+
+    /// The allocator of heap memory.
+    const Allocator& allocator() const noexcept { return m_allocator; }
+
+    /// Mark the @p size bytes at @p offset read-only for the store instructions, which trap
+    /// when writing any of them. The memory can still be written by the host.
+    /// The range may overlap the ones already marked.
+    void add_read_only_range(uint64_t offset, uint64_t size)
+    {
+        if (size == 0)
+            return;
+
+        // The ranges are kept sorted and disjoint, the ones overlapping or adjacent to the new
+        // range are merged into it.
+        auto begin = offset;
+        auto end = offset + size;
+        auto first = std::lower_bound(m_read_only_ranges.begin(), m_read_only_ranges.end(), begin,
+            [](const auto& range, uint64_t value) { return range.second < value; });
+        auto last = first;
+        for (; last != m_read_only_ranges.end() && last->first <= end; ++last)
+        {
+            begin = std::min(begin, last->first);
+            end = std::max(end, last->second);
+        }
+        first = m_read_only_ranges.erase(first, last);
+        m_read_only_ranges.emplace(first, begin, end);
+    }
+
+    /// The sorted and disjoint [begin, end) ranges of the read-only bytes.
+    const std::vector<std::pair<uint64_t, uint64_t>>& read_only_ranges() const noexcept
+    {
+        return m_read_only_ranges;
+    }
+
+    /// Make all the memory writable again.
+    void clear_read_only_ranges() noexcept { m_read_only_ranges.clear(); }
+
+    /// Whether any of the @p size bytes at @p offset is read-only.
+    bool is_read_only(uint64_t offset, uint64_t size) const noexcept
+    {
+        if (m_read_only_ranges.empty())
+            return false;
+
+        // Only the last range beginning before the end of the bytes can overlap them.
+        const auto it = std::lower_bound(m_read_only_ranges.begin(), m_read_only_ranges.end(),
+            offset + size, [](const auto& range, uint64_t value) { return range.first < value; });
+        return it != m_read_only_ranges.begin() && std::prev(it)->second > offset;
+    }
+
+    /// Change the size of the memory, new bytes are zero-initialized.
+    /// Throws std::bad_alloc if the memory cannot be grown, the memory is unchanged then.
+    /// Mapped memory cannot be grown beyond its reservation, but its data stays in place.
+    void resize(size_t new_size);
+};
+}  // namespace fizzy
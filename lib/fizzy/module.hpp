@@ -7,6 +7,8 @@
 #include "types.hpp"
 #include <cassert>
 #include <optional>
+#include <string>
+#include <unordered_map>
 #include <vector>
 
 namespace fizzy
@@ -45,6 +47,10 @@ struct Module
     // Types of globals defined in import section
     std::vector<GlobalType> imported_global_types;
 
+    // Function names from the "name" custom section, if present and well-formed.
+    // https://webassembly.github.io/spec/core/appendix/custom.html#name-section
+    std::unordered_map<FuncIdx, std::string> function_names;
+
     size_t get_function_count() const noexcept
     {
         return imported_function_types.size() + funcsec.size();
@@ -90,5 +96,20 @@ struct Module
     {
         return !memorysec.empty() || !imported_memory_types.empty();
     }
+
+    // The number of memories, either imported or defined. It is more than 1 only in modules using
+    // the multi-memory proposal.
+    size_t get_memory_count() const noexcept
+    {
+        return imported_memory_types.size() + memorysec.size();
+    }
+
+    Limits get_memory_limits(MemIdx idx) const noexcept
+    {
+        assert(idx < get_memory_count());
+        return idx < imported_memory_types.size() ?
+                   imported_memory_types[idx].limits :
+                   memorysec[idx - imported_memory_types.size()].limits;
+    }
 };
 }  // namespace fizzy
@@ -7,6 +7,7 @@
 #include "limits.hpp"
 #include "types.hpp"
 #include "utf8.hpp"
+#include <algorithm>
 #include <cassert>
 #include <unordered_set>
 
@@ -36,8 +37,10 @@ void validate_constant_expression(
 template <typename T>
 parser_result<T> parse(const uint8_t* pos, const uint8_t* end);
 
-template <typename T>
-inline parser_result<std::vector<T>> parse_vec(const uint8_t* pos, const uint8_t* end)
+/// Parses the vec of elements with the @p parse_element function.
+template <typename T, typename ParseFn>
+inline parser_result<std::vector<T>> parse_vec(
+    const uint8_t* pos, const uint8_t* end, ParseFn parse_element)
 {
     uint32_t size;
     std::tie(size, pos) = leb128u_decode<uint32_t>(pos, end);
@@ -52,10 +55,16 @@ inline parser_result<std::vector<T>> parse_vec(const uint8_t* pos, const uint8_t
 
     auto inserter = std::back_inserter(result);
     for (uint32_t i = 0; i < size; ++i)
-        std::tie(inserter, pos) = parse<T>(pos, end);
+        std::tie(inserter, pos) = parse_element(pos, end);
     return {result, pos};
 }
 
+template <typename T>
+inline parser_result<std::vector<T>> parse_vec(const uint8_t* pos, const uint8_t* end)
+{
+    return parse_vec<T>(pos, end, parse<T>);
+}
+
 template <>
 inline parser_result<uint32_t> parse(const uint8_t* pos, const uint8_t* end)
 {
@@ -375,8 +384,12 @@ inline parser_result<code_view> parse(const uint8_t* pos, const uint8_t* end)
     return {{code_begin, code_size}, code_end};
 }
 
-inline Code parse_code(code_view code_binary, FuncIdx func_idx, const Module& module)
+inline Code parse_code(code_view code_binary, FuncIdx func_idx, const Module& module,
+    const ParserLimits& limits, const Features& features)
 {
+    if (code_binary.size() > limits.max_function_body_size)
+        throw parser_error{"function body size limit exceeded"};
+
     const auto begin = code_binary.begin();
     const auto end = code_binary.end();
     const auto [locals_vec, pos1] = parse_vec<Locals>(begin, end);
@@ -388,28 +401,37 @@ inline Code parse_code(code_view code_binary, FuncIdx func_idx, const Module& mo
         if (local_count > std::numeric_limits<uint32_t>::max())
             throw parser_error{"too many local variables"};
     }
+    if (local_count > limits.max_local_count)
+        throw parser_error{"local count limit exceeded"};
 
     // TODO: Clarify in spec what happens if count of locals and arguments exceed uint32_t::max()
     //       Leave this assert here for the time being.
     assert((uint64_t{local_count} + module.typesec[module.funcsec[func_idx]].inputs.size()) <=
            std::numeric_limits<uint32_t>::max());
 
-    auto [code, pos2] = parse_expr(pos1, end, func_idx, locals_vec, module);
+    auto [code, pos2] =
+        parse_expr(pos1, end, func_idx, locals_vec, module, limits.max_nesting_depth, features);
 
     // Size is the total bytes of locals and expressions.
     if (pos2 != end)
         throw parser_error{"malformed size field for function"};
 
     code.local_count = static_cast<uint32_t>(local_count);
+    code.body_size = static_cast<uint32_t>(code_binary.size());
     return code;
 }
 
-template <>
-inline parser_result<Data> parse(const uint8_t* pos, const uint8_t* end)
+inline parser_result<Data> parse_data(
+    const uint8_t* pos, const uint8_t* end, const Features& features)
 {
     MemIdx memory_index;
     std::tie(memory_index, pos) = leb128u_decode<uint32_t>(pos, end);
-    if (memory_index != 0)
+
+    // In the multi-memory proposal the active segments of the memories other than 0 have
+    // the flags 2 followed by the memory index, as in the bulk memory proposal.
+    if (features.multi_memory && memory_index == 2)
+        std::tie(memory_index, pos) = leb128u_decode<uint32_t>(pos, end);
+    else if (memory_index != 0)
         throw parser_error{"unexpected memidx value " + std::to_string(memory_index)};
 
     ConstantExpression offset;
@@ -427,10 +449,51 @@ inline parser_result<Data> parse(const uint8_t* pos, const uint8_t* end)
     auto init = bytes(pos, pos + size);
     pos += size;
 
-    return {{offset, std::move(init)}, pos};
+    return {{offset, std::move(init), memory_index}, pos};
 }
 
-std::unique_ptr<const Module> parse(bytes_view input)
+inline std::unordered_map<FuncIdx, std::string> parse_function_names(
+    const uint8_t* pos, const uint8_t* end)
+{
+    std::unordered_map<FuncIdx, std::string> function_names;
+    // Malformed name section must not make the module invalid, so it is ignored in such case.
+    try
+    {
+        while (pos != end)
+        {
+            const auto subsection_id = *pos++;
+            uint32_t size;
+            std::tie(size, pos) = leb128u_decode<uint32_t>(pos, end);
+            if ((end - pos) < size)
+                return {};
+            const auto subsection_end = pos + size;
+
+            // Function names subsection.
+            if (subsection_id == 1)
+            {
+                uint32_t count;
+                std::tie(count, pos) = leb128u_decode<uint32_t>(pos, subsection_end);
+                for (uint32_t i = 0; i < count; ++i)
+                {
+                    FuncIdx func_idx;
+                    std::tie(func_idx, pos) = leb128u_decode<uint32_t>(pos, subsection_end);
+                    std::string name;
+                    std::tie(name, pos) = parse_string(pos, subsection_end);
+                    function_names.emplace(func_idx, std::move(name));
+                }
+            }
+            pos = subsection_end;
+        }
+    }
+    catch (const parser_error&)
+    {
+        return {};
+    }
+    return function_names;
+}
+
+std::unique_ptr<const Module> parse(
+    bytes_view input, const ParserLimits& limits, const Features& features)
 {
     if (input.substr(0, wasm_prefix.size()) != wasm_prefix)
         throw parser_error{"invalid wasm module prefix"};
@@ -439,6 +502,14 @@ std::unique_ptr<const Module> parse(bytes_view input)
 
     auto module{std::make_unique<Module>()};
     std::vector<code_view> code_binaries;
+
+    uint64_t allocation_size = 0;
+    const auto track_allocation = [&limits, &allocation_size](uint64_t size) {
+        allocation_size += size;
+        if (allocation_size > limits.max_allocation_size)
+            throw parser_error{"allocation size limit exceeded"};
+    };
+
     SectionId last_id = SectionId::custom;
     for (auto it = input.begin(); it != input.end();)
     {
@@ -461,43 +532,80 @@ std::unique_ptr<const Module> parse(bytes_view input)
         {
         case SectionId::type:
             std::tie(module->typesec, it) = parse_vec<FuncType>(it, input.end());
+            track_allocation(module->typesec.size() * sizeof(FuncType));
+            for (const auto& type : module->typesec)
+                track_allocation(type.inputs.size() + type.outputs.size());
             break;
         case SectionId::import:
             std::tie(module->importsec, it) = parse_vec<Import>(it, input.end());
+            track_allocation(module->importsec.size() * sizeof(Import));
+            for (const auto& import : module->importsec)
+                track_allocation(import.module.size() + import.name.size());
             break;
         case SectionId::function:
+        {
             std::tie(module->funcsec, it) = parse_vec<TypeIdx>(it, input.end());
+            track_allocation(module->funcsec.size() * sizeof(TypeIdx));
+            // The import section precedes the function section, so all functions are known here.
+            const auto imported_function_count = std::count_if(module->importsec.begin(),
+                module->importsec.end(),
+                [](const Import& import) { return import.kind == ExternalKind::Function; });
+            if (uint64_t{module->funcsec.size()} + static_cast<uint64_t>(imported_function_count) >
+                limits.max_function_count)
+                throw parser_error{"function count limit exceeded"};
             break;
+        }
         case SectionId::table:
             std::tie(module->tablesec, it) = parse_vec<Table>(it, input.end());
+            track_allocation(module->tablesec.size() * sizeof(Table));
             break;
         case SectionId::memory:
             std::tie(module->memorysec, it) = parse_vec<Memory>(it, input.end());
+            track_allocation(module->memorysec.size() * sizeof(Memory));
             break;
         case SectionId::global:
             std::tie(module->globalsec, it) = parse_vec<Global>(it, input.end());
+            track_allocation(module->globalsec.size() * sizeof(Global));
             break;
         case SectionId::export_:
             std::tie(module->exportsec, it) = parse_vec<Export>(it, input.end());
+            track_allocation(module->exportsec.size() * sizeof(Export));
+            for (const auto& export_ : module->exportsec)
+                track_allocation(export_.name.size());
             break;
         case SectionId::start:
             std::tie(module->startfunc, it) = leb128u_decode<uint32_t>(it, input.end());
             break;
         case SectionId::element:
             std::tie(module->elementsec, it) = parse_vec<Element>(it, input.end());
+            track_allocation(module->elementsec.size() * sizeof(Element));
+            for (const auto& element : module->elementsec)
+                track_allocation(element.init.size() * sizeof(FuncIdx));
             break;
         case SectionId::code:
             std::tie(code_binaries, it) = parse_vec<code_view>(it, input.end());
             break;
         case SectionId::data:
-            std::tie(module->datasec, it) = parse_vec<Data>(it, input.end());
+        {
+            const auto parse_segment = [&features](const uint8_t* pos, const uint8_t* end) {
+                return parse_data(pos, end, features);
+            };
+            std::tie(module->datasec, it) = parse_vec<Data>(it, input.end(), parse_segment);
+            track_allocation(module->datasec.size() * sizeof(Data));
+            for (const auto& data : module->datasec)
+                track_allocation(data.init.size());
             break;
+        }
         case SectionId::custom:
+        {
             // NOTE: this section can be ignored, but the name must be parseable (and valid UTF-8)
-            parse_string(it, expected_section_end);
-            // These sections are ignored for now.
+            const auto [name, payload] = parse_string(it, expected_section_end);
+            if (name == "name")
+                module->function_names = parse_function_names(payload, expected_section_end);
+            // Other sections are ignored for now.
             it += size;
             break;
+        }
         default:
             throw parser_error{
                 "unknown section encountered " + std::to_string(static_cast<int>(id))};
@@ -546,16 +654,21 @@ std::unique_ptr<const Module> parse(bytes_view input)
     if (module->tablesec.size() > 1)
         throw validation_error{"too many table sections (at most one is allowed)"};
 
-    if (module->memorysec.size() > 1)
-        throw validation_error{"too many memory sections (at most one is allowed)"};
+    // Multiple memories, imported and defined, are allowed by the multi-memory proposal.
+    if (!features.multi_memory)
+    {
+        if (module->memorysec.size() > 1)
+            throw validation_error{"too many memory sections (at most one is allowed)"};
 
-    if (module->imported_memory_types.size() > 1)
-        throw validation_error{"too many imported memories (at most one is allowed)"};
+        if (module->imported_memory_types.size() > 1)
+            throw validation_error{"too many imported memories (at most one is allowed)"};
 
-    if (!module->memorysec.empty() && !module->imported_memory_types.empty())
-    {
-        throw validation_error{
-            "both module memory and imported memory are defined (at most one of them is allowed)"};
+        if (!module->memorysec.empty() && !module->imported_memory_types.empty())
+        {
+            throw validation_error{
+                "both module memory and imported memory are defined (at most one of them is "
+                "allowed)"};
+        }
     }
 
     if (!module->datasec.empty() && !module->has_memory())
@@ -563,6 +676,9 @@ std::unique_ptr<const Module> parse(bytes_view input)
 
     for (const auto& data : module->datasec)
     {
+        if (data.memory_index >= module->get_memory_count())
+            throw validation_error{"invalid memory index in data segment"};
+
         // Offset expression is required to have i32 result value
         // https://webassembly.github.io/spec/core/valid/modules.html#data-segments
         validate_constant_expression(data.offset, *module, ValType::i32);
@@ -628,7 +744,7 @@ std::unique_ptr<const Module> parse(bytes_view input)
                 throw validation_error{"invalid index of an exported table"};
             break;
         case ExternalKind::Memory:
-            if (export_.index != 0 || !module->has_memory())
+            if (export_.index >= module->get_memory_count())
                 throw validation_error{"invalid index of an exported memory"};
             break;
         case ExternalKind::Global:
@@ -653,10 +769,15 @@ std::unique_ptr<const Module> parse(bytes_view input)
     }
 
     // Process code. TODO: This can be done lazily.
+    track_allocation(code_binaries.size() * sizeof(Code));
     module->codesec.reserve(code_binaries.size());
     for (size_t i = 0; i < code_binaries.size(); ++i)
-        module->codesec.emplace_back(
-            parse_code(code_binaries[i], static_cast<FuncIdx>(i), *module));
+    {
+        const auto& code = module->codesec.emplace_back(
+            parse_code(code_binaries[i], static_cast<FuncIdx>(i), *module, limits, features));
+        track_allocation(code.instructions.size() + code.immediates.size() +
+                         code.callees.size() * sizeof(FuncIdx));
+    }
 
     return module;
 }
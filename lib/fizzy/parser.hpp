@@ -5,8 +5,10 @@
 #pragma once
 
 #include "exceptions.hpp"
+#include "le.hpp"
 #include "leb128.hpp"
 #include "module.hpp"
+#include <limits>
 #include <memory>
 
 namespace fizzy
@@ -17,7 +19,38 @@ constexpr bytes_view wasm_prefix{wasm_prefix_data, sizeof(wasm_prefix_data)};
 template <typename T>
 using parser_result = std::pair<T, const uint8_t*>;
 
-std::unique_ptr<const Module> parse(bytes_view input);
+/// Limits of the resources the parser is allowed to use.
+/// The defaults impose no limits beyond the ones of the binary format.
+/// Lower them when parsing untrusted input.
+struct ParserLimits
+{
+    /// The maximum number of functions, including imported ones.
+    uint32_t max_function_count = std::numeric_limits<uint32_t>::max();
+
+    /// The maximum size of a function body in bytes, including the local declarations.
+    uint32_t max_function_body_size = std::numeric_limits<uint32_t>::max();
+
+    /// The maximum number of locals of a function, excluding the function parameters.
+    uint32_t max_local_count = std::numeric_limits<uint32_t>::max();
+
+    /// The maximum depth of nested blocks, loops and ifs in a function body.
+    uint32_t max_nesting_depth = std::numeric_limits<uint32_t>::max();
+
+    /// The maximum total size in bytes of the module representation built by the parser.
+    /// This is an approximation counting the section elements, data segments and code.
+    uint64_t max_allocation_size = std::numeric_limits<uint64_t>::max();
+};
+
+/// The WebAssembly proposals the parser accepts on top of WebAssembly 1.0, all disabled by default.
+struct Features
+{
+    /// Multiple imported and defined memories, and the memory index immediates of the memory
+    /// instructions. https://github.com/WebAssembly/multi-memory
+    bool multi_memory = false;
+};
+
+std::unique_ptr<const Module> parse(
+    bytes_view input, const ParserLimits& limits = {}, const Features& features = {});
 
 inline parser_result<uint8_t> parse_byte(const uint8_t* pos, const uint8_t* end)
 {
@@ -34,9 +67,7 @@ inline parser_result<T> parse_value(const uint8_t* pos, const uint8_t* end)
     if ((end - pos) < size)
         throw parser_error{"unexpected EOF"};
 
-    T value;
-    __builtin_memcpy(&value, pos, size);
-    return {value, pos + size};
+    return {le::load<T>(pos), pos + size};
 }
 
 /// Parse `expr`, i.e. a function's instructions residing in the code section.
@@ -47,8 +78,12 @@ inline parser_result<T> parse_value(const uint8_t* pos, const uint8_t* end)
 /// @param func_idx Index of the function being parsed.
 /// @param locals   Vector of local type and counts for the function being parsed.
 /// @param module   Module that this code is part of.
+/// @param max_nesting_depth  The maximum depth of nested blocks, loops and ifs.
+/// @param features The enabled WebAssembly proposals.
 parser_result<Code> parse_expr(const uint8_t* pos, const uint8_t* end, FuncIdx func_idx,
-    const std::vector<Locals>& locals, const Module& module);
+    const std::vector<Locals>& locals, const Module& module,
+    uint32_t max_nesting_depth = std::numeric_limits<uint32_t>::max(),
+    const Features& features = {});
 
 parser_result<std::string> parse_string(const uint8_t* pos, const uint8_t* end);
 
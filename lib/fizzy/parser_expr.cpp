@@ -253,7 +253,8 @@ ValType find_local_type(
 }  // namespace
 
 parser_result<Code> parse_expr(const uint8_t* pos, const uint8_t* end, FuncIdx func_idx,
-    const std::vector<Locals>& locals, const Module& module)
+    const std::vector<Locals>& locals, const Module& module, uint32_t max_nesting_depth,
+    const Features& features)
 {
     Code code;
 
@@ -261,6 +262,12 @@ parser_result<Code> parse_expr(const uint8_t* pos, const uint8_t* end, FuncIdx f
     // instructions as defined in Wasm Validation Algorithm.
     Stack<ControlFrame> control_stack;
 
+    // The function's implicit block does not count towards the nesting depth.
+    const auto check_nesting_depth = [&control_stack, max_nesting_depth] {
+        if (control_stack.size() > max_nesting_depth)
+            throw parser_error{"nesting depth limit exceeded"};
+    };
+
     Stack<OperandStackType> operand_stack;
 
     const auto func_type_idx = module.funcsec[func_idx];
@@ -462,6 +469,7 @@ parser_result<Code> parse_expr(const uint8_t* pos, const uint8_t* end, FuncIdx f
         {
             std::optional<ValType> block_type;
             std::tie(block_type, pos) = parse_blocktype(pos, end);
+            check_nesting_depth();
 
             // Push label with immediates offset after arity.
             control_stack.emplace(Instr::block, block_type, static_cast<int>(operand_stack.size()),
@@ -473,6 +481,7 @@ parser_result<Code> parse_expr(const uint8_t* pos, const uint8_t* end, FuncIdx f
         {
             std::optional<ValType> loop_type;
             std::tie(loop_type, pos) = parse_blocktype(pos, end);
+            check_nesting_depth();
 
             control_stack.emplace(Instr::loop, loop_type, static_cast<int>(operand_stack.size()),
                 code.instructions.size(), code.immediates.size());
@@ -483,6 +492,7 @@ parser_result<Code> parse_expr(const uint8_t* pos, const uint8_t* end, FuncIdx f
         {
             std::optional<ValType> if_type;
             std::tie(if_type, pos) = parse_blocktype(pos, end);
+            check_nesting_depth();
 
             control_stack.emplace(Instr::if_, if_type, static_cast<int>(operand_stack.size()),
                 code.instructions.size(), code.immediates.size());
@@ -691,6 +701,7 @@ parser_result<Code> parse_expr(const uint8_t* pos, const uint8_t* end, FuncIdx f
                 frame, operand_stack, callee_func_type.inputs, callee_func_type.outputs);
 
             push(code.immediates, callee_func_idx);
+            code.callees.push_back(callee_func_idx);
             break;
         }
 
@@ -842,6 +853,16 @@ parser_result<Code> parse_expr(const uint8_t* pos, const uint8_t* end, FuncIdx f
         {
             uint32_t align;
             std::tie(align, pos) = leb128u_decode<uint32_t>(pos, end);
+
+            // In the multi-memory proposal the bit 6 of the alignment flags the memory index
+            // following it.
+            MemIdx memory_idx = 0;
+            if (features.multi_memory && (align & 0x40) != 0)
+            {
+                align &= ~uint32_t{0x40};
+                std::tie(memory_idx, pos) = leb128u_decode<uint32_t>(pos, end);
+            }
+
             // NOTE: [0, 3] is the correct range (the hard limit is log2(64 / 8)) and checking it to
             // avoid overflows
             if (align > max_align)
@@ -850,22 +871,38 @@ parser_result<Code> parse_expr(const uint8_t* pos, const uint8_t* end, FuncIdx f
             uint32_t offset;
             std::tie(offset, pos) = leb128u_decode<uint32_t>(pos, end);
             push(code.immediates, offset);
+            // The memory index is an immediate only in the modules with multiple memories,
+            // so the execution of the other modules doesn't read it.
+            if (module.get_memory_count() > 1)
+                push(code.immediates, memory_idx);
 
             if (!module.has_memory())
                 throw validation_error{"memory instructions require imported or defined memory"};
+            if (memory_idx >= module.get_memory_count())
+                throw validation_error{"invalid memory index"};
             break;
         }
 
         case Instr::memory_size:
         case Instr::memory_grow:
         {
-            uint8_t memory_idx;
-            std::tie(memory_idx, pos) = parse_byte(pos, end);
-            if (memory_idx != 0)
-                throw parser_error{"invalid memory index encountered"};
+            MemIdx memory_idx = 0;
+            if (features.multi_memory)
+                std::tie(memory_idx, pos) = leb128u_decode<uint32_t>(pos, end);
+            else
+            {
+                uint8_t memory_idx_byte;
+                std::tie(memory_idx_byte, pos) = parse_byte(pos, end);
+                if (memory_idx_byte != 0)
+                    throw parser_error{"invalid memory index encountered"};
+            }
+            if (module.get_memory_count() > 1)
+                push(code.immediates, memory_idx);
 
             if (!module.has_memory())
                 throw validation_error{"memory instructions require imported or defined memory"};
+            if (memory_idx >= module.get_memory_count())
+                throw validation_error{"invalid memory index"};
             break;
         }
         }
@@ -0,0 +1,39 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+#pragma once
+
+#include <array>
+#include <cstddef>
+#include <cstdint>
+
+namespace fizzy
+{
+using hash256 = std::array<uint8_t, 32>;
+
+/// The incremental SHA-256 hash function (FIPS 180-4).
+class Sha256
+{
+    std::array<uint32_t, 8> m_state;
+    std::array<uint8_t, 64> m_block{};
+    uint64_t m_size = 0;
+
+    void compress() noexcept;
+
+public:
+    Sha256() noexcept;
+
+    void update(const uint8_t* data, size_t size) noexcept;
+
+    /// Finish the hashing. The object must not be used afterwards.
+    hash256 finalize() noexcept;
+};
+
+inline hash256 sha256(const uint8_t* data, size_t size) noexcept
+{
+    Sha256 h;
+    h.update(data, size);
+    return h.finalize();
+}
+}  // namespace fizzy
@@ -121,6 +121,12 @@ public:
     OperandStack(const OperandStack&) = delete;
     OperandStack& operator=(const OperandStack&) = delete;
 
+    /// Returns the pointer to the beginning of the locals array (arguments and local variables).
+    const Value* locals() const noexcept { return m_locals; }
+
+    /// The number of locals, including arguments.
+    size_t num_locals() const noexcept { return static_cast<size_t>(m_bottom - m_locals); }
+
     Value& local(size_t index) noexcept
     {
         assert(m_locals + index < m_bottom);
@@ -0,0 +1,71 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+#include "trap_handler.hpp"
+
+#if defined(FIZZY_GUARDED_MEMORY)
+#include <csignal>
+
+namespace fizzy
+{
+namespace
+{
+thread_local TrapScope* current_scope = nullptr;
+
+struct sigaction previous_segv_action;
+struct sigaction previous_bus_action;
+
+void handle_fault(int sig, siginfo_t* info, void* context)
+{
+    auto* const scope = current_scope;
+    if (scope != nullptr && scope->contains(info->si_addr))
+        siglongjmp(scope->env, 1);
+
+    const auto& previous = (sig == SIGSEGV) ? previous_segv_action : previous_bus_action;
+    if ((previous.sa_flags & SA_SIGINFO) != 0)
+        previous.sa_sigaction(sig, info, context);
+    else if (previous.sa_handler == SIG_DFL || previous.sa_handler == SIG_IGN)
+    {
+        // Restore the previous action, which handles the fault when the faulting instruction
+        // is executed again after return.
+        sigaction(sig, &previous, nullptr);
+    }
+    else
+        previous.sa_handler(sig);
+}
+
+bool install() noexcept
+{
+    struct sigaction action = {};
+    action.sa_sigaction = handle_fault;
+    // The handler does not return after jumping out of it, so the signal must not stay blocked.
+    action.sa_flags = SA_SIGINFO | SA_NODEFER | SA_ONSTACK;
+    sigemptyset(&action.sa_mask);
+    return sigaction(SIGSEGV, &action, &previous_segv_action) == 0 &&
+           sigaction(SIGBUS, &action, &previous_bus_action) == 0;
+}
+}  // namespace
+
+bool install_trap_handler() noexcept
+{
+    static const bool installed = install();
+    return installed;
+}
+
+TrapScope::TrapScope(const LinearMemory* memory) noexcept : m_prev{current_scope}
+{
+    if (memory != nullptr)
+    {
+        m_begin = memory->data();
+        m_end = m_begin + memory->capacity();
+    }
+    current_scope = this;
+}
+
+TrapScope::~TrapScope() noexcept
+{
+    current_scope = m_prev;
+}
+}  // namespace fizzy
+#endif
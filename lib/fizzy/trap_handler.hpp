@@ -0,0 +1,60 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+#pragma once
+
+#include "limits.hpp"
+#include "linear_memory.hpp"
+#include <cstddef>
+#include <cstdint>
+
+#if (defined(__unix__) || defined(__APPLE__)) && UINTPTR_MAX > 0xffffffff && \
+    !defined(FIZZY_MINIMAL)
+#define FIZZY_GUARDED_MEMORY 1
+#include <csetjmp>
+#endif
+
+namespace fizzy
+{
+#if defined(FIZZY_GUARDED_MEMORY)
+/// The size of the address space reserved for guarded memory: 4 GiB addressable with 32-bit
+/// address, 4 GiB of the maximum static offset and a page covering the size of the accessed value.
+/// Any memory access of a WebAssembly instruction falls into this reservation.
+constexpr size_t GuardedMemoryReservation = (size_t{1} << 33) + PageSize;
+
+/// Install the process-wide SIGSEGV and SIGBUS handler converting the faults of accessing
+/// guarded memory into traps. Other faults are forwarded to the previously installed handlers.
+/// Thread-safe, the handler is installed only once.
+/// @returns  false if the handler cannot be installed.
+bool install_trap_handler() noexcept;
+
+/// The scope of an execution accessing guarded memory without bounds checks.
+///
+/// The scopes of a thread form a stack. A fault in the reservation of the memory of the innermost
+/// scope jumps to @a env, which must be set with sigsetjmp() by the function owning the scope.
+/// A scope without memory disables the conversion, e.g. for the duration of a host function call.
+class TrapScope
+{
+    const uint8_t* m_begin = nullptr;
+    const uint8_t* m_end = nullptr;
+    TrapScope* const m_prev;
+
+public:
+    sigjmp_buf env;
+
+    explicit TrapScope(const LinearMemory* memory) noexcept;
+    ~TrapScope() noexcept;
+
+    TrapScope(const TrapScope&) = delete;
+    TrapScope& operator=(const TrapScope&) = delete;
+
+    /// Whether the address is in the reservation of the memory of the scope.
+    bool contains(const void* address) const noexcept
+    {
+        const auto* const ptr = static_cast<const uint8_t*>(address);
+        return ptr >= m_begin && ptr < m_end;
+    }
+};
+#endif
+}  // namespace fizzy
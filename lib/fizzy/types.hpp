@@ -367,6 +367,12 @@ struct Code
     // The decoded instructions' immediate values.
     // These are instruction-type dependent fixed size value in the order of instructions.
     bytes immediates;
+
+    /// The indices of the functions called directly, in the order of the call instructions.
+    std::vector<FuncIdx> callees;
+
+    /// The size of the function body in the wasm binary, including the local declarations.
+    uint32_t body_size = 0;
 };
 
 /// The reference to the `code` in the wasm binary.
@@ -383,6 +389,8 @@ struct Data
 {
     ConstantExpression offset;
     bytes init;
+    // The index of the memory to initialize, non-zero only in multi-memory modules.
+    MemIdx memory_index = 0;
 };
 
 enum class SectionId : uint8_t
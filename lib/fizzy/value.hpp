@@ -4,11 +4,17 @@
 
 #pragma once
 
+#include "cxx20/bit.hpp"
 #include <cstdint>
 #include <limits>
 
 namespace fizzy
 {
+/// The untyped value.
+///
+/// The i32 and f32 values are kept in the lower 32 bits of i64, so the representation of
+/// all values is independent of the host endianness. The f32 member aliases the lower
+/// 32 bits only on little-endian hosts, therefore use as<float>() to access f32 values.
 union Value
 {
     uint64_t i64;
@@ -31,7 +37,7 @@ union Value
     constexpr Value(int64_t v) noexcept : i64{static_cast<uint64_t>(v)} {}
     constexpr Value(int32_t v) noexcept : i64{static_cast<uint32_t>(v)} {}
 
-    constexpr Value(float v) noexcept : f32{v} {}
+    Value(float v) noexcept : i64{bit_cast<uint32_t>(v)} {}
     constexpr Value(double v) noexcept : f64{v} {}
 
     /// Converting constructor from any other type (including smaller integer types) is deleted.
@@ -69,9 +75,9 @@ constexpr int32_t Value::as<int32_t>() const noexcept
 }
 
 template <>
-constexpr float Value::as<float>() const noexcept
+inline float Value::as<float>() const noexcept
 {
-    return f32;
+    return bit_cast<float>(static_cast<uint32_t>(i64));
 }
 
 template <>
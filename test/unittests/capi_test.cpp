@@ -2,13 +2,29 @@
 // Copyright 2020 The Fizzy Authors.
 // SPDX-License-Identifier: Apache-2.0
 
+#include "trap_handler.hpp"
 #include <fizzy/fizzy.h>
 #include <gtest/gtest.h>
 #include <test/utils/asserts.hpp>
 #include <test/utils/hex.hpp>
+#include <vector>
 
 using namespace fizzy::test;
 
+TEST(capi, get_version)
+{
+    EXPECT_STREQ(fizzy_get_version(), FIZZY_VERSION);
+}
+
+TEST(capi, has_guarded_memory)
+{
+#if defined(FIZZY_GUARDED_MEMORY)
+    EXPECT_TRUE(fizzy_has_guarded_memory());
+#else
+    EXPECT_FALSE(fizzy_has_guarded_memory());
+#endif
+}
+
 TEST(capi, validate)
 {
     uint8_t wasm_prefix[]{0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00};
@@ -17,183 +33,1635 @@ TEST(capi, validate)
     EXPECT_FALSE(fizzy_validate(wasm_prefix, sizeof(wasm_prefix)));
 }
 
-TEST(capi, parse)
-{
-    uint8_t wasm_prefix[]{0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00};
-    auto module = fizzy_parse(wasm_prefix, sizeof(wasm_prefix));
+TEST(capi, parse)
+{
+    uint8_t wasm_prefix[]{0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00};
+    auto module = fizzy_parse(wasm_prefix, sizeof(wasm_prefix));
+    EXPECT_NE(module, nullptr);
+    fizzy_free_module(module);
+    wasm_prefix[7] = 1;
+    EXPECT_EQ(fizzy_parse(wasm_prefix, sizeof(wasm_prefix)), nullptr);
+}
+
+TEST(capi, parse_error)
+{
+    FizzyError error;
+    uint8_t wasm_prefix[]{0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x01};
+    EXPECT_EQ(fizzy_parse_with_limits(wasm_prefix, sizeof(wasm_prefix), nullptr, &error), nullptr);
+    EXPECT_EQ(error.code, FizzyErrorMalformedModule);
+    EXPECT_STREQ(error.message, "invalid wasm module prefix");
+
+    /* wat2wasm --no-check
+      (func (result i32))
+    */
+    const auto wasm = from_hex("0061736d010000000105016000017f030201000a040102000b");
+    EXPECT_EQ(fizzy_parse_with_limits(wasm.data(), wasm.size(), nullptr, &error), nullptr);
+    EXPECT_EQ(error.code, FizzyErrorInvalidModule);
+    EXPECT_STREQ(error.message, "stack underflow");
+}
+
+TEST(capi, parse_with_limits)
+{
+    /* wat2wasm
+      (func (local i32 i32) block nop end)
+      (func)
+    */
+    const auto wasm =
+        from_hex("0061736d0100000001040160000003030200000a0d020801027f0240010b0b02000b");
+
+    FizzyParserLimits limits{2, 8, 2, 1, 1024 * 1024};
+    FizzyError error{FizzyErrorOther, "garbage"};
+    auto module = fizzy_parse_with_limits(wasm.data(), wasm.size(), &limits, &error);
+    EXPECT_NE(module, nullptr);
+    EXPECT_EQ(error.code, FizzySuccess);
+    EXPECT_STREQ(error.message, "");
+    fizzy_free_module(module);
+
+    module = fizzy_parse_with_limits(wasm.data(), wasm.size(), nullptr, nullptr);
+    EXPECT_NE(module, nullptr);
+    fizzy_free_module(module);
+
+    limits.max_function_count = 1;
+    EXPECT_EQ(fizzy_parse_with_limits(wasm.data(), wasm.size(), &limits, &error), nullptr);
+    EXPECT_EQ(error.code, FizzyErrorMalformedModule);
+    EXPECT_STREQ(error.message, "function count limit exceeded");
+    limits.max_function_count = 2;
+
+    limits.max_nesting_depth = 0;
+    EXPECT_EQ(fizzy_parse_with_limits(wasm.data(), wasm.size(), &limits, nullptr), nullptr);
+}
+
+TEST(capi, free_module_null)
+{
+    fizzy_free_module(nullptr);
+}
+
+TEST(capi, clone_module)
+{
+    /* wat2wasm
+      (func (export "foo") (result i32) i32.const 42)
+    */
+    const auto wasm =
+        from_hex("0061736d010000000105016000017f0302010007070103666f6f00000a06010400412a0b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    auto clone = fizzy_clone_module(module);
+    ASSERT_NE(clone, nullptr);
+    EXPECT_NE(clone, module);
+
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_THAT(fizzy_execute(instance, 0, nullptr, 0), Result(42));
+    fizzy_free_instance(instance);
+
+    uint32_t func_idx;
+    ASSERT_TRUE(fizzy_find_exported_function(clone, "foo", &func_idx));
+    instance = fizzy_instantiate(clone, nullptr, 0);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_THAT(fizzy_execute(instance, func_idx, nullptr, 0), Result(42));
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, get_function_type)
+{
+    /* wat2wasm
+      (func (import "mod" "f") (param i32 i64))
+      (func (result f64) f64.const 0)
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010a0260027f7e006000017c020901036d6f6401660000030201010a0d010b004400000000"
+        "000000000b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    const auto type0 = fizzy_get_function_type(module, 0);
+    EXPECT_EQ(type0.output, FizzyValueTypeVoid);
+    ASSERT_EQ(type0.inputs_size, 2);
+    EXPECT_EQ(type0.inputs[0], FizzyValueTypeI32);
+    EXPECT_EQ(type0.inputs[1], FizzyValueTypeI64);
+
+    const auto type1 = fizzy_get_function_type(module, 1);
+    EXPECT_EQ(type1.output, FizzyValueTypeF64);
+    EXPECT_EQ(type1.inputs, nullptr);
+    EXPECT_EQ(type1.inputs_size, 0);
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, get_type)
+{
+    /* wat2wasm
+      (func (import "mod" "f") (param i32 i64))
+      (func (result f64) f64.const 0)
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010a0260027f7e006000017c020901036d6f6401660000030201010a0d010b004400000000"
+        "000000000b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    ASSERT_EQ(fizzy_get_type_count(module), 2);
+
+    const auto type0 = fizzy_get_type(module, 0);
+    EXPECT_EQ(type0.output, FizzyValueTypeVoid);
+    ASSERT_EQ(type0.inputs_size, 2);
+    EXPECT_EQ(type0.inputs[0], FizzyValueTypeI32);
+    EXPECT_EQ(type0.inputs[1], FizzyValueTypeI64);
+
+    const auto type1 = fizzy_get_type(module, 1);
+    EXPECT_EQ(type1.output, FizzyValueTypeF64);
+    EXPECT_EQ(type1.inputs_size, 0);
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, get_global)
+{
+    /* wat2wasm
+    (module
+      (global (import "env" "g") i32)
+      (global i64 (i64.const -1))
+      (global (export "f") (mut f32) (f32.const 1.5))
+      (global i32 (global.get 0))
+    )
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000020a0103656e760167037f000613037e00427f0b7d01430000c03f0b7f0023000b07050101"
+        "660302");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    ASSERT_EQ(fizzy_get_global_count(module), 3);
+
+    const auto global0 = fizzy_get_global(module, 0);
+    EXPECT_EQ(global0.type.value_type, FizzyValueTypeI64);
+    EXPECT_FALSE(global0.type.is_mutable);
+    EXPECT_EQ(global0.initializer.kind, FizzyConstantExpressionKindConstant);
+    EXPECT_EQ(global0.initializer.value.constant.i64, uint64_t(-1));
+
+    const auto global1 = fizzy_get_global(module, 1);
+    EXPECT_EQ(global1.type.value_type, FizzyValueTypeF32);
+    EXPECT_TRUE(global1.type.is_mutable);
+    EXPECT_EQ(global1.initializer.kind, FizzyConstantExpressionKindConstant);
+    EXPECT_EQ(global1.initializer.value.constant.i64, 0x3fc00000);
+
+    const auto global2 = fizzy_get_global(module, 2);
+    EXPECT_EQ(global2.type.value_type, FizzyValueTypeI32);
+    EXPECT_FALSE(global2.type.is_mutable);
+    EXPECT_EQ(global2.initializer.kind, FizzyConstantExpressionKindGlobalGet);
+    EXPECT_EQ(global2.initializer.value.global_index, 0);
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, get_data_segment)
+{
+    /* wat2wasm
+    (module
+      (global (import "env" "g") i32)
+      (memory 1)
+      (data (i32.const 16) "abc")
+      (data (global.get 0) "")
+    )
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000020a0103656e760167037f0005030100010b0e020041100b036162630023000b00");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    ASSERT_EQ(fizzy_get_data_segment_count(module), 2);
+
+    const auto data0 = fizzy_get_data_segment(module, 0);
+    EXPECT_EQ(data0.memory_index, 0);
+    EXPECT_EQ(data0.offset.kind, FizzyConstantExpressionKindConstant);
+    EXPECT_EQ(data0.offset.value.constant.i64, 16);
+    ASSERT_EQ(data0.size, 3);
+    EXPECT_EQ(fizzy::bytes(data0.data, data0.size), "616263"_bytes);
+
+    const auto data1 = fizzy_get_data_segment(module, 1);
+    EXPECT_EQ(data1.memory_index, 0);
+    EXPECT_EQ(data1.offset.kind, FizzyConstantExpressionKindGlobalGet);
+    EXPECT_EQ(data1.offset.value.global_index, 0);
+    EXPECT_EQ(data1.data, nullptr);
+    EXPECT_EQ(data1.size, 0);
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, get_element_segment)
+{
+    /* wat2wasm
+    (module
+      (global (import "env" "g") i32)
+      (table 4 anyfunc)
+      (elem (i32.const 1) $f1 $f0 $f1)
+      (elem (global.get 0))
+      (func $f0)
+      (func $f1)
+    )
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010401600000020a0103656e760167037f000303020000040401700004090e020041010b03"
+        "0100010023000b000a070202000b02000b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    ASSERT_EQ(fizzy_get_element_segment_count(module), 2);
+
+    const auto elem0 = fizzy_get_element_segment(module, 0);
+    EXPECT_EQ(elem0.table_index, 0);
+    EXPECT_EQ(elem0.offset.kind, FizzyConstantExpressionKindConstant);
+    EXPECT_EQ(elem0.offset.value.constant.i64, 1);
+    ASSERT_EQ(elem0.func_indices_size, 3);
+    EXPECT_EQ(elem0.func_indices[0], 1);
+    EXPECT_EQ(elem0.func_indices[1], 0);
+    EXPECT_EQ(elem0.func_indices[2], 1);
+
+    const auto elem1 = fizzy_get_element_segment(module, 1);
+    EXPECT_EQ(elem1.table_index, 0);
+    EXPECT_EQ(elem1.offset.kind, FizzyConstantExpressionKindGlobalGet);
+    EXPECT_EQ(elem1.offset.value.global_index, 0);
+    EXPECT_EQ(elem1.func_indices, nullptr);
+    EXPECT_EQ(elem1.func_indices_size, 0);
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, find_exported_function)
+{
+    /* wat2wasm
+    (module
+      (func $f (export "foo") (result i32) (i32.const 42))
+      (global (export "g1") i32 (i32.const 0))
+      (table (export "tab") 0 anyfunc)
+      (memory (export "mem") 1 2)
+    )
+    */
+    const auto wasm = from_hex(
+        "0061736d010000000105016000017f030201000404017000000504010101020606017f0041000b07180403666f"
+        "6f00000267310300037461620100036d656d02000a06010400412a0b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    EXPECT_NE(module, nullptr);
+
+    uint32_t func_idx;
+    EXPECT_TRUE(fizzy_find_exported_function(module, "foo", &func_idx));
+    EXPECT_EQ(func_idx, 0);
+
+    EXPECT_FALSE(fizzy_find_exported_function(module, "bar", &func_idx));
+    EXPECT_FALSE(fizzy_find_exported_function(module, "g1", &func_idx));
+    EXPECT_FALSE(fizzy_find_exported_function(module, "tab", &func_idx));
+    EXPECT_FALSE(fizzy_find_exported_function(module, "mem", &func_idx));
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, get_function_metrics)
+{
+    /* wat2wasm
+    (module
+      (func (import "env" "f") (param i32))
+      (func (param i32) (local i64 i64)
+        (drop (i32.add (i32.const 1) (i32.const 2)))
+      )
+    )
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001050160017f0002090103656e7601660000030201000a0c010a01027e410141026a1a0b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    FizzyFunctionMetrics metrics;
+    EXPECT_FALSE(fizzy_get_function_metrics(module, 0, &metrics));
+    EXPECT_FALSE(fizzy_get_function_metrics(module, 2, &metrics));
+
+    ASSERT_TRUE(fizzy_get_function_metrics(module, 1, &metrics));
+    EXPECT_EQ(metrics.body_size, 10);
+    EXPECT_EQ(metrics.local_count, 2);
+    EXPECT_EQ(metrics.max_stack_height, 2);
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, get_function_opcodes)
+{
+    /* wat2wasm
+    (module
+      (func (import "env" "f") (param i32) (result i32))
+      (memory (import "env" "mem") 1 3)
+      (table 2 anyfunc)
+      (func (export "f") (param i32) (result i32)
+        (i32.add (i32.load (local.get 0)) (i32.const 1))
+        (drop (i32.const 0))
+        (block (nop))
+      )
+      (export "mem" (memory 0))
+    )
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001060160017f017f02150203656e760166000003656e76036d656d0201010303020100040401"
+        "700002070b0201660001036d656d02000a13011100200028020041016a41001a0240010b0b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    EXPECT_EQ(fizzy_get_function_count(module), 2);
+
+    EXPECT_EQ(fizzy_get_function_opcodes(module, 0, nullptr, 0), 0);
+    EXPECT_EQ(fizzy_get_function_opcodes(module, 2, nullptr, 0), 0);
+
+    ASSERT_EQ(fizzy_get_function_opcodes(module, 1, nullptr, 0), 10);
+    std::vector<uint8_t> opcodes(10);
+    EXPECT_EQ(fizzy_get_function_opcodes(module, 1, opcodes.data(), 3), 10);
+    EXPECT_EQ(opcodes, (std::vector<uint8_t>{0x20, 0x28, 0x41, 0, 0, 0, 0, 0, 0, 0}));
+    EXPECT_EQ(fizzy_get_function_opcodes(module, 1, opcodes.data(), opcodes.size()), 10);
+    EXPECT_EQ(opcodes,
+        (std::vector<uint8_t>{0x20, 0x28, 0x41, 0x6a, 0x41, 0x1a, 0x02, 0x01, 0x0b, 0x0b}));
+
+    FizzyLimits limits;
+    ASSERT_TRUE(fizzy_get_table_limits(module, &limits));
+    EXPECT_EQ(limits.min, 2);
+    EXPECT_FALSE(limits.has_max);
+    ASSERT_TRUE(fizzy_get_memory_limits(module, &limits));
+    EXPECT_EQ(limits.min, 1);
+    EXPECT_TRUE(limits.has_max);
+    EXPECT_EQ(limits.max, 3);
+    EXPECT_EQ(fizzy_get_memory_count(module), 1);
+
+    fizzy_free_module(module);
+
+    /* wat2wasm
+    (module)
+    */
+    const auto wasm_empty = from_hex("0061736d01000000");
+    module = fizzy_parse(wasm_empty.data(), wasm_empty.size());
+    ASSERT_NE(module, nullptr);
+
+    EXPECT_EQ(fizzy_get_function_count(module), 0);
+    EXPECT_FALSE(fizzy_get_table_limits(module, &limits));
+    EXPECT_FALSE(fizzy_get_memory_limits(module, &limits));
+    EXPECT_EQ(fizzy_get_memory_count(module), 0);
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, get_function_callees)
+{
+    /* wat2wasm
+    (module
+      (func $f (import "env" "f"))
+      (func (call $b) (call $f) (call $b))
+      (func $b)
+    )
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001040160000002090103656e760166000003030200000a0d0208001002100010020b02000"
+        "b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    EXPECT_EQ(fizzy_get_function_callees(module, 0, nullptr, 0), 0);
+    EXPECT_EQ(fizzy_get_function_callees(module, 2, nullptr, 0), 0);
+    EXPECT_EQ(fizzy_get_function_callees(module, 3, nullptr, 0), 0);
+
+    ASSERT_EQ(fizzy_get_function_callees(module, 1, nullptr, 0), 3);
+    std::vector<uint32_t> callees(3);
+    EXPECT_EQ(fizzy_get_function_callees(module, 1, callees.data(), 2), 3);
+    EXPECT_EQ(callees, (std::vector<uint32_t>{2, 0, 0}));
+    EXPECT_EQ(fizzy_get_function_callees(module, 1, callees.data(), callees.size()), 3);
+    EXPECT_EQ(callees, (std::vector<uint32_t>{2, 0, 2}));
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, get_start_function)
+{
+    /* wat2wasm
+    (module
+      (func)
+      (func $start)
+      (start $start)
+    )
+    */
+    const auto wasm = from_hex("0061736d0100000001040160000003030200000801010a070202000b02000b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    uint32_t func_idx = 0;
+    ASSERT_TRUE(fizzy_get_start_function(module, &func_idx));
+    EXPECT_EQ(func_idx, 1);
+    fizzy_free_module(module);
+
+    /* wat2wasm
+    (module)
+    */
+    const auto wasm_empty = from_hex("0061736d01000000");
+    module = fizzy_parse(wasm_empty.data(), wasm_empty.size());
+    ASSERT_NE(module, nullptr);
+    EXPECT_FALSE(fizzy_get_start_function(module, &func_idx));
+    fizzy_free_module(module);
+}
+
+TEST(capi, get_import_description)
+{
+    /* wat2wasm
+    (module
+      (func (import "mod1" "foo") (param i32 i64) (result f32))
+      (table (import "mod2" "t") 1 2 anyfunc)
+      (memory (import "mod3" "mem") 1)
+      (global (import "mod4" "g") (mut i64))
+    )
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001070160027f7e017d022e04046d6f643103666f6f0000046d6f643201740170010102046d"
+        "6f6433036d656d020001046d6f64340167037e01");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    ASSERT_EQ(fizzy_get_import_count(module), 4);
+
+    const auto import0 = fizzy_get_import_description(module, 0);
+    EXPECT_STREQ(import0.module, "mod1");
+    EXPECT_STREQ(import0.name, "foo");
+    EXPECT_EQ(import0.kind, FizzyExternalKindFunction);
+    ASSERT_EQ(import0.desc.function_type.inputs_size, 2);
+    EXPECT_EQ(import0.desc.function_type.inputs[0], FizzyValueTypeI32);
+    EXPECT_EQ(import0.desc.function_type.inputs[1], FizzyValueTypeI64);
+    EXPECT_EQ(import0.desc.function_type.output, FizzyValueTypeF32);
+
+    const auto import1 = fizzy_get_import_description(module, 1);
+    EXPECT_STREQ(import1.module, "mod2");
+    EXPECT_STREQ(import1.name, "t");
+    EXPECT_EQ(import1.kind, FizzyExternalKindTable);
+    EXPECT_EQ(import1.desc.table_limits.min, 1);
+    EXPECT_TRUE(import1.desc.table_limits.has_max);
+    EXPECT_EQ(import1.desc.table_limits.max, 2);
+
+    const auto import2 = fizzy_get_import_description(module, 2);
+    EXPECT_STREQ(import2.module, "mod3");
+    EXPECT_STREQ(import2.name, "mem");
+    EXPECT_EQ(import2.kind, FizzyExternalKindMemory);
+    EXPECT_EQ(import2.desc.memory_limits.min, 1);
+    EXPECT_FALSE(import2.desc.memory_limits.has_max);
+
+    const auto import3 = fizzy_get_import_description(module, 3);
+    EXPECT_STREQ(import3.module, "mod4");
+    EXPECT_STREQ(import3.name, "g");
+    EXPECT_EQ(import3.kind, FizzyExternalKindGlobal);
+    EXPECT_EQ(import3.desc.global_type.value_type, FizzyValueTypeI64);
+    EXPECT_TRUE(import3.desc.global_type.is_mutable);
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, get_export_description)
+{
+    /* wat2wasm
+    (module
+      (func $f (export "foo") (result i32) (i32.const 42))
+      (global (export "g1") i32 (i32.const 0))
+      (table (export "tab") 0 anyfunc)
+      (memory (export "mem") 1 2)
+    )
+    */
+    const auto wasm = from_hex(
+        "0061736d010000000105016000017f030201000404017000000504010101020606017f0041000b07180403666f"
+        "6f00000267310300037461620100036d656d02000a06010400412a0b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    ASSERT_EQ(fizzy_get_export_count(module), 4);
+
+    const auto export0 = fizzy_get_export_description(module, 0);
+    EXPECT_STREQ(export0.name, "foo");
+    EXPECT_EQ(export0.kind, FizzyExternalKindFunction);
+    EXPECT_EQ(export0.index, 0);
+
+    const auto export1 = fizzy_get_export_description(module, 1);
+    EXPECT_STREQ(export1.name, "g1");
+    EXPECT_EQ(export1.kind, FizzyExternalKindGlobal);
+    EXPECT_EQ(export1.index, 0);
+
+    const auto export2 = fizzy_get_export_description(module, 2);
+    EXPECT_STREQ(export2.name, "tab");
+    EXPECT_EQ(export2.kind, FizzyExternalKindTable);
+    EXPECT_EQ(export2.index, 0);
+
+    const auto export3 = fizzy_get_export_description(module, 3);
+    EXPECT_STREQ(export3.name, "mem");
+    EXPECT_EQ(export3.kind, FizzyExternalKindMemory);
+    EXPECT_EQ(export3.index, 0);
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, instantiate)
+{
+    uint8_t wasm_prefix[]{0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00};
+    auto module = fizzy_parse(wasm_prefix, sizeof(wasm_prefix));
+    EXPECT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    EXPECT_NE(instance, nullptr);
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, instantiate_imported_function)
+{
+    /* wat2wasm
+      (func (import "mod1" "foo1") (result i32))
+    */
+    const auto wasm = from_hex("0061736d010000000105016000017f020d01046d6f643104666f6f310000");
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    EXPECT_NE(module, nullptr);
+
+    EXPECT_EQ(fizzy_instantiate(module, nullptr, 0), nullptr);
+
+    module = fizzy_parse(wasm.data(), wasm.size());
+    EXPECT_NE(module, nullptr);
+
+    FizzyExternalFunction host_funcs[] = {
+        {[](void*, FizzyInstance*, const FizzyValue*, size_t, int) {
+             return FizzyExecutionResult{false, true, {42}};
+         },
+            nullptr}};
+
+    auto instance = fizzy_instantiate(module, host_funcs, 1);
+    EXPECT_NE(instance, nullptr);
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, get_instance_module)
+{
+    /* wat2wasm
+      (func (result i32) i32.const 0)
+    */
+    const auto wasm = from_hex("0061736d010000000105016000017f030201000a0601040041000b");
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    ASSERT_NE(instance, nullptr);
+
+    auto instance_module = fizzy_get_instance_module(instance);
+    ASSERT_NE(instance_module, nullptr);
+
+    EXPECT_EQ(fizzy_get_function_type(instance_module, 0).output, FizzyValueTypeI32);
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, free_instance_null)
+{
+    fizzy_free_instance(nullptr);
+}
+
+TEST(capi, memory_access_no_memory)
+{
+    /* wat2wasm
+      (module)
+    */
+    const auto wasm = from_hex("0061736d01000000");
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    EXPECT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    EXPECT_NE(instance, nullptr);
+
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance), nullptr);
+    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 0);
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, memory_access)
+{
+    /* wat2wasm
+      (memory 1)
+      (data (i32.const 1) "\11\22")
+      (func (result i32)
+        i32.const 0
+        i32.load
+      )
+    */
+    const auto wasm = from_hex(
+        "0061736d010000000105016000017f0302010005030100010a0901070041002802000b0b08010041010b02112"
+        "2");
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    EXPECT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    EXPECT_NE(instance, nullptr);
+
+    uint8_t* memory = fizzy_get_instance_memory_data(instance);
+    EXPECT_NE(memory, nullptr);
+    EXPECT_EQ(memory[0], 0);
+    EXPECT_EQ(memory[1], 0x11);
+    EXPECT_EQ(memory[2], 0x22);
+    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 65536);
+
+    memory[0] = 0xaa;
+    memory[1] = 0xbb;
+
+    EXPECT_EQ(fizzy_execute(instance, 0, nullptr, 0).value.i64, 0x22bbaa);
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, get_instance_state_hash)
+{
+    /* wat2wasm
+      (memory 1)
+      (global (mut i32) (i32.const 1))
+    */
+    const auto wasm = from_hex("0061736d0100000005030100010606017f0141010b");
+    auto module1 = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module1, nullptr);
+    auto instance1 = fizzy_instantiate(module1, nullptr, 0);
+    ASSERT_NE(instance1, nullptr);
+    auto module2 = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module2, nullptr);
+    auto instance2 = fizzy_instantiate(module2, nullptr, 0);
+    ASSERT_NE(instance2, nullptr);
+
+    uint8_t hash1[32];
+    uint8_t hash2[32];
+    fizzy_get_instance_state_hash(instance1, hash1);
+    fizzy_get_instance_state_hash(instance2, hash2);
+    EXPECT_EQ(hex(hash1, sizeof(hash1)), hex(hash2, sizeof(hash2)));
+
+    fizzy_get_instance_memory_data(instance1)[0] = 1;
+    fizzy_get_instance_state_hash(instance1, hash1);
+    EXPECT_NE(hex(hash1, sizeof(hash1)), hex(hash2, sizeof(hash2)));
+
+    fizzy_free_instance(instance2);
+    fizzy_free_instance(instance1);
+}
+
+TEST(capi, memory_read_only)
+{
+    /* wat2wasm
+      (memory 1)
+      (func (param i32 i32)
+        get_local 0
+        get_local 1
+        i32.store16 offset=2
+      )
+      (func (param i32) (result i32)
+        get_local 0
+        i32.load
+      )
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010b0260027f7f0060017f017f030302000105030100010a13020900200020013b01020b07"
+        "0020002802000b");
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    ASSERT_NE(instance, nullptr);
+
+    EXPECT_FALSE(fizzy_add_instance_memory_read_only_range(instance, 0, 65535, 2));
+    EXPECT_FALSE(fizzy_add_instance_memory_read_only_range(instance, 1, 8, 4));
+    EXPECT_TRUE(fizzy_add_instance_memory_read_only_range(instance, 0, 8, 4));
+
+    FizzyValue args[] = {{6}, {0x1122}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Traps());
+    // The host can write the read-only memory.
+    fizzy_get_instance_memory_data(instance)[8] = 0x33;
+    FizzyValue load_args[] = {{8}};
+    EXPECT_THAT(fizzy_execute(instance, 1, load_args, 0), Result(0x33));
+
+    fizzy_clear_instance_memory_read_only_ranges(instance, 1);
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Traps());
+    fizzy_clear_instance_memory_read_only_ranges(instance, 0);
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result());
+    EXPECT_THAT(fizzy_execute(instance, 1, load_args, 0), Result(0x1122));
+
+    fizzy_free_instance(instance);
+
+    /* wat2wasm
+      (module)
+    */
+    const auto wasm_no_memory = from_hex("0061736d01000000");
+    module = fizzy_parse(wasm_no_memory.data(), wasm_no_memory.size());
+    ASSERT_NE(module, nullptr);
+    instance = fizzy_instantiate(module, nullptr, 0);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_FALSE(fizzy_add_instance_memory_read_only_range(instance, 0, 0, 0));
+    fizzy_clear_instance_memory_read_only_ranges(instance, 0);
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, memory_access_mapped)
+{
+    /* wat2wasm
+      (memory 1 2)
+      (data (i32.const 1) "\11\22")
+      (func (result i32)
+        i32.const 1
+        memory.grow
+      )
+    */
+    const auto wasm = from_hex(
+        "0061736d010000000105016000017f030201000504010101020a08010600410140000b0b08010041010b021122"
+        "");
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    FizzyInstantiateOptions options{};
+    options.memory_backing = FizzyMemoryBackingMapped;
+    auto instance = fizzy_instantiate_with_options(
+        module, nullptr, 0, nullptr, nullptr, 0, nullptr, 0, &options, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    const uint8_t* memory = fizzy_get_instance_memory_data(instance);
+    ASSERT_NE(memory, nullptr);
+    EXPECT_EQ(memory[1], 0x11);
+    EXPECT_EQ(memory[2], 0x22);
+    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 65536);
+
+    EXPECT_THAT(fizzy_execute(instance, 0, nullptr, 0), Result(1));
+    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 2 * 65536);
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance)[1], 0x11);
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance)[65536], 0);
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, defer_start)
+{
+    /* wat2wasm
+    (global (export "g") (mut i32) (i32.const 0))
+    (start 0)
+    (func (global.set 0 (i32.add (global.get 0) (i32.const 1))))
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010401600000030201000606017f0141000b070501016703000801000a0b010900230041"
+        "016a24000b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    FizzyInstantiateOptions options{};
+    options.defer_start = true;
+    auto instance = fizzy_instantiate_with_options(
+        module, nullptr, 0, nullptr, nullptr, 0, nullptr, 0, &options, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    FizzyExternalGlobal global;
+    ASSERT_TRUE(fizzy_find_exported_global(instance, "g", &global));
+    EXPECT_EQ(global.value->i64, 0);
+    global.value->i64 = 10;
+    EXPECT_THAT(fizzy_run_start(instance), Result());
+    EXPECT_EQ(global.value->i64, 11);
+    EXPECT_THAT(fizzy_run_start(instance), Result());
+    EXPECT_EQ(global.value->i64, 11);
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, start_function_trapped)
+{
+    /* wat2wasm
+    (start 0)
+    (func (unreachable))
+    */
+    const auto wasm = from_hex("0061736d01000000010401600000030201000801000a05010300000b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    FizzyError error;
+    EXPECT_EQ(fizzy_instantiate_with_options(
+                  module, nullptr, 0, nullptr, nullptr, 0, nullptr, 0, nullptr, &error),
+        nullptr);
+    EXPECT_EQ(error.code, FizzyErrorStartFunctionTrapped);
+    EXPECT_STREQ(error.message, "start function failed to execute");
+
+    module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    FizzyInstantiateOptions options{};
+    options.defer_start = true;
+    auto instance = fizzy_instantiate_with_options(
+        module, nullptr, 0, nullptr, nullptr, 0, nullptr, 0, &options, &error);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_EQ(error.code, FizzySuccess);
+    EXPECT_THAT(fizzy_run_start(instance), Traps());
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, duplicate_instance)
+{
+    /* wat2wasm
+    (table 1 anyfunc)
+    (memory 1)
+    (global $g (mut i32) (i32.const 0))
+    (func $inc (export "inc") (result i32)
+      (global.set $g (i32.add (global.get $g) (i32.const 1)))
+      (i32.store (i32.const 0) (global.get $g))
+      (global.get $g))
+    (func (export "call") (result i32) (call_indirect (result i32) (i32.const 0)))
+    (elem (i32.const 0) $inc)
+    */
+    const auto wasm = from_hex(
+        "0061736d010000000105016000017f030302000004040170000105030100010606017f0141000b070e0203696e"
+        "6300000463616c6c00010907010041000b01000a1c021200230041016a24004100230036020023000b070041"
+        "001100000b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_THAT(fizzy_execute(instance, 0, nullptr, 0), Result(1));
+
+    FizzyError error;
+    auto copy = fizzy_duplicate_instance(instance, &error);
+    ASSERT_NE(copy, nullptr);
+    EXPECT_EQ(error.code, FizzySuccess);
+    EXPECT_EQ(fizzy_get_instance_module(copy), fizzy_get_instance_module(instance));
+    EXPECT_NE(fizzy_get_instance_memory_data(copy), fizzy_get_instance_memory_data(instance));
+    EXPECT_EQ(fizzy_get_instance_memory_size(copy), 65536);
+    EXPECT_EQ(fizzy_get_instance_memory_data(copy)[0], 1);
+
+    EXPECT_THAT(fizzy_execute(instance, 0, nullptr, 0), Result(2));
+    EXPECT_THAT(fizzy_execute(instance, 0, nullptr, 0), Result(3));
+    EXPECT_THAT(fizzy_execute(copy, 1, nullptr, 0), Result(2));
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance)[0], 3);
+    EXPECT_EQ(fizzy_get_instance_memory_data(copy)[0], 2);
+
+    // The copy does not refer to the instance.
+    fizzy_free_instance(instance);
+    EXPECT_THAT(fizzy_execute(copy, 1, nullptr, 0), Result(3));
+    EXPECT_EQ(fizzy_get_instance_memory_data(copy)[0], 3);
+    fizzy_free_instance(copy);
+}
+
+TEST(capi, memory_pages_limit)
+{
+    /* wat2wasm
+    (memory 1)
+    (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001060160017f017f0302010005030100010708010467726f7700000a08010600200040000b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    FizzyInstantiateOptions options{};
+    options.memory_pages_limit = 2;
+    auto instance = fizzy_instantiate_with_options(
+        module, nullptr, 0, nullptr, nullptr, 0, nullptr, 0, &options, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    const FizzyValue args[] = {{1}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(1));
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(uint32_t(-1)));
+    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 2 * 65536);
+    fizzy_free_instance(instance);
+
+    module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    options.memory_pages_limit = 0;
+    instance = fizzy_instantiate_with_options(
+        module, nullptr, 0, nullptr, nullptr, 0, nullptr, 0, &options, nullptr);
+    ASSERT_NE(instance, nullptr);
+    const FizzyValue big_args[] = {{4096}};
+    EXPECT_THAT(fizzy_execute(instance, 0, big_args, 0), Result(uint32_t(-1)));
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(1));
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, call_stack_limit)
+{
+    /* wat2wasm
+    (func (export "f") (param i32) (result i32)
+      (if (result i32) (local.get 0)
+        (then (call 0 (i32.sub (local.get 0) (i32.const 1))))
+        (else (i32.const 0))))
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001060160017f017f03020100070501016600000a130111002000047f200041016b100005"
+        "41000b0b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    FizzyInstantiateOptions options{};
+    options.call_stack_limit = 10;
+    auto instance = fizzy_instantiate_with_options(
+        module, nullptr, 0, nullptr, nullptr, 0, nullptr, 0, &options, nullptr);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_EQ(fizzy_get_instance_call_stack_limit(instance), 10);
+
+    const FizzyValue args10[] = {{10}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args10, 0), Result(0));
+    const FizzyValue args11[] = {{11}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args11, 0), Traps());
+    fizzy_free_instance(instance);
+
+    // The limit greater than the default one is reduced to it.
+    module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    options.call_stack_limit = 100000;
+    instance = fizzy_instantiate_with_options(
+        module, nullptr, 0, nullptr, nullptr, 0, nullptr, 0, &options, nullptr);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_EQ(fizzy_get_instance_call_stack_limit(instance), 2048);
+    const FizzyValue args_max[] = {{2048}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args_max, 0), Result(0));
+    const FizzyValue args_over[] = {{2049}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args_over, 0), Traps());
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, memory_allocator)
+{
+    /* wat2wasm
+      (memory 1 2)
+      (data (i32.const 1) "\11\22")
+      (func (result i32)
+        i32.const 1
+        memory.grow
+      )
+    */
+    const auto wasm = from_hex(
+        "0061736d010000000105016000017f030201000504010101020a08010600410140000b0b08010041010b021122"
+        "");
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    size_t allocated = 0;
+    FizzyAllocator allocator;
+    allocator.allocate = [](void* context, size_t size) -> void* {
+        *static_cast<size_t*>(context) += size;
+        return malloc(size);
+    };
+    allocator.reallocate = [](void* context, void* ptr, size_t old_size,
+                               size_t new_size) -> void* {
+        *static_cast<size_t*>(context) += new_size - old_size;
+        return realloc(ptr, new_size);
+    };
+    allocator.deallocate = [](void* context, void* ptr, size_t size) {
+        *static_cast<size_t*>(context) -= size;
+        free(ptr);
+    };
+    allocator.context = &allocated;
+
+    FizzyInstantiateOptions options{};
+    options.memory_backing = FizzyMemoryBackingHeap;
+    options.memory_allocator = &allocator;
+    auto instance = fizzy_instantiate_with_options(
+        module, nullptr, 0, nullptr, nullptr, 0, nullptr, 0, &options, nullptr);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_EQ(allocated, 65536);
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance)[1], 0x11);
+
+    EXPECT_THAT(fizzy_execute(instance, 0, nullptr, 0), Result(1));
+    EXPECT_EQ(allocated, 2 * 65536);
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance)[2], 0x22);
+
+    fizzy_free_instance(instance);
+    EXPECT_EQ(allocated, 0);
+}
+
+TEST(capi, memory_allocation_failure)
+{
+    /* wat2wasm
+      (memory 1 2)
+      (data (i32.const 1) "\11\22")
+      (func (result i32)
+        i32.const 1
+        memory.grow
+      )
+    */
+    const auto wasm = from_hex(
+        "0061736d010000000105016000017f030201000504010101020a08010600410140000b0b08010041010b021122"
+        "");
+
+    // The allocator failing the allocations above the limit stored in the context.
+    FizzyAllocator allocator;
+    allocator.allocate = [](void* context, size_t size) -> void* {
+        return (size <= *static_cast<size_t*>(context)) ? malloc(size) : nullptr;
+    };
+    allocator.reallocate = [](void* context, void* ptr, size_t, size_t new_size) -> void* {
+        return (new_size <= *static_cast<size_t*>(context)) ? realloc(ptr, new_size) : nullptr;
+    };
+    allocator.deallocate = [](void*, void* ptr, size_t) { free(ptr); };
+    size_t limit = 0;
+    allocator.context = &limit;
+    FizzyInstantiateOptions options{};
+    options.memory_backing = FizzyMemoryBackingHeap;
+    options.memory_allocator = &allocator;
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    FizzyError error;
+    EXPECT_EQ(fizzy_instantiate_with_options(
+                  module, nullptr, 0, nullptr, nullptr, 0, nullptr, 0, &options, &error),
+        nullptr);
+    EXPECT_EQ(error.code, FizzyErrorMemoryAllocationFailed);
+    EXPECT_STREQ(error.message, "memory allocation failed");
+
+    limit = 65536;
+    module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    auto instance = fizzy_instantiate_with_options(
+        module, nullptr, 0, nullptr, nullptr, 0, nullptr, 0, &options, &error);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_EQ(error.code, FizzySuccess);
+
+    // The failed growth is reported to the instance.
+    EXPECT_THAT(fizzy_execute(instance, 0, nullptr, 0), Result(-1));
+    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 65536);
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance)[1], 0x11);
+
+    limit = 2 * 65536;
+    EXPECT_THAT(fizzy_execute(instance, 0, nullptr, 0), Result(1));
+    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 2 * 65536);
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, imported_memory_access)
+{
+    /* wat2wasm
+      (memory (import "mod" "mem") 1)
+    */
+    const auto wasm = from_hex("0061736d01000000020c01036d6f64036d656d020001");
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    EXPECT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    EXPECT_EQ(instance, nullptr);
+}
+
+TEST(capi, create_memory)
+{
+    EXPECT_EQ(fizzy_create_memory(65537), nullptr);
+    fizzy_free_memory(nullptr);
+
+    auto memory = fizzy_create_memory(1);
+    ASSERT_NE(memory, nullptr);
+    EXPECT_EQ(fizzy_get_memory_size(memory), 65536);
+    ASSERT_NE(fizzy_get_memory_data(memory), nullptr);
+    EXPECT_EQ(fizzy_get_memory_data(memory)[65535], 0);
+
+    /* wat2wasm
+      (memory (import "m" "mem") 1 2)
+      (func (param i32 i32) (i32.store (local.get 0) (local.get 1)))
+      (func (param i32) (result i32) (i32.load (local.get 0)))
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010b0260027f7f0060017f017f020b01016d036d656d0201010203030200010a1302090020"
+        "0020013602000b070020002802000b");
+
+    const FizzyExternalMemory external_memory{memory, {1, 2, true}};
+    auto module1 = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module1, nullptr);
+    auto instance1 = fizzy_instantiate_with_imports(
+        module1, nullptr, 0, nullptr, &external_memory, nullptr, 0);
+    ASSERT_NE(instance1, nullptr);
+    auto module2 = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module2, nullptr);
+    auto instance2 = fizzy_instantiate_with_imports(
+        module2, nullptr, 0, nullptr, &external_memory, nullptr, 0);
+    ASSERT_NE(instance2, nullptr);
+
+    FizzyValue args[] = {{4}, {42}};
+    EXPECT_THAT(fizzy_execute(instance1, 0, args, 0), Result());
+    EXPECT_THAT(fizzy_execute(instance2, 1, args, 0), Result(42));
+    EXPECT_EQ(fizzy_get_memory_data(memory)[4], 42);
+
+    fizzy_free_instance(instance2);
+    fizzy_free_instance(instance1);
+    fizzy_free_memory(memory);
+}
+
+TEST(capi, execute)
+{
+    /* wat2wasm
+      (func)
+      (func (result i32) i32.const 42)
+      (func (param i32 i32) (result i32)
+        (i32.div_u (local.get 0) (local.get 1))
+      )
+      (func unreachable)
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010e036000006000017f60027f7f017f030504000102000a150402000b0400412a0b070020"
+        "0020016e0b0300000b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
     EXPECT_NE(module, nullptr);
-    fizzy_free_module(module);
-    wasm_prefix[7] = 1;
-    EXPECT_EQ(fizzy_parse(wasm_prefix, sizeof(wasm_prefix)), nullptr);
+
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    EXPECT_NE(instance, nullptr);
+
+    EXPECT_THAT(fizzy_execute(instance, 0, nullptr, 0), Result());
+    EXPECT_THAT(fizzy_execute(instance, 1, nullptr, 0), Result(42));
+    FizzyValue args[] = {{42}, {2}};
+    EXPECT_THAT(fizzy_execute(instance, 2, args, 0), Result(21));
+    EXPECT_THAT(fizzy_execute(instance, 3, nullptr, 0), Traps());
+
+    fizzy_free_instance(instance);
 }
 
-TEST(capi, free_module_null)
+TEST(capi, instruction_hook)
 {
-    fizzy_free_module(nullptr);
+    /* wat2wasm
+      (func (param i32 i32) (result i32)
+        (i32.div_u (local.get 0) (local.get 1))
+      )
+    */
+    const auto wasm = from_hex("0061736d0100000001070160027f7f017f030201000a09010700200020016e0b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    ASSERT_NE(instance, nullptr);
+
+    std::vector<FizzyExecutionState> states;
+    const auto hook = [](void* context, FizzyInstance*, const FizzyExecutionState* state) {
+        static_cast<std::vector<FizzyExecutionState>*>(context)->push_back(*state);
+        return true;
+    };
+    fizzy_set_instruction_hook(instance, hook, &states);
+
+    FizzyValue args[] = {{42}, {2}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(21));
+
+    ASSERT_EQ(states.size(), 4);
+    EXPECT_EQ(states[0].func_idx, 0);
+    EXPECT_EQ(states[0].instr_offset, 0);
+    EXPECT_EQ(states[0].opcode, 0x20);
+    EXPECT_EQ(states[0].locals_size, 2);
+    EXPECT_EQ(states[0].stack_size, 0);
+    EXPECT_EQ(states[2].opcode, 0x6e);
+    EXPECT_EQ(states[2].instr_offset, 2);
+    EXPECT_EQ(states[2].stack_size, 2);
+    EXPECT_EQ(states[3].opcode, 0x0b);
+    EXPECT_EQ(states[3].stack_size, 1);
+
+    const auto abort_hook = [](void*, FizzyInstance*, const FizzyExecutionState*) { return false; };
+    fizzy_set_instruction_hook(instance, abort_hook, nullptr);
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Traps());
+
+    fizzy_set_instruction_hook(instance, nullptr, nullptr);
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(21));
+
+    fizzy_free_instance(instance);
 }
 
-TEST(capi, find_exported_function)
+TEST(capi, periodic_hook)
 {
     /* wat2wasm
-    (module
-      (func $f (export "foo") (result i32) (i32.const 42))
-      (global (export "g1") i32 (i32.const 0))
-      (table (export "tab") 0 anyfunc)
-      (memory (export "mem") 1 2)
-    )
+      (func (param i32 i32) (result i32)
+        (i32.div_u (local.get 0) (local.get 1))
+      )
     */
-    const auto wasm = from_hex(
-        "0061736d010000000105016000017f030201000404017000000504010101020606017f0041000b07180403666f"
-        "6f00000267310300037461620100036d656d02000a06010400412a0b");
+    const auto wasm = from_hex("0061736d0100000001070160027f7f017f030201000a09010700200020016e0b");
 
     auto module = fizzy_parse(wasm.data(), wasm.size());
-    EXPECT_NE(module, nullptr);
+    ASSERT_NE(module, nullptr);
 
-    uint32_t func_idx;
-    EXPECT_TRUE(fizzy_find_exported_function(module, "foo", &func_idx));
-    EXPECT_EQ(func_idx, 0);
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    ASSERT_NE(instance, nullptr);
 
-    EXPECT_FALSE(fizzy_find_exported_function(module, "bar", &func_idx));
-    EXPECT_FALSE(fizzy_find_exported_function(module, "g1", &func_idx));
-    EXPECT_FALSE(fizzy_find_exported_function(module, "tab", &func_idx));
-    EXPECT_FALSE(fizzy_find_exported_function(module, "mem", &func_idx));
+    int calls = 0;
+    const auto hook = [](void* context, FizzyInstance*) {
+        ++*static_cast<int*>(context);
+        return true;
+    };
+    fizzy_set_periodic_hook(instance, 3, hook, &calls);
 
-    fizzy_free_module(module);
+    // Each execution runs 4 instructions, counted across the executions.
+    FizzyValue args[] = {{42}, {2}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(21));
+    EXPECT_EQ(calls, 1);
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(21));
+    EXPECT_EQ(calls, 2);
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(21));
+    EXPECT_EQ(calls, 4);
+
+    const auto abort_hook = [](void*, FizzyInstance*) { return false; };
+    fizzy_set_periodic_hook(instance, 5, abort_hook, nullptr);
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(21));
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Traps());
+
+    fizzy_set_periodic_hook(instance, 0, abort_hook, nullptr);
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(21));
+
+    fizzy_free_instance(instance);
 }
 
-TEST(capi, instantiate)
+TEST(capi, instruction_hook_memory_offset)
 {
-    uint8_t wasm_prefix[]{0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00};
-    auto module = fizzy_parse(wasm_prefix, sizeof(wasm_prefix));
-    EXPECT_NE(module, nullptr);
+    /* wat2wasm
+      (memory 1)
+      (func (param i32) (result i32)
+        (i32.store offset=16 (local.get 0) (i32.const 7))
+        (i32.load offset=16 (local.get 0))
+      )
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001060160017f017f0302010005030100010a10010e002000410736021020002802100b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
 
     auto instance = fizzy_instantiate(module, nullptr, 0);
-    EXPECT_NE(instance, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    std::vector<FizzyExecutionState> states;
+    const auto hook = [](void* context, FizzyInstance*, const FizzyExecutionState* state) {
+        static_cast<std::vector<FizzyExecutionState>*>(context)->push_back(*state);
+        return true;
+    };
+    fizzy_set_instruction_hook(instance, hook, &states);
+
+    FizzyValue args[] = {{4}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(7));
+
+    ASSERT_EQ(states.size(), 6);
+    EXPECT_EQ(states[1].opcode, 0x41);
+    EXPECT_EQ(states[1].memory_offset, 0);
+    EXPECT_EQ(states[2].opcode, 0x36);
+    EXPECT_EQ(states[2].memory_offset, 16);
+    EXPECT_EQ(states[4].opcode, 0x28);
+    EXPECT_EQ(states[4].memory_offset, 16);
+    EXPECT_EQ(states[4].stack_size, 1);
 
     fizzy_free_instance(instance);
 }
 
-TEST(capi, instantiate_imported_function)
+TEST(capi, memory_grow_hook)
 {
     /* wat2wasm
-      (func (import "mod1" "foo1") (result i32))
+    (memory 1 3)
+    (func (param i32) (result i32)
+      get_local 0
+      memory.grow
+    )
     */
-    const auto wasm = from_hex("0061736d010000000105016000017f020d01046d6f643104666f6f310000");
+    const auto wasm =
+        from_hex("0061736d0100000001060160017f017f030201000504010101030a08010600200040000b");
+
     auto module = fizzy_parse(wasm.data(), wasm.size());
-    EXPECT_NE(module, nullptr);
+    ASSERT_NE(module, nullptr);
 
-    EXPECT_EQ(fizzy_instantiate(module, nullptr, 0), nullptr);
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    ASSERT_NE(instance, nullptr);
 
-    module = fizzy_parse(wasm.data(), wasm.size());
-    EXPECT_NE(module, nullptr);
+    std::vector<std::pair<uint32_t, uint32_t>> growths;
+    const auto hook = [](void* context, FizzyInstance*, uint32_t memory_idx, uint32_t old_pages,
+                          uint32_t new_pages) {
+        EXPECT_EQ(memory_idx, 0);
+        static_cast<std::vector<std::pair<uint32_t, uint32_t>>*>(context)->emplace_back(
+            old_pages, new_pages);
+    };
+    fizzy_set_memory_grow_hook(instance, hook, &growths);
 
-    FizzyExternalFunction host_funcs[] = {
-        {[](void*, FizzyInstance*, const FizzyValue*, size_t, int) {
-             return FizzyExecutionResult{false, true, {42}};
-         },
-            nullptr}};
+    FizzyValue args[] = {{2}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(1));
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(-1));
+    EXPECT_EQ(growths, (std::vector<std::pair<uint32_t, uint32_t>>{{1, 3}}));
 
-    auto instance = fizzy_instantiate(module, host_funcs, 1);
-    EXPECT_NE(instance, nullptr);
+    fizzy_set_memory_grow_hook(instance, nullptr, nullptr);
+    args[0].i64 = 0;
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(3));
+    EXPECT_EQ(growths.size(), 1);
 
     fizzy_free_instance(instance);
 }
 
-TEST(capi, free_instance_null)
+TEST(capi, memory_grow_limiter)
 {
-    fizzy_free_instance(nullptr);
+    /* wat2wasm
+    (memory 1)
+    (func (param i32) (result i32)
+      get_local 0
+      memory.grow
+    )
+    */
+    const auto wasm =
+        from_hex("0061736d0100000001060160017f017f0302010005030100010a08010600200040000b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(module, nullptr, 0);
+    ASSERT_NE(instance, nullptr);
+
+    uint32_t max_pages = 0;
+    const auto limiter = [](void* context, FizzyInstance*, uint32_t memory_idx, uint32_t,
+                             uint32_t desired_pages, uint32_t _max_pages) {
+        EXPECT_EQ(memory_idx, 0);
+        *static_cast<uint32_t*>(context) = _max_pages;
+        return desired_pages <= 2;
+    };
+    fizzy_set_memory_grow_limiter(instance, limiter, &max_pages);
+
+    FizzyValue args[] = {{2}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(-1));
+    // The default hard limit of 256 MB.
+    EXPECT_EQ(max_pages, 4096);
+    args[0].i64 = 1;
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(1));
+
+    fizzy_set_memory_grow_limiter(instance, nullptr, nullptr);
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(2));
+
+    fizzy_free_instance(instance);
 }
 
-TEST(capi, memory_access_no_memory)
+TEST(capi, table_elements)
 {
     /* wat2wasm
-      (module)
+    (type $t (func (result i32)))
+    (table 1 3 funcref)
+    (elem (i32.const 0) $one)
+    (func $one (result i32) (i32.const 1))
+    (func $two (export "two") (result i32) (i32.const 2))
+    (func (export "call") (param i32) (result i32) (call_indirect (type $t) (local.get 0)))
     */
-    const auto wasm = from_hex("0061736d01000000");
+    const auto wasm = from_hex(
+        "0061736d01000000010a026000017f60017f017f03040300000104050170010103070e020374776f0001046361"
+        "6c6c00020907010041000b01000a1303040041010b040041020b070020001100000b");
+
     auto module = fizzy_parse(wasm.data(), wasm.size());
-    EXPECT_NE(module, nullptr);
+    ASSERT_NE(module, nullptr);
 
     auto instance = fizzy_instantiate(module, nullptr, 0);
-    EXPECT_NE(instance, nullptr);
+    ASSERT_NE(instance, nullptr);
 
-    EXPECT_EQ(fizzy_get_instance_memory_data(instance), nullptr);
-    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 0);
+    FizzyExternalTable table;
+    ASSERT_TRUE(fizzy_get_instance_table(instance, &table));
+    EXPECT_EQ(table.limits.min, 1);
+    EXPECT_TRUE(table.limits.has_max);
+    EXPECT_EQ(table.limits.max, 3);
+    EXPECT_EQ(fizzy_get_table_size(table.table), 1);
 
+    EXPECT_FALSE(fizzy_grow_table(table.table, 3, table.limits.max));
+    ASSERT_TRUE(fizzy_grow_table(table.table, 2, table.limits.max));
+    EXPECT_EQ(fizzy_get_table_size(table.table), 3);
+    EXPECT_EQ(fizzy_get_table_element(table.table, 2), nullptr);
+
+    FizzyValue args[] = {{2}};
+    EXPECT_THAT(fizzy_execute(instance, 2, args, 0), Traps());
+
+    EXPECT_EQ(fizzy_find_exported_funcref(instance, "one"), nullptr);
+    auto two = fizzy_find_exported_funcref(instance, "two");
+    ASSERT_NE(two, nullptr);
+    EXPECT_TRUE(fizzy_set_table_element(table.table, 2, two));
+    fizzy_free_funcref(two);
+    EXPECT_THAT(fizzy_execute(instance, 2, args, 0), Result(2));
+
+    auto one = fizzy_get_table_element(table.table, 0);
+    ASSERT_NE(one, nullptr);
+    const auto one_type = fizzy_get_funcref_type(one);
+    EXPECT_EQ(one_type.output, FizzyValueTypeI32);
+    EXPECT_EQ(one_type.inputs_size, 0);
+    EXPECT_TRUE(fizzy_set_table_element(table.table, 2, one));
+    fizzy_free_funcref(one);
+    EXPECT_THAT(fizzy_execute(instance, 2, args, 0), Result(1));
+
+    EXPECT_TRUE(fizzy_set_table_element(table.table, 0, nullptr));
+    args[0].i64 = 0;
+    EXPECT_THAT(fizzy_execute(instance, 2, args, 0), Traps());
+
+    fizzy_free_funcref(nullptr);
     fizzy_free_instance(instance);
 }
 
-TEST(capi, memory_access)
+TEST(capi, create_funcref)
 {
     /* wat2wasm
-      (memory 1)
-      (data (i32.const 1) "\11\22")
-      (func (result i32)
-        i32.const 0
-        i32.load
-      )
+    (type $t (func (param i32) (result i32)))
+    (table 1 funcref)
+    (func (export "call") (param i32) (result i32)
+      (call_indirect (type $t) (local.get 0) (i32.const 0)))
     */
     const auto wasm = from_hex(
-        "0061736d010000000105016000017f0302010005030100010a0901070041002802000b0b08010041010b02112"
-        "2");
+        "0061736d0100000001060160017f017f030201000404017000010708010463616c6c00000a0b01090020004100"
+        "1100000b");
+
     auto module = fizzy_parse(wasm.data(), wasm.size());
-    EXPECT_NE(module, nullptr);
+    ASSERT_NE(module, nullptr);
 
     auto instance = fizzy_instantiate(module, nullptr, 0);
-    EXPECT_NE(instance, nullptr);
+    ASSERT_NE(instance, nullptr);
 
-    uint8_t* memory = fizzy_get_instance_memory_data(instance);
-    EXPECT_NE(memory, nullptr);
-    EXPECT_EQ(memory[0], 0);
-    EXPECT_EQ(memory[1], 0x11);
-    EXPECT_EQ(memory[2], 0x22);
+    FizzyExternalTable table;
+    ASSERT_TRUE(fizzy_get_instance_table(instance, &table));
+
+    uint32_t calls = 0;
+    const auto host_fn = [](void* context, FizzyInstance*, const FizzyValue* args, size_t,
+                             int) noexcept {
+        ++*static_cast<uint32_t*>(context);
+        return FizzyExecutionResult{false, true, {args[0].i64 * 2}};
+    };
+
+    const FizzyValueType inputs[] = {FizzyValueTypeI32};
+    auto funcref = fizzy_create_funcref({FizzyValueTypeI32, inputs, 1}, host_fn, &calls);
+    ASSERT_NE(funcref, nullptr);
+    EXPECT_TRUE(fizzy_set_table_element(table.table, 0, funcref));
+    fizzy_free_funcref(funcref);
+
+    FizzyValue args[] = {{21}};
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Result(42));
+    EXPECT_EQ(calls, 1);
+
+    auto mismatched = fizzy_create_funcref({FizzyValueTypeI32, nullptr, 0}, host_fn, &calls);
+    ASSERT_NE(mismatched, nullptr);
+    EXPECT_TRUE(fizzy_set_table_element(table.table, 0, mismatched));
+    fizzy_free_funcref(mismatched);
+    EXPECT_THAT(fizzy_execute(instance, 0, args, 0), Traps());
+    EXPECT_EQ(calls, 1);
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, multiple_memories)
+{
+    auto memory = fizzy_create_memory(1);
+    ASSERT_NE(memory, nullptr);
+
+    /* wat2wasm --enable-multi-memory
+      (memory (import "m" "mem") 1)
+      (memory 2 3)
+      (export "mem1" (memory 1))
+    */
+    const auto wasm =
+        from_hex("0061736d01000000020a01016d036d656d020001050401010203070801046d656d310201");
+
+    EXPECT_EQ(fizzy_parse(wasm.data(), wasm.size()), nullptr);
+
+    const FizzyFeatures features{true};
+    auto module = fizzy_parse_with_features(wasm.data(), wasm.size(), nullptr, &features, nullptr);
+    ASSERT_NE(module, nullptr);
+    ASSERT_EQ(fizzy_get_memory_count(module), 2);
+    auto limits = fizzy_get_memory_type(module, 0);
+    EXPECT_EQ(limits.min, 1);
+    EXPECT_FALSE(limits.has_max);
+    limits = fizzy_get_memory_type(module, 1);
+    EXPECT_EQ(limits.min, 2);
+    EXPECT_TRUE(limits.has_max);
+    EXPECT_EQ(limits.max, 3);
+
+    const FizzyExternalMemory external_memory{memory, {1, 0, false}};
+    auto instance = fizzy_instantiate_with_imports(
+        module, nullptr, 0, nullptr, &external_memory, nullptr, 0);
+    ASSERT_NE(instance, nullptr);
+
+    FizzyExternalMemory instance_memory;
+    ASSERT_TRUE(fizzy_get_instance_memory(instance, 0, &instance_memory));
+    EXPECT_EQ(instance_memory.memory, memory);
+    ASSERT_TRUE(fizzy_get_instance_memory(instance, 1, &instance_memory));
+    EXPECT_EQ(fizzy_get_memory_size(instance_memory.memory), 2 * 65536);
+    EXPECT_EQ(instance_memory.limits.max, 3);
+    EXPECT_FALSE(fizzy_get_instance_memory(instance, 2, &instance_memory));
+
+    FizzyExternalMemory exported_memory;
+    ASSERT_TRUE(fizzy_find_exported_memory(instance, "mem1", &exported_memory));
+    EXPECT_EQ(exported_memory.memory, instance_memory.memory);
+
+    // The memory 0 is the imported one.
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance), fizzy_get_memory_data(memory));
     EXPECT_EQ(fizzy_get_instance_memory_size(instance), 65536);
 
-    memory[0] = 0xaa;
-    memory[1] = 0xbb;
+    fizzy_free_instance(instance);
+    fizzy_free_memory(memory);
+}
 
-    EXPECT_EQ(fizzy_execute(instance, 0, nullptr, 0).value.i64, 0x22bbaa);
+TEST(capi, instantiate_with_options_imported_memories)
+{
+    /* wat2wasm --enable-multi-memory
+      (memory (import "m" "a") 1)
+      (memory (import "m" "b") 1)
+    */
+    const auto wasm = from_hex("0061736d01000000020f02016d0161020001016d0162020001");
+
+    auto memory_a = fizzy_create_memory(1);
+    ASSERT_NE(memory_a, nullptr);
+    auto memory_b = fizzy_create_memory(1);
+    ASSERT_NE(memory_b, nullptr);
+    const FizzyExternalMemory memories[] = {{memory_a, {1, 0, false}}, {memory_b, {1, 0, false}}};
+
+    const FizzyFeatures features{true};
+    auto module = fizzy_parse_with_features(wasm.data(), wasm.size(), nullptr, &features, nullptr);
+    ASSERT_NE(module, nullptr);
+    FizzyError error;
+    EXPECT_EQ(fizzy_instantiate_with_options(
+                  module, nullptr, 0, nullptr, memories, 1, nullptr, 0, nullptr, &error),
+        nullptr);
+    EXPECT_EQ(error.code, FizzyErrorOther);
+    EXPECT_STREQ(error.message, "module requires 2 imported memories, 1 provided");
+
+    module = fizzy_parse_with_features(wasm.data(), wasm.size(), nullptr, &features, nullptr);
+    ASSERT_NE(module, nullptr);
+    auto instance = fizzy_instantiate_with_options(
+        module, nullptr, 0, nullptr, memories, 2, nullptr, 0, nullptr, &error);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_EQ(error.code, FizzySuccess);
+
+    FizzyExternalMemory instance_memory;
+    ASSERT_TRUE(fizzy_get_instance_memory(instance, 1, &instance_memory));
+    EXPECT_EQ(instance_memory.memory, memory_b);
 
     fizzy_free_instance(instance);
+    fizzy_free_memory(memory_b);
+    fizzy_free_memory(memory_a);
 }
 
-TEST(capi, imported_memory_access)
+TEST(capi, get_instance_table_missing)
 {
     /* wat2wasm
-      (memory (import "mod" "mem") 1)
+    (memory 1)
     */
-    const auto wasm = from_hex("0061736d01000000020c01036d6f64036d656d020001");
+    const auto wasm = from_hex("0061736d010000000503010001");
+
     auto module = fizzy_parse(wasm.data(), wasm.size());
-    EXPECT_NE(module, nullptr);
+    ASSERT_NE(module, nullptr);
 
     auto instance = fizzy_instantiate(module, nullptr, 0);
-    EXPECT_EQ(instance, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    FizzyExternalTable table;
+    EXPECT_FALSE(fizzy_get_instance_table(instance, &table));
+
+    fizzy_free_instance(instance);
 }
 
-TEST(capi, execute)
+TEST(capi, trap_stack_trace)
 {
-    /* wat2wasm
-      (func)
-      (func (result i32) i32.const 42)
-      (func (param i32 i32) (result i32)
-        (i32.div_u (local.get 0) (local.get 1))
-      )
-      (func unreachable)
+    /* wat2wasm --debug-names
+      (func $trap (import "m" "trap"))
+      (func $inner call $trap)
+      (func $outer (export "outer") call $inner)
+      (func (export "fail") unreachable)
     */
     const auto wasm = from_hex(
-        "0061736d01000000010e036000006000017f60027f7f017f030504000102000a150402000b0400412a0b070020"
-        "0020016e0b0300000b");
+        "0061736d01000000010401600000020a01016d04747261700000030403000000071002056f757465720002046661"
+        "696c00030a0f03040010000b040010010b0300000b001c046e616d650115030004747261700105696e6e65720205"
+        "6f75746572");
 
     auto module = fizzy_parse(wasm.data(), wasm.size());
-    EXPECT_NE(module, nullptr);
+    ASSERT_NE(module, nullptr);
 
-    auto instance = fizzy_instantiate(module, nullptr, 0);
-    EXPECT_NE(instance, nullptr);
+    EXPECT_STREQ(fizzy_get_function_name(module, 0), "trap");
+    EXPECT_STREQ(fizzy_get_function_name(module, 1), "inner");
+    EXPECT_STREQ(fizzy_get_function_name(module, 2), "outer");
+    EXPECT_EQ(fizzy_get_function_name(module, 3), nullptr);
+
+    FizzyExternalFunction host_funcs[] = {{[](void*, FizzyInstance*, const FizzyValue*, size_t,
+                                               int) { return FizzyExecutionResult{true, false, {}}; },
+        nullptr}};
+
+    auto instance = fizzy_instantiate(module, host_funcs, 1);
+    ASSERT_NE(instance, nullptr);
+
+    EXPECT_EQ(fizzy_get_trap_stack_trace(instance, nullptr, 0), 0);
+
+    EXPECT_THAT(fizzy_execute(instance, 2, nullptr, 0), Traps());
+    uint32_t func_indices[3] = {};
+    ASSERT_EQ(fizzy_get_trap_stack_trace(instance, func_indices, 3), 3);
+    EXPECT_EQ(func_indices[0], 0);
+    EXPECT_EQ(func_indices[1], 1);
+    EXPECT_EQ(func_indices[2], 2);
+
+    uint32_t innermost = 0;
+    EXPECT_EQ(fizzy_get_trap_stack_trace(instance, &innermost, 1), 3);
+    EXPECT_EQ(innermost, 0);
 
-    EXPECT_THAT(fizzy_execute(instance, 0, nullptr, 0), Result());
-    EXPECT_THAT(fizzy_execute(instance, 1, nullptr, 0), Result(42));
-    FizzyValue args[] = {{42}, {2}};
-    EXPECT_THAT(fizzy_execute(instance, 2, args, 0), Result(21));
     EXPECT_THAT(fizzy_execute(instance, 3, nullptr, 0), Traps());
+    ASSERT_EQ(fizzy_get_trap_stack_trace(instance, func_indices, 3), 1);
+    EXPECT_EQ(func_indices[0], 3);
+
+    fizzy_free_instance(instance);
+
+    module = fizzy_parse(wasm.data(), wasm.size());
+    ASSERT_NE(module, nullptr);
+    FizzyInstantiateOptions options{};
+    options.disable_trap_stack_trace = true;
+    instance = fizzy_instantiate_with_options(
+        module, host_funcs, 1, nullptr, nullptr, 0, nullptr, 0, &options, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    EXPECT_THAT(fizzy_execute(instance, 2, nullptr, 0), Traps());
+    EXPECT_EQ(fizzy_get_trap_stack_trace(instance, nullptr, 0), 0);
 
     fizzy_free_instance(instance);
 }
@@ -341,3 +1809,92 @@ TEST(capi, imported_function_from_another_module)
     fizzy_free_instance(instance2);
     fizzy_free_instance(instance1);
 }
+
+TEST(capi, imported_externals_from_another_module)
+{
+    /* wat2wasm
+    (module
+      (func $f (result i32) i32.const 5)
+      (table (export "tab") 1 funcref)
+      (elem (i32.const 0) $f)
+      (memory (export "mem") 1)
+      (data (i32.const 0) "\2a")
+      (global (export "g") (mut i32) (i32.const 7))
+    )
+    */
+    const auto bin1 = from_hex(
+        "0061736d010000000105016000017f0302010004040170000105030100010606017f0141070b07110303746162"
+        "0100036d656d0200016703000907010041000b01000a0601040041050b0b07010041000b012a");
+    auto module1 = fizzy_parse(bin1.data(), bin1.size());
+    ASSERT_NE(module1, nullptr);
+    auto instance1 = fizzy_instantiate(module1, nullptr, 0);
+    ASSERT_NE(instance1, nullptr);
+
+    FizzyExternalTable table;
+    EXPECT_FALSE(fizzy_find_exported_table(instance1, "mem", &table));
+    ASSERT_TRUE(fizzy_find_exported_table(instance1, "tab", &table));
+    EXPECT_NE(table.table, nullptr);
+    EXPECT_EQ(table.limits.min, 1);
+    EXPECT_FALSE(table.limits.has_max);
+
+    FizzyExternalMemory memory;
+    EXPECT_FALSE(fizzy_find_exported_memory(instance1, "tab", &memory));
+    ASSERT_TRUE(fizzy_find_exported_memory(instance1, "mem", &memory));
+    EXPECT_NE(memory.memory, nullptr);
+    EXPECT_EQ(memory.limits.min, 1);
+    EXPECT_FALSE(memory.limits.has_max);
+
+    FizzyExternalGlobal global;
+    EXPECT_FALSE(fizzy_find_exported_global(instance1, "mem", &global));
+    ASSERT_TRUE(fizzy_find_exported_global(instance1, "g", &global));
+    EXPECT_EQ(global.value->i64, 7);
+    EXPECT_EQ(global.type.value_type, FizzyValueTypeI32);
+    EXPECT_TRUE(global.type.is_mutable);
+
+    /* wat2wasm
+    (module
+      (table (import "m1" "tab") 1 funcref)
+      (memory (import "m1" "mem") 1)
+      (global (import "m1" "g") (mut i32))
+      (type $t (func (result i32)))
+      (func (result i32)
+        (i32.add (i32.add (i32.load (i32.const 0)) (global.get 0))
+          (call_indirect (type $t) (i32.const 0)))
+      )
+      (func
+        (global.set 0 (i32.const 10))
+        (i32.store8 (i32.const 4) (i32.const 11))
+      )
+    )
+    */
+    const auto bin2 = from_hex(
+        "0061736d010000000108026000017f600000021e03026d310374616201700001026d31036d656d020001026d31"
+        "0167037f0103030200010a20021000410028020023006a41001100006a0b0d00410a24004104410b3a00000b");
+
+    auto module2 = fizzy_parse(bin2.data(), bin2.size());
+    ASSERT_NE(module2, nullptr);
+    EXPECT_EQ(fizzy_instantiate_with_imports(module2, nullptr, 0, &table, &memory, nullptr, 0),
+        nullptr);
+
+    const FizzyExternalGlobal immutable_global{global.value, {FizzyValueTypeI32, false}};
+    module2 = fizzy_parse(bin2.data(), bin2.size());
+    ASSERT_NE(module2, nullptr);
+    EXPECT_EQ(fizzy_instantiate_with_imports(
+                  module2, nullptr, 0, &table, &memory, &immutable_global, 1),
+        nullptr);
+
+    module2 = fizzy_parse(bin2.data(), bin2.size());
+    ASSERT_NE(module2, nullptr);
+    auto instance2 =
+        fizzy_instantiate_with_imports(module2, nullptr, 0, &table, &memory, &global, 1);
+    ASSERT_NE(instance2, nullptr);
+
+    EXPECT_THAT(fizzy_execute(instance2, 0, nullptr, 0), Result(42 + 7 + 5));
+    EXPECT_THAT(fizzy_execute(instance2, 1, nullptr, 0), Result());
+    EXPECT_EQ(global.value->i64, 10);
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance1)[4], 11);
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance2), fizzy_get_instance_memory_data(instance1));
+
+    fizzy_free_instance(instance2);
+    fizzy_free_instance(instance1);
+}
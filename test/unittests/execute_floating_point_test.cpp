@@ -2028,7 +2028,7 @@ TEST(execute_floating_point, f32_store)
         auto instance = instantiate(*module);
 
         EXPECT_THAT(execute(*instance, 0, {arg, 1}), Result());
-        EXPECT_EQ(instance->memory->substr(0, 6), expected);
+        EXPECT_EQ(bytes_view{*instance->memory}.substr(0, 6), expected);
 
         EXPECT_THAT(execute(*instance, 0, {arg, 65534}), Traps());
         EXPECT_THAT(execute(*instance, 0, {arg, 65537}), Traps());
@@ -2105,7 +2105,7 @@ TEST(execute_floating_point, f64_store)
         auto instance = instantiate(*module);
 
         EXPECT_THAT(execute(*instance, 0, {arg, 1}), Result());
-        EXPECT_EQ(instance->memory->substr(0, 10), expected);
+        EXPECT_EQ(bytes_view{*instance->memory}.substr(0, 10), expected);
 
         EXPECT_THAT(execute(*instance, 0, {arg, 65534}), Traps());
         EXPECT_THAT(execute(*instance, 0, {arg, 65537}), Traps());
@@ -9,6 +9,7 @@
 #include <test/utils/asserts.hpp>
 #include <test/utils/execute_helpers.hpp>
 #include <test/utils/hex.hpp>
+#include <array>
 
 using namespace fizzy;
 using namespace fizzy::test;
@@ -315,7 +316,7 @@ TEST(execute, i32_load_imported_memory)
     const auto wasm = from_hex(
         "0061736d0100000001060160017f017f020b01036d6f64016d02010101030201000a0901070020002802000b");
 
-    bytes memory(PageSize, 0);
+    LinearMemory memory(PageSize);
     auto instance = instantiate(parse(wasm), {}, {}, {{&memory, {1, 1}}});
     memory[1] = 42;
     EXPECT_THAT(execute(*instance, 0, {1}), Result(42));
@@ -459,10 +460,10 @@ TEST(execute, i32_store_imported_memory)
         "0061736d0100000001060160027f7f00020b01036d6f64016d02010101030201000a0b01090020012000360200"
         "0b");
 
-    bytes memory(PageSize, 0);
+    LinearMemory memory(PageSize);
     auto instance = instantiate(parse(wasm), {}, {}, {{&memory, {1, 1}}});
     EXPECT_THAT(execute(*instance, 0, {42, 0}), Result());
-    EXPECT_EQ(memory.substr(0, 4), from_hex("2a000000"));
+    EXPECT_EQ(bytes_view{memory}.substr(0, 4), from_hex("2a000000"));
 
     EXPECT_THAT(execute(*instance, 0, {42, 65537}), Traps());
 }
@@ -545,7 +546,7 @@ TEST(execute, i32_store_all_variants)
         auto instance = instantiate(*module);
         std::fill_n(instance->memory->begin(), 6, uint8_t{0xcc});
         EXPECT_THAT(execute(*instance, 0, {0xb3b2b1b0, 1}), Result());
-        EXPECT_EQ(instance->memory->substr(0, 6), std::get<1>(test_case));
+        EXPECT_EQ(bytes_view{*instance->memory}.substr(0, 6), std::get<1>(test_case));
 
         EXPECT_THAT(execute(*instance, 0, {0xb3b2b1b0, 65537}), Traps());
     }
@@ -582,7 +583,7 @@ TEST(execute, i64_store_all_variants)
         auto instance = instantiate(*module);
         std::fill_n(instance->memory->begin(), 10, uint8_t{0xcc});
         EXPECT_THAT(execute(*instance, 0, {0xb7b6b5b4b3b2b1b0, 1}), Result());
-        EXPECT_EQ(instance->memory->substr(0, 10), std::get<1>(test_case));
+        EXPECT_EQ(bytes_view{*instance->memory}.substr(0, 10), std::get<1>(test_case));
 
         EXPECT_THAT(execute(*instance, 0, {0xb7b6b5b4b3b2b1b0, 65537}), Traps());
     }
@@ -630,6 +631,251 @@ TEST(execute, memory_grow)
     EXPECT_THAT(execute(module, 0, {0xffffffe}), Result(-1));
 }
 
+TEST(execute, memory_grow_hook)
+{
+    /* wat2wasm
+    (memory 1 3)
+    (func (param i32) (result i32)
+      get_local 0
+      memory.grow
+    )
+    */
+    const auto wasm =
+        from_hex("0061736d0100000001060160017f017f030201000504010101030a08010600200040000b");
+
+    auto instance = instantiate(parse(wasm));
+    std::vector<std::pair<uint32_t, uint32_t>> growths;
+    instance->memory_grow_hook = [&growths](Instance& _instance, uint32_t memory_idx,
+                                     uint32_t old_pages, uint32_t new_pages) {
+        EXPECT_EQ(memory_idx, 0);
+        EXPECT_EQ(_instance.memory->size(), new_pages * PageSize);
+        growths.emplace_back(old_pages, new_pages);
+    };
+
+    EXPECT_THAT(execute(*instance, 0, {1}), Result(1));
+    EXPECT_THAT(execute(*instance, 0, {0}), Result(2));
+    EXPECT_THAT(execute(*instance, 0, {2}), Result(-1));
+    EXPECT_THAT(execute(*instance, 0, {1}), Result(2));
+    EXPECT_EQ(growths, (std::vector<std::pair<uint32_t, uint32_t>>{{1, 2}, {2, 3}}));
+}
+
+TEST(execute, memory_grow_limiter)
+{
+    /* wat2wasm
+    (memory 1 3)
+    (func (param i32) (result i32)
+      get_local 0
+      memory.grow
+    )
+    */
+    const auto wasm =
+        from_hex("0061736d0100000001060160017f017f030201000504010101030a08010600200040000b");
+
+    auto instance = instantiate(parse(wasm));
+    std::vector<std::pair<uint32_t, uint32_t>> requests;
+    instance->memory_grow_limiter = [&requests](Instance&, uint32_t memory_idx, uint32_t current,
+                                        uint32_t desired) {
+        EXPECT_EQ(memory_idx, 0);
+        requests.emplace_back(current, desired);
+        return desired <= 2;
+    };
+
+    EXPECT_THAT(execute(*instance, 0, {2}), Result(-1));
+    EXPECT_THAT(execute(*instance, 0, {1}), Result(1));
+    EXPECT_THAT(execute(*instance, 0, {0}), Result(2));
+    EXPECT_THAT(execute(*instance, 0, {1}), Result(-1));
+    // The memory limits are checked first.
+    EXPECT_THAT(execute(*instance, 0, {2}), Result(-1));
+    EXPECT_EQ(instance->memory->size(), 2 * PageSize);
+    EXPECT_EQ(
+        requests, (std::vector<std::pair<uint32_t, uint32_t>>{{1, 3}, {1, 2}, {2, 3}}));
+}
+
+TEST(execute, multiple_memories)
+{
+    /* wat2wasm --enable-multi-memory
+    (memory 1)
+    (memory 1 2)
+    (data (memory 1) (i32.const 0) "\2a")
+    (func (param i32) (result i32) (i32.load8_u 1 (local.get 0)))
+    (func (param i32 i32) (i32.store8 1 (local.get 0) (local.get 1)))
+    (func (result i32) (memory.size 1))
+    (func (param i32) (result i32) (memory.grow 1 (local.get 0)))
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010f0360017f017f60027f7f006000017f0305040001020005060200010101020a210408"
+        "0020002d4001000b0a00200020013a4001000b04003f010b0600200040010b0b0801020141000b012a");
+
+    auto instance = instantiate(parse(wasm, {}, Features{true}));
+    ASSERT_EQ(instance->memories.size(), 1);
+    auto& memory1 = *instance->memories[0].memory;
+    std::vector<std::array<uint32_t, 3>> growths;
+    instance->memory_grow_hook = [&growths](Instance&, uint32_t memory_idx, uint32_t old_pages,
+                                     uint32_t new_pages) {
+        growths.push_back({memory_idx, old_pages, new_pages});
+    };
+    std::vector<std::array<uint32_t, 3>> requests;
+    instance->memory_grow_limiter = [&requests](Instance&, uint32_t memory_idx, uint32_t current,
+                                        uint32_t desired) {
+        requests.push_back({memory_idx, current, desired});
+        return true;
+    };
+    std::vector<uint32_t> accessed_memories;
+    instance->instruction_hook = [&accessed_memories](Instance&, const ExecutionState& state) {
+        if (state.opcode == Instr::i32_load8_u)
+            accessed_memories.push_back(state.memory_idx);
+        return true;
+    };
+
+    EXPECT_THAT(execute(*instance, 0, {0}), Result(0x2a));
+    EXPECT_EQ(accessed_memories, std::vector<uint32_t>{1});
+    EXPECT_THAT(execute(*instance, 1, {1, 0xff}), Result());
+    EXPECT_EQ(memory1[1], 0xff);
+    EXPECT_EQ((*instance->memory)[0], 0);
+    EXPECT_EQ((*instance->memory)[1], 0);
+
+    // The accesses of the other memories are bounds-checked.
+    EXPECT_THAT(execute(*instance, 0, {PageSize}), Traps());
+    EXPECT_THAT(execute(*instance, 1, {PageSize, 0}), Traps());
+
+    EXPECT_THAT(execute(*instance, 2, {}), Result(1));
+    EXPECT_THAT(execute(*instance, 3, {2}), Result(-1));
+    EXPECT_THAT(execute(*instance, 3, {1}), Result(1));
+    EXPECT_THAT(execute(*instance, 2, {}), Result(2));
+    EXPECT_THAT(execute(*instance, 0, {PageSize}), Result(0));
+    EXPECT_EQ(instance->memory->size(), PageSize);
+    // The growth of the memory 1 is reported with its index. The growth beyond its limits does not
+    // reach the limiter.
+    EXPECT_EQ(growths, (std::vector<std::array<uint32_t, 3>>{{1, 1, 2}}));
+    EXPECT_EQ(requests, (std::vector<std::array<uint32_t, 3>>{{1, 1, 2}}));
+}
+
+TEST(execute, memory_grow_mapped)
+{
+    /* wat2wasm
+    (memory 1 3)
+    (func (param i32) (result i32)
+      get_local 0
+      memory.grow
+    )
+    */
+    const auto wasm =
+        from_hex("0061736d0100000001060160017f017f030201000504010101030a08010600200040000b");
+    const auto module = parse(wasm);
+
+    const auto instance = instantiate(*module, {}, {}, {}, {}, 3, MemoryBacking::mapped);
+    const auto* const data = instance->memory->data();
+    (*instance->memory)[PageSize - 1] = 0xaa;
+
+    EXPECT_THAT(execute(*instance, 0, {1}), Result(1));
+    EXPECT_THAT(execute(*instance, 0, {2}), Result(-1));
+    ASSERT_EQ(instance->memory->size(), 2 * PageSize);
+    EXPECT_EQ((*instance->memory)[PageSize - 1], 0xaa);
+    EXPECT_EQ((*instance->memory)[PageSize], 0);
+    if (instance->memory->backing() == MemoryBacking::mapped)
+    {
+        EXPECT_EQ(instance->memory->data(), data);
+    }
+
+    /* wat2wasm
+    (memory 1)
+    (func (param i32) (result i32)
+      get_local 0
+      memory.grow
+    )
+    */
+    const auto wasm_unbounded =
+        from_hex("0061736d0100000001060160017f017f0302010005030100010a08010600200040000b");
+    const auto instance_unbounded =
+        instantiate(*parse(wasm_unbounded), {}, {}, {}, {}, 16, MemoryBacking::mapped);
+    EXPECT_THAT(execute(*instance_unbounded, 0, {15}), Result(1));
+    EXPECT_THAT(execute(*instance_unbounded, 0, {1}), Result(-1));
+}
+
+TEST(execute, memory_guarded)
+{
+    /* wat2wasm
+    (memory 1 2)
+    (func (param i32) (result i32)
+      get_local 0
+      i32.load
+    )
+    (func (param i32) (result i32)
+      get_local 0
+      i32.load offset=0xffffffff
+    )
+    (func (param i32 i32)
+      get_local 0
+      get_local 1
+      i32.store
+    )
+    (func (param i32) (result i32)
+      get_local 0
+      memory.grow
+    )
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010b0260017f017f60027f7f00030504000001000504010101020a2604070020002802000b"
+        "0b0020002802ffffffff0f0b0900200020013602000b0600200040000b");
+    const auto module = parse(wasm);
+
+    auto instance =
+        instantiate(*module, {}, {}, {}, {}, DefaultMemoryPagesLimit, MemoryBacking::guarded);
+
+    EXPECT_THAT(execute(*instance, 2, {PageSize - 4, 0x11223344}), Result());
+    EXPECT_THAT(execute(*instance, 0, {PageSize - 4}), Result(0x11223344));
+    EXPECT_THAT(execute(*instance, 0, {PageSize - 3}), Traps());
+    EXPECT_THAT(execute(*instance, 2, {PageSize, 1}), Traps());
+    EXPECT_THAT(execute(*instance, 0, {0xffffffff}), Traps());
+    EXPECT_THAT(execute(*instance, 1, {0}), Traps());
+    EXPECT_THAT(execute(*instance, 1, {0xffffffff}), Traps());
+    EXPECT_EQ(instance->trap_stack_trace, std::vector<FuncIdx>{1});
+
+    EXPECT_THAT(execute(*instance, 3, {1}), Result(1));
+    EXPECT_THAT(execute(*instance, 2, {PageSize, 0x55667788}), Result());
+    EXPECT_THAT(execute(*instance, 0, {PageSize}), Result(0x55667788));
+    EXPECT_THAT(execute(*instance, 0, {2 * PageSize - 4}), Result(0));
+    EXPECT_THAT(execute(*instance, 0, {2 * PageSize - 3}), Traps());
+    EXPECT_THAT(execute(*instance, 1, {1}), Traps());
+}
+
+TEST(execute, memory_read_only)
+{
+    /* wat2wasm
+    (memory 1)
+    (func (param i32 i32)
+      get_local 0
+      get_local 1
+      i32.store16 offset=2
+    )
+    (func (param i32) (result i32)
+      get_local 0
+      i32.load
+    )
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010b0260027f7f0060017f017f030302000105030100010a13020900200020013b01020b07"
+        "0020002802000b");
+    const auto module = parse(wasm);
+
+    for (const auto backing : {MemoryBacking::heap, MemoryBacking::guarded})
+    {
+        auto instance = instantiate(*module, {}, {}, {}, {}, DefaultMemoryPagesLimit, backing);
+        instance->memory->add_read_only_range(8, 4);
+
+        EXPECT_THAT(execute(*instance, 0, {4, 0x1122}), Result());
+        EXPECT_THAT(execute(*instance, 0, {5, 0x3344}), Traps());
+        EXPECT_THAT(execute(*instance, 0, {9, 0x3344}), Traps());
+        EXPECT_THAT(execute(*instance, 0, {10, 0x5566}), Result());
+        EXPECT_THAT(execute(*instance, 1, {8}), Result(0));
+        EXPECT_THAT(execute(*instance, 1, {4}), Result(0x11220000));
+
+        instance->memory->clear_read_only_ranges();
+        EXPECT_THAT(execute(*instance, 0, {6, 0x3344}), Result());
+        EXPECT_THAT(execute(*instance, 1, {8}), Result(0x3344));
+    }
+}
+
 TEST(execute, memory_grow_custom_hard_limit)
 {
     constexpr std::pair<uint32_t, uint32_t> test_cases[]{
@@ -687,12 +933,12 @@ TEST(execute, memory_grow_custom_hard_limit)
 
     for (const auto& test_case : test_cases)
     {
-        bytes memory(PageSize, 0);
+        LinearMemory memory(PageSize);
         const auto instance =
             instantiate(*module_imported, {}, {}, {{&memory, {1, std::nullopt}}}, {}, 16);
         EXPECT_THAT(execute(*instance, 0, {test_case.first}), Result(test_case.second));
 
-        bytes memory_max_limit(PageSize, 0);
+        LinearMemory memory_max_limit(PageSize);
         const auto instance_max_limit =
             instantiate(*module_imported, {}, {}, {{&memory_max_limit, {1, 16}}}, {}, 32);
         EXPECT_THAT(execute(*instance_max_limit, 0, {test_case.first}), Result(test_case.second));
@@ -712,7 +958,7 @@ TEST(execute, memory_grow_custom_hard_limit)
 
     for (const auto& test_case : test_cases)
     {
-        bytes memory(PageSize, 0);
+        LinearMemory memory(PageSize);
         const auto instance =
             instantiate(*module_imported_max_limit, {}, {}, {{&memory, {1, 16}}}, {}, 32);
         EXPECT_THAT(execute(*instance, 0, {test_case.first}), Result(test_case.second));
@@ -732,7 +978,7 @@ TEST(execute, memory_grow_custom_hard_limit)
 
     for (const auto& test_case : test_cases)
     {
-        bytes memory(PageSize, 0);
+        LinearMemory memory(PageSize);
         const auto instance =
             instantiate(*module_imported_max_limit_narrowing, {}, {}, {{&memory, {1, 16}}}, {}, 32);
         EXPECT_THAT(execute(*instance, 0, {test_case.first}), Result(test_case.second));
@@ -759,10 +1005,11 @@ TEST(execute, start_section)
         "4100412a3602000b");
 
     auto instance = instantiate(parse(wasm));
-    ASSERT_EQ(instance->memory->substr(0, 4), "2a000000"_bytes);  // Start function sets this.
+    // Start function sets this.
+    ASSERT_EQ(bytes_view{*instance->memory}.substr(0, 4), "2a000000"_bytes);
 
     EXPECT_THAT(execute(*instance, 0, {}), Result(42));
-    EXPECT_EQ(instance->memory->substr(0, 4), "2a000000"_bytes);
+    EXPECT_EQ(bytes_view{*instance->memory}.substr(0, 4), "2a000000"_bytes);
 }
 
 TEST(execute, imported_function)
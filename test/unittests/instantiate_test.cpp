@@ -184,7 +184,7 @@ TEST(instantiate, imported_memory)
     */
     const auto bin = from_hex("0061736d01000000020b01036d6f64016d02010103");
 
-    bytes memory(PageSize, 0);
+    LinearMemory memory(PageSize);
     auto instance = instantiate(parse(bin), {}, {}, {{&memory, {1, 3}}});
 
     ASSERT_TRUE(instance->memory);
@@ -202,7 +202,7 @@ TEST(instantiate, imported_memory_unlimited)
     */
     const auto bin = from_hex("0061736d01000000020a01036d6f64016d020001");
 
-    bytes memory(PageSize, 0);
+    LinearMemory memory(PageSize);
     auto instance = instantiate(parse(bin), {}, {}, {{&memory, {1, std::nullopt}}});
 
     ASSERT_TRUE(instance->memory);
@@ -219,7 +219,7 @@ TEST(instantiate, imported_memory_stricter_limits)
     */
     const auto bin = from_hex("0061736d01000000020b01036d6f64016d02010103");
 
-    bytes memory(PageSize * 2, 0);
+    LinearMemory memory(PageSize * 2);
     auto instance = instantiate(parse(bin), {}, {}, {{&memory, {2, 2}}});
 
     ASSERT_TRUE(instance->memory);
@@ -238,11 +238,11 @@ TEST(instantiate, imported_memory_invalid)
     const auto bin = from_hex("0061736d01000000020b01036d6f64016d02010103");
     const auto module = parse(bin);
 
-    bytes memory(PageSize, 0);
+    LinearMemory memory(PageSize);
 
-    // Providing more than 1 memory
+    // Providing more memories than imported
     EXPECT_THROW_MESSAGE(instantiate(*module, {}, {}, {{&memory, {1, 3}}, {&memory, {1, 1}}}),
-        instantiate_error, "only 1 imported memory is allowed");
+        instantiate_error, "module requires 1 imported memories, 2 provided");
 
     // Providing memory when none expected
     /* wat2wasm
@@ -257,7 +257,7 @@ TEST(instantiate, imported_memory_invalid)
         "module defines an imported memory but none was provided");
 
     // Provided min too low
-    bytes memory_empty;
+    LinearMemory memory_empty;
     EXPECT_THROW_MESSAGE(instantiate(*module, {}, {}, {{&memory_empty, {0, 3}}}), instantiate_error,
         "provided import's min is below import's min defined in module");
 
@@ -278,7 +278,7 @@ TEST(instantiate, imported_memory_invalid)
         "provided imported memory doesn't fit provided limits");
 
     // Allocated more than max
-    bytes memory_big(PageSize * 4, 0);
+    LinearMemory memory_big(PageSize * 4);
     EXPECT_THROW_MESSAGE(instantiate(*module, {}, {}, {{&memory_big, {1, 3}}}), instantiate_error,
         "provided imported memory doesn't fit provided limits");
 
@@ -496,7 +496,7 @@ TEST(instantiate, imported_memory_custom_hard_limit)
     const auto bin = from_hex("0061736d01000000020c01036d6f64036d656d020002");
     const auto module = parse(bin);
 
-    bytes memory(PageSize * 3, 0);
+    LinearMemory memory(PageSize * 3);
 
     EXPECT_THROW_MESSAGE(instantiate(*module, {}, {}, {{&memory, {3, 4}}}, {}, 1),
         instantiate_error, "imported memory limits cannot exceed hard memory limit of 65536 bytes");
@@ -689,7 +689,7 @@ TEST(instantiate, data_section)
 
     auto instance = instantiate(*module);
 
-    EXPECT_EQ(instance->memory->substr(0, 6), from_hex("00aa55550000"));
+    EXPECT_EQ(bytes_view{*instance->memory}.substr(0, 6), from_hex("00aa55550000"));
 }
 
 TEST(instantiate, data_section_offset_from_global)
@@ -703,7 +703,7 @@ TEST(instantiate, data_section_offset_from_global)
 
     auto instance = instantiate(*module);
 
-    EXPECT_EQ(instance->memory->substr(42, 2), "aaff"_bytes);
+    EXPECT_EQ(bytes_view{*instance->memory}.substr(42, 2), "aaff"_bytes);
 }
 
 TEST(instantiate, data_section_offset_from_imported_global)
@@ -721,7 +721,7 @@ TEST(instantiate, data_section_offset_from_imported_global)
 
     auto instance = instantiate(parse(bin), {}, {}, {}, {g});
 
-    EXPECT_EQ(instance->memory->substr(42, 2), "aaff"_bytes);
+    EXPECT_EQ(bytes_view{*instance->memory}.substr(42, 2), "aaff"_bytes);
 }
 
 TEST(instantiate, data_section_offset_too_large)
@@ -745,10 +745,10 @@ TEST(instantiate, data_section_fills_imported_memory)
     const auto bin =
         from_hex("0061736d01000000020b01036d6f64016d020101010b0f020041010b02aaff0041020b025555");
 
-    bytes memory(PageSize, 0);
+    LinearMemory memory(PageSize);
     auto instance = instantiate(parse(bin), {}, {}, {{&memory, {1, 1}}});
 
-    EXPECT_EQ(memory.substr(0, 6), from_hex("00aa55550000"));
+    EXPECT_EQ(bytes_view{memory}.substr(0, 6), from_hex("00aa55550000"));
 }
 
 TEST(instantiate, data_section_out_of_bounds_doesnt_change_imported_memory)
@@ -763,7 +763,7 @@ TEST(instantiate, data_section_out_of_bounds_doesnt_change_imported_memory)
     const auto bin =
         from_hex("0061736d01000000020a01016d036d656d0200010b0f020041000b016100418080040b0161");
 
-    bytes memory(PageSize, 0);
+    LinearMemory memory(PageSize);
     EXPECT_THROW_MESSAGE(instantiate(parse(bin), {}, {}, {{&memory, {1, 1}}}), instantiate_error,
         "data segment is out of memory bounds");
 
@@ -787,7 +787,7 @@ TEST(instantiate, data_elem_section_errors_dont_change_imports)
         "41000b0200000a0601040041010b0b0f020041000b016100418080040b0161");
 
     table_elements table(3);
-    bytes memory(PageSize, 0);
+    LinearMemory memory(PageSize);
     EXPECT_THROW_MESSAGE(
         instantiate(parse(bin_data_error), {}, {{&table, {3, std::nullopt}}}, {{&memory, {1, 1}}}),
         instantiate_error, "data segment is out of memory bounds");
@@ -929,5 +929,98 @@ TEST(instantiate, start_unreachable)
     const auto wasm = from_hex("0061736d01000000010401600000030201000801000a05010300000b");
 
     EXPECT_THROW_MESSAGE(
-        instantiate(parse(wasm)), instantiate_error, "start function failed to execute");
+        instantiate(parse(wasm)), start_function_trapped, "start function failed to execute");
+
+    auto instance = instantiate(parse(wasm), {}, {}, {}, {}, DefaultMemoryPagesLimit,
+        MemoryBacking::heap, {}, true);
+    EXPECT_TRUE(instance->start_pending);
+    EXPECT_THAT(run_start(*instance), Traps());
+    EXPECT_FALSE(instance->start_pending);
+}
+
+TEST(instantiate, start_deferred)
+{
+    /* wat2wasm
+    (global (mut i32) (i32.const 0))
+    (start 0)
+    (func (global.set 0 (i32.add (global.get 0) (i32.const 1))))
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010401600000030201000606017f0141000b0801000a0b010900230041016a24000b");
+
+    const auto instance = instantiate(parse(wasm));
+    EXPECT_FALSE(instance->start_pending);
+    EXPECT_EQ(instance->globals[0].i64, 1);
+    EXPECT_THAT(run_start(*instance), Result());
+    EXPECT_EQ(instance->globals[0].i64, 1);
+
+    const auto deferred = instantiate(parse(wasm), {}, {}, {}, {}, DefaultMemoryPagesLimit,
+        MemoryBacking::heap, {}, true);
+    EXPECT_TRUE(deferred->start_pending);
+    EXPECT_EQ(deferred->globals[0].i64, 0);
+    deferred->globals[0] = Value{10};
+    EXPECT_THAT(run_start(*deferred), Result());
+    EXPECT_EQ(deferred->globals[0].i64, 11);
+    EXPECT_THAT(run_start(*deferred), Result());
+    EXPECT_EQ(deferred->globals[0].i64, 11);
+
+    /* wat2wasm
+    (func)
+    */
+    const auto no_start = instantiate(
+        parse(from_hex("0061736d01000000010401600000030201000a040102000b")), {}, {}, {}, {},
+        DefaultMemoryPagesLimit, MemoryBacking::heap, {}, true);
+    EXPECT_FALSE(no_start->start_pending);
+    EXPECT_THAT(run_start(*no_start), Result());
+}
+
+TEST(instantiate, state_hash)
+{
+    const auto empty = instantiate(parse(from_hex("0061736d01000000")));
+    const auto empty_hash = state_hash(*empty);
+    EXPECT_EQ(hex(empty_hash.data(), empty_hash.size()),
+        "b0f66adc83641586656866813fd9dd0b8ebb63796075661ba45d1aa8089e1d44");
+
+    /* wat2wasm
+      (global (import "m" "g") (mut i32))
+      (table 2 funcref)
+      (elem (i32.const 0) 0)
+      (memory 1)
+      (global (mut i64) (i64.const 1))
+      (func)
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010401600000020801016d0167037f010302010004040170000205030100010606017e01"
+        "42010b0907010041000b01000a040102000b");
+
+    Value imported_value1{uint32_t{2}};
+    Value imported_value2{uint32_t{2}};
+    auto instance1 =
+        instantiate(parse(wasm), {}, {}, {}, {{&imported_value1, {ValType::i32, true}}});
+    const auto instance2 =
+        instantiate(parse(wasm), {}, {}, {}, {{&imported_value2, {ValType::i32, true}}});
+    const auto hash = state_hash(*instance1);
+    EXPECT_EQ(hash, state_hash(*instance2));
+    EXPECT_NE(hash, empty_hash);
+
+    imported_value1 = uint32_t{3};
+    EXPECT_NE(state_hash(*instance1), hash);
+    imported_value1 = uint32_t{2};
+    EXPECT_EQ(state_hash(*instance1), hash);
+
+    instance1->globals[0] = uint64_t{0x100000001};
+    EXPECT_NE(state_hash(*instance1), hash);
+    instance1->globals[0] = uint64_t{1};
+
+    instance1->memory->data()[65535] = 1;
+    EXPECT_NE(state_hash(*instance1), hash);
+    instance1->memory->data()[65535] = 0;
+    EXPECT_EQ(state_hash(*instance1), hash);
+
+    (*instance1->table)[0].reset();
+    EXPECT_NE(state_hash(*instance1), hash);
+    (*instance1->table)[0] = (*instance2->table)[0];
+    EXPECT_EQ(state_hash(*instance1), hash);
+    (*instance1->table)[0]->type.outputs.push_back(ValType::i32);
+    EXPECT_NE(state_hash(*instance1), hash);
 }
@@ -0,0 +1,54 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+#include "le.hpp"
+#include <gtest/gtest.h>
+
+using namespace fizzy;
+
+TEST(le, byteswap)
+{
+    EXPECT_EQ(le::byteswap(uint8_t{0x01}), 0x01);
+    EXPECT_EQ(le::byteswap(uint16_t{0x0102}), 0x0201);
+    EXPECT_EQ(le::byteswap(uint32_t{0x01020304}), 0x04030201);
+    EXPECT_EQ(le::byteswap(uint64_t{0x0102030405060708}), 0x0807060504030201);
+    EXPECT_EQ(le::byteswap(int32_t{-2}), int32_t(0xfeffffff));
+}
+
+TEST(le, load)
+{
+    const uint8_t bytes[]{0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08};
+    EXPECT_EQ(le::load<uint8_t>(bytes), 0x01);
+    EXPECT_EQ(le::load<uint16_t>(bytes), 0x0201);
+    EXPECT_EQ(le::load<uint32_t>(bytes), 0x04030201);
+    EXPECT_EQ(le::load<uint64_t>(bytes), 0x0807060504030201);
+    EXPECT_EQ(le::load<int16_t>(bytes + 6), 0x0807);
+
+    const uint8_t f32_bytes[]{0x00, 0x00, 0xc0, 0x3f};
+    EXPECT_EQ(le::load<float>(f32_bytes), 1.5f);
+    const uint8_t f64_bytes[]{0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf8, 0xbf};
+    EXPECT_EQ(le::load<double>(f64_bytes), -1.5);
+}
+
+TEST(le, store)
+{
+    uint8_t bytes[8]{};
+    le::store(bytes, uint32_t{0x04030201});
+    EXPECT_EQ(bytes[0], 0x01);
+    EXPECT_EQ(bytes[3], 0x04);
+
+    le::store(bytes, uint64_t{0x0807060504030201});
+    for (uint8_t i = 0; i < 8; ++i)
+        EXPECT_EQ(bytes[i], i + 1);
+
+    le::store(bytes, 1.5f);
+    EXPECT_EQ(bytes[0], 0x00);
+    EXPECT_EQ(bytes[1], 0x00);
+    EXPECT_EQ(bytes[2], 0xc0);
+    EXPECT_EQ(bytes[3], 0x3f);
+
+    le::store(bytes, -1.5);
+    EXPECT_EQ(bytes[6], 0xf8);
+    EXPECT_EQ(bytes[7], 0xbf);
+}
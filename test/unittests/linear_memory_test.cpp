@@ -0,0 +1,242 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+#include "limits.hpp"
+#include "linear_memory.hpp"
+#include <gtest/gtest.h>
+#include <algorithm>
+#include <cstdlib>
+#include <limits>
+
+using namespace fizzy;
+
+namespace
+{
+bool is_zero(bytes_view data)
+{
+    return std::all_of(data.begin(), data.end(), [](uint8_t b) { return b == 0; });
+}
+
+/// The allocator counting the allocated bytes and failing allocations above the limit.
+struct CountingAllocator
+{
+    size_t allocated = 0;
+    size_t limit = std::numeric_limits<size_t>::max();
+
+    Allocator hooks() noexcept
+    {
+        Allocator allocator;
+        allocator.allocate = [](void* context, size_t size) -> void* {
+            auto& self = *static_cast<CountingAllocator*>(context);
+            if (self.allocated + size > self.limit)
+                return nullptr;
+            self.allocated += size;
+            return std::malloc(size);
+        };
+        allocator.reallocate = [](void* context, void* ptr, size_t old_size,
+                                   size_t new_size) -> void* {
+            auto& self = *static_cast<CountingAllocator*>(context);
+            if (self.allocated - old_size + new_size > self.limit)
+                return nullptr;
+            self.allocated = self.allocated - old_size + new_size;
+            return std::realloc(ptr, new_size);
+        };
+        allocator.deallocate = [](void* context, void* ptr, size_t size) {
+            static_cast<CountingAllocator*>(context)->allocated -= size;
+            std::free(ptr);
+        };
+        allocator.context = this;
+        return allocator;
+    }
+};
+}  // namespace
+
+TEST(linear_memory, empty)
+{
+    LinearMemory memory;
+    EXPECT_TRUE(memory.empty());
+    EXPECT_EQ(memory.size(), 0);
+    EXPECT_EQ(memory.backing(), MemoryBacking::heap);
+
+    memory.resize(PageSize);
+    EXPECT_EQ(memory.size(), PageSize);
+    EXPECT_TRUE(is_zero(memory));
+}
+
+TEST(linear_memory, heap)
+{
+    LinearMemory memory{PageSize};
+    EXPECT_EQ(memory.backing(), MemoryBacking::heap);
+    EXPECT_EQ(memory.size(), PageSize);
+    EXPECT_TRUE(is_zero(memory));
+
+    memory[0] = 0xaa;
+    memory[PageSize - 1] = 0xbb;
+    memory.resize(3 * PageSize);
+    EXPECT_EQ(memory.size(), 3 * PageSize);
+    EXPECT_EQ(memory[0], 0xaa);
+    EXPECT_EQ(memory[PageSize - 1], 0xbb);
+    EXPECT_TRUE(is_zero(bytes_view{memory}.substr(PageSize)));
+
+    // Regrown bytes are zero-initialized.
+    memory.resize(1);
+    memory.resize(PageSize);
+    EXPECT_EQ(memory[0], 0xaa);
+    EXPECT_TRUE(is_zero(bytes_view{memory}.substr(1)));
+}
+
+TEST(linear_memory, mapped)
+{
+    LinearMemory memory{PageSize, MemoryBacking::mapped, 3 * PageSize};
+#if defined(_WIN32) || defined(__unix__) || defined(__APPLE__)
+    EXPECT_EQ(memory.backing(), MemoryBacking::mapped);
+#endif
+    EXPECT_EQ(memory.size(), PageSize);
+    EXPECT_TRUE(is_zero(memory));
+
+    memory[0] = 0xaa;
+    memory[PageSize - 1] = 0xbb;
+    const auto* const data = memory.data();
+    memory.resize(3 * PageSize);
+    EXPECT_EQ(memory.size(), 3 * PageSize);
+    EXPECT_EQ(memory[0], 0xaa);
+    EXPECT_EQ(memory[PageSize - 1], 0xbb);
+    EXPECT_TRUE(is_zero(bytes_view{memory}.substr(PageSize)));
+    memory[3 * PageSize - 1] = 0xcc;
+
+    if (memory.backing() == MemoryBacking::mapped)
+    {
+        // Mapped memory grows in place, but not beyond its reservation.
+        EXPECT_EQ(memory.data(), data);
+        EXPECT_THROW(memory.resize(4 * PageSize), std::bad_alloc);
+        EXPECT_EQ(memory.size(), 3 * PageSize);
+        EXPECT_EQ(memory[3 * PageSize - 1], 0xcc);
+    }
+
+    // Regrown bytes are zero-initialized.
+    memory.resize(1);
+    memory.resize(3 * PageSize);
+    EXPECT_EQ(memory[0], 0xaa);
+    EXPECT_TRUE(is_zero(bytes_view{memory}.substr(1)));
+}
+
+TEST(linear_memory, mapped_empty)
+{
+    LinearMemory memory{0, MemoryBacking::mapped, 0};
+    EXPECT_TRUE(memory.empty());
+    memory.resize(0);
+    EXPECT_TRUE(memory.empty());
+}
+
+TEST(linear_memory, guarded)
+{
+    LinearMemory memory{PageSize, MemoryBacking::guarded, 2 * PageSize};
+#if (defined(__unix__) || defined(__APPLE__)) && UINTPTR_MAX > 0xffffffff
+    EXPECT_EQ(memory.backing(), MemoryBacking::guarded);
+    // The reservation covers any address of a memory access, regardless of the max size.
+    EXPECT_GT(memory.capacity(), (size_t{1} << 33));
+#endif
+    EXPECT_EQ(memory.size(), PageSize);
+    EXPECT_TRUE(is_zero(memory));
+
+    memory[PageSize - 1] = 0xbb;
+    const auto* const data = memory.data();
+    memory.resize(4 * PageSize);
+    EXPECT_EQ(memory.size(), 4 * PageSize);
+    EXPECT_EQ(memory[PageSize - 1], 0xbb);
+    EXPECT_TRUE(is_zero(bytes_view{memory}.substr(PageSize)));
+    if (memory.backing() == MemoryBacking::guarded)
+    {
+        EXPECT_EQ(memory.data(), data);
+    }
+
+    memory.resize(PageSize);
+    memory.resize(2 * PageSize);
+    EXPECT_EQ(memory[PageSize - 1], 0xbb);
+    EXPECT_TRUE(is_zero(bytes_view{memory}.substr(PageSize)));
+}
+
+TEST(linear_memory, read_only_ranges)
+{
+    LinearMemory memory{PageSize};
+    EXPECT_FALSE(memory.is_read_only(0, PageSize));
+
+    memory.add_read_only_range(16, 8);
+    memory.add_read_only_range(20, 8);
+    EXPECT_TRUE(memory.is_read_only(16, 1));
+    EXPECT_TRUE(memory.is_read_only(27, 1));
+    EXPECT_TRUE(memory.is_read_only(12, 8));
+    EXPECT_FALSE(memory.is_read_only(12, 4));
+    EXPECT_FALSE(memory.is_read_only(28, 4));
+    EXPECT_FALSE(memory.is_read_only(16, 0));
+
+    memory.clear_read_only_ranges();
+    EXPECT_FALSE(memory.is_read_only(16, 8));
+}
+
+TEST(linear_memory, read_only_ranges_merged)
+{
+    using Ranges = std::vector<std::pair<uint64_t, uint64_t>>;
+    LinearMemory memory{PageSize};
+
+    memory.add_read_only_range(40, 8);
+    memory.add_read_only_range(8, 8);
+    memory.add_read_only_range(24, 0);
+    EXPECT_EQ(memory.read_only_ranges(), (Ranges{{8, 16}, {40, 48}}));
+    EXPECT_FALSE(memory.is_read_only(16, 24));
+    EXPECT_TRUE(memory.is_read_only(16, 25));
+    EXPECT_TRUE(memory.is_read_only(0, 9));
+
+    // The overlapping and adjacent ranges are merged.
+    memory.add_read_only_range(16, 4);
+    memory.add_read_only_range(30, 12);
+    EXPECT_EQ(memory.read_only_ranges(), (Ranges{{8, 20}, {30, 48}}));
+    memory.add_read_only_range(0, 64);
+    EXPECT_EQ(memory.read_only_ranges(), (Ranges{{0, 64}}));
+    EXPECT_TRUE(memory.is_read_only(63, 1));
+    EXPECT_FALSE(memory.is_read_only(64, 1));
+}
+
+TEST(linear_memory, allocator)
+{
+    CountingAllocator counter;
+    counter.limit = 2 * PageSize;
+    {
+        LinearMemory memory{PageSize, MemoryBacking::heap, 0, counter.hooks()};
+        EXPECT_EQ(counter.allocated, PageSize);
+        EXPECT_TRUE(is_zero(memory));
+
+        memory[0] = 0xaa;
+        memory.resize(2 * PageSize);
+        EXPECT_EQ(counter.allocated, 2 * PageSize);
+        EXPECT_EQ(memory[0], 0xaa);
+        EXPECT_TRUE(is_zero(bytes_view{memory}.substr(1)));
+
+        EXPECT_THROW(memory.resize(3 * PageSize), std::bad_alloc);
+        EXPECT_EQ(memory.size(), 2 * PageSize);
+        EXPECT_EQ(memory[0], 0xaa);
+    }
+    EXPECT_EQ(counter.allocated, 0);
+
+    {
+        LinearMemory memory{0, MemoryBacking::heap, 0, counter.hooks()};
+        EXPECT_EQ(counter.allocated, 0);
+        memory.resize(PageSize);
+        EXPECT_EQ(counter.allocated, PageSize);
+        EXPECT_TRUE(is_zero(memory));
+    }
+    EXPECT_EQ(counter.allocated, 0);
+
+    EXPECT_THROW(LinearMemory(3 * PageSize, MemoryBacking::heap, 0, counter.hooks()),
+        std::bad_alloc);
+    EXPECT_EQ(counter.allocated, 0);
+
+    // Mapped memory does not use the allocator.
+    LinearMemory mapped{PageSize, MemoryBacking::mapped, 4 * PageSize, counter.hooks()};
+    mapped.resize(3 * PageSize);
+    if (mapped.backing() == MemoryBacking::mapped)
+    {
+        EXPECT_EQ(counter.allocated, 0);
+    }
+}
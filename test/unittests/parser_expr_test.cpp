@@ -14,7 +14,7 @@ using namespace fizzy::test;
 namespace
 {
 const Module ModuleWithSingleFunction = {
-    {FuncType{{}, {}}}, {}, {0}, {}, {}, {}, {}, std::nullopt, {}, {}, {}, {}, {}, {}, {}};
+    {FuncType{{}, {}}}, {}, {0}, {}, {}, {}, {}, std::nullopt, {}, {}, {}, {}, {}, {}, {}, {}};
 
 inline auto parse_expr(bytes_view input, FuncIdx func_idx = 0,
     const std::vector<Locals>& locals = {}, const Module& module = ModuleWithSingleFunction)
@@ -1062,6 +1062,7 @@ TEST(parser, code_section_with_2_trivial_codes)
     EXPECT_EQ(module->typesec[0].outputs.size(), 0);
     ASSERT_EQ(module->codesec.size(), 2);
     EXPECT_EQ(module->codesec[0].local_count, 0);
+    EXPECT_EQ(module->codesec[0].body_size, 2);
     ASSERT_EQ(module->codesec[0].instructions.size(), 1);
     EXPECT_EQ(module->codesec[0].instructions[0], Instr::end);
     EXPECT_EQ(module->codesec[1].local_count, 0);
@@ -1091,6 +1092,7 @@ TEST(parser, code_section_with_basic_instructions)
     EXPECT_EQ(module->typesec[0].outputs.size(), 0);
     ASSERT_EQ(module->codesec.size(), 1);
     EXPECT_EQ(module->codesec[0].local_count, 4);
+    EXPECT_EQ(module->codesec[0].body_size, func_bin.size());
     ASSERT_EQ(module->codesec[0].instructions.size(), 7);
     EXPECT_EQ(module->codesec[0].instructions[0], Instr::local_get);
     EXPECT_EQ(module->codesec[0].instructions[1], Instr::i32_const);
@@ -1164,6 +1166,35 @@ TEST(parser, code_section_with_memory_grow)
     EXPECT_THROW_MESSAGE(parse(bin_invalid), parser_error, "invalid memory index encountered");
 }
 
+TEST(parser, code_section_with_memory_index_multi_memory)
+{
+    const Features multi_memory{true};
+    const auto memory_section = make_section(5, make_vec({"0000"_bytes, "0001"_bytes}));
+
+    // memory.size 1, memory.grow 1 and i32.load8_u with the memory index flag in the alignment.
+    const auto func_bin = "00"_bytes +  // vec(locals)
+                          "3f01"_bytes + i32_const(0) + "40011a2d400100"_bytes + "1a0b"_bytes;
+    const auto code_section = make_section(10, make_vec({add_size_prefix(func_bin)}));
+    const auto bin = bytes{wasm_prefix} + make_section(1, make_vec({make_functype({}, {})})) +
+                     make_section(3, "0100"_bytes) + memory_section + code_section;
+
+    EXPECT_THROW_MESSAGE(parse(bin), validation_error,
+        "too many memory sections (at most one is allowed)");
+
+    const auto module = parse(bin, {}, multi_memory);
+    ASSERT_EQ(module->codesec[0].instructions.size(), 7);
+    EXPECT_EQ(module->codesec[0].instructions[0], Instr::memory_size);
+    EXPECT_EQ(module->codesec[0].instructions[2], Instr::memory_grow);
+    EXPECT_EQ(module->codesec[0].instructions[4], Instr::i32_load8_u);
+    // The memory indices are immediates in the modules with multiple memories.
+    EXPECT_EQ(module->codesec[0].immediates,
+        "01000000"  // memory.size memory index
+        "00000000"  // i32.const value
+        "01000000"  // memory.grow memory index
+        "00000000"  // i32.load8_u offset
+        "01000000"_bytes);  // i32.load8_u memory index
+}
+
 TEST(parser, code_section_fp_instructions)
 {
     const uint8_t fp_instructions[] = {0x2a, 0x2b, 0x38, 0x39, 0x43, 0x44, 0x5b, 0x5c, 0x5d, 0x5e,
@@ -1346,6 +1377,27 @@ TEST(parser, data_section_memidx_nonzero)
     EXPECT_THROW_MESSAGE(parse(bin), parser_error, "unexpected memidx value 1");
 }
 
+TEST(parser, data_section_explicit_memidx)
+{
+    // flags=2 memidx=1 expression=<i32.const 1> items=1
+    const auto section_contents = make_vec({"020141010b01aa"_bytes});
+    const auto bin = bytes{wasm_prefix} + make_section(5, make_vec({"0000"_bytes, "0001"_bytes})) +
+                     make_section(11, section_contents);
+    EXPECT_THROW_MESSAGE(parse(bin), parser_error, "unexpected memidx value 2");
+
+    const auto module = parse(bin, {}, Features{true});
+    ASSERT_EQ(module->datasec.size(), 1);
+    EXPECT_EQ(module->datasec[0].memory_index, 1);
+    EXPECT_EQ(as_uint32(module->datasec[0].offset.value.constant), 1);
+    EXPECT_EQ(module->datasec[0].init, "aa"_bytes);
+
+    const auto bin_invalid = bytes{wasm_prefix} + make_section(5, make_vec({"0000"_bytes})) +
+                             make_section(11, section_contents);
+    EXPECT_THROW_MESSAGE(parse(bin_invalid), parser_error, "unexpected memidx value 2");
+    EXPECT_THROW_MESSAGE(parse(bin_invalid, {}, Features{true}), validation_error,
+        "invalid memory index in data segment");
+}
+
 TEST(parser, data_section_invalid_initializer)
 {
     // memidx=0 expression=<memory_size> items=0
@@ -1481,3 +1533,67 @@ TEST(parser, milestone1)
         "02000000"
         "00000000"_bytes);
 }
+
+TEST(parser, limits)
+{
+    /* wat2wasm
+    (func (import "m" "f"))
+    (func (local i32 i32)
+      block
+        block
+          nop
+        end
+      end
+    )
+    (func)
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010401600000020701016d0166000003030200000a10020b01027f02400240010b0b0b0200"
+        "0b");
+
+    ParserLimits limits;
+    limits.max_function_count = 3;
+    limits.max_function_body_size = 11;
+    limits.max_local_count = 2;
+    limits.max_nesting_depth = 2;
+    limits.max_allocation_size = 1024 * 1024;
+    EXPECT_EQ(parse(wasm, limits)->codesec.size(), 2);
+
+    auto function_count_limits = limits;
+    function_count_limits.max_function_count = 2;
+    EXPECT_THROW_MESSAGE(
+        parse(wasm, function_count_limits), parser_error, "function count limit exceeded");
+
+    auto body_size_limits = limits;
+    body_size_limits.max_function_body_size = 10;
+    EXPECT_THROW_MESSAGE(
+        parse(wasm, body_size_limits), parser_error, "function body size limit exceeded");
+
+    auto local_count_limits = limits;
+    local_count_limits.max_local_count = 1;
+    EXPECT_THROW_MESSAGE(
+        parse(wasm, local_count_limits), parser_error, "local count limit exceeded");
+
+    auto nesting_depth_limits = limits;
+    nesting_depth_limits.max_nesting_depth = 1;
+    EXPECT_THROW_MESSAGE(
+        parse(wasm, nesting_depth_limits), parser_error, "nesting depth limit exceeded");
+
+    auto allocation_size_limits = limits;
+    allocation_size_limits.max_allocation_size = 100;
+    EXPECT_THROW_MESSAGE(
+        parse(wasm, allocation_size_limits), parser_error, "allocation size limit exceeded");
+}
+
+TEST(parser, limits_data_allocation)
+{
+    const auto wasm = bytes{wasm_prefix} + make_section(5, make_vec({"0001"_bytes})) +
+                      make_section(11, make_vec({"0041000b"_bytes + add_size_prefix(bytes(1000, 0xfe))}));
+
+    ParserLimits limits;
+    limits.max_allocation_size = 1000;
+    EXPECT_THROW_MESSAGE(parse(wasm, limits), parser_error, "allocation size limit exceeded");
+
+    limits.max_allocation_size = 2000;
+    EXPECT_EQ(parse(wasm, limits)->datasec[0].init.size(), 1000);
+}
@@ -0,0 +1,41 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+#include "sha256.hpp"
+#include <gtest/gtest.h>
+#include <test/utils/hex.hpp>
+
+using namespace fizzy;
+using namespace fizzy::test;
+
+namespace
+{
+std::string sha256_hex(std::string_view input)
+{
+    const auto h = sha256(reinterpret_cast<const uint8_t*>(input.data()), input.size());
+    return hex(h.data(), h.size());
+}
+}  // namespace
+
+TEST(sha256, test_vectors)
+{
+    EXPECT_EQ(
+        sha256_hex(""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    EXPECT_EQ(
+        sha256_hex("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    EXPECT_EQ(sha256_hex("abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+}
+
+TEST(sha256, incremental)
+{
+    const std::string input(1000, 'a');
+    Sha256 h;
+    for (size_t i = 0; i < input.size(); i += 10)
+        h.update(reinterpret_cast<const uint8_t*>(&input[i]), 10);
+    const auto result = h.finalize();
+    EXPECT_EQ(hex(result.data(), result.size()),
+        "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3");
+    EXPECT_EQ(result, sha256(reinterpret_cast<const uint8_t*>(input.data()), input.size()));
+}
@@ -124,6 +124,38 @@ TEST(validation, memory_multi_min_limit)
         parse(bin), validation_error, "too many memory sections (at most one is allowed)");
 }
 
+TEST(validation, multi_memory_memories)
+{
+    const Features multi_memory{true};
+
+    // (import "m1" "abc" (memory 0)) (import "m2" "def" (memory 0x7f))
+    const auto import_section = make_section(2,
+        make_vec({bytes{0x02, 'm', '1', 0x03, 'a', 'b', 'c', 0x02, 0x00, 0x00},
+            bytes{0x02, 'm', '2', 0x03, 'd', 'e', 'f', 0x02, 0x00, 0x7f}}));
+    auto module = parse(bytes{wasm_prefix} + import_section, {}, multi_memory);
+    EXPECT_EQ(module->get_memory_count(), 2);
+    EXPECT_EQ(module->get_memory_limits(1).min, 0x7f);
+
+    // The imported memories come first in the index space.
+    const auto memory_section = make_section(5, "0200020003"_bytes);
+    module = parse(bytes{wasm_prefix} + import_section + memory_section, {}, multi_memory);
+    ASSERT_EQ(module->get_memory_count(), 4);
+    EXPECT_EQ(module->get_memory_limits(0).min, 0);
+    EXPECT_EQ(module->get_memory_limits(2).min, 2);
+    EXPECT_EQ(module->get_memory_limits(3).min, 3);
+}
+
+TEST(validation, memory_index_invalid)
+{
+    /* wat2wasm --enable-multi-memory --no-check
+      (memory 1)
+      (func (drop (i32.load 1 (i32.const 0))))
+    */
+    const auto wasm =
+        from_hex("0061736d010000000104016000000302010005030100010a0b0109004100284201001a0b");
+    EXPECT_THROW_MESSAGE(parse(wasm, {}, Features{true}), validation_error, "invalid memory index");
+}
+
 TEST(validation, import_tables_multiple)
 {
     const auto section_contents =
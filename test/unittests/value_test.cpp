@@ -47,6 +47,15 @@ TEST(value, constructor_from_floating_points)
     EXPECT_EQ(Value{123.456789001}.f64, 123.456789001);
 }
 
+TEST(value, f32_representation)
+{
+    // f32 values are kept in the lower 32 bits of i64, regardless of the host endianness.
+    EXPECT_EQ(Value{1.5f}.i64, 0x3fc00000);
+    EXPECT_EQ(Value{-0.0f}.i64, 0x80000000);
+    EXPECT_EQ(Value{uint32_t{0xbf800000}}.as<float>(), -1.0f);
+    EXPECT_EQ(Value{uint64_t{0xffffffff3fc00000}}.as<float>(), 1.5f);
+}
+
 TEST(value, as_integer)
 {
     const Value v{0xfffffffffffffffe};
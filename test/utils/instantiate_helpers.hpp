@@ -13,10 +13,11 @@ inline std::unique_ptr<Instance> instantiate(Module module,
     std::vector<ExternalTable> imported_tables = {},
     std::vector<ExternalMemory> imported_memories = {},
     std::vector<ExternalGlobal> imported_globals = {},
-    uint32_t memory_pages_limit = DefaultMemoryPagesLimit)
+    uint32_t memory_pages_limit = DefaultMemoryPagesLimit,
+    MemoryBacking memory_backing = MemoryBacking::heap, const Allocator& memory_allocator = {})
 {
     return instantiate(std::make_unique<Module>(std::move(module)), std::move(imported_functions),
         std::move(imported_tables), std::move(imported_memories), std::move(imported_globals),
-        memory_pages_limit);
+        memory_pages_limit, memory_backing, memory_allocator);
 }
 }  // namespace fizzy::test